@@ -89,7 +89,14 @@ fn main() {
     loop {
         // Dispatch Dakota's main event loop. Here we will block waiting
         // for events and allow the
-        dakota.dispatch(None).unwrap();
+        //
+        // While a scene is still scrolling from a fling we can't block,
+        // since it needs to be redrawn every frame.
+        let timeout = match scenes.iter().any(|s| s.is_kinetic_scrolling()) {
+            true => Some(16),
+            false => None,
+        };
+        dakota.dispatch(timeout).unwrap();
 
         // Process any global events first. These events show global
         // changes in state or give updates from Dakota's main polling
@@ -113,6 +120,7 @@ fn main() {
                         position,
                         xrel,
                         yrel,
+                        source,
                         ..
                     } => {
                         // Use the default input scrolling handler which will scroll
@@ -122,14 +130,80 @@ fn main() {
                                 &mut scenes[i],
                                 position,
                                 (xrel.unwrap_or(0).into(), yrel.unwrap_or(0).into()),
+                                source,
                             )
                             .expect("Error while handling scrolling");
+                        // Scrolling a list may have revealed rows that need
+                        // to be created
+                        if scenes[i].needs_refresh() {
+                            scenes[i]
+                                .recompile(virtual_output)
+                                .expect("Failed to refresh scrolled scene");
+                        }
                         // Tell our Output to present the new contents
                         outputs[i].request_redraw();
                     }
+                    // Clicks may dismiss popups or grab a scrollbar
+                    PlatformEvent::InputMouseButtonDown { x, y, .. } => {
+                        virtual_output.handle_click(&mut scenes[i], (x, y));
+                        if scenes[i].needs_refresh() {
+                            scenes[i]
+                                .recompile(virtual_output)
+                                .expect("Failed to refresh scene after click");
+                        }
+                        outputs[i].request_redraw();
+                    }
+                    PlatformEvent::InputMouseButtonUp { x, y, .. } => {
+                        virtual_output.handle_click_release(&mut scenes[i], (x, y))
+                    }
+                    PlatformEvent::InputMouseMove { dx, dy, .. } => {
+                        // Dragging a scrollbar moves its viewport
+                        let scrolled = virtual_output.handle_mouse_move(&mut scenes[i], dx, dy);
+                        if scrolled {
+                            outputs[i].request_redraw();
+                        }
+                    }
+                    // Dragging a finger scrolls the content under it
+                    PlatformEvent::InputTouchDown { id, x, y, .. } => {
+                        virtual_output.handle_touch_down(&mut scenes[i], id, (x, y));
+                        if scenes[i].needs_refresh() {
+                            scenes[i]
+                                .recompile(virtual_output)
+                                .expect("Failed to refresh scene after touch");
+                        }
+                        outputs[i].request_redraw();
+                    }
+                    PlatformEvent::InputTouchMotion { id, x, y, .. } => {
+                        let scrolled =
+                            virtual_output.handle_touch_motion(&mut scenes[i], id, (x, y));
+                        if scrolled {
+                            if scenes[i].needs_refresh() {
+                                scenes[i]
+                                    .recompile(virtual_output)
+                                    .expect("Failed to refresh scrolled scene");
+                            }
+                            outputs[i].request_redraw();
+                        }
+                    }
+                    PlatformEvent::InputTouchUp { id, .. } => {
+                        virtual_output.handle_touch_up(&mut scenes[i], id);
+                    }
+                    PlatformEvent::InputTouchCancel { .. } => {
+                        virtual_output.handle_touch_cancel(&mut scenes[i])
+                    }
                     _ => {}
                 }
             }
+
+            // Keep scrolling any flings
+            if scenes[i].update_kinetic_scroll() {
+                if scenes[i].needs_refresh() {
+                    scenes[i]
+                        .recompile(virtual_output)
+                        .expect("Failed to refresh scrolled scene");
+                }
+                outputs[i].request_redraw();
+            }
         }
 
         // Process any events which dakota encountered on this output
@@ -166,6 +240,34 @@ fn main() {
                             .recompile(&virtual_outputs[i])
                             .expect("Failed to handle resize of scene")
                     }
+                    // Free any images we loaded that aren't in use anymore
+                    OutputEvent::LowMemory => scenes[i].clear_asset_cache(),
+                    // One of the images in our scene finished loading, so
+                    // lay it out and show it
+                    OutputEvent::AssetLoaded => {
+                        scenes[i]
+                            .recompile(&virtual_outputs[i])
+                            .expect("Failed to refresh scene with loaded images");
+                        outputs[i].request_redraw();
+                    }
+                    // The GPU was reset, so move to a new one. All of our images
+                    // were lost, so load the scene from scratch.
+                    OutputEvent::DeviceLost => {
+                        dakota
+                            .recover_from_device_loss(&mut outputs[i], &mut scenes[i])
+                            .expect("Failed to recover from GPU device loss");
+                        scenes[i] = outputs[i]
+                            .create_scene(&virtual_outputs[i])
+                            .expect("Could not create scene");
+                        scenes[i]
+                            .load_xml_reader(BufReader::new(
+                                File::open(&args[1]).expect("could not open file"),
+                            ))
+                            .expect("Could not parse XML dakota file");
+                        scenes[i]
+                            .recompile(&virtual_outputs[i])
+                            .expect("Refreshing Dakota Scene");
+                    }
                     // Exit gracefully if this output has terminated
                     OutputEvent::Destroyed => dead_outputs.push(i),
                 }
@@ -0,0 +1,152 @@
+//! Accessibility tree
+//!
+//! Screen readers need to know what is being displayed, which they can't
+//! get from the pixels we draw. Dakota keeps an accessibility tree next to
+//! the layout tree, describing elements with a role, a name, and their
+//! bounds on the output.
+//!
+//! This follows the model used by AccessKit, so that applications can
+//! hand these to an AccessKit platform adapter: nodes are identified by
+//! stable ids, the full tree is available for the adapter's initial
+//! request, and after that only the nodes that changed are sent.
+// Austin Shafer - 2024
+use crate::dom;
+use utils::region::Rect;
+
+use std::collections::{HashMap, HashSet};
+
+/// Identifies a node in the accessibility tree
+///
+/// This is stable for the lifetime of the element it describes.
+pub type AccessNodeId = u64;
+
+/// An element as seen by accessibility tools
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessNode {
+    pub an_id: AccessNodeId,
+    pub an_role: dom::Role,
+    /// The text a screen reader should read for this node
+    pub an_name: Option<String>,
+    /// The area of the output this node covers
+    pub an_bounds: Rect<i32>,
+    pub an_children: Vec<AccessNodeId>,
+}
+
+/// A set of changes to the accessibility tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessUpdate {
+    /// Nodes which were added or changed
+    pub au_nodes: Vec<AccessNode>,
+    /// Nodes which no longer exist
+    pub au_removed: Vec<AccessNodeId>,
+    /// The root of the tree, which is the window
+    pub au_root: AccessNodeId,
+    /// The node with keyboard focus
+    pub au_focus: Option<AccessNodeId>,
+}
+
+/// The last accessibility tree we built, along with the changes that
+/// have not been given to the application yet
+pub(crate) struct AccessTree {
+    at_nodes: HashMap<AccessNodeId, AccessNode>,
+    at_root: AccessNodeId,
+    at_focus: Option<AccessNodeId>,
+    at_changed: HashSet<AccessNodeId>,
+    at_removed: HashSet<AccessNodeId>,
+    at_focus_changed: bool,
+}
+
+impl AccessTree {
+    pub fn new() -> Self {
+        Self {
+            at_nodes: HashMap::new(),
+            at_root: 0,
+            at_focus: None,
+            at_changed: HashSet::new(),
+            at_removed: HashSet::new(),
+            at_focus_changed: false,
+        }
+    }
+
+    /// Is this node in the tree
+    pub fn contains(&self, id: AccessNodeId) -> bool {
+        self.at_nodes.contains_key(&id)
+    }
+
+    /// Replace the tree with a newly built one, recording what changed
+    pub fn update(&mut self, root: AccessNodeId, nodes: Vec<AccessNode>) {
+        let mut new_nodes = HashMap::with_capacity(nodes.len());
+
+        for node in nodes.into_iter() {
+            if self.at_nodes.get(&node.an_id) != Some(&node) {
+                self.at_changed.insert(node.an_id);
+            }
+            self.at_removed.remove(&node.an_id);
+            new_nodes.insert(node.an_id, node);
+        }
+
+        for id in self.at_nodes.keys() {
+            if !new_nodes.contains_key(id) {
+                self.at_changed.remove(id);
+                self.at_removed.insert(*id);
+            }
+        }
+
+        if self.at_root != root {
+            self.at_root = root;
+            self.at_changed.insert(root);
+        }
+        self.at_nodes = new_nodes;
+
+        // Focus can't stay on a node that was removed
+        if let Some(focus) = self.at_focus {
+            if !self.contains(focus) {
+                self.set_focus(None);
+            }
+        }
+    }
+
+    pub fn set_focus(&mut self, focus: Option<AccessNodeId>) {
+        if self.at_focus != focus {
+            self.at_focus = focus;
+            self.at_focus_changed = true;
+        }
+    }
+
+    /// Get the changes since the last call
+    ///
+    /// Returns None if nothing changed.
+    pub fn take_update(&mut self) -> Option<AccessUpdate> {
+        if self.at_changed.is_empty() && self.at_removed.is_empty() && !self.at_focus_changed {
+            return None;
+        }
+
+        let nodes = &self.at_nodes;
+        let nodes = self
+            .at_changed
+            .drain()
+            .filter_map(|id| nodes.get(&id).cloned())
+            .collect();
+        self.at_focus_changed = false;
+
+        Some(AccessUpdate {
+            au_nodes: nodes,
+            au_removed: self.at_removed.drain().collect(),
+            au_root: self.at_root,
+            au_focus: self.at_focus,
+        })
+    }
+
+    /// Get the entire tree
+    ///
+    /// This is used to initialize accessibility tools. It does not affect
+    /// the changes returned by `take_update`.
+    pub fn get_full_tree(&self) -> AccessUpdate {
+        AccessUpdate {
+            au_nodes: self.at_nodes.values().cloned().collect(),
+            au_removed: Vec::new(),
+            au_root: self.at_root,
+            au_focus: self.at_focus,
+        }
+    }
+}
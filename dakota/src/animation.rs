@@ -0,0 +1,236 @@
+//! Animated image resources
+//!
+//! GIF and APNG files with more than one frame are decoded into a list of
+//! full size frames. Each resource defined from one gets its own image,
+//! which the Scene advances to the next frame once its delay has passed.
+//! Only the part of the image that differs from the previous frame is
+//! uploaded again.
+//!
+//! Animated WebP files are not supported by our version of the `image`
+//! crate, and are shown as a still image.
+// Austin Shafer - 2024
+extern crate image;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::AnimationDecoder;
+use th::Damage;
+use utils::region::Rect;
+use utils::{Context, Result};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Frames with a delay shorter than this are shown for `DEFAULT_FRAME_DELAY`
+///
+/// Many GIFs have a delay of zero, which browsers treat as 100ms. We do the
+/// same so that they play at the speed their authors expected.
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// One image in an animation
+pub(crate) struct AnimationFrame {
+    /// The BGRA8 pixels of the entire image
+    pub af_pixels: Vec<u8>,
+    /// How long this frame is shown for
+    pub af_delay: Duration,
+    /// The parts of the image which differ from the previous frame
+    pub af_damage: Damage,
+}
+
+/// The decoded frames of an animated image
+pub(crate) struct Animation {
+    pub an_width: u32,
+    pub an_height: u32,
+    pub an_frames: Vec<AnimationFrame>,
+}
+
+/// The playback state of an animated resource
+#[derive(Clone)]
+pub(crate) struct AnimationState {
+    pub as_animation: Arc<Animation>,
+    /// The frame the resource's image currently holds
+    pub as_frame: usize,
+    /// When to show the next frame
+    pub as_next: Instant,
+}
+
+impl AnimationState {
+    /// Start playing an animation from its first frame at `now`
+    pub fn new(animation: Arc<Animation>, now: Instant) -> Self {
+        let delay = animation.an_frames[0].af_delay;
+        Self {
+            as_animation: animation,
+            as_frame: 0,
+            as_next: now + delay,
+        }
+    }
+
+    /// Advance to the frame which should be shown at `now`
+    ///
+    /// Returns the damage between the old and new frames, or None if the
+    /// frame did not change. If the whole animation was skipped the
+    /// damage is the entire image.
+    pub fn advance(&mut self, now: Instant) -> Option<Damage> {
+        if now < self.as_next {
+            return None;
+        }
+
+        let frames = &self.as_animation.an_frames;
+        let mut damage = Damage::empty();
+        let mut skipped = 0;
+        while now >= self.as_next {
+            self.as_frame = (self.as_frame + 1) % frames.len();
+            self.as_next += frames[self.as_frame].af_delay;
+            damage.union(&frames[self.as_frame].af_damage);
+            skipped += 1;
+
+            // If we fell far behind, such as while the output was
+            // powered off, restart the timer instead of catching up
+            if skipped >= frames.len() {
+                self.as_next = now + frames[self.as_frame].af_delay;
+                damage = Damage::new(vec![Rect::new(
+                    0,
+                    0,
+                    self.as_animation.an_width as i32,
+                    self.as_animation.an_height as i32,
+                )]);
+                break;
+            }
+        }
+
+        Some(damage)
+    }
+
+    /// Get the pixels of the current frame
+    pub fn get_pixels(&self) -> &[u8] {
+        &self.as_animation.an_frames[self.as_frame].af_pixels
+    }
+}
+
+/// Find the parts of an image which changed between two frames
+///
+/// Each run of changed rows becomes one region covering the columns that
+/// changed in those rows.
+fn frame_damage(prev: &[u8], next: &[u8], width: u32) -> Damage {
+    let stride = width as usize * 4;
+    let mut damage = Damage::empty();
+    // The first row and column range of the run we are building
+    let mut run: Option<(usize, usize, usize)> = None;
+
+    let rows = prev.chunks(stride).zip(next.chunks(stride)).enumerate();
+    for (y, (a, b)) in rows {
+        let changed = |x: &usize| a[x * 4..x * 4 + 4] != b[x * 4..x * 4 + 4];
+        let first = (0..width as usize).find(changed);
+        let last = (0..width as usize).rev().find(changed);
+
+        run = match (run, first, last) {
+            (Some((y0, x0, x1)), Some(first), Some(last)) => {
+                Some((y0, x0.min(first), x1.max(last)))
+            }
+            (None, Some(first), Some(last)) => Some((y, first, last)),
+            (Some((y0, x0, x1)), _, _) => {
+                damage.add(&Rect::new(
+                    x0 as i32,
+                    y0 as i32,
+                    (x1 - x0 + 1) as i32,
+                    (y - y0) as i32,
+                ));
+                None
+            }
+            (None, _, _) => None,
+        };
+    }
+
+    if let Some((y0, x0, x1)) = run {
+        let height = prev.len() / stride;
+        damage.add(&Rect::new(
+            x0 as i32,
+            y0 as i32,
+            (x1 - x0 + 1) as i32,
+            (height - y0) as i32,
+        ));
+    }
+
+    damage
+}
+
+/// Turn the frames from an `image` decoder into an Animation
+///
+/// Returns None if there is only one frame.
+fn collect_animation(frames: image::Frames) -> Result<Option<Animation>> {
+    let frames = frames
+        .collect_frames()
+        .context("Could not decode animation frames")?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+
+    let (width, height) = frames[0].buffer().dimensions();
+    let mut ret = Animation {
+        an_width: width,
+        an_height: height,
+        an_frames: Vec::with_capacity(frames.len()),
+    };
+
+    for frame in frames.into_iter() {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = match Duration::from_micros(numer as u64 * 1000 / denom.max(1) as u64) {
+            delay if delay < MIN_FRAME_DELAY => DEFAULT_FRAME_DELAY,
+            delay => delay,
+        };
+
+        // image gives us RGBA, but Thundr wants BGRA
+        let mut pixels = frame.into_buffer().into_raw();
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+
+        ret.an_frames.push(AnimationFrame {
+            af_pixels: pixels,
+            af_delay: delay,
+            af_damage: Damage::empty(),
+        });
+    }
+
+    // The first frame follows the last one when we loop
+    let count = ret.an_frames.len();
+    for i in 0..count {
+        let prev = &ret.an_frames[(i + count - 1) % count].af_pixels;
+        let damage = frame_damage(prev, &ret.an_frames[i].af_pixels, width);
+        ret.an_frames[i].af_damage = damage;
+    }
+
+    Ok(Some(ret))
+}
+
+/// Decode an animated image file
+///
+/// Returns None if this file is not a GIF or APNG, or only has one frame.
+/// Such files should be decoded as a still image instead.
+pub(crate) fn decode_animation(path: &Path) -> Result<Option<Animation>> {
+    let ext = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.to_ascii_lowercase(),
+        None => return Ok(None),
+    };
+    let open = || -> Result<std::io::BufReader<std::fs::File>> {
+        Ok(std::io::BufReader::new(
+            std::fs::File::open(path).context("Could not open image path")?,
+        ))
+    };
+
+    match ext.as_str() {
+        "gif" => {
+            let decoder = GifDecoder::new(open()?).context("Could not read GIF")?;
+            collect_animation(decoder.into_frames())
+        }
+        "png" | "apng" => {
+            let decoder = PngDecoder::new(open()?).context("Could not read PNG")?;
+            match decoder.is_apng() {
+                true => collect_animation(decoder.apng().into_frames()),
+                false => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,258 @@
+//! Asynchronous image loading
+//!
+//! Decoding images is slow, and doing it while parsing a scene stalls the
+//! application. The AssetLoader decodes images on a set of worker threads
+//! and hands the pixels back to the Scene, which uploads them to the GPU
+//! and assigns them to every resource waiting on that file.
+//!
+//! Uploaded images are cached by path, so that all resources using the
+//! same file share one Thundr image. SVGs are rasterized by the same
+//! workers, and are cached by path and size. Animated images are decoded
+//! here too, but are not cached since every resource plays its own copy.
+// Austin Shafer - 2024
+extern crate utils;
+use crate::animation::{self, Animation};
+use crate::svg;
+use crate::DakotaId;
+use utils::{anyhow, Context, Result};
+use utils::{log, trace};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// The most worker threads we will decode images on
+const MAX_ASSET_WORKERS: usize = 4;
+
+/// Called from a worker thread whenever an image finishes decoding
+pub(crate) type AssetNotifier = Box<dyn Fn() + Send + Sync>;
+
+/// The width, height, and BGRA8 pixels of an image
+type Pixels = (u32, u32, Vec<u8>);
+
+/// How a worker should produce an image
+pub(crate) enum AssetSource {
+    /// Decode the image file at the job's path
+    File,
+    /// Rasterize this SVG document at the given size
+    Svg(Arc<Vec<u8>>, (u32, u32)),
+}
+
+/// The contents of an image decoded by a worker
+pub(crate) struct DecodedImage {
+    /// The cache key this image was requested with
+    pub di_path: PathBuf,
+    /// The width, height, and BGRA8 pixels of the image. For animations
+    /// this is the first frame.
+    pub di_result: Result<(u32, u32, Vec<u8>)>,
+    /// All of the frames if this is an animated image
+    pub di_animation: Option<Arc<Animation>>,
+}
+
+/// Decodes images in the background and caches the results
+pub(crate) struct AssetLoader {
+    /// Images to decode. This is None once we are shutting down.
+    al_jobs: Option<mpsc::Sender<(PathBuf, AssetSource)>>,
+    al_results: mpsc::Receiver<DecodedImage>,
+    al_workers: Vec<JoinHandle<()>>,
+    /// Images which have already been loaded, keyed by canonical path.
+    /// SVG rasters are keyed by `svg::svg_cache_key`.
+    al_cache: HashMap<PathBuf, th::Image>,
+    /// Resources waiting for a path to finish decoding
+    al_pending: HashMap<PathBuf, Vec<DakotaId>>,
+    al_notifiers: Arc<Mutex<Vec<AssetNotifier>>>,
+}
+
+/// Decode an animated image file
+///
+/// Returns the first frame along with the animation, or None if this is
+/// not an animated image.
+fn decode_animated_image(path: &Path) -> Result<Option<(Pixels, Arc<Animation>)>> {
+    Ok(animation::decode_animation(path)?.map(|anim| {
+        let first = (
+            anim.an_width,
+            anim.an_height,
+            anim.an_frames[0].af_pixels.clone(),
+        );
+        (first, Arc::new(anim))
+    }))
+}
+
+/// Decode an image file into tightly packed BGRA8 pixels
+fn decode_image(path: &Path) -> Result<(u32, u32, Vec<u8>)> {
+    let img = image::open(path)
+        .context("Could not open image path")?
+        .to_bgra8();
+
+    Ok((img.width(), img.height(), img.into_vec()))
+}
+
+impl AssetLoader {
+    pub fn new() -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<(PathBuf, AssetSource)>();
+        let (result_tx, result_rx) = mpsc::channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let notifiers: Arc<Mutex<Vec<AssetNotifier>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let worker_count = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(MAX_ASSET_WORKERS);
+
+        let workers = (0..worker_count)
+            .map(|i| {
+                let jobs = job_rx.clone();
+                let results = result_tx.clone();
+                let notifiers = notifiers.clone();
+
+                // Named so they can be told apart in traces
+                std::thread::Builder::new()
+                    .name(format!("dakota-asset-{}", i))
+                    .spawn(move || loop {
+                        // Only hold the lock while waiting for a job, so that
+                        // other workers can decode at the same time
+                        let (path, source) = match jobs.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            // The loader was dropped
+                            Err(_) => return,
+                        };
+
+                        let (result, animation) = match source {
+                            AssetSource::File => {
+                                let _decode = trace::span("dakota", "decode_image");
+                                match decode_animated_image(&path) {
+                                    Ok(Some((first, anim))) => (Ok(first), Some(anim)),
+                                    Ok(None) => (decode_image(&path), None),
+                                    Err(e) => (Err(e), None),
+                                }
+                            }
+                            AssetSource::Svg(data, size) => {
+                                let _raster = trace::span("dakota", "rasterize_svg");
+                                (svg::rasterize_svg(&data, size), None)
+                            }
+                        };
+                        if results
+                            .send(DecodedImage {
+                                di_path: path,
+                                di_result: result,
+                                di_animation: animation,
+                            })
+                            .is_err()
+                        {
+                            return;
+                        }
+
+                        for notify in notifiers.lock().unwrap().iter() {
+                            notify();
+                        }
+                    })
+                    .expect("Could not spawn asset worker")
+            })
+            .collect();
+
+        Self {
+            al_jobs: Some(job_tx),
+            al_results: result_rx,
+            al_workers: workers,
+            al_cache: HashMap::new(),
+            al_pending: HashMap::new(),
+            al_notifiers: notifiers,
+        }
+    }
+
+    /// Get the path used to identify a file in our cache
+    pub fn cache_key(path: &Path) -> PathBuf {
+        std::fs::canonicalize(path).unwrap_or(path.to_path_buf())
+    }
+
+    /// Register a callback to run when an image has been decoded
+    ///
+    /// This is called from the worker thread, so it should only wake up
+    /// the main loop. The Scene will process the image once
+    /// `process_loaded_assets` is called.
+    pub fn add_notifier(&self, notifier: AssetNotifier) {
+        self.al_notifiers.lock().unwrap().push(notifier);
+    }
+
+    /// Get an already loaded image
+    pub fn get_cached(&self, key: &Path) -> Option<th::Image> {
+        self.al_cache.get(key).cloned()
+    }
+
+    pub fn add_to_cache(&mut self, key: PathBuf, image: th::Image) {
+        self.al_cache.insert(key, image);
+    }
+
+    /// Drop all cached images
+    ///
+    /// Images still in use by resources stay alive until those resources
+    /// are redefined or destroyed.
+    pub fn clear_cache(&mut self) {
+        self.al_cache.clear();
+    }
+
+    /// Is this resource waiting on an image to decode
+    pub fn is_pending(&self, res: &DakotaId) -> bool {
+        self.al_pending.values().any(|ids| ids.contains(res))
+    }
+
+    /// Are any images currently being decoded
+    pub fn has_pending(&self) -> bool {
+        !self.al_pending.is_empty()
+    }
+
+    /// Load the image at `key` into the resource `res`
+    ///
+    /// `source` describes how to create the image. If this key is already
+    /// being decoded the resource will share the result instead of
+    /// decoding it a second time.
+    pub fn request(&mut self, res: &DakotaId, key: PathBuf, source: AssetSource) -> Result<()> {
+        if let Some(waiting) = self.al_pending.get_mut(&key) {
+            waiting.push(res.clone());
+            return Ok(());
+        }
+
+        self.al_jobs
+            .as_ref()
+            .unwrap()
+            .send((key.clone(), source))
+            .map_err(|_| anyhow!("Asset worker threads have exited"))?;
+        self.al_pending.insert(key, vec![res.clone()]);
+
+        Ok(())
+    }
+
+    /// Get the next decoded image and the resources waiting on it
+    ///
+    /// If `block` is true this waits for an image to finish decoding.
+    pub fn next_decoded(&mut self, block: bool) -> Option<(DecodedImage, Vec<DakotaId>)> {
+        if !self.has_pending() {
+            return None;
+        }
+
+        let decoded = match block {
+            true => self.al_results.recv().ok()?,
+            false => self.al_results.try_recv().ok()?,
+        };
+
+        match self.al_pending.remove(&decoded.di_path) {
+            Some(ids) => Some((decoded, ids)),
+            None => {
+                log::error!("Decoded image {:?} that was not requested", decoded.di_path);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // Closing the job channel tells the workers to exit
+        self.al_jobs = None;
+        for worker in self.al_workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Glyph atlas
+//!
+//! Creating a Thundr image for every glyph uses one descriptor per glyph,
+//! which quickly runs out with real amounts of text. Instead glyph bitmaps
+//! are packed into one shared image, and each glyph is drawn from its
+//! rectangle within it.
+//!
+//! Glyphs are packed into shelves: rows the height of the tallest glyph
+//! placed in them. The atlas grows as shelves are added, and once it has
+//! reached its maximum size the least recently used shelf is evicted to
+//! make room.
+// Austin Shafer - 2024
+use crate::DakotaId;
+use utils::region::Rect;
+use utils::{Context, Result};
+
+/// The width of the atlas image. Only the height grows.
+pub(crate) const ATLAS_WIDTH: i32 = 1024;
+const ATLAS_INITIAL_HEIGHT: i32 = 256;
+pub(crate) const ATLAS_MAX_HEIGHT: i32 = 4096;
+/// Empty space around glyphs so that filtering doesn't sample neighbors
+const ATLAS_PADDING: i32 = 1;
+
+/// A row of glyphs in the atlas
+struct Shelf {
+    s_y: i32,
+    s_height: i32,
+    /// The start of the free space on this shelf
+    s_x: i32,
+    /// The generation this shelf was last used in
+    s_last_used: u64,
+    /// Incremented when this shelf is evicted, invalidating its glyphs
+    s_epoch: u64,
+}
+
+/// The location of a glyph in the atlas
+#[derive(Clone)]
+pub(crate) struct AtlasSlot {
+    as_rect: Rect<i32>,
+    as_shelf: usize,
+    as_epoch: u64,
+}
+
+pub(crate) struct GlyphAtlas {
+    /// Where each glyph was placed, indexed by the glyph id
+    ga_slots: ll::Component<AtlasSlot>,
+    ga_shelves: Vec<Shelf>,
+    ga_height: i32,
+    /// Copy of the atlas contents, which is uploaded to ga_image
+    ga_data: Vec<u8>,
+    ga_image: Option<th::Image>,
+    /// The areas which changed since the last upload
+    ga_damage: th::Damage,
+    /// Was the atlas resized since the last upload
+    ga_resized: bool,
+    /// Glyphs used in the current generation can't be evicted
+    ga_generation: u64,
+}
+
+impl GlyphAtlas {
+    pub fn new(ecs: &mut ll::Instance) -> Self {
+        Self {
+            ga_slots: ecs.add_component(),
+            ga_shelves: Vec::new(),
+            ga_height: ATLAS_INITIAL_HEIGHT,
+            ga_data: vec![0; (ATLAS_WIDTH * ATLAS_INITIAL_HEIGHT * 4) as usize],
+            ga_image: None,
+            ga_damage: th::Damage::empty(),
+            ga_resized: true,
+            ga_generation: 0,
+        }
+    }
+
+    /// Start a new generation
+    ///
+    /// This is called before each layout. Glyphs used since the last call
+    /// are kept in the atlas, since they may be drawn.
+    pub fn next_generation(&mut self) {
+        self.ga_generation += 1;
+    }
+
+    /// Get the image holding the atlas contents
+    ///
+    /// This is None until the atlas is first uploaded.
+    pub fn get_image(&self) -> Option<&th::Image> {
+        self.ga_image.as_ref()
+    }
+
+    /// Get the area of the atlas holding this glyph
+    pub fn get_rect(&self, id: &DakotaId) -> Option<Rect<i32>> {
+        let slot = self.ga_slots.get(id)?;
+        match self.ga_shelves[slot.as_shelf].s_epoch == slot.as_epoch {
+            true => Some(slot.as_rect),
+            false => None,
+        }
+    }
+
+    /// Remove every glyph from the atlas
+    ///
+    /// This is used when glyphs are rasterized again, such as when the
+    /// subpixel layout changes.
+    pub fn clear(&mut self) {
+        self.ga_slots.clear();
+        self.ga_shelves.clear();
+        self.ga_data.fill(0);
+        self.ga_resized = true;
+    }
+
+    /// Forget our atlas image
+    ///
+    /// This is used when the GPU the image was on has been lost. The glyphs
+    /// are kept, and will be uploaded to a new image in the next flush.
+    pub fn drop_image(&mut self) {
+        self.ga_image = None;
+        self.ga_damage = th::Damage::empty();
+        self.ga_resized = true;
+    }
+
+    /// Double the height of the atlas
+    fn grow(&mut self) {
+        self.ga_height *= 2;
+        // Rows are stored top to bottom, so new rows go on the end
+        self.ga_data
+            .resize((ATLAS_WIDTH * self.ga_height * 4) as usize, 0);
+        self.ga_resized = true;
+    }
+
+    /// Find a shelf with room for an area this size
+    ///
+    /// This will add a new shelf or evict an old one if needed.
+    fn allocate(&mut self, width: i32, height: i32) -> Option<usize> {
+        if width > ATLAS_WIDTH || height > ATLAS_MAX_HEIGHT {
+            return None;
+        }
+
+        // Use the shortest shelf this fits on, to avoid wasting space
+        let fit = self
+            .ga_shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.s_height >= height && ATLAS_WIDTH - s.s_x >= width)
+            .min_by_key(|(_, s)| s.s_height)
+            .map(|(i, _)| i);
+        if fit.is_some() {
+            return fit;
+        }
+
+        // Add a new shelf below the others
+        let y = self
+            .ga_shelves
+            .last()
+            .map(|s| s.s_y + s.s_height)
+            .unwrap_or(0);
+        while y + height > self.ga_height && self.ga_height < ATLAS_MAX_HEIGHT {
+            self.grow();
+        }
+        if y + height <= self.ga_height {
+            self.ga_shelves.push(Shelf {
+                s_y: y,
+                s_height: height,
+                s_x: 0,
+                s_last_used: self.ga_generation,
+                s_epoch: 0,
+            });
+            return Some(self.ga_shelves.len() - 1);
+        }
+
+        // We are out of space, so evict the least recently used shelf
+        let generation = self.ga_generation;
+        let (index, shelf) = self
+            .ga_shelves
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, s)| s.s_height >= height && s.s_last_used < generation)
+            .min_by_key(|(_, s)| s.s_last_used)?;
+        shelf.s_x = 0;
+        shelf.s_epoch += 1;
+
+        // Clear the old glyphs, since our padding relies on being empty
+        for row in shelf.s_y..(shelf.s_y + shelf.s_height) {
+            let start = (row * ATLAS_WIDTH * 4) as usize;
+            self.ga_data[start..start + (ATLAS_WIDTH * 4) as usize].fill(0);
+        }
+        self.ga_damage
+            .add(&Rect::new(0, shelf.s_y, ATLAS_WIDTH, shelf.s_height));
+
+        Some(index)
+    }
+
+    /// Make sure a glyph is in the atlas, adding it if needed
+    ///
+    /// `bitmap` holds the tightly packed BGRA contents of the glyph. This
+    /// returns the area of the atlas holding the glyph, or None if there is
+    /// no room for it.
+    pub fn add_glyph(
+        &mut self,
+        id: &DakotaId,
+        width: i32,
+        height: i32,
+        bitmap: &[u8],
+    ) -> Option<Rect<i32>> {
+        let shelf_index = match self.get_rect(id) {
+            Some(rect) => {
+                let shelf = self.ga_slots.get(id).unwrap().as_shelf;
+                self.ga_shelves[shelf].s_last_used = self.ga_generation;
+                return Some(rect);
+            }
+            None => self.allocate(width + ATLAS_PADDING, height + ATLAS_PADDING)?,
+        };
+
+        let shelf = &mut self.ga_shelves[shelf_index];
+        let rect = Rect::new(shelf.s_x, shelf.s_y, width, height);
+        shelf.s_x += width + ATLAS_PADDING;
+        shelf.s_last_used = self.ga_generation;
+
+        // Copy the glyph into place, one row at a time
+        let row_size = (width * 4) as usize;
+        for row in 0..height {
+            let src = row as usize * row_size;
+            let dst = (((rect.r_pos.1 + row) * ATLAS_WIDTH + rect.r_pos.0) * 4) as usize;
+            self.ga_data[dst..dst + row_size].copy_from_slice(&bitmap[src..src + row_size]);
+        }
+        self.ga_damage.add(&rect);
+
+        self.ga_slots.set(
+            id,
+            AtlasSlot {
+                as_rect: rect,
+                as_shelf: shelf_index,
+                as_epoch: shelf.s_epoch,
+            },
+        );
+
+        Some(rect)
+    }
+
+    /// Upload any changes to the atlas image
+    pub fn flush(&mut self, dev: &th::Device) -> Result<()> {
+        if self.ga_damage.is_empty() && !self.ga_resized {
+            return Ok(());
+        }
+
+        match self.ga_image.as_ref() {
+            Some(image) => {
+                // The whole image is reallocated when it changes size
+                let damage = match self.ga_resized {
+                    true => None,
+                    false => Some(self.ga_damage.clone()),
+                };
+                dev.update_image_from_bits(
+                    image,
+                    self.ga_data.as_slice(),
+                    ATLAS_WIDTH as u32,
+                    self.ga_height as u32,
+                    0,
+                    th::ShmFormat::ARGB8888,
+                    damage,
+                    None,
+                )
+                .context("Could not update glyph atlas")?;
+            }
+            None => {
+                self.ga_image = Some(
+                    dev.create_image_from_bits(
+                        self.ga_data.as_slice(),
+                        ATLAS_WIDTH as u32,
+                        self.ga_height as u32,
+                        0,
+                        th::ShmFormat::ARGB8888,
+                        None,
+                    )
+                    .context("Could not create glyph atlas image")?,
+                );
+            }
+        }
+
+        self.ga_damage = th::Damage::empty();
+        self.ga_resized = false;
+        Ok(())
+    }
+}
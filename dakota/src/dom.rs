@@ -8,19 +8,8 @@ use crate::DakotaId;
 use std::cmp::{Ord, PartialOrd};
 use std::sync::Arc;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum Format {
-    ARGB8888,
-    XRGB8888,
-}
-
-impl Format {
-    pub fn get_size(&self) -> usize {
-        match self {
-            Format::XRGB8888 | Format::ARGB8888 => 4,
-        }
-    }
-}
+/// The layout of pixels in memory, see `Output::get_supported_shm_formats`
+pub use th::ShmFormat as Format;
 
 #[derive(Debug, Clone)]
 pub struct Image {
@@ -33,6 +22,21 @@ pub struct Hints {
     pub constant: bool,
 }
 
+/// Nine-slice scaling for an image resource
+///
+/// These are the sizes of the image's borders in image pixels. When the
+/// resource is drawn the corners are kept at their original size, the
+/// edges are stretched along one axis, and the center is stretched to fill
+/// the rest of the element. This allows one image to be used for frames
+/// and buttons of any size.
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct NinePatch {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct Data {
     pub rel_path: Option<String>,
@@ -78,6 +82,150 @@ impl Color {
     }
 }
 
+/// The shape of a gradient
+#[derive(Copy, PartialEq, Clone, Debug)]
+pub enum GradientKind {
+    /// Colors change along the line from start to end
+    Linear,
+    /// Colors change with the distance from start. The radius is the
+    /// distance from start to end.
+    Radial,
+}
+
+/// A fill that blends between two colors across an element
+///
+/// The start and end points are in pixels relative to the element's
+/// top left corner. The element is filled with `start_color` before the
+/// start point and `end_color` past the end point.
+#[derive(Copy, PartialEq, Clone, Debug)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub start_color: Color,
+    pub end_color: Color,
+}
+
+impl Gradient {
+    /// Create a gradient along the line from `start` to `end`
+    pub fn linear(
+        start: (f32, f32),
+        end: (f32, f32),
+        start_color: Color,
+        end_color: Color,
+    ) -> Self {
+        Self {
+            kind: GradientKind::Linear,
+            start,
+            end,
+            start_color,
+            end_color,
+        }
+    }
+
+    /// Create a gradient which changes with the distance from `center`
+    pub fn radial(center: (f32, f32), radius: f32, inner_color: Color, outer_color: Color) -> Self {
+        Self {
+            kind: GradientKind::Radial,
+            start: center,
+            end: (center.0 + radius, center.1),
+            start_color: inner_color,
+            end_color: outer_color,
+        }
+    }
+}
+
+/// The purpose of an element, for accessibility tools
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// An element with no particular purpose
+    Generic,
+    Window,
+    Group,
+    Label,
+    Button,
+    Image,
+    List,
+    ListItem,
+    TextInput,
+    ScrollView,
+    Menu,
+    Tooltip,
+}
+
+/// Which side of its anchor a popup is placed on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PopupPlacement {
+    Below,
+    Above,
+    Right,
+    Left,
+}
+
+/// An element floating above the rest of the scene
+///
+/// Popups are placed next to their anchor element and drawn after the
+/// main element tree, so they are not clipped by the anchor's viewports.
+/// Menus, tooltips, and other overlays are popups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Popup {
+    /// The element this popup is placed next to
+    pub anchor: DakotaId,
+    pub placement: PopupPlacement,
+    /// Hide this popup when the user clicks outside of it
+    pub dismiss_on_outside_click: bool,
+}
+
+/// A named set of element properties
+///
+/// Elements reference styles by name with classes. Any property an element
+/// does not set itself is taken from its classes, with later classes taking
+/// precedence over earlier ones. A style may inherit the properties of
+/// another style, and any it sets replace the inherited ones.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Style {
+    pub name: String,
+    /// The name of the style to inherit properties from
+    pub inherits: Option<String>,
+    pub resource: Option<DakotaId>,
+    pub width: Option<Value>,
+    pub height: Option<Value>,
+    pub offset: Option<RelativeOffset>,
+    pub opacity: Option<f32>,
+    pub font: Option<DakotaId>,
+}
+
+impl Style {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Replace our properties with any that are set in `other`
+    pub fn apply(&mut self, other: &Style) {
+        if other.resource.is_some() {
+            self.resource = other.resource.clone();
+        }
+        if other.width.is_some() {
+            self.width = other.width;
+        }
+        if other.height.is_some() {
+            self.height = other.height;
+        }
+        if other.offset.is_some() {
+            self.offset = other.offset;
+        }
+        if other.opacity.is_some() {
+            self.opacity = other.opacity;
+        }
+        if other.font.is_some() {
+            self.font = other.font.clone();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Resource {
     pub name: String,
@@ -238,6 +386,8 @@ pub struct Font {
     pub name: String,
     /// This is the name of the font to use (for example Inconsolata)
     pub font_name: String,
+    /// The style of the typeface to use (for example Bold or Italic)
+    pub style: Option<String>,
     pub pixel_size: u32,
     pub color: Option<Color>,
 }
@@ -246,6 +396,9 @@ pub struct Font {
 #[derive(Debug, Clone)]
 pub struct TextRun {
     pub value: String,
+    /// The font to use for this run. If this is None the font of the
+    /// Text is used.
+    pub font: Option<DakotaId>,
     pub cache: Option<Vec<CachedChar>>,
 }
 
@@ -258,6 +411,9 @@ pub struct TextRun {
 pub enum TextItem {
     p(TextRun),
     b(TextRun),
+    /// A run with its own font, for changing the size, color, or style of
+    /// part of a paragraph
+    span(TextRun),
 }
 
 /// Represnts a collection of text items
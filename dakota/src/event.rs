@@ -3,7 +3,8 @@
 // Austin Shafer - 2022
 
 use crate::input::{Keycode, Mods, MouseButton};
-use std::collections::VecDeque;
+use crate::{OutputInfo, TimerId};
+use std::collections::{HashMap, VecDeque};
 
 /// Global Dakota Event Queue
 pub struct GlobalEventSystem {
@@ -30,8 +31,20 @@ pub enum GlobalEvent {
     /// dakota `select()` a set of fds and wake the application up
     /// when they are ready.
     UserFdReadable,
+    /// A timer added with `Dakota::add_timer` or
+    /// `Dakota::add_repeating_timer` expired
+    Timer(TimerId),
     /// Dakota is quitting, the app should terminate
     Quit,
+    /// At least one child process of the app exited and can be waited
+    /// on. Several exits may be reported by one event.
+    ChildExited,
+    /// A display was plugged in. The app may create an Output from
+    /// this info to start drawing on it.
+    OutputAdded(OutputInfo),
+    /// A display was unplugged. Any Outputs created from this info
+    /// will receive the `Destroyed` event and should be dropped.
+    OutputRemoved(OutputInfo),
 }
 
 impl GlobalEventSystem {
@@ -39,6 +52,10 @@ impl GlobalEventSystem {
         self.es_event_queue.push_back(GlobalEvent::UserFdReadable);
     }
 
+    pub fn add_event_timer(&mut self, id: TimerId) {
+        self.es_event_queue.push_back(GlobalEvent::Timer(id));
+    }
+
     /// Notify the app that a window was closed
     ///
     /// This is not an optional event. It will always be sent. It is
@@ -47,6 +64,23 @@ impl GlobalEventSystem {
         self.es_event_queue.push_back(GlobalEvent::Quit);
     }
 
+    /// Notify the app that its children need to be waited on
+    pub fn add_event_child_exited(&mut self) {
+        self.es_event_queue.push_back(GlobalEvent::ChildExited);
+    }
+
+    /// Notify the app that a display was plugged in
+    pub fn add_event_output_added(&mut self, info: OutputInfo) {
+        self.es_event_queue
+            .push_back(GlobalEvent::OutputAdded(info));
+    }
+
+    /// Notify the app that a display was unplugged
+    pub fn add_event_output_removed(&mut self, info: OutputInfo) {
+        self.es_event_queue
+            .push_back(GlobalEvent::OutputRemoved(info));
+    }
+
     /// Drain the queue of currently unhandled events
     ///
     /// The app should do this in its main loop after dispatching.
@@ -77,6 +111,19 @@ pub enum OutputEvent {
     ///
     /// This happens on window systems, when the window needs redrawn.
     Redraw,
+    /// The GPU this output renders on is running low on memory. The
+    /// app should free any resources it does not need, such as images
+    /// which are not currently shown.
+    LowMemory,
+    /// An image being loaded in the background for a Scene created from
+    /// this output has finished decoding. The scene should be recompiled
+    /// to show it.
+    AssetLoaded,
+    /// The GPU this output renders on was lost, normally because of a
+    /// driver reset. Nothing can be drawn until the app calls
+    /// `Dakota::recover_from_device_loss`, after which it must define
+    /// its resources again.
+    DeviceLost,
 }
 
 impl OutputEventSystem {
@@ -106,6 +153,21 @@ impl OutputEventSystem {
         self.es_event_queue.push_back(OutputEvent::Redraw);
     }
 
+    /// Notify the app that the GPU is running low on memory
+    pub fn add_event_low_memory(&mut self) {
+        self.es_event_queue.push_back(OutputEvent::LowMemory);
+    }
+
+    /// Notify the app that a Scene's image finished loading
+    pub fn add_event_asset_loaded(&mut self) {
+        self.es_event_queue.push_back(OutputEvent::AssetLoaded);
+    }
+
+    /// Notify the app that the GPU was lost
+    pub fn add_event_device_lost(&mut self) {
+        self.es_event_queue.push_back(OutputEvent::DeviceLost);
+    }
+
     /// Notify the app that a window was closed
     ///
     /// This is not an optional event. It will always be sent. It is
@@ -133,6 +195,18 @@ pub struct PlatformEventSystem {
     /// location. This adds a place to cache this. The platforms will
     /// report relative mouse changes and we will update this here.
     es_mouse_pos: (i32, i32),
+    /// Recent motion of each input device
+    es_history: HashMap<InputDevice, EventHistory>,
+    /// The position of each finger on a touchscreen, indexed by touch id
+    ///
+    /// Touch up events don't report a position, so we report the last
+    /// place the finger was seen.
+    es_touch_points: HashMap<i32, (i32, i32)>,
+    /// The size of the virtual output these events are delivered to
+    ///
+    /// Touchscreens report absolute positions scaled to the output, so
+    /// platforms need to know how big it is.
+    es_output_size: (u32, u32),
 }
 
 impl PlatformEventSystem {
@@ -140,7 +214,92 @@ impl PlatformEventSystem {
         Self {
             es_event_queue: VecDeque::new(),
             es_mouse_pos: (0, 0),
+            es_history: HashMap::new(),
+            es_touch_points: HashMap::new(),
+            es_output_size: (0, 0),
+        }
+    }
+}
+
+/// The number of samples kept for each input device
+const EVENT_HISTORY_LEN: usize = 32;
+/// Only samples this recent are used to estimate velocity, in microseconds
+const EVENT_HISTORY_WINDOW: u64 = 100_000;
+
+/// Input devices which we keep a history of motion for
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum InputDevice {
+    /// Mouse or touchpad pointer motion
+    Pointer,
+    /// Scroll wheels and touchpad scrolling
+    Scroll,
+}
+
+/// The recent motion of an input device
+///
+/// This holds the last few relative motions reported by a device along
+/// with their timestamps, which gestures use to find how fast the device
+/// is moving.
+#[derive(Debug, Clone, Default)]
+pub struct EventHistory {
+    /// (time in microseconds, relative motion), oldest first
+    eh_samples: VecDeque<(u64, (f64, f64))>,
+}
+
+impl EventHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record motion reported at `time`
+    ///
+    /// Samples must be added in the order they happened.
+    pub fn push(&mut self, time: u64, motion: (f64, f64)) {
+        if self.eh_samples.len() == EVENT_HISTORY_LEN {
+            self.eh_samples.pop_front();
+        }
+        self.eh_samples.push_back((time, motion));
+    }
+
+    pub fn clear(&mut self) {
+        self.eh_samples.clear();
+    }
+
+    /// Get the recorded samples, oldest first
+    pub fn samples(&self) -> impl Iterator<Item = &(u64, (f64, f64))> {
+        self.eh_samples.iter()
+    }
+
+    /// The time of the most recent sample
+    pub fn last_time(&self) -> Option<u64> {
+        self.eh_samples.back().map(|(time, _)| *time)
+    }
+
+    /// Estimate the velocity of the device, in units per second
+    ///
+    /// This averages the motion reported in the last
+    /// EVENT_HISTORY_WINDOW microseconds before `now`. Motion is reported
+    /// for the time since the previous sample, so the oldest sample in the
+    /// window only marks where the window starts. Returns zero if there is
+    /// not enough recent motion.
+    pub fn velocity(&self, now: u64) -> (f64, f64) {
+        let mut recent = self
+            .eh_samples
+            .iter()
+            .filter(|(time, _)| *time <= now && now - *time <= EVENT_HISTORY_WINDOW);
+        let start = match recent.next() {
+            Some((time, _)) => *time,
+            None => return (0.0, 0.0),
+        };
+        let (end, total) = recent.fold((start, (0.0, 0.0)), |(_, acc), (time, motion)| {
+            (*time, (acc.0 + motion.0, acc.1 + motion.1))
+        });
+        if end == start {
+            return (0.0, 0.0);
         }
+
+        let elapsed = (end - start) as f64 / 1_000_000.0;
+        (total.0 / elapsed, total.1 / elapsed)
     }
 }
 
@@ -154,6 +313,18 @@ pub enum AxisSource {
     Finger = 1,
 }
 
+/// The kind of tool being used on a drawing tablet
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TabletToolType {
+    Pen,
+    Eraser,
+    Brush,
+    Pencil,
+    Airbrush,
+    Mouse,
+    Lens,
+}
+
 /// This represents the raw integer keycode that the system gave us.
 ///
 /// These are identified by encoding, in case the application wants to
@@ -172,6 +343,10 @@ pub enum RawKeycode {
 /// These events are delivered on a virtual output and represent window
 /// system events that are relevant to the surface the scene is being
 /// applied to. The main event type delieverd here is user input.
+///
+/// Input events carry the time the hardware reported them at, in
+/// microseconds. This comes from the platform's clock, so only the
+/// differences between event times are meaningful.
 #[derive(Debug, Clone)]
 pub enum PlatformEvent {
     /// Key has been pressed. Includes the updated modifiers.
@@ -180,6 +355,8 @@ pub enum PlatformEvent {
         /// Any utf8 characters that were generated by this keystroke
         utf8: String,
         raw_keycode: RawKeycode,
+        /// When this happened, in microseconds
+        time: u64,
     },
     /// Key has been released.
     InputKeyUp {
@@ -187,20 +364,32 @@ pub enum PlatformEvent {
         /// Any utf8 characters that were generated by this keystroke
         utf8: String,
         raw_keycode: RawKeycode,
+        /// When this happened, in microseconds
+        time: u64,
     },
     /// The set of active Keyboard Modifier keys has changed. The modifier
     /// keypresses are also delivered in the `InputKey*` events, but the current
     /// set of modifiers is included separately here for convenience.
-    InputKeyboardModifiers { mods: Mods },
+    InputKeyboardModifiers { mods: Mods, time: u64 },
     /// Movement of the mouse relative to the previous position
     ///
     /// This is the amount the mouse moved.
-    InputMouseMove { dx: i32, dy: i32 },
+    InputMouseMove { dx: i32, dy: i32, time: u64 },
     /// A mouse button has been pressed. The button is specified
     /// in the case that there are multiple buttons on the mouse.
-    InputMouseButtonDown { button: MouseButton, x: i32, y: i32 },
+    InputMouseButtonDown {
+        button: MouseButton,
+        x: i32,
+        y: i32,
+        time: u64,
+    },
     /// A mouse button has been released
-    InputMouseButtonUp { button: MouseButton, x: i32, y: i32 },
+    InputMouseButtonUp {
+        button: MouseButton,
+        x: i32,
+        y: i32,
+        time: u64,
+    },
     /// User has taken a scrolling action.
     ///
     /// This is complex since there are a variety of scrolling options
@@ -224,52 +413,182 @@ pub enum PlatformEvent {
         v120_val: (f64, f64),
         /// The axis source.
         source: AxisSource,
+        /// When this happened, in microseconds
+        time: u64,
     },
+    /// A finger touched a touchscreen
+    ///
+    /// `id` identifies this finger until it is lifted, after which it
+    /// may be reused. Multiple fingers may be down at once.
+    InputTouchDown { id: i32, x: i32, y: i32, time: u64 },
+    /// A finger on a touchscreen moved to a new position
+    InputTouchMotion { id: i32, x: i32, y: i32, time: u64 },
+    /// A finger was lifted from a touchscreen
+    ///
+    /// The position is the last place the finger was seen.
+    InputTouchUp { id: i32, x: i32, y: i32, time: u64 },
+    /// All touch points were cancelled
+    ///
+    /// This happens when the system takes over the touch sequence. The
+    /// fingers should be treated as gone without any action being taken.
+    InputTouchCancel { time: u64 },
+    /// Fingers started swiping on a touchpad
+    InputSwipeBegin {
+        /// The number of fingers being swiped
+        fingers: u32,
+        time: u64,
+    },
+    /// The fingers of a swipe moved
+    ///
+    /// This is the amount the center of the fingers moved.
+    InputSwipeUpdate { dx: f64, dy: f64, time: u64 },
+    /// The fingers of a swipe were lifted
+    ///
+    /// If `cancelled` is true the swipe should not take effect, such as
+    /// when another finger touches the touchpad.
+    InputSwipeEnd { cancelled: bool, time: u64 },
+    /// Fingers started pinching on a touchpad
+    InputPinchBegin {
+        /// The number of fingers in the pinch
+        fingers: u32,
+        time: u64,
+    },
+    /// The fingers of a pinch moved
+    InputPinchUpdate {
+        /// The amount the center of the fingers moved
+        dx: f64,
+        dy: f64,
+        /// The distance between the fingers relative to when the pinch
+        /// began. Values above 1.0 mean the fingers spread apart, which is
+        /// usually zooming in.
+        scale: f64,
+        /// Clockwise rotation in degrees since the last update
+        rotation: f64,
+        time: u64,
+    },
+    /// The fingers of a pinch were lifted
+    ///
+    /// If `cancelled` is true the pinch should not take effect.
+    InputPinchEnd { cancelled: bool, time: u64 },
+    /// A tablet tool came into or left proximity of the tablet
+    ///
+    /// Tools are in proximity when they are close enough to the tablet
+    /// for their position to be reported. All other tablet tool events
+    /// happen between a tool entering and leaving proximity.
+    InputTabletToolProximity {
+        tool: TabletToolType,
+        /// Identifies this physical tool. This is zero if the tablet
+        /// can't tell tools of the same type apart.
+        serial: u64,
+        in_proximity: bool,
+        x: i32,
+        y: i32,
+        time: u64,
+    },
+    /// A tablet tool moved or its pressure or tilt changed
+    InputTabletToolAxis {
+        tool: TabletToolType,
+        serial: u64,
+        x: i32,
+        y: i32,
+        /// Pressure of the tip, from 0.0 to 1.0
+        pressure: f64,
+        /// (x, y) tilt of the tool away from vertical, in degrees
+        tilt: (f64, f64),
+        time: u64,
+    },
+    /// The tip of a tablet tool touched or was lifted from the tablet
+    InputTabletToolTip {
+        tool: TabletToolType,
+        serial: u64,
+        down: bool,
+        x: i32,
+        y: i32,
+        time: u64,
+    },
+    /// A button on a tablet tool was pressed or released
+    InputTabletToolButton {
+        tool: TabletToolType,
+        serial: u64,
+        /// The Linux kernel button code, such as BTN_STYLUS
+        button: u32,
+        pressed: bool,
+        time: u64,
+    },
+    /// The following input events come from devices on this seat
+    ///
+    /// Devices are put on seats with `DeviceSettings::seat`, and ones
+    /// without a seat are on the `default` seat. This is only sent when
+    /// the seat changes, so events are on the default seat until the
+    /// first one of these.
+    InputSeat { seat: String },
+    /// The window moved to a display with a different DPI
+    ///
+    /// Text should be rasterized again at the new scale, see
+    /// `Scene::set_text_scale`.
+    DpiChanged { dpi: (i32, i32) },
+    /// The window moved to a different display
+    ///
+    /// `refresh` is the refresh rate of the new display in mHz, or zero
+    /// if it is unknown.
+    DisplayChanged { index: i32, refresh: u32 },
 }
 
 impl PlatformEventSystem {
-    pub fn add_event_key_down(&mut self, key: Keycode, utf8: String, raw_key: RawKeycode) {
+    pub fn add_event_key_down(
+        &mut self,
+        key: Keycode,
+        utf8: String,
+        raw_key: RawKeycode,
+        time: u64,
+    ) {
         self.es_event_queue.push_back(PlatformEvent::InputKeyDown {
             key: key,
             utf8: utf8,
             raw_keycode: raw_key,
+            time,
         });
     }
-    pub fn add_event_key_up(&mut self, key: Keycode, utf8: String, raw_key: RawKeycode) {
+    pub fn add_event_key_up(&mut self, key: Keycode, utf8: String, raw_key: RawKeycode, time: u64) {
         self.es_event_queue.push_back(PlatformEvent::InputKeyUp {
             key: key,
             utf8: utf8,
             raw_keycode: raw_key,
+            time,
         });
     }
 
-    pub fn add_event_keyboard_modifiers(&mut self, mods: Mods) {
+    pub fn add_event_keyboard_modifiers(&mut self, mods: Mods, time: u64) {
         self.es_event_queue
-            .push_back(PlatformEvent::InputKeyboardModifiers { mods: mods });
+            .push_back(PlatformEvent::InputKeyboardModifiers { mods, time });
     }
 
-    pub fn add_event_mouse_move(&mut self, dx: i32, dy: i32) {
+    pub fn add_event_mouse_move(&mut self, dx: i32, dy: i32, time: u64) {
         // Update our cached mouse position
         self.es_mouse_pos.0 += dx;
         self.es_mouse_pos.1 += dy;
+        self.get_history_mut(InputDevice::Pointer)
+            .push(time, (dx as f64, dy as f64));
 
         self.es_event_queue
-            .push_back(PlatformEvent::InputMouseMove { dx: dx, dy: dy });
+            .push_back(PlatformEvent::InputMouseMove { dx, dy, time });
     }
-    pub fn add_event_mouse_button_down(&mut self, button: MouseButton) {
+    pub fn add_event_mouse_button_down(&mut self, button: MouseButton, time: u64) {
         self.es_event_queue
             .push_back(PlatformEvent::InputMouseButtonDown {
                 button: button,
                 x: self.es_mouse_pos.0,
                 y: self.es_mouse_pos.1,
+                time,
             });
     }
-    pub fn add_event_mouse_button_up(&mut self, button: MouseButton) {
+    pub fn add_event_mouse_button_up(&mut self, button: MouseButton, time: u64) {
         self.es_event_queue
             .push_back(PlatformEvent::InputMouseButtonUp {
                 button: button,
                 x: self.es_mouse_pos.0,
                 y: self.es_mouse_pos.1,
+                time,
             });
     }
 
@@ -279,16 +598,200 @@ impl PlatformEventSystem {
         y: Option<i32>,
         v120: (f64, f64),
         source: AxisSource,
+        time: u64,
     ) {
+        // A zero motion ends the scroll, which is not motion itself
+        let motion = (x.unwrap_or(0) as f64, y.unwrap_or(0) as f64);
+        if motion != (0.0, 0.0) {
+            self.get_history_mut(InputDevice::Scroll).push(time, motion);
+        }
+
         self.es_event_queue.push_back(PlatformEvent::InputScroll {
             position: self.es_mouse_pos,
             xrel: x,
             yrel: y,
             v120_val: v120,
             source: source,
+            time,
         });
     }
 
+    pub fn add_event_touch_down(&mut self, id: i32, x: i32, y: i32, time: u64) {
+        self.es_touch_points.insert(id, (x, y));
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTouchDown { id, x, y, time });
+    }
+    pub fn add_event_touch_motion(&mut self, id: i32, x: i32, y: i32, time: u64) {
+        self.es_touch_points.insert(id, (x, y));
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTouchMotion { id, x, y, time });
+    }
+    pub fn add_event_touch_up(&mut self, id: i32, time: u64) {
+        // Ignore fingers we never saw go down
+        let (x, y) = match self.es_touch_points.remove(&id) {
+            Some(pos) => pos,
+            None => return,
+        };
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTouchUp { id, x, y, time });
+    }
+    pub fn add_event_touch_cancel(&mut self, time: u64) {
+        self.es_touch_points.clear();
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTouchCancel { time });
+    }
+
+    pub fn add_event_swipe_begin(&mut self, fingers: u32, time: u64) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputSwipeBegin { fingers, time });
+    }
+    pub fn add_event_swipe_update(&mut self, dx: f64, dy: f64, time: u64) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputSwipeUpdate { dx, dy, time });
+    }
+    pub fn add_event_swipe_end(&mut self, cancelled: bool, time: u64) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputSwipeEnd { cancelled, time });
+    }
+    pub fn add_event_pinch_begin(&mut self, fingers: u32, time: u64) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputPinchBegin { fingers, time });
+    }
+    pub fn add_event_pinch_update(
+        &mut self,
+        dx: f64,
+        dy: f64,
+        scale: f64,
+        rotation: f64,
+        time: u64,
+    ) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputPinchUpdate {
+                dx,
+                dy,
+                scale,
+                rotation,
+                time,
+            });
+    }
+    pub fn add_event_pinch_end(&mut self, cancelled: bool, time: u64) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputPinchEnd { cancelled, time });
+    }
+
+    pub fn add_event_tablet_tool_proximity(
+        &mut self,
+        tool: TabletToolType,
+        serial: u64,
+        in_proximity: bool,
+        position: (i32, i32),
+        time: u64,
+    ) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTabletToolProximity {
+                tool,
+                serial,
+                in_proximity,
+                x: position.0,
+                y: position.1,
+                time,
+            });
+    }
+    pub fn add_event_tablet_tool_axis(
+        &mut self,
+        tool: TabletToolType,
+        serial: u64,
+        position: (i32, i32),
+        pressure: f64,
+        tilt: (f64, f64),
+        time: u64,
+    ) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTabletToolAxis {
+                tool,
+                serial,
+                x: position.0,
+                y: position.1,
+                pressure,
+                tilt,
+                time,
+            });
+    }
+    pub fn add_event_tablet_tool_tip(
+        &mut self,
+        tool: TabletToolType,
+        serial: u64,
+        down: bool,
+        position: (i32, i32),
+        time: u64,
+    ) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTabletToolTip {
+                tool,
+                serial,
+                down,
+                x: position.0,
+                y: position.1,
+                time,
+            });
+    }
+    pub fn add_event_tablet_tool_button(
+        &mut self,
+        tool: TabletToolType,
+        serial: u64,
+        button: u32,
+        pressed: bool,
+        time: u64,
+    ) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputTabletToolButton {
+                tool,
+                serial,
+                button,
+                pressed,
+                time,
+            });
+    }
+    pub fn add_event_seat(&mut self, seat: String) {
+        self.es_event_queue
+            .push_back(PlatformEvent::InputSeat { seat });
+    }
+    pub fn add_event_dpi_changed(&mut self, dpi: (i32, i32)) {
+        self.es_event_queue
+            .push_back(PlatformEvent::DpiChanged { dpi });
+    }
+    pub fn add_event_display_changed(&mut self, index: i32, refresh: u32) {
+        self.es_event_queue
+            .push_back(PlatformEvent::DisplayChanged { index, refresh });
+    }
+
+    /// Get the fingers currently on the touchscreen
+    ///
+    /// Returns (touch id, position) for each one.
+    pub fn get_touch_points(&self) -> Vec<(i32, (i32, i32))> {
+        self.es_touch_points
+            .iter()
+            .map(|(id, pos)| (*id, *pos))
+            .collect()
+    }
+
+    pub fn get_output_size(&self) -> (u32, u32) {
+        self.es_output_size
+    }
+
+    pub fn set_output_size(&mut self, size: (u32, u32)) {
+        self.es_output_size = size;
+    }
+
+    fn get_history_mut(&mut self, device: InputDevice) -> &mut EventHistory {
+        self.es_history.entry(device).or_default()
+    }
+
+    /// Get the recent motion of an input device
+    pub fn get_history(&self, device: InputDevice) -> Option<&EventHistory> {
+        self.es_history.get(&device)
+    }
+
     /// Get the next event
     ///
     /// The app should do this in its main loop after dispatching.
@@ -5,9 +5,23 @@ extern crate harfbuzz_sys as hb_sys;
 use crate::DakotaId;
 use lluvia as ll;
 
+use std::ops::Range;
+
+/// Font families to search for characters missing from a font
+///
+/// These are resolved through fontconfig, so generic names will find the
+/// system's preferred font for them.
+pub(crate) const FALLBACK_FONTS: &[&str] = &["sans-serif", "emoji", "Noto Sans CJK SC"];
+
 // Define this ourselves since hb crate doesn't do it
 extern "C" {
     pub fn hb_ft_font_create_referenced(face: ft::ffi::FT_Face) -> *mut hb_sys::hb_font_t;
+    pub fn hb_ft_font_changed(font: *mut hb_sys::hb_font_t);
+}
+
+/// Get the pixel size to rasterize a font at for this text scale
+pub(crate) fn scale_pixel_size(pixel_size: u32, scale: f32) -> u32 {
+    ((pixel_size as f32 * scale).round() as u32).max(1)
 }
 
 #[derive(Debug)]
@@ -28,11 +42,15 @@ pub struct Cursor {
 
 #[derive(Clone)]
 pub struct Glyph {
-    /// The thundr image backing this glyph.
+    /// The tightly packed BGRA pixels of this glyph. These are copied
+    /// into the glyph atlas when the glyph is used.
     /// This will be none if the glyph does not have an outline
     /// which happens if it's a space.
-    pub g_image: Option<th::Image>,
+    pub g_bitmap: Option<Vec<u8>>,
     pub g_bitmap_size: (i32, i32),
+    /// The bitmap holds the coverage of each subpixel, and should be
+    /// drawn with `th::Surface::set_subpixel`
+    pub g_subpixel: bool,
     pub g_bitmap_left: i32,
     pub g_bitmap_top: i32,
     _g_metrics: ft::GlyphMetrics,
@@ -62,6 +80,9 @@ pub struct CachedChar {
     pub glyph_id: DakotaId,
     /// The raw freetype glyph index
     pub raw_glyph_id: u16,
+    /// The character this glyph was shaped from. This is used to find
+    /// word and line breaks.
+    pub ch: char,
     /// The final offset calculated by freetype/harfbuzz that we will add to the
     /// cursor when laying out text.
     pub cursor_advance: (i32, i32),
@@ -91,6 +112,13 @@ pub struct FontInstance {
     /// The ab::GlyphId is really just an index into this. That's all
     /// glyph ids are, is the index of the glyph in the font.
     f_glyphs: Vec<Option<DakotaId>>,
+    /// Fonts to use for characters this font does not have, in order of
+    /// preference
+    f_fallbacks: Vec<FontInstance>,
+    /// The subpixel layout to rasterize glyphs for
+    f_subpixel: th::SubpixelLayout,
+    /// The size glyphs are rasterized at
+    f_pixel_size: u32,
 }
 
 impl FontInstance {
@@ -99,52 +127,232 @@ impl FontInstance {
     /// This is a particular font from a typeface at a
     /// particular size. Size is specified in points.
     pub fn new(ft_lib: &ft::Library, font_path: &str, pixel_size: u32) -> Self {
-        let mut ft_face: ft::Face = ft_lib.new_face(font_path, 0).unwrap();
-        let raw_font =
-            unsafe { hb_ft_font_create_referenced(ft_face.raw_mut() as *mut ft::ffi::FT_FaceRec) };
+        Self::try_new(ft_lib, font_path, pixel_size).expect("Could not set freetype char size")
+    }
+
+    /// Create a new font, returning None if it can't be used at this size
+    ///
+    /// This happens with bitmap fonts which only come in fixed sizes.
+    pub fn try_new(ft_lib: &ft::Library, font_path: &str, pixel_size: u32) -> Option<Self> {
+        let mut ft_face: ft::Face = ft_lib.new_face(font_path, 0).ok()?;
 
         ft_face
             .set_pixel_sizes(pixel_size, pixel_size)
             //.set_point_sizes(point_size as u32, point_size as u32)
-            .expect("Could not set freetype char size");
+            .ok()?;
 
-        Self {
+        let raw_font =
+            unsafe { hb_ft_font_create_referenced(ft_face.raw_mut() as *mut ft::ffi::FT_FaceRec) };
+
+        Some(Self {
             f_ft_face: ft_face,
             f_hb_raw_font: raw_font,
             f_glyphs: Vec::new(),
-        }
+            f_fallbacks: Vec::new(),
+            f_subpixel: th::SubpixelLayout::None,
+            f_pixel_size: pixel_size,
+        })
     }
 
-    fn create_glyph(
+    /// Set the subpixel layout to rasterize glyphs for
+    ///
+    /// Glyphs which have already been created are rasterized again,
+    /// keeping their ids so that shaped text does not need to be updated.
+    pub fn set_subpixel_layout(
         &mut self,
-        dev: &th::Device,
-        inst: &mut ll::Instance,
         glyphs: &mut ll::Snapshot<Glyph>,
-        id: u16,
-    ) -> DakotaId {
-        let flags = match self.f_ft_face.has_color() {
-            true => ft::face::LoadFlag::COLOR,
-            false => ft::face::LoadFlag::DEFAULT,
+        layout: th::SubpixelLayout,
+    ) {
+        for fallback in self.f_fallbacks.iter_mut() {
+            fallback.set_subpixel_layout(glyphs, layout);
+        }
+        if self.f_subpixel == layout {
+            return;
+        }
+        self.f_subpixel = layout;
+
+        for i in 0..self.f_glyphs.len() {
+            if let Some(id) = self.f_glyphs[i].clone() {
+                let glyph = self.render_glyph(i as u16);
+                glyphs.set(&id, glyph);
+            }
+        }
+    }
+
+    /// Add a font to use for characters this font does not have
+    ///
+    /// Fallbacks are searched in the order they were added.
+    pub fn add_fallback(&mut self, fallback: FontInstance) {
+        self.f_fallbacks.push(fallback);
+    }
+
+    /// Change the size glyphs are rasterized at
+    ///
+    /// This is used when the scale of the display changes. Glyphs which
+    /// have already been created are rasterized again, keeping their ids.
+    /// Text needs to be shaped again afterwards, as its metrics change.
+    pub fn set_pixel_size(&mut self, glyphs: &mut ll::Snapshot<Glyph>, pixel_size: u32) {
+        for fallback in self.f_fallbacks.iter_mut() {
+            fallback.set_pixel_size(glyphs, pixel_size);
+        }
+        if self.f_pixel_size == pixel_size {
+            return;
+        }
+
+        // Bitmap fonts only come in some sizes, keep the old one if
+        // the new size isn't available
+        if self
+            .f_ft_face
+            .set_pixel_sizes(pixel_size, pixel_size)
+            .is_err()
+        {
+            return;
+        }
+        self.f_pixel_size = pixel_size;
+        // Harfbuzz caches the size of the face
+        unsafe { hb_ft_font_changed(self.f_hb_raw_font) };
+
+        for i in 0..self.f_glyphs.len() {
+            if let Some(id) = self.f_glyphs[i].clone() {
+                let glyph = self.render_glyph(i as u16);
+                glyphs.set(&id, glyph);
+            }
+        }
+    }
+
+    /// Does this font have a glyph for this character
+    fn has_char(&self, ch: char) -> bool {
+        self.f_ft_face.get_char_index(ch as usize).unwrap_or(0) != 0
+    }
+
+    /// Get the font to shape this character with
+    ///
+    /// Zero is this font, and other values are the index of the fallback
+    /// plus one. If no font has this character we use this one, which
+    /// will draw its missing glyph box.
+    fn get_font_index_for_char(&self, ch: char) -> usize {
+        if self.has_char(ch) {
+            return 0;
+        }
+
+        match self.f_fallbacks.iter().position(|f| f.has_char(ch)) {
+            Some(i) => i + 1,
+            None => 0,
+        }
+    }
+
+    /// Split text into runs which can each be shaped by one font
+    ///
+    /// Returns the font index (see `get_font_index_for_char`) and the byte
+    /// range of each run. Whitespace stays in the current run, so that
+    /// runs aren't split between words.
+    fn itemize(&self, text: &str) -> Vec<(usize, Range<usize>)> {
+        let mut ret: Vec<(usize, Range<usize>)> = Vec::new();
+
+        for (i, ch) in text.char_indices() {
+            let end = i + ch.len_utf8();
+            match ret.last_mut() {
+                Some((_, range)) if ch.is_whitespace() => range.end = end,
+                Some((font, range)) if *font == self.get_font_index_for_char(ch) => range.end = end,
+                _ => ret.push((self.get_font_index_for_char(ch), i..end)),
+            }
+        }
+
+        ret
+    }
+
+    /// Rasterize a glyph
+    ///
+    /// Outline glyphs are rendered for our subpixel layout if we have one.
+    /// Color glyphs are always rendered normally.
+    fn render_glyph(&mut self, id: u16) -> Glyph {
+        let (flags, mode) = match (self.f_ft_face.has_color(), self.f_subpixel) {
+            (true, _) => (
+                ft::face::LoadFlag::COLOR,
+                ft::render_mode::RenderMode::Normal,
+            ),
+            (false, th::SubpixelLayout::None) => (
+                ft::face::LoadFlag::DEFAULT,
+                ft::render_mode::RenderMode::Normal,
+            ),
+            (false, th::SubpixelLayout::Rgb | th::SubpixelLayout::Bgr) => (
+                ft::face::LoadFlag::TARGET_LCD,
+                ft::render_mode::RenderMode::Lcd,
+            ),
+            (false, th::SubpixelLayout::VerticalRgb | th::SubpixelLayout::VerticalBgr) => (
+                ft::face::LoadFlag::TARGET_LCD_V,
+                ft::render_mode::RenderMode::LcdV,
+            ),
         };
         self.f_ft_face.load_glyph(id as u32, flags).unwrap();
         let glyph = self.f_ft_face.glyph();
-        glyph
-            .render_glyph(ft::render_mode::RenderMode::Normal)
-            .unwrap();
+        glyph.render_glyph(mode).unwrap();
         let bitmap = glyph.bitmap();
+        let mut size = (bitmap.width(), bitmap.rows());
+        let mut subpixel = false;
 
         // If the glyph does not have a bitmap, it's an invisible character and
         // we shouldn't make an image for it.
-        let th_image = if bitmap.rows() > 0 {
-            let width = bitmap.width() as usize;
-            let height = bitmap.rows() as usize;
+        let pixels = if bitmap.rows() > 0 {
+            let pixel_mode = bitmap.pixel_mode().expect("Failed to query pixel mode");
+
+            // LCD bitmaps have three samples per pixel
+            match pixel_mode {
+                ft::bitmap::PixelMode::Lcd => size.0 /= 3,
+                ft::bitmap::PixelMode::LcdV => size.1 /= 3,
+                _ => {}
+            }
+            let width = size.0 as usize;
+            let height = size.1 as usize;
             let mut img: Vec<u8> = std::iter::repeat(0)
                 .take(width * height * 4 as usize)
                 .collect();
 
-            let pixel_mode = bitmap.pixel_mode().expect("Failed to query pixel mode");
-
-            if pixel_mode == ft::bitmap::PixelMode::Gray {
+            if pixel_mode == ft::bitmap::PixelMode::Lcd || pixel_mode == ft::bitmap::PixelMode::LcdV
+            {
+                // Handle Subpixel Coverage
+                // ------------------------
+                //
+                // Each pixel has a red, green, and blue coverage value. These
+                // are stored in the matching channels of our BGRA image, and
+                // the alpha is their average for when subpixel blending
+                // isn't available.
+                let b = bitmap.buffer();
+                let pitch = bitmap.pitch().unsigned_abs() as usize;
+                let bgr = matches!(
+                    self.f_subpixel,
+                    th::SubpixelLayout::Bgr | th::SubpixelLayout::VerticalBgr
+                );
+
+                for y in 0..height {
+                    for x in 0..width {
+                        // The offsets of the three samples for this pixel
+                        let samples = match pixel_mode {
+                            ft::bitmap::PixelMode::Lcd => {
+                                let off = y * pitch + x * 3;
+                                [off, off + 1, off + 2]
+                            }
+                            _ => [
+                                y * 3 * pitch + x,
+                                (y * 3 + 1) * pitch + x,
+                                (y * 3 + 2) * pitch + x,
+                            ],
+                        };
+                        let (mut red, green, mut blue) =
+                            (b[samples[0]], b[samples[1]], b[samples[2]]);
+                        if bgr {
+                            std::mem::swap(&mut red, &mut blue);
+                        }
+
+                        let idx = (y * width + x) * 4;
+                        img[idx] = blue;
+                        img[idx + 1] = green;
+                        img[idx + 2] = red;
+                        img[idx + 3] = ((red as u32 + green as u32 + blue as u32) / 3) as u8;
+                    }
+                }
+                subpixel = true;
+            } else if pixel_mode == ft::bitmap::PixelMode::Gray {
                 // Handle Gray Pixels
                 // ------------------
                 //
@@ -179,32 +387,32 @@ impl FontInstance {
                 unimplemented!("Unimplemented freetype pixel mode {:?}", pixel_mode);
             }
 
-            Some(
-                dev.create_image_from_bits(
-                    img.as_slice(),
-                    width as u32,
-                    bitmap.rows() as u32,
-                    0,
-                    None,
-                )
-                .unwrap(),
-            )
+            Some(img)
         } else {
             None
         };
 
+        Glyph {
+            g_bitmap: pixels,
+            g_bitmap_size: size,
+            g_subpixel: subpixel,
+            g_bitmap_left: glyph.bitmap_left(),
+            g_bitmap_top: glyph.bitmap_top(),
+            _g_metrics: glyph.metrics(),
+        }
+    }
+
+    fn create_glyph(
+        &mut self,
+        inst: &mut ll::Instance,
+        glyphs: &mut ll::Snapshot<Glyph>,
+        id: u16,
+    ) -> DakotaId {
+        let glyph = self.render_glyph(id);
+
         // Create a new glyph for this UTF-8 character
         let id = inst.add_entity();
-        glyphs.set(
-            &id,
-            Glyph {
-                g_image: th_image,
-                g_bitmap_size: (bitmap.width(), bitmap.rows()),
-                g_bitmap_left: glyph.bitmap_left(),
-                g_bitmap_top: glyph.bitmap_top(),
-                _g_metrics: glyph.metrics(),
-            },
-        );
+        glyphs.set(&id, glyph);
 
         return id;
     }
@@ -212,7 +420,6 @@ impl FontInstance {
     /// Go ahead and create the Glyph for an id in our map
     fn ensure_glyph_exists(
         &mut self,
-        dev: &th::Device,
         inst: &mut ll::Instance,
         glyphs: &mut ll::Snapshot<Glyph>,
         id: u16,
@@ -223,7 +430,7 @@ impl FontInstance {
         }
 
         if self.f_glyphs[id as usize].is_none() {
-            self.f_glyphs[id as usize] = Some(self.create_glyph(dev, inst, glyphs, id));
+            self.f_glyphs[id as usize] = Some(self.create_glyph(inst, glyphs, id));
         }
     }
 
@@ -253,8 +460,6 @@ impl FontInstance {
 
         // First find the last glyph we should include on this line
         for i in cursor.c_i..text.len() {
-            let glyph_id = text[i].raw_glyph_id;
-
             // Move the cursor
             line_pos += text[i].cursor_advance.0;
             end_index = i + 1;
@@ -262,23 +467,12 @@ impl FontInstance {
             // check for word breaks
             // For now this is just checking for spaces
             // TODO: use something smarter
-            if self
-                .f_ft_face
-                .get_char_index(' ' as u32 as usize)
-                .unwrap_or(0)
-                == glyph_id as u32
-            {
+            if text[i].ch == ' ' {
                 last_word = end_index;
             }
 
             // Check for newlines
-            // gross, we have to convert to usize through u32 :(
-            if self
-                .f_ft_face
-                .get_char_index('\n' as u32 as usize)
-                .unwrap_or(0)
-                == glyph_id as u32
-            {
+            if text[i].ch == '\n' {
                 last_word = end_index;
                 ret = true;
                 break;
@@ -382,9 +576,34 @@ impl FontInstance {
         self.for_each_text_block(dev, cursor, text, glyph_callback)
     }
 
+    /// Shape text and create the glyphs for it
+    ///
+    /// Text is itemized into runs of characters which are present in the
+    /// same font, and each run is shaped with that font. This lets one
+    /// paragraph contain characters from our fallback fonts.
     pub fn initialize_cached_chars(
         &mut self,
-        dev: &th::Device,
+        inst: &mut ll::Instance,
+        glyphs: &mut ll::Snapshot<Glyph>,
+        text: &str,
+    ) -> Vec<CachedChar> {
+        let mut ret = Vec::new();
+
+        for (font, range) in self.itemize(text) {
+            let run = &text[range];
+            let mut chars = match font {
+                0 => self.shape_run(inst, glyphs, run),
+                i => self.f_fallbacks[i - 1].shape_run(inst, glyphs, run),
+            };
+            ret.append(&mut chars);
+        }
+
+        return ret;
+    }
+
+    /// Shape a run of text using only this font
+    fn shape_run(
+        &mut self,
         inst: &mut ll::Instance,
         glyphs: &mut ll::Snapshot<Glyph>,
         text: &str,
@@ -410,18 +629,24 @@ impl FontInstance {
 
         for i in 0..infos.len() {
             let raw_glyph_id = infos[i].codepoint as u16;
-            self.ensure_glyph_exists(dev, inst, glyphs, raw_glyph_id);
+            self.ensure_glyph_exists(inst, glyphs, raw_glyph_id);
             let glyph_id = self.f_glyphs[raw_glyph_id as usize]
                 .as_ref()
                 .expect("Bug: No Glyph created for this character");
             let glyph = glyphs.get(&glyph_id).unwrap();
 
             let (x_offset, y_offset, x_advance, y_advance) = scale_hb_positions(&positions[i]);
+            // Clusters are the byte offset of the character in the text
+            let ch = text[infos[i].cluster as usize..]
+                .chars()
+                .next()
+                .unwrap_or(' ');
 
             ret.push(CachedChar {
                 node: inst.add_entity(),
                 glyph_id: glyph_id.clone(),
                 raw_glyph_id: raw_glyph_id,
+                ch,
                 cursor_advance: (x_advance, y_advance),
                 offset: (
                     x_offset + glyph.g_bitmap_left,
@@ -4,11 +4,26 @@
 //
 // Austin Shafer - 2020
 
-#[cfg(any(feature = "direct2display", feature = "drm"))]
+#[cfg(any(
+    feature = "direct2display",
+    feature = "drm",
+    feature = "sdl",
+    feature = "vnc",
+    feature = "winit"
+))]
 extern crate xkbcommon;
-#[cfg(any(feature = "direct2display", feature = "drm"))]
+#[cfg(any(
+    feature = "direct2display",
+    feature = "drm",
+    feature = "sdl",
+    feature = "vnc",
+    feature = "winit"
+))]
 use xkbcommon::xkb;
 
+#[cfg(any(feature = "direct2display", feature = "drm"))]
+use crate::event::TabletToolType;
+
 bitflags::bitflags! {
     pub struct Mods: u16 {
         const NONE = 0x0000;
@@ -32,6 +47,26 @@ pub fn convert_sdl_mods_to_dakota(keymods: sdl2::keyboard::Mod) -> Mods {
     Mods::from_bits(keymods.bits()).expect("Invalid mod bits")
 }
 
+/// winit doesn't tell us which side of the keyboard a modifier is on,
+/// so these are all reported as the left ones.
+#[cfg(feature = "winit")]
+pub fn convert_winit_mods_to_dakota(state: winit::event::ModifiersState) -> Mods {
+    let mut mods = Mods::NONE;
+    if state.shift() {
+        mods |= Mods::LSHIFT;
+    }
+    if state.ctrl() {
+        mods |= Mods::LCTRL;
+    }
+    if state.alt() {
+        mods |= Mods::LALT;
+    }
+    if state.logo() {
+        mods |= Mods::LMETA;
+    }
+    mods
+}
+
 /// Keycodes for mouse buttons.
 ///
 /// Names are self explanitory, `LEFT` for left click and etc.
@@ -67,6 +102,27 @@ pub fn convert_libinput_mouse_to_dakota(button: u32) -> MouseButton {
     }
 }
 
+/// Converts a libinput tablet tool type into a Dakota enum.
+///
+/// libinput's totems are reported as pucks, the closest tool Wayland
+/// has for them.
+#[cfg(any(feature = "direct2display", feature = "drm"))]
+pub fn convert_libinput_tool_to_dakota(
+    tool: Option<::input::event::tablet_tool::TabletToolType>,
+) -> TabletToolType {
+    use ::input::event::tablet_tool::TabletToolType as Tool;
+
+    match tool {
+        Some(Tool::Eraser) => TabletToolType::Eraser,
+        Some(Tool::Brush) => TabletToolType::Brush,
+        Some(Tool::Pencil) => TabletToolType::Pencil,
+        Some(Tool::Airbrush) => TabletToolType::Airbrush,
+        Some(Tool::Mouse) | Some(Tool::Totem) => TabletToolType::Mouse,
+        Some(Tool::Lens) => TabletToolType::Lens,
+        _ => TabletToolType::Pen,
+    }
+}
+
 #[cfg(any(feature = "direct2display", feature = "drm"))]
 impl MouseButton {
     /// Get the Linux kernel button code corresponding to this mouse button
@@ -102,6 +158,20 @@ pub fn convert_sdl_mouse_to_dakota(button: sdl2::mouse::MouseButton) -> MouseBut
     }
 }
 
+/// The back and forward buttons are reported as `Other` with their
+/// X11 button numbers.
+#[cfg(feature = "winit")]
+pub fn convert_winit_mouse_to_dakota(button: winit::event::MouseButton) -> MouseButton {
+    match button {
+        winit::event::MouseButton::Left => MouseButton::LEFT,
+        winit::event::MouseButton::Middle => MouseButton::MIDDLE,
+        winit::event::MouseButton::Right => MouseButton::RIGHT,
+        winit::event::MouseButton::Other(8) => MouseButton::SIDE,
+        winit::event::MouseButton::Other(9) => MouseButton::EXTRA,
+        _ => MouseButton::UNKNOWN,
+    }
+}
+
 /// Generic table for Keycode translations
 ///
 /// We have three different sets of keycodes to translate between: Dakota::Keycode,
@@ -142,7 +212,13 @@ impl<K: PartialEq + Copy, T: PartialEq + Copy> CodeTranslator<K, T> {
 }
 
 // Define different tables for our different possible keycode sets
-#[cfg(any(feature = "direct2display", feature = "drm"))]
+#[cfg(any(
+    feature = "direct2display",
+    feature = "drm",
+    feature = "sdl",
+    feature = "vnc",
+    feature = "winit"
+))]
 lazy_static::lazy_static! {
     static ref CT_XKB_TO_DAKOTA: CodeTranslator<Keycode, u32> =
         CodeTranslator {
@@ -1127,7 +1203,13 @@ impl Keycode {
 /// This handles looking up the keycode translation using an internal lookup table.
 ///
 /// TODO: Make this O(1)
-#[cfg(any(feature = "direct2display", feature = "drm"))]
+#[cfg(any(
+    feature = "direct2display",
+    feature = "drm",
+    feature = "sdl",
+    feature = "vnc",
+    feature = "winit"
+))]
 pub fn convert_xkb_keycode_to_dakota(key: u32) -> Keycode {
     CT_XKB_TO_DAKOTA.val_to_key(key).unwrap_or(Keycode::UNKNOWN)
 }
@@ -1153,3 +1235,120 @@ pub fn convert_sdl_keycode_to_dakota(key: sdl2::keyboard::Keycode) -> Keycode {
 pub fn convert_sdl_scancode_to_linux(code: sdl2::keyboard::Scancode) -> u32 {
     CT_SDL_TO_LINUX_KEY.key_to_val(code as u32).unwrap_or(0) // Unknown
 }
+
+/// Pointer acceleration profiles
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AccelProfile {
+    /// Constant acceleration, the pointer moves the same distance
+    /// no matter how fast the device is moved
+    Flat,
+    /// Acceleration increases with the speed of the device
+    Adaptive,
+}
+
+#[cfg(any(feature = "direct2display", feature = "drm"))]
+pub fn convert_dakota_accel_profile_to_libinput(profile: AccelProfile) -> ::input::AccelProfile {
+    match profile {
+        AccelProfile::Flat => ::input::AccelProfile::Flat,
+        AccelProfile::Adaptive => ::input::AccelProfile::Adaptive,
+    }
+}
+
+/// The seat input devices are on unless they are given another
+pub const DEFAULT_SEAT: &str = "default";
+
+/// Configuration for a single input device
+///
+/// Any setting left as `None` keeps the device's default. Settings that
+/// a device does not support are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceSettings {
+    /// Tapping a touchpad generates a button click
+    pub tap_to_click: Option<bool>,
+    /// Scroll the content in the direction the fingers move
+    pub natural_scroll: Option<bool>,
+    pub accel_profile: Option<AccelProfile>,
+    /// Pointer speed, from -1.0 (slowest) to 1.0 (fastest)
+    pub accel_speed: Option<f64>,
+    /// Disable the touchpad while the keyboard is being typed on
+    pub disable_while_typing: Option<bool>,
+    /// The seat this device belongs to. Devices are on the `default`
+    /// seat unless given another.
+    pub seat: Option<String>,
+}
+
+impl DeviceSettings {
+    /// Replace our settings with any that are set in `other`
+    pub fn merge(&mut self, other: &DeviceSettings) {
+        self.tap_to_click = other.tap_to_click.or(self.tap_to_click);
+        self.natural_scroll = other.natural_scroll.or(self.natural_scroll);
+        self.accel_profile = other.accel_profile.or(self.accel_profile);
+        self.accel_speed = other.accel_speed.or(self.accel_speed);
+        self.disable_while_typing = other.disable_while_typing.or(self.disable_while_typing);
+        if other.seat.is_some() {
+            self.seat = other.seat.clone();
+        }
+    }
+}
+
+/// How held down keys repeat
+///
+/// A key starts repeating once it has been held for `delay` milliseconds,
+/// and then repeats `rate` times per second. A rate of zero disables
+/// key repeat.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct KeyRepeatConfig {
+    pub delay: u32,
+    pub rate: u32,
+}
+
+impl Default for KeyRepeatConfig {
+    fn default() -> Self {
+        Self {
+            delay: 600,
+            rate: 25,
+        }
+    }
+}
+
+/// The keyboard layout to use
+///
+/// These are the xkb rules, model, layout, variant and options names.
+/// Empty names use xkbcommon's defaults, which can be set with the
+/// `XKB_DEFAULT_*` environment variables. Several layouts can be given
+/// separated by commas, i.e. `us,de`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeymapConfig {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    /// Such as `grp:alt_shift_toggle` or `compose:ralt`
+    pub options: Option<String>,
+}
+
+/// Configuration for all input devices
+///
+/// The default settings are applied to every device, followed by any
+/// overrides whose name is contained in the device's name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputConfig {
+    pub default: DeviceSettings,
+    /// Per-device overrides, applied in order
+    pub devices: Vec<(String, DeviceSettings)>,
+}
+
+impl InputConfig {
+    /// Get the settings to use for the device with this name
+    pub fn get_device_settings(&self, name: &str) -> DeviceSettings {
+        let mut ret = self.default.clone();
+
+        for (pattern, settings) in self.devices.iter() {
+            if name.contains(pattern.as_str()) {
+                ret.merge(settings);
+            }
+        }
+
+        ret
+    }
+}
@@ -0,0 +1,46 @@
+//! A small job system for layout
+//!
+//! Runs a list of jobs on a few scoped threads. The calling thread works
+//! on jobs too, so running one job never starts a thread. Jobs are
+//! taken from a shared queue so that one large job doesn't hold up the
+//! rest.
+// Austin Shafer - 2024
+use std::sync::Mutex;
+
+/// Run `f` on each of `jobs` using up to `threads` threads
+///
+/// Returns the results in the same order as `jobs`.
+pub(crate) fn run<T, R, F>(threads: usize, jobs: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let count = jobs.len();
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..count).map(|_| None).collect());
+
+    let work = || loop {
+        // Only hold the lock while taking a job
+        let (i, job) = match queue.lock().unwrap().next() {
+            Some(job) => job,
+            None => return,
+        };
+        let ret = f(job);
+        results.lock().unwrap()[i] = Some(ret);
+    };
+
+    std::thread::scope(|s| {
+        for _ in 1..threads.min(count) {
+            s.spawn(work);
+        }
+        work();
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|ret| ret.expect("Layout job did not finish"))
+        .collect()
+}
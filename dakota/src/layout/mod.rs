@@ -8,15 +8,25 @@
 /// Austin Shafer - 2024
 extern crate regex;
 use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
+use std::sync::Arc;
 
+use crate::atlas::GlyphAtlas;
 use crate::font::*;
 use crate::{dom, DakotaId, Result, Scene};
+use utils::region::Rect;
 use utils::{anyhow, log, Context};
 
+mod jobs;
 #[cfg(test)]
 mod tests;
 
+/// The fewest elements a subtree needs to be laid out by a job
+const LAYOUT_JOB_MIN_ELEMENTS: usize = 64;
+/// The most threads layout uses by default
+pub(crate) const MAX_LAYOUT_THREADS: usize = 4;
+
 fn regex_trim_excess_space(str: &String) -> String {
     let re = Regex::new(r"\s+").unwrap();
     let trimmed = re.replace_all(str, " ");
@@ -38,7 +48,7 @@ struct TileInfo {
 /// This is the available space for a layout calculation.
 /// this handles the number of children sharing the space, the
 /// available size
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LayoutSpace {
     /// This is essentially the width of the parent container
     pub avail_width: i32,
@@ -59,6 +69,9 @@ pub(crate) struct LayoutNode {
     pub l_size: dom::Size<i32>,
     /// Ids of the children that this layout node has
     pub l_children: Vec<DakotaId>,
+    /// The space this node was laid out in. If it is laid out in the
+    /// same space again and is not dirty, this node can be reused.
+    pub l_space: Option<LayoutSpace>,
 }
 
 impl Default for LayoutNode {
@@ -69,6 +82,7 @@ impl Default for LayoutNode {
             l_offset: dom::Offset::new(0, 0),
             l_size: dom::Size::new(0, 0),
             l_children: Vec::with_capacity(0),
+            l_space: None,
         }
     }
 }
@@ -81,6 +95,7 @@ impl LayoutNode {
             l_offset: off,
             l_size: size,
             l_children: Vec::with_capacity(0),
+            l_space: None,
         }
     }
 
@@ -89,6 +104,104 @@ impl LayoutNode {
     }
 }
 
+/// The result of laying out the glyphs of a text element
+///
+/// Placing glyphs only depends on the text, its fonts, and the horizontal
+/// space it wraps in. If none of those change the glyph layout nodes from
+/// last time are still correct and are reused. Changing the text drops
+/// its measurement.
+#[derive(Clone)]
+pub(crate) struct TextMeasurement {
+    /// The fonts of the text and each of its runs
+    tm_fonts: Vec<dom::Font>,
+    /// The left and right sides of the line wrap bounds
+    tm_min: i32,
+    tm_max: i32,
+    /// The glyph layout nodes placed in the text element
+    tm_glyphs: Vec<DakotaId>,
+}
+
+/// The Scene components used during layout
+///
+/// Every LayoutTransaction takes its own snapshots of these. Snapshots
+/// only hold read locks on the components, so jobs laying out subtrees on
+/// other threads can each have a transaction without waiting on each
+/// other. Changes are kept in the job's snapshots until they are merged
+/// into the main transaction.
+///
+/// These fields correspond to the identically named variants in Dakota.
+#[derive(Copy, Clone)]
+pub(crate) struct LayoutComponents<'a> {
+    lc_ecs_inst: &'a ll::Instance,
+    lc_resources: &'a ll::Component<DakotaId>,
+    lc_resource_thundr_image: &'a ll::Component<th::Image>,
+    lc_resource_color: &'a ll::Component<dom::Color>,
+    lc_fonts: &'a ll::Component<dom::Font>,
+    lc_text_font: &'a ll::Component<DakotaId>,
+    lc_texts: &'a ll::Component<dom::Text>,
+    lc_default_font_inst: &'a DakotaId,
+    lc_glyphs: &'a ll::Component<Glyph>,
+    lc_is_viewport: &'a ll::Component<bool>,
+    lc_viewports: &'a ll::Component<th::Viewport>,
+    lc_layout_nodes: &'a ll::Component<LayoutNode>,
+    lc_text_measurements: &'a ll::Component<TextMeasurement>,
+    lc_contents: &'a ll::Component<dom::Content>,
+    lc_offsets: &'a ll::Component<dom::RelativeOffset>,
+    lc_widths: &'a ll::Component<dom::Value>,
+    lc_heights: &'a ll::Component<dom::Value>,
+    lc_children: &'a ll::Component<Vec<DakotaId>>,
+    lc_dev: &'a th::Device,
+}
+
+impl<'a> LayoutComponents<'a> {
+    /// Start a transaction
+    ///
+    /// Transactions without font instances and a glyph atlas can't lay
+    /// out text, which is the case for jobs.
+    fn transaction(
+        &self,
+        font_instances: Option<&'a mut Vec<(dom::Font, FontInstance)>>,
+        glyph_atlas: Option<&'a mut GlyphAtlas>,
+        dirty: Option<Arc<HashSet<usize>>>,
+    ) -> LayoutTransaction<'a> {
+        LayoutTransaction {
+            lt_ecs_inst: self.lc_ecs_inst.clone(),
+            lt_resources: self.lc_resources.snapshot(),
+            lt_resource_thundr_image: self.lc_resource_thundr_image.snapshot(),
+            lt_resource_color: self.lc_resource_color.snapshot(),
+            lt_fonts: self.lc_fonts.snapshot(),
+            lt_text_font: self.lc_text_font.snapshot(),
+            lt_texts: self.lc_texts.snapshot(),
+            lt_default_font_inst: self.lc_default_font_inst.clone(),
+            lt_glyphs: self.lc_glyphs.snapshot(),
+            lt_is_viewport: self.lc_is_viewport.snapshot(),
+            lt_viewports: self.lc_viewports.snapshot(),
+            lt_layout_nodes: self.lc_layout_nodes.snapshot(),
+            lt_text_measurements: self.lc_text_measurements.snapshot(),
+            lt_contents: self.lc_contents.snapshot(),
+            lt_offsets: self.lc_offsets.snapshot(),
+            lt_widths: self.lc_widths.snapshot(),
+            lt_heights: self.lc_heights.snapshot(),
+            lt_children: self.lc_children.snapshot(),
+            lt_font_instances: font_instances,
+            lt_glyph_atlas: glyph_atlas,
+            lt_dev: self.lc_dev,
+            lt_dirty: dirty,
+            lt_components: *self,
+            lt_threads: 1,
+            lt_subtree_sizes: None,
+        }
+    }
+}
+
+/// The changes made by a layout job
+///
+/// Jobs never lay out text, so only layout nodes and viewports can change.
+struct JobChanges {
+    jc_layout_nodes: Vec<(DakotaId, Option<LayoutNode>)>,
+    jc_viewports: Vec<(DakotaId, Option<th::Viewport>)>,
+}
+
 /// LayoutTransaction
 ///
 /// This transaction allows the layout engine to have a consistent,
@@ -109,13 +222,27 @@ pub(crate) struct LayoutTransaction<'a> {
     lt_is_viewport: ll::Snapshot<'a, bool>,
     lt_viewports: ll::Snapshot<'a, th::Viewport>,
     lt_layout_nodes: ll::Snapshot<'a, LayoutNode>,
+    lt_text_measurements: ll::Snapshot<'a, TextMeasurement>,
     lt_contents: ll::Snapshot<'a, dom::Content>,
     lt_offsets: ll::Snapshot<'a, dom::RelativeOffset>,
     lt_widths: ll::Snapshot<'a, dom::Value>,
     lt_heights: ll::Snapshot<'a, dom::Value>,
     lt_children: ll::Snapshot<'a, Vec<DakotaId>>,
-    lt_font_instances: &'a mut Vec<(dom::Font, FontInstance)>,
+    /// These are None in jobs, which can't lay out text
+    lt_font_instances: Option<&'a mut Vec<(dom::Font, FontInstance)>>,
+    lt_glyph_atlas: Option<&'a mut GlyphAtlas>,
     lt_dev: &'a th::Device,
+    /// Raw ids of the elements which need to be laid out again. If this
+    /// is None then every element does.
+    lt_dirty: Option<Arc<HashSet<usize>>>,
+    /// Where our snapshots came from, used to start jobs
+    lt_components: LayoutComponents<'a>,
+    /// The most threads to lay out subtrees on
+    lt_threads: usize,
+    /// The number of elements to lay out in each subtree without text,
+    /// keyed by raw id. Subtrees large enough are laid out by jobs. This
+    /// is None if we aren't starting jobs.
+    lt_subtree_sizes: Option<HashMap<usize, usize>>,
 }
 
 impl<'a> Drop for LayoutTransaction<'a> {
@@ -141,6 +268,7 @@ impl<'a> LayoutTransaction<'a> {
         self.lt_is_viewport.precommit();
         self.lt_viewports.precommit();
         self.lt_layout_nodes.precommit();
+        self.lt_text_measurements.precommit();
         self.lt_contents.precommit();
         self.lt_widths.precommit();
         self.lt_heights.precommit();
@@ -163,6 +291,7 @@ impl<'a> LayoutTransaction<'a> {
         self.lt_is_viewport.commit();
         self.lt_viewports.commit();
         self.lt_layout_nodes.commit();
+        self.lt_text_measurements.commit();
         self.lt_contents.commit();
         self.lt_widths.commit();
         self.lt_heights.commit();
@@ -170,6 +299,105 @@ impl<'a> LayoutTransaction<'a> {
         self.lt_children.commit();
     }
 
+    /// Take the changes made by this job's transaction
+    fn take_job_changes(&mut self) -> JobChanges {
+        JobChanges {
+            jc_layout_nodes: self.lt_layout_nodes.take_changes(),
+            jc_viewports: self.lt_viewports.take_changes(),
+        }
+    }
+
+    /// Merge the changes made by a job into this transaction
+    fn apply_job_changes(&mut self, changes: JobChanges) {
+        self.lt_layout_nodes.apply_changes(changes.jc_layout_nodes);
+        self.lt_viewports.apply_changes(changes.jc_viewports);
+    }
+
+    /// Count the elements which will be laid out in each subtree
+    ///
+    /// This fills in `lt_subtree_sizes` for every subtree in `el` which
+    /// doesn't contain text, since only those can be laid out by jobs.
+    /// Returns None if this subtree has text.
+    fn count_subtree_sizes(
+        &self,
+        el: &DakotaId,
+        sizes: &mut HashMap<usize, usize>,
+    ) -> Option<usize> {
+        let mut size = match self.lt_dirty.as_ref() {
+            Some(dirty) if !dirty.contains(&el.get_raw_id()) => 0,
+            _ => 1,
+        };
+        let mut has_text = self.lt_texts.get(el).is_some();
+
+        let content = self.lt_contents.get(el).map(|content| &content.el);
+        let children = self.lt_children.get(el).into_iter().flat_map(|c| c.iter());
+        for child in children.chain(content) {
+            match self.count_subtree_sizes(child, sizes) {
+                Some(child_size) => size += child_size,
+                None => has_text = true,
+            }
+        }
+
+        if has_text {
+            return None;
+        }
+        sizes.insert(el.get_raw_id(), size);
+        Some(size)
+    }
+
+    /// Lay out the large children of `el` in parallel
+    ///
+    /// Children with big enough subtrees are each laid out by a job in its
+    /// own transaction. Their layout only depends on the space they are
+    /// given, not on their siblings, so this can be done before tiling
+    /// them. Text can't be laid out by jobs since FontInstances aren't
+    /// thread safe. Once every job finishes their changes are merged into
+    /// this transaction.
+    ///
+    /// Returns the raw ids of the children which were laid out.
+    fn calculate_sizes_jobs(
+        &mut self,
+        el: &DakotaId,
+        space: &LayoutSpace,
+    ) -> Result<HashSet<usize>> {
+        let children: Vec<DakotaId> = match self.lt_subtree_sizes.as_ref() {
+            Some(sizes) => self
+                .lt_children
+                .get(el)
+                .ok_or(anyhow!("Expected children"))?
+                .iter()
+                .filter(|child| {
+                    sizes
+                        .get(&child.get_raw_id())
+                        .map(|size| *size >= LAYOUT_JOB_MIN_ELEMENTS)
+                        .unwrap_or(false)
+                })
+                .cloned()
+                .collect(),
+            None => return Ok(HashSet::new()),
+        };
+        // A single subtree is faster to lay out here
+        if children.len() < 2 {
+            return Ok(HashSet::new());
+        }
+
+        log::debug!("Laying out {} subtrees in parallel", children.len());
+        let components = self.lt_components;
+        let dirty = self.lt_dirty.clone();
+        let results = jobs::run(self.lt_threads, children.clone(), |child| {
+            let mut trans = components.transaction(None, None, dirty.clone());
+            let ret = trans.calculate_sizes(&child, None, space);
+            (ret, trans.take_job_changes())
+        });
+
+        for (ret, changes) in results.into_iter() {
+            ret.context("Layout Tree Calculation: processing subtree in a job")?;
+            self.apply_job_changes(changes);
+        }
+
+        Ok(children.iter().map(|child| child.get_raw_id()).collect())
+    }
+
     /// Helper to get the Font Instance for a particular element
     ///
     /// This will choose the default font (including size) if none
@@ -390,13 +618,18 @@ impl<'a> LayoutTransaction<'a> {
             .ok_or(anyhow!("Expected children"))?
             .len();
 
+        // Large subtrees are laid out first, and only placed here
+        let laid_out = self.calculate_sizes_jobs(el, space)?;
+
         for i in 0..child_count {
             let child_id = self
                 .lt_children
                 .get(el)
                 .ok_or(anyhow!("Expected children"))?[i]
                 .clone();
-            self.calculate_sizes(&child_id, Some(el), &space)?;
+            if !laid_out.contains(&child_id.get_raw_id()) {
+                self.calculate_sizes(&child_id, Some(el), &space)?;
+            }
 
             // ----- adjust child position ----
             {
@@ -468,6 +701,7 @@ impl<'a> LayoutTransaction<'a> {
             .into();
 
         node.l_size = self.get_default_size(el, space)?.into();
+        node.l_space = Some(space.clone());
 
         log::debug!("Offset of element is {:?}", node.l_offset);
         log::debug!("Size of element is {:?}", node.l_size);
@@ -489,6 +723,79 @@ impl<'a> LayoutTransaction<'a> {
         !texts.get(id).is_some()
     }
 
+    /// Can we keep the layout calculated for this element last time
+    ///
+    /// This is true if nothing in its subtree was modified and it is being
+    /// laid out in the same space as before.
+    fn can_reuse_node(&self, el: &DakotaId, space: &LayoutSpace) -> bool {
+        match self.lt_dirty.as_ref() {
+            Some(dirty) if !dirty.contains(&el.get_raw_id()) => {}
+            _ => return false,
+        }
+
+        match self.lt_layout_nodes.get(el) {
+            Some(node) => node.l_space.as_ref() == Some(space),
+            None => false,
+        }
+    }
+
+    /// Keep the layout calculated for this element last time
+    ///
+    /// The subtree is left alone, but our offset is calculated again since
+    /// our parent may have moved us while tiling.
+    fn reuse_node(&mut self, el: &DakotaId, space: &LayoutSpace) -> Result<()> {
+        log::debug!("Reusing layout of element");
+        let offset = self
+            .get_final_offset(el, space)
+            .context("Failed to calculate offset size of Element")?;
+        self.lt_layout_nodes.get_mut(el).unwrap().l_offset = offset;
+
+        self.keep_glyphs(el);
+
+        if *self.lt_is_viewport.get(el).unwrap_or(&false) {
+            self.set_viewport_internal(&el);
+        }
+
+        Ok(())
+    }
+
+    /// Keep the glyphs drawn by this subtree in the atlas
+    ///
+    /// Glyphs which are not used in a layout may be evicted from the atlas,
+    /// so the glyphs of reused text need to be added again.
+    fn keep_glyphs(&mut self, el: &DakotaId) {
+        let children = self.lt_layout_nodes.get(el).unwrap().l_children.clone();
+
+        for child in children.iter() {
+            let glyph_id = match self.lt_layout_nodes.get(child) {
+                Some(node) => node.l_glyph_id.clone(),
+                None => continue,
+            };
+
+            match glyph_id {
+                Some(glyph_id) => {
+                    let glyph = match self.lt_glyphs.get(&glyph_id) {
+                        Some(glyph) => glyph,
+                        None => continue,
+                    };
+                    let size = glyph.g_bitmap_size;
+                    // Jobs don't have glyphs to keep
+                    let atlas = match self.lt_glyph_atlas.as_deref_mut() {
+                        Some(atlas) => atlas,
+                        None => continue,
+                    };
+
+                    if let Some(bitmap) = glyph.g_bitmap.as_ref() {
+                        if atlas.add_glyph(&glyph_id, size.0, size.1, bitmap).is_none() {
+                            log::error!("No room in the glyph atlas, glyph will not be drawn");
+                        }
+                    }
+                }
+                None => self.keep_glyphs(child),
+            }
+        }
+    }
+
     /// Handles creating LayoutNodes for every glyph in a passage
     ///
     /// This is the handler for the text field in the dakota file
@@ -496,33 +803,69 @@ impl<'a> LayoutTransaction<'a> {
         if !self.node_can_have_children(&self.lt_texts, el) && self.lt_children.get(el).is_some() {
             return Err(anyhow!("Text Elements cannot have children"));
         }
+        if self.lt_font_instances.is_none() || self.lt_glyph_atlas.is_none() {
+            return Err(anyhow!("Text cannot be laid out in a layout job"));
+        }
+
+        let block_font_id = self.get_font_id_for_el(el);
+        let mut fonts = vec![self.lt_fonts.get(&block_font_id).unwrap().clone()];
+        for item in self.lt_texts.get(el).unwrap().items.iter() {
+            match item {
+                dom::TextItem::p(run) | dom::TextItem::b(run) | dom::TextItem::span(run) => {
+                    if let Some(font_id) = run.font.as_ref() {
+                        fonts.push(
+                            self.lt_fonts
+                                .get(font_id)
+                                .context("Text run font has not been defined")?
+                                .clone(),
+                        );
+                    }
+                }
+            }
+        }
+        let (min, max) = {
+            let node = self.lt_layout_nodes.get(el).unwrap();
+            (node.l_offset.x, node.l_offset.x + node.l_size.width)
+        };
+
+        // Skip placing the glyphs if we can use the ones from last time
+        let glyphs = self
+            .lt_text_measurements
+            .get(el)
+            .filter(|m| m.tm_fonts == fonts && m.tm_min == min && m.tm_max == max)
+            .map(|m| m.tm_glyphs.clone());
+        if let Some(glyphs) = glyphs {
+            log::debug!("Reusing text measurement");
+            self.lt_layout_nodes.get_mut(el).unwrap().l_children = glyphs;
+            self.keep_glyphs(el);
+            return Ok(());
+        }
 
-        let font_id = self.get_font_id_for_el(el);
-        let font = self.lt_fonts.get(&font_id).unwrap();
-        let font_inst = &mut self
-            .lt_font_instances
-            .iter_mut()
-            .find(|(f, _)| *f == *font)
-            .expect("Could not find FontInstance")
-            .1;
+        let line_space = {
+            let font = self.lt_fonts.get(&block_font_id).unwrap();
+            self.lt_font_instances
+                .as_ref()
+                .unwrap()
+                .iter()
+                .find(|(f, _)| *f == *font)
+                .expect("Could not find FontInstance")
+                .1
+                .get_vertical_line_spacing()
+        };
 
         let text = self.lt_texts.get_mut(el).unwrap();
-        let line_space = font_inst.get_vertical_line_spacing();
 
         // This is how far we have advanced on a line
         // Go down by one line space before writing the first line. This deals
         // with the problem that ft/hb want to index by the bottom left corner
         // and all my stuff wants to index from the top left corner. Without this
         // text starts being written "above" the element it is assigned to.
-        let mut cursor = {
-            let node = self.lt_layout_nodes.get(el).unwrap();
-            Cursor {
-                c_i: 0,
-                c_x: 0,
-                c_y: line_space,
-                c_min: node.l_offset.x,
-                c_max: node.l_offset.x + node.l_size.width,
-            }
+        let mut cursor = Cursor {
+            c_i: 0,
+            c_x: 0,
+            c_y: line_space,
+            c_min: min,
+            c_max: max,
         };
 
         log::debug!("Calculating text size");
@@ -532,7 +875,22 @@ impl<'a> LayoutTransaction<'a> {
         // through text formatting in the dakota file.
         for item in text.items.iter_mut() {
             match item {
-                dom::TextItem::p(run) | dom::TextItem::b(run) => {
+                dom::TextItem::p(run) | dom::TextItem::b(run) | dom::TextItem::span(run) => {
+                    // Runs may change the font used for part of the text
+                    let font_id = run.font.clone().unwrap_or(block_font_id.clone());
+                    let font = self
+                        .lt_fonts
+                        .get(&font_id)
+                        .context("Text run font has not been defined")?;
+                    let font_inst = &mut self
+                        .lt_font_instances
+                        .as_mut()
+                        .unwrap()
+                        .iter_mut()
+                        .find(|(f, _)| *f == *font)
+                        .expect("Could not find FontInstance")
+                        .1;
+
                     if run.cache.is_none() {
                         // TODO: we can get the available height from above, pass it to a font instance
                         // and create layout nodes for all character surfaces.
@@ -543,7 +901,6 @@ impl<'a> LayoutTransaction<'a> {
                         // This must be called to initialize the glyphs before we do
                         // the layout and line splitting.
                         run.cache = Some(font_inst.initialize_cached_chars(
-                            &self.lt_dev,
                             &mut self.lt_ecs_inst,
                             &mut self.lt_glyphs,
                             &trim,
@@ -556,6 +913,7 @@ impl<'a> LayoutTransaction<'a> {
                     let layouts = &mut self.lt_layout_nodes;
                     let text_fonts = &mut self.lt_text_font;
                     let glyphs = &mut self.lt_glyphs;
+                    let atlas = self.lt_glyph_atlas.as_deref_mut().unwrap();
 
                     // Record text locations
                     // We will create a whole bunch of sub-nodes which will be assigned
@@ -566,7 +924,20 @@ impl<'a> LayoutTransaction<'a> {
                         run.cache.as_ref().unwrap(),
                         &mut |_inst: &mut FontInstance, _thund, curse, ch| {
                             // --- calculate sizes for the character surfaces ---
-                            let size = glyphs.get(&ch.glyph_id).unwrap().g_bitmap_size;
+                            let glyph = glyphs.get(&ch.glyph_id).unwrap();
+                            let size = glyph.g_bitmap_size;
+
+                            // Make sure this glyph is in the atlas so it can be drawn
+                            if let Some(bitmap) = glyph.g_bitmap.as_ref() {
+                                if atlas
+                                    .add_glyph(&ch.glyph_id, size.0, size.1, bitmap)
+                                    .is_none()
+                                {
+                                    log::error!(
+                                        "No room in the glyph atlas, glyph will not be drawn"
+                                    );
+                                }
+                            }
 
                             let child_size = LayoutNode::new(
                                 Some(ch.glyph_id.clone()),
@@ -599,6 +970,17 @@ impl<'a> LayoutTransaction<'a> {
             }
         }
 
+        let glyphs = self.lt_layout_nodes.get(el).unwrap().l_children.clone();
+        self.lt_text_measurements.set(
+            el,
+            TextMeasurement {
+                tm_fonts: fonts,
+                tm_min: min,
+                tm_max: max,
+                tm_glyphs: glyphs,
+            },
+        );
+
         Ok(())
     }
 
@@ -619,6 +1001,11 @@ impl<'a> LayoutTransaction<'a> {
         parent: Option<&DakotaId>,
         space: &LayoutSpace,
     ) -> Result<()> {
+        // Skip this subtree if nothing in it changed
+        if self.can_reuse_node(el, space) {
+            return self.reuse_node(el, space);
+        }
+
         // ------------------------------------------
         // HANDLE THIS ELEMENT
         // ------------------------------------------
@@ -711,36 +1098,252 @@ impl<'a> LayoutTransaction<'a> {
         let scroll_region = self.get_node_internal_size(id.clone());
         viewport.set_scroll_region(scroll_region.0 as i32, scroll_region.1 as i32);
 
+        // Keep our scroll position from the last layout, clamping it to the
+        // new scroll region
+        if let Some(old) = self.lt_viewports.get(id) {
+            viewport.scroll_offset = old.scroll_offset;
+            viewport.update_scroll_amount(0, 0);
+        }
+
         self.lt_viewports.set(id, viewport);
     }
+
+    /// Find the area of the window covered by `el`
+    ///
+    /// This searches the layout tree rooted at `node`, which is placed at
+    /// `base`.
+    fn find_element_rect(
+        &self,
+        node: &DakotaId,
+        el: &DakotaId,
+        base: (i32, i32),
+    ) -> Option<Rect<i32>> {
+        let layout = self.lt_layout_nodes.get(node)?;
+        let pos = (base.0 + layout.l_offset.x, base.1 + layout.l_offset.y);
+        if node == el {
+            return Some(Rect::new(
+                pos.0,
+                pos.1,
+                layout.l_size.width,
+                layout.l_size.height,
+            ));
+        }
+        // Skip the glyphs of text elements
+        if !self.node_can_have_children(&self.lt_texts, node) {
+            return None;
+        }
+
+        // Children of viewports are moved by the scroll amount
+        let mut child_base = pos;
+        if let Some(viewport) = self.lt_viewports.get(node) {
+            child_base.0 += viewport.scroll_offset.0;
+            child_base.1 += viewport.scroll_offset.1;
+        }
+        layout
+            .l_children
+            .iter()
+            .find_map(|child| self.find_element_rect(child, el, child_base))
+    }
+
+    /// Place a popup next to its anchor
+    ///
+    /// `roots` are the layout trees the anchor may be in, which are the
+    /// main tree and the popups below this one. If the popup does not fit
+    /// on the requested side of the anchor it is flipped to the other
+    /// side, and it is always kept inside the window.
+    ///
+    /// Returns false if the anchor could not be found.
+    fn place_popup(
+        &mut self,
+        roots: &[DakotaId],
+        el: &DakotaId,
+        popup: &dom::Popup,
+        window: (i32, i32),
+    ) -> bool {
+        let anchor = match roots
+            .iter()
+            .find_map(|root| self.find_element_rect(root, &popup.anchor, (0, 0)))
+        {
+            Some(anchor) => anchor,
+            None => return false,
+        };
+        let (ax, ay) = anchor.r_pos;
+        let (aw, ah) = anchor.r_size;
+
+        let size = self.lt_layout_nodes.get(el).unwrap().l_size;
+        let (w, h) = (size.width, size.height);
+
+        let placement = match popup.placement {
+            dom::PopupPlacement::Below if ay + ah + h > window.1 && ay - h >= 0 => {
+                dom::PopupPlacement::Above
+            }
+            dom::PopupPlacement::Above if ay - h < 0 && ay + ah + h <= window.1 => {
+                dom::PopupPlacement::Below
+            }
+            dom::PopupPlacement::Right if ax + aw + w > window.0 && ax - w >= 0 => {
+                dom::PopupPlacement::Left
+            }
+            dom::PopupPlacement::Left if ax - w < 0 && ax + aw + w <= window.0 => {
+                dom::PopupPlacement::Right
+            }
+            placement => placement,
+        };
+        let (x, y) = match placement {
+            dom::PopupPlacement::Below => (ax, ay + ah),
+            dom::PopupPlacement::Above => (ax, ay - h),
+            dom::PopupPlacement::Right => (ax + aw, ay),
+            dom::PopupPlacement::Left => (ax - w, ay),
+        };
+
+        let offset = (x.min(window.0 - w).max(0), y.min(window.1 - h).max(0));
+        self.lt_layout_nodes.get_mut(el).unwrap().l_offset = dom::Offset::new(offset.0, offset.1);
+
+        // Our viewport was created before we knew where we would be
+        if let Some(viewport) = self.lt_viewports.get_mut(el) {
+            viewport.offset = offset;
+        }
+        true
+    }
 }
 
 impl Scene {
+    /// Find the elements in this subtree which need to be laid out again
+    ///
+    /// An element is dirty if it was modified, if it has never been laid
+    /// out, or if any of its children are dirty. Elements which are sized
+    /// by their image are also dirty if the image changed.
+    ///
+    /// Returns true if `el` is dirty.
+    fn find_dirty_elements(
+        &self,
+        el: &DakotaId,
+        modified: &HashSet<usize>,
+        modified_resources: &HashSet<usize>,
+        dirty: &mut HashSet<usize>,
+    ) -> bool {
+        let mut is_dirty =
+            modified.contains(&el.get_raw_id()) || self.d_layout_nodes.get(el).is_none();
+
+        if let Some(res) = self.d_resources.get_clone(el) {
+            if modified_resources.contains(&res.get_raw_id())
+                && (self.d_widths.get(el).is_none() || self.d_heights.get(el).is_none())
+            {
+                is_dirty = true;
+            }
+        }
+
+        if let Some(children) = self.d_children.get_clone(el) {
+            for child in children.iter() {
+                is_dirty |= self.find_dirty_elements(child, modified, modified_resources, dirty);
+            }
+        }
+        if let Some(content) = self.d_contents.get_clone(el) {
+            is_dirty |= self.find_dirty_elements(&content.el, modified, modified_resources, dirty);
+        }
+
+        if is_dirty {
+            dirty.insert(el.get_raw_id());
+        }
+        is_dirty
+    }
+
+    /// Get the elements which need to be laid out again
+    ///
+    /// This consumes the list of entities modified since the last layout.
+    /// Returns None if the entire scene needs to be laid out, such as when
+    /// fonts change.
+    fn get_dirty_elements(&mut self, roots: &[DakotaId]) -> Option<HashSet<usize>> {
+        // Changing text invalidates how it was measured
+        let text_changes = self.d_texts.take_modified_entities();
+        match text_changes.as_ref() {
+            Some(texts) => {
+                self.d_text_measurements.take_many(texts.iter());
+            }
+            None => self.d_text_measurements.clear(),
+        }
+
+        let element_changes = [
+            self.d_resources.take_modified_entities(),
+            self.d_offsets.take_modified_entities(),
+            self.d_widths.take_modified_entities(),
+            self.d_heights.take_modified_entities(),
+            text_changes,
+            self.d_text_font.take_modified_entities(),
+            self.d_contents.take_modified_entities(),
+            self.d_children.take_modified_entities(),
+            self.d_is_viewport.take_modified_entities(),
+        ];
+        let resource_changes = self.d_resource_thundr_image.take_modified_entities();
+        let fonts_changed = match self.d_fonts.take_modified_entities() {
+            Some(fonts) => !fonts.is_empty(),
+            None => true,
+        };
+
+        if std::mem::take(&mut self.d_relayout_all) || fonts_changed {
+            return None;
+        }
+
+        let mut modified = HashSet::new();
+        for changes in element_changes.iter() {
+            modified.extend(changes.as_ref()?.iter().map(|id| id.get_raw_id()));
+        }
+        let modified_resources: HashSet<usize> =
+            resource_changes?.iter().map(|id| id.get_raw_id()).collect();
+
+        let mut dirty = HashSet::new();
+        for root in roots.iter() {
+            self.find_dirty_elements(root, &modified, &modified_resources, &mut dirty);
+        }
+
+        Some(dirty)
+    }
+
     /// Draw the entire scene
     ///
-    /// This starts at the root viewport and draws all child viewports
+    /// This starts at the root viewport and draws all child viewports.
+    /// Only the subtrees containing modified elements are laid out again.
     pub(crate) fn layout(&mut self, root_node: &DakotaId) -> Result<()> {
-        let mut trans = LayoutTransaction {
-            lt_ecs_inst: self.d_ecs_inst.clone(),
-            lt_resources: self.d_resources.snapshot(),
-            lt_resource_thundr_image: self.d_resource_thundr_image.snapshot(),
-            lt_resource_color: self.d_resource_color.snapshot(),
-            lt_fonts: self.d_fonts.snapshot(),
-            lt_text_font: self.d_text_font.snapshot(),
-            lt_texts: self.d_texts.snapshot(),
-            lt_default_font_inst: self.d_default_font_inst.clone(),
-            lt_glyphs: self.d_glyphs.snapshot(),
-            lt_is_viewport: self.d_is_viewport.snapshot(),
-            lt_viewports: self.d_viewports.snapshot(),
-            lt_layout_nodes: self.d_layout_nodes.snapshot(),
-            lt_contents: self.d_contents.snapshot(),
-            lt_widths: self.d_widths.snapshot(),
-            lt_heights: self.d_heights.snapshot(),
-            lt_offsets: self.d_offsets.snapshot(),
-            lt_children: self.d_children.snapshot(),
-            lt_font_instances: &mut self.d_font_instances,
-            lt_dev: &self.d_dev,
+        let mut trees = vec![root_node.clone()];
+        trees.extend(self.d_popups.iter().map(|(el, _)| el.clone()));
+        let dirty = self.get_dirty_elements(&trees).map(Arc::new);
+        self.d_glyph_atlas.next_generation();
+
+        let components = LayoutComponents {
+            lc_ecs_inst: &self.d_ecs_inst,
+            lc_resources: &self.d_resources,
+            lc_resource_thundr_image: &self.d_resource_thundr_image,
+            lc_resource_color: &self.d_resource_color,
+            lc_fonts: &self.d_fonts,
+            lc_text_font: &self.d_text_font,
+            lc_texts: &self.d_texts,
+            lc_default_font_inst: &self.d_default_font_inst,
+            lc_glyphs: &self.d_glyphs,
+            lc_is_viewport: &self.d_is_viewport,
+            lc_viewports: &self.d_viewports,
+            lc_layout_nodes: &self.d_layout_nodes,
+            lc_text_measurements: &self.d_text_measurements,
+            lc_contents: &self.d_contents,
+            lc_offsets: &self.d_offsets,
+            lc_widths: &self.d_widths,
+            lc_heights: &self.d_heights,
+            lc_children: &self.d_children,
+            lc_dev: &self.d_dev,
         };
+        let mut trans = components.transaction(
+            Some(&mut self.d_font_instances),
+            Some(&mut self.d_glyph_atlas),
+            dirty,
+        );
+
+        // Find the subtrees big enough to be worth laying out in parallel
+        if self.d_layout_threads > 1 {
+            let mut sizes = HashMap::new();
+            for tree in trees.iter() {
+                trans.count_subtree_sizes(tree, &mut sizes);
+            }
+            trans.lt_threads = self.d_layout_threads;
+            trans.lt_subtree_sizes = Some(sizes);
+        }
 
         trans.calculate_sizes(
             root_node,
@@ -750,7 +1353,41 @@ impl Scene {
                 avail_height: self.d_window_dims.1 as i32, // available height
             },
         )?;
+
+        // Popups are laid out on their own, with the whole window available
+        let window = (self.d_window_dims.0 as i32, self.d_window_dims.1 as i32);
+        let mut roots = vec![root_node.clone()];
+        let mut unanchored = Vec::new();
+        for (el, popup) in self.d_popups.iter() {
+            trans.calculate_sizes(
+                el,
+                None,
+                &LayoutSpace {
+                    avail_width: window.0,
+                    avail_height: window.1,
+                },
+            )?;
+            match trans.place_popup(&roots, el, popup, window) {
+                true => roots.push(el.clone()),
+                false => unanchored.push(el.clone()),
+            }
+        }
+
+        // Upload any glyphs added during layout
+        let dev = trans.lt_dev;
+        trans.lt_glyph_atlas.as_deref_mut().unwrap().flush(dev)?;
         trans.commit();
+        drop(trans);
+
+        // Layout caches shaped text and assigns fonts to glyphs, which
+        // shouldn't make anything dirty the next time
+        self.d_texts.take_modified_entities();
+        self.d_text_font.take_modified_entities();
+
+        for el in unanchored.iter() {
+            log::debug!("Hiding popup whose anchor is not in the scene");
+            self.hide_popup(el);
+        }
 
         Ok(())
     }
@@ -227,3 +227,154 @@ fn resource_from_bits() {
     assert!(child2_node.l_size == dom::Size::new(320, 240));
     assert!(child2_node.l_children.len() == 0);
 }
+
+/// Test that only the modified parts of the tree are laid out again:
+///  * resizing a child moves its clean sibling
+///  * resizing a grandchild resizes the parent sized to it
+#[test]
+fn partial_relayout() {
+    let (_, virtual_output, _, mut scene, root) = setup_dakota();
+
+    let child = scene.create_element().unwrap();
+    scene.add_child_to_element(&root, child.clone());
+    scene.width().set(&child, dom::Value::Constant(100));
+    scene.height().set(&child, dom::Value::Constant(128));
+
+    // This element has no size, so it is sized to its child
+    let container = scene.create_element().unwrap();
+    scene.add_child_to_element(&root, container.clone());
+    let grandchild = scene.create_element().unwrap();
+    scene.add_child_to_element(&container, grandchild.clone());
+    scene.width().set(&grandchild, dom::Value::Constant(64));
+    scene.height().set(&grandchild, dom::Value::Constant(64));
+
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+
+    let container_node = scene.d_layout_nodes.get(&container).unwrap();
+    assert!(container_node.l_offset == dom::Offset::new(100, 0));
+    assert!(container_node.l_size == dom::Size::new(64, 64));
+    drop(container_node);
+
+    // The container is not modified, but still needs to be moved
+    scene.width().set(&child, dom::Value::Constant(200));
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+
+    let child_node = scene.d_layout_nodes.get(&child).unwrap();
+    assert!(child_node.l_size == dom::Size::new(200, 128));
+    drop(child_node);
+    let container_node = scene.d_layout_nodes.get(&container).unwrap();
+    assert!(container_node.l_offset == dom::Offset::new(200, 0));
+    assert!(container_node.l_size == dom::Size::new(64, 64));
+    assert!(container_node.l_children.len() == 1);
+    drop(container_node);
+
+    // Changes to the grandchild are propagated up to the container
+    scene.width().set(&grandchild, dom::Value::Constant(96));
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+
+    let container_node = scene.d_layout_nodes.get(&container).unwrap();
+    assert!(container_node.l_offset == dom::Offset::new(200, 0));
+    assert!(container_node.l_size == dom::Size::new(96, 64));
+    drop(container_node);
+    let grandchild_node = scene.d_layout_nodes.get(&grandchild).unwrap();
+    assert!(grandchild_node.l_size == dom::Size::new(96, 64));
+}
+
+/// Test laying out large sibling subtrees on multiple threads
+#[test]
+fn parallel_layout() {
+    let (_, virtual_output, _, mut scene, root) = setup_dakota();
+    scene.set_layout_threads(4);
+
+    // Each container holds a row of 80 squares, which is enough for
+    // them to be laid out by jobs
+    let mut containers = Vec::new();
+    for _ in 0..4 {
+        let container = scene.create_element().unwrap();
+        scene.add_child_to_element(&root, container.clone());
+        let mut children = Vec::new();
+        for _ in 0..80 {
+            let child = scene.create_element().unwrap();
+            scene.add_child_to_element(&container, child.clone());
+            scene.width().set(&child, dom::Value::Constant(8));
+            scene.height().set(&child, dom::Value::Constant(8));
+            children.push(child);
+        }
+        containers.push((container, children));
+    }
+
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+
+    for (i, (container, children)) in containers.iter().enumerate() {
+        let node = scene.d_layout_nodes.get(container).unwrap();
+        assert!(node.l_offset == dom::Offset::new(0, i as i32 * 8));
+        assert!(node.l_size == dom::Size::new(640, 8));
+        assert!(node.l_children.len() == 80);
+        drop(node);
+
+        for (j, child) in children.iter().enumerate() {
+            let node = scene.d_layout_nodes.get(child).unwrap();
+            assert!(node.l_offset == dom::Offset::new(j as i32 * 8, 0));
+        }
+    }
+
+    // Growing one square wraps the last one in its row, which moves the
+    // containers after it down
+    scene
+        .width()
+        .set(&containers[2].1[0], dom::Value::Constant(16));
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+
+    let node = scene.d_layout_nodes.get(&containers[2].0).unwrap();
+    assert!(node.l_size == dom::Size::new(640, 16));
+    drop(node);
+    let node = scene.d_layout_nodes.get(&containers[2].1[79]).unwrap();
+    assert!(node.l_offset == dom::Offset::new(0, 8));
+    drop(node);
+    let node = scene.d_layout_nodes.get(&containers[3].0).unwrap();
+    assert!(node.l_offset == dom::Offset::new(0, 32));
+}
+
+/// Test that text keeps its glyph layout when the window is resized but
+/// the text is not, and is laid out again once the text changes
+#[test]
+fn text_measurement_cache() {
+    let (_, mut virtual_output, mut output, mut scene, root) = setup_dakota();
+
+    let child = scene.create_element().unwrap();
+    scene.add_child_to_element(&root, child.clone());
+    scene.width().set(&child, dom::Value::Constant(200));
+    scene.set_text_regular(&child, "Hello Dakota, this is some wrapped text");
+
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+    let glyphs = scene.d_layout_nodes.get(&child).unwrap().l_children.clone();
+    assert!(!glyphs.is_empty());
+
+    // The text is a fixed width, so resizing the window doesn't move its glyphs
+    output.set_resolution(&mut scene, 800, 600).unwrap();
+    virtual_output.set_size((800, 600));
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+    assert!(scene.d_layout_nodes.get(&child).unwrap().l_children == glyphs);
+
+    scene.set_text_regular(&child, "Goodbye");
+    scene
+        .recompile(&virtual_output)
+        .expect("Refreshing Dakota Scene");
+    let new_glyphs = scene.d_layout_nodes.get(&child).unwrap().l_children.clone();
+    assert!(!new_glyphs.is_empty());
+    assert!(new_glyphs != glyphs);
+}
@@ -11,7 +11,10 @@ extern crate image;
 extern crate lluvia as ll;
 extern crate thundr as th;
 pub use th::ThundrError as DakotaError;
-pub use th::{Damage, Dmabuf, DmabufPlane, Droppable, MappedImage};
+pub use th::{
+    Damage, Dmabuf, DmabufPlane, Droppable, GpuFrameTimings, MappedImage, PresentMode,
+    PresentationTime, SamplerMode, SubpixelLayout, Transform,
+};
 
 extern crate bitflags;
 
@@ -20,32 +23,53 @@ extern crate utils;
 use utils::log;
 pub use utils::MemImage;
 pub use utils::{
-    anyhow, fdwatch::FdWatch, region::Rect, timing::StopWatch, Context, Error, Result,
+    anyhow,
+    event_loop::{EventLoop, TimerId},
+    region::Rect,
+    timing::StopWatch,
+    Context, Error, Result,
 };
 
+pub mod access;
+mod animation;
+mod asset;
+mod atlas;
 pub mod dom;
 pub mod input;
 #[cfg(test)]
 mod tests;
-pub use crate::input::{Keycode, MouseButton};
+pub use crate::input::{
+    AccelProfile, DeviceSettings, InputConfig, KeyRepeatConfig, Keycode, KeymapConfig, MouseButton,
+    DEFAULT_SEAT,
+};
 mod platform;
-use platform::Platform;
+use platform::{OutputPlatform, Platform};
 pub mod xml;
 
 pub mod event;
-pub use event::{AxisSource, GlobalEvent, OutputEvent, PlatformEvent, RawKeycode};
+pub use event::{
+    AxisSource, EventHistory, GlobalEvent, InputDevice, OutputEvent, PlatformEvent, RawKeycode,
+    TabletToolType,
+};
 use event::{GlobalEventSystem, OutputEventSystem, PlatformEventSystem};
 mod layout;
 mod output;
 mod virtual_output;
 pub use virtual_output::VirtualOutput;
 mod render;
-pub use output::{Output, OutputInfo};
+pub use output::{FrameStats, Output, OutputInfo};
 mod font;
 mod scene;
-pub use scene::Scene;
+mod svg;
+pub use scene::{
+    CanvasCommand, CanvasItemId, CanvasPath, FillRule, Scene, VideoFormat, VideoFrame,
+    MAX_VIDEO_QUEUE,
+};
 
 use std::os::fd::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+use utils::event_loop::{LoopEvent, Signal};
 
 /// Dakota Object Id
 ///
@@ -94,6 +118,9 @@ pub struct Dakota {
     // It might reference the window inside plat, and will segfault if
     // dropped after it.
     d_thund: th::Thundr,
+    /// The info Thundr was created with. This is used to create it again
+    /// if the GPU is lost.
+    d_th_info: th::CreateInfo<'static>,
     /// The list of OutputInfos available for use. These can be used to
     /// specify a particular display region while creating an Output.
     d_output_infos: Vec<OutputInfo>,
@@ -102,6 +129,12 @@ pub struct Dakota {
     /// This may be SDL2 for windowed systems, or direct2display. This handles platform-specific
     /// initialization.
     d_plat: Box<dyn Platform>,
+    /// The event loop shared by the platform and the app
+    ///
+    /// Input, the app's fds and its timers all wake up the same loop.
+    d_event_loop: EventLoop,
+    /// The fds the app asked us to watch
+    d_user_fds: Vec<RawFd>,
     /// Global event queue
     d_global_event_system: GlobalEventSystem,
     /// Output Id system
@@ -110,6 +143,8 @@ pub struct Dakota {
     d_output_event_system: ll::Component<OutputEventSystem>,
     /// per-VirtualOutput event queues
     d_platform_event_system: ll::Component<PlatformEventSystem>,
+    /// Samples per pixel that new Outputs are drawn with
+    d_sample_count: u32,
 }
 
 /// Enum for specifying subsurface operations
@@ -145,11 +180,22 @@ impl Dakota {
         Self::init_thundr(plat)
     }
 
+    /// Create a winit backend
+    #[cfg(feature = "winit")]
+    fn create_winit_platform() -> Result<(Box<dyn Platform>, th::Thundr)> {
+        let plat = Box::new(platform::WinitPlat::new().map_err(|e| {
+            log::error!("Failed to create new winit platform: {:?}", e);
+            e
+        })?);
+
+        Self::init_thundr(plat)
+    }
+
     /// Create an atomic DRM-KMS backend
     #[cfg(feature = "drm")]
-    fn create_drm_platform() -> Result<(Box<dyn Platform>, th::Thundr)> {
+    fn create_drm_platform(evloop: &mut EventLoop) -> Result<(Box<dyn Platform>, th::Thundr)> {
         let plat = Box::new(
-            platform::LibinputPlat::new(platform::BackendType::Drm).map_err(|e| {
+            platform::LibinputPlat::new(platform::BackendType::Drm, evloop).map_err(|e| {
                 log::error!("Failed to create new libinput platform: {:?}", e);
                 e
             })?,
@@ -160,9 +206,9 @@ impl Dakota {
 
     /// Create a Vulkan "Direct to Display" platform
     #[cfg(feature = "direct2display")]
-    fn create_vkd2d_platform() -> Result<(Box<dyn Platform>, th::Thundr)> {
+    fn create_vkd2d_platform(evloop: &mut EventLoop) -> Result<(Box<dyn Platform>, th::Thundr)> {
         let plat = Box::new(
-            platform::LibinputPlat::new(platform::BackendType::VkD2d).map_err(|e| {
+            platform::LibinputPlat::new(platform::BackendType::VkD2d, evloop).map_err(|e| {
                 log::error!("Failed to create new libinput platform: {:?}", e);
                 e
             })?,
@@ -171,6 +217,20 @@ impl Dakota {
         Self::init_thundr(plat)
     }
 
+    /// Create a VNC server platform
+    #[cfg(feature = "vnc")]
+    fn create_vnc_platform(
+        address: &str,
+        evloop: &mut EventLoop,
+    ) -> Result<(Box<dyn Platform>, th::Thundr)> {
+        let plat = Box::new(platform::VncPlat::new(address, evloop).map_err(|e| {
+            log::error!("Failed to create new VNC platform: {:?}", e);
+            e
+        })?);
+
+        Self::init_thundr(plat)
+    }
+
     /// Create a headless platform
     fn create_headless_platform() -> Result<(Box<dyn Platform>, th::Thundr)> {
         let plat = Box::new(platform::HeadlessPlat::new());
@@ -184,7 +244,20 @@ impl Dakota {
     /// get the DPI of the display. These three are tested since they all may fail
     /// given different configurations. DPI fails if SDL2 tries to initialize us on
     /// a physical display.
-    fn initialize_platform() -> Result<(Box<dyn Platform>, th::Thundr)> {
+    #[allow(unused_variables)]
+    fn initialize_platform(evloop: &mut EventLoop) -> Result<(Box<dyn Platform>, th::Thundr)> {
+        // ------------------------------------------------------------------------
+        // VNC
+        // ------------------------------------------------------------------------
+        // This is only used when asked for, since it serves the desktop over
+        // the network. The variable holds the address to listen on.
+        #[cfg(feature = "vnc")]
+        if let Ok(address) = std::env::var("DAKOTA_VNC_BACKEND") {
+            let ret = Self::create_vnc_platform(&address, evloop)?;
+            log::debug!("Using VNC");
+            return Ok(ret);
+        }
+
         if std::env::var("DAKOTA_HEADLESS_BACKEND").is_err() {
             // ------------------------------------------------------------------------
             // SDL 2
@@ -198,11 +271,27 @@ impl Dakota {
                 }
             }
 
+            // ------------------------------------------------------------------------
+            // winit
+            // ------------------------------------------------------------------------
+            // The other window system backend, for when SDL2 isn't built or
+            // failed to start. macOS always has a window system.
+            #[cfg(feature = "winit")]
+            if cfg!(target_os = "macos")
+                || std::env::var("DISPLAY").is_ok()
+                || std::env::var("WAYLAND_DISPLAY").is_ok()
+            {
+                if let Ok(ret) = Self::create_winit_platform() {
+                    log::debug!("Using winit");
+                    return Ok(ret);
+                }
+            }
+
             // ------------------------------------------------------------------------
             // DRM
             // ------------------------------------------------------------------------
             #[cfg(feature = "drm")]
-            if let Ok(ret) = Self::create_drm_platform() {
+            if let Ok(ret) = Self::create_drm_platform(evloop) {
                 log::debug!("Using Atomic DRM-KMS");
                 return Ok(ret);
             }
@@ -211,7 +300,7 @@ impl Dakota {
             // Vulkan Direct to Display
             // ------------------------------------------------------------------------
             #[cfg(feature = "direct2display")]
-            if let Ok(ret) = Self::create_vkd2d_platform() {
+            if let Ok(ret) = Self::create_vkd2d_platform(evloop) {
                 log::debug!("Using Vulkan Direct to Display");
                 return Ok(ret);
             }
@@ -236,7 +325,18 @@ impl Dakota {
     /// This returns the main Dakota instance along with the primary/default
     /// output.
     pub fn new() -> Result<Self> {
-        let (plat, thundr) = Self::initialize_platform()?;
+        let mut event_loop = EventLoop::new();
+        // Quit gracefully when asked to, and let the app know when one
+        // of its children exits. This needs to happen before Thundr
+        // starts any threads, which would otherwise still receive these
+        // signals.
+        if let Err(e) =
+            event_loop.watch_signals(&[Signal::SIGINT, Signal::SIGTERM, Signal::SIGCHLD])
+        {
+            log::error!("Could not watch for signals: {:?}", e);
+        }
+
+        let (plat, thundr) = Self::initialize_platform(&mut event_loop)?;
         let info = th::CreateInfo::builder()
             .surface_type(plat.get_th_surf_type()?)
             .build();
@@ -252,12 +352,20 @@ impl Dakota {
 
         Ok(Self {
             d_plat: plat,
+            d_event_loop: event_loop,
+            d_user_fds: Vec::new(),
             d_output_infos: output_infos,
             d_thund: thundr,
+            d_th_info: info,
             d_global_event_system: GlobalEventSystem::new(),
             d_output_event_system: output_evsys,
             d_platform_event_system: output_ecs.add_component(),
             d_output_ecs: output_ecs,
+            // This can be overridden for debugging
+            d_sample_count: std::env::var("DAKOTA_MSAA_SAMPLES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
         })
     }
 
@@ -290,7 +398,11 @@ impl Dakota {
     /// This chooses the default output type. For fine-grained control use
     /// `create_output_with_info`.
     pub fn create_output(&mut self, virtual_output: &VirtualOutput) -> Result<Output> {
-        let output_info = self.d_output_infos[0].clone();
+        let output_info = self
+            .d_output_infos
+            .first()
+            .cloned()
+            .ok_or(anyhow!("No displays are available"))?;
         self.create_output_with_info(&output_info, virtual_output)
     }
 
@@ -322,27 +434,84 @@ impl Dakota {
                 e
             })?;
 
+        let display = self.create_th_display(
+            win.as_ref(),
+            output_info.oi_payload.clone(),
+            self.d_sample_count,
+        )?;
+
+        let ret = Output::new(
+            win,
+            display,
+            output_info.oi_payload.clone(),
+            self.d_sample_count,
+            output_id,
+            self.d_output_event_system.clone(),
+        );
+        // If we successfully created an Output, add its id to our OutputInfo for tracking
+        if let Ok(output) = &ret {
+            output_info.add_output(output.d_id.clone());
+        }
+
+        return ret;
+    }
+
+    /// Create a Thundr Display for an output window
+    fn create_th_display(
+        &mut self,
+        win: &dyn OutputPlatform,
+        payload: Arc<dyn th::DisplayInfoPayload>,
+        sample_count: u32,
+    ) -> Result<th::Display> {
         let info = th::CreateInfo::builder()
             .surface_type(self.d_plat.get_th_surf_type()?)
             // This is the private information Dakota's platform provides
             .window_info(win.get_th_window_info()?)
             // This is the private information about the virtual/physical
             // output provided by Thundr
-            .display_info(output_info.oi_payload.clone())
+            .display_info(payload)
+            // Optionally enable MSAA for smoother transformed surfaces
+            .sample_count(sample_count)
             .build();
 
-        let display = self
-            .d_thund
+        self.d_thund
             .get_display(&info)
-            .context("Failed to get Thundr Display")?;
+            .context("Failed to get Thundr Display")
+    }
 
-        let ret = Output::new(win, display, output_id, self.d_output_event_system.clone());
-        // If we successfully created an Output, add its id to our OutputInfo for tracking
-        if let Ok(output) = &ret {
-            output_info.add_output(output.d_id.clone());
-        }
+    /// Recover from losing the GPU
+    ///
+    /// This should be called when the `DeviceLost` event is received. Thundr
+    /// is initialized again, and `output` and `scene` are moved to the new
+    /// device. All image resources in `scene` are undefined afterwards, and
+    /// the app must define them again before the next redraw.
+    pub fn recover_from_device_loss(
+        &mut self,
+        output: &mut Output,
+        scene: &mut Scene,
+    ) -> Result<()> {
+        log::error!("Dakota: recreating Thundr after the GPU was lost");
+        self.d_thund = th::Thundr::new(&self.d_th_info).context("Failed to initialize Thundr")?;
+
+        let display = self.create_th_display(
+            output.d_output_plat.as_ref(),
+            output.d_payload.clone(),
+            output.d_sample_count,
+        )?;
+        output.replace_display(display)?;
+        scene.replace_device(output.d_display.d_dev.clone());
+
+        Ok(())
+    }
 
-        return ret;
+    /// Set the number of samples per pixel to draw with
+    ///
+    /// Values above 1 enable MSAA, which smooths the edges of transformed
+    /// surfaces. This applies to Outputs created after this is called. If
+    /// the device does not support this many samples then the next lower
+    /// supported count is used, see `Output::get_sample_count`.
+    pub fn set_sample_count(&mut self, count: u32) {
+        self.d_sample_count = count.max(1);
     }
 
     /// Add a file descriptor to watch
@@ -351,7 +520,71 @@ impl Dakota {
     /// meaning dakota will return control to the user when this fd is readable.
     /// This is done through the `UserFdReadable` event.
     pub fn add_watch_fd(&mut self, fd: RawFd) {
-        self.d_plat.add_watch_fd(fd);
+        self.d_event_loop.add_fd(fd);
+        self.d_user_fds.push(fd);
+    }
+
+    /// Stop watching a file descriptor
+    ///
+    /// This should be called before the fd is closed.
+    pub fn remove_watch_fd(&mut self, fd: RawFd) {
+        self.d_event_loop.remove_fd(fd);
+        self.d_user_fds.retain(|f| *f != fd);
+    }
+
+    /// Wake up once after `after` has passed
+    ///
+    /// This will return control to the user with the `Timer` event.
+    pub fn add_timer(&mut self, after: Duration) -> TimerId {
+        self.d_event_loop.add_timer(after)
+    }
+
+    /// Wake up every `interval` until the timer is removed
+    ///
+    /// The `Timer` event is sent each time. Expirations are skipped if the
+    /// app falls behind instead of being delivered all at once.
+    pub fn add_repeating_timer(&mut self, interval: Duration) -> TimerId {
+        self.d_event_loop.add_repeating_timer(interval)
+    }
+
+    /// Stop a timer
+    ///
+    /// No more `Timer` events will be sent for it.
+    pub fn remove_timer(&mut self, id: TimerId) {
+        self.d_event_loop.remove_timer(id)
+    }
+
+    /// Run `f` right before the next dispatch goes to sleep
+    ///
+    /// This is useful for deferring work until all pending events have
+    /// been handled.
+    pub fn add_idle<F: FnOnce() + 'static>(&mut self, f: F) {
+        self.d_event_loop.add_idle(f)
+    }
+
+    /// Configure the input devices
+    ///
+    /// This sets options such as tap-to-click and pointer acceleration
+    /// for the devices on this system, including ones plugged in later.
+    /// This has no effect when running inside another window system.
+    pub fn set_input_config(&mut self, config: &InputConfig) {
+        self.d_plat.set_input_config(config);
+    }
+
+    /// Change the keyboard layout used for key events
+    ///
+    /// If the layout is invalid an error is returned and the current
+    /// one is kept.
+    pub fn set_keymap(&mut self, config: &KeymapConfig) -> Result<()> {
+        self.d_plat.set_keymap(config)
+    }
+
+    /// Change how held down keys repeat
+    ///
+    /// Repeated keys show up as additional key down events. When running
+    /// inside another window system its repeat settings are used instead.
+    pub fn set_key_repeat(&mut self, config: &KeyRepeatConfig) {
+        self.d_plat.set_key_repeat(&mut self.d_event_loop, config);
     }
 
     /// Drain the queue of currently unhandled events
@@ -365,13 +598,89 @@ impl Dakota {
     /// run the main Dakota platform loop
     ///
     /// This waits for incoming events which will trigger user input or rendering
-    /// to take place.
+    /// to take place. SIGINT and SIGTERM are delivered as the `Quit` event,
+    /// and SIGCHLD as the `ChildExited` event.
     pub fn dispatch(&mut self, timeout: Option<usize>) -> Result<()> {
         self.d_plat.run(
+            &mut self.d_event_loop,
             &mut self.d_global_event_system,
             &mut self.d_output_event_system,
             &mut self.d_platform_event_system,
             timeout,
-        )
+        )?;
+
+        let mut user_fd_readable = false;
+        for event in self.d_event_loop.drain_events() {
+            match event {
+                // The platform handles its own fds
+                LoopEvent::Readable(fd) => user_fd_readable |= self.d_user_fds.contains(&fd),
+                LoopEvent::Timer(id) => self.d_global_event_system.add_event_timer(id),
+                LoopEvent::Signal(Signal::SIGCHLD) => {
+                    self.d_global_event_system.add_event_child_exited()
+                }
+                LoopEvent::Signal(sig) => {
+                    log::info!("Received {:?}, quitting", sig);
+                    self.d_global_event_system.add_event_quit();
+                }
+            }
+        }
+        if user_fd_readable {
+            self.d_global_event_system.add_event_user_fd();
+        }
+
+        if self.d_plat.take_display_hotplug() {
+            self.refresh_output_infos()?;
+        }
+
+        Ok(())
+    }
+
+    /// Enumerate the available displays again after a hotplug
+    ///
+    /// OutputInfos for displays which were unplugged are destroyed, and
+    /// infos are created for new displays. The app is notified of both.
+    fn refresh_output_infos(&mut self) -> Result<()> {
+        let info = th::CreateInfo::builder()
+            .surface_type(self.d_plat.get_th_surf_type()?)
+            .build();
+        let payloads = self.d_thund.get_display_info_list(&info)?;
+
+        let mut i = 0;
+        while i < self.d_output_infos.len() {
+            let present = payloads
+                .iter()
+                .any(|p| self.d_output_infos[i].is_same_display(p.as_ref()));
+            if present {
+                i += 1;
+                continue;
+            }
+
+            let removed = self.d_output_infos.remove(i);
+            log::debug!("Display was unplugged: {:?}", removed);
+            removed.destroy();
+            self.d_global_event_system.add_event_output_removed(removed);
+        }
+
+        for payload in payloads {
+            if self
+                .d_output_infos
+                .iter()
+                .any(|oi| oi.is_same_display(payload.as_ref()))
+            {
+                continue;
+            }
+
+            let added = OutputInfo::new(self.d_output_event_system.clone(), payload);
+            log::debug!("Display was plugged in: {:?}", added);
+            self.d_output_infos.push(added.clone());
+            self.d_global_event_system.add_event_output_added(added);
+        }
+
+        Ok(())
+    }
+
+    /// Get the displays we can create Outputs on
+    pub fn get_output_infos(&self) -> &[OutputInfo] {
+        &self.d_output_infos
     }
 }
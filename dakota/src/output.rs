@@ -10,13 +10,46 @@
 extern crate utils;
 use crate::event::OutputEventSystem;
 use crate::platform::OutputPlatform;
-use crate::{OutputEvent, OutputId, Scene, VirtualOutput};
+use crate::render::DrawRecord;
+use crate::{dom, DakotaId, OutputEvent, OutputId, Rect, Scene, VirtualOutput};
 use utils::log;
-use utils::{anyhow, Error, Result};
+use utils::{anyhow, Context, Error, Result};
 
+use std::collections::HashMap;
 use std::ops::DerefMut;
+use std::os::fd::RawFd;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
+/// The number of entries in each gamma ramp accepted by an Output
+static GAMMA_RAMP_SIZE: u32 = 256;
+/// The size of the color table used when only gamma ramps are set
+static GAMMA_LUT_SIZE: u32 = 33;
+
+/// Statistics about the last frame drawn to an Output
+///
+/// These are meant for debugging tools such as performance overlays.
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    /// The number of frames this Output has drawn, including this one
+    pub fs_frame_number: u64,
+    /// The parts of the screen which changed. This is the entire screen
+    /// if the frame was drawn from scratch.
+    pub fs_damage: Vec<Rect<i32>>,
+    /// The area Thundr actually redrew, which covers all of `fs_damage`
+    pub fs_redraw_area: Rect<i32>,
+    /// The number of surfaces in the scene
+    pub fs_surface_count: usize,
+    /// The number of surfaces which were inside the redrawn area
+    pub fs_drawn_count: usize,
+    /// The number of surfaces which were not in the last frame
+    pub fs_surfaces_added: usize,
+    /// The number of surfaces from the last frame which were not drawn
+    pub fs_surfaces_removed: usize,
+    /// The number of surfaces which moved or changed since the last frame
+    pub fs_surfaces_changed: usize,
+}
+
 /// OutputInfo
 ///
 /// This trait encapsulates per Output backend information about
@@ -39,6 +72,16 @@ pub struct OutputInfo {
     oi_internal: Arc<RwLock<OutputInfoInternal>>,
 }
 
+impl std::fmt::Debug for OutputInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let internal = self.oi_internal.read().unwrap();
+        f.debug_struct("OutputInfo")
+            .field("active_outputs", &internal.oi_outputs.len())
+            .field("destroyed", &internal.oi_destroyed)
+            .finish()
+    }
+}
+
 /// OutputInfo is clonable because we need to both keep an internal
 /// list of it and pass it in as an argument to create_output, which
 /// requires interior mutability for tracking active usage counts.
@@ -90,6 +133,16 @@ impl OutputInfo {
         }
     }
 
+    /// Has the display this describes been removed?
+    pub fn is_destroyed(&self) -> bool {
+        self.oi_internal.read().unwrap().oi_destroyed
+    }
+
+    /// Does this describe the same display as `other`?
+    pub(crate) fn is_same_display(&self, other: &dyn th::DisplayInfoPayload) -> bool {
+        self.oi_payload.is_same_display(other)
+    }
+
     /// Multiple Displays may be created for the platform this info describes
     /// or only one, depending on the capabilities of this Display backend.
     /// Returns the number of Displays we can create for this output.
@@ -124,33 +177,136 @@ pub struct Output {
     /// Our thundr output object
     pub(crate) d_display: th::Display,
     /// Platform handling specific to this output
-    d_output_plat: Box<dyn OutputPlatform>,
+    pub(crate) d_output_plat: Box<dyn OutputPlatform>,
+    /// The OutputInfo payload this output was created from. This is
+    /// used to create a new display if the GPU is lost.
+    pub(crate) d_payload: Arc<dyn th::DisplayInfoPayload>,
+    /// The sample count this output was created with, which is also
+    /// used for a new display if the GPU is lost
+    pub(crate) d_sample_count: u32,
     /// per-Output event queues
     d_output_event_system: ll::Component<OutputEventSystem>,
+    /// Color correction loaded from the user's profile
+    d_color_profile: Option<th::ColorLut>,
+    /// Red, green and blue gamma ramps applied after the color profile
+    d_gamma_ramps: Option<[Vec<u16>; 3]>,
+    /// What we drew in the last frame, used to find what changed in the
+    /// next one. None if the next frame needs to be completely redrawn.
+    pub(crate) d_last_frame: Option<Vec<DrawRecord>>,
+    /// The number of draw commands recorded last frame
+    pub(crate) d_last_cmd_count: usize,
+    /// Scratch space for matching up the records of the last frame,
+    /// kept to avoid reallocating it every frame
+    pub(crate) d_old_records: HashMap<(usize, usize), usize>,
+    /// Is the display turned on? Nothing is drawn while it is off.
+    d_powered: bool,
+    /// Are frames shown without waiting for vblank?
+    d_tearing: bool,
+    /// Is variable refresh rate turned on?
+    d_adaptive_sync: bool,
+    /// The present mode selected by the user, if any
+    d_present_mode: Option<th::PresentMode>,
+    /// What happened in the last frame drawn
+    pub(crate) d_frame_stats: FrameStats,
 }
 
 impl Output {
     pub fn new(
         window_plat: Box<dyn OutputPlatform>,
         display: th::Display,
+        payload: Arc<dyn th::DisplayInfoPayload>,
+        sample_count: u32,
         id: OutputId,
         evsys: ll::Component<OutputEventSystem>,
     ) -> Result<Self> {
         evsys.set(&id, OutputEventSystem::new());
+        Self::add_low_memory_callback(&display, &id, &evsys);
 
         Ok(Self {
             d_id: id,
             d_output_event_system: evsys,
             d_output_plat: window_plat,
+            d_payload: payload,
+            d_sample_count: sample_count,
             d_display: display,
+            d_color_profile: None,
+            d_gamma_ramps: None,
+            d_last_frame: None,
+            d_last_cmd_count: 0,
+            d_old_records: HashMap::new(),
+            d_powered: true,
+            d_tearing: false,
+            d_adaptive_sync: false,
+            d_present_mode: None,
+            d_frame_stats: FrameStats::default(),
         })
     }
 
+    /// Forward low memory notifications from Thundr to our event queue.
+    ///
+    /// Only hold a weak reference so the callback doesn't keep this
+    /// Output's id alive.
+    fn add_low_memory_callback(
+        display: &th::Display,
+        id: &OutputId,
+        evsys: &ll::Component<OutputEventSystem>,
+    ) {
+        let low_memory_evsys = evsys.clone();
+        let low_memory_id = Arc::downgrade(id);
+        display
+            .d_dev
+            .add_low_memory_callback(Box::new(move |_usage| {
+                if let Some(id) = low_memory_id.upgrade() {
+                    if let Some(mut evsys) = low_memory_evsys.get_mut(&id) {
+                        evsys.add_event_low_memory();
+                    }
+                }
+            }));
+    }
+
+    /// Replace our Thundr display after the GPU was lost
+    ///
+    /// Our color correction, subpixel layout, present mode, tearing and
+    /// adaptive sync are applied to the new display, and the next frame
+    /// is completely redrawn.
+    pub(crate) fn replace_display(&mut self, display: th::Display) -> Result<()> {
+        let layout = self.d_display.get_subpixel_layout();
+        Self::add_low_memory_callback(&display, &self.d_id, &self.d_output_event_system);
+        self.d_display = display;
+        self.d_display.set_subpixel_layout(layout);
+        if let Some(mode) = self.d_present_mode {
+            // The new display may not support what the old one did
+            if self.d_display.set_present_mode(mode).is_err() {
+                log::error!("Present mode {:?} is not supported by this display", mode);
+                self.d_present_mode = None;
+            }
+        }
+        self.d_display.set_tearing(self.d_tearing)?;
+        self.d_display.set_adaptive_sync(self.d_adaptive_sync);
+
+        self.d_last_frame = None;
+        self.update_color_lut()
+    }
+
     /// Create a scene compatible with this Output and VirtualOutput
     ///
     /// Resources will be created on the GPU this Output is present on.
     pub fn create_scene(&self, virtual_output: &VirtualOutput) -> Result<Scene> {
-        Scene::new(self.d_display.d_dev.clone(), virtual_output.get_size())
+        let mut scene = Scene::new(self.d_display.d_dev.clone(), virtual_output.get_size())?;
+        scene.set_subpixel_layout(self.d_display.get_subpixel_layout());
+
+        // Let the app know when the scene's images finish loading
+        let evsys = self.d_output_event_system.clone();
+        let id = Arc::downgrade(&self.d_id);
+        scene.add_asset_notifier(Box::new(move || {
+            if let Some(id) = id.upgrade() {
+                if let Some(mut evsys) = evsys.get_mut(&id) {
+                    evsys.add_event_asset_loaded();
+                }
+            }
+        }));
+
+        Ok(scene)
     }
 
     /// Get the current size of the drawing region for this display
@@ -158,6 +314,14 @@ impl Output {
         self.d_display.get_resolution()
     }
 
+    /// Get the horizontal and vertical Dots Per Inch of this display
+    ///
+    /// For windowed platforms this is the DPI of the display the window
+    /// is on, and changes when `PlatformEvent::DpiChanged` is delivered.
+    pub fn get_dpi(&self) -> Result<(i32, i32)> {
+        Ok(self.d_display.get_dpi()?)
+    }
+
     /// Get the major, minor of the DRM device currently in use
     pub fn get_drm_dev(&self) -> Option<(i64, i64)> {
         self.d_display.get_drm_dev()
@@ -189,6 +353,36 @@ impl Output {
             .pop_event()
     }
 
+    /// Get the memory usage of the GPU this Output renders on
+    pub fn get_memory_usage(&self) -> th::MemoryUsage {
+        self.d_display.d_dev.get_memory_usage()
+    }
+
+    /// Get the number of samples per pixel this Output draws with
+    ///
+    /// This may be lower than the count passed to
+    /// `Dakota::set_sample_count` if the device does not support it.
+    pub fn get_sample_count(&self) -> u32 {
+        self.d_display.get_sample_count()
+    }
+
+    /// Compile the pipelines this Output may switch to later
+    ///
+    /// This covers every color space the display supports, along with
+    /// color correction, so that changing them doesn't stall a frame.
+    /// The compiled pipelines are saved to disk to speed up the next
+    /// startup as well.
+    pub fn prewarm_pipelines(&self) -> Result<()> {
+        let dev = &self.d_display.d_dev;
+        dev.prewarm_pipelines(
+            &self.d_display.get_supported_color_spaces(),
+            self.d_display.get_sample_count(),
+            true,
+        );
+        dev.save_pipeline_cache()
+            .context("Could not save the pipeline cache")
+    }
+
     /// Mark this Output as needing a redraw
     ///
     /// This should be called after a Scene this Output is presenting
@@ -206,14 +400,191 @@ impl Output {
     /// Handle vulkan swapchain out of date. This is probably because the
     /// window's size has changed. This will requery the window size and
     /// refresh the layout tree.
+    ///
+    /// The swapchain itself is only recreated once the size stops
+    /// changing, frames are scaled to the old size until then.
     pub fn handle_resize(&mut self) -> Result<()> {
-        self.d_display.handle_ood()?;
+        self.d_display.recreate_swapchain(None);
+
+        self.request_redraw();
+
+        Ok(())
+    }
+
+    /// Set the color profile for this display
+    ///
+    /// `path` is either an ICC profile or a `.cube` 3D LUT, which is
+    /// used to correct the colors of everything drawn on this Output.
+    /// Passing None turns off color correction.
+    pub fn set_color_profile(&mut self, path: Option<&Path>) -> Result<()> {
+        self.d_color_profile = match path {
+            Some(path) => Some(
+                th::ColorLut::from_file(path)
+                    .context(format!("Could not load color profile {:?}", path))?,
+            ),
+            None => None,
+        };
+
+        self.update_color_lut()
+    }
+
+    /// Set the subpixel layout of this display
+    ///
+    /// Text in `scene` will be rasterized for this layout, which makes it
+    /// sharper on LCD panels. Passing `SubpixelLayout::None` uses
+    /// grayscale antialiasing. Subpixel rendering is not available on all
+    /// devices, in which case this has no effect.
+    pub fn set_subpixel_layout(&mut self, scene: &mut Scene, layout: th::SubpixelLayout) {
+        self.d_display.set_subpixel_layout(layout);
+        scene.set_subpixel_layout(self.d_display.get_subpixel_layout());
+
+        // All text needs to be redrawn
+        self.d_last_frame = None;
+        self.request_redraw();
+    }
+
+    /// Get the subpixel layout of this display
+    ///
+    /// This is None if subpixel rendering is not in use.
+    pub fn get_subpixel_layout(&self) -> th::SubpixelLayout {
+        self.d_display.get_subpixel_layout()
+    }
+
+    /// Get the number of entries in each gamma ramp
+    pub fn get_gamma_size(&self) -> u32 {
+        GAMMA_RAMP_SIZE
+    }
+
+    /// Set the gamma ramps for this display
+    ///
+    /// This takes the red, green and blue ramps, each of which must have
+    /// `get_gamma_size` entries. These are applied on top of any color
+    /// profile, and are used by night light tools to change the color
+    /// temperature. Passing None restores the original gamma.
+    pub fn set_gamma_ramps(&mut self, ramps: Option<[Vec<u16>; 3]>) -> Result<()> {
+        if let Some(ramps) = ramps.as_ref() {
+            if ramps.iter().any(|r| r.len() != GAMMA_RAMP_SIZE as usize) {
+                return Err(anyhow!("Gamma ramps must have {} entries", GAMMA_RAMP_SIZE));
+            }
+        }
+        self.d_gamma_ramps = ramps;
+
+        self.update_color_lut()
+    }
+
+    /// Combine our color profile and gamma ramps and send them to Thundr
+    fn update_color_lut(&mut self) -> Result<()> {
+        let lut = match (self.d_color_profile.as_ref(), self.d_gamma_ramps.as_ref()) {
+            (None, None) => None,
+            (profile, ramps) => {
+                let mut lut = profile
+                    .cloned()
+                    .unwrap_or(th::ColorLut::identity(GAMMA_LUT_SIZE));
+                if let Some([red, green, blue]) = ramps {
+                    lut.apply_gamma_ramps(red, green, blue)?;
+                }
+                Some(lut)
+            }
+        };
+        self.d_display.set_color_lut(lut.as_ref())?;
+        self.request_redraw();
+
+        Ok(())
+    }
+
+    /// Turn the physical display on or off
+    ///
+    /// This is used to blank the screen when the user is idle. Redraws
+    /// are skipped while the display is off. Only DRM displays can be
+    /// powered off, on other platforms this just stops drawing.
+    pub fn set_power(&mut self, on: bool) -> Result<()> {
+        if on == self.d_powered {
+            return Ok(());
+        }
+        self.d_display.set_power(on)?;
+        self.d_powered = on;
+
+        if on {
+            self.d_last_frame = None;
+            self.request_redraw();
+        }
+        Ok(())
+    }
+
+    /// Is the display turned on?
+    pub fn is_powered(&self) -> bool {
+        self.d_powered
+    }
+
+    /// Allow frames to tear
+    ///
+    /// Frames are shown as soon as they are drawn instead of waiting for
+    /// vblank. This should only be enabled while a fullscreen app that
+    /// asked for it is being shown, usually a game trying to reduce its
+    /// latency. Outputs which can't tear keep using vsync.
+    pub fn set_tearing(&mut self, allowed: bool) -> Result<()> {
+        if allowed == self.d_tearing {
+            return Ok(());
+        }
+        self.d_display.set_tearing(allowed)?;
+        self.d_tearing = allowed;
+        self.request_redraw();
+
+        Ok(())
+    }
 
+    /// Are frames allowed to tear?
+    pub fn is_tearing(&self) -> bool {
+        self.d_tearing
+    }
+
+    /// Get the present modes this Output can use
+    pub fn get_supported_present_modes(&self) -> Vec<th::PresentMode> {
+        self.d_display.get_supported_present_modes()
+    }
+
+    /// Get the present mode used while frames are not allowed to tear
+    pub fn get_present_mode(&self) -> th::PresentMode {
+        self.d_display.get_present_mode()
+    }
+
+    /// Select how frames are presented
+    ///
+    /// Outputs default to Mailbox if available and Fifo if not. Immediate
+    /// shows frames as soon as they are drawn, which tears but is useful
+    /// for benchmarking. `mode` must be one of
+    /// `get_supported_present_modes`.
+    pub fn set_present_mode(&mut self, mode: th::PresentMode) -> Result<()> {
+        self.d_display.set_present_mode(mode)?;
+        self.d_present_mode = Some(mode);
         self.request_redraw();
 
         Ok(())
     }
 
+    /// Does this Output's monitor support variable refresh rates?
+    pub fn supports_adaptive_sync(&self) -> bool {
+        self.d_display.supports_adaptive_sync()
+    }
+
+    /// Turn variable refresh rate on or off
+    ///
+    /// While enabled the monitor refreshes whenever we present a frame,
+    /// so an app drawing slower than the refresh rate is not held to
+    /// the next vblank. This is best used while a fullscreen app is
+    /// being shown. Outputs which don't support it ignore this.
+    pub fn set_adaptive_sync(&mut self, enabled: bool) {
+        if enabled != self.d_adaptive_sync {
+            self.d_display.set_adaptive_sync(enabled);
+            self.d_adaptive_sync = enabled;
+        }
+    }
+
+    /// Is variable refresh rate turned on?
+    pub fn is_adaptive_sync(&self) -> bool {
+        self.d_adaptive_sync
+    }
+
     /// Get the DRM format modifiers supported by this display
     pub fn get_supported_drm_render_modifiers(&self) -> Vec<u64> {
         self.d_display
@@ -224,14 +595,42 @@ impl Output {
             .collect()
     }
 
+    /// Get the pixel formats resources can be defined from with
+    /// `Scene::define_resource_from_bits`
+    pub fn get_supported_shm_formats(&self) -> Vec<dom::Format> {
+        self.d_display.d_dev.get_supported_shm_formats()
+    }
+
     /// Draw the next frame
     ///
     /// This dispatches *only* the rendering backend of Dakota. The `dispatch_platform`
     /// call *must* take place before this in order for correct updates to happen, as
     /// this will only render the current state of Dakota.
+    ///
+    /// Animated images and videos in `scene` are advanced to the frame
+    /// that should be shown now before drawing.
     pub fn redraw(&mut self, _virtual_output: &VirtualOutput, scene: &mut Scene) -> Result<()> {
+        if !self.d_powered {
+            log::debug!("Dakota::Output: display is powered off, not drawing");
+            return Ok(());
+        }
+
+        scene.update_animations()?;
+        scene.update_videos()?;
+
         match self.draw_surfacelists(scene) {
-            Ok(()) => {}
+            Ok(()) => {
+                // Some platforms show our frames themselves
+                if self.d_output_plat.wants_frames() {
+                    let frame = self.d_display.read_frame();
+                    self.d_output_plat
+                        .present_frame(&frame.mi_data, self.d_display.get_resolution());
+                }
+                // The resize is applied in a later frame
+                if self.d_display.is_resize_pending() {
+                    self.request_redraw();
+                }
+            }
             Err(th::ThundrError::OUT_OF_DATE) => {
                 // If Thundr returned out of date while
                 self.d_output_event_system
@@ -241,6 +640,14 @@ impl Output {
                     .add_event_resized();
                 log::debug!("Dakota::Output: Swapchain out of date, triggering resize");
             }
+            Err(th::ThundrError::DEVICE_LOST) => {
+                self.d_output_event_system
+                    .get_mut(&self.d_id)
+                    .unwrap()
+                    .deref_mut()
+                    .add_event_device_lost();
+                log::error!("Dakota::Output: GPU was lost, waiting for recovery");
+            }
             Err(e) => return Err(Error::from(e).context("Thundr: drawing failed with error")),
         };
         log::debug!("Dakota::Output: finished dispatching rendering",);
@@ -248,6 +655,122 @@ impl Output {
         return Ok(());
     }
 
+    /// Get the number of the last frame drawn to this Output
+    ///
+    /// This increases by one every time `redraw` draws a frame, and
+    /// identifies frames in `take_presentation_times`.
+    pub fn get_frame_count(&self) -> u64 {
+        self.d_display.get_frame_count()
+    }
+
+    /// Get when recently drawn frames were shown on the display
+    ///
+    /// Each frame is reported once. Some backends only know this some
+    /// time after `redraw` returns, in which case `get_presentation_fd`
+    /// becomes readable once they have been reported.
+    pub fn take_presentation_times(&mut self) -> Vec<th::PresentationTime> {
+        self.d_display.take_presentation_times()
+    }
+
+    /// Get an fd to watch for presentation times
+    ///
+    /// This becomes readable when `take_presentation_times` has new
+    /// times to report, and can be passed to `Dakota::add_watch_fd`.
+    /// It is None if times are known as soon as `redraw` returns. The
+    /// fd is only valid for the lifetime of this Output.
+    pub fn get_presentation_fd(&self) -> Option<RawFd> {
+        self.d_display.get_presentation_fd()
+    }
+
+    /// Get statistics about the last frame drawn
+    pub fn get_frame_stats(&self) -> &FrameStats {
+        &self.d_frame_stats
+    }
+
+    /// Turn GPU profiling on or off
+    ///
+    /// While this is on, the GPU time spent on each frame can be read
+    /// with `take_gpu_timings`.
+    pub fn set_gpu_profiling(&mut self, enabled: bool) -> Result<()> {
+        self.d_display
+            .set_gpu_profiling(enabled)
+            .context("Could not change GPU profiling")
+    }
+
+    /// Get the GPU timings of recently drawn frames
+    ///
+    /// Each frame is reported once, after the GPU has finished drawing
+    /// it, so this lags `redraw` by a frame or two.
+    pub fn take_gpu_timings(&mut self) -> Vec<th::GpuFrameTimings> {
+        self.d_display.take_gpu_timings()
+    }
+
+    /// Export the last frame drawn to this Output as a dmabuf
+    ///
+    /// This is used for screen recording. The frame is a single linear
+    /// plane in DRM_FORMAT_ARGB8888, and should be exported right after
+    /// `redraw`. The copy is done on the GPU after the frame, its
+    /// contents are ready once `is_frame_point_done` returns true for
+    /// `get_last_frame_point`. The memory is reused for a later frame,
+    /// so it should only be read until the next few frames are drawn.
+    pub fn export_frame(&mut self) -> Result<th::Dmabuf> {
+        self.d_display
+            .export_frame()
+            .context("Could not export frame")
+    }
+
+    /// Get the GPU timeline point of the last frame drawn
+    ///
+    /// This also covers copies made by `export_frame`.
+    pub fn get_last_frame_point(&self) -> u64 {
+        self.d_display.get_last_frame_point()
+    }
+
+    /// Has the GPU finished the frame at timeline `point`
+    pub fn is_frame_point_done(&self, point: u64) -> bool {
+        self.d_display.d_dev.is_point_signaled(point)
+    }
+
+    /// Read back the last frame drawn to this Output
+    ///
+    /// The pixels are tightly packed BGRA rows the size of this Output.
+    /// This should be done right after `redraw`.
+    pub fn read_frame(&mut self) -> th::MappedImage {
+        self.d_display.read_frame()
+    }
+
+    /// Copy the last frame drawn to this Output into a dmabuf
+    ///
+    /// Unlike `export_frame` the memory is provided by the caller, which is
+    /// what screencopy clients and nested compositors need. The dmabuf must
+    /// be a single plane in DRM_FORMAT_ARGB8888 at least the size of this
+    /// Output. This should be done right after `redraw`.
+    pub fn copy_frame_to_dmabuf(&mut self, dmabuf: &th::Dmabuf) -> Result<()> {
+        self.d_display
+            .copy_frame_to_dmabuf(dmabuf)
+            .context("Could not copy frame into dmabuf")
+    }
+
+    /// Copy the last frame drawn to this Output into a resource
+    ///
+    /// This lets one Output be shown inside of another scene. The resource
+    /// must already have been defined with contents at least the size of
+    /// this Output, and belong to the same device.
+    pub fn copy_frame_to_resource(&mut self, scene: &Scene, res: &DakotaId) -> Result<()> {
+        {
+            let image = scene
+                .d_resource_thundr_image
+                .get(res)
+                .ok_or(anyhow!("Resource does not have a GPU image defined"))?;
+            self.d_display
+                .copy_frame_to_image(&image)
+                .context("Could not copy frame into resource")?;
+        }
+
+        scene.add_resource_damage(res, None);
+        Ok(())
+    }
+
     /// Dump the current swapchain image to a file
     ///
     /// This dumps the image contents to a simple PPM file, used for automated testing
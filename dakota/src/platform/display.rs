@@ -4,23 +4,41 @@
 /// present. This is done with the `VK_KHR_Display` Vulkan surface type
 /// and using libinput to get input events.
 extern crate input;
+use input::event::device::DeviceEvent;
+use input::event::gesture::{
+    GestureEndEvent, GestureEvent, GestureEventCoordinates, GestureEventTrait, GesturePinchEvent,
+    GesturePinchEventTrait, GestureSwipeEvent,
+};
 use input::event::keyboard::{KeyState, KeyboardEvent, KeyboardEventTrait};
 use input::event::pointer;
-use input::event::pointer::{ButtonState, PointerEvent, PointerScrollEvent};
-use input::{Libinput, LibinputInterface};
+use input::event::pointer::{ButtonState, PointerEvent, PointerEventTrait, PointerScrollEvent};
+use input::event::tablet_tool::{ProximityState, TabletToolEvent, TabletToolEventTrait, TipState};
+use input::event::touch::{TouchEvent, TouchEventPosition, TouchEventSlot, TouchEventTrait};
+use input::event::EventTrait;
+use input::{Device, Libinput, LibinputInterface};
 
 extern crate xkbcommon;
+#[cfg(feature = "drm")]
 use xkbcommon::xkb;
 
+use super::keyboard::XkbKeyboard;
+use super::repeat::KeyRepeat;
 use super::{BackendType, OutputPlatform, Platform};
 use crate::event::*;
-use crate::input::{convert_libinput_mouse_to_dakota, convert_xkb_keycode_to_dakota, Mods};
+use crate::input::{
+    convert_dakota_accel_profile_to_libinput, convert_libinput_mouse_to_dakota,
+    convert_libinput_tool_to_dakota, DeviceSettings, InputConfig, KeyRepeatConfig, KeymapConfig,
+};
 use crate::OutputId;
 use crate::*;
 use utils::log;
+#[cfg(feature = "drm")]
+use utils::platform::DeviceMonitor;
 
+#[cfg(not(feature = "drm"))]
 use std::fs::{File, OpenOptions};
-use std::os::fd::{AsRawFd, RawFd};
+use std::os::fd::AsRawFd;
+#[cfg(not(feature = "drm"))]
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::OwnedFd;
 use std::path::Path;
@@ -28,8 +46,8 @@ use std::path::Path;
 /// This is sort of like a private userdata struct which
 /// is used as an interface to the systems devices
 ///
-/// i.e. this could call consolekit to avoid having to
-/// be a root user to get raw input.
+/// With the DRM backend devices are opened through Thundr's seat
+/// session, so that we don't need to be a root user to get raw input.
 struct Inkit {
     // For now we don't have anything special to do,
     // so we are just putting a phantom int here since
@@ -40,11 +58,23 @@ struct Inkit {
 /// This is the interface that libinput uses to abstract away
 /// consolekit and friends.
 ///
-/// In our case we just pass the arguments through to `open`.
+/// With the DRM backend we ask the seat manager to open the device.
+/// Otherwise we just pass the arguments through to `open`.
 /// We need to use the unix open extensions so that we can pass
 /// custom flags.
 impl LibinputInterface for Inkit {
     // open a device
+    #[cfg(feature = "drm")]
+    fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<OwnedFd, i32> {
+        log::debug!(" Opening device {:?}", path);
+        th::session::open_device(path, flags).map_err(|e| {
+            log::error!("Error on opening {:?}", e);
+            e.raw_os_error().unwrap_or(-1)
+        })
+    }
+
+    // open a device
+    #[cfg(not(feature = "drm"))]
     fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<OwnedFd, i32> {
         log::debug!(" Opening device {:?}", path);
         match OpenOptions::new()
@@ -74,6 +104,13 @@ impl LibinputInterface for Inkit {
     }
 
     // close a device
+    #[cfg(feature = "drm")]
+    fn close_restricted(&mut self, fd: OwnedFd) {
+        th::session::close_device(fd);
+    }
+
+    // close a device
+    #[cfg(not(feature = "drm"))]
     fn close_restricted(&mut self, fd: OwnedFd) {
         // this will close the file
         drop(File::from(fd));
@@ -89,69 +126,203 @@ pub struct LibinputPlat {
     dp_type: BackendType,
     /// libinput context
     dp_libin: Libinput,
-    /// libxkbcommon context
-    _dp_xkb_ctx: xkb::Context,
-    _dp_xkb_keymap: xkb::Keymap,
-    /// this is referenced by Seat, which needs to map and
-    /// share it with the clients
-    _dp_xkb_keymap_name: String,
-    /// xkb state machine
-    dp_xkb_state: xkb::State,
-    /// The current modifier key state. This will be updated using
-    /// xkb.
-    dp_current_modifiers: Mods,
-    /// Our private fd listener
-    dp_fdwatch: FdWatch,
+    /// Turns our keycodes into keysyms and text
+    dp_keyboard: XkbKeyboard,
+    /// Repeats the key being held down
+    dp_repeat: KeyRepeat,
     /// This is the Id of the virtual output we are driving
     /// TODO: right now this does not ever free our VirtualOutput
     /// id, so we need to find a way to allow recreation of the
     /// VirtualOutput.
     dp_output_id: Option<OutputId>,
+    /// The outputs we have created, which need redrawing when our
+    /// session becomes active again
+    dp_outputs: Vec<OutputId>,
+    /// Settings for our input devices
+    dp_input_config: InputConfig,
+    /// All input devices currently plugged in, so that new settings
+    /// can be applied to them
+    dp_devices: Vec<Device>,
+    /// The seat of the device our last input event came from
+    dp_seat: String,
+    /// Listens for DRM connector hotplug events
+    #[cfg(feature = "drm")]
+    dp_device_monitor: Option<DeviceMonitor>,
+    /// Has a display been plugged in or unplugged since we last checked
+    dp_display_hotplug: bool,
 }
 
 impl LibinputPlat {
-    pub fn new(backend_type: BackendType) -> Result<Self> {
+    /// Create the platform, registering our fds with `evloop`
+    pub fn new(backend_type: BackendType, evloop: &mut EventLoop) -> Result<Self> {
         let kit: Inkit = Inkit { _inner: 0 };
         let mut libin = Libinput::new_with_udev(kit);
 
-        // Create all the components for xkb
-        // A description of this can be found in the xkb
-        // section of wayland-book.com
-        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
-        let keymap = xkb::Keymap::new_from_names(
-            &context,
-            &"",
-            &"",
-            &"",
-            &"", // These should be env vars
-            None,
-            xkb::KEYMAP_COMPILE_NO_FLAGS,
-        )
-        .expect("Could not initialize a xkb keymap");
-        let km_name = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
-
-        let state = xkb::State::new(&keymap);
+        let keyboard = XkbKeyboard::new(&KeymapConfig::default())?;
         // we need to choose a "seat" for udev to listen on
         // the default seat is seat0, which is all input devs
         libin.udev_assign_seat("seat0").unwrap();
 
-        let mut fdwatch = FdWatch::new();
-        fdwatch.add_fd(libin.as_raw_fd());
-        fdwatch.register_events();
+        evloop.add_fd(libin.as_raw_fd());
+        // Wake up when the seat manager enables or disables our session
+        #[cfg(feature = "drm")]
+        if let Some(fd) = th::session::get_fd() {
+            evloop.add_fd(fd);
+        }
+        // Wake up when a display is plugged in or unplugged
+        #[cfg(feature = "drm")]
+        let device_monitor = match backend_type {
+            BackendType::Drm => Self::create_device_monitor(),
+            _ => None,
+        };
+        #[cfg(feature = "drm")]
+        if let Some(monitor) = device_monitor.as_ref() {
+            evloop.add_fd(monitor.get_fd());
+        }
 
         Ok(Self {
             dp_type: backend_type,
             dp_libin: libin,
-            _dp_xkb_ctx: context,
-            _dp_xkb_keymap: keymap,
-            _dp_xkb_keymap_name: km_name,
-            dp_xkb_state: state,
-            dp_current_modifiers: Mods::NONE,
-            dp_fdwatch: fdwatch,
+            dp_keyboard: keyboard,
+            dp_repeat: KeyRepeat::new(),
             dp_output_id: None,
+            dp_outputs: Vec::new(),
+            dp_input_config: InputConfig::default(),
+            dp_devices: Vec::new(),
+            dp_seat: DEFAULT_SEAT.to_string(),
+            #[cfg(feature = "drm")]
+            dp_device_monitor: device_monitor,
+            dp_display_hotplug: false,
         })
     }
 
+    /// Listen for changes to DRM devices
+    ///
+    /// Hotplug is not required, so if this fails we keep using the
+    /// displays found at startup.
+    #[cfg(feature = "drm")]
+    fn create_device_monitor() -> Option<DeviceMonitor> {
+        match DeviceMonitor::new(&["drm"]) {
+            Ok(monitor) => Some(monitor),
+            Err(e) => {
+                log::error!("Could not listen for display hotplug: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Check for displays being plugged in or unplugged
+    ///
+    /// The kernel notifies us when the state of one of a DRM device's
+    /// connectors changes.
+    #[cfg(feature = "drm")]
+    fn handle_device_events(&mut self) {
+        let monitor = match self.dp_device_monitor.as_mut() {
+            Some(monitor) => monitor,
+            None => return,
+        };
+
+        for event in monitor.read_events() {
+            if event.de_hotplug {
+                log::debug!("Display hotplug on {:?}", event.de_devnode);
+                self.dp_display_hotplug = true;
+            }
+        }
+    }
+
+    /// Handle our session being enabled or disabled
+    ///
+    /// When the user switches to another VT our devices are taken away.
+    /// Libinput releases its devices before we let the switch continue,
+    /// and when we come back the outputs are redrawn since the other
+    /// session may have drawn over them.
+    #[cfg(feature = "drm")]
+    fn handle_session_events(
+        &mut self,
+        evloop: &mut EventLoop,
+        output_queues: &mut ll::Component<OutputEventSystem>,
+    ) {
+        th::session::dispatch();
+
+        match th::session::take_state_change() {
+            Some(false) => {
+                // We won't see the key being released
+                self.dp_repeat.stop(evloop);
+                self.dp_libin.suspend();
+                th::session::acknowledge_disable();
+            }
+            Some(true) => {
+                if self.dp_libin.resume().is_err() {
+                    log::error!("Could not resume libinput");
+                }
+                for id in self.dp_outputs.iter() {
+                    if let Some(mut queue) = output_queues.get_mut(id) {
+                        queue.add_event_redraw();
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Apply our input config to a device
+    ///
+    /// Settings the device does not support are skipped.
+    fn configure_device(config: &InputConfig, device: &mut Device) {
+        let settings: DeviceSettings = config.get_device_settings(device.name());
+        log::debug!("Configuring input device {}: {:?}", device.name(), settings);
+
+        let mut results = Vec::new();
+        if let Some(enabled) = settings.tap_to_click {
+            if device.config_tap_finger_count() > 0 {
+                results.push(device.config_tap_set_enabled(enabled));
+            }
+        }
+        if let Some(enabled) = settings.natural_scroll {
+            if device.config_scroll_has_natural_scroll() {
+                results.push(device.config_scroll_set_natural_scroll_enabled(enabled));
+            }
+        }
+        if device.config_accel_is_available() {
+            if let Some(profile) = settings.accel_profile {
+                results.push(
+                    device.config_accel_set_profile(convert_dakota_accel_profile_to_libinput(
+                        profile,
+                    )),
+                );
+            }
+            if let Some(speed) = settings.accel_speed {
+                results.push(device.config_accel_set_speed(speed.clamp(-1.0, 1.0)));
+            }
+        }
+        if let Some(enabled) = settings.disable_while_typing {
+            if device.config_dwt_is_available() {
+                results.push(device.config_dwt_set_enabled(enabled));
+            }
+        }
+
+        // Moving a device to another seat makes libinput remove it and
+        // add it again, so only do this if it is on the wrong one
+        let seat = settings.seat.as_deref().unwrap_or(DEFAULT_SEAT);
+        if device.seat().logical_name() != seat && device.set_seat_logical_name(seat).is_err() {
+            log::error!(
+                "Could not move input device {} to seat {}",
+                device.name(),
+                seat
+            );
+        }
+
+        for res in results.iter() {
+            if let Err(e) = res {
+                log::error!(
+                    "Could not configure input device {}: {:?}",
+                    device.name(),
+                    e
+                );
+            }
+        }
+    }
+
     /// Translate a libinput scroll event into a Dakota axis event
     ///
     /// This is dyn since it can handle both horizontal and vertical
@@ -162,6 +333,7 @@ impl LibinputPlat {
         ev: &dyn pointer::PointerScrollEvent,
         source: AxisSource,
         v120: (f64, f64),
+        time: u64,
     ) {
         let mut horizontal = None;
         let mut vertical = None;
@@ -174,28 +346,85 @@ impl LibinputPlat {
             vertical = Some((ev.scroll_value(pointer::Axis::Vertical) * -1.0) as i32);
         }
 
-        evsys.add_event_scroll(horizontal, vertical, v120, source);
+        evsys.add_event_scroll(horizontal, vertical, v120, source, time);
+    }
+
+    /// Get the position of a touch point on the output
+    ///
+    /// libinput reports touchscreen positions in millimeters, which it
+    /// can scale to the size of the output for us.
+    fn get_touch_position(evsys: &PlatformEventSystem, ev: &dyn TouchEventPosition) -> (i32, i32) {
+        let size = evsys.get_output_size();
+        (
+            ev.x_transformed(size.0) as i32,
+            ev.y_transformed(size.1) as i32,
+        )
+    }
+
+    /// Get the tool, serial, and output position of a tablet tool event
+    ///
+    /// Like touchscreens, tablets are mapped to the entire output.
+    fn get_tablet_tool_info(
+        evsys: &PlatformEventSystem,
+        ev: &dyn TabletToolEventTrait,
+    ) -> (TabletToolType, u64, (i32, i32)) {
+        let size = evsys.get_output_size();
+        let tool = ev.tool();
+        (
+            convert_libinput_tool_to_dakota(tool.tool_type()),
+            tool.serial(),
+            (
+                ev.x_transformed(size.0) as i32,
+                ev.y_transformed(size.1) as i32,
+            ),
+        )
     }
 
     /// Get the next available event from libinput
     ///
     /// Dispatch should be called before this so libinput can
-    fn process_available(&mut self, platform_queues: &mut ll::Component<PlatformEventSystem>) {
+    fn process_available(
+        &mut self,
+        evloop: &mut EventLoop,
+        platform_queues: &mut ll::Component<PlatformEventSystem>,
+    ) {
         let mut evsys = platform_queues
             .get_mut(self.dp_output_id.as_ref().unwrap())
             .unwrap();
 
+        if let Some(key) = self.dp_repeat.check_timer(evloop) {
+            evsys.add_event_key_down(
+                key.rk_keycode,
+                key.rk_utf8,
+                RawKeycode::Linux(key.rk_key),
+                key.rk_time,
+            );
+        }
+
         while let Some(ev) = self.dp_libin.next() {
+            // Tell the app when events start coming from another seat
+            let seat = ev.device().seat();
+            if seat.logical_name() != self.dp_seat {
+                self.dp_seat = seat.logical_name().to_string();
+                evsys.add_event_seat(self.dp_seat.clone());
+            }
+
             match ev {
                 input::event::Event::Pointer(PointerEvent::Motion(m)) => {
-                    evsys.add_event_mouse_move(m.dx() as i32, m.dy() as i32);
+                    evsys.add_event_mouse_move(m.dx() as i32, m.dy() as i32, m.time_usec());
                 }
                 // TODO: actually handle advanced scrolling/finger behavior
                 // We should track ScrollWheel using the v120 api, and handle
                 // high-res and wheel click behavior. For ScrollFinger we
                 // should handle kinetic scrolling
                 input::event::Event::Pointer(PointerEvent::ScrollFinger(sf)) => {
-                    self.get_scroll_event(&mut evsys, &sf, AxisSource::Finger, (0.0, 0.0));
+                    self.get_scroll_event(
+                        &mut evsys,
+                        &sf,
+                        AxisSource::Finger,
+                        (0.0, 0.0),
+                        sf.time_usec(),
+                    );
                 }
                 input::event::Event::Pointer(PointerEvent::ScrollWheel(sw)) => {
                     let mut v120 = (0.0, 0.0);
@@ -209,69 +438,159 @@ impl LibinputPlat {
                         v120.1 = sw.scroll_value_v120(pointer::Axis::Vertical);
                     }
 
-                    self.get_scroll_event(&mut evsys, &sw, AxisSource::Wheel, v120);
+                    self.get_scroll_event(&mut evsys, &sw, AxisSource::Wheel, v120, sw.time_usec());
                 }
                 input::event::Event::Pointer(PointerEvent::Button(b)) => {
                     let button = convert_libinput_mouse_to_dakota(b.button());
 
                     if b.button_state() == ButtonState::Pressed {
-                        evsys.add_event_mouse_button_down(button);
+                        evsys.add_event_mouse_button_down(button, b.time_usec());
                     } else {
-                        evsys.add_event_mouse_button_up(button);
+                        evsys.add_event_mouse_button_up(button, b.time_usec());
                     }
                 }
-                input::event::Event::Keyboard(KeyboardEvent::Key(k)) => {
-                    // let xkb keep track of the keyboard state
-                    let changed = self.dp_xkb_state.update_key(
-                        // add 8 to account for differences between evdev and x11
-                        k.key() as u32 + 8,
-                        match k.key_state() {
-                            KeyState::Pressed => xkb::KeyDirection::Down,
-                            KeyState::Released => xkb::KeyDirection::Up,
-                        },
+                // Touchscreens. The seat slot identifies a finger across all
+                // touch devices on the seat, so we use it as the touch id.
+                input::event::Event::Touch(TouchEvent::Down(d)) => {
+                    let (x, y) = Self::get_touch_position(&evsys, &d);
+                    evsys.add_event_touch_down(d.seat_slot() as i32, x, y, d.time_usec());
+                }
+                input::event::Event::Touch(TouchEvent::Motion(m)) => {
+                    let (x, y) = Self::get_touch_position(&evsys, &m);
+                    evsys.add_event_touch_motion(m.seat_slot() as i32, x, y, m.time_usec());
+                }
+                input::event::Event::Touch(TouchEvent::Up(u)) => {
+                    evsys.add_event_touch_up(u.seat_slot() as i32, u.time_usec());
+                }
+                input::event::Event::Touch(TouchEvent::Cancel(c)) => {
+                    evsys.add_event_touch_cancel(c.time_usec());
+                }
+                // Touchpad gestures. Updates are relative to the last
+                // update, except for the pinch scale.
+                input::event::Event::Gesture(GestureEvent::Swipe(GestureSwipeEvent::Begin(b))) => {
+                    evsys.add_event_swipe_begin(b.finger_count() as u32, b.time_usec());
+                }
+                input::event::Event::Gesture(GestureEvent::Swipe(GestureSwipeEvent::Update(u))) => {
+                    evsys.add_event_swipe_update(u.dx(), u.dy(), u.time_usec());
+                }
+                input::event::Event::Gesture(GestureEvent::Swipe(GestureSwipeEvent::End(e))) => {
+                    evsys.add_event_swipe_end(e.cancelled(), e.time_usec());
+                }
+                input::event::Event::Gesture(GestureEvent::Pinch(GesturePinchEvent::Begin(b))) => {
+                    evsys.add_event_pinch_begin(b.finger_count() as u32, b.time_usec());
+                }
+                input::event::Event::Gesture(GestureEvent::Pinch(GesturePinchEvent::Update(u))) => {
+                    evsys.add_event_pinch_update(
+                        u.dx(),
+                        u.dy(),
+                        u.scale(),
+                        u.angle_delta(),
+                        u.time_usec(),
                     );
-
-                    let keysym = self.dp_xkb_state.key_get_one_sym(k.key() + 8);
-                    let key = convert_xkb_keycode_to_dakota(keysym);
-                    let utf = self.dp_xkb_state.key_get_utf8(k.key() + 8);
-
-                    // Update each modifier
-                    if changed != 0 {
-                        let mod_options = [
-                            (xkb::MOD_NAME_ALT, Mods::LALT),
-                            (xkb::MOD_NAME_NUM, Mods::NUM),
-                            (xkb::MOD_NAME_CAPS, Mods::CAPS),
-                            (xkb::MOD_NAME_CTRL, Mods::LCTRL),
-                            (xkb::MOD_NAME_LOGO, Mods::LMETA),
-                            (xkb::MOD_NAME_SHIFT, Mods::LCTRL),
-                        ];
-
-                        for opt in mod_options.iter() {
-                            self.dp_current_modifiers |= if self
-                                .dp_xkb_state
-                                .mod_name_is_active(&opt.0, xkb::STATE_MODS_EFFECTIVE)
-                            {
-                                opt.1
-                            } else {
-                                Mods::NONE
-                            };
+                }
+                input::event::Event::Gesture(GestureEvent::Pinch(GesturePinchEvent::End(e))) => {
+                    evsys.add_event_pinch_end(e.cancelled(), e.time_usec());
+                }
+                // Drawing tablets
+                input::event::Event::Tablet(TabletToolEvent::Proximity(p)) => {
+                    let (tool, serial, pos) = Self::get_tablet_tool_info(&evsys, &p);
+                    evsys.add_event_tablet_tool_proximity(
+                        tool,
+                        serial,
+                        p.proximity_state() == ProximityState::In,
+                        pos,
+                        p.time_usec(),
+                    );
+                }
+                input::event::Event::Tablet(TabletToolEvent::Axis(a)) => {
+                    let (tool, serial, pos) = Self::get_tablet_tool_info(&evsys, &a);
+                    evsys.add_event_tablet_tool_axis(
+                        tool,
+                        serial,
+                        pos,
+                        a.pressure(),
+                        (a.tilt_x(), a.tilt_y()),
+                        a.time_usec(),
+                    );
+                }
+                input::event::Event::Tablet(TabletToolEvent::Tip(t)) => {
+                    let (tool, serial, pos) = Self::get_tablet_tool_info(&evsys, &t);
+                    evsys.add_event_tablet_tool_tip(
+                        tool,
+                        serial,
+                        t.tip_state() == TipState::Down,
+                        pos,
+                        t.time_usec(),
+                    );
+                }
+                input::event::Event::Tablet(TabletToolEvent::Button(b)) => {
+                    let (tool, serial, _) = Self::get_tablet_tool_info(&evsys, &b);
+                    evsys.add_event_tablet_tool_button(
+                        tool,
+                        serial,
+                        b.button(),
+                        b.button_state() == ButtonState::Pressed,
+                        b.time_usec(),
+                    );
+                }
+                input::event::Event::Keyboard(KeyboardEvent::Key(k)) => {
+                    let pressed = k.key_state() == KeyState::Pressed;
+                    let info = self.dp_keyboard.handle_key(k.key(), pressed);
+
+                    // Ctrl+Alt+F<n> switches to VT n, this is never given
+                    // to the app
+                    #[cfg(feature = "drm")]
+                    if (xkb::keysyms::KEY_XF86Switch_VT_1..=xkb::keysyms::KEY_XF86Switch_VT_12)
+                        .contains(&info.ki_keysym)
+                    {
+                        if pressed {
+                            let vt = info.ki_keysym - xkb::keysyms::KEY_XF86Switch_VT_1 + 1;
+                            th::session::switch_session(vt as i32);
                         }
+                        continue;
+                    }
+
+                    let repeats = self.dp_keyboard.key_repeats(k.key());
+                    self.dp_repeat.handle_key(
+                        evloop,
+                        k.key(),
+                        pressed,
+                        repeats,
+                        &info,
+                        k.time_usec(),
+                    );
 
-                        // Add the modifier event with the latest mods
-                        evsys.add_event_keyboard_modifiers(self.dp_current_modifiers);
+                    // Add the modifier event with the latest mods
+                    if let Some(mods) = info.ki_mods {
+                        evsys.add_event_keyboard_modifiers(mods, k.time_usec());
                     }
 
-                    if k.key_state() == KeyState::Pressed {
-                        evsys.add_event_key_down(key, utf, RawKeycode::Linux(k.key()));
+                    if pressed {
+                        evsys.add_event_key_down(
+                            info.ki_key,
+                            info.ki_utf8,
+                            RawKeycode::Linux(k.key()),
+                            k.time_usec(),
+                        );
                     } else {
-                        // Key up events do not generate utf characters
                         evsys.add_event_key_up(
-                            key,
-                            String::with_capacity(0),
+                            info.ki_key,
+                            info.ki_utf8,
                             RawKeycode::Linux(k.key()),
+                            k.time_usec(),
                         );
                     }
                 }
+                // Newly plugged in devices pick up our settings
+                input::event::Event::Device(DeviceEvent::Added(a)) => {
+                    let mut device = a.device();
+                    Self::configure_device(&self.dp_input_config, &mut device);
+                    self.dp_devices.push(device);
+                }
+                input::event::Event::Device(DeviceEvent::Removed(r)) => {
+                    let device = r.device();
+                    self.dp_devices.retain(|d| *d != device);
+                }
                 _e => log::debug!("Unhandled Input Event: {:?}", _e),
             };
         }
@@ -285,9 +604,10 @@ impl Platform for LibinputPlat {
     /// then use this with a Thundr `Display`.
     fn create_output(
         &mut self,
-        _id: OutputId,
+        id: OutputId,
         _virtual_output_id: OutputId,
     ) -> Result<Box<dyn OutputPlatform>> {
+        self.dp_outputs.push(id);
         Ok(Box::new(LibinputOutput {
             lo_type: self.dp_type,
         }))
@@ -308,37 +628,56 @@ impl Platform for LibinputPlat {
         Ok(ret)
     }
 
-    /// Add a watch descriptor to our list. This will cause the platform's
-    /// event loop to wake when this fd is readable and queue the UserFd
-    /// event.
-    fn add_watch_fd(&mut self, fd: RawFd) {
-        self.dp_fdwatch.add_fd(fd);
-        self.dp_fdwatch.register_events();
+    /// Apply the new settings to every device we know of. Devices
+    /// added later will be configured when they appear.
+    fn set_input_config(&mut self, config: &InputConfig) {
+        self.dp_input_config = config.clone();
+
+        for device in self.dp_devices.iter_mut() {
+            Self::configure_device(&self.dp_input_config, device);
+        }
+    }
+
+    fn set_keymap(&mut self, config: &KeymapConfig) -> Result<()> {
+        self.dp_keyboard.set_keymap(config)
+    }
+
+    fn set_key_repeat(&mut self, evloop: &mut EventLoop, config: &KeyRepeatConfig) {
+        self.dp_repeat.set_config(evloop, config);
     }
 
     /// Run the event loop for this platform
     ///
     /// This will dispatch winsys handling and will wait for user
     /// input.
-    ///
-    /// Returns true if we should redraw the app due to an out of
-    /// date swapchain.
     fn run(
         &mut self,
+        evloop: &mut EventLoop,
         _global_evsys: &mut GlobalEventSystem,
-        _output_queues: &mut ll::Component<OutputEventSystem>,
+        output_queues: &mut ll::Component<OutputEventSystem>,
         platform_queues: &mut ll::Component<PlatformEventSystem>,
         timeout: Option<usize>,
     ) -> Result<()> {
-        self.dp_fdwatch.wait_for_events(timeout);
-        // TODO: return UserFdReadable?
+        evloop.wait_for_events(timeout);
+
+        #[cfg(feature = "drm")]
+        {
+            self.handle_session_events(evloop, output_queues);
+            self.handle_device_events();
+        }
+        #[cfg(not(feature = "drm"))]
+        let _ = output_queues;
 
         self.dp_libin.dispatch().unwrap();
-        self.process_available(platform_queues);
+        self.process_available(evloop, platform_queues);
 
         Ok(())
     }
 
+    fn take_display_hotplug(&mut self) -> bool {
+        std::mem::take(&mut self.dp_display_hotplug)
+    }
+
     fn get_th_surf_type<'a>(&self) -> Result<th::SurfaceType> {
         Ok(th::SurfaceType::Display)
     }
@@ -7,9 +7,8 @@ use super::{OutputPlatform, Platform};
 use crate::dom;
 use crate::{
     event::{GlobalEventSystem, OutputEventSystem, PlatformEventSystem},
-    OutputId, Result,
+    EventLoop, InputConfig, OutputId, Result,
 };
-use std::os::fd::RawFd;
 use utils::log;
 
 pub struct HeadlessPlat();
@@ -54,16 +53,19 @@ impl Platform for HeadlessPlat {
         Ok(th::SurfaceType::Headless)
     }
 
-    fn add_watch_fd(&mut self, _fd: RawFd) {}
+    fn set_input_config(&mut self, _config: &InputConfig) {}
 
+    /// There is nothing to wait for besides the app's fds and timers,
+    /// so this never sleeps for long
     fn run(
         &mut self,
+        evloop: &mut EventLoop,
         _global_evsys: &mut GlobalEventSystem,
         _output_queues: &mut ll::Component<OutputEventSystem>,
         _platform_queues: &mut ll::Component<PlatformEventSystem>,
-        _timeout: Option<usize>,
+        timeout: Option<usize>,
     ) -> Result<()> {
-        std::thread::sleep(std::time::Duration::from_millis(32));
+        evloop.wait_for_events(Some(timeout.map_or(32, |t| t.min(32))));
 
         Ok(())
     }
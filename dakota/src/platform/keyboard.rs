@@ -0,0 +1,211 @@
+/// Keyboard state shared by the platforms
+///
+/// This wraps xkbcommon, which turns Linux keycodes into keysyms and
+/// text using the user's keymap. Dead keys and compose sequences are
+/// handled here, so key events carry the text that was composed.
+use crate::input::{convert_xkb_keycode_to_dakota, Keycode, KeymapConfig, Mods};
+use crate::utils::log;
+use crate::{anyhow, Result};
+
+extern crate xkbcommon;
+use xkbcommon::xkb;
+use xkbcommon::xkb::compose;
+
+use std::ffi::OsString;
+
+/// The result of pressing or releasing a key
+///
+/// Not every platform reads every field.
+#[allow(dead_code)]
+pub(crate) struct KeyInfo {
+    pub ki_key: Keycode,
+    /// The xkb keysym of this key in the current layout
+    pub ki_keysym: xkb::Keysym,
+    /// The text this key generated. This is empty for key releases and
+    /// for keys in the middle of a compose sequence.
+    pub ki_utf8: String,
+    /// The new modifiers, if this key changed them
+    pub ki_mods: Option<Mods>,
+}
+
+/// xkbcommon keyboard state
+#[allow(dead_code)]
+pub(crate) struct XkbKeyboard {
+    xk_ctx: xkb::Context,
+    xk_keymap: xkb::Keymap,
+    /// xkb state machine
+    xk_state: xkb::State,
+    /// Tracks dead keys and compose sequences. This is None if there is
+    /// no compose table for the user's locale.
+    xk_compose: Option<compose::State>,
+    /// The current set of active modifiers
+    xk_mods: Mods,
+}
+
+/// Get the locale to load the compose table for
+///
+/// This follows the same order as setlocale.
+fn get_locale() -> OsString {
+    for var in ["LC_ALL", "LC_CTYPE", "LANG"].iter() {
+        match std::env::var_os(var) {
+            Some(locale) if !locale.is_empty() => return locale,
+            _ => {}
+        }
+    }
+    "C".into()
+}
+
+impl XkbKeyboard {
+    /// Create a keymap, returning None if the names are invalid
+    fn create_keymap(ctx: &xkb::Context, config: &KeymapConfig) -> Option<xkb::Keymap> {
+        xkb::Keymap::new_from_names(
+            ctx,
+            &config.rules,
+            &config.model,
+            &config.layout,
+            &config.variant,
+            config.options.clone(),
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+    }
+
+    fn create_compose_state(ctx: &xkb::Context) -> Option<compose::State> {
+        let locale = get_locale();
+        match compose::Table::new_from_locale(ctx, &locale, compose::COMPILE_NO_FLAGS) {
+            Ok(table) => Some(compose::State::new(&table, compose::STATE_NO_FLAGS)),
+            Err(_) => {
+                log::debug!("No compose table for locale {:?}", locale);
+                None
+            }
+        }
+    }
+
+    pub fn new(config: &KeymapConfig) -> Result<Self> {
+        // Create all the components for xkb
+        // A description of this can be found in the xkb
+        // section of wayland-book.com
+        let ctx = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = Self::create_keymap(&ctx, config).ok_or(anyhow!(
+            "Could not initialize a xkb keymap for {:?}",
+            config
+        ))?;
+        let compose = Self::create_compose_state(&ctx);
+
+        Ok(Self {
+            xk_state: xkb::State::new(&keymap),
+            xk_keymap: keymap,
+            xk_compose: compose,
+            xk_ctx: ctx,
+            xk_mods: Mods::NONE,
+        })
+    }
+
+    /// Switch to a different keymap
+    ///
+    /// If the new keymap is invalid the current one is kept.
+    pub fn set_keymap(&mut self, config: &KeymapConfig) -> Result<()> {
+        let keymap = Self::create_keymap(&self.xk_ctx, config)
+            .ok_or(anyhow!("Invalid xkb keymap {:?}", config))?;
+
+        self.xk_state = xkb::State::new(&keymap);
+        self.xk_keymap = keymap;
+        if let Some(compose) = self.xk_compose.as_mut() {
+            compose.reset();
+        }
+        Ok(())
+    }
+
+    /// Get our modifiers from the xkb state
+    fn get_mods(&self) -> Mods {
+        let mod_options = [
+            (xkb::MOD_NAME_ALT, Mods::LALT),
+            (xkb::MOD_NAME_NUM, Mods::NUM),
+            (xkb::MOD_NAME_CAPS, Mods::CAPS),
+            (xkb::MOD_NAME_CTRL, Mods::LCTRL),
+            (xkb::MOD_NAME_LOGO, Mods::LMETA),
+            (xkb::MOD_NAME_SHIFT, Mods::LSHIFT),
+        ];
+
+        let mut mods = Mods::NONE;
+        for opt in mod_options.iter() {
+            if self
+                .xk_state
+                .mod_name_is_active(&opt.0, xkb::STATE_MODS_EFFECTIVE)
+            {
+                mods |= opt.1;
+            }
+        }
+        mods
+    }
+
+    /// Get the text for a key press, running it through the compose
+    /// state first
+    fn get_utf8(&mut self, keycode: u32, keysym: xkb::Keysym) -> String {
+        if let Some(compose) = self.xk_compose.as_mut() {
+            if let compose::FeedResult::Accepted = compose.feed(keysym) {
+                match compose.status() {
+                    // Waiting on the rest of the sequence
+                    compose::Status::Composing => return String::with_capacity(0),
+                    compose::Status::Composed => {
+                        let utf = compose.utf8().unwrap_or_default();
+                        compose.reset();
+                        return utf;
+                    }
+                    compose::Status::Cancelled => {
+                        compose.reset();
+                        return String::with_capacity(0);
+                    }
+                    compose::Status::Nothing => {}
+                }
+            }
+        }
+
+        self.xk_state.key_get_utf8(keycode)
+    }
+
+    /// Should this key repeat when held down
+    ///
+    /// Modifiers and a few other keys don't repeat.
+    #[cfg(any(feature = "direct2display", feature = "drm"))]
+    pub fn key_repeats(&self, key: u32) -> bool {
+        self.xk_keymap.key_repeats(key + 8)
+    }
+
+    /// Update the keyboard state with a key event
+    ///
+    /// `key` is a Linux `KEY_*` value.
+    pub fn handle_key(&mut self, key: u32, pressed: bool) -> KeyInfo {
+        // add 8 to account for differences between evdev and x11
+        let keycode = key + 8;
+        let changed = self.xk_state.update_key(
+            keycode,
+            match pressed {
+                true => xkb::KeyDirection::Down,
+                false => xkb::KeyDirection::Up,
+            },
+        );
+
+        let keysym = self.xk_state.key_get_one_sym(keycode);
+        let utf = match pressed {
+            true => self.get_utf8(keycode, keysym),
+            // Key up events do not generate utf characters
+            false => String::with_capacity(0),
+        };
+
+        let mut mods = None;
+        if changed != 0 {
+            let new_mods = self.get_mods();
+            if new_mods != self.xk_mods {
+                self.xk_mods = new_mods;
+                mods = Some(new_mods);
+            }
+        }
+
+        KeyInfo {
+            ki_key: convert_xkb_keycode_to_dakota(keysym),
+            ki_keysym: keysym,
+            ki_utf8: utf,
+            ki_mods: mods,
+        }
+    }
+}
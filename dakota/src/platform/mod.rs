@@ -4,13 +4,22 @@
 use crate::dom;
 use crate::{
     event::{GlobalEventSystem, OutputEventSystem, PlatformEventSystem},
-    OutputId, Result,
+    EventLoop, InputConfig, KeyRepeatConfig, KeymapConfig, OutputId, Result,
 };
-use std::os::fd::RawFd;
+
+#[cfg(any(
+    feature = "direct2display",
+    feature = "drm",
+    feature = "sdl",
+    feature = "winit"
+))]
+mod keyboard;
 
 #[cfg(any(feature = "direct2display", feature = "drm"))]
 mod display;
 #[cfg(any(feature = "direct2display", feature = "drm"))]
+mod repeat;
+#[cfg(any(feature = "direct2display", feature = "drm"))]
 pub use display::LibinputPlat;
 
 #[cfg(feature = "sdl")]
@@ -18,6 +27,16 @@ mod sdl2;
 #[cfg(feature = "sdl")]
 pub use self::sdl2::SDL2Plat;
 
+#[cfg(feature = "winit")]
+mod winit;
+#[cfg(feature = "winit")]
+pub use self::winit::WinitPlat;
+
+#[cfg(feature = "vnc")]
+mod vnc;
+#[cfg(feature = "vnc")]
+pub use self::vnc::VncPlat;
+
 mod headless;
 pub use self::headless::HeadlessPlat;
 
@@ -63,20 +82,43 @@ pub trait Platform {
     /// This may fail if the platform only supports one virtual surface
     fn create_virtual_output(&mut self, output_ecs: &ll::Instance) -> Result<OutputId>;
 
-    /// Add a watch descriptor to our list. This will cause the platform's
-    /// event loop to wake when this fd is readable and queue the UserFd
-    /// event.
-    fn add_watch_fd(&mut self, fd: RawFd);
+    /// Configure the input devices of this platform
+    ///
+    /// The settings should also be applied to any devices added later.
+    /// Platforms which do not own their input devices ignore this.
+    fn set_input_config(&mut self, config: &InputConfig);
+
+    /// Change the keyboard layout
+    ///
+    /// This changes the keysyms and text of our key events. Platforms
+    /// which are given keysyms by the window system ignore this.
+    fn set_keymap(&mut self, _config: &KeymapConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// Change how held down keys repeat
+    ///
+    /// Platforms whose window system repeats keys for us ignore this.
+    fn set_key_repeat(&mut self, _evloop: &mut EventLoop, _config: &KeyRepeatConfig) {}
+
+    /// Check if a display was plugged in or unplugged
+    ///
+    /// Returns true once for each hotplug event seen during `run`, after
+    /// which the displays should be enumerated again. Platforms which
+    /// can't detect hotplug always return false.
+    fn take_display_hotplug(&mut self) -> bool {
+        false
+    }
 
     /// Run the event loop for this platform
     ///
     /// This will dispatch winsys handling and will wait for user
-    /// input.
-    ///
-    /// Returns true if we should redraw the app due to an out of
-    /// date swapchain.
+    /// input. Platforms should sleep in `evloop`, which also wakes up
+    /// for the app's fds and timers. Events from the loop which don't
+    /// belong to the platform are left for Dakota to handle.
     fn run(
         &mut self,
+        evloop: &mut EventLoop,
         global_evsys: &mut GlobalEventSystem,
         output_queues: &mut ll::Component<OutputEventSystem>,
         platform_queues: &mut ll::Component<PlatformEventSystem>,
@@ -98,4 +140,18 @@ pub trait OutputPlatform {
 
     /// Set the dimensions of this window
     fn set_geometry(&mut self, win: &dom::Window, dims: (u32, u32)) -> Result<()>;
+
+    /// Does this output show frames itself?
+    ///
+    /// Outputs which are not shown by Thundr, such as ones streamed over
+    /// the network, return true here. They are given a copy of each frame
+    /// through `present_frame`.
+    fn wants_frames(&self) -> bool {
+        false
+    }
+
+    /// Show the frame that was just drawn
+    ///
+    /// `frame` is tightly packed 8-bit BGRA of the given size.
+    fn present_frame(&mut self, _frame: &[u8], _size: (u32, u32)) {}
 }
@@ -0,0 +1,127 @@
+/// Key repeat for platforms which only get raw key events
+///
+/// Window systems repeat keys for us, but libinput only tells us when
+/// keys go up and down. This repeats the held key off of event loop
+/// timers so that nothing has to busy poll.
+use super::keyboard::KeyInfo;
+use crate::input::{KeyRepeatConfig, Keycode};
+use crate::{EventLoop, TimerId};
+
+use std::time::{Duration, Instant};
+
+/// The key currently being repeated
+struct HeldKey {
+    /// The Linux `KEY_*` value
+    hk_key: u32,
+    hk_keycode: Keycode,
+    hk_utf8: String,
+    /// The time of the original key press, in microseconds
+    hk_time: u64,
+    hk_pressed_at: Instant,
+    hk_timer: TimerId,
+    /// False while we are still waiting for the repeat delay
+    hk_repeating: bool,
+}
+
+/// A repeated key press
+pub(crate) struct RepeatedKey {
+    pub rk_key: u32,
+    pub rk_keycode: Keycode,
+    pub rk_utf8: String,
+    pub rk_time: u64,
+}
+
+/// Repeats held down keys
+///
+/// This runs off of event loop timers: one for the repeat delay, which
+/// is then replaced by a repeating timer for the repeat rate. Only the
+/// most recently pressed key repeats, like everywhere else.
+pub(crate) struct KeyRepeat {
+    kr_config: KeyRepeatConfig,
+    kr_held: Option<HeldKey>,
+}
+
+impl KeyRepeat {
+    pub fn new() -> Self {
+        Self {
+            kr_config: KeyRepeatConfig::default(),
+            kr_held: None,
+        }
+    }
+
+    pub fn set_config(&mut self, evloop: &mut EventLoop, config: &KeyRepeatConfig) {
+        self.stop(evloop);
+        self.kr_config = *config;
+    }
+
+    /// Stop repeating the held key, if there is one
+    pub fn stop(&mut self, evloop: &mut EventLoop) {
+        if let Some(held) = self.kr_held.take() {
+            evloop.remove_timer(held.hk_timer);
+        }
+    }
+
+    /// Update the held key with a key event
+    ///
+    /// Pressing a key that repeats starts repeating it in place of the
+    /// last key, and releasing the held key stops it.
+    pub fn handle_key(
+        &mut self,
+        evloop: &mut EventLoop,
+        key: u32,
+        pressed: bool,
+        repeats: bool,
+        info: &KeyInfo,
+        time: u64,
+    ) {
+        if !pressed {
+            if self.kr_held.as_ref().map(|h| h.hk_key) == Some(key) {
+                self.stop(evloop);
+            }
+            return;
+        }
+
+        if !repeats {
+            return;
+        }
+        self.stop(evloop);
+        if self.kr_config.rate == 0 {
+            return;
+        }
+
+        self.kr_held = Some(HeldKey {
+            hk_key: key,
+            hk_keycode: info.ki_key,
+            hk_utf8: info.ki_utf8.clone(),
+            hk_time: time,
+            hk_pressed_at: Instant::now(),
+            hk_timer: evloop.add_timer(Duration::from_millis(self.kr_config.delay as u64)),
+            hk_repeating: false,
+        });
+    }
+
+    /// Check if the held key should be repeated
+    ///
+    /// This should be called after waiting on `evloop`. Our timer events
+    /// are removed from the loop so they aren't reported to the app.
+    pub fn check_timer(&mut self, evloop: &mut EventLoop) -> Option<RepeatedKey> {
+        let held = self.kr_held.as_mut()?;
+        if !evloop.take_timer(held.hk_timer) {
+            return None;
+        }
+
+        // The delay has passed, now start repeating at our rate
+        if !held.hk_repeating {
+            let interval = Duration::from_micros(1_000_000 / self.kr_config.rate as u64);
+            held.hk_timer = evloop.add_repeating_timer(interval);
+            held.hk_repeating = true;
+        }
+
+        Some(RepeatedKey {
+            rk_key: held.hk_key,
+            rk_keycode: held.hk_keycode,
+            rk_utf8: held.hk_utf8.clone(),
+            rk_time: held.hk_time + held.hk_pressed_at.elapsed().as_micros() as u64,
+        })
+    }
+}
@@ -1,12 +1,13 @@
 /// SDL2 backend platform
 ///
 /// This handles all window systems using SDL2
+use super::keyboard::XkbKeyboard;
 use super::{OutputPlatform, Platform};
 use crate::dom;
-use crate::utils::{fdwatch::FdWatch, log};
+use crate::utils::log;
 use crate::{
     event::{AxisSource, GlobalEventSystem, OutputEventSystem, PlatformEventSystem, RawKeycode},
-    OutputId, Result,
+    EventLoop, KeymapConfig, OutputId, Result,
 };
 
 extern crate sdl2;
@@ -14,13 +15,17 @@ extern crate sdl2_sys;
 use crate::input::*;
 use sdl2::event::{Event, WindowEvent};
 
-extern crate xkbcommon;
-use std::os::fd::RawFd;
 use std::sync::{Arc, RwLock};
-use xkbcommon::xkb;
 
 const SCROLL_SENSITIVITY: f64 = 32.0;
 
+/// Convert an SDL event timestamp to microseconds
+///
+/// SDL only reports milliseconds since it was initialized.
+fn sdl_time_to_usec(timestamp: u32) -> u64 {
+    timestamp as u64 * 1000
+}
+
 /// Common SDL2 dispatch backend
 #[allow(dead_code)]
 pub struct SDL2Plat {
@@ -34,20 +39,42 @@ pub struct SDL2Plat {
     sdl_mouse_pos: (i32, i32),
     /// The current set of active modifiers
     sdl_mods: Mods,
-    /// libxkbcommon context
-    sdl_xkb_ctx: xkb::Context,
-    sdl_xkb_keymap: xkb::Keymap,
-    /// this is referenced by Seat, which needs to map and
-    /// share it with the clients
-    sdl_xkb_keymap_name: String,
-    /// xkb state machine
-    sdl_xkb_state: xkb::State,
-    /// fds the user wants us to wake up on
-    sdl_user_fds: Option<FdWatch>,
+    /// SDL doesn't compose text for us, so keys are also fed
+    /// through xkbcommon
+    sdl_keyboard: XkbKeyboard,
     /// This maps a SDL window_id to the OutputIds of our Output
     /// and VirtualOutput that events should be delivered one.
     /// The format is `(SDL window_id, Output, VirtualOutput)`.
     sdl_window_id_map: Arc<RwLock<Vec<(u32, OutputId, OutputId)>>>,
+    /// The display each window was last seen on, and its DPI. The
+    /// format is `(SDL window_id, display index, dpi)`.
+    sdl_window_displays: Vec<(u32, i32, (i32, i32))>,
+}
+
+/// Get the DPI of the display `window` is on
+///
+/// This matches `th::Display::get_dpi` for this window, which scales the
+/// display's DPI by the window's content scale.
+fn get_window_dpi(
+    video: &sdl2::VideoSubsystem,
+    window: *mut sdl2_sys::SDL_Window,
+) -> Option<(i32, i32)> {
+    let index = unsafe { sdl2_sys::SDL_GetWindowDisplayIndex(window) };
+    let (_, hdpi, vdpi) = video.display_dpi(index).ok()?;
+
+    let (mut win_w, mut win_h, mut vk_w, mut vk_h) = (0, 0, 0, 0);
+    unsafe {
+        sdl2_sys::SDL_GetWindowSize(window, &mut win_w, &mut win_h);
+        sdl2_sys::SDL_Vulkan_GetDrawableSize(window, &mut vk_w, &mut vk_h);
+    }
+    if vk_w <= 0 || vk_h <= 0 {
+        return Some((hdpi as i32, vdpi as i32));
+    }
+
+    Some((
+        (hdpi * win_w as f32 / vk_w as f32) as i32,
+        (vdpi * win_h as f32 / vk_h as f32) as i32,
+    ))
 }
 
 impl SDL2Plat {
@@ -55,37 +82,72 @@ impl SDL2Plat {
         // SDL goodies
         let sdl_context = sdl2::init().unwrap();
         let event_pump = sdl_context.event_pump().unwrap();
-        // Create all the components for xkb
-        // A description of this can be found in the xkb
-        // section of wayland-book.com
-        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
-        let keymap = xkb::Keymap::new_from_names(
-            &context,
-            &"",
-            &"",
-            &"",
-            &"", // These should be env vars
-            None,
-            xkb::KEYMAP_COMPILE_NO_FLAGS,
-        )
-        .expect("Could not initialize a xkb keymap");
-        let km_name = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
-
-        let state = xkb::State::new(&keymap);
+        let keyboard = XkbKeyboard::new(&KeymapConfig::default())?;
+
         Ok(Self {
             sdl: sdl_context,
             sdl_event_pump: event_pump,
             sdl_mouse_pos: (0, 0),
             sdl_mods: Mods::NONE,
-            sdl_xkb_ctx: context,
-            sdl_xkb_keymap: keymap,
-            sdl_xkb_keymap_name: km_name,
-            sdl_xkb_state: state,
-            sdl_user_fds: None,
+            sdl_keyboard: keyboard,
             sdl_window_id_map: Arc::new(RwLock::new(Vec::with_capacity(1))),
+            sdl_window_displays: Vec::with_capacity(1),
         })
     }
 
+    /// Check if a window moved to a different display
+    ///
+    /// SDL 2.0.18 added an event for this, but we can't depend on having
+    /// it. Instead we check which display the window is on whenever it is
+    /// moved or resized, and send DisplayChanged if it is a new one. If
+    /// the new display has a different DPI DpiChanged is sent as well.
+    fn check_display_change(&mut self, window_id: u32, platform_evsys: &mut PlatformEventSystem) {
+        let window = unsafe { sdl2_sys::SDL_GetWindowFromID(window_id) };
+        if window.is_null() {
+            // The window was destroyed
+            self.sdl_window_displays.retain(|e| e.0 != window_id);
+            return;
+        }
+        let index = unsafe { sdl2_sys::SDL_GetWindowDisplayIndex(window) };
+
+        let entry = match self
+            .sdl_window_displays
+            .iter_mut()
+            .find(|(id, _, _)| *id == window_id)
+        {
+            Some(entry) => entry,
+            None => return,
+        };
+        if index < 0 || entry.1 == index {
+            return;
+        }
+        entry.1 = index;
+
+        let video = match self.sdl.video() {
+            Ok(video) => video,
+            Err(_) => return,
+        };
+        let dpi = get_window_dpi(&video, window).unwrap_or((0, 0));
+
+        let refresh = match video.current_display_mode(index) {
+            Ok(mode) => mode.refresh_rate.max(0) as u32 * 1000,
+            Err(_) => 0,
+        };
+        log::debug!(
+            "SDL window {} moved to display {} ({}mHz, DPI {:?})",
+            window_id,
+            index,
+            refresh,
+            dpi
+        );
+        platform_evsys.add_event_display_changed(index, refresh);
+
+        if dpi != entry.2 {
+            entry.2 = dpi;
+            platform_evsys.add_event_dpi_changed(dpi);
+        }
+    }
+
     /// SDL hands us events that are identified by a window_id to tell us
     /// which SDL toplevel surface the event was delivered on. We need to
     /// turn this into our OutputId for the Output or VirtualOutput that
@@ -154,20 +216,22 @@ impl SDL2Plat {
                 // enum that we control. See input.rs for how this is done. We
                 // also wrap the Keyboard Modifiercodes in a similar way
                 Event::KeyDown {
+                    timestamp,
                     keycode,
                     keymod,
                     scancode,
                     ..
                 } => {
+                    let time = sdl_time_to_usec(timestamp);
                     let key = convert_sdl_keycode_to_dakota(keycode.unwrap());
                     let mods = convert_sdl_mods_to_dakota(keymod);
-                    self.update_xkb_from_scancode(scancode.unwrap(), xkb::KeyDirection::Down);
-                    let (raw, utf) = self.get_utf8_from_key(scancode.unwrap());
+                    let (raw, utf) = self.handle_xkb_key(scancode.unwrap(), true);
 
                     platform_evsys.as_mut().unwrap().add_event_key_down(
                         key,
                         utf,
                         RawKeycode::Linux(raw),
+                        time,
                     );
 
                     if mods != self.sdl_mods {
@@ -175,24 +239,26 @@ impl SDL2Plat {
                         platform_evsys
                             .as_mut()
                             .unwrap()
-                            .add_event_keyboard_modifiers(mods);
+                            .add_event_keyboard_modifiers(mods, time);
                     }
                 }
                 Event::KeyUp {
+                    timestamp,
                     keycode,
                     keymod,
                     scancode,
                     ..
                 } => {
+                    let time = sdl_time_to_usec(timestamp);
                     let key = convert_sdl_keycode_to_dakota(keycode.unwrap());
                     let mods = convert_sdl_mods_to_dakota(keymod);
-                    self.update_xkb_from_scancode(scancode.unwrap(), xkb::KeyDirection::Up);
-                    let (raw, _) = self.get_utf8_from_key(scancode.unwrap());
+                    let (raw, _) = self.handle_xkb_key(scancode.unwrap(), false);
 
                     platform_evsys.as_mut().unwrap().add_event_key_up(
                         key,
                         String::with_capacity(0), // no utf8 characters are generated for lifting a key
                         RawKeycode::Linux(raw),
+                        time,
                     );
 
                     if mods != self.sdl_mods {
@@ -200,38 +266,50 @@ impl SDL2Plat {
                         platform_evsys
                             .as_mut()
                             .unwrap()
-                            .add_event_keyboard_modifiers(mods);
+                            .add_event_keyboard_modifiers(mods, time);
                     }
                 }
                 // handle pointer inputs. This just looks like the above keyboard
-                Event::MouseButtonDown { mouse_btn, .. } => {
+                Event::MouseButtonDown {
+                    timestamp,
+                    mouse_btn,
+                    ..
+                } => {
                     let button = convert_sdl_mouse_to_dakota(mouse_btn);
                     platform_evsys
                         .as_mut()
                         .unwrap()
-                        .add_event_mouse_button_down(button);
+                        .add_event_mouse_button_down(button, sdl_time_to_usec(timestamp));
                 }
-                Event::MouseButtonUp { mouse_btn, .. } => {
+                Event::MouseButtonUp {
+                    timestamp,
+                    mouse_btn,
+                    ..
+                } => {
                     let button = convert_sdl_mouse_to_dakota(mouse_btn);
                     platform_evsys
                         .as_mut()
                         .unwrap()
-                        .add_event_mouse_button_up(button);
+                        .add_event_mouse_button_up(button, sdl_time_to_usec(timestamp));
                 }
-                Event::MouseWheel { x, y, .. } => {
-                    platform_evsys.as_mut().unwrap().add_event_scroll(
-                        // reverse the scroll direction
-                        Some((x as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
-                        Some((y as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
-                        (0.0, 0.0), // v120 value unspecified
-                        AxisSource::Wheel,
-                    )
-                }
-                Event::MouseMotion { x, y, .. } => {
-                    platform_evsys
-                        .as_mut()
-                        .unwrap()
-                        .add_event_mouse_move(x - self.sdl_mouse_pos.0, y - self.sdl_mouse_pos.1);
+                Event::MouseWheel {
+                    timestamp, x, y, ..
+                } => platform_evsys.as_mut().unwrap().add_event_scroll(
+                    // reverse the scroll direction
+                    Some((x as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
+                    Some((y as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
+                    (0.0, 0.0), // v120 value unspecified
+                    AxisSource::Wheel,
+                    sdl_time_to_usec(timestamp),
+                ),
+                Event::MouseMotion {
+                    timestamp, x, y, ..
+                } => {
+                    platform_evsys.as_mut().unwrap().add_event_mouse_move(
+                        x - self.sdl_mouse_pos.0,
+                        y - self.sdl_mouse_pos.1,
+                        sdl_time_to_usec(timestamp),
+                    );
 
                     // Update our mouse position
                     self.sdl_mouse_pos.0 = x;
@@ -243,10 +321,19 @@ impl SDL2Plat {
                 // going to check for OUT_OF_DATE, but it's possible that the toolkit
                 // (SDL) might need refreshing while libvulkan doesn't yet know about
                 // it.
-                Event::Window { win_event, .. } => match win_event {
+                Event::Window {
+                    win_event,
+                    window_id,
+                    ..
+                } => match win_event {
                     WindowEvent::Close => output_evsys.as_mut().unwrap().add_event_destroyed(),
                     WindowEvent::Resized { .. } | WindowEvent::SizeChanged { .. } => {
                         output_evsys.as_mut().unwrap().add_event_resized();
+                        self.check_display_change(window_id, platform_evsys.as_mut().unwrap());
+                    }
+                    // Moving between displays may change our DPI
+                    WindowEvent::Moved { .. } => {
+                        self.check_display_change(window_id, platform_evsys.as_mut().unwrap());
                     }
                     WindowEvent::Exposed { .. } => {
                         output_evsys.as_mut().unwrap().add_event_redraw();
@@ -260,37 +347,20 @@ impl SDL2Plat {
         Ok(())
     }
 
-    /// Update this platform's internal xkbcommon state representing that
-    /// a keystroke has taken place.
-    fn update_xkb_from_scancode(
-        &mut self,
-        code: sdl2::keyboard::Scancode,
-        direction: xkb::KeyDirection,
-    ) {
-        let key = convert_sdl_scancode_to_linux(code);
-
-        // let xkb keep track of the keyboard state
-        self.sdl_xkb_state.update_key(
-            // add 8 to account for differences between evdev and x11
-            key as u32 + 8,
-            direction,
-        );
-    }
-
     /// Convert a sdl2 keycode into a utf8 character
     ///
-    /// This handles upper and lowercase which SDL doesn't do for us by using the
-    /// provided modifiers. It also handles generating utf8 characters using
-    /// xkbcommon.
+    /// This handles upper and lowercase which SDL doesn't do for us by
+    /// feeding the key through xkbcommon, which also handles dead keys
+    /// and compose sequences.
     ///
-    /// Returns an empty array of chars if no utf8 characters were generated by this
+    /// Returns an empty string if no utf8 characters were generated by this
     /// keystroke. Also returns the raw Linux keycode, this is used by system users
     /// such as Category5.
-    fn get_utf8_from_key(&mut self, code: sdl2::keyboard::Scancode) -> (u32, String) {
+    fn handle_xkb_key(&mut self, code: sdl2::keyboard::Scancode, pressed: bool) -> (u32, String) {
         let raw = convert_sdl_scancode_to_linux(code);
+        let info = self.sdl_keyboard.handle_key(raw, pressed);
 
-        // add 8 to account for differences between evdev and x11
-        return (raw, self.sdl_xkb_state.key_get_utf8(raw + 8));
+        (raw, info.ki_utf8)
     }
 }
 
@@ -318,6 +388,12 @@ impl Platform for SDL2Plat {
             .write()
             .unwrap()
             .push((window.id(), id, virtual_output_id));
+        // Remember the display it started on, so we can tell when it moves
+        self.sdl_window_displays.push((
+            window.id(),
+            window.display_index().unwrap_or(-1),
+            get_window_dpi(&video_subsystem, window.raw()).unwrap_or((0, 0)),
+        ));
 
         Ok(Box::new(SDL2Window {
             sdl_video_sys: video_subsystem,
@@ -333,71 +409,52 @@ impl Platform for SDL2Plat {
         Ok(output_ecs.add_entity())
     }
 
-    /// Add a watch descriptor to our list. This will cause the platform's
-    /// event loop to wake when this fd is readable and queue the UserFd
-    /// event.
-    fn add_watch_fd(&mut self, fd: RawFd) {
-        if self.sdl_user_fds.is_none() {
-            self.sdl_user_fds = Some(FdWatch::new());
-        }
+    /// Input devices belong to the window system, which configures them.
+    fn set_input_config(&mut self, _config: &InputConfig) {}
 
-        let watch = self.sdl_user_fds.as_mut().unwrap();
-        watch.add_fd(fd);
-        watch.register_events();
+    fn set_keymap(&mut self, config: &KeymapConfig) -> Result<()> {
+        self.sdl_keyboard.set_keymap(config)
     }
 
     /// Run the event loop for this platform
     ///
-    /// Block and handle all available events from SDL2. If timeout
-    /// is specified it will be passed to SDL's wait_event_timeout function.
+    /// Block and handle all available events from SDL2, or until the
+    /// event loop wakes up. If timeout is specified we will return once
+    /// it has passed.
     fn run(
         &mut self,
+        evloop: &mut EventLoop,
         global_evsys: &mut GlobalEventSystem,
         output_evsys: &mut ll::Component<OutputEventSystem>,
         platform_evsys: &mut ll::Component<PlatformEventSystem>,
         timeout: Option<usize>,
     ) -> Result<()> {
-        // There are two modes we need to consider for polling for SDL events, since
-        // it doesn't follow a unix style: 1) if we are waiting for just SDL, 2) if we
-        // are waiting for SDL and some file descriptors
-        //
-        // In the first case we should use SDL's SDL_WaitEvent, since it will save
-        // power by not busy looping. If we need to wait for some fds then we have no
-        // choice but to busy loop ourselves since SDL doesn't have a good way for us
-        // to deal with this. If this becomes a problem hopefuly SDL3 has a good way to
-        // deal with it..
-        if let Some(fds) = self.sdl_user_fds.as_mut() {
-            loop {
-                // Wait for the first readable fd
-                if fds.wait_for_events(Some(1)) {
-                    global_evsys.add_event_user_fd();
-                    break;
-                }
+        // SDL doesn't follow a unix style, so there is no way for us to wait
+        // for both SDL events and our event loop. We have no choice but to
+        // busy loop ourselves, checking one and then the other. If this
+        // becomes a problem hopefuly SDL3 has a good way to deal with it..
+        let start = std::time::Instant::now();
+        loop {
+            // Wait for the first readable fd, timer or signal
+            if evloop.wait_for_events(Some(1)) {
+                break;
+            }
 
-                // Or wait for the first SDL event
-                let ev = self.sdl_event_pump.poll_event();
-                if let Some(ev) = ev {
-                    self.handle_event(global_evsys, output_evsys, platform_evsys, Some(ev))?;
-                    break;
-                }
+            // Or wait for the first SDL event
+            let ev = self.sdl_event_pump.poll_event();
+            if let Some(ev) = ev {
+                self.handle_event(global_evsys, output_evsys, platform_evsys, Some(ev))?;
+                break;
+            }
 
-                // Don't waste all the CPU
-                std::thread::sleep(std::time::Duration::from_millis(8));
+            if let Some(timeout) = timeout {
+                if start.elapsed().as_millis() >= timeout as u128 {
+                    return Ok(());
+                }
             }
-        } else {
-            // First block for the next event
-            let ev = match timeout {
-                // If we are waiting a certain amount of time, tell SDL. If
-                // it returns an event, great, handle it.
-                // If not, then just return without handling.
-                Some(timeout) => match self.sdl_event_pump.wait_event_timeout(timeout as u32) {
-                    Some(event) => event,
-                    None => return Ok(()),
-                },
-                // No timeout was given, so we wait indefinitely
-                None => self.sdl_event_pump.wait_event(),
-            };
-            self.handle_event(global_evsys, output_evsys, platform_evsys, Some(ev))?;
+
+            // Don't waste all the CPU
+            std::thread::sleep(std::time::Duration::from_millis(8));
         }
 
         // Now drain the available events before returning
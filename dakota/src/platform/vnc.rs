@@ -0,0 +1,770 @@
+/// VNC remote desktop platform
+///
+/// This serves our output over the RFB protocol, so that it can be used
+/// from another machine with any VNC viewer. Frames are drawn with a
+/// headless Thundr display and read back after each redraw, then sent to
+/// viewers with the Raw encoding. Input from viewers is turned into
+/// Dakota events just like input from local devices.
+///
+/// No authentication or encryption is done, so this should only listen
+/// on trusted networks or be tunneled over SSH.
+///
+/// https://github.com/rfbproto/rfbproto/blob/master/rfbproto.rst
+///
+/// Austin Shafer - 2024
+use super::{OutputPlatform, Platform};
+use crate::dom;
+use crate::input::*;
+use crate::utils::log;
+use crate::{
+    event::{AxisSource, GlobalEventSystem, OutputEventSystem, PlatformEventSystem, RawKeycode},
+    EventLoop, InputConfig, OutputId, Result,
+};
+use utils::{anyhow, timing::get_monotonic_micros, Context};
+
+extern crate xkbcommon;
+use xkbcommon::xkb;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+/// The address we listen on if none was specified
+const DEFAULT_ADDRESS: &str = "127.0.0.1:5900";
+/// The name of the desktop shown by viewers
+const DESKTOP_NAME: &str = "Dakota";
+/// The scroll distance of one mouse wheel click
+const SCROLL_STEP: i32 = 15;
+/// The DesktopSize pseudo-encoding, which lets us resize viewers
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+
+/// The last frame drawn to our output
+struct VncFrame {
+    /// Tightly packed 8-bit BGRA pixels
+    vf_data: Vec<u8>,
+    vf_size: (u32, u32),
+    /// Bumped every time a new frame is presented
+    vf_serial: u64,
+}
+
+/// The pixel layout a viewer wants frames in
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct PixelFormat {
+    pf_bpp: u8,
+    pf_big_endian: bool,
+    pf_true_color: bool,
+    /// Maximum red, green and blue values
+    pf_max: [u16; 3],
+    /// Shift of the red, green and blue values within a pixel
+    pf_shift: [u8; 3],
+}
+
+impl PixelFormat {
+    /// Our native format
+    ///
+    /// This is 32-bit little endian BGRX, which is how Thundr lays out
+    /// its frames. Viewers which use this don't need any conversion.
+    fn native() -> Self {
+        Self {
+            pf_bpp: 32,
+            pf_big_endian: false,
+            pf_true_color: true,
+            pf_max: [255, 255, 255],
+            pf_shift: [16, 8, 0],
+        }
+    }
+
+    fn from_bytes(b: &[u8]) -> Self {
+        Self {
+            pf_bpp: b[0],
+            pf_big_endian: b[2] != 0,
+            pf_true_color: b[3] != 0,
+            pf_max: [
+                u16::from_be_bytes([b[4], b[5]]),
+                u16::from_be_bytes([b[6], b[7]]),
+                u16::from_be_bytes([b[8], b[9]]),
+            ],
+            pf_shift: [b[10], b[11], b[12]],
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut ret = [0; 16];
+        ret[0] = self.pf_bpp;
+        // depth
+        ret[1] = 24;
+        ret[2] = self.pf_big_endian as u8;
+        ret[3] = self.pf_true_color as u8;
+        for i in 0..3 {
+            ret[4 + i * 2..6 + i * 2].copy_from_slice(&self.pf_max[i].to_be_bytes());
+            ret[10 + i] = self.pf_shift[i];
+        }
+        ret
+    }
+
+    /// Can we convert our frames to this format?
+    ///
+    /// Color maps are not supported.
+    fn is_supported(&self) -> bool {
+        self.pf_true_color && [8, 16, 32].contains(&self.pf_bpp)
+    }
+
+    /// Convert a BGRA frame into this format
+    fn encode(&self, frame: &[u8], out: &mut Vec<u8>) {
+        if *self == Self::native() {
+            out.extend_from_slice(frame);
+            return;
+        }
+
+        let bytes = (self.pf_bpp / 8) as usize;
+        for px in frame.chunks_exact(4) {
+            let rgb = [px[2], px[1], px[0]];
+            let mut val: u32 = 0;
+            for i in 0..3 {
+                val |= (rgb[i] as u32 * self.pf_max[i] as u32 / 255) << self.pf_shift[i];
+            }
+
+            match self.pf_big_endian {
+                true => out.extend_from_slice(&val.to_be_bytes()[4 - bytes..]),
+                false => out.extend_from_slice(&val.to_le_bytes()[..bytes]),
+            }
+        }
+    }
+}
+
+/// How far a viewer is through the RFB handshake
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ClientState {
+    /// Waiting for the viewer's protocol version
+    Version,
+    /// Waiting for the viewer to pick a security type
+    Security,
+    /// Waiting for the viewer to say if it shares the desktop
+    ClientInit,
+    /// The handshake is done
+    Running,
+}
+
+/// A connected VNC viewer
+struct VncClient {
+    vc_stream: TcpStream,
+    vc_state: ClientState,
+    /// The minor RFB version, which changes the handshake
+    vc_minor_version: u32,
+    /// Data received but not yet handled
+    vc_buf: Vec<u8>,
+    vc_format: PixelFormat,
+    /// Does this viewer support being resized?
+    vc_desktop_size: bool,
+    /// Has the viewer asked for a frame?
+    vc_update_requested: bool,
+    /// Does the viewer need the frame even if it has seen it before?
+    vc_full_update: bool,
+    /// The serial of the last frame sent
+    vc_sent_serial: u64,
+    /// The size of the desktop as the viewer knows it
+    vc_size: (u32, u32),
+}
+
+/// Input state shared by all viewers
+struct VncInput {
+    /// libxkbcommon context
+    _vi_xkb_ctx: xkb::Context,
+    vi_xkb_keymap: xkb::Keymap,
+    /// xkb state machine
+    vi_xkb_state: xkb::State,
+    vi_mods: Mods,
+    /// Viewers send absolute positions, so we remember the last one to
+    /// find how far the pointer moved
+    vi_pointer: (i32, i32),
+    /// The mask of pointer buttons held down
+    vi_buttons: u8,
+}
+
+impl VncInput {
+    fn new() -> Self {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            &"",
+            &"",
+            &"",
+            &"",
+            None,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("Could not initialize a xkb keymap");
+        let state = xkb::State::new(&keymap);
+
+        Self {
+            _vi_xkb_ctx: context,
+            vi_xkb_keymap: keymap,
+            vi_xkb_state: state,
+            vi_mods: Mods::NONE,
+            vi_pointer: (0, 0),
+            vi_buttons: 0,
+        }
+    }
+
+    /// Find the xkb keycode which produces a keysym
+    ///
+    /// Viewers send keysyms, but apps need to know the physical key
+    /// that was pressed. Shifted keysyms are found on their unshifted
+    /// key, since the viewer sends the shift key separately.
+    fn get_xkb_keycode(&self, keysym: u32) -> Option<u32> {
+        let keymap = &self.vi_xkb_keymap;
+
+        for code in keymap.min_keycode()..=keymap.max_keycode() {
+            for level in 0..keymap.num_levels_for_key(code, 0) {
+                if keymap
+                    .key_get_syms_by_level(code, 0, level)
+                    .contains(&keysym)
+                {
+                    return Some(code);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn handle_key(&mut self, evsys: &mut PlatformEventSystem, keysym: u32, down: bool) {
+        let time = get_monotonic_micros();
+        let code = match self.get_xkb_keycode(keysym) {
+            Some(code) => code,
+            None => {
+                log::debug!("VNC: no key produces keysym {:#x}", keysym);
+                return;
+            }
+        };
+
+        let changed = self.vi_xkb_state.update_key(
+            code,
+            match down {
+                true => xkb::KeyDirection::Down,
+                false => xkb::KeyDirection::Up,
+            },
+        );
+
+        if changed != 0 {
+            let mod_options = [
+                (xkb::MOD_NAME_ALT, Mods::LALT),
+                (xkb::MOD_NAME_NUM, Mods::NUM),
+                (xkb::MOD_NAME_CAPS, Mods::CAPS),
+                (xkb::MOD_NAME_CTRL, Mods::LCTRL),
+                (xkb::MOD_NAME_LOGO, Mods::LMETA),
+                (xkb::MOD_NAME_SHIFT, Mods::LSHIFT),
+            ];
+
+            self.vi_mods = Mods::NONE;
+            for opt in mod_options.iter() {
+                if self
+                    .vi_xkb_state
+                    .mod_name_is_active(&opt.0, xkb::STATE_MODS_EFFECTIVE)
+                {
+                    self.vi_mods |= opt.1;
+                }
+            }
+            evsys.add_event_keyboard_modifiers(self.vi_mods, time);
+        }
+
+        let key = convert_xkb_keycode_to_dakota(keysym);
+        // xkb keycodes are offset by 8 from the kernel's
+        let raw = RawKeycode::Linux(code - 8);
+        match down {
+            true => {
+                let utf = self.vi_xkb_state.key_get_utf8(code);
+                evsys.add_event_key_down(key, utf, raw, time);
+            }
+            false => evsys.add_event_key_up(key, String::with_capacity(0), raw, time),
+        }
+    }
+
+    fn handle_pointer(&mut self, evsys: &mut PlatformEventSystem, mask: u8, x: i32, y: i32) {
+        let time = get_monotonic_micros();
+
+        if (x, y) != self.vi_pointer {
+            evsys.add_event_mouse_move(x - self.vi_pointer.0, y - self.vi_pointer.1, time);
+            self.vi_pointer = (x, y);
+        }
+
+        let pressed = mask & !self.vi_buttons;
+        let released = self.vi_buttons & !mask;
+        self.vi_buttons = mask;
+
+        let buttons = [MouseButton::LEFT, MouseButton::MIDDLE, MouseButton::RIGHT];
+        for (i, button) in buttons.iter().enumerate() {
+            if pressed & (1 << i) != 0 {
+                evsys.add_event_mouse_button_down(*button, time);
+            }
+            if released & (1 << i) != 0 {
+                evsys.add_event_mouse_button_up(*button, time);
+            }
+        }
+
+        // The wheel is reported as buttons 4 through 7, which are pressed
+        // and released for each click
+        let wheel = [
+            (None, Some(SCROLL_STEP), (0.0, -120.0)),
+            (None, Some(-SCROLL_STEP), (0.0, 120.0)),
+            (Some(SCROLL_STEP), None, (-120.0, 0.0)),
+            (Some(-SCROLL_STEP), None, (120.0, 0.0)),
+        ];
+        for (i, (x, y, v120)) in wheel.iter().enumerate() {
+            if pressed & (1 << (i + 3)) != 0 {
+                evsys.add_event_scroll(*x, *y, *v120, AxisSource::Wheel, time);
+            }
+        }
+    }
+}
+
+impl VncClient {
+    fn new(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nonblocking(true)?;
+
+        let mut ret = Self {
+            vc_stream: stream,
+            vc_state: ClientState::Version,
+            vc_minor_version: 8,
+            vc_buf: Vec::new(),
+            vc_format: PixelFormat::native(),
+            vc_desktop_size: false,
+            vc_update_requested: false,
+            vc_full_update: true,
+            vc_sent_serial: 0,
+            vc_size: (0, 0),
+        };
+        ret.send(b"RFB 003.008\n")?;
+        Ok(ret)
+    }
+
+    /// Send data to the viewer
+    ///
+    /// This blocks until all of it has been written.
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.vc_stream.set_nonblocking(false)?;
+        let ret = self.vc_stream.write_all(data);
+        self.vc_stream.set_nonblocking(true)?;
+        ret
+    }
+
+    /// Read everything the viewer has sent
+    ///
+    /// Returns false if the viewer disconnected.
+    fn receive(&mut self) -> std::io::Result<bool> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.vc_stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.vc_buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Handle the next complete message from the viewer
+    ///
+    /// Returns the number of bytes used, or None if the message has not
+    /// been fully received yet.
+    fn handle_message(
+        &mut self,
+        input: &mut VncInput,
+        evsys: Option<&mut PlatformEventSystem>,
+        frame: &VncFrame,
+    ) -> Result<Option<usize>> {
+        let buf = &self.vc_buf;
+
+        match self.vc_state {
+            ClientState::Version => {
+                if buf.len() < 12 {
+                    return Ok(None);
+                }
+                let version = std::str::from_utf8(&buf[..12]).unwrap_or("");
+                if !version.starts_with("RFB 003.") {
+                    return Err(anyhow!("Unknown RFB version {:?}", version));
+                }
+                self.vc_minor_version = version[8..11].parse().unwrap_or(3);
+
+                // Version 3.3 lets the server pick the security type
+                if self.vc_minor_version < 7 {
+                    self.send(&1u32.to_be_bytes())?;
+                    self.vc_state = ClientState::ClientInit;
+                } else {
+                    // We only offer no authentication
+                    self.send(&[1, 1])?;
+                    self.vc_state = ClientState::Security;
+                }
+                Ok(Some(12))
+            }
+            ClientState::Security => {
+                if buf.is_empty() {
+                    return Ok(None);
+                }
+                if buf[0] != 1 {
+                    return Err(anyhow!("Viewer chose unsupported security type {}", buf[0]));
+                }
+                // Only 3.8 reports the result of no authentication
+                if self.vc_minor_version >= 8 {
+                    self.send(&0u32.to_be_bytes())?;
+                }
+                self.vc_state = ClientState::ClientInit;
+                Ok(Some(1))
+            }
+            ClientState::ClientInit => {
+                // We can't tell the viewer the size of the desktop until
+                // something has been drawn
+                if buf.is_empty() || frame.vf_serial == 0 {
+                    return Ok(None);
+                }
+
+                let mut msg = Vec::new();
+                msg.extend_from_slice(&(frame.vf_size.0 as u16).to_be_bytes());
+                msg.extend_from_slice(&(frame.vf_size.1 as u16).to_be_bytes());
+                msg.extend_from_slice(&self.vc_format.to_bytes());
+                msg.extend_from_slice(&(DESKTOP_NAME.len() as u32).to_be_bytes());
+                msg.extend_from_slice(DESKTOP_NAME.as_bytes());
+                self.send(&msg)?;
+
+                self.vc_size = frame.vf_size;
+                self.vc_state = ClientState::Running;
+                Ok(Some(1))
+            }
+            ClientState::Running => self.handle_client_message(input, evsys),
+        }
+    }
+
+    /// Handle a message sent after the handshake
+    fn handle_client_message(
+        &mut self,
+        input: &mut VncInput,
+        evsys: Option<&mut PlatformEventSystem>,
+    ) -> Result<Option<usize>> {
+        let buf = &self.vc_buf;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let read_u16 = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+        let read_u32 = |i: usize| u32::from_be_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+        let len = match buf[0] {
+            // SetPixelFormat
+            0 => 20,
+            // SetEncodings
+            2 if buf.len() >= 4 => 4 + 4 * read_u16(2) as usize,
+            // FramebufferUpdateRequest
+            3 => 10,
+            // KeyEvent
+            4 => 8,
+            // PointerEvent
+            5 => 6,
+            // ClientCutText
+            6 if buf.len() >= 8 => 8 + read_u32(4) as usize,
+            2 | 6 => return Ok(None),
+            ty => return Err(anyhow!("Unknown VNC message type {}", ty)),
+        };
+        if buf.len() < len {
+            return Ok(None);
+        }
+
+        match buf[0] {
+            0 => {
+                let format = PixelFormat::from_bytes(&buf[4..20]);
+                if !format.is_supported() {
+                    return Err(anyhow!("Unsupported pixel format {:?}", format));
+                }
+                self.vc_format = format;
+            }
+            2 => {
+                self.vc_desktop_size = (0..read_u16(2) as usize)
+                    .any(|i| read_u32(4 + i * 4) as i32 == ENCODING_DESKTOP_SIZE);
+            }
+            3 => {
+                self.vc_update_requested = true;
+                // A non-incremental update asks for the whole frame, even
+                // if it has not changed
+                if buf[1] == 0 {
+                    self.vc_full_update = true;
+                }
+            }
+            4 => {
+                if let Some(evsys) = evsys {
+                    input.handle_key(evsys, read_u32(4), buf[1] != 0);
+                }
+            }
+            5 => {
+                if let Some(evsys) = evsys {
+                    input.handle_pointer(evsys, buf[1], read_u16(2) as i32, read_u16(4) as i32);
+                }
+            }
+            // We don't have a clipboard to share
+            _ => {}
+        }
+
+        Ok(Some(len))
+    }
+
+    /// Send the latest frame if the viewer asked for it
+    fn send_update(&mut self, frame: &VncFrame) -> std::io::Result<()> {
+        if self.vc_state != ClientState::Running
+            || !self.vc_update_requested
+            || frame.vf_serial == 0
+            || (frame.vf_serial == self.vc_sent_serial && !self.vc_full_update)
+        {
+            return Ok(());
+        }
+
+        let resized = frame.vf_size != self.vc_size;
+        if resized && !self.vc_desktop_size {
+            log::error!("VNC: viewer does not support resizing the desktop");
+        }
+        let resize = resized && self.vc_desktop_size;
+
+        let (width, height) = (frame.vf_size.0 as u16, frame.vf_size.1 as u16);
+        let mut msg = Vec::with_capacity(frame.vf_data.len() + 32);
+        // FramebufferUpdate
+        msg.extend_from_slice(&[0, 0]);
+        msg.extend_from_slice(&(1 + resize as u16).to_be_bytes());
+        if resize {
+            msg.extend_from_slice(&[0, 0, 0, 0]);
+            msg.extend_from_slice(&width.to_be_bytes());
+            msg.extend_from_slice(&height.to_be_bytes());
+            msg.extend_from_slice(&ENCODING_DESKTOP_SIZE.to_be_bytes());
+        }
+        // The entire frame with the Raw encoding
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg.extend_from_slice(&width.to_be_bytes());
+        msg.extend_from_slice(&height.to_be_bytes());
+        msg.extend_from_slice(&0i32.to_be_bytes());
+        self.vc_format.encode(&frame.vf_data, &mut msg);
+        self.send(&msg)?;
+
+        self.vc_size = frame.vf_size;
+        self.vc_sent_serial = frame.vf_serial;
+        self.vc_update_requested = false;
+        self.vc_full_update = false;
+        Ok(())
+    }
+}
+
+/// The VNC platform
+pub struct VncPlat {
+    vp_listener: TcpListener,
+    vp_clients: Vec<VncClient>,
+    vp_input: VncInput,
+    /// Shared with our output, which gives us its frames
+    vp_frame: Arc<Mutex<VncFrame>>,
+    /// Our virtual output, which input is sent to
+    vp_output_id: Option<OutputId>,
+    /// Do we have an output already?
+    vp_has_output: bool,
+}
+
+impl VncPlat {
+    /// Start listening for viewers on `address`
+    ///
+    /// If `address` is empty the default of 127.0.0.1:5900 is used.
+    /// Our socket is registered with `evloop`.
+    pub fn new(address: &str, evloop: &mut EventLoop) -> Result<Self> {
+        let address = match address.is_empty() {
+            true => DEFAULT_ADDRESS,
+            false => address,
+        };
+        let listener = TcpListener::bind(address)
+            .context(format!("Could not listen for VNC viewers on {}", address))?;
+        listener.set_nonblocking(true)?;
+        log::info!("Listening for VNC viewers on {}", address);
+
+        evloop.add_fd(listener.as_raw_fd());
+
+        Ok(Self {
+            vp_listener: listener,
+            vp_clients: Vec::new(),
+            vp_input: VncInput::new(),
+            vp_frame: Arc::new(Mutex::new(VncFrame {
+                vf_data: Vec::new(),
+                vf_size: (0, 0),
+                vf_serial: 0,
+            })),
+            vp_output_id: None,
+            vp_has_output: false,
+        })
+    }
+
+    /// Accept any new viewers
+    fn accept_clients(&mut self, evloop: &mut EventLoop) {
+        loop {
+            match self.vp_listener.accept() {
+                Ok((stream, address)) => match VncClient::new(stream) {
+                    Ok(client) => {
+                        log::info!("VNC viewer connected from {}", address);
+                        evloop.add_fd(client.vc_stream.as_raw_fd());
+                        self.vp_clients.push(client);
+                    }
+                    Err(e) => log::error!("Could not set up VNC viewer {}: {:?}", address, e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::error!("Could not accept VNC viewer: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Handle the messages from every viewer
+    ///
+    /// Viewers which disconnected or misbehaved are dropped.
+    fn process_clients(
+        &mut self,
+        evloop: &mut EventLoop,
+        platform_queues: &mut ll::Component<PlatformEventSystem>,
+    ) {
+        let frame = self.vp_frame.lock().unwrap();
+        let mut evsys = self
+            .vp_output_id
+            .as_ref()
+            .and_then(|id| platform_queues.get_mut(id));
+
+        let mut i = 0;
+        while i < self.vp_clients.len() {
+            let client = &mut self.vp_clients[i];
+
+            let mut ret: Result<bool> = client.receive().map_err(|e| e.into());
+            while let Ok(true) = ret {
+                let evsys = evsys.as_mut().map(|e| &mut **e);
+                match client.handle_message(&mut self.vp_input, evsys, &frame) {
+                    Ok(Some(len)) => {
+                        client.vc_buf.drain(..len);
+                    }
+                    Ok(None) => break,
+                    Err(e) => ret = Err(e),
+                }
+            }
+
+            match ret {
+                Ok(true) => i += 1,
+                Ok(false) => {
+                    log::info!("VNC viewer disconnected");
+                    let client = self.vp_clients.remove(i);
+                    evloop.remove_fd(client.vc_stream.as_raw_fd());
+                }
+                Err(e) => {
+                    log::error!("Dropping VNC viewer: {:?}", e);
+                    let client = self.vp_clients.remove(i);
+                    evloop.remove_fd(client.vc_stream.as_raw_fd());
+                }
+            }
+        }
+    }
+
+    /// Send the latest frame to every viewer waiting for one
+    fn send_updates(&mut self, evloop: &mut EventLoop) {
+        let frame = self.vp_frame.lock().unwrap();
+
+        let mut i = 0;
+        while i < self.vp_clients.len() {
+            match self.vp_clients[i].send_update(&frame) {
+                Ok(()) => i += 1,
+                Err(e) => {
+                    log::error!("Dropping VNC viewer: {:?}", e);
+                    let client = self.vp_clients.remove(i);
+                    evloop.remove_fd(client.vc_stream.as_raw_fd());
+                }
+            }
+        }
+    }
+}
+
+impl Platform for VncPlat {
+    fn create_output(
+        &mut self,
+        _id: OutputId,
+        _virtual_output_id: OutputId,
+    ) -> Result<Box<dyn OutputPlatform>> {
+        if self.vp_has_output {
+            return Err(anyhow!("VNC platform supports only one Output at a time"));
+        }
+        self.vp_has_output = true;
+
+        Ok(Box::new(VncOutput {
+            vo_frame: self.vp_frame.clone(),
+        }))
+    }
+
+    /// Create a new virtual window
+    ///
+    /// This may fail if the platform only supports one virtual surface
+    fn create_virtual_output(&mut self, output_ecs: &ll::Instance) -> Result<OutputId> {
+        if self.vp_output_id.is_some() {
+            return Err(anyhow!(
+                "VNC platform supports only one VirtualOutput at a time"
+            ));
+        }
+
+        let ret = output_ecs.add_entity();
+        self.vp_output_id = Some(ret.clone());
+        Ok(ret)
+    }
+
+    fn get_th_surf_type<'a>(&self) -> Result<th::SurfaceType> {
+        Ok(th::SurfaceType::Headless)
+    }
+
+    /// Viewers handle their own input devices
+    fn set_input_config(&mut self, _config: &InputConfig) {}
+
+    fn run(
+        &mut self,
+        evloop: &mut EventLoop,
+        _global_evsys: &mut GlobalEventSystem,
+        _output_queues: &mut ll::Component<OutputEventSystem>,
+        platform_queues: &mut ll::Component<PlatformEventSystem>,
+        timeout: Option<usize>,
+    ) -> Result<()> {
+        // Send anything drawn since we last ran before waiting, so
+        // viewers see it right away
+        self.send_updates(evloop);
+        evloop.wait_for_events(timeout);
+
+        self.accept_clients(evloop);
+        self.process_clients(evloop, platform_queues);
+        self.send_updates(evloop);
+
+        Ok(())
+    }
+}
+
+/// VNC output
+///
+/// This receives the frames drawn by the headless Thundr display and
+/// hands them to the platform to send.
+pub struct VncOutput {
+    vo_frame: Arc<Mutex<VncFrame>>,
+}
+
+impl OutputPlatform for VncOutput {
+    fn get_th_window_info<'a>(&self) -> Result<th::WindowInfo<'_>> {
+        Ok(th::WindowInfo::Headless)
+    }
+
+    /// The size of the desktop is whatever the headless display uses
+    fn set_geometry(&mut self, _win: &dom::Window, _dims: (u32, u32)) -> Result<()> {
+        log::debug!("set_output_params on VNC is a noop");
+        Ok(())
+    }
+
+    fn wants_frames(&self) -> bool {
+        true
+    }
+
+    fn present_frame(&mut self, frame: &[u8], size: (u32, u32)) {
+        let mut ours = self.vo_frame.lock().unwrap();
+        ours.vf_data.clear();
+        ours.vf_data.extend_from_slice(frame);
+        ours.vf_size = size;
+        ours.vf_serial += 1;
+    }
+}
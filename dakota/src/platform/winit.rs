@@ -0,0 +1,368 @@
+/// winit backend platform
+///
+/// This handles window systems using winit, for when we don't want to
+/// depend on the SDL2 C library.
+use super::keyboard::XkbKeyboard;
+use super::{OutputPlatform, Platform};
+use crate::dom;
+use crate::utils::log;
+use crate::{
+    event::{AxisSource, GlobalEventSystem, OutputEventSystem, PlatformEventSystem, RawKeycode},
+    EventLoop, KeymapConfig, OutputId, Result,
+};
+
+extern crate winit;
+use crate::input::*;
+use raw_window_handle::HasRawDisplayHandle;
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, Event, MouseScrollDelta, WindowEvent};
+use winit::event_loop::ControlFlow;
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::window::{Window, WindowBuilder, WindowId};
+
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+const SCROLL_SENSITIVITY: f64 = 32.0;
+
+/// The DPI of a display with a scale factor of 1.0
+///
+/// This matches `th::Display::get_dpi` for winit windows.
+const BASE_DPI: f64 = 96.0;
+
+/// winit dispatch backend
+#[allow(dead_code)]
+pub struct WinitPlat {
+    wi_event_loop: winit::event_loop::EventLoop<()>,
+    /// winit events don't have timestamps, so we report the time since
+    /// this platform was created.
+    wi_start: Instant,
+    /// last known mouse
+    ///
+    /// winit only tells us the absolute position of the mouse in the
+    /// window, so we calculate relative motion from this.
+    wi_mouse_pos: (f64, f64),
+    /// The current set of active modifiers
+    wi_mods: Mods,
+    /// winit doesn't give us text with its key presses, so keys are
+    /// fed through xkbcommon like the other backends
+    wi_keyboard: XkbKeyboard,
+    /// This maps a winit WindowId to the OutputIds of our Output
+    /// and VirtualOutput that events should be delivered one.
+    /// The format is `(WindowId, Output, VirtualOutput)`.
+    wi_window_id_map: Arc<RwLock<Vec<(WindowId, OutputId, OutputId)>>>,
+}
+
+impl WinitPlat {
+    pub fn new() -> Result<Self> {
+        let event_loop = winit::event_loop::EventLoop::new();
+        let keyboard = XkbKeyboard::new(&KeymapConfig::default())?;
+
+        Ok(Self {
+            wi_event_loop: event_loop,
+            wi_start: Instant::now(),
+            wi_mouse_pos: (0.0, 0.0),
+            wi_mods: Mods::NONE,
+            wi_keyboard: keyboard,
+            wi_window_id_map: Arc::new(RwLock::new(Vec::with_capacity(1))),
+        })
+    }
+
+    /// Get the current time in microseconds
+    fn get_time(&self) -> u64 {
+        self.wi_start.elapsed().as_micros() as u64
+    }
+
+    /// winit hands us events that are identified by a WindowId. We need to
+    /// turn this into our OutputId for the Output or VirtualOutput that
+    /// we should queue this event up on.
+    fn get_output_from_winit_id(
+        &self,
+        window_id: WindowId,
+    ) -> Option<(WindowId, OutputId, OutputId)> {
+        self.wi_window_id_map
+            .read()
+            .unwrap()
+            .iter()
+            .find(|e| e.0 == window_id)
+            .cloned()
+    }
+
+    /// Get all of the events winit has for us without blocking
+    ///
+    /// Scale factor changes are returned separately as
+    /// `(WindowId, scale factor)`, since winit gives them a reference
+    /// to the new window size which can't be kept around.
+    fn pump_events(&mut self) -> (Vec<Event<'static, ()>>, Vec<(WindowId, f64)>) {
+        let mut events = Vec::new();
+        let mut scale_changes = Vec::new();
+
+        self.wi_event_loop
+            .run_return(|event, _, control_flow| match event {
+                // Redraws are requested after the other events, so return
+                // once those have been handled too
+                Event::RedrawEventsCleared => *control_flow = ControlFlow::Exit,
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::ScaleFactorChanged { scale_factor, .. },
+                } => scale_changes.push((window_id, scale_factor)),
+                Event::WindowEvent { .. } | Event::RedrawRequested(_) => {
+                    if let Some(event) = event.to_static() {
+                        events.push(event);
+                    }
+                }
+                _ => {}
+            });
+
+        (events, scale_changes)
+    }
+
+    fn handle_event(
+        &mut self,
+        output_queues: &mut ll::Component<OutputEventSystem>,
+        platform_queues: &mut ll::Component<PlatformEventSystem>,
+        event: Event<'static, ()>,
+    ) -> Result<()> {
+        let (window_id, win_event) = match event {
+            Event::WindowEvent { window_id, event } => (window_id, Some(event)),
+            Event::RedrawRequested(window_id) => (window_id, None),
+            _ => return Ok(()),
+        };
+
+        // If we couldn't find an output for this id then the output
+        // may already be destroyed.
+        let (_, output_id, virtual_id) = match self.get_output_from_winit_id(window_id) {
+            Some(t) => t,
+            None => {
+                log::error!("winit Event on invalid window {:?}", window_id);
+                return Ok(());
+            }
+        };
+        let mut output_evsys = output_queues.get_mut(&output_id).unwrap();
+        let mut platform_evsys = platform_queues.get_mut(&virtual_id).unwrap();
+
+        let win_event = match win_event {
+            Some(ev) => ev,
+            // The window system wants us to draw
+            None => {
+                output_evsys.add_event_redraw();
+                return Ok(());
+            }
+        };
+
+        let time = self.get_time();
+        match win_event {
+            WindowEvent::CloseRequested => output_evsys.add_event_destroyed(),
+            WindowEvent::Resized(_) => output_evsys.add_event_resized(),
+            // Here we record events for our keystrokes
+            //
+            // winit doesn't give us utf8 with its key presses, so we
+            // feed the keys through xkbcommon like the other backends
+            //
+            // On X11 and Wayland winit's scancodes are Linux `KEY_*` values.
+            WindowEvent::KeyboardInput { input, .. } => {
+                let pressed = input.state == ElementState::Pressed;
+                let info = self.wi_keyboard.handle_key(input.scancode, pressed);
+                let raw = RawKeycode::Linux(input.scancode);
+
+                match pressed {
+                    true => platform_evsys.add_event_key_down(info.ki_key, info.ki_utf8, raw, time),
+                    false => platform_evsys.add_event_key_up(info.ki_key, info.ki_utf8, raw, time),
+                }
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                let mods = convert_winit_mods_to_dakota(state);
+                if mods != self.wi_mods {
+                    self.wi_mods = mods;
+                    platform_evsys.add_event_keyboard_modifiers(mods, time);
+                }
+            }
+            // handle pointer inputs. This just looks like the above keyboard
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = convert_winit_mouse_to_dakota(button);
+                match state {
+                    ElementState::Pressed => {
+                        platform_evsys.add_event_mouse_button_down(button, time)
+                    }
+                    ElementState::Released => {
+                        platform_evsys.add_event_mouse_button_up(button, time)
+                    }
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => platform_evsys.add_event_scroll(
+                    // reverse the scroll direction
+                    Some((x as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
+                    Some((y as f64 * SCROLL_SENSITIVITY * -1.0) as i32),
+                    (x as f64 * -120.0, y as f64 * -120.0),
+                    AxisSource::Wheel,
+                    time,
+                ),
+                // Touchpads scroll by pixels instead of by clicks
+                MouseScrollDelta::PixelDelta(pos) => platform_evsys.add_event_scroll(
+                    Some((pos.x * -1.0) as i32),
+                    Some((pos.y * -1.0) as i32),
+                    (0.0, 0.0), // v120 value unspecified
+                    AxisSource::Finger,
+                    time,
+                ),
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                platform_evsys.add_event_mouse_move(
+                    (position.x - self.wi_mouse_pos.0) as i32,
+                    (position.y - self.wi_mouse_pos.1) as i32,
+                    time,
+                );
+
+                // Update our mouse position
+                self.wi_mouse_pos = (position.x, position.y);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Platform for WinitPlat {
+    /// Create a window
+    ///
+    /// This creates a new window output with our winsys, we can
+    /// then use this with a Thundr `Display`.
+    fn create_output(
+        &mut self,
+        id: OutputId,
+        virtual_output_id: OutputId,
+    ) -> Result<Box<dyn OutputPlatform>> {
+        let window = WindowBuilder::new()
+            .with_title("dakota")
+            .with_inner_size(LogicalSize::new(640, 480))
+            .build(&self.wi_event_loop)?;
+
+        // Record this in our map
+        log::debug!("adding winit window id {:?}", window.id());
+        self.wi_window_id_map
+            .write()
+            .unwrap()
+            .push((window.id(), id, virtual_output_id));
+
+        Ok(Box::new(WinitWindow {
+            wi_window: Arc::new(window),
+            wi_window_id_map: self.wi_window_id_map.clone(),
+        }))
+    }
+
+    /// Create a new virtual window
+    ///
+    /// This may fail if the platform only supports one virtual surface
+    fn create_virtual_output(&mut self, output_ecs: &ll::Instance) -> Result<OutputId> {
+        Ok(output_ecs.add_entity())
+    }
+
+    /// Input devices belong to the window system, which configures them.
+    fn set_input_config(&mut self, _config: &InputConfig) {}
+
+    fn set_keymap(&mut self, config: &KeymapConfig) -> Result<()> {
+        self.wi_keyboard.set_keymap(config)
+    }
+
+    /// Run the event loop for this platform
+    ///
+    /// Handle all available events from winit, or wait until the
+    /// event loop wakes up. If timeout is specified we will return once
+    /// it has passed.
+    fn run(
+        &mut self,
+        evloop: &mut EventLoop,
+        _global_evsys: &mut GlobalEventSystem,
+        output_evsys: &mut ll::Component<OutputEventSystem>,
+        platform_evsys: &mut ll::Component<PlatformEventSystem>,
+        timeout: Option<usize>,
+    ) -> Result<()> {
+        // winit can't give us an fd to wait on alongside our event loop,
+        // so just like SDL we have to busy loop checking one and then
+        // the other.
+        let start = Instant::now();
+        loop {
+            // Wait for the first readable fd, timer or signal
+            let woke = evloop.wait_for_events(Some(1));
+
+            let (events, scale_changes) = self.pump_events();
+            let got_events = !events.is_empty() || !scale_changes.is_empty();
+
+            // Moving between displays may change our DPI
+            for (window_id, scale) in scale_changes.into_iter() {
+                if let Some((_, _, virtual_id)) = self.get_output_from_winit_id(window_id) {
+                    let dpi = (BASE_DPI * scale) as i32;
+                    platform_evsys
+                        .get_mut(&virtual_id)
+                        .unwrap()
+                        .add_event_dpi_changed((dpi, dpi));
+                }
+            }
+            for event in events.into_iter() {
+                self.handle_event(output_evsys, platform_evsys, event)?;
+            }
+
+            if woke || got_events {
+                return Ok(());
+            }
+
+            if let Some(timeout) = timeout {
+                if start.elapsed().as_millis() >= timeout as u128 {
+                    return Ok(());
+                }
+            }
+
+            // Don't waste all the CPU
+            std::thread::sleep(std::time::Duration::from_millis(8));
+        }
+    }
+
+    fn get_th_surf_type<'a>(&self) -> Result<th::SurfaceType> {
+        Ok(th::SurfaceType::Winit(
+            self.wi_event_loop.raw_display_handle(),
+        ))
+    }
+}
+
+/// Single winit window
+pub struct WinitWindow {
+    /// This is shared with Thundr, which needs it to create its surface
+    wi_window: Arc<Window>,
+    /// This maps a winit WindowId to the OutputIds of our Output
+    /// and VirtualOutput that events should be delivered one.
+    /// The format is `(WindowId, Output, VirtualOutput)`.
+    wi_window_id_map: Arc<RwLock<Vec<(WindowId, OutputId, OutputId)>>>,
+}
+
+impl Drop for WinitWindow {
+    fn drop(&mut self) {
+        // Remove this window from our tracking list
+        let window_id = self.wi_window.id();
+        log::debug!("removing winit window id {:?}", window_id);
+        self.wi_window_id_map
+            .write()
+            .unwrap()
+            .retain(|e| e.0 != window_id);
+    }
+}
+
+impl OutputPlatform for WinitWindow {
+    /// Get the thundr winsys info that this platform should use.
+    ///
+    /// This is where we share our window system object pointers that
+    /// Thundr will consume when it creates a `Dispaly` that draws to
+    /// this output.
+    fn get_th_window_info<'a>(&self) -> Result<th::WindowInfo> {
+        Ok(th::WindowInfo::Winit(&self.wi_window))
+    }
+
+    /// Set the dimensions of this window
+    fn set_geometry(&mut self, win: &dom::Window, dims: (u32, u32)) -> Result<()> {
+        self.wi_window.set_title(&win.title);
+        self.wi_window
+            .set_inner_size(PhysicalSize::new(dims.0, dims.1));
+        Ok(())
+    }
+}
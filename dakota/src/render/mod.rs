@@ -1,6 +1,8 @@
+use crate::atlas::GlyphAtlas;
 use crate::font::Glyph;
 use crate::layout::LayoutNode;
-use crate::{dom, DakotaId, Output, Scene};
+use crate::scene::{get_scrollbars, SCROLLBAR_THUMB_COLOR, SCROLLBAR_TRACK_COLOR};
+use crate::{dom, DakotaId, FrameStats, Output, Scene};
 
 /// Dakota Drawing logic
 ///
@@ -11,6 +13,12 @@ use crate::{dom, DakotaId, Output, Scene};
 /// LayoutNode tree computed by the layout layer and turns it
 /// into Thundr Surfaces, dispatching the draw calls.
 use thundr as th;
+use utils::trace;
+
+use std::collections::HashMap;
+
+/// Scrollbar surfaces are numbered after the slices of a nine-patch
+const SCROLLBAR_PIECES_START: usize = 9;
 
 /// RenderTransaction
 ///
@@ -22,12 +30,198 @@ pub(crate) struct RenderTransaction<'a> {
     rt_resources: ll::Snapshot<'a, DakotaId>,
     rt_resource_thundr_image: ll::Snapshot<'a, th::Image>,
     rt_resource_color: ll::Snapshot<'a, dom::Color>,
+    rt_resource_gradient: ll::Snapshot<'a, dom::Gradient>,
+    rt_resource_nine_patch: ll::Snapshot<'a, dom::NinePatch>,
     rt_fonts: ll::Snapshot<'a, dom::Font>,
     rt_text_font: ll::Snapshot<'a, DakotaId>,
     rt_default_font_inst: DakotaId,
     rt_glyphs: ll::Snapshot<'a, Glyph>,
+    rt_glyph_atlas: &'a GlyphAtlas,
     rt_viewports: ll::Snapshot<'a, th::Viewport>,
+    rt_scrollbars: ll::Snapshot<'a, bool>,
+    rt_protected: ll::Snapshot<'a, bool>,
+    rt_opacities: ll::Snapshot<'a, f32>,
+    rt_tints: ll::Snapshot<'a, (f32, f32, f32)>,
+    rt_transforms: ll::Snapshot<'a, th::Transform>,
     rt_layout_nodes: ll::Snapshot<'a, LayoutNode>,
+    rt_resource_damage: ll::Snapshot<'a, th::Damage>,
+}
+
+/// One surface drawn in a frame
+///
+/// The records of the last frame are kept by the Output, and compared
+/// with the next frame to find the parts of the screen that changed.
+#[derive(Debug, PartialEq)]
+pub(crate) struct DrawRecord {
+    /// Raw id of the element which drew this surface
+    dr_element: usize,
+    /// Which of the element's surfaces this is. Nine-patch images and
+    /// scrollbars draw more than one.
+    dr_piece: usize,
+    /// The surface as it was drawn on screen
+    dr_surface: th::Surface,
+    /// Raw id of the image sampled, if any
+    dr_image: Option<usize>,
+    /// The viewport region this surface was clipped to
+    dr_clip: th::Rect<i32>,
+}
+
+impl DrawRecord {
+    /// Records of the same element and piece are compared between frames
+    fn key(&self) -> (usize, usize) {
+        (self.dr_element, self.dr_piece)
+    }
+
+    /// The area of the screen covered by this draw
+    fn visible_region(&self) -> Option<th::Rect<i32>> {
+        self.dr_clip
+            .intersection(&self.dr_surface.get_bounding_box())
+    }
+}
+
+/// A recorded drawing command
+///
+/// The scene is walked before the frame is started so that we know
+/// what has been damaged, and then these are replayed into the frame.
+enum DrawCmd<'b> {
+    /// Draw within a viewport nested in the current one
+    PushViewport(th::Viewport),
+    /// Go back to the parent viewport
+    PopViewport,
+    Surface(DrawRecord, Option<&'b th::Image>),
+}
+
+/// Add the regions that differ between two frames to `damage`
+///
+/// Records are matched by the element that drew them, so adding or
+/// removing an element only damages that element and not everything
+/// drawn after it. Anything that was added, removed, changed, or drawn
+/// in a different order is damaged in both its old and new position.
+///
+/// `old_records` is scratch space which is kept between frames. The
+/// number of surfaces which were added, removed, or changed are counted
+/// in `stats`.
+fn add_changed_regions(
+    damage: &mut th::Damage,
+    old_records: &mut HashMap<(usize, usize), usize>,
+    stats: &mut FrameStats,
+    last: &[DrawRecord],
+    next: &[&DrawRecord],
+) {
+    let mut add_damage = |record: &DrawRecord| {
+        if let Some(region) = record.visible_region() {
+            damage.add(&region);
+        }
+    };
+
+    old_records.clear();
+    for (i, record) in last.iter().enumerate() {
+        // Elements drawn twice can't be matched up
+        if let Some(dup) = old_records.insert(record.key(), i) {
+            add_damage(&last[dup]);
+            add_damage(record);
+            stats.fs_surfaces_changed += 1;
+        }
+    }
+
+    // The position in the last frame of the latest record we have matched
+    let mut last_index = 0;
+    for record in next.iter() {
+        match old_records.remove(&record.key()) {
+            Some(index) => {
+                // If this was drawn before something we have already seen,
+                // then it may now be on top of it
+                let in_order = index >= last_index;
+                last_index = last_index.max(index);
+
+                if !in_order || last[index] != **record {
+                    add_damage(&last[index]);
+                    add_damage(record);
+                    stats.fs_surfaces_changed += 1;
+                }
+            }
+            None => {
+                add_damage(record);
+                stats.fs_surfaces_added += 1;
+            }
+        }
+    }
+
+    // Anything left over is no longer drawn
+    for index in old_records.drain().map(|(_, index)| index) {
+        add_damage(&last[index]);
+        stats.fs_surfaces_removed += 1;
+    }
+}
+
+/// Split one axis of a nine-patch into its three pieces
+///
+/// `start` and `end` are the fixed border sizes in image pixels. If the
+/// borders do not fit in `dst_len` they are shrunk proportionally.
+/// Returns (src offset, src length, dst offset, dst length) for each piece.
+fn nine_patch_axis(
+    start: u32,
+    end: u32,
+    image_len: i32,
+    dst_len: i32,
+) -> [(i32, i32, i32, i32); 3] {
+    let start = (start as i32).min(image_len);
+    let end = (end as i32).min(image_len - start);
+
+    let (dst_start, dst_end) = match start + end > dst_len {
+        true => {
+            let dst_start = start * dst_len / (start + end);
+            (dst_start, dst_len - dst_start)
+        }
+        false => (start, end),
+    };
+
+    [
+        (0, start, 0, dst_start),
+        (
+            start,
+            image_len - start - end,
+            dst_start,
+            dst_len - dst_start - dst_end,
+        ),
+        (image_len - end, end, dst_len - dst_end, dst_end),
+    ]
+}
+
+/// Get the pieces to draw an image with nine-slice scaling
+///
+/// Returns the screen region and image region of each piece. Corners are
+/// drawn at their original size, edges are stretched along one axis, and
+/// the center is stretched to fill the rest of `dst`. Empty pieces are
+/// skipped.
+pub(crate) fn nine_patch_slices(
+    patch: &dom::NinePatch,
+    image_size: (u32, u32),
+    dst: &th::Rect<i32>,
+) -> Vec<(th::Rect<i32>, th::Rect<i32>)> {
+    let columns = nine_patch_axis(patch.left, patch.right, image_size.0 as i32, dst.r_size.0);
+    let rows = nine_patch_axis(patch.top, patch.bottom, image_size.1 as i32, dst.r_size.1);
+    let mut ret = Vec::new();
+
+    for (src_y, src_height, dst_y, dst_height) in rows.iter() {
+        for (src_x, src_width, dst_x, dst_width) in columns.iter() {
+            if *src_width <= 0 || *src_height <= 0 || *dst_width <= 0 || *dst_height <= 0 {
+                continue;
+            }
+
+            ret.push((
+                th::Rect::new(
+                    dst.r_pos.0 + dst_x,
+                    dst.r_pos.1 + dst_y,
+                    *dst_width,
+                    *dst_height,
+                ),
+                th::Rect::new(*src_x, *src_y, *src_width, *src_height),
+            ));
+        }
+    }
+
+    ret
 }
 
 impl<'a> RenderTransaction<'a> {
@@ -36,21 +230,37 @@ impl<'a> RenderTransaction<'a> {
         self.rt_resources.precommit();
         self.rt_resource_thundr_image.precommit();
         self.rt_resource_color.precommit();
+        self.rt_resource_gradient.precommit();
+        self.rt_resource_nine_patch.precommit();
         self.rt_fonts.precommit();
         self.rt_text_font.precommit();
         self.rt_glyphs.precommit();
         self.rt_viewports.precommit();
+        self.rt_scrollbars.precommit();
+        self.rt_protected.precommit();
+        self.rt_opacities.precommit();
+        self.rt_tints.precommit();
+        self.rt_transforms.precommit();
         self.rt_layout_nodes.precommit();
+        self.rt_resource_damage.precommit();
 
         // Now do actual commit to WAR ids being dropped
         self.rt_resources.commit();
         self.rt_resource_thundr_image.commit();
         self.rt_resource_color.commit();
+        self.rt_resource_gradient.commit();
+        self.rt_resource_nine_patch.commit();
         self.rt_fonts.commit();
         self.rt_text_font.commit();
         self.rt_glyphs.commit();
         self.rt_viewports.commit();
+        self.rt_scrollbars.commit();
+        self.rt_protected.commit();
+        self.rt_opacities.commit();
+        self.rt_tints.commit();
+        self.rt_transforms.commit();
         self.rt_layout_nodes.commit();
+        self.rt_resource_damage.commit();
     }
 
     /// Helper to get a display surface for a glyph.
@@ -64,6 +274,7 @@ impl<'a> RenderTransaction<'a> {
             th::Rect::new(pos.0, pos.1, glyph.g_bitmap_size.0, glyph.g_bitmap_size.1),
             None,
         );
+        surf.set_subpixel(glyph.g_subpixel);
 
         let font_id = match self.rt_text_font.get(node) {
             Some(f) => f,
@@ -77,6 +288,21 @@ impl<'a> RenderTransaction<'a> {
         return surf;
     }
 
+    /// Convert one of our gradients to the Thundr equivalent
+    fn get_thundr_gradient(gradient: &dom::Gradient) -> th::Gradient {
+        let color = |c: &dom::Color| (c.r, c.g, c.b, c.a);
+        th::Gradient {
+            g_kind: match gradient.kind {
+                dom::GradientKind::Linear => th::GradientKind::Linear,
+                dom::GradientKind::Radial => th::GradientKind::Radial,
+            },
+            g_start: gradient.start,
+            g_end: gradient.end,
+            g_start_color: color(&gradient.start_color),
+            g_end_color: color(&gradient.end_color),
+        }
+    }
+
     /// Populate a display surface with this nodes dimensions and content
     ///
     /// This accepts a base offset to handle child element positioning
@@ -118,6 +344,12 @@ impl<'a> RenderTransaction<'a> {
                 surf.set_color((color.r, color.g, color.b, color.a));
                 content_num += 1;
             }
+            // Gradients are drawn over the other contents, so they may
+            // be the only thing set
+            if let Some(gradient) = self.rt_resource_gradient.get(resource_id) {
+                surf.set_gradient(Self::get_thundr_gradient(gradient));
+                content_num = content_num.max(1);
+            }
 
             assert!(content_num == 1);
         }
@@ -223,20 +455,90 @@ impl<'a> RenderTransaction<'a> {
                     || (offset.x + child_viewport.size.1 < viewport.offset.1))
     }
 
-    /// Helper for drawing a single element
+    /// Add the screen area covered by updated image contents to `damage`
+    ///
+    /// `res_damage` is in image pixels, which are scaled to the size the
+    /// image is drawn at on `surf`.
+    fn add_content_damage(
+        damage: &mut th::Damage,
+        surf: &th::Surface,
+        image: &th::Image,
+        res_damage: &th::Damage,
+        clip: &th::Rect<i32>,
+    ) {
+        let (width, height) = image.get_size();
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (surf_width, surf_height) = surf.get_size();
+        let scale = (
+            surf_width as f32 / width as f32,
+            surf_height as f32 / height as f32,
+        );
+
+        // Round outwards so that partially covered pixels are redrawn
+        let scaled = th::Damage::new(
+            res_damage
+                .regions()
+                .map(|r| {
+                    let start = (
+                        (r.r_pos.0 as f32 * scale.0).floor() as i32,
+                        (r.r_pos.1 as f32 * scale.1).floor() as i32,
+                    );
+                    let end = (
+                        ((r.r_pos.0 + r.r_size.0) as f32 * scale.0).ceil() as i32,
+                        ((r.r_pos.1 + r.r_size.1) as f32 * scale.1).ceil() as i32,
+                    );
+                    th::Rect::new(start.0, start.1, end.0 - start.0, end.1 - start.1)
+                })
+                .collect(),
+        );
+
+        for region in scaled.to_screen_space(surf).regions() {
+            if let Some(region) = region.intersection(clip) {
+                damage.add(&region);
+            }
+        }
+    }
+
+    /// Helper for recording a single element
     ///
     /// This does not recurse. Will skip drawing this node if it is out of the bounds of
-    /// its viewport.
-    fn draw_node(
-        &self,
-        frame: &mut th::FrameRenderer<'a>,
+    /// its viewport. `opacity` and `tint` are the combined opacity and tint of this
+    /// node and its parents, and `transform` is their combined transform in screen
+    /// coordinates.
+    /// Any updates to the contents of this node are added to `damage`.
+    #[allow(clippy::too_many_arguments)]
+    fn record_node<'b>(
+        &'b self,
+        cmds: &mut Vec<DrawCmd<'b>>,
+        damage: &mut th::Damage,
         viewport: &th::Viewport,
         node: &DakotaId,
         base: (i32, i32),
+        opacity: f32,
+        tint: (f32, f32, f32),
+        transform: &th::Transform,
     ) -> th::Result<()> {
-        let surf = self.get_thundr_surf_for_el(node, base)?;
+        let mut surf = self.get_thundr_surf_for_el(node, base)?;
+        surf.set_opacity(opacity);
+        surf.set_tint(tint);
+        surf.set_protected(self.rt_protected.get(node) == Some(&true));
+        Self::set_surface_transform(&mut surf, transform);
+
+        let clip = th::Rect::new(
+            viewport.offset.0,
+            viewport.offset.1,
+            viewport.size.0,
+            viewport.size.1,
+        );
 
-        if !self.is_node_visible(viewport, node, base) {
+        // Transformed nodes may be drawn away from their layout position
+        let visible = match transform.is_identity() {
+            true => self.is_node_visible(viewport, node, base),
+            false => surf.get_bounding_box().intersection(&clip).is_some(),
+        };
+        if !visible {
             return Ok(());
         }
 
@@ -245,29 +547,183 @@ impl<'a> RenderTransaction<'a> {
         // id. The atomic inc/dec to do this shows up in profiling
         let layout = self.rt_layout_nodes.get(node).unwrap();
         let mut image = None;
+        let mut nine_patch = None;
 
         if let Some(glyph_id) = layout.l_glyph_id.as_ref() {
-            let glyph = self.rt_glyphs.get(glyph_id).unwrap();
-            image = glyph.g_image.as_ref();
+            // Glyphs are drawn from their area of the glyph atlas. Glyphs
+            // which aren't in it have nothing to draw.
+            match self.rt_glyph_atlas.get_rect(glyph_id) {
+                Some(rect) => {
+                    image = self.rt_glyph_atlas.get_image();
+                    surf.set_src_rect(rect);
+                }
+                None => return Ok(()),
+            }
         } else if let Some(resource_id) = self.rt_resources.get(node) {
             if let Some(res) = self.rt_resource_thundr_image.get(&resource_id) {
-                image = Some(res)
+                image = Some(res);
+                nine_patch = self.rt_resource_nine_patch.get(resource_id);
+            }
+        }
+
+        // Redraw the parts of this node whose contents were updated
+        if let (Some(image), Some(resource_id)) = (image, self.rt_resources.get(node)) {
+            if let Some(res_damage) = self.rt_resource_damage.get(resource_id) {
+                match nine_patch.is_some() {
+                    // The image is scaled differently across a nine-patch,
+                    // so just redraw all of it
+                    true => {
+                        if let Some(region) = surf.get_bounding_box().intersection(&clip) {
+                            damage.add(&region);
+                        }
+                    }
+                    false => Self::add_content_damage(damage, &surf, image, res_damage, &clip),
+                }
+            }
+        }
+
+        // Nine-patch images are drawn as one surface per slice
+        if let (Some(image), Some(patch)) = (image, nine_patch) {
+            for (i, (dst, src)) in nine_patch_slices(patch, image.get_size(), &surf.s_rect)
+                .into_iter()
+                .enumerate()
+            {
+                let mut piece = th::Surface::new(dst, None);
+                piece.set_opacity(opacity);
+                piece.set_tint(tint);
+                piece.set_protected(surf.s_protected);
+                Self::set_surface_transform(&mut piece, transform);
+                piece.set_src_rect(src);
+
+                cmds.push(DrawCmd::Surface(
+                    DrawRecord {
+                        dr_element: node.get_raw_id(),
+                        dr_piece: i,
+                        dr_surface: piece,
+                        dr_image: Some(image.i_id.get_raw_id()),
+                        dr_clip: clip,
+                    },
+                    Some(image),
+                ));
             }
+            return Ok(());
         }
 
-        frame.draw_surface(&surf, image)
+        cmds.push(DrawCmd::Surface(
+            DrawRecord {
+                dr_element: node.get_raw_id(),
+                dr_piece: 0,
+                dr_surface: surf,
+                dr_image: image.map(|i| i.i_id.get_raw_id()),
+                dr_clip: clip,
+            },
+            image,
+        ));
+        Ok(())
     }
 
-    /// Recursively draw node and all of its children
+    /// Apply a transform in screen coordinates to a surface
+    ///
+    /// Thundr transforms are relative to the surface's top left corner,
+    /// so this is moved to the surface's position.
+    fn set_surface_transform(surf: &mut th::Surface, transform: &th::Transform) {
+        if !transform.is_identity() {
+            let pos = surf.get_pos();
+            surf.set_transform(transform.around(-pos.0 as f32, -pos.1 as f32));
+        }
+    }
+
+    /// Record the scrollbars of a viewport node
+    #[allow(clippy::too_many_arguments)]
+    fn record_scrollbars<'b>(
+        &'b self,
+        cmds: &mut Vec<DrawCmd<'b>>,
+        viewport: &th::Viewport,
+        node: &DakotaId,
+        base: (i32, i32),
+        opacity: f32,
+        tint: (f32, f32, f32),
+        transform: &th::Transform,
+    ) {
+        let layout = self.rt_layout_nodes.get(node).unwrap();
+        let area = th::Rect::new(
+            base.0 + layout.l_offset.x,
+            base.1 + layout.l_offset.y,
+            layout.l_size.width,
+            layout.l_size.height,
+        );
+        let clip = th::Rect::new(
+            viewport.offset.0,
+            viewport.offset.1,
+            viewport.size.0,
+            viewport.size.1,
+        );
+
+        let mut piece = SCROLLBAR_PIECES_START;
+        for bar in get_scrollbars(&area, self.rt_viewports.get(node).unwrap()) {
+            for (rect, color) in [
+                (bar.sc_track, SCROLLBAR_TRACK_COLOR),
+                (bar.sc_thumb, SCROLLBAR_THUMB_COLOR),
+            ] {
+                let mut surf = th::Surface::new(rect, Some(color));
+                surf.set_opacity(opacity);
+                surf.set_tint(tint);
+                Self::set_surface_transform(&mut surf, transform);
+                cmds.push(DrawCmd::Surface(
+                    DrawRecord {
+                        dr_element: node.get_raw_id(),
+                        dr_piece: piece,
+                        dr_surface: surf,
+                        dr_image: None,
+                        dr_clip: clip,
+                    },
+                    None,
+                ));
+                piece += 1;
+            }
+        }
+    }
+
+    /// Recursively record node and all of its children
     ///
     /// This does not cross viewport boundaries
-    fn draw_node_recurse(
-        &self,
-        frame: &mut th::FrameRenderer<'a>,
+    #[allow(clippy::too_many_arguments)]
+    fn record_node_recurse<'b>(
+        &'b self,
+        cmds: &mut Vec<DrawCmd<'b>>,
+        damage: &mut th::Damage,
         viewport: &th::Viewport,
         node: &DakotaId,
         base: (i32, i32),
+        opacity: f32,
+        tint: (f32, f32, f32),
+        transform: th::Transform,
     ) -> th::Result<()> {
+        // Opacity is inherited by all of our children
+        let opacity = match self.rt_opacities.get(node) {
+            Some(o) => opacity * *o,
+            None => opacity,
+        };
+        // Nothing under here will be visible
+        if opacity <= 0.0 {
+            return Ok(());
+        }
+        // As is our tint
+        let tint = match self.rt_tints.get(node) {
+            Some(t) => (tint.0 * t.0, tint.1 * t.1, tint.2 * t.2),
+            None => tint,
+        };
+        // Transforms are also inherited, our transform is applied around
+        // our top left corner before the ones of our parents
+        let transform = match self.rt_transforms.get(node) {
+            Some(t) => {
+                let layout = self.rt_layout_nodes.get(node).unwrap();
+                let pos = (base.0 + layout.l_offset.x, base.1 + layout.l_offset.y);
+                t.around(pos.0 as f32, pos.1 as f32).then(&transform)
+            }
+            None => transform,
+        };
+
         // If this node is a viewport then update our display viewport
         let new_th_viewport = match self.rt_viewports.get(node).is_some() {
             true => {
@@ -282,7 +738,7 @@ impl<'a> RenderTransaction<'a> {
 
                 // Set Thundr's currently in use viewport
                 let th_viewport = self.get_display_viewport(viewport, node, base).unwrap();
-                frame.set_viewport(&th_viewport)?;
+                cmds.push(DrawCmd::PushViewport(th_viewport.clone()));
 
                 Some(th_viewport)
             }
@@ -295,7 +751,16 @@ impl<'a> RenderTransaction<'a> {
         };
 
         // Start by drawing ourselves
-        self.draw_node(frame, new_viewport, node, base)?;
+        self.record_node(
+            cmds,
+            damage,
+            new_viewport,
+            node,
+            base,
+            opacity,
+            tint,
+            &transform,
+        )?;
 
         let layout = self.rt_layout_nodes.get(node).unwrap();
 
@@ -309,25 +774,71 @@ impl<'a> RenderTransaction<'a> {
 
         // Now draw each of our children
         for child in layout.l_children.iter() {
-            self.draw_node_recurse(frame, new_viewport, child, new_base)?;
+            self.record_node_recurse(
+                cmds,
+                damage,
+                new_viewport,
+                child,
+                new_base,
+                opacity,
+                tint,
+                transform,
+            )?;
+        }
+
+        // Scrollbars are drawn over the contents of the viewport
+        if new_th_viewport.is_some() && self.rt_scrollbars.get(node) == Some(&true) {
+            self.record_scrollbars(cmds, new_viewport, node, base, opacity, tint, &transform);
         }
 
         // If this node was a viewport then restore our old viewport
         if new_th_viewport.is_some() {
-            frame.set_viewport(viewport)?;
+            cmds.push(DrawCmd::PopViewport);
         }
 
         Ok(())
     }
 
-    /// Draw a scene using the provided renderer and transaction view.
-    pub(crate) fn draw_surfacelists(
-        &self,
-        frame: &mut th::FrameRenderer<'a>,
+    /// Record the draw commands for a scene using this transaction view
+    ///
+    /// Returns the commands along with the damage caused by updates to
+    /// the contents of the scene's resources. Popups are recorded after
+    /// the main tree so that they are drawn on top of it.
+    ///
+    /// `capacity` is the number of commands expected, usually the number
+    /// recorded last frame, so the list doesn't have to grow while recording.
+    fn record_surfacelists<'b>(
+        &'b self,
         root_viewport: &th::Viewport,
         root_node: DakotaId,
-    ) -> th::Result<()> {
-        self.draw_node_recurse(frame, &root_viewport, &root_node, (0, 0))
+        popups: &[DakotaId],
+        capacity: usize,
+    ) -> th::Result<(Vec<DrawCmd<'b>>, th::Damage)> {
+        let mut cmds = Vec::with_capacity(capacity);
+        let mut damage = th::Damage::empty();
+        self.record_node_recurse(
+            &mut cmds,
+            &mut damage,
+            root_viewport,
+            &root_node,
+            (0, 0),
+            1.0,
+            (1.0, 1.0, 1.0),
+            th::Transform::identity(),
+        )?;
+        for popup in popups.iter() {
+            self.record_node_recurse(
+                &mut cmds,
+                &mut damage,
+                root_viewport,
+                popup,
+                (0, 0),
+                1.0,
+                (1.0, 1.0, 1.0),
+                th::Transform::identity(),
+            )?;
+        }
+        Ok((cmds, damage))
     }
 }
 
@@ -335,28 +846,114 @@ impl Output {
     /// Draw the entire scene
     ///
     /// This starts at the root viewport and draws all child viewports
-    /// present in the specified scene object.
-    pub(crate) fn draw_surfacelists(&mut self, scene: &Scene) -> th::Result<()> {
+    /// present in the specified scene object. Only the parts of the
+    /// screen that changed since the last frame are redrawn.
+    pub(crate) fn draw_surfacelists(&mut self, scene: &mut Scene) -> th::Result<()> {
         let root_node = scene
             .d_layout_tree_root
             .clone()
             .expect("No compiled layout found, need to compile this Scene before using it");
         let root_viewport = scene.d_viewports.get_clone(&root_node).unwrap();
+        let popups = scene.get_popups();
+
+        // If drawing fails we don't know what is on screen, so the next
+        // frame will be redrawn completely
+        let last_frame = self.d_last_frame.take();
 
-        let mut frame = self.d_display.acquire_next_frame()?;
         let mut trans = RenderTransaction {
             rt_resources: scene.d_resources.snapshot(),
             rt_resource_thundr_image: scene.d_resource_thundr_image.snapshot(),
             rt_resource_color: scene.d_resource_color.snapshot(),
+            rt_resource_gradient: scene.d_resource_gradient.snapshot(),
+            rt_resource_nine_patch: scene.d_resource_nine_patch.snapshot(),
             rt_fonts: scene.d_fonts.snapshot(),
             rt_text_font: scene.d_text_font.snapshot(),
             rt_default_font_inst: scene.d_default_font_inst.clone(),
             rt_glyphs: scene.d_glyphs.snapshot(),
+            rt_glyph_atlas: &scene.d_glyph_atlas,
             rt_viewports: scene.d_viewports.snapshot(),
+            rt_scrollbars: scene.d_scrollbars.snapshot(),
+            rt_protected: scene.d_protected.snapshot(),
+            rt_opacities: scene.d_opacities.snapshot(),
+            rt_tints: scene.d_tints.snapshot(),
+            rt_transforms: scene.d_transforms.snapshot(),
             rt_layout_nodes: scene.d_layout_nodes.snapshot(),
+            rt_resource_damage: scene.d_resource_damage.snapshot(),
+        };
+        let record = trace::span("dakota", "record_surfacelists");
+        let (cmds, mut damage) =
+            trans.record_surfacelists(&root_viewport, root_node, &popups, self.d_last_cmd_count)?;
+        drop(record);
+        self.d_last_cmd_count = cmds.len();
+
+        let mut stats = FrameStats::default();
+        let mut frame = match last_frame.as_ref() {
+            Some(last_frame) => {
+                let records: Vec<&DrawRecord> = cmds
+                    .iter()
+                    .filter_map(|cmd| match cmd {
+                        DrawCmd::Surface(record, _) => Some(record),
+                        _ => None,
+                    })
+                    .collect();
+                add_changed_regions(
+                    &mut damage,
+                    &mut self.d_old_records,
+                    &mut stats,
+                    last_frame,
+                    &records,
+                );
+                self.d_display.acquire_next_frame_with_damage(&damage)?
+            }
+            None => self.d_display.acquire_next_frame()?,
         };
-        trans.draw_surfacelists(&mut frame, &root_viewport, root_node)?;
+
+        // Replay our commands, keeping the records for the next frame.
+        // Surfaces outside of the area Thundr is redrawing are skipped,
+        // as the swapchain image already holds them. Protected surfaces
+        // are always passed to Thundr so it can hide them from captures.
+        let redraw_area = frame.get_redraw_area();
+        let incremental = last_frame.is_some();
+        // Reuse the list of records from the last frame, which has room
+        // for about as many records as we need
+        let mut records = last_frame.unwrap_or_default();
+        records.clear();
+        let mut drawn = 0;
+        for cmd in cmds.into_iter() {
+            match cmd {
+                DrawCmd::PushViewport(viewport) => frame.push_viewport(&viewport),
+                DrawCmd::PopViewport => frame.pop_viewport(),
+                DrawCmd::Surface(record, image) => {
+                    if record.dr_surface.s_protected
+                        || record
+                            .visible_region()
+                            .and_then(|region| region.intersection(&redraw_area))
+                            .is_some()
+                    {
+                        frame.draw_surface(&record.dr_surface, image)?;
+                        drawn += 1;
+                    }
+                    records.push(record);
+                }
+            }
+        }
         trans.commit();
-        frame.present()
+        drop(trans);
+        frame.present()?;
+
+        self.d_frame_stats = FrameStats {
+            fs_damage: match incremental {
+                true => damage.regions().cloned().collect(),
+                false => vec![redraw_area],
+            },
+            fs_redraw_area: redraw_area,
+            fs_surface_count: records.len(),
+            fs_drawn_count: drawn,
+            fs_frame_number: self.d_frame_stats.fs_frame_number + 1,
+            ..stats
+        };
+        self.d_last_frame = Some(records);
+        scene.d_resource_damage.clear();
+        Ok(())
     }
 }
@@ -0,0 +1,148 @@
+//! Accessibility support
+//!
+//! After layout we walk the layout tree and describe the elements that
+//! accessibility tools care about: those with a role, a label, or text,
+//! along with the focused element. Elements without any of these are
+//! left out, and their children are attached to the closest ancestor
+//! that is in the tree. Popups are added as children of the window.
+// Austin Shafer - 2024
+use crate::access::{AccessNode, AccessNodeId, AccessUpdate};
+use crate::{dom, DakotaId, Scene};
+use utils::region::Rect;
+
+/// Get the accessibility id for an element
+fn get_access_id(el: &DakotaId) -> AccessNodeId {
+    el.get_raw_id() as AccessNodeId
+}
+
+impl Scene {
+    /// Set the element with keyboard focus
+    ///
+    /// This is reported to accessibility tools. Passing None clears the
+    /// focus.
+    pub fn set_focus(&mut self, el: Option<&DakotaId>) {
+        self.d_focus = el.cloned();
+
+        let id = el.map(get_access_id);
+        match id {
+            // The focused element will be added to the tree the next time
+            // the scene is recompiled
+            Some(id) if !self.d_access.contains(id) => self.d_access_modified = true,
+            _ => self.d_access.set_focus(id),
+        }
+    }
+
+    /// Get the element with keyboard focus
+    pub fn get_focus(&self) -> Option<DakotaId> {
+        self.d_focus.clone()
+    }
+
+    /// Get the changes to the accessibility tree since the last call
+    ///
+    /// The tree is rebuilt when the Scene is recompiled. Returns None if
+    /// nothing changed.
+    pub fn take_access_update(&mut self) -> Option<AccessUpdate> {
+        self.d_access.take_update()
+    }
+
+    /// Get the entire accessibility tree
+    ///
+    /// This is for accessibility tools which are being initialized.
+    pub fn get_access_tree(&self) -> AccessUpdate {
+        self.d_access.get_full_tree()
+    }
+
+    /// Get the text that should be read for an element's text contents
+    fn get_text_name(text: &dom::Text) -> String {
+        text.items
+            .iter()
+            .map(|item| match item {
+                dom::TextItem::p(run) | dom::TextItem::b(run) | dom::TextItem::span(run) => {
+                    run.value.as_str()
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// Add `el` and its children to the accessibility tree
+    ///
+    /// The ids of the nodes that should be children of our parent are
+    /// added to `siblings`.
+    fn build_access_node(
+        &self,
+        nodes: &mut Vec<AccessNode>,
+        siblings: &mut Vec<AccessNodeId>,
+        el: &DakotaId,
+        base: (i32, i32),
+        is_root: bool,
+    ) {
+        let layout = match self.d_layout_nodes.get(el) {
+            Some(layout) => layout.clone(),
+            None => return,
+        };
+        // Glyphs are described by the text element they belong to
+        if layout.l_glyph_id.is_some() {
+            return;
+        }
+
+        let pos = (base.0 + layout.l_offset.x, base.1 + layout.l_offset.y);
+        let text = self.d_texts.get(el).map(|text| Self::get_text_name(&text));
+        let role = self.d_roles.get(el).map(|role| *role);
+        let name = self.d_labels.get_clone(el).or(text.clone());
+        let focused = self.d_focus.as_ref() == Some(el);
+
+        // Children of viewports are moved by the scroll amount
+        let mut child_base = pos;
+        if let Some(viewport) = self.d_viewports.get(el) {
+            child_base.0 += viewport.scroll_offset.0;
+            child_base.1 += viewport.scroll_offset.1;
+        }
+        let mut children = Vec::new();
+        for child in layout.l_children.iter() {
+            self.build_access_node(nodes, &mut children, child, child_base, false);
+        }
+
+        if !(is_root || role.is_some() || name.is_some() || focused) {
+            siblings.append(&mut children);
+            return;
+        }
+
+        let role = role.unwrap_or(match (is_root, text.is_some()) {
+            (true, _) => dom::Role::Window,
+            (false, true) => dom::Role::Label,
+            (false, false) => dom::Role::Generic,
+        });
+
+        nodes.push(AccessNode {
+            an_id: get_access_id(el),
+            an_role: role,
+            an_name: name,
+            an_bounds: Rect::new(pos.0, pos.1, layout.l_size.width, layout.l_size.height),
+            an_children: children,
+        });
+        siblings.push(get_access_id(el));
+    }
+
+    /// Rebuild the accessibility tree from the layout tree rooted at `root`
+    ///
+    /// This is called after layout when recompiling the Scene.
+    pub(crate) fn update_access_tree(&mut self, root: &DakotaId) {
+        let mut nodes = Vec::new();
+        let mut top = Vec::new();
+        self.build_access_node(&mut nodes, &mut top, root, (0, 0), true);
+
+        // Popups are shown as children of the window
+        let mut popups = Vec::new();
+        for popup in self.get_popups() {
+            self.build_access_node(&mut nodes, &mut popups, &popup, (0, 0), false);
+        }
+        if let Some(window) = nodes.iter_mut().find(|n| n.an_id == get_access_id(root)) {
+            window.an_children.append(&mut popups);
+        }
+
+        self.d_access.update(get_access_id(root), nodes);
+        self.d_access
+            .set_focus(self.d_focus.as_ref().map(get_access_id));
+    }
+}
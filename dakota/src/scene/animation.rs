@@ -0,0 +1,91 @@
+//! Playing animated images
+//!
+//! Animated resources are advanced when their Output draws a frame. Apps
+//! should use `get_next_animation_time` to know when to redraw, so that
+//! the next frame of every shown animation is presented on time.
+// Austin Shafer - 2024
+use super::Scene;
+use crate::{dom, DakotaId};
+use utils::{Context, Result};
+
+use std::time::Instant;
+
+impl Scene {
+    /// Find the animated resources used in the tree rooted at `el`
+    pub(crate) fn get_animated_resources(&self, el: &DakotaId, animated: &mut Vec<DakotaId>) {
+        if let Some(res) = self.d_resources.get_clone(el) {
+            if self.d_resource_animation.get(&res).is_some() && !animated.contains(&res) {
+                animated.push(res);
+            }
+        }
+
+        let mut children = self.d_children.get_clone(el).unwrap_or_default();
+        if let Some(content) = self.d_contents.get(el) {
+            children.push(content.el.clone());
+        }
+        for child in children.iter() {
+            self.get_animated_resources(child, animated);
+        }
+    }
+
+    /// Advance animated images to `now`
+    pub(crate) fn update_animations_at(&mut self, now: Instant) -> Result<bool> {
+        let mut updated = false;
+
+        for res in self.d_animated.clone().iter() {
+            let mut state = match self.d_resource_animation.get_clone(res) {
+                Some(state) => state,
+                None => continue,
+            };
+            let damage = match state.advance(now) {
+                Some(damage) => damage,
+                None => continue,
+            };
+
+            // Frames which are identical to the last one don't need to
+            // be uploaded
+            if !damage.is_empty() {
+                let anim = state.as_animation.clone();
+                self.update_resource_from_bits(
+                    res,
+                    state.get_pixels(),
+                    anim.an_width,
+                    anim.an_height,
+                    0,
+                    dom::Format::ARGB8888,
+                    Some(damage),
+                )
+                .context("Uploading animation frame")?;
+                updated = true;
+            }
+            self.d_resource_animation.set(res, state);
+        }
+
+        Ok(updated)
+    }
+
+    /// Advance animated images
+    ///
+    /// This shows the frame of each animation which should be visible
+    /// now, uploading only the parts of it which changed. It is called by
+    /// `Output::redraw`. Returns true if any images were changed.
+    pub fn update_animations(&mut self) -> Result<bool> {
+        self.update_animations_at(Instant::now())
+    }
+
+    /// Are any animated images shown in this scene
+    pub fn is_animating(&self) -> bool {
+        !self.d_animated.is_empty()
+    }
+
+    /// Get when the next frame of an animated image should be shown
+    ///
+    /// The app should redraw at this time. Returns None if no animations
+    /// are being shown.
+    pub fn get_next_animation_time(&self) -> Option<Instant> {
+        self.d_animated
+            .iter()
+            .filter_map(|res| self.d_resource_animation.get(res).map(|s| s.as_next))
+            .min()
+    }
+}
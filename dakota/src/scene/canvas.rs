@@ -0,0 +1,752 @@
+//! Canvas elements
+//!
+//! A canvas is an element whose contents are drawn by the application
+//! with 2D drawing commands: filled and stroked paths made of lines,
+//! curves and arcs. This lets applications draw charts and other simple
+//! graphics without shipping their own renderer.
+//!
+//! Canvases are retained: the application adds draw commands, and can
+//! later replace or remove individual ones. Dakota rasterizes the
+//! commands into an image on the CPU, and tracks the area each command
+//! covers so that changing one only redraws and uploads that part of the
+//! image.
+// Austin Shafer - 2024
+use crate::{dom, DakotaId, Damage, Rect, Scene};
+use utils::{anyhow, Context, Result};
+
+use std::f32::consts::PI;
+
+/// The number of sub-scanlines sampled in each row of pixels
+const CANVAS_SUBSAMPLES: usize = 4;
+/// The largest distance in pixels a flattened curve may be from the
+/// real one
+const CANVAS_TOLERANCE: f32 = 0.25;
+
+/// A shape made of lines and curves
+///
+/// Paths are built from a series of contours, each of which is started
+/// with `move_to`. Curves are flattened into lines as they are added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CanvasPath {
+    p_contours: Vec<Contour>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Contour {
+    c_points: Vec<(f32, f32)>,
+    c_closed: bool,
+}
+
+/// The number of lines to split a curve of this length into
+fn curve_segments(length: f32) -> usize {
+    ((length / 4.0).ceil() as usize).clamp(1, 128)
+}
+
+/// The number of lines to split a full circle of this radius into
+fn circle_segments(radius: f32) -> usize {
+    if radius <= CANVAS_TOLERANCE {
+        return 8;
+    }
+    let step = (1.0 - CANVAS_TOLERANCE / radius).acos() * 2.0;
+    ((2.0 * PI / step).ceil() as usize).clamp(8, 256)
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+impl CanvasPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The contour being added to, starting one at the origin if needed
+    fn current(&mut self) -> &mut Contour {
+        if self.p_contours.last().map(|c| c.c_closed).unwrap_or(true) {
+            let start = self
+                .p_contours
+                .last()
+                .and_then(|c| c.c_points.first().copied())
+                .unwrap_or((0.0, 0.0));
+            self.p_contours.push(Contour {
+                c_points: vec![start],
+                c_closed: false,
+            });
+        }
+        self.p_contours.last_mut().unwrap()
+    }
+
+    fn last_point(&mut self) -> (f32, f32) {
+        *self.current().c_points.last().unwrap()
+    }
+
+    /// Start a new contour at this point
+    pub fn move_to(mut self, x: f32, y: f32) -> Self {
+        // Drop a previous move_to that was never drawn from
+        if let Some(last) = self.p_contours.last() {
+            if !last.c_closed && last.c_points.len() == 1 {
+                self.p_contours.pop();
+            }
+        }
+        self.p_contours.push(Contour {
+            c_points: vec![(x, y)],
+            c_closed: false,
+        });
+        self
+    }
+
+    /// Add a straight line to this point
+    pub fn line_to(mut self, x: f32, y: f32) -> Self {
+        self.current().c_points.push((x, y));
+        self
+    }
+
+    /// Add a quadratic bezier curve with control point `(cx, cy)`
+    pub fn quad_to(mut self, cx: f32, cy: f32, x: f32, y: f32) -> Self {
+        let start = self.last_point();
+        let count = curve_segments(distance(start, (cx, cy)) + distance((cx, cy), (x, y)));
+
+        let points = &mut self.current().c_points;
+        for i in 1..=count {
+            let t = i as f32 / count as f32;
+            let mt = 1.0 - t;
+            points.push((
+                mt * mt * start.0 + 2.0 * mt * t * cx + t * t * x,
+                mt * mt * start.1 + 2.0 * mt * t * cy + t * t * y,
+            ));
+        }
+        self
+    }
+
+    /// Add a cubic bezier curve with control points `(c1x, c1y)` and
+    /// `(c2x, c2y)`
+    pub fn cubic_to(mut self, c1x: f32, c1y: f32, c2x: f32, c2y: f32, x: f32, y: f32) -> Self {
+        let start = self.last_point();
+        let count = curve_segments(
+            distance(start, (c1x, c1y))
+                + distance((c1x, c1y), (c2x, c2y))
+                + distance((c2x, c2y), (x, y)),
+        );
+
+        let points = &mut self.current().c_points;
+        for i in 1..=count {
+            let t = i as f32 / count as f32;
+            let mt = 1.0 - t;
+            let (a, b, c, d) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+            points.push((
+                a * start.0 + b * c1x + c * c2x + d * x,
+                a * start.1 + b * c1y + c * c2y + d * y,
+            ));
+        }
+        self
+    }
+
+    /// Add an arc of the circle centered at `(cx, cy)`
+    ///
+    /// The angles are in radians, measured clockwise from the positive x
+    /// axis. The arc goes from `start` to `end`, counterclockwise if `end`
+    /// is less than `start`. A line is added from the current point to
+    /// the start of the arc, as with the HTML canvas.
+    pub fn arc(mut self, cx: f32, cy: f32, radius: f32, start: f32, end: f32) -> Self {
+        let sweep = (end - start).clamp(-2.0 * PI, 2.0 * PI);
+        let count =
+            ((circle_segments(radius) as f32 * sweep.abs() / (2.0 * PI)).ceil() as usize).max(1);
+
+        let point = |angle: f32| (cx + radius * angle.cos(), cy + radius * angle.sin());
+        let (x, y) = point(start);
+        self = match self.p_contours.last().map(|c| !c.c_closed).unwrap_or(false) {
+            true => self.line_to(x, y),
+            false => self.move_to(x, y),
+        };
+
+        let points = &mut self.current().c_points;
+        for i in 1..=count {
+            points.push(point(start + sweep * i as f32 / count as f32));
+        }
+        self
+    }
+
+    /// Close the current contour with a line back to its start
+    pub fn close(mut self) -> Self {
+        if let Some(last) = self.p_contours.last_mut() {
+            last.c_closed = true;
+        }
+        self
+    }
+
+    /// Add a closed rectangle
+    pub fn rect(self, x: f32, y: f32, width: f32, height: f32) -> Self {
+        self.move_to(x, y)
+            .line_to(x + width, y)
+            .line_to(x + width, y + height)
+            .line_to(x, y + height)
+            .close()
+    }
+
+    /// Add a closed circle
+    pub fn circle(self, cx: f32, cy: f32, radius: f32) -> Self {
+        self.move_to(cx + radius, cy)
+            .arc(cx, cy, radius, 0.0, 2.0 * PI)
+            .close()
+    }
+
+    /// Get the area covered by the path's points
+    ///
+    /// Returns None if the path is empty.
+    fn bounds(&self) -> Option<(f32, f32, f32, f32)> {
+        let mut points = self.p_contours.iter().flat_map(|c| c.c_points.iter());
+        let first = points.next()?;
+        Some(points.fold(
+            (first.0, first.1, first.0, first.1),
+            |(x0, y0, x1, y1), p| (x0.min(p.0), y0.min(p.1), x1.max(p.0), y1.max(p.1)),
+        ))
+    }
+}
+
+/// How to decide which parts of a path are inside of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+/// A drawing operation on a canvas
+#[derive(Debug, Clone, PartialEq)]
+pub enum CanvasCommand {
+    /// Fill the inside of a path. Contours are closed automatically.
+    Fill {
+        path: CanvasPath,
+        color: dom::Color,
+        rule: FillRule,
+    },
+    /// Draw a line `width` pixels wide along a path, with round joins
+    Stroke {
+        path: CanvasPath,
+        color: dom::Color,
+        width: f32,
+    },
+}
+
+impl CanvasCommand {
+    /// The pixels this command may draw to
+    fn bounds(&self) -> Option<Rect<i32>> {
+        let (path, pad) = match self {
+            CanvasCommand::Fill { path, .. } => (path, 1.0),
+            CanvasCommand::Stroke { path, width, .. } => (path, width / 2.0 + 1.0),
+        };
+        let (x0, y0, x1, y1) = path.bounds()?;
+        let (x0, y0) = ((x0 - pad).floor() as i32, (y0 - pad).floor() as i32);
+        let (x1, y1) = ((x1 + pad).ceil() as i32, (y1 + pad).ceil() as i32);
+        Some(Rect::new(x0, y0, x1 - x0, y1 - y0))
+    }
+}
+
+/// Refers to a command added to a canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CanvasItemId(u64);
+
+struct CanvasItem {
+    ci_id: CanvasItemId,
+    ci_command: CanvasCommand,
+    /// Cached bounds of the command, or None if it is empty
+    ci_bounds: Option<Rect<i32>>,
+}
+
+/// The state of a canvas element
+pub(crate) struct Canvas {
+    cv_size: (u32, u32),
+    /// The image the commands are drawn into
+    cv_resource: DakotaId,
+    /// The commands in the order they are drawn
+    cv_items: Vec<CanvasItem>,
+    cv_next_id: u64,
+    /// Premultiplied RGBA contents
+    cv_pixels: Vec<[f32; 4]>,
+    /// The contents in the ARGB8888 format uploaded to the resource
+    pub(crate) cv_bytes: Vec<u8>,
+    /// The parts of the canvas which need to be redrawn
+    pub(crate) cv_damage: Damage,
+    /// Does the resource need to be resized
+    cv_resized: bool,
+}
+
+impl Canvas {
+    fn full_rect(&self) -> Rect<i32> {
+        Rect::new(0, 0, self.cv_size.0 as i32, self.cv_size.1 as i32)
+    }
+
+    fn add_damage(&mut self, bounds: Option<Rect<i32>>) {
+        if let Some(rect) = bounds.and_then(|b| b.intersection(&self.full_rect())) {
+            self.cv_damage.add(&rect);
+        }
+    }
+
+    fn find_item(&self, id: CanvasItemId) -> Result<usize> {
+        self.cv_items
+            .iter()
+            .position(|item| item.ci_id == id)
+            .ok_or(anyhow!("Canvas item {:?} does not exist", id))
+    }
+
+    /// Redraw the damaged parts of the canvas
+    fn rasterize(&mut self) {
+        let full = self.full_rect();
+        let width = self.cv_size.0 as usize;
+        let regions: Vec<Rect<i32>> = self
+            .cv_damage
+            .regions()
+            .filter_map(|r| r.intersection(&full))
+            .collect();
+
+        for region in regions.iter() {
+            for y in region.r_pos.1..region.r_pos.1 + region.r_size.1 {
+                let row = y as usize * width;
+                for x in region.r_pos.0..region.r_pos.0 + region.r_size.0 {
+                    self.cv_pixels[row + x as usize] = [0.0; 4];
+                }
+            }
+
+            for item in self.cv_items.iter() {
+                let hit = item
+                    .ci_bounds
+                    .as_ref()
+                    .and_then(|b| b.intersection(region))
+                    .is_some();
+                if hit {
+                    draw_command(&mut self.cv_pixels, self.cv_size, region, &item.ci_command);
+                }
+            }
+
+            for y in region.r_pos.1..region.r_pos.1 + region.r_size.1 {
+                let row = y as usize * width;
+                for x in region.r_pos.0..region.r_pos.0 + region.r_size.0 {
+                    let i = row + x as usize;
+                    let [r, g, b, a] = self.cv_pixels[i];
+                    // Thundr expects straight alpha
+                    let channel = |c: f32| match a > 0.0 {
+                        true => ((c / a).clamp(0.0, 1.0) * 255.0).round() as u8,
+                        false => 0,
+                    };
+                    self.cv_bytes[i * 4..i * 4 + 4].copy_from_slice(&[
+                        channel(b),
+                        channel(g),
+                        channel(r),
+                        (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ]);
+                }
+            }
+        }
+    }
+}
+
+/// One edge of a polygon, with y0 < y1
+struct Edge {
+    e_x0: f32,
+    e_y0: f32,
+    e_x1: f32,
+    e_y1: f32,
+    /// 1 if the edge points down, -1 if it points up
+    e_dir: i32,
+}
+
+/// Turn closed polygons into the list of their edges
+fn polygon_edges(polygons: &[Vec<(f32, f32)>]) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for poly in polygons.iter() {
+        for (i, a) in poly.iter().enumerate() {
+            let b = poly[(i + 1) % poly.len()];
+            if a.1 == b.1 {
+                continue;
+            }
+            let (top, bottom, dir) = match a.1 < b.1 {
+                true => (*a, b, 1),
+                false => (b, *a, -1),
+            };
+            edges.push(Edge {
+                e_x0: top.0,
+                e_y0: top.1,
+                e_x1: bottom.0,
+                e_y1: bottom.1,
+                e_dir: dir,
+            });
+        }
+    }
+    edges
+}
+
+/// Get the signed area of a polygon, positive if it is clockwise on
+/// screen
+fn polygon_area(poly: &[(f32, f32)]) -> f32 {
+    let mut area = 0.0;
+    for (i, a) in poly.iter().enumerate() {
+        let b = poly[(i + 1) % poly.len()];
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+    area / 2.0
+}
+
+/// Add a polygon which winds clockwise, so that overlapping ones do not
+/// cancel each other out with the nonzero rule
+fn push_clockwise(polygons: &mut Vec<Vec<(f32, f32)>>, mut poly: Vec<(f32, f32)>) {
+    if polygon_area(&poly) < 0.0 {
+        poly.reverse();
+    }
+    polygons.push(poly);
+}
+
+/// Get the polygons covered by stroking a path
+///
+/// Each line becomes a rectangle and each point a circle, which gives
+/// round joins and caps. These overlap, so they should be filled with
+/// the nonzero rule.
+fn stroke_polygons(path: &CanvasPath, width: f32) -> Vec<Vec<(f32, f32)>> {
+    let hw = width / 2.0;
+    let mut polygons = Vec::new();
+    if hw <= 0.0 {
+        return polygons;
+    }
+
+    let joint_count = circle_segments(hw);
+    let joint = |p: (f32, f32)| -> Vec<(f32, f32)> {
+        (0..joint_count)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f32 / joint_count as f32;
+                (p.0 + hw * angle.cos(), p.1 + hw * angle.sin())
+            })
+            .collect()
+    };
+
+    for contour in path.p_contours.iter() {
+        let points = &contour.c_points;
+        let mut lines: Vec<((f32, f32), (f32, f32))> =
+            points.windows(2).map(|w| (w[0], w[1])).collect();
+        if contour.c_closed && points.len() > 2 {
+            lines.push((*points.last().unwrap(), points[0]));
+        }
+
+        for (a, b) in lines.iter() {
+            let len = distance(*a, *b);
+            if len == 0.0 {
+                continue;
+            }
+            let (nx, ny) = (-(b.1 - a.1) / len * hw, (b.0 - a.0) / len * hw);
+            push_clockwise(
+                &mut polygons,
+                vec![
+                    (a.0 + nx, a.1 + ny),
+                    (b.0 + nx, b.1 + ny),
+                    (b.0 - nx, b.1 - ny),
+                    (a.0 - nx, a.1 - ny),
+                ],
+            );
+        }
+        for p in points.iter() {
+            push_clockwise(&mut polygons, joint(*p));
+        }
+    }
+
+    polygons
+}
+
+/// Add the parts of a row covered by `[x0, x1)` to `coverage`
+///
+/// `coverage` starts at pixel `start`, and `weight` is the amount of the
+/// row this span represents.
+fn add_span(coverage: &mut [f32], start: i32, x0: f32, x1: f32, weight: f32) {
+    let end = start + coverage.len() as i32;
+    let x0 = x0.max(start as f32);
+    let x1 = x1.min(end as f32);
+    if x0 >= x1 {
+        return;
+    }
+
+    let (first, last) = (x0.floor() as i32, (x1.ceil() as i32 - 1).min(end - 1));
+    for px in first..=last {
+        let covered = x1.min(px as f32 + 1.0) - x0.max(px as f32);
+        coverage[(px - start) as usize] += covered.max(0.0) * weight;
+    }
+}
+
+/// Fill polygons into the pixels within `clip`
+fn fill_polygons(
+    pixels: &mut [[f32; 4]],
+    size: (u32, u32),
+    clip: &Rect<i32>,
+    polygons: &[Vec<(f32, f32)>],
+    rule: FillRule,
+    color: &dom::Color,
+) {
+    let edges = polygon_edges(polygons);
+    let (x_start, x_end) = (clip.r_pos.0, clip.r_pos.0 + clip.r_size.0);
+    let mut coverage = vec![0.0; clip.r_size.0.max(0) as usize];
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    let weight = 1.0 / CANVAS_SUBSAMPLES as f32;
+
+    for y in clip.r_pos.1..clip.r_pos.1 + clip.r_size.1 {
+        coverage.iter_mut().for_each(|c| *c = 0.0);
+
+        for sample in 0..CANVAS_SUBSAMPLES {
+            let sy = y as f32 + (sample as f32 + 0.5) * weight;
+            crossings.clear();
+            for edge in edges.iter().filter(|e| e.e_y0 <= sy && sy < e.e_y1) {
+                let t = (sy - edge.e_y0) / (edge.e_y1 - edge.e_y0);
+                crossings.push((edge.e_x0 + t * (edge.e_x1 - edge.e_x0), edge.e_dir));
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for (i, (x, dir)) in crossings.iter().enumerate() {
+                winding += dir;
+                let inside = match rule {
+                    FillRule::NonZero => winding != 0,
+                    FillRule::EvenOdd => winding % 2 != 0,
+                };
+                if inside {
+                    if let Some((next, _)) = crossings.get(i + 1) {
+                        add_span(&mut coverage, x_start, *x, *next, weight);
+                    }
+                }
+            }
+        }
+
+        let row = y as usize * size.0 as usize;
+        for x in x_start..x_end {
+            let alpha = color.a * coverage[(x - x_start) as usize].min(1.0);
+            if alpha <= 0.0 {
+                continue;
+            }
+            // Source over, in premultiplied alpha
+            let dst = &mut pixels[row + x as usize];
+            let src = [color.r * alpha, color.g * alpha, color.b * alpha, alpha];
+            for c in 0..4 {
+                dst[c] = src[c] + dst[c] * (1.0 - alpha);
+            }
+        }
+    }
+}
+
+/// Draw one command into the pixels within `clip`
+fn draw_command(pixels: &mut [[f32; 4]], size: (u32, u32), clip: &Rect<i32>, cmd: &CanvasCommand) {
+    match cmd {
+        CanvasCommand::Fill { path, color, rule } => {
+            let polygons: Vec<Vec<(f32, f32)>> = path
+                .p_contours
+                .iter()
+                .filter(|c| c.c_points.len() > 2)
+                .map(|c| c.c_points.clone())
+                .collect();
+            fill_polygons(pixels, size, clip, &polygons, *rule, color);
+        }
+        CanvasCommand::Stroke { path, color, width } => {
+            let polygons = stroke_polygons(path, *width);
+            fill_polygons(pixels, size, clip, &polygons, FillRule::NonZero, color);
+        }
+    }
+}
+
+impl Scene {
+    /// Make an element a canvas `width` by `height` pixels in size
+    ///
+    /// The element's contents become an image holding the drawing
+    /// commands added with `canvas_draw`. If the element has no size it
+    /// is sized to the canvas, otherwise the image is stretched to fit it.
+    ///
+    /// Calling this on an existing canvas resizes it, keeping the commands
+    /// already added. The canvas is drawn when the Scene is recompiled.
+    pub fn create_canvas(&mut self, el: &DakotaId, width: u32, height: u32) -> Result<()> {
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Canvas must be at least 1x1"));
+        }
+        let len = (width * height) as usize;
+
+        if self.d_canvases.get(el).is_some() {
+            let mut canvas = self.d_canvases.get_mut(el).unwrap();
+            canvas.cv_size = (width, height);
+            canvas.cv_pixels = vec![[0.0; 4]; len];
+            canvas.cv_bytes = vec![0; len * 4];
+            canvas.cv_damage = Damage::empty();
+            let full = canvas.full_rect();
+            canvas.add_damage(Some(full));
+            canvas.cv_resized = true;
+        } else {
+            let res = self.create_resource()?;
+            self.d_resources.set(el, res.clone());
+            if self.d_widths.get(el).is_none() {
+                self.d_widths.set(el, dom::Value::Constant(width as i32));
+            }
+            if self.d_heights.get(el).is_none() {
+                self.d_heights.set(el, dom::Value::Constant(height as i32));
+            }
+
+            let mut canvas = Canvas {
+                cv_size: (width, height),
+                cv_resource: res,
+                cv_items: Vec::new(),
+                cv_next_id: 0,
+                cv_pixels: vec![[0.0; 4]; len],
+                cv_bytes: vec![0; len * 4],
+                cv_damage: Damage::empty(),
+                cv_resized: false,
+            };
+            let full = canvas.full_rect();
+            canvas.add_damage(Some(full));
+            self.d_canvases.set(el, canvas);
+        }
+
+        self.d_canvases_modified = true;
+        Ok(())
+    }
+
+    /// Stop using an element as a canvas
+    ///
+    /// This removes the canvas image from the element.
+    pub fn remove_canvas(&mut self, el: &DakotaId) {
+        if self.d_canvases.take(el).is_some() {
+            self.d_resources.take(el);
+            self.d_canvases_modified = true;
+        }
+    }
+
+    /// Run a function on an element's canvas, marking the Scene modified
+    fn with_canvas<T, F>(&mut self, el: &DakotaId, func: F) -> Result<T>
+    where
+        F: FnOnce(&mut Canvas) -> Result<T>,
+    {
+        let mut canvas = self
+            .d_canvases
+            .get_mut(el)
+            .ok_or(anyhow!("Element is not a canvas"))?;
+        let ret = func(&mut canvas)?;
+        self.d_canvases_modified = true;
+        Ok(ret)
+    }
+
+    /// Add a drawing command to a canvas
+    ///
+    /// The command is drawn over all of the canvas's existing commands.
+    /// The returned id can be used to replace or remove it later.
+    pub fn canvas_draw(&mut self, el: &DakotaId, cmd: CanvasCommand) -> Result<CanvasItemId> {
+        self.with_canvas(el, |canvas| {
+            let id = CanvasItemId(canvas.cv_next_id);
+            canvas.cv_next_id += 1;
+
+            let bounds = cmd.bounds();
+            canvas.add_damage(bounds);
+            canvas.cv_items.push(CanvasItem {
+                ci_id: id,
+                ci_command: cmd,
+                ci_bounds: bounds,
+            });
+            Ok(id)
+        })
+    }
+
+    /// Replace one of a canvas's commands, keeping its place in the
+    /// drawing order
+    pub fn canvas_replace(
+        &mut self,
+        el: &DakotaId,
+        id: CanvasItemId,
+        cmd: CanvasCommand,
+    ) -> Result<()> {
+        self.with_canvas(el, |canvas| {
+            let index = canvas.find_item(id)?;
+            let bounds = cmd.bounds();
+
+            let old = canvas.cv_items[index].ci_bounds;
+            canvas.add_damage(old);
+            canvas.add_damage(bounds);
+            canvas.cv_items[index].ci_command = cmd;
+            canvas.cv_items[index].ci_bounds = bounds;
+            Ok(())
+        })
+    }
+
+    /// Remove one of a canvas's commands
+    pub fn canvas_remove(&mut self, el: &DakotaId, id: CanvasItemId) -> Result<()> {
+        self.with_canvas(el, |canvas| {
+            let index = canvas.find_item(id)?;
+            let item = canvas.cv_items.remove(index);
+            canvas.add_damage(item.ci_bounds);
+            Ok(())
+        })
+    }
+
+    /// Remove all of a canvas's commands
+    pub fn canvas_clear(&mut self, el: &DakotaId) -> Result<()> {
+        self.with_canvas(el, |canvas| {
+            for item in std::mem::take(&mut canvas.cv_items) {
+                canvas.add_damage(item.ci_bounds);
+            }
+            Ok(())
+        })
+    }
+
+    /// Redraw a canvas and upload the damaged parts to its image
+    fn update_canvas(&mut self, canvas: &mut Canvas) -> Result<()> {
+        if canvas.cv_damage.is_empty() {
+            return Ok(());
+        }
+        canvas.rasterize();
+
+        let (width, height) = canvas.cv_size;
+        let res = canvas.cv_resource.clone();
+        // Resizing the image replaces all of its contents
+        let damage = match canvas.cv_resized {
+            true => None,
+            false => Some(std::mem::replace(&mut canvas.cv_damage, Damage::empty())),
+        };
+
+        match self.is_resource_defined(&res) {
+            true => self.update_resource_from_bits(
+                &res,
+                &canvas.cv_bytes,
+                width,
+                height,
+                0,
+                dom::Format::ARGB8888,
+                damage,
+            ),
+            false => self.define_resource_from_bits(
+                &res,
+                &canvas.cv_bytes,
+                width,
+                height,
+                0,
+                dom::Format::ARGB8888,
+            ),
+        }
+        .context("Uploading canvas contents")?;
+
+        canvas.cv_damage = Damage::empty();
+        canvas.cv_resized = false;
+        Ok(())
+    }
+
+    /// Draw the canvases in the tree rooted at `el`
+    fn update_canvases_recursive(&mut self, el: &DakotaId) -> Result<()> {
+        if let Some(mut canvas) = self.d_canvases.take(el) {
+            let ret = self.update_canvas(&mut canvas);
+            self.d_canvases.set(el, canvas);
+            ret?;
+        }
+
+        let mut children = self.d_children.get_clone(el).unwrap_or_default();
+        if let Some(content) = self.d_contents.get(el) {
+            children.push(content.el.clone());
+        }
+        for child in children.iter() {
+            self.update_canvases_recursive(child)?;
+        }
+
+        Ok(())
+    }
+
+    /// Redraw the damaged parts of every canvas in the tree rooted at
+    /// `root`
+    ///
+    /// This is called when recompiling the Scene.
+    pub(crate) fn update_canvases(&mut self, root: &DakotaId) -> Result<()> {
+        self.update_canvases_recursive(root)
+    }
+}
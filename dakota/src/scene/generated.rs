@@ -5,7 +5,7 @@ extern crate paste;
 use paste::paste;
 extern crate lluvia as ll;
 
-use crate::{dom, DakotaId, DakotaObjectType, Scene};
+use crate::{dom, DakotaId, DakotaObjectType, Scene, Transform};
 
 // ------------------------------------------------
 // Now implement some getters/setters
@@ -41,6 +41,18 @@ impl Scene {
     // If set the elements assigned this resource will be filled with the
     // color specified in this component.
     define_element_property!(resource_color, resource_color, dom::Color);
+    // Resource Gradient
+    //
+    // If set the elements assigned this resource will be filled with
+    // this gradient. A resource with only a gradient does not need to
+    // be defined. If the resource has an image then the gradient colors
+    // it, keeping the image's alpha.
+    define_element_property!(resource_gradient, resource_gradient, dom::Gradient);
+    // Resource Nine-Patch
+    //
+    // If set the image of this resource will be drawn with nine-slice
+    // scaling, keeping the borders specified at their original size.
+    define_element_property!(resource_nine_patch, resource_nine_patch, dom::NinePatch);
     // Resource assigned to an Element
     // If the DakotaId is of type Element, then we can assign another
     // DakotaId that represents a Resource which will define what Dakota
@@ -110,4 +122,45 @@ impl Scene {
     //
     // This excepts it from being clipped inside of the parent during drawing.
     define_element_property!(unbounded_subsurface, unbounded_subsurf, bool);
+    // Element Opacity
+    //
+    // Multiplied with the alpha of this Element's contents when drawing,
+    // ranging from 0.0 (invisible) to 1.0 (opaque). This also applies to
+    // all child Elements. Defaults to 1.0.
+    define_element_property!(opacity, opacities, f32);
+    // Element Tint
+    //
+    // Multiplied with the red, green and blue of this Element's contents
+    // when drawing. This also applies to all child Elements. Defaults to
+    // (1.0, 1.0, 1.0), values below that darken the Element.
+    define_element_property!(tint, tints, (f32, f32, f32));
+    // Element Transform
+    //
+    // An affine transform applied when drawing this Element and all of
+    // its children, in pixels relative to the Element's top left corner.
+    // This does not change the layout, input regions, or viewport
+    // clipping of the Element.
+    define_element_property!(transform, transforms, Transform);
+    // Element Classes
+    //
+    // The names of the styles this Element uses. Any properties not set on
+    // the Element itself are taken from these styles when the Scene is
+    // recompiled.
+    define_element_property!(classes, classes, Vec<String>);
+    // Element Role
+    //
+    // The purpose of this Element, which is reported to accessibility
+    // tools such as screen readers. Elements with text default to being
+    // labels.
+    define_element_property!(role, roles, dom::Role);
+    // Element Label
+    //
+    // The name accessibility tools will use for this Element. This
+    // defaults to the Element's text.
+    define_element_property!(label, labels, String);
+    // Element Scrollbars
+    //
+    // Show scrollbars along the edges of this viewport Element whose
+    // content overflows. The Element must also be a viewport.
+    define_element_property!(scrollbars, scrollbars, bool);
 }
@@ -0,0 +1,244 @@
+//! Data bound lists
+//!
+//! Applications with long dynamic lists (file lists, chat logs) bind a
+//! Vec of items to a list element instead of creating an element for
+//! every entry. Dakota creates a row element for each item and calls the
+//! application to fill it in.
+//!
+//! Lists are virtualized: only the rows that are visible in the list's
+//! viewport exist, so long lists don't have to be laid out. Rows that are
+//! scrolled out of view are reused for the ones scrolled into view.
+// Austin Shafer - 2024
+use crate::{dom, DakotaId, Scene};
+use utils::{Context, Result};
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The number of rows to keep on either side of the visible ones, so
+/// that small scrolls don't need new rows
+const LIST_OVERSCAN: usize = 2;
+
+/// Fills in a row element with the contents of the item at this index
+type ListRowUpdate = Box<dyn FnMut(&mut Scene, &DakotaId, usize) -> Result<()> + Send + Sync>;
+
+/// The state of a list element with bound items
+pub(crate) struct ListBinding {
+    lb_len: usize,
+    lb_row_height: i32,
+    /// This is None while it is being called
+    lb_update: Option<ListRowUpdate>,
+    /// Empty element sized to the full list, so that the list's scroll
+    /// region covers rows that don't exist
+    lb_spacer: DakotaId,
+    /// The rows currently in the list, keyed by item index
+    lb_rows: HashMap<usize, DakotaId>,
+    /// Rows that were scrolled out of view and can be reused
+    lb_free: Vec<DakotaId>,
+    /// The item indices the rows were last created for
+    lb_range: Range<usize>,
+    /// Do the existing rows need to be updated, since the items changed
+    lb_dirty: bool,
+}
+
+impl Scene {
+    /// Bind a list of items to an element
+    ///
+    /// `list` becomes a scrolling viewport, and a child row element
+    /// `row_height` pixels tall is created for each visible item. `update`
+    /// is called to fill in a row with its item's contents, and should set
+    /// all of the row's contents since rows are reused for other items as
+    /// the list is scrolled. The row's size and offset are set by Dakota.
+    ///
+    /// Calling this again replaces the items, reusing the existing rows.
+    /// Rows are created when the Scene is recompiled.
+    pub fn bind_list<T, F>(
+        &mut self,
+        list: &DakotaId,
+        items: Vec<T>,
+        row_height: u32,
+        mut update: F,
+    ) -> Result<()>
+    where
+        T: Send + Sync + 'static,
+        F: FnMut(&mut Scene, &DakotaId, &T) -> Result<()> + Send + Sync + 'static,
+    {
+        let len = items.len();
+        let update: ListRowUpdate =
+            Box::new(move |scene, row, index| update(scene, row, &items[index]));
+
+        if self.d_lists.get(list).is_some() {
+            let mut binding = self.d_lists.get_mut(list).unwrap();
+            binding.lb_len = len;
+            binding.lb_row_height = row_height as i32;
+            binding.lb_update = Some(update);
+            binding.lb_dirty = true;
+        } else {
+            let spacer = self.create_element()?;
+            self.d_widths.set(&spacer, dom::Value::Relative(1.0));
+            self.add_child_to_element(list, spacer.clone());
+            self.d_is_viewport.set(list, true);
+
+            self.d_lists.set(
+                list,
+                ListBinding {
+                    lb_len: len,
+                    lb_row_height: row_height as i32,
+                    lb_update: Some(update),
+                    lb_spacer: spacer,
+                    lb_rows: HashMap::new(),
+                    lb_free: Vec::new(),
+                    lb_range: 0..0,
+                    lb_dirty: true,
+                },
+            );
+        }
+
+        self.d_lists_modified = true;
+        Ok(())
+    }
+
+    /// Remove the items bound to a list element
+    ///
+    /// This removes all of the rows Dakota created for it.
+    pub fn unbind_list(&mut self, list: &DakotaId) -> Result<()> {
+        let binding = match self.d_lists.take(list) {
+            Some(binding) => binding,
+            None => return Ok(()),
+        };
+
+        self.remove_child_from_element(list, &binding.lb_spacer)?;
+        for row in binding.lb_rows.values() {
+            self.remove_child_from_element(list, row)?;
+        }
+        self.d_lists_modified = true;
+
+        Ok(())
+    }
+
+    /// Get the item indices which are visible in this list
+    fn get_list_visible_range(&self, list: &DakotaId, binding: &ListBinding) -> Range<usize> {
+        let row_height = binding.lb_row_height.max(1);
+
+        // Before the list has been laid out we may not know its size, in
+        // which case assume it could cover the window
+        let (scroll, height) = match self.d_viewports.get(list) {
+            Some(viewport) => (-viewport.scroll_offset.1, viewport.size.1),
+            None => match self.d_heights.get(list).map(|h| *h) {
+                Some(dom::Value::Constant(height)) => (0, height),
+                _ => (0, self.d_window_dims.1 as i32),
+            },
+        };
+
+        let start = (scroll.max(0) / row_height) as usize;
+        let end = ((scroll + height).max(0) / row_height) as usize + 1;
+
+        start.saturating_sub(LIST_OVERSCAN).min(binding.lb_len)
+            ..(end + LIST_OVERSCAN).min(binding.lb_len)
+    }
+
+    /// Check if a list needs new rows after being scrolled
+    ///
+    /// If so the Scene will need to be recompiled.
+    pub(crate) fn handle_list_scroll(&mut self, list: &DakotaId) {
+        let needs_update = match self.d_lists.get(list) {
+            Some(binding) => self.get_list_visible_range(list, &binding) != binding.lb_range,
+            None => false,
+        };
+
+        if needs_update {
+            self.d_lists_modified = true;
+        }
+    }
+
+    /// Create and update the rows of a list element
+    fn update_list(&mut self, list: &DakotaId, binding: &mut ListBinding) -> Result<()> {
+        let range = self.get_list_visible_range(list, binding);
+        let row_height = binding.lb_row_height;
+
+        self.d_heights.set(
+            &binding.lb_spacer,
+            dom::Value::Constant(row_height * binding.lb_len as i32),
+        );
+
+        // Release the rows that are no longer visible
+        let hidden: Vec<usize> = binding
+            .lb_rows
+            .keys()
+            .filter(|i| !range.contains(i))
+            .copied()
+            .collect();
+        for index in hidden {
+            let row = binding.lb_rows.remove(&index).unwrap();
+            self.remove_child_from_element(list, &row)?;
+            binding.lb_free.push(row);
+        }
+
+        let mut update = binding.lb_update.take().unwrap();
+        for index in range.clone() {
+            let row = match binding.lb_rows.get(&index) {
+                Some(row) => match binding.lb_dirty {
+                    true => row.clone(),
+                    false => continue,
+                },
+                None => {
+                    let row = match binding.lb_free.pop() {
+                        Some(row) => row,
+                        None => self.create_element()?,
+                    };
+                    self.add_child_to_element(list, row.clone());
+                    binding.lb_rows.insert(index, row.clone());
+                    row
+                }
+            };
+
+            self.d_offsets.set(
+                &row,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant(0),
+                    y: dom::Value::Constant(row_height * index as i32),
+                },
+            );
+            self.d_widths.set(&row, dom::Value::Relative(1.0));
+            self.d_heights.set(&row, dom::Value::Constant(row_height));
+
+            if let Err(e) = update(self, &row, index) {
+                binding.lb_update = Some(update);
+                return Err(e).context("Updating list row");
+            }
+        }
+
+        binding.lb_update = Some(update);
+        binding.lb_range = range;
+        binding.lb_dirty = false;
+        Ok(())
+    }
+
+    /// Update the rows of all lists in the tree rooted at `el`
+    fn update_lists_recursive(&mut self, el: &DakotaId) -> Result<()> {
+        // Take the binding out while updating, since the row callback
+        // needs the whole Scene
+        if let Some(mut binding) = self.d_lists.take(el) {
+            let ret = self.update_list(el, &mut binding);
+            self.d_lists.set(el, binding);
+            ret?;
+        }
+
+        let mut children = self.d_children.get_clone(el).unwrap_or_default();
+        if let Some(content) = self.d_contents.get(el) {
+            children.push(content.el.clone());
+        }
+        for child in children.iter() {
+            self.update_lists_recursive(child)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create the visible rows of every list in the tree rooted at `root`
+    ///
+    /// This is called when recompiling the Scene.
+    pub(crate) fn update_lists(&mut self, root: &DakotaId) -> Result<()> {
+        self.update_lists_recursive(root)
+    }
+}
@@ -5,17 +5,42 @@
 //! layout information.
 // Austin Shafer - 2024
 extern crate utils;
+use crate::access::AccessTree;
+use crate::animation::AnimationState;
+use crate::asset::{AssetLoader, AssetNotifier, AssetSource, DecodedImage};
+use crate::atlas::GlyphAtlas;
 use crate::font;
-use crate::layout::LayoutNode;
+use crate::layout::{LayoutNode, TextMeasurement, MAX_LAYOUT_THREADS};
+use crate::svg::{self, SvgResource};
 use crate::{dom, DakotaId, DakotaObjectType, SubsurfaceOrder, VirtualOutput};
-use th::{Damage, Dmabuf, Droppable};
-use utils::log;
+use th::{Damage, Dmabuf, Droppable, SamplerMode};
+use utils::region::Rect;
 use utils::{anyhow, Context, Result};
+use utils::{log, trace};
 
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 
 // Re-exmport our getters/setters
+mod accessibility;
+mod animation;
+mod canvas;
 mod generated;
+mod list;
+mod popup;
+mod scroll;
+mod style;
+mod touch;
+mod video;
+use canvas::Canvas;
+pub use canvas::{CanvasCommand, CanvasItemId, CanvasPath, FillRule};
+use list::ListBinding;
+pub(crate) use scroll::{get_scrollbars, SCROLLBAR_THUMB_COLOR, SCROLLBAR_TRACK_COLOR};
+use scroll::{Fling, ScrollDrag};
+use touch::TouchPoint;
+use video::Video;
+pub use video::{VideoFormat, VideoFrame, MAX_VIDEO_QUEUE};
 
 pub struct Scene {
     /// The default device to create resources with
@@ -25,6 +50,9 @@ pub struct Scene {
     /// This is all of the LayoutNodes in the system, each corresponding to
     /// an Element or a subcomponent of an Element. Indexed by DakotaId.
     pub(crate) d_layout_nodes: ll::Component<LayoutNode>,
+    /// The glyph layout of text elements, reused until the text or the
+    /// space it is laid out in changes
+    pub(crate) d_text_measurements: ll::Component<TextMeasurement>,
     // NOTE: --------------------------------
     //
     // If you update the following you may have to edit the generated
@@ -40,6 +68,22 @@ pub struct Scene {
     pub d_resource_thundr_image: ll::Component<th::Image>,
     /// Color to pass to Thundr for this resource
     pub d_resource_color: ll::Component<dom::Color>,
+    /// Gradient to fill this resource with
+    pub d_resource_gradient: ll::Component<dom::Gradient>,
+    /// Borders to keep at a fixed size when stretching this resource's image
+    pub d_resource_nine_patch: ll::Component<dom::NinePatch>,
+    /// The parts of this resource's image that have changed since the
+    /// last frame, in image pixels
+    pub(crate) d_resource_damage: ll::Component<Damage>,
+    /// The SVG document this resource is rasterized from
+    pub(crate) d_resource_svg: ll::Component<SvgResource>,
+    /// The frames of an animated image and which one is shown
+    pub(crate) d_resource_animation: ll::Component<AnimationState>,
+    /// Animated resources used by the elements in the scene, found
+    /// during recompile
+    pub(crate) d_animated: Vec<DakotaId>,
+    /// Decodes image files for resources in the background
+    pub(crate) d_assets: AssetLoader,
 
     // Element components
     // --------------------------------------------
@@ -51,12 +95,20 @@ pub struct Scene {
     pub d_fonts: ll::Component<dom::Font>,
     pub d_texts: ll::Component<dom::Text>,
     pub d_glyphs: ll::Component<font::Glyph>,
+    /// The shared image glyphs are drawn from
+    pub(crate) d_glyph_atlas: GlyphAtlas,
     /// points to an id with font instance
     pub d_text_font: ll::Component<DakotaId>,
     pub d_contents: ll::Component<dom::Content>,
     pub d_bounds: ll::Component<dom::Edges>,
     pub d_children: ll::Component<Vec<DakotaId>>,
     pub d_unbounded_subsurf: ll::Component<bool>,
+    /// Opacity multiplier for this element and its children
+    pub d_opacities: ll::Component<f32>,
+    /// Color multiplier for this element and its children
+    pub d_tints: ll::Component<(f32, f32, f32)>,
+    /// Transform applied when drawing this element and its children
+    pub d_transforms: ll::Component<th::Transform>,
     /// Is this element a viewport node. If so it will have a viewport
     /// boundary and scroll the content inside of it.
     pub d_is_viewport: ll::Component<bool>,
@@ -65,6 +117,68 @@ pub struct Scene {
     /// If this is a viewport boundary then this will be populated to
     /// control draw clipping
     pub d_viewports: ll::Component<th::Viewport>,
+    /// Should this viewport show scrollbars when its content overflows
+    pub d_scrollbars: ll::Component<bool>,
+    /// Should this element be hidden from screenshots and screencasts
+    pub d_protected: ll::Component<bool>,
+    /// The names of the styles this element uses
+    pub d_classes: ll::Component<Vec<String>>,
+    /// The style properties that were last applied to this element. This
+    /// lets us tell them apart from properties the element set itself.
+    pub(crate) d_applied_styles: ll::Component<dom::Style>,
+    /// Items bound to this list element
+    pub(crate) d_lists: ll::Component<ListBinding>,
+    /// Drawing commands of canvas elements
+    pub(crate) d_canvases: ll::Component<Canvas>,
+    /// Frames and playback state of video elements
+    pub(crate) d_videos: ll::Component<Video>,
+    /// Video elements in the scene, found during recompile
+    pub(crate) d_video_elements: Vec<DakotaId>,
+    /// The purpose of this element, for accessibility tools
+    pub d_roles: ll::Component<dom::Role>,
+    /// The name accessibility tools should use for this element
+    pub d_labels: ll::Component<String>,
+
+    // Style definitions
+    // --------------------------------------------
+    /// Styles that elements may use, keyed by name
+    pub(crate) d_styles: HashMap<String, dom::Style>,
+    /// Have styles been changed since we last recompiled
+    pub(crate) d_styles_modified: bool,
+    /// Have any lists been bound or scrolled to rows that don't exist yet
+    pub(crate) d_lists_modified: bool,
+    /// Have any canvases been drawn to since we last recompiled
+    pub(crate) d_canvases_modified: bool,
+
+    // Accessibility
+    // --------------------------------------------
+    /// The element with keyboard focus
+    pub(crate) d_focus: Option<DakotaId>,
+    /// The accessibility tree built during the last recompile
+    pub(crate) d_access: AccessTree,
+    /// Does the accessibility tree need to be rebuilt
+    pub(crate) d_access_modified: bool,
+
+    // Popups
+    // --------------------------------------------
+    /// The popups being shown, from bottom to top
+    pub(crate) d_popups: Vec<(DakotaId, dom::Popup)>,
+    /// Have popups been shown or hidden since we last recompiled
+    pub(crate) d_popups_modified: bool,
+
+    // Scrolling
+    // --------------------------------------------
+    /// The scrollbar being dragged
+    pub(crate) d_scroll_drag: Option<ScrollDrag>,
+    /// Recent finger scrolling motion, used to find fling velocity
+    pub(crate) d_finger_samples: Vec<(std::time::Instant, (i32, i32))>,
+    /// The viewport scrolling after a fling
+    pub(crate) d_fling: Option<Fling>,
+
+    // Touch
+    // --------------------------------------------
+    /// The fingers on the touchscreen, indexed by touch id
+    pub(crate) d_touch_points: HashMap<i32, TouchPoint>,
 
     // DOM components
     // --------------------------------------------
@@ -85,6 +199,15 @@ pub struct Scene {
     /// since it is not threadsafe. This associates a Font with the corresponding
     /// instance containing the shaping information.
     pub d_font_instances: Vec<(dom::Font, font::FontInstance)>,
+    /// The subpixel layout text is rasterized for
+    pub(crate) d_subpixel: th::SubpixelLayout,
+    /// Fonts are rasterized at their pixel size multiplied by this
+    pub(crate) d_text_scale: f32,
+    /// Should every element be laid out again during the next recompile,
+    /// instead of only the modified ones
+    pub(crate) d_relayout_all: bool,
+    /// The most threads to lay out independent subtrees on
+    pub(crate) d_layout_threads: usize,
 }
 
 macro_rules! create_component_and_table {
@@ -97,6 +220,7 @@ impl Scene {
     pub(crate) fn new(dev: Arc<th::Device>, resolution: (u32, u32)) -> Result<Self> {
         let mut layout_ecs = ll::Instance::new();
         create_component_and_table!(layout_ecs, LayoutNode, layout_table);
+        create_component_and_table!(layout_ecs, TextMeasurement, text_measurements_table);
         create_component_and_table!(layout_ecs, DakotaObjectType, types_table);
         create_component_and_table!(layout_ecs, DakotaId, resources_table);
         create_component_and_table!(layout_ecs, dom::RelativeOffset, offsets_table);
@@ -110,13 +234,32 @@ impl Scene {
         create_component_and_table!(layout_ecs, dom::Edges, bounds_table);
         create_component_and_table!(layout_ecs, Vec<DakotaId>, children_table);
         create_component_and_table!(layout_ecs, bool, unbounded_subsurf_table);
+        create_component_and_table!(layout_ecs, f32, opacities_table);
+        create_component_and_table!(layout_ecs, (f32, f32, f32), tints_table);
+        create_component_and_table!(layout_ecs, th::Transform, transforms_table);
         create_component_and_table!(layout_ecs, th::Viewport, viewports_table);
         create_component_and_table!(layout_ecs, bool, is_viewports_table);
+        create_component_and_table!(layout_ecs, bool, scrollbars_table);
+        create_component_and_table!(layout_ecs, bool, protected_table);
+        create_component_and_table!(layout_ecs, Vec<String>, classes_table);
+        create_component_and_table!(layout_ecs, dom::Style, applied_styles_table);
+        create_component_and_table!(layout_ecs, ListBinding, lists_table);
+        create_component_and_table!(layout_ecs, Canvas, canvases_table);
+        create_component_and_table!(layout_ecs, Video, videos_table);
+        create_component_and_table!(layout_ecs, dom::Role, roles_table);
+        create_component_and_table!(layout_ecs, String, labels_table);
 
         let mut resource_ecs = ll::Instance::new();
         create_component_and_table!(resource_ecs, dom::Hints, resource_hints_table);
         create_component_and_table!(resource_ecs, th::Image, resource_thundr_image_table);
         create_component_and_table!(resource_ecs, dom::Color, resource_color_table);
+        create_component_and_table!(resource_ecs, dom::Gradient, resource_gradient_table);
+        create_component_and_table!(resource_ecs, dom::NinePatch, resource_nine_patch_table);
+        create_component_and_table!(resource_ecs, Damage, resource_damage_table);
+        create_component_and_table!(resource_ecs, SvgResource, resource_svg_table);
+        create_component_and_table!(resource_ecs, AnimationState, resource_animation_table);
+
+        let glyph_atlas = GlyphAtlas::new(&mut layout_ecs);
 
         // Create a default Font instance
         let default_inst = layout_ecs.add_entity();
@@ -127,8 +270,16 @@ impl Scene {
             d_resource_hints: resource_hints_table,
             d_resource_thundr_image: resource_thundr_image_table,
             d_resource_color: resource_color_table,
+            d_resource_gradient: resource_gradient_table,
+            d_resource_nine_patch: resource_nine_patch_table,
+            d_resource_damage: resource_damage_table,
+            d_resource_svg: resource_svg_table,
+            d_resource_animation: resource_animation_table,
+            d_animated: Vec::new(),
+            d_assets: AssetLoader::new(),
             d_ecs_inst: layout_ecs,
             d_layout_nodes: layout_table,
+            d_text_measurements: text_measurements_table,
             d_node_types: types_table,
             d_resources: resources_table,
             d_offsets: offsets_table,
@@ -138,13 +289,40 @@ impl Scene {
             d_texts: texts_table,
             d_text_font: text_font_table,
             d_glyphs: glyph_table,
+            d_glyph_atlas: glyph_atlas,
             d_contents: content_table,
             d_bounds: bounds_table,
             d_children: children_table,
             d_dom: None,
             d_unbounded_subsurf: unbounded_subsurf_table,
+            d_opacities: opacities_table,
+            d_tints: tints_table,
+            d_transforms: transforms_table,
             d_is_viewport: is_viewports_table,
             d_viewports: viewports_table,
+            d_scrollbars: scrollbars_table,
+            d_protected: protected_table,
+            d_classes: classes_table,
+            d_applied_styles: applied_styles_table,
+            d_lists: lists_table,
+            d_canvases: canvases_table,
+            d_videos: videos_table,
+            d_video_elements: Vec::new(),
+            d_roles: roles_table,
+            d_labels: labels_table,
+            d_styles: HashMap::new(),
+            d_styles_modified: false,
+            d_lists_modified: false,
+            d_canvases_modified: false,
+            d_focus: None,
+            d_access: AccessTree::new(),
+            d_access_modified: false,
+            d_popups: Vec::new(),
+            d_popups_modified: false,
+            d_scroll_drag: None,
+            d_finger_samples: Vec::new(),
+            d_fling: None,
+            d_touch_points: HashMap::new(),
             d_layout_tree_root: None,
             d_window_dims: resolution,
             d_default_font_inst: default_inst.clone(),
@@ -152,8 +330,36 @@ impl Scene {
             d_fontconfig: fc::Fontconfig::new()
                 .context(anyhow!("Could not initialize fontconfig"))?,
             d_font_instances: Vec::new(),
+            d_subpixel: th::SubpixelLayout::None,
+            d_text_scale: 1.0,
+            d_relayout_all: true,
+            d_layout_threads: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+                .min(MAX_LAYOUT_THREADS),
         };
 
+        // Record which elements change so that only they are laid out again
+        ret.d_resources.track_modified_entities();
+        ret.d_offsets.track_modified_entities();
+        ret.d_widths.track_modified_entities();
+        ret.d_heights.track_modified_entities();
+        ret.d_fonts.track_modified_entities();
+        ret.d_texts.track_modified_entities();
+        ret.d_text_font.track_modified_entities();
+        ret.d_contents.track_modified_entities();
+        ret.d_children.track_modified_entities();
+        ret.d_is_viewport.track_modified_entities();
+        ret.d_resource_thundr_image.track_modified_entities();
+
+        // Subpixel rendering needs an LCD filter to reduce color fringes
+        if let Err(e) = ret
+            .d_freetype
+            .set_lcd_filter(ft::LcdFilter::LcdFilterDefault)
+        {
+            log::error!("Could not set freetype LCD filter: {:?}", e);
+        }
+
         // Define our default font
         ret.d_node_types.set(&default_inst, DakotaObjectType::Font);
         ret.define_font(
@@ -161,6 +367,7 @@ impl Scene {
             dom::Font {
                 name: "Default".to_string(),
                 font_name: "JetBrainsMono".to_string(),
+                style: None,
                 pixel_size: 16,
                 color: None,
             },
@@ -191,6 +398,8 @@ impl Scene {
             || self.d_resource_hints.is_modified()
             || self.d_resource_thundr_image.is_modified()
             || self.d_resource_color.is_modified()
+            || self.d_resource_gradient.is_modified()
+            || self.d_resource_nine_patch.is_modified()
             || self.d_resources.is_modified()
             || self.d_offsets.is_modified()
             || self.d_widths.is_modified()
@@ -202,6 +411,20 @@ impl Scene {
             || self.d_bounds.is_modified()
             || self.d_children.is_modified()
             || self.d_unbounded_subsurf.is_modified()
+            || self.d_opacities.is_modified()
+            || self.d_tints.is_modified()
+            || self.d_transforms.is_modified()
+            || self.d_scrollbars.is_modified()
+            || self.d_protected.is_modified()
+            || self.d_classes.is_modified()
+            || self.d_styles_modified
+            || self.d_lists_modified
+            || self.d_canvases_modified
+            || self.d_roles.is_modified()
+            || self.d_labels.is_modified()
+            || self.d_access_modified
+            || self.d_popups_modified
+            || self.d_glyphs.is_modified()
     }
 
     fn clear_needs_refresh(&mut self) {
@@ -209,6 +432,8 @@ impl Scene {
         self.d_resource_hints.clear_modified();
         self.d_resource_thundr_image.clear_modified();
         self.d_resource_color.clear_modified();
+        self.d_resource_gradient.clear_modified();
+        self.d_resource_nine_patch.clear_modified();
         self.d_resources.clear_modified();
         self.d_offsets.clear_modified();
         self.d_widths.clear_modified();
@@ -220,6 +445,20 @@ impl Scene {
         self.d_bounds.clear_modified();
         self.d_children.clear_modified();
         self.d_unbounded_subsurf.clear_modified();
+        self.d_opacities.clear_modified();
+        self.d_tints.clear_modified();
+        self.d_transforms.clear_modified();
+        self.d_scrollbars.clear_modified();
+        self.d_protected.clear_modified();
+        self.d_classes.clear_modified();
+        self.d_styles_modified = false;
+        self.d_lists_modified = false;
+        self.d_canvases_modified = false;
+        self.d_roles.clear_modified();
+        self.d_labels.clear_modified();
+        self.d_access_modified = false;
+        self.d_popups_modified = false;
+        self.d_glyphs.clear_modified();
     }
 
     /// Create a new Dakota Id
@@ -271,6 +510,7 @@ impl Scene {
             dom::Text {
                 items: vec![dom::TextItem::p(dom::TextRun {
                     value: text.to_owned(),
+                    font: None,
                     cache: None,
                 })],
             },
@@ -283,42 +523,104 @@ impl Scene {
     }
 
     pub(crate) fn define_resource_from_image_internal(
-        dev: &th::Device,
+        assets: &mut AssetLoader,
         resource_thundr_image: &mut ll::Snapshot<th::Image>,
         resource_color: &ll::Snapshot<dom::Color>,
+        resource_svg: &mut ll::Snapshot<SvgResource>,
         res: &DakotaId,
         file_path: &std::path::Path,
         format: dom::Format,
     ) -> Result<()> {
-        if Self::is_resource_defined_internal(resource_thundr_image, resource_color, res) {
+        if format != dom::Format::ARGB8888 {
+            return Err(anyhow!("Invalid image format"));
+        }
+
+        if Self::is_resource_defined_internal(resource_thundr_image, resource_color, res)
+            || assets.is_pending(res)
+        {
             return Err(anyhow!("Cannot redefine Resource contents"));
         }
 
-        // Create an in-memory representation of the image contents
-        let resolution = image::image_dimensions(file_path)
+        let key = AssetLoader::cache_key(file_path);
+        if svg::is_svg(file_path) {
+            return Self::define_resource_from_svg_internal(
+                assets,
+                resource_thundr_image,
+                resource_svg,
+                res,
+                file_path,
+                key,
+            );
+        }
+
+        // If this file has already been loaded then share its image
+        if let Some(image) = assets.get_cached(&key) {
+            resource_thundr_image.set(res, image);
+            return Ok(());
+        }
+
+        // Reading the header is cheap, so check that we will be able to
+        // decode this file now instead of failing on a worker thread
+        image::image_dimensions(file_path)
             .context("Format of image could not be guessed correctly. Could not get resolution")?;
-        let img = image::open(file_path)
-            .context("Could not open image path")?
-            .to_bgra8();
-        let pixels: Vec<u8> = img.into_vec();
-
-        Self::define_resource_from_bits_internal(
-            dev,
-            resource_thundr_image,
-            resource_color,
+
+        assets.request(res, key, AssetSource::File)
+    }
+
+    /// Define a resource from an SVG file
+    ///
+    /// The first raster is drawn at the document's own size. Once the
+    /// resource is laid out `update_svg_sizes` will draw it again at the
+    /// size it is displayed at.
+    fn define_resource_from_svg_internal(
+        assets: &mut AssetLoader,
+        resource_thundr_image: &mut ll::Snapshot<th::Image>,
+        resource_svg: &mut ll::Snapshot<SvgResource>,
+        res: &DakotaId,
+        file_path: &std::path::Path,
+        key: std::path::PathBuf,
+    ) -> Result<()> {
+        // Parse the document now so that invalid files are reported here
+        // instead of failing on a worker thread
+        let data = std::fs::read(file_path).context(anyhow!("Could not read {:?}", file_path))?;
+        let size = svg::svg_size(&data)?;
+        let data = Arc::new(data);
+
+        let raster_key = svg::svg_cache_key(&key, size);
+        match assets.get_cached(&raster_key) {
+            Some(image) => resource_thundr_image.set(res, image),
+            None => assets.request(res, raster_key, AssetSource::Svg(data.clone(), size))?,
+        }
+
+        resource_svg.set(
             res,
-            pixels.as_slice(),
-            resolution.0,
-            resolution.1,
-            0,
-            format,
-        )
+            SvgResource {
+                sr_path: key,
+                sr_data: data,
+                sr_size: size,
+            },
+        );
+        Ok(())
     }
 
     /// Define a resource's contents given a PNG image
     ///
     /// This will look up and open the image at `file_path`, and populate
     /// the resource `res`'s contents from it.
+    ///
+    /// Files ending in `.svg` or `.svgz` are rasterized instead of decoded.
+    /// They are drawn again at the size of the elements using them when
+    /// the scene is recompiled, so that they stay sharp when scaled.
+    ///
+    /// Animated GIF and APNG files are played while they are shown, see
+    /// `update_animations`.
+    ///
+    /// The image is decoded in the background, and the resource will not
+    /// have any contents until it finishes. Once it has been decoded the
+    /// `AssetLoaded` event is sent to the Output this scene was created
+    /// from, and `process_loaded_assets` or `recompile` should be called to
+    /// apply it. Files which have already been loaded are shared and
+    /// defined immediately.
     pub fn define_resource_from_image(
         &mut self,
         res: &DakotaId,
@@ -327,19 +629,211 @@ impl Scene {
     ) -> Result<()> {
         let mut images = self.d_resource_thundr_image.snapshot();
         let mut colors = self.d_resource_color.snapshot();
-        let res = Self::define_resource_from_image_internal(
-            &mut self.d_dev,
+        let mut svgs = self.d_resource_svg.snapshot();
+        let ret = Self::define_resource_from_image_internal(
+            &mut self.d_assets,
             &mut images,
             &colors,
+            &mut svgs,
             res,
             file_path,
             format,
         );
         images.precommit();
         colors.precommit();
+        svgs.precommit();
         images.commit();
         colors.commit();
-        res
+        svgs.commit();
+        if ret.is_ok() {
+            self.add_resource_damage(res, None);
+        }
+        ret
+    }
+
+    /// Upload a decoded image and assign it to the resources waiting on it
+    fn apply_decoded_image(&mut self, decoded: DecodedImage, ids: Vec<DakotaId>) -> Result<()> {
+        let (width, height, pixels) = decoded
+            .di_result
+            .context(anyhow!("Could not load image {:?}", decoded.di_path))?;
+
+        // Every resource plays its own copy of an animation, so that they
+        // can be started at different times
+        if let Some(animation) = decoded.di_animation {
+            let now = Instant::now();
+            for res in ids.iter() {
+                let image = self
+                    .d_dev
+                    .create_image_from_bits(
+                        pixels.as_slice(),
+                        width,
+                        height,
+                        0,
+                        th::ShmFormat::ARGB8888,
+                        None,
+                    )
+                    .context("Could not create Image resources")?;
+                self.d_resource_thundr_image.set(res, image);
+                self.d_resource_animation
+                    .set(res, AnimationState::new(animation.clone(), now));
+                self.add_resource_damage(res, None);
+            }
+            return Ok(());
+        }
+
+        let image = self
+            .d_dev
+            .create_image_from_bits(
+                pixels.as_slice(),
+                width,
+                height,
+                0,
+                th::ShmFormat::ARGB8888,
+                None,
+            )
+            .context("Could not create Image resources")?;
+
+        for res in ids.iter() {
+            self.d_resource_thundr_image.set(res, image.clone());
+            self.add_resource_damage(res, None);
+        }
+        self.d_assets.add_to_cache(decoded.di_path, image);
+
+        Ok(())
+    }
+
+    /// Apply any images which have finished loading
+    ///
+    /// This does not wait for images which are still being decoded.
+    /// Returns true if any resources were updated, in which case the scene
+    /// needs to be recompiled.
+    pub fn process_loaded_assets(&mut self) -> Result<bool> {
+        let mut updated = false;
+
+        while let Some((decoded, ids)) = self.d_assets.next_decoded(false) {
+            self.apply_decoded_image(decoded, ids)?;
+            updated = true;
+        }
+
+        Ok(updated)
+    }
+
+    /// Wait for all images to finish loading and apply them
+    ///
+    /// This is useful for applications which need their scene to be
+    /// complete before it is first presented.
+    pub fn wait_for_assets(&mut self) -> Result<()> {
+        while let Some((decoded, ids)) = self.d_assets.next_decoded(true) {
+            self.apply_decoded_image(decoded, ids)?;
+        }
+
+        Ok(())
+    }
+
+    /// Register a callback for when an image finishes decoding
+    ///
+    /// This is called from a worker thread.
+    pub(crate) fn add_asset_notifier(&self, notifier: AssetNotifier) {
+        self.d_assets.add_notifier(notifier);
+    }
+
+    /// Drop our cache of loaded images
+    ///
+    /// Resources using these images keep them alive, but new resources
+    /// defined from the same files will load them again. This can be
+    /// used to free memory when the GPU is running low.
+    pub fn clear_asset_cache(&mut self) {
+        self.d_assets.clear_cache();
+    }
+
+    /// Find the largest size each SVG resource is laid out at
+    fn get_svg_sizes(&self, el: &DakotaId, sizes: &mut Vec<(DakotaId, (u32, u32))>) {
+        if let Some(res) = self.d_resources.get_clone(el) {
+            if let (true, Some(node)) = (
+                self.d_resource_svg.get(&res).is_some(),
+                self.d_layout_nodes.get(el),
+            ) {
+                let size = (
+                    node.l_size.width.max(0) as u32,
+                    node.l_size.height.max(0) as u32,
+                );
+                match sizes.iter_mut().find(|(id, _)| *id == res) {
+                    Some((_, max)) => *max = (max.0.max(size.0), max.1.max(size.1)),
+                    None => sizes.push((res, size)),
+                }
+            }
+        }
+
+        let mut children = self.d_children.get_clone(el).unwrap_or_default();
+        if let Some(content) = self.d_contents.get(el) {
+            children.push(content.el.clone());
+        }
+        for child in children.iter() {
+            self.get_svg_sizes(child, sizes);
+        }
+    }
+
+    /// Rasterize SVG resources again if their elements were scaled
+    ///
+    /// Each resource is drawn at the largest size it is displayed at. To
+    /// avoid drawing icons during every step of a resize, this waits until
+    /// the size differs from the current raster by `SVG_RESIZE_THRESHOLD`.
+    /// The old raster is used until the new one is ready.
+    pub(crate) fn update_svg_sizes(&mut self, root: &DakotaId) -> Result<()> {
+        let mut sizes = Vec::new();
+        self.get_svg_sizes(root, &mut sizes);
+        for popup in self.get_popups() {
+            self.get_svg_sizes(&popup, &mut sizes);
+        }
+
+        for (res, size) in sizes.drain(..) {
+            let size = svg::clamp_svg_size(size);
+            if self.d_assets.is_pending(&res) {
+                continue;
+            }
+            let mut svg = match self.d_resource_svg.get_clone(&res) {
+                Some(svg) => svg,
+                None => continue,
+            };
+            if !svg::svg_needs_resize(svg.sr_size, size) {
+                continue;
+            }
+
+            let key = svg::svg_cache_key(&svg.sr_path, size);
+            match self.d_assets.get_cached(&key) {
+                Some(image) => {
+                    self.d_resource_thundr_image.set(&res, image);
+                    self.add_resource_damage(&res, None);
+                }
+                None => {
+                    self.d_assets
+                        .request(&res, key, AssetSource::Svg(svg.sr_data.clone(), size))?
+                }
+            }
+
+            svg.sr_size = size;
+            self.d_resource_svg.set(&res, svg);
+        }
+
+        Ok(())
+    }
+
+    /// Move this Scene to a new device after the GPU was lost
+    ///
+    /// Every image belongs to the old device, so all image resources are
+    /// left undefined. The app must define them again, which can be
+    /// checked with `is_resource_defined`. Resources defined with a color
+    /// are kept. Text is uploaded again the next time the Scene is
+    /// recompiled.
+    pub(crate) fn replace_device(&mut self, dev: Arc<th::Device>) {
+        self.d_dev = dev;
+        self.d_resource_thundr_image.clear();
+        self.d_resource_damage.clear();
+        self.d_resource_svg.clear();
+        self.d_resource_animation.clear();
+        self.d_animated.clear();
+        self.d_assets.clear_cache();
+        self.d_glyph_atlas.drop_image();
     }
 
     /// Has this Resource been defined
@@ -372,7 +866,8 @@ impl Scene {
     /// specify the layout of memory within `data`, a stride of zero implies that
     /// pixels are tightly packed.
     ///
-    /// A stride of zero implies the pixels are tightly packed.
+    /// A stride of zero implies the pixels are tightly packed. Returns an
+    /// error if `format` is not in `Output::get_supported_shm_formats`.
     pub fn define_resource_from_bits(
         &mut self,
         res: &DakotaId,
@@ -384,7 +879,7 @@ impl Scene {
     ) -> Result<()> {
         let mut images = &mut self.d_resource_thundr_image.snapshot();
         let mut colors = self.d_resource_color.snapshot();
-        let res = Self::define_resource_from_bits_internal(
+        let ret = Self::define_resource_from_bits_internal(
             &self.d_dev,
             &mut images,
             &colors,
@@ -399,7 +894,10 @@ impl Scene {
         colors.precommit();
         images.commit();
         colors.commit();
-        res
+        if ret.is_ok() {
+            self.add_resource_damage(res, None);
+        }
+        ret
     }
 
     fn define_resource_from_bits_internal(
@@ -413,17 +911,13 @@ impl Scene {
         stride: u32, // TODO: Handle stride properly
         format: dom::Format,
     ) -> Result<()> {
-        if format != dom::Format::ARGB8888 {
-            return Err(anyhow!("Invalid image format"));
-        }
-
         if Self::is_resource_defined_internal(resource_thundr_image, resource_color, res) {
             return Err(anyhow!("Cannot redefine Resource contents"));
         }
 
         // create a thundr image for each resource
         let image = dev
-            .create_image_from_bits(data, width, height, stride, None)
+            .create_image_from_bits(data, width, height, stride, format, None)
             .context("Could not create Image resources")?;
 
         resource_thundr_image.set(res, image);
@@ -446,21 +940,59 @@ impl Scene {
         format: dom::Format,
         damage: Option<Damage>,
     ) -> Result<()> {
-        if !(format == dom::Format::ARGB8888 || format == dom::Format::XRGB8888) {
-            return Err(anyhow!("Invalid image format"));
-        }
+        self.add_resource_damage(res, damage.as_ref());
 
         let image = self.d_resource_thundr_image.get_mut(res).ok_or(anyhow!(
             "Resource does not have a internal GPU resource defined"
         ))?;
 
         self.d_dev
-            .update_image_from_bits(&image, data, width, height, stride, damage, None)
+            .update_image_from_bits(&image, data, width, height, stride, format, damage, None)
             .context("Could not update image with damaged region")?;
 
         Ok(())
     }
 
+    /// Populate a resource which samples CPU memory in place
+    ///
+    /// This is the same as `define_resource_from_bits` except the contents
+    /// are not copied, the GPU reads `data` directly whenever it draws this
+    /// resource. Not all devices or buffer layouts support this, and if
+    /// an error is returned `define_resource_from_bits` should be used.
+    ///
+    /// # Safety
+    ///
+    /// The pages holding `data` must stay mapped until `release_info` is
+    /// dropped, which happens once the GPU is done with this resource.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn define_resource_from_host_memory(
+        &mut self,
+        res: &DakotaId,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: dom::Format,
+        release_info: Option<Box<dyn Droppable + Send + Sync>>,
+    ) -> Result<()> {
+        if Self::is_resource_defined_internal(
+            &self.d_resource_thundr_image.snapshot(),
+            &self.d_resource_color.snapshot(),
+            res,
+        ) {
+            return Err(anyhow!("Cannot redefine Resource contents"));
+        }
+
+        let image = self
+            .d_dev
+            .create_image_from_host_memory(data, width, height, stride, format, release_info)
+            .context("Could not import host memory")?;
+
+        self.d_resource_thundr_image.set(res, image);
+        self.add_resource_damage(res, None);
+        Ok(())
+    }
+
     /// Populate a resource by importing a dmabuf
     ///
     /// This allows for loading the `fd` specified into Dakota's internal
@@ -486,9 +1018,134 @@ impl Scene {
             .context("Could not create Image resources")?;
 
         self.d_resource_thundr_image.set(res, image);
+        self.add_resource_damage(res, None);
+        Ok(())
+    }
+
+    /// Populate a resource with a scaled down copy of another resource
+    ///
+    /// The copy is `width` by `height` and is made on the GPU from the
+    /// current contents of `src_res`. It does not follow later changes to
+    /// `src_res`, `update_scaled_copy` must be called to refresh it. This
+    /// fails if `src_res` is a color or samples host memory, or if the
+    /// device can't scale it.
+    pub fn define_resource_as_scaled_copy(
+        &mut self,
+        res: &DakotaId,
+        src_res: &DakotaId,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        if Self::is_resource_defined_internal(
+            &self.d_resource_thundr_image.snapshot(),
+            &self.d_resource_color.snapshot(),
+            res,
+        ) {
+            return Err(anyhow!("Cannot redefine Resource contents"));
+        }
+
+        let image = {
+            let src = self
+                .d_resource_thundr_image
+                .get(src_res)
+                .ok_or(anyhow!("Source resource does not have a GPU image"))?;
+            self.d_dev
+                .create_scaled_copy(&src, width, height)
+                .context("Could not create scaled copy of resource")?
+        };
+
+        self.d_resource_thundr_image.set(res, image);
+        self.add_resource_damage(res, None);
+        Ok(())
+    }
+
+    /// Refresh a resource made with `define_resource_as_scaled_copy`
+    ///
+    /// This scales the current contents of `src_res` into `res`.
+    pub fn update_scaled_copy(&mut self, res: &DakotaId, src_res: &DakotaId) -> Result<()> {
+        {
+            let src = self
+                .d_resource_thundr_image
+                .get(src_res)
+                .ok_or(anyhow!("Source resource does not have a GPU image"))?;
+            let image = self
+                .d_resource_thundr_image
+                .get(res)
+                .ok_or(anyhow!("Resource is not a scaled copy"))?;
+            self.d_dev
+                .update_scaled_copy(&src, &image)
+                .context("Could not update scaled copy of resource")?;
+        }
+
+        self.add_resource_damage(res, None);
+        Ok(())
+    }
+
+    /// Choose how a resource's image is sampled
+    ///
+    /// `SamplerMode::Trilinear` keeps a mip chain for the image, which
+    /// should be used while it is drawn much smaller than its size. The
+    /// chain is built from the current contents right away. Resources
+    /// without an image are left as is.
+    pub fn set_resource_sampler_mode(&mut self, res: &DakotaId, mode: SamplerMode) -> Result<()> {
+        {
+            let image = match self.d_resource_thundr_image.get(res) {
+                Some(image) => image,
+                None => return Ok(()),
+            };
+            if image.get_sampler_mode() == mode {
+                return Ok(());
+            }
+            self.d_dev
+                .set_image_sampler_mode(&image, mode)
+                .context("Could not change the sampler mode of resource")?;
+        }
+
+        self.add_resource_damage(res, None);
+        Ok(())
+    }
+
+    /// Populate a resource with a solid color
+    ///
+    /// No GPU image is allocated for this resource. Elements using it
+    /// are drawn as a quad of this color, which is much cheaper than
+    /// sampling an image of the same color.
+    pub fn define_resource_from_color(&mut self, res: &DakotaId, color: dom::Color) -> Result<()> {
+        if Self::is_resource_defined_internal(
+            &self.d_resource_thundr_image.snapshot(),
+            &self.d_resource_color.snapshot(),
+            res,
+        ) {
+            return Err(anyhow!("Cannot redefine Resource contents"));
+        }
+
+        self.d_resource_color.set(res, color);
         Ok(())
     }
 
+    /// Record that part of a resource's contents changed
+    ///
+    /// This is used to find what needs to be redrawn in the next frame.
+    /// `damage` is in image pixels. If it is None then the entire image
+    /// changed.
+    pub(crate) fn add_resource_damage(&self, res: &DakotaId, damage: Option<&Damage>) {
+        let damage = match damage {
+            Some(damage) => damage.clone(),
+            None => match self.d_resource_thundr_image.get(res) {
+                Some(image) => {
+                    let (width, height) = image.get_size();
+                    Damage::new(vec![Rect::new(0, 0, width as i32, height as i32)])
+                }
+                None => return,
+            },
+        };
+
+        match self.d_resource_damage.get_mut(res) {
+            Some(mut existing) => existing.union(&damage),
+            None => self.d_resource_damage.set(res, damage),
+        }
+    }
+
     /// Create a new Dakota Font object
     ///
     /// This creates a new id representing the requested font.
@@ -503,25 +1160,48 @@ impl Scene {
         return res;
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn define_font_internal(
         font_instances: &mut Vec<(dom::Font, font::FontInstance)>,
         fonts: &mut ll::Snapshot<dom::Font>,
+        glyphs: &mut ll::Snapshot<font::Glyph>,
         freetype: &ft::Library,
         fontconfig: &fc::Fontconfig,
+        subpixel: th::SubpixelLayout,
+        text_scale: f32,
         id: &DakotaId,
         font: dom::Font,
     ) {
-        let font_path = fontconfig.find(&font.font_name, None).unwrap();
+        let font_path = fontconfig
+            .find(&font.font_name, font.style.as_deref())
+            .unwrap();
 
         if font_instances.iter().find(|(f, _)| *f == font).is_none() {
-            font_instances.push((
-                font.clone(),
-                font::FontInstance::new(
-                    freetype,
-                    font_path.path.to_str().unwrap(),
-                    font.pixel_size,
-                ),
-            ));
+            let pixel_size = font::scale_pixel_size(font.pixel_size, text_scale);
+            let mut instance =
+                font::FontInstance::new(freetype, font_path.path.to_str().unwrap(), pixel_size);
+
+            // Add the fonts we will use for characters this font is missing
+            let mut fallback_paths = vec![font_path.path];
+            for name in font::FALLBACK_FONTS.iter() {
+                let path = match fontconfig.find(name, None) {
+                    Some(fallback) => fallback.path,
+                    None => continue,
+                };
+                if fallback_paths.contains(&path) {
+                    continue;
+                }
+
+                if let Some(fallback) =
+                    font::FontInstance::try_new(freetype, path.to_str().unwrap(), pixel_size)
+                {
+                    instance.add_fallback(fallback);
+                }
+                fallback_paths.push(path);
+            }
+
+            instance.set_subpixel_layout(glyphs, subpixel);
+            font_instances.push((font.clone(), instance));
         }
 
         fonts.set(id, font);
@@ -534,15 +1214,87 @@ impl Scene {
     /// is allowed with the font.
     pub fn define_font(&mut self, id: &DakotaId, font: dom::Font) {
         let mut fonts = self.d_fonts.snapshot();
+        let mut glyphs = self.d_glyphs.snapshot();
         Self::define_font_internal(
             &mut self.d_font_instances,
             &mut fonts,
+            &mut glyphs,
             &self.d_freetype,
             &self.d_fontconfig,
+            self.d_subpixel,
+            self.d_text_scale,
             id,
             font,
         );
         fonts.commit();
+        glyphs.commit();
+    }
+
+    /// Set the most threads to use during layout
+    ///
+    /// Large subtrees without text are laid out in parallel on up to this
+    /// many threads. A count of one lays out everything on the calling
+    /// thread. This defaults to the number of CPUs, up to four.
+    pub fn set_layout_threads(&mut self, count: usize) {
+        self.d_layout_threads = count.max(1);
+    }
+
+    /// Set the subpixel layout to rasterize text for
+    ///
+    /// This should match the layout of the Output this Scene is drawn on,
+    /// see `Output::set_subpixel_layout`. Text is rasterized again the
+    /// next time the Scene is recompiled.
+    pub fn set_subpixel_layout(&mut self, layout: th::SubpixelLayout) {
+        if layout == self.d_subpixel {
+            return;
+        }
+        self.d_subpixel = layout;
+
+        let mut glyphs = self.d_glyphs.snapshot();
+        for (_, instance) in self.d_font_instances.iter_mut() {
+            instance.set_subpixel_layout(&mut glyphs, layout);
+        }
+        glyphs.commit();
+
+        // The atlas holds the old glyph bitmaps, and their sizes may change
+        self.d_glyph_atlas.clear();
+        self.d_text_measurements.clear();
+        self.d_relayout_all = true;
+    }
+
+    /// Get the subpixel layout text is rasterized for
+    pub fn get_subpixel_layout(&self) -> th::SubpixelLayout {
+        self.d_subpixel
+    }
+
+    /// Set the scale to rasterize text at
+    ///
+    /// Fonts are rasterized at their pixel size multiplied by `scale`, so
+    /// that text keeps its physical size on high DPI displays. This should
+    /// follow the DPI of the Output this Scene is drawn on, see
+    /// `Output::get_dpi`. Text is rasterized and laid out again the next
+    /// time the Scene is recompiled.
+    pub fn set_text_scale(&mut self, scale: f32) {
+        if scale <= 0.0 || scale == self.d_text_scale {
+            return;
+        }
+        self.d_text_scale = scale;
+
+        let mut glyphs = self.d_glyphs.snapshot();
+        for (font, instance) in self.d_font_instances.iter_mut() {
+            instance.set_pixel_size(&mut glyphs, font::scale_pixel_size(font.pixel_size, scale));
+        }
+        glyphs.commit();
+
+        // The atlas holds the old glyph bitmaps, and text changes size
+        self.d_glyph_atlas.clear();
+        self.d_text_measurements.clear();
+        self.d_relayout_all = true;
+    }
+
+    /// Get the scale text is rasterized at
+    pub fn get_text_scale(&self) -> f32 {
+        self.d_text_scale
     }
 
     pub(crate) fn add_child_to_element_internal(
@@ -664,8 +1416,13 @@ impl Scene {
 
     /// This refreshes the entire scene, and regenerates
     /// the Thundr surface list.
+    ///
+    /// Any images which have finished loading are applied first.
     pub fn recompile(&mut self, virtual_output: &VirtualOutput) -> Result<()> {
         log::verbose!("Dakota: Refreshing element tree");
+        let _layout = trace::span("dakota", "recompile");
+        self.process_loaded_assets()?;
+
         let root_node_id = {
             let dom = self
                 .d_dom
@@ -678,6 +1435,25 @@ impl Scene {
         // Update our cached output size. This gets consumed by the layout engine
         self.d_window_dims = virtual_output.get_size();
 
+        // Create the visible rows of any lists. This happens first so that
+        // the rows can use styles.
+        self.update_lists(&root_node_id)?;
+        for popup in self.get_popups() {
+            self.update_lists(&popup)?;
+        }
+
+        // Draw any canvases that changed, so their images are up to date
+        self.update_canvases(&root_node_id)?;
+        for popup in self.get_popups() {
+            self.update_canvases(&popup)?;
+        }
+
+        // Fill in element properties from their styles
+        self.apply_styles(&root_node_id)?;
+        for popup in self.get_popups() {
+            self.apply_styles(&popup)?;
+        }
+
         // Set the size of our root node. We need to assign this a size manually so
         // that it doesn't default and size itself to its children, causing the viewport
         // scroll region calculation to go wrong.
@@ -698,7 +1474,30 @@ impl Scene {
         self.d_is_viewport.set(&root_node_id, true);
 
         // construct layout tree with sizes of all boxes
+        let layout = trace::span("dakota", "layout");
         self.layout(&root_node_id)?;
+        drop(layout);
+
+        // Tell accessibility tools about the new layout
+        self.update_access_tree(&root_node_id);
+
+        // Draw SVGs again if their elements were scaled
+        self.update_svg_sizes(&root_node_id)?;
+
+        // Only animations which are shown need to be played
+        let mut animated = Vec::new();
+        self.get_animated_resources(&root_node_id, &mut animated);
+        for popup in self.get_popups() {
+            self.get_animated_resources(&popup, &mut animated);
+        }
+        self.d_animated = animated;
+
+        let mut videos = Vec::new();
+        self.get_video_elements(&root_node_id, &mut videos);
+        for popup in self.get_popups() {
+            self.get_video_elements(&popup, &mut videos);
+        }
+        self.d_video_elements = videos;
 
         // Perform the Thundr pass
         //
@@ -778,9 +1577,12 @@ impl Scene {
     /// Walks the viewport tree and returns the ECS id of the
     /// viewport at this location. Note there will always be a viewport
     /// because the entire window surface is at the very least, the root viewport
+    ///
+    /// Popups are above the main tree, so they are checked first.
     pub fn get_viewport_at_position(&self, x: i32, y: i32) -> DakotaId {
         assert!(self.d_layout_tree_root.is_some());
         let root_node = self.d_layout_tree_root.as_ref().unwrap();
+        let popup = self.get_popup_at_position(x, y);
 
         // use some snapshots here to hold the read locks open
         let layout_nodes = self.d_layout_nodes.snapshot();
@@ -788,6 +1590,12 @@ impl Scene {
         let texts = self.d_texts.snapshot();
         assert!(viewports.get(root_node).is_some());
 
+        if let Some(popup) = popup {
+            return self
+                .viewport_at_pos_recursive(&layout_nodes, &viewports, &texts, &popup, (0, 0), x, y)
+                .unwrap();
+        }
+
         self.viewport_at_pos_recursive(&layout_nodes, &viewports, &texts, root_node, (0, 0), x, y)
             .unwrap()
     }
@@ -0,0 +1,93 @@
+//! Popups
+//!
+//! Popups are elements which float above the rest of the scene, such as
+//! menus and tooltips. They are not children of any element. Instead each
+//! one is laid out on its own after the main element tree, placed next to
+//! its anchor element, and drawn on top of everything shown before it.
+//!
+//! Popups are stacked in the order they were shown, so a submenu shown
+//! from a menu is above it. Clicking outside of a popup can dismiss it.
+// Austin Shafer - 2024
+use crate::{dom, DakotaId, Scene};
+
+impl Scene {
+    /// Show an element as a popup
+    ///
+    /// The popup is placed above all other popups. `el` should not be the
+    /// child of another element. Calling this again for a popup that is
+    /// already shown updates it and raises it to the top.
+    ///
+    /// The popup is laid out when the Scene is recompiled. If its anchor
+    /// is not in the scene at that time then the popup is hidden.
+    pub fn show_popup(&mut self, el: &DakotaId, popup: dom::Popup) {
+        self.d_popups.retain(|(id, _)| id != el);
+        self.d_popups.push((el.clone(), popup));
+        // Popups are their own viewport, so that they are not clipped by
+        // whatever is below them
+        self.d_is_viewport.set(el, true);
+        self.d_popups_modified = true;
+    }
+
+    /// Hide a popup
+    ///
+    /// Returns the popup's placement if it was being shown.
+    pub fn hide_popup(&mut self, el: &DakotaId) -> Option<dom::Popup> {
+        let index = self.d_popups.iter().position(|(id, _)| id == el)?;
+        self.d_popups_modified = true;
+        Some(self.d_popups.remove(index).1)
+    }
+
+    /// Get the popups being shown, from bottom to top
+    pub fn get_popups(&self) -> Vec<DakotaId> {
+        self.d_popups.iter().map(|(id, _)| id.clone()).collect()
+    }
+
+    /// Is this point inside of a popup
+    fn popup_contains(&self, el: &DakotaId, x: i32, y: i32) -> bool {
+        // Popups are laid out as roots, so their offset is their position
+        match self.d_layout_nodes.get(el) {
+            Some(layout) => {
+                (layout.l_offset.x..(layout.l_offset.x + layout.l_size.width)).contains(&x)
+                    && (layout.l_offset.y..(layout.l_offset.y + layout.l_size.height)).contains(&y)
+            }
+            None => false,
+        }
+    }
+
+    /// Get the top-most popup at this location
+    pub(crate) fn get_popup_at_position(&self, x: i32, y: i32) -> Option<DakotaId> {
+        self.d_popups
+            .iter()
+            .rev()
+            .map(|(id, _)| id)
+            .find(|id| self.popup_contains(id, x, y))
+            .cloned()
+    }
+
+    /// Dismiss the popups a click at this location is outside of
+    ///
+    /// Starting at the top, popups which dismiss on outside clicks are
+    /// hidden until we reach the popup that was clicked on. This closes a
+    /// submenu when its parent menu is clicked. Popups which don't dismiss
+    /// on outside clicks, such as tooltips, are left alone.
+    ///
+    /// Returns the popups which were hidden.
+    pub fn dismiss_popups_at(&mut self, x: i32, y: i32) -> Vec<DakotaId> {
+        let clicked = self.get_popup_at_position(x, y);
+        let mut ret = Vec::new();
+
+        for (id, popup) in self.d_popups.iter().rev() {
+            if Some(id) == clicked.as_ref() {
+                break;
+            }
+            if popup.dismiss_on_outside_click {
+                ret.push(id.clone());
+            }
+        }
+
+        for id in ret.iter() {
+            self.hide_popup(id);
+        }
+        ret
+    }
+}
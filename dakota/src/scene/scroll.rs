@@ -0,0 +1,415 @@
+//! Scrollbars and kinetic scrolling
+//!
+//! Viewports can show scrollbars along the edges whose content
+//! overflows. The thumb of a scrollbar can be dragged with the mouse, and
+//! clicking elsewhere on the track jumps the thumb to that spot.
+//!
+//! Touchpads report finger scrolling as a stream of small motions, ending
+//! with a zero motion when the fingers are lifted. We track how fast the
+//! fingers were moving, and once they are lifted the viewport keeps
+//! scrolling at that speed, slowing down until it stops.
+// Austin Shafer - 2024
+use crate::layout::LayoutNode;
+use crate::{DakotaId, Scene};
+use utils::region::Rect;
+
+use std::time::{Duration, Instant};
+
+/// The thickness of a scrollbar in pixels
+const SCROLLBAR_WIDTH: i32 = 8;
+/// Thumbs are never shorter than this, so that they are easy to grab
+const SCROLLBAR_MIN_THUMB: i32 = 16;
+pub(crate) const SCROLLBAR_TRACK_COLOR: (f32, f32, f32, f32) = (0.5, 0.5, 0.5, 0.3);
+pub(crate) const SCROLLBAR_THUMB_COLOR: (f32, f32, f32, f32) = (0.3, 0.3, 0.3, 0.8);
+
+/// How quickly a fling slows down. The velocity is multiplied by
+/// e^-KINETIC_FRICTION every second.
+const KINETIC_FRICTION: f32 = 4.0;
+/// Flings stop once they are slower than this, in pixels per second
+const KINETIC_MIN_VELOCITY: f32 = 30.0;
+/// Only finger motion this recent is used to find the fling velocity
+const KINETIC_SAMPLE_WINDOW: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ScrollAxis {
+    Horizontal,
+    Vertical,
+}
+
+/// One scrollbar of a viewport, in output coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Scrollbar {
+    pub sc_axis: ScrollAxis,
+    pub sc_track: Rect<i32>,
+    pub sc_thumb: Rect<i32>,
+}
+
+/// A scrollbar thumb being dragged by the mouse
+pub(crate) struct ScrollDrag {
+    sd_el: DakotaId,
+    sd_axis: ScrollAxis,
+    /// The mouse position along the axis when the drag started
+    sd_start: i32,
+    /// The scroll offset along the axis when the drag started
+    sd_start_offset: i32,
+    /// The number of pixels scrolled for each pixel the mouse moves
+    sd_ratio: f32,
+}
+
+/// A viewport which is still scrolling after the fingers were lifted
+pub(crate) struct Fling {
+    f_el: DakotaId,
+    /// Pixels per second
+    f_velocity: (f32, f32),
+    /// The fractional pixels we have not scrolled yet
+    f_remainder: (f32, f32),
+    f_last: Instant,
+}
+
+/// Get the offset and length of a scrollbar thumb within its track
+fn get_thumb(track_len: i32, size: i32, region: i32, scroll_offset: i32) -> (i32, i32) {
+    let len = (track_len * size / region)
+        .max(SCROLLBAR_MIN_THUMB)
+        .min(track_len);
+    // The scroll offset is in [-(region - size), 0]
+    let pos = (track_len - len) * -scroll_offset / (region - size);
+    (pos, len)
+}
+
+/// Get the scrollbars to draw for a viewport
+///
+/// `area` is where the viewport's element is on the output. A scrollbar
+/// is only shown for an axis whose content overflows the viewport.
+pub(crate) fn get_scrollbars(area: &Rect<i32>, viewport: &th::Viewport) -> Vec<Scrollbar> {
+    let overflow = (
+        viewport.scroll_region.0 > viewport.size.0,
+        viewport.scroll_region.1 > viewport.size.1,
+    );
+    // Leave the corner empty when both are shown
+    let corner = match overflow.0 && overflow.1 {
+        true => SCROLLBAR_WIDTH,
+        false => 0,
+    };
+    let mut ret = Vec::new();
+
+    if overflow.1 {
+        let track = Rect::new(
+            area.r_pos.0 + area.r_size.0 - SCROLLBAR_WIDTH,
+            area.r_pos.1,
+            SCROLLBAR_WIDTH,
+            area.r_size.1 - corner,
+        );
+        let (pos, len) = get_thumb(
+            track.r_size.1,
+            viewport.size.1,
+            viewport.scroll_region.1,
+            viewport.scroll_offset.1,
+        );
+        ret.push(Scrollbar {
+            sc_axis: ScrollAxis::Vertical,
+            sc_thumb: Rect::new(track.r_pos.0, track.r_pos.1 + pos, SCROLLBAR_WIDTH, len),
+            sc_track: track,
+        });
+    }
+    if overflow.0 {
+        let track = Rect::new(
+            area.r_pos.0,
+            area.r_pos.1 + area.r_size.1 - SCROLLBAR_WIDTH,
+            area.r_size.0 - corner,
+            SCROLLBAR_WIDTH,
+        );
+        let (pos, len) = get_thumb(
+            track.r_size.0,
+            viewport.size.0,
+            viewport.scroll_region.0,
+            viewport.scroll_offset.0,
+        );
+        ret.push(Scrollbar {
+            sc_axis: ScrollAxis::Horizontal,
+            sc_thumb: Rect::new(track.r_pos.0 + pos, track.r_pos.1, len, SCROLLBAR_WIDTH),
+            sc_track: track,
+        });
+    }
+
+    ret
+}
+
+/// Is this point inside of `rect`
+pub(crate) fn rect_contains(rect: &Rect<i32>, x: i32, y: i32) -> bool {
+    (rect.r_pos.0..(rect.r_pos.0 + rect.r_size.0)).contains(&x)
+        && (rect.r_pos.1..(rect.r_pos.1 + rect.r_size.1)).contains(&y)
+}
+
+impl Scene {
+    /// Scroll a viewport by a relative amount
+    ///
+    /// Returns false if the viewport could not scroll any further.
+    pub(crate) fn scroll_viewport(&mut self, el: &DakotaId, relative: (i32, i32)) -> bool {
+        let moved = match self.d_viewports.get_mut(el) {
+            Some(mut viewport) => {
+                let old = viewport.scroll_offset;
+                viewport.update_scroll_amount(relative.0, relative.1);
+                viewport.scroll_offset != old
+            }
+            None => false,
+        };
+
+        // If this is a list it may need rows for the newly visible items
+        self.handle_list_scroll(el);
+        moved
+    }
+
+    /// Find the scrollbar at this location in the tree rooted at `el`
+    fn scrollbar_at_pos_recursive(
+        &self,
+        el: &DakotaId,
+        base: (i32, i32),
+        x: i32,
+        y: i32,
+    ) -> Option<(DakotaId, Scrollbar)> {
+        let layout: LayoutNode = self.d_layout_nodes.get_clone(el)?;
+        // Glyphs and text can't contain viewports
+        if layout.l_glyph_id.is_some() || self.d_texts.get(el).is_some() {
+            return None;
+        }
+        let pos = (base.0 + layout.l_offset.x, base.1 + layout.l_offset.y);
+        let area = Rect::new(pos.0, pos.1, layout.l_size.width, layout.l_size.height);
+
+        let mut child_base = pos;
+        if let Some(viewport) = self.d_viewports.get_clone(el) {
+            // Children are clipped to the viewport
+            if !rect_contains(&area, x, y) {
+                return None;
+            }
+            // Our scrollbars are drawn on top of our children
+            if self.d_scrollbars.get_clone(el) == Some(true) {
+                if let Some(bar) = get_scrollbars(&area, &viewport)
+                    .into_iter()
+                    .find(|bar| rect_contains(&bar.sc_track, x, y))
+                {
+                    return Some((el.clone(), bar));
+                }
+            }
+            child_base.0 += viewport.scroll_offset.0;
+            child_base.1 += viewport.scroll_offset.1;
+        }
+
+        layout
+            .l_children
+            .iter()
+            .rev()
+            .find_map(|child| self.scrollbar_at_pos_recursive(child, child_base, x, y))
+    }
+
+    /// Start dragging the scrollbar at this location
+    ///
+    /// If the track was clicked outside of the thumb, the thumb is first
+    /// moved to be centered on the mouse. Returns false if there is no
+    /// scrollbar here.
+    pub(crate) fn begin_scrollbar_drag(&mut self, x: i32, y: i32) -> bool {
+        let root = match self.d_layout_tree_root.clone() {
+            Some(root) => root,
+            None => return false,
+        };
+        let popups = self.get_popups();
+        let (el, bar) = match popups
+            .iter()
+            .rev()
+            .chain(std::iter::once(&root))
+            .find_map(|tree| self.scrollbar_at_pos_recursive(tree, (0, 0), x, y))
+        {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let viewport = self.d_viewports.get_clone(&el).unwrap();
+
+        let (mouse, track_start, track_len, thumb_start, thumb_len, size, region, offset) =
+            match bar.sc_axis {
+                ScrollAxis::Vertical => (
+                    y,
+                    bar.sc_track.r_pos.1,
+                    bar.sc_track.r_size.1,
+                    bar.sc_thumb.r_pos.1,
+                    bar.sc_thumb.r_size.1,
+                    viewport.size.1,
+                    viewport.scroll_region.1,
+                    viewport.scroll_offset.1,
+                ),
+                ScrollAxis::Horizontal => (
+                    x,
+                    bar.sc_track.r_pos.0,
+                    bar.sc_track.r_size.0,
+                    bar.sc_thumb.r_pos.0,
+                    bar.sc_thumb.r_size.0,
+                    viewport.size.0,
+                    viewport.scroll_region.0,
+                    viewport.scroll_offset.0,
+                ),
+            };
+        let ratio = match track_len - thumb_len {
+            0 => 0.0,
+            free => (region - size) as f32 / free as f32,
+        };
+
+        // Jump the thumb to the mouse if the track was clicked
+        let mut start_offset = offset;
+        if !(thumb_start..(thumb_start + thumb_len)).contains(&mouse) {
+            let thumb_pos = (mouse - track_start - thumb_len / 2).clamp(0, track_len - thumb_len);
+            start_offset = -(thumb_pos as f32 * ratio).round() as i32;
+            let delta = offset - start_offset;
+            let relative = match bar.sc_axis {
+                ScrollAxis::Vertical => (0, delta),
+                ScrollAxis::Horizontal => (delta, 0),
+            };
+            self.scroll_viewport(&el, relative);
+        }
+
+        self.d_scroll_drag = Some(ScrollDrag {
+            sd_el: el,
+            sd_axis: bar.sc_axis,
+            sd_start: mouse,
+            sd_start_offset: start_offset,
+            sd_ratio: ratio,
+        });
+        true
+    }
+
+    /// Move the scrollbar being dragged to follow the mouse
+    ///
+    /// Returns true if a viewport was scrolled.
+    pub(crate) fn update_scrollbar_drag(&mut self, x: i32, y: i32) -> bool {
+        let (el, relative) = match self.d_scroll_drag.as_ref() {
+            Some(drag) => {
+                let viewport = match self.d_viewports.get(&drag.sd_el) {
+                    Some(viewport) => viewport.scroll_offset,
+                    None => return false,
+                };
+                let (mouse, current) = match drag.sd_axis {
+                    ScrollAxis::Vertical => (y, viewport.1),
+                    ScrollAxis::Horizontal => (x, viewport.0),
+                };
+                // Moving the thumb down scrolls the content up
+                let target = drag.sd_start_offset
+                    - ((mouse - drag.sd_start) as f32 * drag.sd_ratio).round() as i32;
+                let delta = current - target;
+                let relative = match drag.sd_axis {
+                    ScrollAxis::Vertical => (0, delta),
+                    ScrollAxis::Horizontal => (delta, 0),
+                };
+                (drag.sd_el.clone(), relative)
+            }
+            None => return false,
+        };
+
+        self.scroll_viewport(&el, relative)
+    }
+
+    /// Stop dragging a scrollbar
+    pub(crate) fn end_scrollbar_drag(&mut self) {
+        self.d_scroll_drag = None;
+    }
+
+    /// Is a scrollbar being dragged
+    pub fn is_dragging_scrollbar(&self) -> bool {
+        self.d_scroll_drag.is_some()
+    }
+
+    /// Handle finger scrolling on a viewport
+    ///
+    /// `relative` is the amount the fingers moved at time `now`. A zero
+    /// motion means the fingers were lifted, which starts a fling if they
+    /// were moving quickly enough.
+    pub(crate) fn handle_finger_scroll(
+        &mut self,
+        el: &DakotaId,
+        relative: (i32, i32),
+        now: Instant,
+    ) {
+        self.d_fling = None;
+        self.d_finger_samples
+            .retain(|(time, _)| now.duration_since(*time) <= KINETIC_SAMPLE_WINDOW);
+
+        if relative != (0, 0) {
+            self.d_finger_samples.push((now, relative));
+            return;
+        }
+
+        // The fingers were lifted, find how fast they were moving
+        let samples = std::mem::take(&mut self.d_finger_samples);
+        let first = match samples.first() {
+            Some((time, _)) => *time,
+            None => return,
+        };
+        let elapsed = now.duration_since(first).as_secs_f32();
+        if elapsed <= 0.0 {
+            return;
+        }
+        let total = samples.iter().fold((0, 0), |acc, (_, motion)| {
+            (acc.0 + motion.0, acc.1 + motion.1)
+        });
+        let velocity = (total.0 as f32 / elapsed, total.1 as f32 / elapsed);
+
+        if velocity.0.hypot(velocity.1) >= KINETIC_MIN_VELOCITY {
+            self.d_fling = Some(Fling {
+                f_el: el.clone(),
+                f_velocity: velocity,
+                f_remainder: (0.0, 0.0),
+                f_last: now,
+            });
+        }
+    }
+
+    /// Stop any kinetic scrolling
+    ///
+    /// This is used when the user scrolls in some other way.
+    pub(crate) fn stop_kinetic_scroll(&mut self) {
+        self.d_fling = None;
+        self.d_finger_samples.clear();
+    }
+
+    /// Is a viewport scrolling on its own after a fling
+    ///
+    /// While this is true `update_kinetic_scroll` should be called every
+    /// frame.
+    pub fn is_kinetic_scrolling(&self) -> bool {
+        self.d_fling.is_some()
+    }
+
+    /// Advance kinetic scrolling to `now`
+    pub(crate) fn update_kinetic_scroll_at(&mut self, now: Instant) -> bool {
+        let fling = match self.d_fling.as_mut() {
+            Some(fling) => fling,
+            None => return false,
+        };
+
+        let dt = now.duration_since(fling.f_last).as_secs_f32();
+        fling.f_last = now;
+        // Integrate the exponentially decaying velocity over this step
+        let decay = (-KINETIC_FRICTION * dt).exp();
+        let distance = (
+            fling.f_velocity.0 * (1.0 - decay) / KINETIC_FRICTION + fling.f_remainder.0,
+            fling.f_velocity.1 * (1.0 - decay) / KINETIC_FRICTION + fling.f_remainder.1,
+        );
+        let relative = (distance.0.trunc() as i32, distance.1.trunc() as i32);
+        fling.f_remainder = (distance.0.fract(), distance.1.fract());
+        fling.f_velocity = (fling.f_velocity.0 * decay, fling.f_velocity.1 * decay);
+
+        let slow = fling.f_velocity.0.hypot(fling.f_velocity.1) < KINETIC_MIN_VELOCITY;
+        let el = fling.f_el.clone();
+
+        // Stop once we have slowed down or hit the end of the content
+        let moved = relative == (0, 0) || self.scroll_viewport(&el, relative);
+        if slow || !moved {
+            self.d_fling = None;
+        }
+        true
+    }
+
+    /// Advance kinetic scrolling
+    ///
+    /// This should be called once per frame. Returns true if a viewport
+    /// was scrolled and the output should be redrawn.
+    pub fn update_kinetic_scroll(&mut self) -> bool {
+        self.update_kinetic_scroll_at(Instant::now())
+    }
+}
@@ -0,0 +1,211 @@
+//! Style classes
+//!
+//! Styles are named sets of element properties which elements reference
+//! through their classes. They are resolved when the Scene is recompiled,
+//! filling in any properties the element has not set itself.
+//!
+//! We keep a copy of the properties last applied to each element, so that
+//! changing a style can replace them without clobbering values the
+//! application set directly.
+// Austin Shafer - 2024
+use crate::{dom, DakotaId, Scene};
+use utils::{anyhow, Context, Result};
+
+use std::collections::HashMap;
+
+/// Update one property of an element from its styles
+///
+/// `applied` is the value our styles last set, and `value` is the value
+/// they want now. If the element's current value is not the one we
+/// applied then it was set by the application, and is left alone.
+fn apply_style_property<T: Clone + PartialEq + Send + Sync + 'static>(
+    component: &ll::Component<T>,
+    el: &DakotaId,
+    applied: Option<&T>,
+    value: Option<&T>,
+) {
+    let current = component.get_clone(el);
+    if current.is_some() && current.as_ref() != applied {
+        return;
+    }
+
+    match value {
+        Some(val) => {
+            if current.as_ref() != Some(val) {
+                component.set(el, val.clone());
+            }
+        }
+        None => {
+            if current.is_some() {
+                component.take(el);
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// Define or replace a style
+    ///
+    /// Elements using this style will be updated the next time the Scene
+    /// is recompiled.
+    pub fn define_style(&mut self, style: dom::Style) {
+        self.d_styles.insert(style.name.clone(), style);
+        self.d_styles_modified = true;
+    }
+
+    /// Get the definition of a style
+    pub fn get_style(&self, name: &str) -> Option<&dom::Style> {
+        self.d_styles.get(name)
+    }
+
+    /// Remove a style
+    ///
+    /// Elements still referencing this style will fail to recompile until
+    /// it is defined again or removed from their classes.
+    pub fn remove_style(&mut self, name: &str) -> Option<dom::Style> {
+        let ret = self.d_styles.remove(name);
+        self.d_styles_modified = true;
+        ret
+    }
+
+    /// Add a style class to an element
+    ///
+    /// Classes added later take precedence over earlier ones.
+    pub fn add_class(&mut self, el: &DakotaId, name: &str) {
+        match self.d_classes.get_mut(el) {
+            Some(mut classes) => {
+                if !classes.iter().any(|c| c == name) {
+                    classes.push(name.to_string());
+                }
+            }
+            None => self.d_classes.set(el, vec![name.to_string()]),
+        }
+    }
+
+    /// Remove a style class from an element
+    pub fn remove_class(&mut self, el: &DakotaId, name: &str) {
+        if let Some(mut classes) = self.d_classes.get_mut(el) {
+            classes.retain(|c| c != name);
+        }
+    }
+
+    /// Load styles from a stylesheet file
+    ///
+    /// The file's toplevel element is `<styles>`, which holds `<style>`
+    /// definitions and optionally a `<resourceMap>` of the resources they
+    /// use.
+    pub fn load_stylesheet(&mut self, path: &std::path::Path) -> Result<()> {
+        let file =
+            std::fs::File::open(path).context(anyhow!("Could not open stylesheet {:?}", path))?;
+        self.load_xml_reader(std::io::BufReader::new(file))
+    }
+
+    /// Get the combined properties of a style and the styles it inherits
+    fn resolve_style(styles: &HashMap<String, dom::Style>, name: &str) -> Result<dom::Style> {
+        let mut chain = Vec::new();
+        let mut next = Some(name);
+
+        while let Some(name) = next {
+            if chain.iter().any(|s: &&dom::Style| s.name == name) {
+                return Err(anyhow!("Style {} inherits from itself", name));
+            }
+            let style = styles
+                .get(name)
+                .ok_or(anyhow!("Style {} is not defined", name))?;
+            chain.push(style);
+            next = style.inherits.as_deref();
+        }
+
+        // Apply the base styles first, so that their children override them
+        let mut ret = dom::Style::new(name);
+        for style in chain.iter().rev() {
+            ret.apply(style);
+        }
+
+        Ok(ret)
+    }
+
+    /// Get the properties an element takes from its classes
+    fn get_element_style(&self, el: &DakotaId) -> Result<dom::Style> {
+        let mut ret = dom::Style::default();
+
+        if let Some(classes) = self.d_classes.get(el) {
+            for name in classes.iter() {
+                ret.apply(&Self::resolve_style(&self.d_styles, name)?);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Apply the styles of `el` and all of its children
+    fn apply_styles_recursive(&mut self, el: &DakotaId) -> Result<()> {
+        let style = self
+            .get_element_style(el)
+            .context("Resolving element styles")?;
+        let applied = self.d_applied_styles.get_clone(el).unwrap_or_default();
+
+        if style != applied {
+            apply_style_property(
+                &self.d_resources,
+                el,
+                applied.resource.as_ref(),
+                style.resource.as_ref(),
+            );
+            apply_style_property(
+                &self.d_widths,
+                el,
+                applied.width.as_ref(),
+                style.width.as_ref(),
+            );
+            apply_style_property(
+                &self.d_heights,
+                el,
+                applied.height.as_ref(),
+                style.height.as_ref(),
+            );
+            apply_style_property(
+                &self.d_offsets,
+                el,
+                applied.offset.as_ref(),
+                style.offset.as_ref(),
+            );
+            apply_style_property(
+                &self.d_opacities,
+                el,
+                applied.opacity.as_ref(),
+                style.opacity.as_ref(),
+            );
+            apply_style_property(
+                &self.d_text_font,
+                el,
+                applied.font.as_ref(),
+                style.font.as_ref(),
+            );
+
+            match style == dom::Style::default() {
+                true => {
+                    self.d_applied_styles.take(el);
+                }
+                false => self.d_applied_styles.set(el, style),
+            }
+        }
+
+        let mut children = self.d_children.get_clone(el).unwrap_or_default();
+        if let Some(content) = self.d_contents.get(el) {
+            children.push(content.el.clone());
+        }
+        for child in children.iter() {
+            self.apply_styles_recursive(child)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolve the styles of every element in the tree rooted at `root`
+    ///
+    /// This is called when recompiling the Scene.
+    pub(crate) fn apply_styles(&mut self, root: &DakotaId) -> Result<()> {
+        self.apply_styles_recursive(root)
+    }
+}
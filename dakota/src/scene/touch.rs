@@ -0,0 +1,161 @@
+//! Touchscreen input
+//!
+//! Each finger on a touchscreen is tracked from when it touches the
+//! screen until it is lifted, along with the element it touched. Dragging
+//! a single finger scrolls the viewport under it, and lifting the finger
+//! while it is moving flings the viewport like touchpad scrolling does.
+//! When more than one finger is down nothing is scrolled, leaving
+//! multi-finger gestures to the application.
+// Austin Shafer - 2024
+use super::scroll::rect_contains;
+use crate::layout::LayoutNode;
+use crate::{DakotaId, Scene};
+use utils::region::Rect;
+
+use std::time::Instant;
+
+/// A finger on the touchscreen
+pub(crate) struct TouchPoint {
+    /// The element the finger first touched
+    tp_element: Option<DakotaId>,
+    /// The viewport this finger scrolls
+    tp_viewport: DakotaId,
+    /// The last position of the finger
+    tp_pos: (i32, i32),
+}
+
+impl Scene {
+    /// Find the top-most element at this location in the tree rooted at `el`
+    fn element_at_pos_recursive(
+        &self,
+        el: &DakotaId,
+        base: (i32, i32),
+        x: i32,
+        y: i32,
+    ) -> Option<DakotaId> {
+        let layout: LayoutNode = self.d_layout_nodes.get_clone(el)?;
+        // Glyphs are part of the text element they belong to
+        if layout.l_glyph_id.is_some() {
+            return None;
+        }
+        let pos = (base.0 + layout.l_offset.x, base.1 + layout.l_offset.y);
+        let area = Rect::new(pos.0, pos.1, layout.l_size.width, layout.l_size.height);
+        let inside = rect_contains(&area, x, y);
+
+        let mut child_base = pos;
+        if let Some(viewport) = self.d_viewports.get_clone(el) {
+            // Children are clipped to the viewport
+            if !inside {
+                return None;
+            }
+            child_base.0 += viewport.scroll_offset.0;
+            child_base.1 += viewport.scroll_offset.1;
+        }
+
+        if self.d_texts.get(el).is_none() {
+            // Later children are drawn on top, so check them first
+            if let Some(ret) = layout
+                .l_children
+                .iter()
+                .rev()
+                .find_map(|child| self.element_at_pos_recursive(child, child_base, x, y))
+            {
+                return Some(ret);
+            }
+        }
+
+        match inside {
+            true => Some(el.clone()),
+            false => None,
+        }
+    }
+
+    /// Get the top-most element at this location
+    ///
+    /// Popups are above the main tree, so they are checked first. Text is
+    /// reported as the element holding it. Returns None if the scene has
+    /// not been laid out.
+    pub fn get_element_at_position(&self, x: i32, y: i32) -> Option<DakotaId> {
+        let root = self.d_layout_tree_root.clone()?;
+
+        self.get_popups()
+            .iter()
+            .rev()
+            .chain(std::iter::once(&root))
+            .find_map(|tree| self.element_at_pos_recursive(tree, (0, 0), x, y))
+    }
+
+    /// Get the element a finger first touched
+    ///
+    /// Returns None if this finger is not on the touchscreen.
+    pub fn get_touched_element(&self, id: i32) -> Option<DakotaId> {
+        self.d_touch_points
+            .get(&id)
+            .and_then(|point| point.tp_element.clone())
+    }
+
+    /// Start tracking a finger which touched the screen
+    ///
+    /// Returns the element that was touched.
+    pub(crate) fn begin_touch(&mut self, id: i32, x: i32, y: i32) -> Option<DakotaId> {
+        self.d_layout_tree_root.as_ref()?;
+        // Touching the screen catches any fling in progress
+        self.stop_kinetic_scroll();
+
+        let element = self.get_element_at_position(x, y);
+        self.d_touch_points.insert(
+            id,
+            TouchPoint {
+                tp_element: element.clone(),
+                tp_viewport: self.get_viewport_at_position(x, y),
+                tp_pos: (x, y),
+            },
+        );
+        element
+    }
+
+    /// Move a finger on the touchscreen
+    ///
+    /// Returns true if a viewport was scrolled.
+    pub(crate) fn update_touch(&mut self, id: i32, x: i32, y: i32, now: Instant) -> bool {
+        let single = self.d_touch_points.len() == 1;
+        let (viewport, last) = match self.d_touch_points.get_mut(&id) {
+            Some(point) => {
+                let last = point.tp_pos;
+                point.tp_pos = (x, y);
+                (point.tp_viewport.clone(), last)
+            }
+            None => return false,
+        };
+        if !single {
+            return false;
+        }
+
+        // The content follows the finger, which is the opposite direction
+        // of scrolling
+        let relative = (last.0 - x, last.1 - y);
+        if relative == (0, 0) {
+            return false;
+        }
+        self.handle_finger_scroll(&viewport, relative, now);
+        self.scroll_viewport(&viewport, relative)
+    }
+
+    /// Stop tracking a finger which was lifted
+    ///
+    /// If this was the only finger down and it was moving, its viewport
+    /// is flung. Returns the element the finger first touched.
+    pub(crate) fn end_touch(&mut self, id: i32, now: Instant) -> Option<DakotaId> {
+        let point = self.d_touch_points.remove(&id)?;
+        if self.d_touch_points.is_empty() {
+            self.handle_finger_scroll(&point.tp_viewport, (0, 0), now);
+        }
+        point.tp_element
+    }
+
+    /// Forget all fingers on the touchscreen without taking any action
+    pub(crate) fn cancel_touches(&mut self) {
+        self.d_touch_points.clear();
+        self.stop_kinetic_scroll();
+    }
+}
@@ -0,0 +1,435 @@
+//! Video elements
+//!
+//! A video element shows frames pushed to it by the application's
+//! decoder. Each frame has a presentation timestamp relative to the start
+//! of the stream, and frames are queued until the video's clock reaches
+//! them. When the Output draws, every video shows the newest frame that is
+//! due and drops any older ones that were missed.
+//!
+//! Frames may be mapped memory in BGRA, NV12, or I420, or a dmabuf. YUV
+//! frames are converted to RGB with the BT.601 limited range matrix, which
+//! is what most decoders produce. Thundr can only import dmabufs in the
+//! same format as other images, so hardware decoders should use their
+//! post processing to output BGRA.
+// Austin Shafer - 2024
+use super::Scene;
+use crate::{dom, DakotaId};
+use th::{Dmabuf, Droppable};
+use utils::{anyhow, Context, Result};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The most frames which may be waiting to be shown on a video
+pub const MAX_VIDEO_QUEUE: usize = 16;
+
+/// The layout of a mapped video frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VideoFormat {
+    /// Packed B, G, R, A bytes, the same as `dom::Format::ARGB8888`
+    ARGB8888,
+    /// A full size Y plane followed by a half size plane of interleaved
+    /// U and V
+    NV12,
+    /// A full size Y plane followed by half size U and V planes
+    I420,
+}
+
+impl VideoFormat {
+    /// Get the number of bytes in a tightly packed frame
+    fn get_frame_len(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        let chroma = width.div_ceil(2) * height.div_ceil(2);
+        match self {
+            VideoFormat::ARGB8888 => width * height * 4,
+            VideoFormat::NV12 | VideoFormat::I420 => width * height + chroma * 2,
+        }
+    }
+}
+
+enum VideoFrameData {
+    Mapped {
+        format: VideoFormat,
+        data: Vec<u8>,
+    },
+    Dmabuf {
+        dmabuf: Dmabuf,
+        release: Option<Box<dyn Droppable + Send + Sync>>,
+    },
+}
+
+/// One decoded frame of a video
+pub struct VideoFrame {
+    /// When to show this frame, relative to the start of the stream
+    vf_pts: Duration,
+    vf_size: (u32, u32),
+    vf_data: VideoFrameData,
+}
+
+impl VideoFrame {
+    /// Create a frame from tightly packed memory
+    ///
+    /// Returns an error if `data` is the wrong size for this format.
+    pub fn from_mapped(
+        pts: Duration,
+        format: VideoFormat,
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        let len = format.get_frame_len(width, height);
+        if data.len() != len {
+            return Err(anyhow!(
+                "{:?} frame of {}x{} must be {} bytes, not {}",
+                format,
+                width,
+                height,
+                len,
+                data.len()
+            ));
+        }
+
+        Ok(Self {
+            vf_pts: pts,
+            vf_size: (width, height),
+            vf_data: VideoFrameData::Mapped { format, data },
+        })
+    }
+
+    /// Create a frame from a dmabuf
+    ///
+    /// `release` is dropped once the frame is no longer shown, which can
+    /// be used to give the buffer back to the decoder.
+    pub fn from_dmabuf(
+        pts: Duration,
+        dmabuf: Dmabuf,
+        release: Option<Box<dyn Droppable + Send + Sync>>,
+    ) -> Self {
+        Self {
+            vf_pts: pts,
+            vf_size: (dmabuf.db_width as u32, dmabuf.db_height as u32),
+            vf_data: VideoFrameData::Dmabuf { dmabuf, release },
+        }
+    }
+
+    pub fn get_pts(&self) -> Duration {
+        self.vf_pts
+    }
+}
+
+/// Convert one pixel from BT.601 limited range YUV to BGRA
+fn yuv_to_bgra(y: u8, u: u8, v: u8) -> [u8; 4] {
+    let c = (y as i32 - 16) * 298;
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+    let clamp = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+
+    [
+        clamp(c + 516 * d),
+        clamp(c - 100 * d - 208 * e),
+        clamp(c + 409 * e),
+        255,
+    ]
+}
+
+/// Convert a mapped frame to BGRA pixels
+pub(crate) fn convert_to_bgra(
+    format: VideoFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let chroma_width = width.div_ceil(2);
+    let chroma_len = chroma_width * height.div_ceil(2);
+    let (luma, chroma) = data.split_at(width * height);
+
+    // Get the U and V offsets of a chroma sample
+    let (u_base, v_base, step) = match format {
+        VideoFormat::ARGB8888 => return data.to_vec(),
+        VideoFormat::NV12 => (0, 1, 2),
+        VideoFormat::I420 => (0, chroma_len, 1),
+    };
+
+    let mut ret = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let sample = ((y / 2) * chroma_width + x / 2) * step;
+            ret.extend_from_slice(&yuv_to_bgra(
+                luma[y * width + x],
+                chroma[u_base + sample],
+                chroma[v_base + sample],
+            ));
+        }
+    }
+
+    ret
+}
+
+/// The frames and playback clock of a video element
+pub(crate) struct Video {
+    /// The resource the current frame is shown with
+    v_resource: DakotaId,
+    /// Frames waiting to be shown, in presentation order
+    v_queue: VecDeque<VideoFrame>,
+    /// The time at which the stream position was zero. This is None while
+    /// paused.
+    v_start: Option<Instant>,
+    /// The stream position while paused
+    v_position: Duration,
+    /// The number of frames that were never shown because a later frame
+    /// was due
+    v_dropped: u64,
+}
+
+impl Video {
+    /// Get the stream position at `now`
+    fn get_position(&self, now: Instant) -> Duration {
+        match self.v_start {
+            Some(start) => now.saturating_duration_since(start),
+            None => self.v_position,
+        }
+    }
+
+    /// Take the newest frame due at `now`, dropping any older frames
+    fn take_due_frame(&mut self, now: Instant) -> Option<VideoFrame> {
+        let position = self.get_position(now);
+        let due = self
+            .v_queue
+            .iter()
+            .take_while(|frame| frame.vf_pts <= position)
+            .count();
+        if due == 0 {
+            return None;
+        }
+
+        self.v_dropped += due as u64 - 1;
+        self.v_queue.drain(..due - 1);
+        self.v_queue.pop_front()
+    }
+}
+
+impl Scene {
+    /// Turn an element into a video
+    ///
+    /// The video starts out paused at the beginning of the stream, and
+    /// shows nothing until its first frame is due. If the element does not
+    /// have a size it will take the size of the frames.
+    pub fn create_video(&mut self, el: &DakotaId) -> Result<()> {
+        if self.d_videos.get(el).is_some() {
+            return Err(anyhow!("Element is already a video"));
+        }
+
+        let res = self.create_resource()?;
+        self.d_resources.set(el, res.clone());
+        self.d_videos.set(
+            el,
+            Video {
+                v_resource: res,
+                v_queue: VecDeque::new(),
+                v_start: None,
+                v_position: Duration::ZERO,
+                v_dropped: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stop using an element as a video
+    ///
+    /// Any queued frames are released.
+    pub fn remove_video(&mut self, el: &DakotaId) {
+        if self.d_videos.take(el).is_some() {
+            self.d_resources.take(el);
+        }
+    }
+
+    /// Run a function on an element's video
+    fn with_video<T, F>(&mut self, el: &DakotaId, func: F) -> Result<T>
+    where
+        F: FnOnce(&mut Video) -> Result<T>,
+    {
+        let mut video = self
+            .d_videos
+            .get_mut(el)
+            .ok_or(anyhow!("Element is not a video"))?;
+        func(&mut video)
+    }
+
+    /// Queue a frame to be shown on a video
+    ///
+    /// Frames must be pushed in presentation order. Returns an error if
+    /// there are already `MAX_VIDEO_QUEUE` frames waiting, in which case
+    /// the decoder should wait for the video to catch up.
+    pub fn video_push_frame(&mut self, el: &DakotaId, frame: VideoFrame) -> Result<()> {
+        self.with_video(el, |video| {
+            if video.v_queue.len() >= MAX_VIDEO_QUEUE {
+                return Err(anyhow!("Video frame queue is full"));
+            }
+            if let Some(last) = video.v_queue.back() {
+                if frame.vf_pts < last.vf_pts {
+                    return Err(anyhow!("Video frames must be queued in order"));
+                }
+            }
+            video.v_queue.push_back(frame);
+            Ok(())
+        })
+    }
+
+    /// Get the number of frames waiting to be shown on a video
+    pub fn video_queue_len(&self, el: &DakotaId) -> usize {
+        self.d_videos
+            .get(el)
+            .map(|video| video.v_queue.len())
+            .unwrap_or(0)
+    }
+
+    /// Get the number of frames which were skipped because they were late
+    pub fn video_dropped_frames(&self, el: &DakotaId) -> u64 {
+        self.d_videos
+            .get(el)
+            .map(|video| video.v_dropped)
+            .unwrap_or(0)
+    }
+
+    /// Get the current stream position of a video
+    pub fn video_position(&self, el: &DakotaId) -> Option<Duration> {
+        self.d_videos
+            .get(el)
+            .map(|video| video.get_position(Instant::now()))
+    }
+
+    /// Start or resume playing a video
+    pub fn video_play(&mut self, el: &DakotaId) -> Result<()> {
+        self.with_video(el, |video| {
+            if video.v_start.is_none() {
+                let now = Instant::now();
+                video.v_start = Some(now.checked_sub(video.v_position).unwrap_or(now));
+            }
+            Ok(())
+        })
+    }
+
+    /// Pause a video, keeping its current frame on screen
+    pub fn video_pause(&mut self, el: &DakotaId) -> Result<()> {
+        self.with_video(el, |video| {
+            video.v_position = video.get_position(Instant::now());
+            video.v_start = None;
+            Ok(())
+        })
+    }
+
+    /// Move a video to a new stream position
+    ///
+    /// All queued frames are released, and the decoder should push frames
+    /// starting from `position`.
+    pub fn video_seek(&mut self, el: &DakotaId, position: Duration) -> Result<()> {
+        self.with_video(el, |video| {
+            video.v_queue.clear();
+            video.v_position = position;
+            if video.v_start.is_some() {
+                let now = Instant::now();
+                video.v_start = Some(now.checked_sub(position).unwrap_or(now));
+            }
+            Ok(())
+        })
+    }
+
+    /// Find the video elements in the tree rooted at `el`
+    pub(crate) fn get_video_elements(&self, el: &DakotaId, videos: &mut Vec<DakotaId>) {
+        if self.d_videos.get(el).is_some() {
+            videos.push(el.clone());
+        }
+
+        let mut children = self.d_children.get_clone(el).unwrap_or_default();
+        if let Some(content) = self.d_contents.get(el) {
+            children.push(content.el.clone());
+        }
+        for child in children.iter() {
+            self.get_video_elements(child, videos);
+        }
+    }
+
+    /// Show a video frame with the resource `res`
+    fn show_video_frame(&mut self, res: &DakotaId, frame: VideoFrame) -> Result<()> {
+        let (width, height) = frame.vf_size;
+
+        match frame.vf_data {
+            VideoFrameData::Mapped { format, data } => {
+                let pixels = convert_to_bgra(format, width, height, &data);
+                match self.is_resource_defined(res) {
+                    true => self.update_resource_from_bits(
+                        res,
+                        &pixels,
+                        width,
+                        height,
+                        0,
+                        dom::Format::ARGB8888,
+                        None,
+                    ),
+                    false => self.define_resource_from_bits(
+                        res,
+                        &pixels,
+                        width,
+                        height,
+                        0,
+                        dom::Format::ARGB8888,
+                    ),
+                }
+            }
+            VideoFrameData::Dmabuf { dmabuf, release } => {
+                // Replacing the image releases the last frame's buffer
+                let image = self
+                    .d_dev
+                    .create_image_from_dmabuf(&dmabuf, release)
+                    .context("Could not import video frame")?;
+                self.d_resource_thundr_image.set(res, image);
+                self.add_resource_damage(res, None);
+                Ok(())
+            }
+        }
+    }
+
+    /// Show the frames of each video which are due at `now`
+    pub(crate) fn update_videos_at(&mut self, now: Instant) -> Result<bool> {
+        let mut updated = false;
+
+        for el in self.d_video_elements.clone().iter() {
+            let (res, frame) = match self.d_videos.get_mut(el) {
+                Some(mut video) => (video.v_resource.clone(), video.take_due_frame(now)),
+                None => continue,
+            };
+
+            if let Some(frame) = frame {
+                self.show_video_frame(&res, frame)
+                    .context("Showing video frame")?;
+                updated = true;
+            }
+        }
+
+        Ok(updated)
+    }
+
+    /// Show the current frame of each video
+    ///
+    /// This is called by `Output::redraw`. Frames which were due before
+    /// the newest one are dropped. Returns true if any videos changed.
+    pub fn update_videos(&mut self) -> Result<bool> {
+        self.update_videos_at(Instant::now())
+    }
+
+    /// Get when the next queued frame of a playing video is due
+    ///
+    /// The app should redraw at this time. Returns None if no shown videos
+    /// are playing or have frames queued.
+    pub fn get_next_video_time(&self) -> Option<Instant> {
+        self.d_video_elements
+            .iter()
+            .filter_map(|el| {
+                let video = self.d_videos.get(el)?;
+                let frame = video.v_queue.front()?;
+                video.v_start.map(|start| start + frame.vf_pts)
+            })
+            .min()
+    }
+}
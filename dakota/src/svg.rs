@@ -0,0 +1,112 @@
+//! SVG image resources
+//!
+//! Icons are often drawn at many different sizes, and a bitmap scaled up
+//! for a HiDPI output looks blurry. Resources defined from SVG files are
+//! instead rasterized at the size their elements are laid out with. The
+//! first raster uses the size the file asks for, and the resource is drawn
+//! again whenever an element using it is scaled far enough from the size
+//! of its current raster.
+//!
+//! Rasterizing happens on the AssetLoader's worker threads. Each size is
+//! cached separately, so icons switching between a few sizes only draw
+//! each of them once.
+// Austin Shafer - 2024
+extern crate resvg;
+use resvg::tiny_skia;
+use resvg::usvg::{self, TreeParsing};
+use utils::{anyhow, Context, Result};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How far an element may be scaled from the size of its raster, as a
+/// fraction of that size, before we draw it again
+pub(crate) const SVG_RESIZE_THRESHOLD: f32 = 0.25;
+
+/// The largest size we will rasterize an SVG at
+const SVG_MAX_SIZE: u32 = 4096;
+
+/// The SVG a resource was defined from
+///
+/// The parsed document is not thread safe, so we keep the file contents
+/// and parse them again on a worker whenever a new size is needed.
+#[derive(Clone)]
+pub(crate) struct SvgResource {
+    /// The cache key of the file
+    pub sr_path: PathBuf,
+    pub sr_data: Arc<Vec<u8>>,
+    /// The size of the raster the resource currently uses, or is waiting on
+    pub sr_size: (u32, u32),
+}
+
+/// Does this file look like an SVG
+pub(crate) fn is_svg(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("svg") || ext.eq_ignore_ascii_case("svgz"),
+        None => false,
+    }
+}
+
+fn parse_svg(data: &[u8]) -> Result<usvg::Tree> {
+    usvg::Tree::from_data(data, &usvg::Options::default()).context("Could not parse SVG")
+}
+
+/// Get the size an SVG document asks to be drawn at
+pub(crate) fn svg_size(data: &[u8]) -> Result<(u32, u32)> {
+    let tree = parse_svg(data)?;
+    Ok(clamp_svg_size((
+        tree.size.width().ceil() as u32,
+        tree.size.height().ceil() as u32,
+    )))
+}
+
+/// Limit a raster size to something we can allocate
+pub(crate) fn clamp_svg_size(size: (u32, u32)) -> (u32, u32) {
+    (size.0.clamp(1, SVG_MAX_SIZE), size.1.clamp(1, SVG_MAX_SIZE))
+}
+
+/// Get the key a raster of an SVG is cached under
+///
+/// Every size of a file gets its own cache entry.
+pub(crate) fn svg_cache_key(path: &Path, size: (u32, u32)) -> PathBuf {
+    PathBuf::from(format!("{}#{}x{}", path.display(), size.0, size.1))
+}
+
+/// Does an element of size `target` need a new raster of this SVG?
+///
+/// `current` is the size of the raster we already have.
+pub(crate) fn svg_needs_resize(current: (u32, u32), target: (u32, u32)) -> bool {
+    let differs = |cur: u32, new: u32| {
+        (new as f32 - cur as f32).abs() > cur.max(1) as f32 * SVG_RESIZE_THRESHOLD
+    };
+
+    differs(current.0, target.0) || differs(current.1, target.1)
+}
+
+/// Draw an SVG document into tightly packed BGRA8 pixels
+///
+/// The document is stretched to fill `size`.
+pub(crate) fn rasterize_svg(data: &[u8], size: (u32, u32)) -> Result<(u32, u32, Vec<u8>)> {
+    let tree = parse_svg(data)?;
+    let (width, height) = clamp_svg_size(size);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or(anyhow!(
+        "Could not allocate {}x{} SVG raster",
+        width,
+        height
+    ))?;
+
+    let transform = tiny_skia::Transform::from_scale(
+        width as f32 / tree.size.width(),
+        height as f32 / tree.size.height(),
+    );
+    resvg::Tree::from_usvg(&tree).render(transform, &mut pixmap.as_mut());
+
+    // tiny-skia gives us premultiplied RGBA, but Thundr wants straight BGRA
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for pixel in pixmap.pixels() {
+        let color = pixel.demultiply();
+        pixels.extend_from_slice(&[color.blue(), color.green(), color.red(), color.alpha()]);
+    }
+
+    Ok((width, height, pixels))
+}
@@ -36,32 +36,44 @@ fn check_pixels(output: &mut dak::Output, testname: &str, threshold: u32) {
     assert!(result.success());
 }
 
-/// Test one of the scenes
+/// Create a Dakota instance with an Output and an empty Scene
 ///
-/// This will render one frame with Dakota of the specified test
-/// scene from dakota-test
-fn test_file(testname: &str, threshold: u32) {
+/// This is the starting point for most of our tests.
+fn test_setup() -> (dak::Dakota, dak::VirtualOutput, dak::Output, dak::Scene) {
     let mut dak = dak::Dakota::new().expect("Could not create Dakota");
-
-    // Set up our output
-    let mut virtual_output = dak
+    let virtual_output = dak
         .create_virtual_output()
         .expect("Failed to create Dakota Virtual Output Surface");
-    let mut output = dak
+    let output = dak
         .create_output(&virtual_output)
         .expect("Failed to create Dakota Output");
+    let scene = output
+        .create_scene(&virtual_output)
+        .expect("Could not create scene");
+
+    (dak, virtual_output, output, scene)
+}
+
+/// Test one of the scenes
+///
+/// This will render one frame with Dakota of the specified test
+/// scene from dakota-test
+fn test_file(testname: &str, threshold: u32) {
+    let (mut dak, mut virtual_output, mut output, mut scene) = test_setup();
 
     let filename = ["../dakota-test/data/", testname, ".xml"].join("");
     let f = File::open(&filename).expect("could not open file");
     let reader = BufReader::new(f);
 
-    let mut scene = output
-        .create_scene(&virtual_output)
-        .expect("Could not create scene");
     // For convenience we load our scene contents from an XML file
     scene
         .load_xml_reader(reader)
         .expect("Could not parse XML dakota file");
+    // Images are decoded in the background, make sure they are all
+    // loaded before we draw
+    scene
+        .wait_for_assets()
+        .expect("Could not load scene images");
     // Now refresh our scene to recalculate the layout of the contents
     // that we just loaded in
     output.set_resolution(&mut scene, 640, 480).unwrap();
@@ -115,3 +127,1402 @@ fn text() {
 fn tiling() {
     test_file("tiling", 0)
 }
+
+#[test]
+fn shared_image_assets() {
+    let (_dak, _virtual_output, _output, mut scene) = test_setup();
+
+    let path = std::path::Path::new("images/brock.png");
+    let first = scene.create_resource().unwrap();
+    let second = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_image(&first, path, dak::dom::Format::ARGB8888)
+        .unwrap();
+    scene
+        .define_resource_from_image(&second, path, dak::dom::Format::ARGB8888)
+        .unwrap();
+
+    // Resources can't be redefined while they are loading
+    assert!(scene
+        .define_resource_from_image(&first, path, dak::dom::Format::ARGB8888)
+        .is_err());
+
+    scene.wait_for_assets().unwrap();
+    let image = scene.d_resource_thundr_image.get_clone(&first).unwrap();
+    assert!(image == scene.d_resource_thundr_image.get_clone(&second).unwrap());
+
+    // Once loaded the image is shared without decoding it again
+    let third = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_image(&third, path, dak::dom::Format::ARGB8888)
+        .unwrap();
+    assert!(image == scene.d_resource_thundr_image.get_clone(&third).unwrap());
+}
+
+#[test]
+fn svg_resources() {
+    assert!(crate::svg::svg_needs_resize((16, 16), (32, 32)));
+    assert!(!crate::svg::svg_needs_resize((64, 64), (70, 56)));
+
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <el>
+    <width><constant>64</constant></width>
+    <height><constant>64</constant></height>
+   </el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let icon = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    let image_size = |scene: &dak::Scene, res: &dak::DakotaId| {
+        scene.d_resource_thundr_image.get(res).unwrap().get_size()
+    };
+
+    // The first raster uses the size from the file
+    let path = std::path::Path::new("images/circle.svg");
+    let res = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_image(&res, path, dak::dom::Format::ARGB8888)
+        .unwrap();
+    scene.wait_for_assets().unwrap();
+    assert_eq!(image_size(&scene, &res), (16, 16));
+
+    // Once laid out it is drawn at the element's size
+    scene.resource().set(&icon, res.clone());
+    scene.recompile(&virtual_output).unwrap();
+    scene.wait_for_assets().unwrap();
+    assert_eq!(image_size(&scene, &res), (64, 64));
+
+    // Small changes keep the current raster
+    scene.width().set(&icon, dak::dom::Value::Constant(70));
+    scene.recompile(&virtual_output).unwrap();
+    assert!(!scene.d_assets.has_pending());
+    assert_eq!(image_size(&scene, &res), (64, 64));
+
+    // Sizes which were already drawn are shared
+    scene.width().set(&icon, dak::dom::Value::Constant(16));
+    scene.height().set(&icon, dak::dom::Value::Constant(16));
+    scene.recompile(&virtual_output).unwrap();
+    assert!(!scene.d_assets.has_pending());
+    assert_eq!(image_size(&scene, &res), (16, 16));
+}
+
+#[test]
+fn animated_images() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <el>
+   </el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let el = scene.d_children.get_clone(&root).unwrap()[0].clone();
+
+    // This GIF is 4x4, and its second frame changes the bottom half
+    let path = std::path::Path::new("images/blink.gif");
+    let res = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_image(&res, path, dak::dom::Format::ARGB8888)
+        .unwrap();
+    scene.wait_for_assets().unwrap();
+    let start = scene.d_resource_animation.get(&res).unwrap().as_next;
+    let anim = scene
+        .d_resource_animation
+        .get(&res)
+        .unwrap()
+        .as_animation
+        .clone();
+    assert_eq!(anim.an_frames.len(), 2);
+    assert_eq!(
+        anim.an_frames[1].af_damage.bounding_box(),
+        Some(dak::Rect::new(0, 2, 4, 2))
+    );
+
+    // Animations are only played while they are shown
+    assert!(!scene.is_animating());
+    scene.resource().set(&el, res.clone());
+    scene.recompile(&virtual_output).unwrap();
+    assert!(scene.is_animating());
+    assert_eq!(scene.get_next_animation_time(), Some(start));
+
+    assert!(!scene
+        .update_animations_at(start - std::time::Duration::from_millis(1))
+        .unwrap());
+    assert!(scene.update_animations_at(start).unwrap());
+    assert_eq!(scene.d_resource_animation.get(&res).unwrap().as_frame, 1);
+    assert_eq!(
+        scene.get_next_animation_time(),
+        Some(start + anim.an_frames[1].af_delay)
+    );
+}
+
+#[test]
+fn video_frames() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <el>
+   </el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let video = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    let ms = std::time::Duration::from_millis;
+
+    // A gray 4x2 NV12 frame
+    let frame = |pts| {
+        dak::VideoFrame::from_mapped(ms(pts), dak::VideoFormat::NV12, 4, 2, vec![128; 12]).unwrap()
+    };
+    assert!(dak::VideoFrame::from_mapped(ms(0), dak::VideoFormat::I420, 4, 2, vec![0; 8]).is_err());
+
+    scene.create_video(&video).unwrap();
+    for pts in [0, 10, 20, 30] {
+        scene.video_push_frame(&video, frame(pts)).unwrap();
+    }
+    assert!(scene.video_push_frame(&video, frame(5)).is_err());
+    scene.recompile(&virtual_output).unwrap();
+
+    // Nothing is due while paused
+    assert_eq!(scene.get_next_video_time(), None);
+    scene.video_play(&video).unwrap();
+    let start = scene.get_next_video_time().unwrap();
+
+    // Late frames are dropped in favor of the newest one
+    assert!(scene.update_videos_at(start + ms(25)).unwrap());
+    assert_eq!(scene.video_queue_len(&video), 1);
+    assert_eq!(scene.video_dropped_frames(&video), 2);
+    let res = scene.d_resources.get_clone(&video).unwrap();
+    assert!(scene.is_resource_defined(&res));
+    assert_eq!(
+        scene.d_resource_thundr_image.get(&res).unwrap().get_size(),
+        (4, 2)
+    );
+    assert_eq!(scene.get_next_video_time(), Some(start + ms(30)));
+    assert!(!scene.update_videos_at(start + ms(29)).unwrap());
+
+    // Seeking releases the queued frames
+    scene.video_seek(&video, ms(1000)).unwrap();
+    assert_eq!(scene.video_queue_len(&video), 0);
+}
+
+#[test]
+fn nine_patch_slices() {
+    let patch = dak::dom::NinePatch {
+        left: 4,
+        right: 4,
+        top: 2,
+        bottom: 6,
+    };
+
+    // Corners keep their size and the center stretches
+    let slices = crate::render::nine_patch_slices(&patch, (16, 16), &th::Rect::new(10, 20, 40, 30));
+    assert_eq!(slices.len(), 9);
+    assert_eq!(
+        slices[0],
+        (th::Rect::new(10, 20, 4, 2), th::Rect::new(0, 0, 4, 2))
+    );
+    assert_eq!(
+        slices[4],
+        (th::Rect::new(14, 22, 32, 22), th::Rect::new(4, 2, 8, 8))
+    );
+    assert_eq!(
+        slices[8],
+        (th::Rect::new(46, 44, 4, 6), th::Rect::new(12, 10, 4, 6))
+    );
+
+    // Borders which don't fit are shrunk, and the empty center is skipped
+    let slices = crate::render::nine_patch_slices(&patch, (16, 16), &th::Rect::new(0, 0, 4, 30));
+    assert_eq!(slices.len(), 6);
+    assert_eq!(
+        slices[0],
+        (th::Rect::new(0, 0, 2, 2), th::Rect::new(0, 0, 4, 2))
+    );
+    assert_eq!(
+        slices[1],
+        (th::Rect::new(2, 0, 2, 2), th::Rect::new(12, 0, 4, 2))
+    );
+}
+
+#[test]
+fn styles() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_stylesheet(std::path::Path::new("../dakota-test/data/stylesheet.xml"))
+        .expect("Could not load stylesheet");
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <styles>
+  <style>
+   <name>wide</name>
+   <inherits>panel</inherits>
+   <size>
+    <width><constant>400</constant></width>
+   </size>
+  </style>
+ </styles>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <class>wide</class>
+  </el>
+  <el>
+   <class>wide</class>
+   <size>
+    <width><constant>50</constant></width>
+   </size>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let children = scene.d_children.get_clone(&root).unwrap();
+    scene.recompile(&virtual_output).unwrap();
+
+    // Properties are inherited, and overridden by the element's own
+    let width = |scene: &dak::Scene, el| scene.d_widths.get_clone(el).unwrap();
+    assert_eq!(width(&scene, &children[0]), dak::dom::Value::Constant(400));
+    assert_eq!(
+        scene.d_heights.get_clone(&children[0]),
+        Some(dak::dom::Value::Constant(100))
+    );
+    assert!(scene.d_resources.get(&children[0]).is_some());
+    assert_eq!(width(&scene, &children[1]), dak::dom::Value::Constant(50));
+
+    // Styles can be changed at runtime
+    let mut wide = scene.get_style("wide").unwrap().clone();
+    wide.width = Some(dak::dom::Value::Constant(300));
+    scene.define_style(wide);
+    assert!(scene.needs_refresh());
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(width(&scene, &children[0]), dak::dom::Value::Constant(300));
+    assert_eq!(width(&scene, &children[1]), dak::dom::Value::Constant(50));
+
+    // Removing a class removes the properties it set
+    scene.remove_class(&children[0], "wide");
+    scene.recompile(&virtual_output).unwrap();
+    assert!(scene.d_heights.get(&children[0]).is_none());
+}
+
+#[test]
+fn bound_list() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <size>
+    <width><constant>200</constant></width>
+    <height><constant>100</constant></height>
+   </size>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let list = scene.d_children.get_clone(&root).unwrap()[0].clone();
+
+    let updated = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let updated_clone = updated.clone();
+    let items: Vec<usize> = (0..1000).collect();
+    scene
+        .bind_list(&list, items, 20, move |_scene, _row, item| {
+            updated_clone.lock().unwrap().push(*item);
+            Ok(())
+        })
+        .unwrap();
+    scene.recompile(&virtual_output).unwrap();
+
+    // Only the visible rows and a few extra are created, along with
+    // the spacer
+    assert_eq!(*updated.lock().unwrap(), (0..8).collect::<Vec<usize>>());
+    assert_eq!(scene.d_children.get(&list).unwrap().len(), 9);
+    assert_eq!(
+        scene.d_viewports.get(&list).unwrap().scroll_region,
+        (200, 20000)
+    );
+
+    // Scrolling reuses the hidden rows for the newly visible items
+    updated.lock().unwrap().clear();
+    scene
+        .d_viewports
+        .get_mut(&list)
+        .unwrap()
+        .update_scroll_amount(0, 400);
+    scene.handle_list_scroll(&list);
+    assert!(scene.needs_refresh());
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(*updated.lock().unwrap(), (18..28).collect::<Vec<usize>>());
+    assert_eq!(scene.d_children.get(&list).unwrap().len(), 11);
+    assert_eq!(
+        scene.d_viewports.get(&list).unwrap().scroll_offset,
+        (0, -400)
+    );
+}
+
+#[test]
+fn accessibility_tree() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <offset>
+    <x><constant>10</constant></x>
+    <y><constant>20</constant></y>
+   </offset>
+   <el>
+    <role>button</role>
+    <label>Ok</label>
+    <offset>
+     <x><constant>5</constant></x>
+     <y><constant>5</constant></y>
+    </offset>
+    <size>
+     <width><constant>64</constant></width>
+     <height><constant>32</constant></height>
+    </size>
+   </el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let container = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    let button = scene.d_children.get_clone(&container).unwrap()[0].clone();
+    scene.recompile(&virtual_output).unwrap();
+
+    // The unlabeled container is left out, and its button is attached
+    // to the window
+    let update = scene.take_access_update().unwrap();
+    let root_id = root.get_raw_id() as u64;
+    let button_id = button.get_raw_id() as u64;
+    assert_eq!(update.au_root, root_id);
+    assert_eq!(update.au_nodes.len(), 2);
+    let window = update.au_nodes.iter().find(|n| n.an_id == root_id).unwrap();
+    assert_eq!(window.an_role, dak::dom::Role::Window);
+    assert_eq!(window.an_children, vec![button_id]);
+    let node = update
+        .au_nodes
+        .iter()
+        .find(|n| n.an_id == button_id)
+        .unwrap();
+    assert_eq!(node.an_role, dak::dom::Role::Button);
+    assert_eq!(node.an_name.as_deref(), Some("Ok"));
+    assert_eq!(node.an_bounds, utils::region::Rect::new(15, 25, 64, 32));
+
+    // Nothing changed, so there is no update
+    scene.recompile(&virtual_output).unwrap();
+    assert!(scene.take_access_update().is_none());
+
+    // Only the focus and the renamed node are reported
+    scene.set_focus(Some(&button));
+    scene.d_labels.set(&button, "Cancel".to_string());
+    scene.recompile(&virtual_output).unwrap();
+    let update = scene.take_access_update().unwrap();
+    assert_eq!(update.au_focus, Some(button_id));
+    assert_eq!(update.au_nodes.len(), 1);
+    assert_eq!(update.au_nodes[0].an_name.as_deref(), Some("Cancel"));
+}
+
+#[test]
+fn text_spans() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <resourceMap>
+  <define_font>
+   <name>Bold</name>
+   <font_name>JetBrainsMono</font_name>
+   <font_style>Bold</font_style>
+   <pixel_size>24</pixel_size>
+  </define_font>
+ </resourceMap>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <text>
+    <p>Regular</p>
+    <span><font>Bold</font>Bold</span>
+   </text>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let el = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    scene.recompile(&virtual_output).unwrap();
+
+    let text = scene.d_texts.get_clone(&el).unwrap();
+    let (regular, bold) = match text.items.as_slice() {
+        [dak::dom::TextItem::p(regular), dak::dom::TextItem::span(bold)] => (regular, bold),
+        items => panic!("Unexpected text items {:?}", items),
+    };
+    let bold_font = bold.font.clone().expect("Span did not get its font");
+    assert_eq!(scene.d_fonts.get(&bold_font).unwrap().pixel_size, 24);
+
+    // Each run's glyphs are drawn with the run's font
+    for ch in regular.cache.as_ref().unwrap().iter() {
+        assert_ne!(*scene.d_text_font.get(&ch.node).unwrap(), bold_font);
+    }
+    for ch in bold.cache.as_ref().unwrap().iter() {
+        assert_eq!(*scene.d_text_font.get(&ch.node).unwrap(), bold_font);
+    }
+    let chars: String = bold.cache.as_ref().unwrap().iter().map(|c| c.ch).collect();
+    assert_eq!(chars, "Bold ");
+}
+
+#[test]
+fn glyph_atlas() {
+    use crate::atlas::{GlyphAtlas, ATLAS_MAX_HEIGHT, ATLAS_WIDTH};
+
+    let mut ecs = ll::Instance::new();
+    let mut atlas = GlyphAtlas::new(&mut ecs);
+    let bitmap = vec![255; 100 * 100 * 4];
+
+    // Glyphs of the same size share a shelf
+    let a = ecs.add_entity();
+    let b = ecs.add_entity();
+    atlas.next_generation();
+    let rect_a = atlas.add_glyph(&a, 100, 100, &bitmap).unwrap();
+    let rect_b = atlas.add_glyph(&b, 100, 100, &bitmap).unwrap();
+    assert_eq!(rect_a, th::Rect::new(0, 0, 100, 100));
+    assert_eq!(rect_b, th::Rect::new(101, 0, 100, 100));
+    assert_eq!(atlas.add_glyph(&a, 100, 100, &bitmap), Some(rect_a));
+
+    // Fill the atlas. Glyphs used in this generation can't be evicted.
+    let per_shelf = (ATLAS_WIDTH / 101) as usize;
+    let capacity = per_shelf * (ATLAS_MAX_HEIGHT / 101) as usize;
+    let mut glyphs = Vec::new();
+    for _ in 2..capacity {
+        let id = ecs.add_entity();
+        assert!(atlas.add_glyph(&id, 100, 100, &bitmap).is_some());
+        glyphs.push(id);
+    }
+    let extra = ecs.add_entity();
+    assert!(atlas.add_glyph(&extra, 100, 100, &bitmap).is_none());
+
+    // Keep using everything but the first shelf, which is then evicted
+    atlas.next_generation();
+    for id in glyphs[per_shelf - 2..].iter() {
+        assert!(atlas.add_glyph(id, 100, 100, &bitmap).is_some());
+    }
+    assert_eq!(
+        atlas.add_glyph(&extra, 100, 100, &bitmap),
+        Some(th::Rect::new(0, 0, 100, 100))
+    );
+    assert_eq!(atlas.get_rect(&a), None);
+    assert_eq!(atlas.get_rect(&b), None);
+}
+
+#[test]
+fn subpixel_text() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <text>
+    <p>Subpixel</p>
+   </text>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let el = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    scene.recompile(&virtual_output).unwrap();
+
+    let get_glyphs = |scene: &dak::Scene| -> Vec<dak::DakotaId> {
+        let text = scene.d_texts.get_clone(&el).unwrap();
+        match text.items.as_slice() {
+            [dak::dom::TextItem::p(run)] => run
+                .cache
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|ch| ch.glyph_id.clone())
+                .collect(),
+            items => panic!("Unexpected text items {:?}", items),
+        }
+    };
+    let before = get_glyphs(&scene);
+    for id in before.iter() {
+        assert!(!scene.d_glyphs.get(id).unwrap().g_subpixel);
+    }
+
+    // Glyphs are rasterized again in place, keeping their ids
+    scene.set_subpixel_layout(dak::SubpixelLayout::Rgb);
+    assert!(scene.needs_refresh());
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(get_glyphs(&scene), before);
+
+    for id in before.iter() {
+        let glyph = scene.d_glyphs.get(id).unwrap();
+        if let Some(bitmap) = glyph.g_bitmap.as_ref() {
+            assert!(glyph.g_subpixel);
+            let (width, height) = glyph.g_bitmap_size;
+            assert_eq!(bitmap.len(), (width * height * 4) as usize);
+        }
+    }
+}
+
+#[test]
+fn text_scale() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <text>
+    <p>Scaled</p>
+   </text>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let el = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    scene.recompile(&virtual_output).unwrap();
+
+    let get_glyphs = |scene: &dak::Scene| -> Vec<dak::DakotaId> {
+        let text = scene.d_texts.get_clone(&el).unwrap();
+        match text.items.as_slice() {
+            [dak::dom::TextItem::p(run)] => run
+                .cache
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|ch| ch.glyph_id.clone())
+                .collect(),
+            items => panic!("Unexpected text items {:?}", items),
+        }
+    };
+    let get_height = |scene: &dak::Scene, glyphs: &[dak::DakotaId]| -> i32 {
+        glyphs
+            .iter()
+            .map(|id| scene.d_glyphs.get(id).unwrap().g_bitmap_size.1)
+            .max()
+            .unwrap()
+    };
+    let before = get_glyphs(&scene);
+    let height = get_height(&scene, &before);
+
+    // Glyphs are rasterized again in place at the larger size
+    scene.set_text_scale(2.0);
+    assert_eq!(scene.get_text_scale(), 2.0);
+    assert!(scene.needs_refresh());
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(get_glyphs(&scene), before);
+    assert!(get_height(&scene, &before) > height);
+}
+
+#[test]
+fn popups() {
+    let (_dak, mut virtual_output, _output, mut scene) = test_setup();
+    virtual_output.set_size((640, 480));
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <offset>
+    <x><constant>600</constant></x>
+    <y><constant>440</constant></y>
+   </offset>
+   <size>
+    <width><constant>30</constant></width>
+    <height><constant>30</constant></height>
+   </size>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let button = scene.d_children.get_clone(&root).unwrap()[0].clone();
+
+    let menu = scene.create_element().unwrap();
+    scene.d_widths.set(&menu, dak::dom::Value::Constant(100));
+    scene.d_heights.set(&menu, dak::dom::Value::Constant(80));
+    scene.show_popup(
+        &menu,
+        dak::dom::Popup {
+            anchor: button.clone(),
+            placement: dak::dom::PopupPlacement::Below,
+            dismiss_on_outside_click: true,
+        },
+    );
+    assert!(scene.needs_refresh());
+    scene.recompile(&virtual_output).unwrap();
+
+    // There is no room below the button, so the menu is flipped above it
+    // and kept inside the window
+    let layout = scene.d_layout_nodes.get_clone(&menu).unwrap();
+    assert_eq!((layout.l_offset.x, layout.l_offset.y), (540, 360));
+    assert_eq!(scene.get_viewport_at_position(560, 400), menu);
+
+    // Clicking inside the menu keeps it open, clicking outside hides it
+    assert!(virtual_output
+        .handle_click(&mut scene, (560, 400))
+        .is_empty());
+    assert_eq!(
+        virtual_output.handle_click(&mut scene, (10, 10)),
+        vec![menu.clone()]
+    );
+    assert!(scene.get_popups().is_empty());
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(scene.get_viewport_at_position(560, 400), root);
+}
+
+#[test]
+fn scrollbars_and_kinetic_scrolling() {
+    let (_dak, mut virtual_output, _output, mut scene) = test_setup();
+    virtual_output.set_size((640, 480));
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <scrollbar/>
+   <size>
+    <width><constant>100</constant></width>
+    <height><constant>100</constant></height>
+   </size>
+   <el>
+    <size>
+     <width><constant>100</constant></width>
+     <height><constant>400</constant></height>
+    </size>
+   </el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let view = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    scene.recompile(&virtual_output).unwrap();
+
+    // Only the vertical axis overflows
+    let bars = crate::scene::get_scrollbars(
+        &utils::region::Rect::new(0, 0, 100, 100),
+        &scene.d_viewports.get_clone(&view).unwrap(),
+    );
+    assert_eq!(bars.len(), 1);
+    assert_eq!(bars[0].sc_track, utils::region::Rect::new(92, 0, 8, 100));
+    assert_eq!(bars[0].sc_thumb, utils::region::Rect::new(92, 0, 8, 25));
+
+    // Dragging the thumb scrolls by the ratio of content to track
+    virtual_output.handle_click(&mut scene, (95, 10));
+    assert!(scene.is_dragging_scrollbar());
+    assert!(virtual_output.handle_mouse_move(&mut scene, 0, 15));
+    assert_eq!(
+        scene.d_viewports.get(&view).unwrap().scroll_offset,
+        (0, -60)
+    );
+    virtual_output.handle_click_release(&mut scene, (95, 25));
+    assert!(!scene.is_dragging_scrollbar());
+
+    // Clicking the track jumps the thumb there
+    virtual_output.handle_click(&mut scene, (95, 90));
+    virtual_output.handle_click_release(&mut scene, (95, 90));
+    assert_eq!(
+        scene.d_viewports.get(&view).unwrap().scroll_offset,
+        (0, -300)
+    );
+
+    // Lifting the fingers while moving 1000 pixels per second flings
+    scene.d_viewports.get_mut(&view).unwrap().scroll_offset = (0, 0);
+    let start = std::time::Instant::now();
+    let ms = |n| start + std::time::Duration::from_millis(n);
+    for i in 0..5 {
+        scene.handle_finger_scroll(&view, (0, 10), ms(i * 10));
+    }
+    scene.handle_finger_scroll(&view, (0, 0), ms(50));
+    assert!(scene.is_kinetic_scrolling());
+
+    let mut time = 50;
+    while scene.is_kinetic_scrolling() && time < 5000 {
+        time += 16;
+        assert!(scene.update_kinetic_scroll_at(ms(time)));
+    }
+    assert!(!scene.is_kinetic_scrolling());
+    // The fling travels velocity / friction pixels before stopping
+    let offset = scene.d_viewports.get(&view).unwrap().scroll_offset.1;
+    assert!((-250..=-230).contains(&offset), "offset was {}", offset);
+}
+
+#[test]
+fn event_history_velocity() {
+    let mut history = dak::EventHistory::new();
+    assert_eq!(history.velocity(0), (0.0, 0.0));
+
+    // 10 pixels every 10ms is 1000 pixels per second. The first sample
+    // only marks the start of the motion.
+    for i in 0..5 {
+        history.push(i * 10_000, (0.0, 10.0));
+    }
+    assert_eq!(history.last_time(), Some(40_000));
+    assert_eq!(history.velocity(40_000), (0.0, 1000.0));
+
+    // Old motion is ignored
+    history.push(500_000, (5.0, 0.0));
+    assert_eq!(history.velocity(500_000), (0.0, 0.0));
+    history.push(510_000, (5.0, 0.0));
+    assert_eq!(history.velocity(510_000), (500.0, 0.0));
+
+    history.clear();
+    assert_eq!(history.samples().count(), 0);
+}
+
+#[test]
+fn input_config_device_overrides() {
+    let config = dak::InputConfig {
+        default: dak::DeviceSettings {
+            tap_to_click: Some(true),
+            accel_speed: Some(0.5),
+            ..Default::default()
+        },
+        devices: vec![(
+            "Touchpad".to_string(),
+            dak::DeviceSettings {
+                accel_profile: Some(dak::AccelProfile::Flat),
+                accel_speed: Some(-0.25),
+                ..Default::default()
+            },
+        )],
+    };
+
+    // Devices without an override get the defaults
+    assert_eq!(config.get_device_settings("USB Mouse"), config.default);
+
+    let touchpad = config.get_device_settings("SynPS/2 Synaptics Touchpad");
+    assert_eq!(touchpad.tap_to_click, Some(true));
+    assert_eq!(touchpad.accel_profile, Some(dak::AccelProfile::Flat));
+    assert_eq!(touchpad.accel_speed, Some(-0.25));
+    assert_eq!(touchpad.natural_scroll, None);
+}
+
+#[test]
+fn input_config_device_seats() {
+    let config = dak::InputConfig {
+        default: dak::DeviceSettings::default(),
+        devices: vec![
+            (
+                "Keyboard".to_string(),
+                dak::DeviceSettings {
+                    seat: Some("kiosk".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "Logitech".to_string(),
+                dak::DeviceSettings {
+                    natural_scroll: Some(true),
+                    ..Default::default()
+                },
+            ),
+        ],
+    };
+
+    // Devices stay on the default seat unless given another
+    assert_eq!(config.get_device_settings("USB Mouse").seat, None);
+    // Later overrides without a seat don't move the device back
+    let keyboard = config.get_device_settings("Logitech Keyboard K120");
+    assert_eq!(keyboard.seat.as_deref(), Some("kiosk"));
+    assert_eq!(keyboard.natural_scroll, Some(true));
+}
+
+#[test]
+fn touch_hit_testing_and_scrolling() {
+    let (_dak, mut virtual_output, _output, mut scene) = test_setup();
+    virtual_output.set_size((640, 480));
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+   <viewport/>
+   <size>
+    <width><constant>100</constant></width>
+    <height><constant>100</constant></height>
+   </size>
+   <el>
+    <size>
+     <width><constant>100</constant></width>
+     <height><constant>400</constant></height>
+    </size>
+   </el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let view = scene.d_children.get_clone(&root).unwrap()[0].clone();
+    let content = scene.d_children.get_clone(&view).unwrap()[0].clone();
+    scene.recompile(&virtual_output).unwrap();
+
+    // The top-most element is hit, and outside of it we hit the window
+    assert_eq!(scene.get_element_at_position(50, 50), Some(content.clone()));
+    assert_eq!(scene.get_element_at_position(300, 300), Some(root.clone()));
+
+    // Dragging one finger up scrolls the content up
+    assert_eq!(
+        virtual_output.handle_touch_down(&mut scene, 0, (50, 50)),
+        Some(content.clone())
+    );
+    assert_eq!(scene.get_touched_element(0), Some(content.clone()));
+    assert!(virtual_output.handle_touch_motion(&mut scene, 0, (50, 30)));
+    assert_eq!(
+        scene.d_viewports.get(&view).unwrap().scroll_offset,
+        (0, -20)
+    );
+
+    // Nothing scrolls while a second finger is down
+    virtual_output.handle_touch_down(&mut scene, 1, (60, 60));
+    assert!(!virtual_output.handle_touch_motion(&mut scene, 0, (50, 10)));
+    assert_eq!(
+        scene.d_viewports.get(&view).unwrap().scroll_offset,
+        (0, -20)
+    );
+
+    assert_eq!(
+        virtual_output.handle_touch_up(&mut scene, 1),
+        Some(content.clone())
+    );
+    assert_eq!(virtual_output.handle_touch_up(&mut scene, 0), Some(content));
+    assert_eq!(scene.get_touched_element(0), None);
+    assert!(!scene.is_kinetic_scrolling());
+}
+
+#[test]
+fn injected_input() {
+    let mut dak = dak::Dakota::new().expect("Could not create Dakota");
+    let mut virtual_output = dak
+        .create_virtual_output()
+        .expect("Failed to create Dakota Virtual Output Surface");
+    virtual_output.set_size((640, 480));
+
+    // Injected events are queued in order like real ones
+    virtual_output.inject_mouse_move(10, 20);
+    virtual_output.inject_mouse_button(dak::MouseButton::LEFT, true);
+    virtual_output.inject_key(dak::Keycode::A, dak::RawKeycode::Linux(30), "a", true);
+    virtual_output.inject_key(dak::Keycode::A, dak::RawKeycode::Linux(30), "a", false);
+    virtual_output.inject_touch_down(0, 5, 5);
+    virtual_output.inject_touch_motion(0, 6, 7);
+    virtual_output.inject_touch_up(0);
+    // Fingers which never touched down are ignored
+    virtual_output.inject_touch_up(1);
+
+    match virtual_output.pop_event() {
+        Some(dak::PlatformEvent::InputMouseMove { dx: 10, dy: 20, .. }) => {}
+        e => panic!("Expected mouse motion, got {:?}", e),
+    }
+    match virtual_output.pop_event() {
+        Some(dak::PlatformEvent::InputMouseButtonDown { x, y, .. }) => assert_eq!((x, y), (10, 20)),
+        e => panic!("Expected a button press, got {:?}", e),
+    }
+    match virtual_output.pop_event() {
+        Some(dak::PlatformEvent::InputKeyDown { utf8, .. }) => assert_eq!(utf8, "a"),
+        e => panic!("Expected a key press, got {:?}", e),
+    }
+    match virtual_output.pop_event() {
+        Some(dak::PlatformEvent::InputKeyUp { utf8, .. }) => assert!(utf8.is_empty()),
+        e => panic!("Expected a key release, got {:?}", e),
+    }
+    assert!(matches!(
+        virtual_output.pop_event(),
+        Some(dak::PlatformEvent::InputTouchDown { id: 0, .. })
+    ));
+    assert!(matches!(
+        virtual_output.pop_event(),
+        Some(dak::PlatformEvent::InputTouchMotion {
+            id: 0,
+            x: 6,
+            y: 7,
+            ..
+        })
+    ));
+    match virtual_output.pop_event() {
+        Some(dak::PlatformEvent::InputTouchUp { id, x, y, .. }) => {
+            assert_eq!((id, x, y), (0, 6, 7))
+        }
+        e => panic!("Expected a touch release, got {:?}", e),
+    }
+    assert!(virtual_output.pop_event().is_none());
+}
+
+#[test]
+fn canvas_drawing() {
+    let (_dak, virtual_output, _output, mut scene) = test_setup();
+
+    scene
+        .load_xml_str(
+            r#"<dakota>
+ <version>0.0.0.1</version>
+ <window>
+  <title>Dakota Test</title>
+ </window>
+ <layout>
+  <el>
+  </el>
+ </layout>
+</dakota>"#,
+        )
+        .expect("Could not parse XML dakota string");
+    let root = scene.d_dom.as_ref().unwrap().root_element.clone();
+    let canvas = scene.d_children.get_clone(&root).unwrap()[0].clone();
+
+    // Get the alpha of a canvas pixel
+    let alpha = |scene: &dak::Scene, x: usize, y: usize| {
+        scene.d_canvases.get(&canvas).unwrap().cv_bytes[(y * 40 + x) * 4 + 3]
+    };
+
+    scene.create_canvas(&canvas, 40, 20).unwrap();
+    assert_eq!(
+        scene.d_heights.get_clone(&canvas),
+        Some(dak::dom::Value::Constant(20))
+    );
+    let red = dak::dom::Color::new(1.0, 0.0, 0.0, 1.0);
+    let bar = scene
+        .canvas_draw(
+            &canvas,
+            dak::CanvasCommand::Fill {
+                path: dak::CanvasPath::new().rect(2.0, 2.0, 10.0, 10.0),
+                color: red,
+                rule: dak::FillRule::NonZero,
+            },
+        )
+        .unwrap();
+    scene
+        .canvas_draw(
+            &canvas,
+            dak::CanvasCommand::Stroke {
+                path: dak::CanvasPath::new()
+                    .move_to(20.0, 10.5)
+                    .line_to(36.0, 10.5),
+                color: red,
+                width: 3.0,
+            },
+        )
+        .unwrap();
+    assert!(scene.needs_refresh());
+    scene.recompile(&virtual_output).unwrap();
+
+    let res = scene.d_resources.get_clone(&canvas).unwrap();
+    assert!(scene.is_resource_defined(&res));
+    assert_eq!(alpha(&scene, 5, 5), 255);
+    assert_eq!(alpha(&scene, 25, 10), 255);
+    assert_eq!(alpha(&scene, 15, 5), 0);
+    assert_eq!(alpha(&scene, 25, 5), 0);
+
+    // Replacing a command only redraws the area it covered and covers now
+    scene
+        .canvas_replace(
+            &canvas,
+            bar,
+            dak::CanvasCommand::Fill {
+                path: dak::CanvasPath::new().rect(2.0, 8.0, 10.0, 4.0),
+                color: red,
+                rule: dak::FillRule::NonZero,
+            },
+        )
+        .unwrap();
+    let damage = scene.d_canvases.get(&canvas).unwrap().cv_damage.clone();
+    assert_eq!(damage.bounding_box(), Some(dak::Rect::new(1, 1, 12, 12)));
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(alpha(&scene, 5, 5), 0);
+    assert_eq!(alpha(&scene, 5, 10), 255);
+    assert_eq!(alpha(&scene, 25, 10), 255);
+
+    scene.canvas_remove(&canvas, bar).unwrap();
+    assert!(scene.canvas_remove(&canvas, bar).is_err());
+    scene.recompile(&virtual_output).unwrap();
+    assert_eq!(alpha(&scene, 5, 10), 0);
+}
+
+#[test]
+fn color_resources() {
+    let (_dak, _virtual_output, _output, mut scene) = test_setup();
+
+    let res = scene.create_resource().unwrap();
+    let color = dak::dom::Color::new(0.0, 0.5, 1.0, 1.0);
+    scene.define_resource_from_color(&res, color).unwrap();
+    assert!(scene.is_resource_defined(&res));
+    assert_eq!(scene.d_resource_color.get_clone(&res), Some(color));
+
+    // Color resources don't get an image, and can't be given one
+    assert!(scene.d_resource_thundr_image.get(&res).is_none());
+    assert!(scene
+        .define_resource_from_bits(&res, &[0; 4], 1, 1, 0, dak::dom::Format::ARGB8888)
+        .is_err());
+    assert!(scene.define_resource_from_color(&res, color).is_err());
+}
+
+#[test]
+fn element_damage() {
+    use dak::dom;
+
+    let (_dak, mut virtual_output, mut output, mut scene) = test_setup();
+
+    let root = scene.create_element().unwrap();
+    scene.set_dakota_dom(dom::DakotaDOM {
+        version: "0.0.1".to_string(),
+        window: dom::Window {
+            title: "Dakota unit test".to_string(),
+            size: Some((640, 480)),
+            events: dom::WindowEvents {
+                resize: None,
+                redraw_complete: None,
+                closed: None,
+            },
+        },
+        root_element: root.clone(),
+    });
+    output.set_resolution(&mut scene, 640, 480).unwrap();
+    virtual_output.set_size((640, 480));
+
+    // Two boxes placed away from each other
+    let red = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_color(&red, dom::Color::new(1.0, 0.0, 0.0, 1.0))
+        .unwrap();
+    let add_box = |scene: &mut dak::Scene, x, y| {
+        let el = scene.create_element().unwrap();
+        scene.add_child_to_element(&root, el.clone());
+        scene.offset().set(
+            &el,
+            dom::RelativeOffset {
+                x: dom::Value::Constant(x),
+                y: dom::Value::Constant(y),
+            },
+        );
+        scene.width().set(&el, dom::Value::Constant(32));
+        scene.height().set(&el, dom::Value::Constant(32));
+        scene.resource().set(&el, red.clone());
+        el
+    };
+    let first = add_box(&mut scene, 0, 0);
+    add_box(&mut scene, 200, 200);
+
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    let stats = output.get_frame_stats();
+    assert_eq!(stats.fs_frame_number, 2);
+    assert!(stats.fs_damage.is_empty());
+    assert_eq!(stats.fs_surfaces_added, 0);
+    assert_eq!(stats.fs_surfaces_changed, 0);
+
+    // Removing the first box doesn't damage the one drawn after it
+    scene.remove_child_from_element(&root, &first).unwrap();
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    let stats = output.get_frame_stats();
+    assert_eq!(stats.fs_surfaces_removed, 1);
+    assert_eq!(stats.fs_surfaces_added, 0);
+    assert_eq!(stats.fs_surfaces_changed, 0);
+    let damage = &stats.fs_damage;
+    assert!(!damage.is_empty());
+    assert!(damage
+        .iter()
+        .all(|r| r.intersection(&dak::Rect::new(200, 200, 32, 32)).is_none()));
+}
+
+#[test]
+fn element_transforms() {
+    use dak::dom;
+
+    let (_dak, mut virtual_output, mut output, mut scene) = test_setup();
+
+    let root = scene.create_element().unwrap();
+    scene.set_dakota_dom(dom::DakotaDOM {
+        version: "0.0.1".to_string(),
+        window: dom::Window {
+            title: "Dakota unit test".to_string(),
+            size: Some((640, 480)),
+            events: dom::WindowEvents {
+                resize: None,
+                redraw_complete: None,
+                closed: None,
+            },
+        },
+        root_element: root.clone(),
+    });
+    output.set_resolution(&mut scene, 640, 480).unwrap();
+    virtual_output.set_size((640, 480));
+
+    let red = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_color(&red, dom::Color::new(1.0, 0.0, 0.0, 1.0))
+        .unwrap();
+    let add_box = |scene: &mut dak::Scene, parent: &dak::DakotaId, pos, size| {
+        let el = scene.create_element().unwrap();
+        scene.add_child_to_element(parent, el.clone());
+        scene.offset().set(
+            &el,
+            dom::RelativeOffset {
+                x: dom::Value::Constant(pos),
+                y: dom::Value::Constant(pos),
+            },
+        );
+        scene.width().set(&el, dom::Value::Constant(size));
+        scene.height().set(&el, dom::Value::Constant(size));
+        scene.resource().set(&el, red.clone());
+        el
+    };
+    let parent = add_box(&mut scene, &root, 100, 200);
+    add_box(&mut scene, &parent, 100, 100);
+
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+
+    // Shrinking the parent moves its child towards the parent's corner,
+    // everything is drawn within the parent's old area
+    scene
+        .transform()
+        .set(&parent, dak::Transform::scale(0.5, 0.5));
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    let stats = output.get_frame_stats();
+    assert_eq!(stats.fs_surfaces_changed, 2);
+    let damage = &stats.fs_damage;
+    assert!(!damage.is_empty());
+    assert!(damage
+        .iter()
+        .all(|r| dak::Rect::new(100, 100, 200, 200).intersection(r) == Some(*r)));
+    // The child's new area, now at (150, 150) with a size of 50
+    assert!(damage
+        .iter()
+        .any(|r| r.intersection(&dak::Rect::new(150, 150, 50, 50)).is_some()));
+}
+
+#[test]
+fn element_tints() {
+    use dak::dom;
+
+    let (_dak, mut virtual_output, mut output, mut scene) = test_setup();
+
+    let root = scene.create_element().unwrap();
+    scene.set_dakota_dom(dom::DakotaDOM {
+        version: "0.0.1".to_string(),
+        window: dom::Window {
+            title: "Dakota unit test".to_string(),
+            size: Some((640, 480)),
+            events: dom::WindowEvents {
+                resize: None,
+                redraw_complete: None,
+                closed: None,
+            },
+        },
+        root_element: root.clone(),
+    });
+    output.set_resolution(&mut scene, 640, 480).unwrap();
+    virtual_output.set_size((640, 480));
+
+    let red = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_color(&red, dom::Color::new(1.0, 0.0, 0.0, 1.0))
+        .unwrap();
+    let add_box = |scene: &mut dak::Scene, parent: &dak::DakotaId, pos, size| {
+        let el = scene.create_element().unwrap();
+        scene.add_child_to_element(parent, el.clone());
+        scene.offset().set(
+            &el,
+            dom::RelativeOffset {
+                x: dom::Value::Constant(pos),
+                y: dom::Value::Constant(pos),
+            },
+        );
+        scene.width().set(&el, dom::Value::Constant(size));
+        scene.height().set(&el, dom::Value::Constant(size));
+        scene.resource().set(&el, red.clone());
+        el
+    };
+    let parent = add_box(&mut scene, &root, 100, 200);
+    add_box(&mut scene, &parent, 50, 50);
+    let other = add_box(&mut scene, &root, 400, 50);
+
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+
+    // Tinting the parent also tints its child, and nothing else
+    scene.tint().set(&parent, (0.5, 0.5, 0.5));
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    let stats = output.get_frame_stats();
+    assert_eq!(stats.fs_surfaces_changed, 2);
+    assert!(stats
+        .fs_damage
+        .iter()
+        .all(|r| dak::Rect::new(100, 100, 200, 200).intersection(r) == Some(*r)));
+
+    // Setting the same tint again doesn't redraw anything
+    scene.tint().set(&parent, (0.5, 0.5, 0.5));
+    scene.tint().set(&other, (1.0, 1.0, 1.0));
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    let stats = output.get_frame_stats();
+    assert!(stats.fs_damage.is_empty());
+    assert_eq!(stats.fs_surfaces_changed, 0);
+}
+
+#[test]
+fn device_loss_recovery() {
+    use dak::dom;
+
+    let mut dak = dak::Dakota::new().expect("Could not create Dakota");
+    dak.set_sample_count(4);
+    let mut virtual_output = dak
+        .create_virtual_output()
+        .expect("Failed to create Dakota Virtual Output Surface");
+    let mut output = dak
+        .create_output(&virtual_output)
+        .expect("Failed to create Dakota Output");
+    let mut scene = output
+        .create_scene(&virtual_output)
+        .expect("Could not create scene");
+
+    let root = scene.create_element().unwrap();
+    scene.set_dakota_dom(dom::DakotaDOM {
+        version: "0.0.1".to_string(),
+        window: dom::Window {
+            title: "Dakota unit test".to_string(),
+            size: Some((640, 480)),
+            events: dom::WindowEvents {
+                resize: None,
+                redraw_complete: None,
+                closed: None,
+            },
+        },
+        root_element: root.clone(),
+    });
+    output.set_resolution(&mut scene, 640, 480).unwrap();
+    virtual_output.set_size((640, 480));
+
+    let red = scene.create_resource().unwrap();
+    scene
+        .define_resource_from_color(&red, dom::Color::new(1.0, 0.0, 0.0, 1.0))
+        .unwrap();
+    scene.width().set(&root, dom::Value::Constant(32));
+    scene.height().set(&root, dom::Value::Constant(32));
+    scene.resource().set(&root, red.clone());
+
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    let sample_count = output.get_sample_count();
+
+    // Losing the GPU is reported as an event instead of an error
+    output.d_display.d_dev.simulate_device_loss();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    assert!(std::iter::from_fn(|| output.pop_event())
+        .any(|ev| matches!(ev, dak::OutputEvent::DeviceLost)));
+
+    // We keep drawing with the same settings on the new device
+    dak.recover_from_device_loss(&mut output, &mut scene)
+        .unwrap();
+    assert!(!output.d_display.d_dev.is_lost());
+    assert_eq!(output.get_sample_count(), sample_count);
+
+    scene
+        .define_resource_from_color(&red, dom::Color::new(1.0, 0.0, 0.0, 1.0))
+        .unwrap();
+    scene.recompile(&virtual_output).unwrap();
+    output.redraw(&virtual_output, &mut scene).unwrap();
+    assert!(!std::iter::from_fn(|| output.pop_event())
+        .any(|ev| matches!(ev, dak::OutputEvent::DeviceLost)));
+}
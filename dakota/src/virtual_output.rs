@@ -4,11 +4,16 @@
 /// Scene can be layed out. Some or all of it will be presented
 /// using an Output.
 // Austin Shafer - 2024
-use crate::event::PlatformEventSystem;
-use crate::{OutputId, PlatformEvent, Scene};
+use crate::event::{EventHistory, InputDevice, PlatformEventSystem};
+use crate::input::Mods;
+use crate::{
+    AxisSource, DakotaId, Keycode, MouseButton, OutputId, PlatformEvent, RawKeycode, Scene,
+};
+use utils::timing::get_monotonic_micros;
 use utils::{log, Result};
 
 use std::ops::DerefMut;
+use std::time::Instant;
 
 /// Virtual Output Surface
 ///
@@ -53,6 +58,10 @@ impl VirtualOutput {
     /// Set the size of this virtual surface
     pub fn set_size(&mut self, size: (u32, u32)) {
         self.d_size = size;
+        self.d_platform_event_system
+            .get_mut(&self.d_id)
+            .unwrap()
+            .set_output_size(size);
     }
 
     /// Get the next currently unhandled event
@@ -66,28 +75,207 @@ impl VirtualOutput {
             .pop_event()
     }
 
+    /// Get the recent motion of an input device
+    ///
+    /// This can be used to estimate how fast the device is moving, such
+    /// as when a gesture ends.
+    pub fn get_event_history(&self, device: InputDevice) -> EventHistory {
+        self.d_platform_event_system
+            .get(&self.d_id)
+            .unwrap()
+            .get_history(device)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     /// Handle dakota-only events coming from the event system
     ///
-    /// Most notably this handles scrolling
+    /// Most notably this handles scrolling. Finger scrolling from
+    /// touchpads keeps going after the fingers are lifted, in which case
+    /// `Scene::update_kinetic_scroll` should be called every frame while
+    /// `Scene::is_kinetic_scrolling` is true.
     pub fn handle_scrolling(
         &mut self,
         scene: &mut Scene,
         position: (i32, i32),
         relative_scroll: (i32, i32),
+        source: AxisSource,
     ) -> Result<()> {
         // Update our mouse
         self.d_mouse_pos = position;
 
-        {
-            // Find viewport at this location
-            let node = scene.get_viewport_at_position(self.d_mouse_pos.0, self.d_mouse_pos.1);
-            let mut viewport = scene.d_viewports.get_mut(&node).unwrap();
-            log::error!("original_scroll_offset: {:?}", viewport.scroll_offset);
+        // Find viewport at this location
+        let node = scene.get_viewport_at_position(self.d_mouse_pos.0, self.d_mouse_pos.1);
+        scene.scroll_viewport(&node, relative_scroll);
+        log::debug!(
+            "new_scroll_offset: {:?}",
+            scene.d_viewports.get(&node).unwrap().scroll_offset
+        );
 
-            viewport.update_scroll_amount(relative_scroll.0, relative_scroll.1);
-            log::error!("new_scroll_offset: {:?}", viewport.scroll_offset);
+        match source {
+            AxisSource::Finger => {
+                scene.handle_finger_scroll(&node, relative_scroll, Instant::now())
+            }
+            AxisSource::Wheel => scene.stop_kinetic_scroll(),
         }
 
         Ok(())
     }
+
+    /// Handle a mouse click
+    ///
+    /// This dismisses any popups the click was outside of, and starts
+    /// dragging a scrollbar if one was clicked. Returns the popups which
+    /// were hidden.
+    pub fn handle_click(&mut self, scene: &mut Scene, position: (i32, i32)) -> Vec<DakotaId> {
+        self.d_mouse_pos = position;
+        let ret = scene.dismiss_popups_at(position.0, position.1);
+
+        if scene.begin_scrollbar_drag(position.0, position.1) {
+            scene.stop_kinetic_scroll();
+        }
+        ret
+    }
+
+    /// Handle the mouse button being released
+    ///
+    /// This ends any scrollbar drag.
+    pub fn handle_click_release(&mut self, scene: &mut Scene, position: (i32, i32)) {
+        self.d_mouse_pos = position;
+        scene.end_scrollbar_drag();
+    }
+
+    /// Handle a finger touching the touchscreen
+    ///
+    /// This dismisses any popups the touch was outside of. Returns the
+    /// element that was touched.
+    pub fn handle_touch_down(
+        &mut self,
+        scene: &mut Scene,
+        id: i32,
+        position: (i32, i32),
+    ) -> Option<DakotaId> {
+        scene.dismiss_popups_at(position.0, position.1);
+        scene.begin_touch(id, position.0, position.1)
+    }
+
+    /// Handle a finger moving on the touchscreen
+    ///
+    /// Dragging a single finger scrolls the viewport it touched. Returns
+    /// true if a viewport was scrolled, in which case the output should be
+    /// redrawn.
+    pub fn handle_touch_motion(
+        &mut self,
+        scene: &mut Scene,
+        id: i32,
+        position: (i32, i32),
+    ) -> bool {
+        scene.update_touch(id, position.0, position.1, Instant::now())
+    }
+
+    /// Handle a finger being lifted from the touchscreen
+    ///
+    /// If the finger was moving its viewport keeps scrolling, see
+    /// `Scene::update_kinetic_scroll`. Returns the element the finger
+    /// first touched.
+    pub fn handle_touch_up(&mut self, scene: &mut Scene, id: i32) -> Option<DakotaId> {
+        scene.end_touch(id, Instant::now())
+    }
+
+    /// Handle the touch sequence being cancelled
+    pub fn handle_touch_cancel(&mut self, scene: &mut Scene) {
+        scene.cancel_touches();
+    }
+
+    /// Get the fingers currently on the touchscreen
+    ///
+    /// Returns (touch id, position) for each one.
+    pub fn get_touch_points(&self) -> Vec<(i32, (i32, i32))> {
+        self.d_platform_event_system
+            .get(&self.d_id)
+            .unwrap()
+            .get_touch_points()
+    }
+
+    /// Handle relative mouse motion
+    ///
+    /// Returns true if a scrollbar was dragged, in which case the output
+    /// should be redrawn.
+    pub fn handle_mouse_move(&mut self, scene: &mut Scene, dx: i32, dy: i32) -> bool {
+        self.d_mouse_pos.0 += dx;
+        self.d_mouse_pos.1 += dy;
+        scene.update_scrollbar_drag(self.d_mouse_pos.0, self.d_mouse_pos.1)
+    }
+
+    /// Inject relative mouse motion
+    ///
+    /// Injected events are queued exactly like ones from a real device,
+    /// and will be returned by `pop_event`. This is used by tests and by
+    /// remote desktop servers.
+    pub fn inject_mouse_move(&mut self, dx: i32, dy: i32) {
+        self.d_platform_event_system
+            .get_mut(&self.d_id)
+            .unwrap()
+            .add_event_mouse_move(dx, dy, get_monotonic_micros());
+    }
+
+    /// Inject a mouse button press or release
+    ///
+    /// This happens at the current mouse position.
+    pub fn inject_mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        let time = get_monotonic_micros();
+        let mut evsys = self.d_platform_event_system.get_mut(&self.d_id).unwrap();
+
+        match pressed {
+            true => evsys.add_event_mouse_button_down(button, time),
+            false => evsys.add_event_mouse_button_up(button, time),
+        }
+    }
+
+    /// Inject a key press or release
+    ///
+    /// There is no keymap here, so the caller provides what the key
+    /// translates to. `utf8` is ignored for releases, which never
+    /// generate text.
+    pub fn inject_key(&mut self, key: Keycode, raw_keycode: RawKeycode, utf8: &str, pressed: bool) {
+        let time = get_monotonic_micros();
+        let mut evsys = self.d_platform_event_system.get_mut(&self.d_id).unwrap();
+
+        match pressed {
+            true => evsys.add_event_key_down(key, utf8.to_string(), raw_keycode, time),
+            false => evsys.add_event_key_up(key, String::with_capacity(0), raw_keycode, time),
+        }
+    }
+
+    /// Inject a change in the active keyboard modifiers
+    pub fn inject_keyboard_modifiers(&mut self, mods: Mods) {
+        self.d_platform_event_system
+            .get_mut(&self.d_id)
+            .unwrap()
+            .add_event_keyboard_modifiers(mods, get_monotonic_micros());
+    }
+
+    /// Inject a finger touching the touchscreen
+    pub fn inject_touch_down(&mut self, id: i32, x: i32, y: i32) {
+        self.d_platform_event_system
+            .get_mut(&self.d_id)
+            .unwrap()
+            .add_event_touch_down(id, x, y, get_monotonic_micros());
+    }
+
+    /// Inject a touchscreen finger moving
+    pub fn inject_touch_motion(&mut self, id: i32, x: i32, y: i32) {
+        self.d_platform_event_system
+            .get_mut(&self.d_id)
+            .unwrap()
+            .add_event_touch_motion(id, x, y, get_monotonic_micros());
+    }
+
+    /// Inject a finger being lifted from the touchscreen
+    pub fn inject_touch_up(&mut self, id: i32) {
+        self.d_platform_event_system
+            .get_mut(&self.d_id)
+            .unwrap()
+            .add_event_touch_up(id, get_monotonic_micros());
+    }
 }
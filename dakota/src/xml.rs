@@ -9,6 +9,8 @@ extern crate quick_xml;
 use quick_xml::events::Event;
 use quick_xml::Reader;
 
+use crate::asset::AssetLoader;
+use crate::svg::SvgResource;
 use crate::utils::anyhow;
 use crate::{dom, font};
 use crate::{Context, DakotaId, DakotaObjectType, Result, Scene};
@@ -26,7 +28,7 @@ use utils::log;
 ///
 /// These fields correspond to the identically named variants in Dakota.
 pub(crate) struct ParserTransaction<'a> {
-    pt_dev: &'a th::Device,
+    pt_assets: &'a mut AssetLoader,
     pt_ecs_inst: ll::Instance,
     pt_resource_ecs_inst: ll::Instance,
     pt_node_types: ll::Snapshot<'a, DakotaObjectType>,
@@ -34,11 +36,14 @@ pub(crate) struct ParserTransaction<'a> {
     pt_resources: ll::Snapshot<'a, DakotaId>,
     pt_resource_thundr_image: ll::Snapshot<'a, th::Image>,
     pt_resource_color: ll::Snapshot<'a, dom::Color>,
+    pt_resource_nine_patch: ll::Snapshot<'a, dom::NinePatch>,
+    pt_resource_svg: ll::Snapshot<'a, SvgResource>,
     pt_fonts: ll::Snapshot<'a, dom::Font>,
     pt_text_font: ll::Snapshot<'a, DakotaId>,
     pt_texts: ll::Snapshot<'a, dom::Text>,
     pt_glyphs: ll::Snapshot<'a, font::Glyph>,
     pt_is_viewport: ll::Snapshot<'a, bool>,
+    pt_scrollbars: ll::Snapshot<'a, bool>,
     pt_contents: ll::Snapshot<'a, dom::Content>,
     pt_offsets: ll::Snapshot<'a, dom::RelativeOffset>,
     pt_widths: ll::Snapshot<'a, dom::Value>,
@@ -47,7 +52,13 @@ pub(crate) struct ParserTransaction<'a> {
     pt_font_instances: &'a mut Vec<(dom::Font, font::FontInstance)>,
     pt_freetype: &'a ft::Library,
     pt_fontconfig: &'a fc::Fontconfig,
+    pt_subpixel: th::SubpixelLayout,
+    pt_text_scale: f32,
     pt_unbounded_subsurf: ll::Snapshot<'a, bool>,
+    pt_classes: ll::Snapshot<'a, Vec<String>>,
+    pt_roles: ll::Snapshot<'a, dom::Role>,
+    pt_labels: ll::Snapshot<'a, String>,
+    pt_styles: &'a mut HashMap<String, dom::Style>,
     /// This maps the string names for resource found in the
     /// XML document to DakotaIds that represent those resources.
     ///
@@ -74,6 +85,8 @@ enum Element {
         height: Option<dom::Value>,
     },
     Text(Vec<dom::TextItem>, Option<String>),
+    /// Text and font name
+    Span(Option<String>, Option<String>),
     TextFont(Option<String>),
     PixelSize(Option<u32>),
     Window {
@@ -90,6 +103,7 @@ enum Element {
     Version(Option<String>),
     Name(Option<String>),
     FontName(Option<String>),
+    FontStyle(Option<String>),
     Title(Option<String>),
     Width(Option<dom::Value>),
     Height(Option<dom::Value>),
@@ -121,6 +135,7 @@ enum Element {
         Option<String>,
         Option<String>,
         Option<String>,
+        Option<String>,
         u32,
         Option<dom::Color>,
     ),
@@ -129,8 +144,14 @@ enum Element {
         image: Option<dom::Image>,
         color: Option<dom::Color>,
         hints: Option<dom::Hints>,
+        nine_patch: Option<dom::NinePatch>,
     },
     Hints(dom::Hints),
+    NinePatch(dom::NinePatch),
+    Left(Option<u32>),
+    Right(Option<u32>),
+    Top(Option<u32>),
+    Bottom(Option<u32>),
     Static(bool),
     Size(Option<dom::Value>, Option<dom::Value>),
     Offset(Option<dom::Value>, Option<dom::Value>),
@@ -151,6 +172,15 @@ enum Element {
     Closed(Option<dom::Event>),
     UnboundedSubsurface,
     Viewport,
+    Scrollbar,
+    Stylesheet(dom::Data),
+    Styles,
+    Style(dom::Style),
+    Inherits(Option<String>),
+    Class(Option<String>),
+    Opacity(Option<f32>),
+    Role(Option<dom::Role>),
+    Label(Option<String>),
 }
 
 impl Element {
@@ -164,6 +194,7 @@ impl Element {
             },
             b"text" => Self::Text(Vec::new(), None),
             b"font" => Self::TextFont(None),
+            b"span" => Self::Span(None, None),
             b"pixel_size" => Self::PixelSize(None),
             b"window" => Self::Window {
                 title: None,
@@ -179,6 +210,7 @@ impl Element {
             b"version" => Self::Version(None),
             b"name" => Self::Name(None),
             b"font_name" => Self::FontName(None),
+            b"font_style" => Self::FontStyle(None),
             b"title" => Self::Title(None),
             b"width" => Self::Width(None),
             b"height" => Self::Height(None),
@@ -209,14 +241,20 @@ impl Element {
             }),
             b"resourceMap" => Self::ResourceMap,
             b"resource" => Self::Resource(None),
-            b"define_font" => Self::FontDefinition(None, None, None, 0, None),
+            b"define_font" => Self::FontDefinition(None, None, None, None, 0, None),
             b"define_resource" => Self::ResourceDefinition {
                 name: None,
                 image: None,
                 color: None,
                 hints: None,
+                nine_patch: None,
             },
             b"hints" => Self::Hints(dom::Hints::default()),
+            b"nine_patch" => Self::NinePatch(dom::NinePatch::default()),
+            b"left" => Self::Left(None),
+            b"right" => Self::Right(None),
+            b"top" => Self::Top(None),
+            b"bottom" => Self::Bottom(None),
             b"static" => Self::Static(false),
             b"size" => Self::Size(None, None),
             b"p" => Self::P(None),
@@ -237,6 +275,18 @@ impl Element {
             b"closed" => Self::Closed(None),
             b"unbounded_subsurface" => Self::UnboundedSubsurface,
             b"viewport" => Self::Viewport,
+            b"scrollbar" => Self::Scrollbar,
+            b"stylesheet" => Self::Stylesheet(dom::Data {
+                rel_path: None,
+                abs_path: None,
+            }),
+            b"styles" => Self::Styles,
+            b"style" => Self::Style(dom::Style::default()),
+            b"inherits" => Self::Inherits(None),
+            b"class" => Self::Class(None),
+            b"opacity" => Self::Opacity(None),
+            b"role" => Self::Role(None),
+            b"label" => Self::Label(None),
             _ => {
                 return Err(anyhow!(
                     "Element name {} is not a valid element name",
@@ -293,17 +343,23 @@ impl<'a> ParserTransaction<'a> {
         self.pt_resources.precommit();
         self.pt_resource_thundr_image.precommit();
         self.pt_resource_color.precommit();
+        self.pt_resource_nine_patch.precommit();
+        self.pt_resource_svg.precommit();
         self.pt_fonts.precommit();
         self.pt_text_font.precommit();
         self.pt_texts.precommit();
         self.pt_glyphs.precommit();
         self.pt_is_viewport.precommit();
+        self.pt_scrollbars.precommit();
         self.pt_contents.precommit();
         self.pt_widths.precommit();
         self.pt_heights.precommit();
         self.pt_offsets.precommit();
         self.pt_children.precommit();
         self.pt_unbounded_subsurf.precommit();
+        self.pt_classes.precommit();
+        self.pt_roles.precommit();
+        self.pt_labels.precommit();
     }
 
     /// Commit this transaction
@@ -316,17 +372,23 @@ impl<'a> ParserTransaction<'a> {
         self.pt_resources.commit();
         self.pt_resource_thundr_image.commit();
         self.pt_resource_color.commit();
+        self.pt_resource_nine_patch.commit();
+        self.pt_resource_svg.commit();
         self.pt_fonts.commit();
         self.pt_text_font.commit();
         self.pt_texts.commit();
         self.pt_glyphs.commit();
         self.pt_is_viewport.commit();
+        self.pt_scrollbars.commit();
         self.pt_contents.commit();
         self.pt_widths.commit();
         self.pt_heights.commit();
         self.pt_offsets.commit();
         self.pt_children.commit();
         self.pt_unbounded_subsurf.commit();
+        self.pt_classes.commit();
+        self.pt_roles.commit();
+        self.pt_labels.commit();
     }
 
     // Similar to main Dakota functions. These here hook into common creation logic
@@ -355,8 +417,11 @@ impl<'a> ParserTransaction<'a> {
         Scene::define_font_internal(
             &mut self.pt_font_instances,
             &mut self.pt_fonts,
+            &mut self.pt_glyphs,
             &self.pt_freetype,
             &self.pt_fontconfig,
+            self.pt_subpixel,
+            self.pt_text_scale,
             id,
             font,
         );
@@ -369,27 +434,50 @@ impl<'a> ParserTransaction<'a> {
         format: dom::Format,
     ) -> Result<()> {
         Scene::define_resource_from_image_internal(
-            &mut self.pt_dev,
+            self.pt_assets,
             &mut self.pt_resource_thundr_image,
             &self.pt_resource_color,
+            &mut self.pt_resource_svg,
             res,
             file_path,
             format,
         )
     }
 
+    /// Parse the styles in an external stylesheet
+    ///
+    /// This happens in the same transaction, so the stylesheet may use
+    /// resources defined in the document that imports it.
+    fn load_stylesheet(&mut self, data: &dom::Data) -> Result<()> {
+        let path = data.get_fs_path()?;
+        let file =
+            std::fs::File::open(path).context(anyhow!("Could not open stylesheet {:?}", path))?;
+        let mut reader = Reader::from_reader(std::io::BufReader::new(file));
+        reader.trim_text(true);
+
+        match self
+            .parse_xml(&mut reader)
+            .context(anyhow!("Failed to parse stylesheet {:?}", path))?
+        {
+            Some(_) => Err(anyhow!("Stylesheet {:?} is not a styles document", path)),
+            None => Ok(()),
+        }
+    }
+
     // --------------------------------------------------------------------------
 
     /// Returns a new id if this element type will have a DakotaId created for
     /// it. None if no
     fn needs_new_id(&mut self, node: &Element) -> Result<Option<DakotaId>> {
         match node {
-            Element::FontDefinition(_, _, _, _, _) => Ok(Some(self.create_font()?)),
+            Element::FontDefinition(_, _, _, _, _, _) => Ok(Some(self.create_font()?)),
             Element::El { .. }
             | Element::Layout
             // create a dummy element Id for the toplevel dakota object to help
             // keep the parsing logic easy. It will not be used.
-            | Element::Dakota {..} => Ok(Some(self.create_element()?)),
+            | Element::Dakota {..}
+            // Stylesheets may also be toplevel objects
+            | Element::Styles => Ok(Some(self.create_element()?)),
             _ => Ok(None),
         }
     }
@@ -427,6 +515,7 @@ impl<'a> ParserTransaction<'a> {
                 .as_ref()
                 .ok_or(anyhow!("No text inside tag that expected text data"))?
                 .clone(),
+            font: None,
             cache: None,
         })
     }
@@ -485,6 +574,24 @@ impl<'a> ParserTransaction<'a> {
                         self.pt_resources.set(id, resource_id)
                     }
                     Element::Viewport => self.pt_is_viewport.set(id, true),
+                    // Scrollbars are only drawn on viewports
+                    Element::Scrollbar => {
+                        self.pt_is_viewport.set(id, true);
+                        self.pt_scrollbars.set(id, true);
+                    }
+                    Element::Role(role) => self
+                        .pt_roles
+                        .set(id, role.context("Element role not specified")?),
+                    Element::Label(label) => self
+                        .pt_labels
+                        .set(id, label.clone().context("Element label not specified")?),
+                    Element::Class(name) => {
+                        let name = name.clone().context("Class name not specified")?;
+                        match self.pt_classes.get_mut(id) {
+                            Some(classes) => classes.push(name),
+                            None => self.pt_classes.set(id, vec![name]),
+                        }
+                    }
                     Element::UnboundedSubsurface => self.pt_unbounded_subsurf.set(id, true),
                     Element::El {
                         x: _,
@@ -573,7 +680,8 @@ impl<'a> ParserTransaction<'a> {
                 root_element,
             } => match old_node {
                 Element::Version(data) => *version = data.clone(),
-                Element::ResourceMap => {}
+                Element::ResourceMap | Element::Styles => {}
+                Element::Stylesheet(data) => self.load_stylesheet(data)?,
                 Element::Window {
                     title,
                     width,
@@ -609,6 +717,22 @@ impl<'a> ParserTransaction<'a> {
             Element::Text(data, font) => match old_node {
                 Element::P(s) => data.push(dom::TextItem::p(self.get_text_run(s)?)),
                 Element::Bold(s) => data.push(dom::TextItem::b(self.get_text_run(s)?)),
+                Element::Span(s, span_font) => {
+                    let mut run = self.get_text_run(s)?;
+                    if let Some(name) = span_font {
+                        run.font = Some(
+                            self.get_id_for_name(true, name)
+                                .context("Getting font reference for text span")?,
+                        );
+                    }
+                    data.push(dom::TextItem::span(run))
+                }
+                Element::TextFont(name) => {
+                    *font = Some(name.clone().context("Font name not specified")?)
+                }
+                e => return Err(anyhow!("Unexpected child element: {:?}", e)),
+            },
+            Element::Span(_, font) => match old_node {
                 Element::TextFont(name) => {
                     *font = Some(name.clone().context("Font name not specified")?)
                 }
@@ -627,7 +751,7 @@ impl<'a> ParserTransaction<'a> {
             },
             // -------------------------------------------------------
             Element::Image(format, data) => match old_node {
-                Element::Format(f) => *format = f.clone(),
+                Element::Format(f) => *format = *f,
                 Element::Data(d) => *data = Some(d.clone()),
                 e => return Err(anyhow!("Unexpected child element: {:?}", e)),
             },
@@ -646,7 +770,7 @@ impl<'a> ParserTransaction<'a> {
             },
             // -------------------------------------------------------
             Element::ResourceMap => match old_node {
-                Element::FontDefinition(name, font_name, _path, size, color) => {
+                Element::FontDefinition(name, font_name, style, _path, size, color) => {
                     let resource_id = self
                         .get_id_for_name(
                             true,
@@ -664,6 +788,7 @@ impl<'a> ParserTransaction<'a> {
                             font_name: font_name
                                 .clone()
                                 .ok_or(anyhow!("Font definition does not specify a font name"))?,
+                            style: style.clone(),
                             pixel_size: *size,
                             color: *color,
                         },
@@ -674,6 +799,7 @@ impl<'a> ParserTransaction<'a> {
                     image,
                     color,
                     hints,
+                    nine_patch,
                 } => {
                     // Look up this resource's id
                     let resource_id = self
@@ -687,6 +813,14 @@ impl<'a> ParserTransaction<'a> {
                     if let Some(h) = hints.clone() {
                         self.pt_resource_hints.set(&resource_id, h);
                     }
+                    if let Some(n) = nine_patch {
+                        if image.is_none() {
+                            return Err(anyhow!(
+                                "nine_patch can only be used with image resources"
+                            ));
+                        }
+                        self.pt_resource_nine_patch.set(&resource_id, *n);
+                    }
 
                     // If this resource is backed by an image, populate it
                     if let Some(i) = image.as_ref() {
@@ -698,9 +832,10 @@ impl<'a> ParserTransaction<'a> {
                 }
                 e => return Err(anyhow!("Unexpected child element: {:?}", e)),
             },
-            Element::FontDefinition(name, font_name, path, size, color) => match old_node {
+            Element::FontDefinition(name, font_name, style, path, size, color) => match old_node {
                 Element::Name(n) => *name = n.clone(),
                 Element::FontName(n) => *font_name = n.clone(),
+                Element::FontStyle(s) => *style = s.clone(),
                 Element::AbsPath(p) | Element::RelPath(p) => *path = p.clone(),
                 Element::PixelSize(s) => *size = s.context("PixelSize was not populated")?,
                 Element::Color { r, g, b, a } => {
@@ -718,13 +853,12 @@ impl<'a> ParserTransaction<'a> {
                 image,
                 color,
                 hints,
+                nine_patch,
             } => match old_node {
                 Element::Name(n) => *name = n.clone(),
                 Element::Image(format, data) => {
                     *image = Some(dom::Image {
-                        format: format
-                            .clone()
-                            .ok_or(anyhow!("Format not specified for image"))?,
+                        format: format.ok_or(anyhow!("Format not specified for image"))?,
                         data: data
                             .clone()
                             .ok_or(anyhow!("Format not specified for image"))?,
@@ -739,12 +873,89 @@ impl<'a> ParserTransaction<'a> {
                     })
                 }
                 Element::Hints(data) => *hints = Some(data.clone()),
+                Element::NinePatch(data) => *nine_patch = Some(*data),
                 e => return Err(anyhow!("Unexpected child element: {:?}", e)),
             },
             Element::Hints(data) => match old_node {
                 Element::Static(val) => data.constant = *val,
                 e => return Err(anyhow!("Unexpected child element: {:?}", e)),
             },
+            Element::NinePatch(data) => match old_node {
+                Element::Left(val) => data.left = val.context("Nine patch left not specified")?,
+                Element::Right(val) => {
+                    data.right = val.context("Nine patch right not specified")?
+                }
+                Element::Top(val) => data.top = val.context("Nine patch top not specified")?,
+                Element::Bottom(val) => {
+                    data.bottom = val.context("Nine patch bottom not specified")?
+                }
+                e => return Err(anyhow!("Unexpected child element: {:?}", e)),
+            },
+            // -------------------------------------------------------
+            Element::Styles => match old_node {
+                Element::ResourceMap => {}
+                Element::Stylesheet(data) => self.load_stylesheet(data)?,
+                Element::Style(style) => {
+                    if style.name.is_empty() {
+                        return Err(anyhow!("Style definition does not have a name"));
+                    }
+                    self.pt_styles.insert(style.name.clone(), style.clone());
+                }
+                e => return Err(anyhow!("Unexpected child element: {:?}", e)),
+            },
+            Element::Style(style) => match old_node {
+                Element::Name(n) => style.name = n.clone().context("Style name not specified")?,
+                Element::Inherits(n) => {
+                    style.inherits = Some(n.clone().context("Inherited style name not specified")?)
+                }
+                Element::Resource(name) => {
+                    let resource_id = self
+                        .get_id_for_name(
+                            false,
+                            name.as_ref()
+                                .ok_or(anyhow!("Style was not assigned a resource"))?,
+                        )
+                        .context("Getting resource reference for style")?;
+                    style.resource = Some(resource_id);
+                }
+                Element::TextFont(name) => {
+                    let font_id = self
+                        .get_id_for_name(
+                            true,
+                            name.as_ref()
+                                .ok_or(anyhow!("Style was not assigned a font"))?,
+                        )
+                        .context("Getting font reference for style")?;
+                    style.font = Some(font_id);
+                }
+                Element::Size(width, height) => {
+                    style.width = *width;
+                    style.height = *height;
+                }
+                Element::Offset(x, y) => {
+                    style.offset = Some(dom::RelativeOffset {
+                        x: x.ok_or(anyhow!("Offset does not specify x"))?,
+                        y: y.ok_or(anyhow!("Offset does not specify y"))?,
+                    })
+                }
+                Element::Opacity(val) => {
+                    style.opacity = Some(val.context("Opacity value not specified")?)
+                }
+                e => return Err(anyhow!("Unexpected child element: {:?}", e)),
+            },
+            Element::Stylesheet(data) => match old_node {
+                Element::RelPath(path) => {
+                    data.rel_path = Some(path.clone().ok_or(anyhow!(
+                        "No path provided in element that expects path value"
+                    ))?)
+                }
+                Element::AbsPath(path) => {
+                    data.abs_path = Some(path.clone().ok_or(anyhow!(
+                        "No path provided in element that expects path value"
+                    ))?)
+                }
+                e => return Err(anyhow!("Unexpected child element: {:?}", e)),
+            },
             // -------------------------------------------------------
             Element::Size(width, height) => match old_node {
                 Element::Width(data) => *width = *data,
@@ -827,12 +1038,18 @@ impl<'a> ParserTransaction<'a> {
                     | Element::Resource(data)
                     | Element::TextFont(data)
                     | Element::FontName(data)
+                    | Element::FontStyle(data)
+                    | Element::Inherits(data)
+                    | Element::Class(data)
+                    | Element::Label(data)
+                    | Element::Span(data, _)
                     | Element::Name(data) => *data = Some(text),
                     // float fields
                     Element::R(data)
                     | Element::G(data)
                     | Element::B(data)
                     | Element::A(data)
+                    | Element::Opacity(data)
                     | Element::Relative(data) => {
                         *data = Some(
                             text.parse::<f32>()
@@ -848,6 +1065,10 @@ impl<'a> ParserTransaction<'a> {
                     // unsigned int fields
                     Element::WindowWidth(data)
                     | Element::PixelSize(data)
+                    | Element::Left(data)
+                    | Element::Right(data)
+                    | Element::Top(data)
+                    | Element::Bottom(data)
                     | Element::WindowHeight(data) => {
                         *data =
                             Some(text.parse::<u32>().context(
@@ -861,6 +1082,23 @@ impl<'a> ParserTransaction<'a> {
                             fmt => return Err(anyhow!("Unknown resource hint {:?}", fmt)),
                         }
                     }
+                    Element::Role(data) => {
+                        *data = Some(match text.as_str() {
+                            "generic" => dom::Role::Generic,
+                            "window" => dom::Role::Window,
+                            "group" => dom::Role::Group,
+                            "label" => dom::Role::Label,
+                            "button" => dom::Role::Button,
+                            "image" => dom::Role::Image,
+                            "list" => dom::Role::List,
+                            "list_item" => dom::Role::ListItem,
+                            "text_input" => dom::Role::TextInput,
+                            "scroll_view" => dom::Role::ScrollView,
+                            "menu" => dom::Role::Menu,
+                            "tooltip" => dom::Role::Tooltip,
+                            role => return Err(anyhow!("Unknown element role {:?}", role)),
+                        })
+                    }
                     Element::Format(data) => {
                         *data = match text.as_str() {
                             "ARGB8888" => Some(dom::Format::ARGB8888),
@@ -891,7 +1129,9 @@ impl<'a> ParserTransaction<'a> {
     /// Parse a quick_xml stream into a Dakota DOM tree
     ///
     /// This initializes our elements to be later processed into layout nodes.
-    fn parse_xml<R: BufRead>(&mut self, reader: &mut Reader<R>) -> Result<dom::DakotaDOM> {
+    /// Returns None if the stream was a stylesheet instead of a Dakota
+    /// document.
+    fn parse_xml<R: BufRead>(&mut self, reader: &mut Reader<R>) -> Result<Option<dom::DakotaDOM>> {
         let mut buf = Vec::new();
 
         // The DakotaId we are currently populating
@@ -942,7 +1182,7 @@ impl<'a> ParserTransaction<'a> {
                                     root_element,
                                 }) => {
                                     // Return a DOM object
-                                    return Ok(dom::DakotaDOM {
+                                    return Ok(Some(dom::DakotaDOM {
                                         version: version
                                             .clone()
                                             .ok_or(anyhow!("Dakota missing field version"))?,
@@ -952,8 +1192,10 @@ impl<'a> ParserTransaction<'a> {
                                         root_element: root_element
                                             .clone()
                                             .ok_or(anyhow!("Dakota missing field version"))?,
-                                    });
+                                    }));
                                 }
+                                // Stylesheets have already added their styles
+                                Some(Element::Styles) => return Ok(None),
                                 _ => {
                                     return Err(anyhow!(
                                         "Toplevel XML tag is not the Dakota object"
@@ -1014,7 +1256,7 @@ impl Scene {
     /// This initializes our elements to be later processed into layout nodes.
     fn parse_xml<R: BufRead>(&mut self, reader: &mut Reader<R>) -> Result<()> {
         let mut trans = ParserTransaction {
-            pt_dev: &self.d_dev,
+            pt_assets: &mut self.d_assets,
             pt_ecs_inst: self.d_ecs_inst.clone(),
             pt_resource_ecs_inst: self.d_resource_ecs_inst.clone(),
             pt_node_types: self.d_node_types.snapshot(),
@@ -1022,11 +1264,14 @@ impl Scene {
             pt_resource_hints: self.d_resource_hints.snapshot(),
             pt_resource_thundr_image: self.d_resource_thundr_image.snapshot(),
             pt_resource_color: self.d_resource_color.snapshot(),
+            pt_resource_nine_patch: self.d_resource_nine_patch.snapshot(),
+            pt_resource_svg: self.d_resource_svg.snapshot(),
             pt_fonts: self.d_fonts.snapshot(),
             pt_text_font: self.d_text_font.snapshot(),
             pt_texts: self.d_texts.snapshot(),
             pt_glyphs: self.d_glyphs.snapshot(),
             pt_is_viewport: self.d_is_viewport.snapshot(),
+            pt_scrollbars: self.d_scrollbars.snapshot(),
             pt_contents: self.d_contents.snapshot(),
             pt_widths: self.d_widths.snapshot(),
             pt_heights: self.d_heights.snapshot(),
@@ -1037,12 +1282,22 @@ impl Scene {
             pt_font_name_to_id_map: HashMap::new(),
             pt_freetype: &self.d_freetype,
             pt_fontconfig: &self.d_fontconfig,
+            pt_subpixel: self.d_subpixel,
+            pt_text_scale: self.d_text_scale,
             pt_unbounded_subsurf: self.d_unbounded_subsurf.snapshot(),
+            pt_classes: self.d_classes.snapshot(),
+            pt_roles: self.d_roles.snapshot(),
+            pt_labels: self.d_labels.snapshot(),
+            pt_styles: &mut self.d_styles,
         };
 
-        self.d_dom = Some(trans.parse_xml(reader)?);
+        // Stylesheets do not contain a DOM
+        if let Some(dom) = trans.parse_xml(reader)? {
+            self.d_dom = Some(dom);
+        }
         trans.precommit();
         trans.commit();
+        self.d_styles_modified = true;
 
         Ok(())
     }
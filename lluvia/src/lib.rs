@@ -83,13 +83,38 @@
 //! Snapshots are another advanced feature which allow you to update many `Entity`
 //! values and then apply all the changes in one commit. Snapshots are a type of
 //! `Component`, and only apply to one Sparse `Component`.
+//!
+//! # Hierarchies
+//!
+//! Entities can be arranged into trees with `Instance::add_child`. A parent
+//! holds a reference to each of its children, so dropping the root of a tree
+//! drops every entity in it that isn't referenced anywhere else. Children
+//! only hold a weak link back to their parent, so this can't create the
+//! circular references described above.
+//!
+//! ```
+//! use lluvia as ll;
+//! let inst = ll::Instance::new();
+//! let root = inst.add_entity();
+//! let child = inst.add_entity();
+//!
+//! inst.add_child(&root, &child);
+//! assert_eq!(inst.get_parent(&child), Some(root.clone()));
+//! assert_eq!(inst.children(&root).count(), 1);
+//!
+//! // The child is kept alive by the root until the root is dropped
+//! drop(child);
+//! assert_eq!(inst.num_entities(), 2);
+//! drop(root);
+//! assert_eq!(inst.num_entities(), 0);
+//! ```
 // Austin Shafer - 2022-2023
 
 use std::any::Any;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use std::sync::{atomic::AtomicBool, Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{atomic::AtomicBool, Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
 
 #[cfg(test)]
 mod tests;
@@ -410,6 +435,12 @@ impl<'a, T: 'static, C: Container<T> + 'static> DerefMut for TableRefMut<'a, T,
 pub struct EntityInternal {
     ecs_inst: Instance,
     ecs_id: usize,
+    /// The entity this is a child of. This is weak so that parents and
+    /// children don't keep each other alive.
+    ecs_parent: Mutex<Weak<EntityInternal>>,
+    /// Our children, in the order they were added. These are dropped
+    /// along with us.
+    ecs_children: Mutex<Vec<Entity>>,
 }
 
 impl fmt::Debug for EntityInternal {
@@ -722,6 +753,7 @@ impl Instance {
             _c_phantom: PhantomData,
             c_table: table.clone(),
             c_modified: Arc::new(AtomicBool::new(false)),
+            c_tracker: Arc::new(ChangeTracker::new()),
         };
     }
 
@@ -742,9 +774,89 @@ impl Instance {
         return Arc::new(EntityInternal {
             ecs_id: first_valid_id,
             ecs_inst: new_self,
+            ecs_parent: Mutex::new(Weak::new()),
+            ecs_children: Mutex::new(Vec::new()),
         });
     }
 
+    /// Add `child` to the end of `parent`'s list of children
+    ///
+    /// `parent` will hold a reference to `child`, keeping it alive until it is
+    /// removed or `parent` is dropped. If `child` already has a parent it is
+    /// moved to `parent`. This is the same as `reparent(child, Some(parent))`.
+    pub fn add_child(&self, parent: &Entity, child: &Entity) {
+        self.reparent(child, Some(parent));
+    }
+
+    /// Move `child` to a new parent
+    ///
+    /// `child` is removed from its current parent, if any, and added to the end
+    /// of `parent`'s children. If `parent` is None then `child` becomes the root
+    /// of its own tree, and is no longer kept alive by its old parent.
+    ///
+    /// This will panic if `parent` is `child` or one of its descendants, since
+    /// that would create a cycle that is never dropped.
+    pub fn reparent(&self, child: &Entity, parent: Option<&Entity>) {
+        self.id_is_valid(child);
+        if let Some(parent) = parent {
+            self.id_is_valid(parent);
+
+            let mut ancestor = Some(parent.clone());
+            while let Some(entity) = ancestor {
+                if Arc::ptr_eq(&entity, child) {
+                    panic!("Bug: Entity cannot be made a child of itself or its descendants");
+                }
+                ancestor = self.get_parent(&entity);
+            }
+        }
+
+        // Remove ourselves from our old parent. The caller holds a reference
+        // to child, so this doesn't drop it while the lock is held.
+        let old_parent = child.ecs_parent.lock().unwrap().upgrade();
+        if let Some(old_parent) = old_parent {
+            old_parent
+                .ecs_children
+                .lock()
+                .unwrap()
+                .retain(|c| !Arc::ptr_eq(c, child));
+        }
+
+        *child.ecs_parent.lock().unwrap() = match parent {
+            Some(parent) => {
+                parent.ecs_children.lock().unwrap().push(child.clone());
+                Arc::downgrade(parent)
+            }
+            None => Weak::new(),
+        };
+    }
+
+    /// Get the parent of this entity
+    ///
+    /// Returns None if `child` has not been added to a parent, or if it has
+    /// outlived its parent.
+    pub fn get_parent(&self, child: &Entity) -> Option<Entity> {
+        self.id_is_valid(child);
+        child.ecs_parent.lock().unwrap().upgrade()
+    }
+
+    /// Get the number of children this entity has
+    pub fn num_children(&self, parent: &Entity) -> usize {
+        self.id_is_valid(parent);
+        parent.ecs_children.lock().unwrap().len()
+    }
+
+    /// Iterate over the children of an entity
+    ///
+    /// Children are returned in the order they were added. The iterator works
+    /// on a copy of the list of children, so it is safe to reparent entities
+    /// while iterating.
+    pub fn children(&self, parent: &Entity) -> ChildIterator {
+        self.id_is_valid(parent);
+        ChildIterator {
+            ci_children: parent.ecs_children.lock().unwrap().clone().into_iter(),
+        }
+    }
+
     /// Invalidate an Entity and free all of its component values
     ///
     /// If this is getting called then this id is no longer in use anywhere in the app, we
@@ -772,6 +884,21 @@ impl Instance {
     }
 }
 
+/// Iterator over the children of an Entity
+///
+/// This is returned by `Instance::children`.
+pub struct ChildIterator {
+    ci_children: std::vec::IntoIter<Entity>,
+}
+
+impl Iterator for ChildIterator {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.ci_children.next()
+    }
+}
+
 /// A Component holding values for each Entity
 ///
 /// Each Component in the system is really a key-value store for each
@@ -787,6 +914,44 @@ pub struct RawComponent<T: 'static, C: Container<T> + 'static> {
     /// Marked true when this component table has outstanding changes
     /// not processed by the user.
     c_modified: Arc<AtomicBool>,
+    /// Which entities have been modified, if requested
+    c_tracker: Arc<ChangeTracker>,
+}
+
+/// Records which entities of a Component have been modified
+///
+/// This does nothing until `track_modified_entities` is called, so that
+/// components which don't need it don't pay for the extra lock.
+struct ChangeTracker {
+    ct_enabled: AtomicBool,
+    /// Set when the entire table was changed by `clear`
+    ct_all: AtomicBool,
+    /// Entities modified since the last call to `take_modified_entities`
+    ct_entities: Mutex<Vec<Entity>>,
+}
+
+impl ChangeTracker {
+    fn new() -> Self {
+        Self {
+            ct_enabled: AtomicBool::new(false),
+            ct_all: AtomicBool::new(false),
+            ct_entities: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[inline]
+    fn record(&self, entity: &Entity) {
+        if self.ct_enabled.load(std::sync::atomic::Ordering::Acquire) {
+            self.ct_entities.lock().unwrap().push(entity.clone());
+        }
+    }
+
+    fn record_all(&self) {
+        if self.ct_enabled.load(std::sync::atomic::Ordering::Acquire) {
+            self.ct_all
+                .store(true, std::sync::atomic::Ordering::Release);
+        }
+    }
 }
 
 /// General Purpose Component
@@ -819,6 +984,7 @@ impl<T: 'static, C: Container<T> + 'static> Clone for RawComponent<T, C> {
             _c_phantom: PhantomData,
             c_table: self.c_table.clone(),
             c_modified: self.c_modified.clone(),
+            c_tracker: self.c_tracker.clone(),
         }
     }
 }
@@ -838,6 +1004,39 @@ impl<T: 'static, C: Container<T> + 'static> RawComponent<T, C> {
             .store(false, std::sync::atomic::Ordering::Release);
     }
 
+    /// Start recording which entities are modified
+    ///
+    /// `is_modified` only says that something in this table changed. After
+    /// this is called every entity whose value is set, taken, or mutably
+    /// borrowed is recorded until it is returned by `take_modified_entities`.
+    /// This applies to all clones of this Component, including snapshots when
+    /// they are committed.
+    ///
+    /// Recorded entities are kept alive until they are taken, so this should
+    /// only be used if `take_modified_entities` is called regularly.
+    pub fn track_modified_entities(&self) {
+        self.c_tracker
+            .ct_enabled
+            .store(true, std::sync::atomic::Ordering::Release);
+    }
+
+    /// Get the entities modified since the last call
+    ///
+    /// Entities may be returned more than once. If the table was cleared since
+    /// the last call then None is returned, and every entity should be treated
+    /// as modified.
+    pub fn take_modified_entities(&self) -> Option<Vec<Entity>> {
+        let entities = std::mem::take(&mut *self.c_tracker.ct_entities.lock().unwrap());
+        match self
+            .c_tracker
+            .ct_all
+            .swap(false, std::sync::atomic::Ordering::AcqRel)
+        {
+            true => None,
+            false => Some(entities),
+        }
+    }
+
     /// Get a reference to data corresponding to the (component, entity) pair
     ///
     /// This provides read-only access to the component value for an Entity. This
@@ -885,6 +1084,7 @@ impl<T: 'static, C: Container<T> + 'static> RawComponent<T, C> {
 
         self.c_modified
             .store(true, std::sync::atomic::Ordering::Release);
+        self.c_tracker.record(entity);
 
         return Some(TableRefMut {
             tr_guard: table_internal,
@@ -908,6 +1108,7 @@ impl<T: 'static, C: Container<T> + 'static> RawComponent<T, C> {
 
         self.c_modified
             .store(true, std::sync::atomic::Ordering::Release);
+        self.c_tracker.record(entity);
         let mut table_internal = self.c_table.t_internal.write().unwrap();
         table_internal.t_entity.set(entity.ecs_id, val);
     }
@@ -925,6 +1126,34 @@ impl<T: 'static, C: Container<T> + 'static> RawComponent<T, C> {
         }
     }
 
+    /// Set the values of many entities at once
+    ///
+    /// This is the same as calling `set` for each (entity, value) pair, but
+    /// only acquires the table lock once. Any existing values are dropped
+    /// after the lock has been released.
+    pub fn set_many<'b, I>(&self, values: I)
+    where
+        I: IntoIterator<Item = (&'b Entity, T)>,
+    {
+        let mut old_values = Vec::new();
+
+        self.c_modified
+            .store(true, std::sync::atomic::Ordering::Release);
+        {
+            let mut table_internal = self.c_table.t_internal.write().unwrap();
+            for (entity, val) in values {
+                self.c_inst.id_is_valid(entity);
+                self.c_tracker.record(entity);
+
+                old_values.push(table_internal.t_entity.take(entity.ecs_id));
+                table_internal.t_entity.set(entity.ecs_id, val);
+            }
+        }
+
+        // Now that the lock is released it is safe to drop the old values
+        drop(old_values);
+    }
+
     /// Take a value out of the component table
     ///
     /// This is the opposite of `set`. It will unset the value of the component for this
@@ -935,10 +1164,34 @@ impl<T: 'static, C: Container<T> + 'static> RawComponent<T, C> {
 
         self.c_modified
             .store(true, std::sync::atomic::Ordering::Release);
+        self.c_tracker.record(entity);
         let mut table_internal = self.c_table.t_internal.write().unwrap();
         table_internal.t_entity.take(entity.ecs_id)
     }
 
+    /// Take the values of many entities at once
+    ///
+    /// This is the same as calling `take` for each entity, but only
+    /// acquires the table lock once. The values are returned in the same
+    /// order as the entities.
+    pub fn take_many<'b, I>(&self, entities: I) -> Vec<Option<T>>
+    where
+        I: IntoIterator<Item = &'b Entity>,
+    {
+        self.c_modified
+            .store(true, std::sync::atomic::Ordering::Release);
+        let mut table_internal = self.c_table.t_internal.write().unwrap();
+
+        entities
+            .into_iter()
+            .map(|entity| {
+                self.c_inst.id_is_valid(entity);
+                self.c_tracker.record(entity);
+                table_internal.t_entity.take(entity.ecs_id)
+            })
+            .collect()
+    }
+
     /// Drop all values in the table
     ///
     /// This will drop all values in this component table, and in the case of
@@ -946,6 +1199,7 @@ impl<T: 'static, C: Container<T> + 'static> RawComponent<T, C> {
     pub fn clear(&mut self) {
         self.c_modified
             .store(true, std::sync::atomic::Ordering::Release);
+        self.c_tracker.record_all();
         let mut table_internal = self.c_table.t_internal.write().unwrap();
         table_internal.t_entity.clear();
     }
@@ -1181,6 +1435,7 @@ impl<'a, T: Clone + 'static> Snapshot<'a, T> {
             // for each entity in the snapshot
             // set the parent value to whatever's contained in the snapshot
             for id in self.s_ids.iter() {
+                self.s_parent.c_tracker.record(id);
                 // we clear our data container here, as every id modified in
                 // the system will have its data set back to None
                 if let Some(val) = self.s_data.take(id.get_raw_id()) {
@@ -1238,6 +1493,61 @@ impl<'a, T: Clone + 'static> Snapshot<'a, T> {
         self.s_data.take(entity.get_raw_id())
     }
 
+    /// Set the values of many entities in this snapshot
+    pub fn set_many<'b, I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = (&'b Entity, T)>,
+    {
+        for (entity, val) in values {
+            self.set(entity, val);
+        }
+    }
+
+    /// Take the values of many entities out of this snapshot
+    ///
+    /// The values are returned in the same order as the entities.
+    pub fn take_many<'b, I>(&mut self, entities: I) -> Vec<Option<T>>
+    where
+        I: IntoIterator<Item = &'b Entity>,
+    {
+        entities
+            .into_iter()
+            .map(|entity| self.take(entity))
+            .collect()
+    }
+
+    /// Take the changes recorded in this snapshot
+    ///
+    /// Instead of committing them to the parent component, this returns
+    /// the new value of every entity changed, or None if it was taken.
+    /// They can then be applied to another snapshot of the same component
+    /// with `apply_changes`, such as one living on another thread.
+    ///
+    /// This resets the snapshot
+    pub fn take_changes(&mut self) -> Vec<(Entity, Option<T>)> {
+        let changes = self
+            .s_ids
+            .iter()
+            .map(|id| (id.clone(), self.s_data.take(id.get_raw_id())))
+            .collect();
+
+        self.s_is_modified = false;
+        self.s_ids.clear();
+        changes
+    }
+
+    /// Apply changes taken from another snapshot with `take_changes`
+    pub fn apply_changes(&mut self, changes: Vec<(Entity, Option<T>)>) {
+        for (entity, val) in changes.into_iter() {
+            match val {
+                Some(val) => self.set(&entity, val),
+                None => {
+                    self.take(&entity);
+                }
+            }
+        }
+    }
+
     pub fn is_modified(&self) -> bool {
         self.s_is_modified
     }
@@ -197,3 +197,201 @@ fn set_drops_existing_without_deadlock() {
     // Check that no deadlock occurs here
     c.set(&e1, e3);
 }
+
+#[test]
+fn set_take_many() {
+    let mut inst = ll::Instance::new();
+    let c = inst.add_component();
+    let entities: Vec<ll::Entity> = (0..4).map(|_| inst.add_entity()).collect();
+
+    c.set_many(entities.iter().zip(0..4));
+    for (i, e) in entities.iter().enumerate() {
+        assert_eq!(c.get_clone(e), Some(i));
+    }
+
+    // Existing values are replaced
+    c.set_many(entities[..2].iter().zip(10..12));
+    assert_eq!(c.get_clone(&entities[0]), Some(10));
+    assert_eq!(c.get_clone(&entities[2]), Some(2));
+
+    let taken = c.take_many(entities[1..].iter());
+    assert_eq!(taken, vec![Some(11), Some(2), Some(3)]);
+    assert_eq!(c.get_clone(&entities[1]), None);
+    assert_eq!(c.take_many(&entities[1..2]), vec![None]);
+}
+
+#[test]
+fn snapshot_set_take_many() {
+    let mut inst = ll::Instance::new();
+    let c = inst.add_component();
+    let entities: Vec<ll::Entity> = (0..3).map(|_| inst.add_entity()).collect();
+    c.set(&entities[2], 5);
+
+    {
+        let mut snap = c.snapshot();
+        snap.set_many(entities[..2].iter().zip(0..2));
+        assert_eq!(snap.get(&entities[1]), Some(&1));
+        assert_eq!(snap.take_many(&entities[1..]), vec![Some(1), Some(5)]);
+        snap.commit();
+    }
+
+    assert_eq!(c.get_clone(&entities[0]), Some(0));
+    assert_eq!(c.get_clone(&entities[1]), None);
+    assert_eq!(c.get_clone(&entities[2]), None);
+}
+
+#[test]
+fn snapshot_move_changes() {
+    let mut inst = ll::Instance::new();
+    let c = inst.add_component();
+    let entities: Vec<ll::Entity> = (0..3).map(|_| inst.add_entity()).collect();
+    c.set(&entities[1], 1);
+    c.set(&entities[2], 2);
+
+    {
+        let mut snap = c.snapshot();
+        let mut other = c.snapshot();
+        other.set(&entities[0], 5);
+        other.take(&entities[1]);
+
+        // Moving the changes resets the other snapshot
+        snap.apply_changes(other.take_changes());
+        assert!(other.take_changes().is_empty());
+        assert_eq!(other.get(&entities[0]), None);
+        assert_eq!(other.get(&entities[1]), Some(&1));
+
+        assert_eq!(snap.get(&entities[0]), Some(&5));
+        assert_eq!(snap.get(&entities[1]), None);
+        assert_eq!(snap.get(&entities[2]), Some(&2));
+        drop(other);
+        snap.commit();
+    }
+
+    assert_eq!(c.get_clone(&entities[0]), Some(5));
+    assert_eq!(c.get_clone(&entities[1]), None);
+    assert_eq!(c.get_clone(&entities[2]), Some(2));
+}
+
+#[test]
+fn set_many_drops_existing_without_deadlock() {
+    let mut inst = ll::Instance::new();
+    let e1 = inst.add_entity();
+    let c = inst.add_component();
+
+    c.set_many(std::iter::once((&e1, inst.add_entity())));
+    // Check that no deadlock occurs here
+    c.set_many(std::iter::once((&e1, inst.add_entity())));
+}
+
+#[test]
+fn hierarchy() {
+    let inst = ll::Instance::new();
+    let root = inst.add_entity();
+    let a = inst.add_entity();
+    let b = inst.add_entity();
+    let grandchild = inst.add_entity();
+
+    inst.add_child(&root, &a);
+    inst.add_child(&root, &b);
+    inst.add_child(&a, &grandchild);
+
+    let children: Vec<ll::Entity> = inst.children(&root).collect();
+    assert_eq!(children, vec![a.clone(), b.clone()]);
+    assert_eq!(inst.get_parent(&grandchild), Some(a.clone()));
+    assert_eq!(inst.get_parent(&root), None);
+
+    // Moving an entity removes it from its old parent
+    inst.reparent(&grandchild, Some(&b));
+    assert_eq!(inst.num_children(&a), 0);
+    assert_eq!(inst.children(&b).next(), Some(grandchild.clone()));
+
+    // Detached entities are no longer owned by their parent
+    inst.reparent(&a, None);
+    assert_eq!(inst.get_parent(&a), None);
+    assert_eq!(inst.num_children(&root), 1);
+}
+
+#[test]
+fn hierarchy_recursive_drop() {
+    let mut inst = ll::Instance::new();
+    let data = Arc::new(Mutex::new(TestData { e: true, e1: true }));
+    let c = inst.add_component();
+
+    let root = inst.add_entity();
+    {
+        let child = inst.add_entity();
+        let grandchild = inst.add_entity();
+        c.set(&child, Empty("e", data.clone()));
+        c.set(&grandchild, Empty("e1", data.clone()));
+
+        inst.add_child(&root, &child);
+        inst.add_child(&child, &grandchild);
+    }
+
+    // The root keeps the whole tree alive
+    assert_eq!(inst.num_entities(), 3);
+    assert!(data.lock().unwrap().e);
+    assert!(data.lock().unwrap().e1);
+
+    drop(root);
+    assert_eq!(inst.num_entities(), 0);
+    assert!(!data.lock().unwrap().e);
+    assert!(!data.lock().unwrap().e1);
+}
+
+#[test]
+fn hierarchy_outlives_parent() {
+    let inst = ll::Instance::new();
+    let root = inst.add_entity();
+    let child = inst.add_entity();
+    inst.add_child(&root, &child);
+
+    // Holding another reference keeps the child alive without its parent
+    drop(root);
+    assert_eq!(inst.num_entities(), 1);
+    assert_eq!(inst.get_parent(&child), None);
+}
+
+#[test]
+#[should_panic]
+fn hierarchy_cycle() {
+    let inst = ll::Instance::new();
+    let root = inst.add_entity();
+    let child = inst.add_entity();
+    inst.add_child(&root, &child);
+    inst.add_child(&child, &root);
+}
+
+#[test]
+fn track_modified_entities() {
+    let mut inst = ll::Instance::new();
+    let a = inst.add_entity();
+    let b = inst.add_entity();
+    let c: ll::Component<u32> = inst.add_component();
+
+    // Nothing is recorded until tracking is turned on
+    c.set(&a, 1);
+    c.track_modified_entities();
+    assert_eq!(c.take_modified_entities(), Some(Vec::new()));
+
+    // Changes through clones and snapshots are recorded too
+    let clone = c.clone();
+    *clone.get_mut(&a).unwrap() += 1;
+    {
+        let mut snap = c.snapshot();
+        snap.set(&b, 3);
+        snap.commit();
+    }
+    assert_eq!(c.take_modified_entities(), Some(vec![a.clone(), b.clone()]));
+    assert_eq!(c.take_modified_entities(), Some(Vec::new()));
+
+    // Reading does not count as a change
+    assert_eq!(*c.get(&b).unwrap(), 3);
+    assert_eq!(c.take_modified_entities(), Some(Vec::new()));
+
+    // Clearing changes everything
+    c.take(&a);
+    clone.clone().clear();
+    assert_eq!(c.take_modified_entities(), None);
+    assert_eq!(c.take_modified_entities(), Some(Vec::new()));
+}
@@ -0,0 +1,132 @@
+// Command line tool for the Category5 IPC socket
+//
+// This sends one request to a running compositor and prints the JSON
+// response. The socket is found through CATEGORY5_SOCK, which is set
+// for every program Category5 launches.
+//
+// Austin Shafer - 2024
+extern crate serde_json;
+
+use serde_json::{json, Value};
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+static USAGE: &str = "Usage: cat5ctl [-s SOCKET] COMMAND
+
+Commands:
+  windows                     List all windows
+  workspaces                  List the workspaces
+  outputs                     List the displays
+  clients                     Count the resources of each client
+  focus ID                    Focus a window
+  close ID                    Ask a window to close
+  move ID workspace NUM       Move a window to a workspace
+  move ID to X Y              Move a window to a desktop position
+  opacity ID OPACITY          Set the opacity of a window and its app
+  reload                      Re-read the config files
+  raw JSON                    Send a request as is
+  ACTION [ARGS]               Run a keybinding action, i.e. toggle_hud
+";
+
+fn parse_num<T: std::str::FromStr>(arg: Option<&String>, name: &str) -> Result<T, String> {
+    arg.ok_or(format!("Missing {}", name))?
+        .parse()
+        .map_err(|_| format!("Invalid {}", name))
+}
+
+/// Turn our arguments into a request
+fn build_request(args: &[String]) -> Result<Value, String> {
+    let cmd = args.first().ok_or("No command given")?;
+    Ok(match cmd.as_str() {
+        "windows" => json!({ "type": "get_windows" }),
+        "workspaces" => json!({ "type": "get_workspaces" }),
+        "outputs" => json!({ "type": "get_outputs" }),
+        "clients" => json!({ "type": "get_clients" }),
+        "reload" => json!({ "type": "reload_config" }),
+        "focus" => {
+            json!({ "type": "focus_window", "id": parse_num::<usize>(args.get(1), "window id")? })
+        }
+        "close" => {
+            json!({ "type": "close_window", "id": parse_num::<usize>(args.get(1), "window id")? })
+        }
+        "move" => {
+            let id: usize = parse_num(args.get(1), "window id")?;
+            match args.get(2).map(|s| s.as_str()) {
+                Some("workspace") => json!({
+                    "type": "move_window",
+                    "id": id,
+                    "workspace": parse_num::<u32>(args.get(3), "workspace")?,
+                }),
+                Some("to") => json!({
+                    "type": "move_window",
+                    "id": id,
+                    "position": [
+                        parse_num::<f32>(args.get(3), "x position")?,
+                        parse_num::<f32>(args.get(4), "y position")?,
+                    ],
+                }),
+                _ => return Err("move requires `workspace NUM` or `to X Y`".to_string()),
+            }
+        }
+        "opacity" => json!({
+            "type": "set_window_opacity",
+            "id": parse_num::<usize>(args.get(1), "window id")?,
+            "opacity": parse_num::<f32>(args.get(2), "opacity")?,
+        }),
+        "raw" => serde_json::from_str(args.get(1).ok_or("Missing request")?)
+            .map_err(|e| format!("Invalid JSON: {}", e))?,
+        _ => json!({ "type": "command", "command": args.join(" ") }),
+    })
+}
+
+/// Send a request and wait for the response
+fn send_request(socket: &str, request: &Value) -> Result<Value, String> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Could not connect to {}: {}", socket, e))?;
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .map_err(|e| format!("Could not send request: {}", e))?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(|e| format!("Could not read response: {}", e))?;
+    serde_json::from_str(&response).map_err(|e| format!("Invalid response: {}", e))
+}
+
+fn run() -> Result<bool, String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
+        print!("{}", USAGE);
+        return Ok(!args.is_empty());
+    }
+
+    let socket = match args[0].as_str() {
+        "-s" => {
+            if args.len() < 2 {
+                return Err("-s requires a socket path".to_string());
+            }
+            let path = args.remove(1);
+            args.remove(0);
+            path
+        }
+        _ => std::env::var("CATEGORY5_SOCK")
+            .map_err(|_| "CATEGORY5_SOCK is not set, is Category5 running?")?,
+    };
+
+    let response = send_request(&socket, &build_request(&args)?)?;
+    println!("{}", serde_json::to_string_pretty(&response).unwrap());
+    Ok(response["success"] == json!(true))
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("cat5ctl: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
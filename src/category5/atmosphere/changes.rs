@@ -0,0 +1,105 @@
+// Property change subscriptions
+//
+// vkcomp used to copy the geometry of every surface into the scene on
+// every frame, even if only one of them had moved. Instead it subscribes
+// to the properties it reads, and the atmosphere records which surfaces
+// ways changed them for. Each frame vkcomp takes a list of the changed
+// surfaces and only updates those.
+//
+// Austin Shafer - 2024
+use super::*;
+
+/// The surface properties which can be subscribed to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Property {
+    SurfacePos,
+    SurfaceSize,
+    WindowPos,
+    WindowSize,
+    Workspace,
+}
+
+/// The surfaces whose subscribed properties changed
+///
+/// This is returned by `Atmosphere::take_changes`.
+pub struct ChangeList {
+    /// The surfaces each property changed for, sorted by raw id. None
+    /// if we can't tell which surfaces changed.
+    cl_changes: Vec<(Property, Option<Vec<SurfaceId>>)>,
+}
+
+impl ChangeList {
+    /// Get the surfaces `prop` changed for
+    ///
+    /// Returns None if we don't know which surfaces changed, such as when
+    /// `prop` was not subscribed to. In that case all of them should be
+    /// treated as changed.
+    pub fn get(&self, prop: Property) -> Option<&[SurfaceId]> {
+        self.cl_changes
+            .iter()
+            .find(|(p, _)| *p == prop)
+            .and_then(|(_, ids)| ids.as_deref())
+    }
+
+    /// Did `prop` change for this surface
+    pub fn contains(&self, prop: Property, id: &SurfaceId) -> bool {
+        match self.get(prop) {
+            Some(ids) => ids
+                .binary_search_by_key(&id.get_raw_id(), |i| i.get_raw_id())
+                .is_ok(),
+            None => true,
+        }
+    }
+}
+
+impl Atmosphere {
+    /// Start recording which surfaces `prop` is changed for
+    ///
+    /// The changes are returned by `take_changes`, which should be called
+    /// every frame once something has subscribed.
+    pub fn subscribe(&mut self, prop: Property) {
+        if self.a_subscriptions.iter().any(|(p, _)| *p == prop) {
+            return;
+        }
+
+        match prop {
+            Property::SurfacePos => self.a_surface_pos.track_modified_entities(),
+            Property::SurfaceSize => self.a_surface_size.track_modified_entities(),
+            Property::WindowPos => self.a_window_pos.track_modified_entities(),
+            Property::WindowSize => self.a_window_size.track_modified_entities(),
+            Property::Workspace => self.a_workspace.track_modified_entities(),
+        }
+        // Changes made before now weren't recorded
+        self.a_subscriptions.push((prop, false));
+    }
+
+    /// Get the surfaces whose subscribed properties changed since the
+    /// last call
+    pub fn take_changes(&mut self) -> ChangeList {
+        let mut changes = Vec::with_capacity(self.a_subscriptions.len());
+
+        for (prop, complete) in self.a_subscriptions.iter_mut() {
+            let ids = match prop {
+                Property::SurfacePos => self.a_surface_pos.take_modified_entities(),
+                Property::SurfaceSize => self.a_surface_size.take_modified_entities(),
+                Property::WindowPos => self.a_window_pos.take_modified_entities(),
+                Property::WindowSize => self.a_window_size.take_modified_entities(),
+                Property::Workspace => self.a_workspace.take_modified_entities(),
+            };
+
+            let ids = match std::mem::replace(complete, true) {
+                true => ids.map(|mut ids| {
+                    ids.sort_by_key(|id| id.get_raw_id());
+                    ids.dedup_by_key(|id| id.get_raw_id());
+                    ids
+                }),
+                false => None,
+            };
+            changes.push((*prop, ids));
+        }
+
+        ChangeList {
+            cl_changes: changes,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// Server side decorations
+//
+// Toplevels which ask for server side decorations through xdg-decoration
+// get a titlebar and border drawn around them by vkcomp. The decorations
+// are outside of the window geometry, so the titlebar sits above the
+// window and the border surrounds the rest of it. They are hidden while
+// the window is tiled, maximized or fullscreen.
+//
+// The decorations belong to us and not the client, so pointer input over
+// them is never delivered to the window. Floating toplevels also get a
+// drop shadow around their decorations, which is only drawn and never
+// takes any input.
+//
+// Austin Shafer - 2024
+extern crate wayland_protocols;
+use wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge;
+
+use super::*;
+
+impl Atmosphere {
+    /// Get the title the client gave this window, if it is a toplevel
+    pub fn get_window_title(&self, id: &SurfaceId) -> Option<String> {
+        let surf = self.get_surface_from_id(id)?;
+        let surf = surf.lock().unwrap();
+        surf.s_state
+            .cs_xdg_state
+            .xs_tlstate
+            .as_ref()
+            .and_then(|tl| tl.tl_title.clone())
+    }
+
+    /// Is this a root window that isn't tiled, maximized or fullscreen?
+    ///
+    /// `default` is returned for root windows which aren't toplevels.
+    fn window_is_floating(&self, id: &SurfaceId, default: bool) -> bool {
+        if self.a_parent_window.get(id).is_some() || self.window_is_tiled(id) {
+            return false;
+        }
+
+        // Maximized and fullscreen windows take up the whole desktop
+        match self.get_surface_from_id(id) {
+            Some(surf) => match surf
+                .lock()
+                .unwrap()
+                .s_state
+                .cs_xdg_state
+                .xs_tlstate
+                .as_ref()
+            {
+                Some(tl) => !tl.tl_maximized && !tl.tl_fullscreen,
+                None => default,
+            },
+            None => false,
+        }
+    }
+
+    /// Should this window be drawn with server side decorations?
+    pub fn window_has_decorations(&self, id: &SurfaceId) -> bool {
+        self.a_server_decorated.get_clone(id).unwrap_or(false) && self.window_is_floating(id, true)
+    }
+
+    /// Should this window be drawn with a drop shadow?
+    pub fn window_has_shadow(&self, id: &SurfaceId) -> bool {
+        self.window_is_floating(id, false)
+    }
+
+    /// Get the position and size of the window including its decorations
+    pub fn get_window_frame(&self, id: &SurfaceId) -> ((f32, f32), (f32, f32)) {
+        let ((wx, wy), (ww, wh)) = self.get_window_geometry(id);
+        if !self.window_has_decorations(id) {
+            return ((wx, wy), (ww, wh));
+        }
+        let (titlebar, border) = self.get_decoration_size();
+        let (titlebar, border) = (titlebar as f32, border as f32);
+
+        (
+            (wx - border, wy - titlebar),
+            (ww + border * 2.0, wh + titlebar + border),
+        )
+    }
+
+    /// Get the position and size of the window geometry
+    ///
+    /// This falls back to the surface size if the client has not set its
+    /// window geometry.
+    pub fn get_window_geometry(&self, id: &SurfaceId) -> ((f32, f32), (f32, f32)) {
+        let size = self
+            .a_window_size
+            .get_clone(id)
+            .or(self.a_surface_size.get_clone(id))
+            .unwrap_or((0.0, 0.0));
+        (self.a_window_pos.get_clone(id).unwrap_or((0.0, 0.0)), size)
+    }
+
+    /// Is a point on the desktop over this window's decorations?
+    ///
+    /// `(x, y)` should already be adjusted into desktop coordinates.
+    pub fn point_is_on_decoration(&self, id: &SurfaceId, x: f32, y: f32) -> bool {
+        if !self.window_has_decorations(id) {
+            return false;
+        }
+        let (titlebar, border) = self.get_decoration_size();
+        let (titlebar, border) = (titlebar as f32, border as f32);
+        let ((wx, wy), (ww, wh)) = self.get_window_geometry(id);
+
+        let in_frame =
+            x >= wx - border && x < wx + ww + border && y >= wy - titlebar && y < wy + wh + border;
+        let in_window = x >= wx && x < wx + ww && y >= wy && y < wy + wh;
+
+        in_frame && !in_window
+    }
+
+    /// Is the current point over the titlebar of the window
+    ///
+    /// Id should have first been found with find_window_at_point
+    pub fn point_is_on_titlebar(&self, id: &SurfaceId, x: f32, y: f32) -> bool {
+        let (x, y) = self.get_adjusted_desktop_coord(x, y);
+        let ((_, wy), _) = self.get_window_geometry(id);
+
+        self.point_is_on_decoration(id, x, y) && y < wy
+    }
+
+    /// Which edge of the window's border is this point over?
+    ///
+    /// Returns ResizeEdge::None if the point is not on the border.
+    pub fn point_is_on_border(&self, id: &SurfaceId, x: f32, y: f32) -> ResizeEdge {
+        let (x, y) = self.get_adjusted_desktop_coord(x, y);
+        if !self.point_is_on_decoration(id, x, y) {
+            return ResizeEdge::None;
+        }
+        let ((wx, wy), (ww, wh)) = self.get_window_geometry(id);
+
+        let left = x < wx;
+        let right = x >= wx + ww;
+        let bottom = y >= wy + wh;
+        match (left, right, bottom) {
+            (true, _, true) => ResizeEdge::BottomLeft,
+            (_, true, true) => ResizeEdge::BottomRight,
+            (_, _, true) => ResizeEdge::Bottom,
+            // The sides stop at the titlebar
+            (true, _, _) if y >= wy => ResizeEdge::Left,
+            (_, true, _) if y >= wy => ResizeEdge::Right,
+            _ => ResizeEdge::None,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Session locking
+//
+// While the session is locked the desktop is hidden and only the
+// surfaces of the screen locker are shown. All input goes to the
+// locker, no matter what is under the cursor. If the locker crashes
+// the session stays locked until a new locker unlocks it.
+//
+// Austin Shafer - 2024
+
+use super::*;
+use crate::category5::input::Input;
+use crate::category5::vkcomp::wm::task::Task;
+use utils::log;
+
+impl Atmosphere {
+    /// Is this one of the screen locker's surfaces?
+    pub fn is_lock_surface(&self, id: &SurfaceId) -> bool {
+        self.a_lock_surfaces.iter().any(|s| s == id)
+    }
+
+    /// Get the lock surfaces, from bottom to top
+    pub fn get_lock_surfaces(&self) -> &[SurfaceId] {
+        &self.a_lock_surfaces
+    }
+
+    /// Get the lock surface that receives input
+    ///
+    /// This is None if the session is not locked or if the locker has
+    /// not created a surface yet.
+    pub fn get_lock_focus(&self) -> Option<SurfaceId> {
+        match self.get_locked() {
+            true => self.a_lock_surfaces.last().cloned(),
+            false => None,
+        }
+    }
+
+    /// Lock the session
+    ///
+    /// Input is taken away from the desktop and vkcomp stops drawing it.
+    /// The focus is remembered so it can be restored when unlocking.
+    pub fn lock_session(&mut self) {
+        if self.get_locked() {
+            return;
+        }
+        log::debug!("Locking the session");
+
+        if let Some(id) = self.get_surf_focus() {
+            Input::keyboard_leave(self, &id);
+        }
+        if let Some(id) = self.get_pointer_focus() {
+            Input::pointer_leave(self, &id);
+        }
+        self.set_pointer_focus(None);
+        self.set_grabbed(None);
+        self.set_resizing(None);
+        self.a_grab_pos = None;
+        self.switcher_cancel();
+        self.overview_cancel();
+
+        self.set_locked(true);
+        self.add_wm_task(Task::lock_session);
+    }
+
+    /// Unlock the session
+    ///
+    /// The desktop is shown again and gets back its input focus.
+    pub fn unlock_session(&mut self) {
+        if !self.get_locked() {
+            return;
+        }
+        log::debug!("Unlocking the session");
+
+        if let Some(id) = self.get_lock_focus() {
+            Input::keyboard_leave(self, &id);
+        }
+        if let Some(id) = self.get_pointer_focus() {
+            Input::pointer_leave(self, &id);
+        }
+        self.set_pointer_focus(None);
+
+        self.set_locked(false);
+        self.add_wm_task(Task::unlock_session);
+
+        if let Some(id) = self.get_surf_focus() {
+            Input::keyboard_enter(self, &id);
+        }
+        self.recalculate_pointer_focus();
+    }
+
+    /// Add a surface from the screen locker
+    ///
+    /// The newest lock surface is placed on top and given the input focus.
+    pub fn add_lock_surface(&mut self, id: &SurfaceId) {
+        if let Some(old) = self.get_lock_focus() {
+            Input::keyboard_leave(self, &old);
+        }
+
+        self.a_lock_surfaces.push(id.clone());
+        // Lock surfaces cover the whole screen, including the menubar
+        self.a_surface_pos.set(id, (0.0, 0.0));
+        self.add_wm_task(Task::new_lock_surface(id.clone()));
+
+        Input::keyboard_enter(self, id);
+        self.recalculate_pointer_focus();
+    }
+
+    /// Remove a surface from the screen locker
+    ///
+    /// This does not unlock the session, if the locker goes away without
+    /// unlocking the screen stays blank.
+    pub fn remove_lock_surface(&mut self, id: &SurfaceId) {
+        if !self.is_lock_surface(id) {
+            return;
+        }
+        let had_focus = self.get_lock_focus().as_ref() == Some(id);
+
+        self.a_lock_surfaces.retain(|s| s != id);
+        self.add_wm_task(Task::remove_lock_surface(id.clone()));
+
+        if had_focus {
+            if let Some(new) = self.get_lock_focus() {
+                Input::keyboard_enter(self, &new);
+            }
+        }
+        if self.get_pointer_focus().as_ref() == Some(id) {
+            self.set_pointer_focus(None);
+            self.recalculate_pointer_focus();
+        }
+    }
+}
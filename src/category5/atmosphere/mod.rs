@@ -10,6 +10,8 @@
 // Austin Shafer - 2020
 extern crate wayland_server as ws;
 use crate::category5::ws::Resource;
+use wayland_protocols::wp::content_type::v1::server::wp_content_type_v1;
+use wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
 use ws::protocol::{wl_buffer, wl_callback, wl_shm, wl_surface};
 extern crate paste;
 use paste::paste;
@@ -17,14 +19,33 @@ use paste::paste;
 extern crate dakota as dak;
 extern crate lluvia as ll;
 
+mod changes;
+mod decoration;
+mod lock;
+mod opacity;
+mod overview;
+mod recovery;
+mod seats;
 mod skiplist;
+mod snapping;
+mod switcher;
+mod tiling;
+mod window_state;
+mod workspace;
+pub use changes::Property;
+pub use overview::{Overview, OverviewSlot};
+pub use switcher::{SwitcherOrder, WindowSwitcher};
+pub use tiling::{LayoutMode, WorkspaceLayout};
+pub use workspace::NUM_WORKSPACES;
 
 use crate::category5::input::Input;
-use crate::category5::vkcomp::{release_info::GenericReleaseInfo, wm};
+use crate::category5::vkcomp::release_info::{GenericReleaseInfo, ShmReleaseInfo};
+use crate::category5::vkcomp::wm;
+use crate::category5::vkcomp::wm::cursor::CursorShape;
 use crate::category5::ways::{seat::Seat, shm::ShmBuffer, surface::*, wl_region::Region};
-use utils::log;
+use utils::{log, MemImage};
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
@@ -85,6 +106,11 @@ pub struct Atmosphere {
     pub a_pointer_focus: Option<SurfaceId>,
     /// Current surface in use for a cursor, if any
     pub a_cursor_surface: Option<SurfaceId>,
+    /// Did a client set the cursor? This may be true while the
+    /// cursor surface is None if the client hid the cursor.
+    pub a_client_cursor: bool,
+    /// The cursor from our theme to show when a client hasn't set one
+    pub a_cursor_shape: CursorShape,
     /// Is recording traces with Renderdoc enabled?
     /// This is used for debugging. input will trigger this, which tells vkcomp
     /// to record frames.
@@ -92,11 +118,56 @@ pub struct Atmosphere {
     /// The name of the DRM node in use. This will be filled in by vkcomp
     /// and populated from VK_EXT_physical_device_drm
     pub a_drm_dev: (i64, i64),
+    /// The workspace currently being displayed
+    ///
+    /// This is global rather than per-output, as we only drive one
+    /// output at a time.
+    pub a_current_workspace: u32,
+    /// The layout policy of each workspace
+    pub a_workspace_layouts: Vec<WorkspaceLayout>,
+    /// Do tiled workspaces need to be laid out again?
+    pub a_tiling_dirty: bool,
+    /// The position the grabbed window would have without snapping
+    pub a_grab_pos: Option<(SurfaceId, (f32, f32))>,
+    /// Is the window being moved or resized snapped to an edge?
+    pub a_grab_snapped: bool,
+    /// Should vkcomp animate windows?
+    pub a_animations_enabled: bool,
+    /// Should vkcomp draw the performance overlay?
+    pub a_hud_enabled: bool,
+    /// Should shm buffers be sampled in place instead of copied?
+    pub a_zero_copy_shm: bool,
+    /// Is the session locked by a screen locker?
+    pub a_locked: bool,
+    /// The screen locker's surfaces, from bottom to top
+    pub a_lock_surfaces: Vec<SurfaceId>,
+    /// The titlebar height and border width of server side decorations
+    pub a_decoration_size: (u32, u32),
+    /// The brightness of windows which aren't in focus, 1.0 turns off
+    /// dimming
+    pub a_dim_unfocused: f32,
+    /// The opacity of each app's windows, by app_id
+    a_opacity_rules: BTreeMap<String, f32>,
+    /// Every seat, with the default seat first
+    a_seats: Vec<seats::SeatInfo>,
+    /// The seat whose cursor and focus are in the fields above
+    a_active_seat: usize,
+    /// Root windows in the order they were last focused, most recent first
+    a_focus_history: Vec<SurfaceId>,
+    /// The alt-tab switcher, if it is open
+    a_switcher: Option<WindowSwitcher>,
+    /// The window overview, if it is open
+    a_overview: Option<Overview>,
 
     pub a_changed: bool,
+    /// The properties vkcomp is told about changes to, and whether all of
+    /// their changes have been recorded since subscribing
+    a_subscriptions: Vec<(Property, bool)>,
 
     /// Tasks to be handled by vkcomp before rendering the next frame
     pub a_wm_tasks: VecDeque<wm::task::Task>,
+    /// Presentation feedback for the surfaces in the frame being drawn
+    pub a_drawn_feedback: Vec<wp_presentation_feedback::WpPresentationFeedback>,
 
     // -------------------------------------------------------
     /// Client id tracking
@@ -124,6 +195,11 @@ pub struct Atmosphere {
     /// does this window have the toplevel role
     /// this controls if SSD are drawn
     pub a_toplevel: ll::Component<bool>,
+    /// Did the client ask us to draw its decorations?
+    pub a_server_decorated: ll::Component<bool>,
+    /// The workspace this toplevel is assigned to
+    /// Only root windows have this set
+    pub a_workspace: ll::Component<u32>,
     /// the position of the visible portion of the window
     pub a_window_pos: ll::Component<(f32, f32)>,
     /// size of the visible portion : `ll::Component<non-CSD>` of the window
@@ -136,6 +212,15 @@ pub struct Atmosphere {
     /// aka the size of the last buffer attached
     /// vkcomp uses this
     pub a_surface_size: ll::Component<(f32, f32)>,
+    /// The size of the last buffer attached
+    ///
+    /// This differs from the surface size if the surface has a viewport.
+    pub a_buffer_size: ll::Component<(f32, f32)>,
+    /// What kind of content the client says this surface shows
+    pub a_content_type: ll::Component<wp_content_type_v1::Type>,
+    /// Has the client asked for this surface to be presented without
+    /// waiting for vblank?
+    pub a_tearing: ll::Component<bool>,
     /// This window's position in the desktop order
     ///
     /// The next window behind this one
@@ -179,6 +264,11 @@ pub struct Atmosphere {
     /// These will be signaled on the next draw point so the
     /// surface can commit new contents
     pub a_frame_callbacks: ll::Component<Vec<wl_callback::WlCallback>>,
+    /// Presentation feedback for the committed contents
+    ///
+    /// These are moved to `a_drawn_feedback` when the surface is drawn
+    pub a_presentation_feedback:
+        ll::Component<Vec<wp_presentation_feedback::WpPresentationFeedback>>,
     /// The opaque region.
     /// vkcomp can optimize displaying this region
     pub a_opaque_region: ll::Component<Arc<Mutex<Region>>>,
@@ -188,6 +278,14 @@ pub struct Atmosphere {
     /// Scene resources per surface. This is the same as dakota.resource(), and
     /// is the resource currently bound to this surface (i.e. dakota element)
     pub a_surf_resource: ll::Component<BufferId>,
+    /// Has this surface committed a buffer since its thumbnail was drawn
+    pub a_thumbnail_stale: ll::Component<bool>,
+    /// The opacity of windows without an app_id
+    a_window_opacity: ll::Component<f32>,
+    /// Is this window minimized
+    a_minimized: ll::Component<bool>,
+    /// Does this fullscreen window's buffer cover the whole output
+    a_covers_output: ll::Component<bool>,
 
     // -------------------------------------------------------
     // Resource id tracking
@@ -196,6 +294,9 @@ pub struct Atmosphere {
     // These will be attached to SurfaceIds to assign window content.
     /// Shadow Resource (local copy of buffer)
     a_shadow_buffer: ll::Component<ShadowBuffer>,
+    /// The client dmabuf imported into this resource. This is kept so the
+    /// dmabuf can be imported again if the GPU is lost.
+    a_dmabuf_buffer: ll::Component<wl_buffer::WlBuffer>,
 }
 
 // Implement getters/setters for our global properties
@@ -223,8 +324,15 @@ impl Atmosphere {
     define_global_getters!(surf_focus, Option<SurfaceId>);
     define_global_getters!(pointer_focus, Option<SurfaceId>);
     define_global_getters!(cursor_surface, Option<SurfaceId>);
+    define_global_getters!(client_cursor, bool);
     define_global_getters!(renderdoc_recording, bool);
     define_global_getters!(drm_dev, (i64, i64));
+    define_global_getters!(current_workspace, u32);
+    define_global_getters!(animations_enabled, bool);
+    define_global_getters!(hud_enabled, bool);
+    define_global_getters!(locked, bool);
+    define_global_getters!(decoration_size, (u32, u32));
+    define_global_getters!(dim_unfocused, f32);
 }
 
 impl Atmosphere {
@@ -249,10 +357,36 @@ impl Atmosphere {
             a_surf_focus: None,
             a_pointer_focus: None,
             a_cursor_surface: None,
+            a_client_cursor: false,
+            a_cursor_shape: CursorShape::Default,
             a_renderdoc_recording: false,
             a_changed: false,
             a_drm_dev: (0, 0),
+            a_current_workspace: 0,
+            a_workspace_layouts: (0..NUM_WORKSPACES)
+                .map(|_| WorkspaceLayout::new())
+                .collect(),
+            a_tiling_dirty: false,
+            a_grab_pos: None,
+            a_grab_snapped: false,
+            // Animations can be turned off with an environment variable
+            a_animations_enabled: std::env::var("CATEGORY5_DISABLE_ANIMATIONS").is_err(),
+            // The performance overlay can be shown at startup for debugging
+            a_hud_enabled: std::env::var("CATEGORY5_HUD").is_ok(),
+            a_zero_copy_shm: false,
+            a_locked: false,
+            a_lock_surfaces: Vec::new(),
+            a_decoration_size: (0, 0),
+            a_dim_unfocused: 1.0,
+            a_opacity_rules: BTreeMap::new(),
+            a_seats: vec![seats::SeatInfo::default_seat()],
+            a_active_seat: 0,
+            a_focus_history: Vec::new(),
+            a_switcher: None,
+            a_overview: None,
+            a_subscriptions: Vec::new(),
             a_wm_tasks: VecDeque::new(),
+            a_drawn_feedback: Vec::new(),
             // ---------------------
             a_windows_for_client: client_ecs.add_component(),
             a_seat: client_ecs.add_component(),
@@ -261,10 +395,15 @@ impl Atmosphere {
             a_window_in_use: surf_ecs.add_component(),
             a_owner: surf_ecs.add_component(),
             a_toplevel: surf_ecs.add_component(),
+            a_server_decorated: surf_ecs.add_component(),
+            a_workspace: surf_ecs.add_component(),
             a_window_pos: surf_ecs.add_component(),
             a_window_size: surf_ecs.add_component(),
             a_surface_pos: surf_ecs.add_component(),
             a_surface_size: surf_ecs.add_component(),
+            a_buffer_size: surf_ecs.add_component(),
+            a_content_type: surf_ecs.add_component(),
+            a_tearing: surf_ecs.add_component(),
             a_skiplist_next: surf_ecs.add_component(),
             a_skiplist_prev: surf_ecs.add_component(),
             a_skiplist_skip: surf_ecs.add_component(),
@@ -277,11 +416,17 @@ impl Atmosphere {
             a_surface_damage: surf_ecs.add_component(),
             a_buffer_damage: surf_ecs.add_component(),
             a_frame_callbacks: surf_ecs.add_component(),
+            a_presentation_feedback: surf_ecs.add_component(),
             a_opaque_region: surf_ecs.add_component(),
             a_input_region: surf_ecs.add_component(),
             a_surf_resource: scene.resource(),
+            a_thumbnail_stale: surf_ecs.add_component(),
+            a_window_opacity: surf_ecs.add_component(),
+            a_minimized: surf_ecs.add_component(),
+            a_covers_output: surf_ecs.add_component(),
             // ---------------------
             a_shadow_buffer: resource_ecs.add_component(),
+            a_dmabuf_buffer: resource_ecs.add_component(),
             a_surface_ecs: surf_ecs,
         }
     }
@@ -297,6 +442,8 @@ impl Atmosphere {
             || self.a_window_in_use.is_modified()
             || self.a_owner.is_modified()
             || self.a_toplevel.is_modified()
+            || self.a_server_decorated.is_modified()
+            || self.a_workspace.is_modified()
             || self.a_window_pos.is_modified()
             || self.a_window_size.is_modified()
             || self.a_surface_pos.is_modified()
@@ -322,6 +469,8 @@ impl Atmosphere {
         self.a_window_in_use.clear_modified();
         self.a_owner.clear_modified();
         self.a_toplevel.clear_modified();
+        self.a_server_decorated.clear_modified();
+        self.a_workspace.clear_modified();
         self.a_window_pos.clear_modified();
         self.a_window_size.clear_modified();
         self.a_surface_pos.clear_modified();
@@ -344,10 +493,6 @@ impl Atmosphere {
         self.a_changed = true;
     }
 
-    pub fn get_barsize(&self) -> f32 {
-        self.get_resolution().1 as f32 * 0.02
-    }
-
     // ------------------------------
     // For the sake of abstraction, the atmosphere will be the
     // point of contact for modifying global state. We will
@@ -428,8 +573,16 @@ impl Atmosphere {
         // we also need to remove this surface from focus
         self.skiplist_remove_win_focus(id);
         self.skiplist_remove_surf_focus(id);
+        // the other seats may be using it too
+        self.seats_remove_surface(id);
+        self.switcher_remove_window(id);
+        self.overview_remove_window(id);
         // remove this id from the heirarchy
         self.skiplist_remove_window(id);
+        // A locker that crashed may not have destroyed its lock surfaces
+        self.remove_lock_surface(id);
+        // Tiled windows need to fill the space left behind
+        self.mark_tiling_dirty();
         // TODO: generate RemoveWindow event?
 
         // remove this window from the clients list
@@ -454,7 +607,11 @@ impl Atmosphere {
     /// convert a global location to a surface local coordinates.
     /// Returns None if the location given is not over the surface
     pub fn global_coords_to_surf(&self, id: &SurfaceId, x: f64, y: f64) -> Option<(f64, f64)> {
-        let (x, y) = self.get_adjusted_desktop_coord(x as f32, y as f32);
+        // Lock surfaces are placed on the screen, not the desktop
+        let (x, y) = match self.is_lock_surface(id) {
+            true => (x as f32, y as f32),
+            false => self.get_adjusted_desktop_coord(x as f32, y as f32),
+        };
         let (x, y) = (x as f64, y as f64);
         // get the surface-local position
         let (mut wx, mut wy) = *self.a_surface_pos.get(id).unwrap();
@@ -515,6 +672,7 @@ impl Atmosphere {
                 wl_buffer: buffer.clone(),
             })),
         )?;
+        self.a_dmabuf_buffer.set(resource, buffer);
 
         Ok(())
     }
@@ -532,9 +690,52 @@ impl Atmosphere {
         return id;
     }
 
+    /// Try to sample a shm buffer in place
+    ///
+    /// The buffer gets its own resource, like a dmabuf, which holds the
+    /// wl_buffer until the GPU is done with it. Returns false if the
+    /// device can't sample this buffer, in which case it should be copied.
+    fn import_shm_resource(
+        &mut self,
+        scene: &mut dak::Scene,
+        surf: &SurfaceId,
+        shm_buffer: &ShmBuffer,
+        pixels: &MemImage,
+        buffer: &wl_buffer::WlBuffer,
+    ) -> bool {
+        let resource = self.mint_buffer_id(scene);
+        let release = ShmReleaseInfo {
+            _release: GenericReleaseInfo {
+                wl_buffer: buffer.clone(),
+            },
+            _mapping: shm_buffer.get_mapping(),
+        };
+
+        // Safe since the release info keeps the pool mapped
+        let ret = unsafe {
+            scene.define_resource_from_host_memory(
+                &resource,
+                pixels,
+                shm_buffer.sb_width as u32,
+                shm_buffer.sb_height as u32,
+                pixels.stride,
+                shm_buffer.get_format(),
+                Some(Box::new(release)),
+            )
+        };
+        if let Err(e) = ret {
+            log::debug!("Copying shm buffer instead of sampling it: {:?}", e);
+            return false;
+        }
+
+        self.a_surf_resource.set(surf, resource);
+        true
+    }
+
     /// Handle update from memimage task
     ///
-    /// Copies the shm buffer into the app's image.
+    /// Copies the shm buffer into the app's image, or samples it in place
+    /// if zero copy shm is enabled and supported.
     /// Creates a new image if one doesn't exist yet.
     pub fn update_shm_resource(
         &mut self,
@@ -543,12 +744,27 @@ impl Atmosphere {
         shm_buffer: &ShmBuffer,
         buffer: &wl_buffer::WlBuffer,
     ) -> dak::Result<()> {
+        let pixels = match shm_buffer.get_pixels() {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                buffer.post_error(
+                    wl_shm::Error::InvalidStride as u32,
+                    format!("Invalid Shm Buffer: {:?}", e),
+                );
+                return Err(e.context("Failed to read Shm Buffer"));
+            }
+        };
+        if self.a_zero_copy_shm
+            && self.import_shm_resource(scene, surf, shm_buffer, &pixels, buffer)
+        {
+            return Ok(());
+        }
+
         // Get the shadow resource if it exists. If not, create it.
         // We do this by checking if the surface is currently assigned a resource
         // which has had its shadow state set.
         let shadow = self.get_shadow_resource(scene, surf);
 
-        let pixels = shm_buffer.get_mem_image();
         if let Err(e) = match scene.is_resource_defined(&shadow) {
             // If the shadow resource is defined, then copy the damaged regions
             // of this new buffer into the shadow copy.
@@ -557,8 +773,8 @@ impl Atmosphere {
                 &pixels,
                 shm_buffer.sb_width as u32,
                 shm_buffer.sb_height as u32,
-                0,
-                dak::dom::Format::ARGB8888,
+                pixels.stride,
+                shm_buffer.get_format(),
                 self.a_buffer_damage.take(&surf),
             ),
             // If the shadow resource is not defined, define it now using the
@@ -568,8 +784,8 @@ impl Atmosphere {
                 &pixels,
                 shm_buffer.sb_width as u32,
                 shm_buffer.sb_height as u32,
-                0,
-                dak::dom::Format::ARGB8888,
+                pixels.stride,
+                shm_buffer.get_format(),
             ),
         } {
             buffer.post_error(
@@ -611,11 +827,48 @@ impl Atmosphere {
     }
 
     /// Update the cursor image
+    ///
+    /// Only the default seat's cursor can be changed, the other seats
+    /// always show our default cursor.
     pub fn set_cursor(&mut self, id: Option<SurfaceId>) {
+        if self.get_active_seat() != 0 {
+            return;
+        }
         self.set_cursor_surface(id.clone());
+        self.set_client_cursor(true);
         self.add_wm_task(wm::task::Task::set_cursor { id: id });
     }
 
+    pub fn get_cursor_shape(&self) -> CursorShape {
+        self.a_cursor_shape
+    }
+
+    /// Show one of our theme's cursors
+    ///
+    /// This replaces any cursor set by a client. Nothing is done if this
+    /// cursor is already being shown.
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        if self.get_active_seat() != 0 {
+            return;
+        }
+        if shape == self.a_cursor_shape && !self.a_client_cursor {
+            return;
+        }
+
+        self.a_cursor_shape = shape;
+        self.a_client_cursor = false;
+        self.a_cursor_surface = None;
+        self.add_wm_task(wm::task::Task::set_cursor_shape(shape));
+    }
+
+    /// Clear the cursor image a client set
+    pub fn reset_cursor(&mut self) {
+        if self.get_active_seat() != 0 {
+            return;
+        }
+        self.add_wm_task(wm::task::Task::reset_cursor);
+    }
+
     /// Add an offset to the cursor patch
     ///
     /// This increments the cursor position, which will later
@@ -627,19 +880,14 @@ impl Atmosphere {
         // Now update the grabbed window if it exists
         let grabbed = match self.get_grabbed() {
             Some(g) => g,
-            None => return,
+            None => {
+                // Forget the last grab so the next one starts fresh
+                self.a_grab_pos = None;
+                return;
+            }
         };
 
-        // Need to update both the surface and window positions
-        let mut gpos = *self.a_window_pos.get(&grabbed).unwrap();
-        gpos.0 += dx as f32;
-        gpos.1 += dy as f32;
-        self.a_window_pos.set(&grabbed, (gpos.0, gpos.1));
-
-        let mut gpos = *self.a_surface_pos.get(&grabbed).unwrap();
-        gpos.0 += dx as f32;
-        gpos.1 += dy as f32;
-        self.a_surface_pos.set(&grabbed, (gpos.0, gpos.1));
+        self.move_grabbed_window(&grabbed, dx as f32, dy as f32);
     }
 
     // -- subsystem specific handlers --
@@ -655,6 +903,31 @@ impl Atmosphere {
         self.a_surface.get_clone(id)
     }
 
+    /// Get the focused window if it is fullscreen
+    ///
+    /// Only windows whose buffer covers the whole output are returned,
+    /// anything else still has to be composited with the border fill.
+    pub fn get_fullscreen_win_in_focus(&self) -> Option<SurfaceId> {
+        self.get_root_win_in_focus()
+            .filter(|win| self.window_is_fullscreen(win) && self.window_covers_output(win))
+    }
+
+    /// Is this window fullscreen?
+    pub fn window_is_fullscreen(&self, id: &SurfaceId) -> bool {
+        match self.get_surface_from_id(id) {
+            Some(surf) => surf
+                .lock()
+                .unwrap()
+                .s_state
+                .cs_xdg_state
+                .xs_tlstate
+                .as_ref()
+                .map(|tl| tl.tl_fullscreen)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
     /// Grab the wayland protocol object wl_surface for this id
     pub fn get_wl_surface_from_id(&self, id: &SurfaceId) -> Option<wl_surface::WlSurface> {
         self.a_wl_surface.get_clone(id)
@@ -675,6 +948,24 @@ impl Atmosphere {
     pub fn get_seat_from_client_id(&self, id: &ClientId) -> Option<Arc<Mutex<Seat>>> {
         self.a_seat.get_clone(id).clone()
     }
+    /// Get the seats of every client
+    pub fn get_all_seats(&self) -> Vec<Arc<Mutex<Seat>>> {
+        self.a_seat
+            .iter()
+            .flatten()
+            .map(|seat| seat.clone())
+            .collect()
+    }
+
+    /// Mark a surface's committed contents as being drawn
+    ///
+    /// Its presentation feedback will be sent once the frame being
+    /// drawn reaches the screen.
+    pub fn mark_feedback_drawn(&mut self, id: &SurfaceId) {
+        if let Some(mut feedback) = self.a_presentation_feedback.get_mut(id) {
+            self.a_drawn_feedback.extend(feedback.drain(..));
+        }
+    }
 
     /// Signal any registered frame callbacks
     /// TODO: actually do optimizations
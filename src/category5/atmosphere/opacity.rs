@@ -0,0 +1,92 @@
+// Window opacity and dimming
+//
+// Windows can be made translucent with keybinding actions or over IPC.
+// Opacity is remembered per app_id, so every window of an application
+// shares it and new windows open with it. The config's window opacity
+// table gives the starting values, and changes made while running last
+// until the config is reloaded. Windows without an app_id keep their own
+// opacity.
+//
+// Windows which aren't in focus can also be dimmed. vkcomp draws both
+// with Dakota's per-element opacity and tint, so subsurfaces and
+// decorations follow their window.
+//
+// Austin Shafer - 2024
+
+use super::*;
+
+use std::collections::BTreeMap;
+
+/// The lowest opacity a window can be given, so it can't be lost entirely
+pub static MIN_WINDOW_OPACITY: f32 = 0.1;
+
+impl Atmosphere {
+    /// Get the app_id the client gave this window, if it is a toplevel
+    pub fn get_window_app_id(&self, id: &SurfaceId) -> Option<String> {
+        let surf = self.get_surface_from_id(id)?;
+        let surf = surf.lock().unwrap();
+        surf.s_state
+            .cs_xdg_state
+            .xs_tlstate
+            .as_ref()
+            .and_then(|tl| tl.tl_app_id.clone())
+    }
+
+    /// Replace the opacity of every app with the rules from the config
+    ///
+    /// Opacities set on windows without an app_id are kept.
+    pub fn set_opacity_rules(&mut self, rules: BTreeMap<String, f32>) {
+        self.a_opacity_rules = rules
+            .into_iter()
+            .map(|(app_id, opacity)| (app_id, opacity.clamp(MIN_WINDOW_OPACITY, 1.0)))
+            .collect();
+        self.mark_changed();
+    }
+
+    /// Get the opacity of a window, 1.0 if it is opaque
+    pub fn get_window_opacity(&self, id: &SurfaceId) -> f32 {
+        if let Some(opacity) = self.a_window_opacity.get_clone(id) {
+            return opacity;
+        }
+
+        self.get_window_app_id(id)
+            .and_then(|app_id| self.a_opacity_rules.get(&app_id).copied())
+            .unwrap_or(1.0)
+    }
+
+    /// Set the opacity of a window and every other window of its app
+    pub fn set_window_opacity(&mut self, id: &SurfaceId, opacity: f32) {
+        let opacity = opacity.clamp(MIN_WINDOW_OPACITY, 1.0);
+        match self.get_window_app_id(id) {
+            Some(app_id) => {
+                log::debug!("Setting the opacity of {} to {}", app_id, opacity);
+                self.a_opacity_rules.insert(app_id, opacity);
+            }
+            None => self.a_window_opacity.set(id, opacity),
+        }
+        self.mark_changed();
+    }
+
+    /// Make a window more or less opaque by `delta`
+    pub fn adjust_window_opacity(&mut self, id: &SurfaceId, delta: f32) {
+        self.set_window_opacity(id, self.get_window_opacity(id) + delta);
+    }
+
+    /// Get how much to dim a window by
+    ///
+    /// Returns the brightness to tint the window with, or None if it
+    /// should be drawn normally. Only toplevels which aren't in focus are
+    /// dimmed, and nothing is dimmed while the overview is open.
+    pub fn get_window_dim(&self, id: &SurfaceId) -> Option<f32> {
+        let brightness = self.get_dim_unfocused();
+        if brightness >= 1.0
+            || self.is_overview_open()
+            || !self.a_toplevel.get_clone(id).unwrap_or(false)
+            || self.get_keyboard_focus().as_ref() == Some(id)
+        {
+            return None;
+        }
+
+        Some(brightness)
+    }
+}
@@ -0,0 +1,254 @@
+// Overview mode
+//
+// The overview shows every window on the current workspace at once,
+// shrunk down and laid out in a grid. A window is picked by clicking on
+// it or with the arrow keys, and is focused when the overview closes.
+// While it is open all pointer and keyboard input is redirected here,
+// so clients never see it.
+//
+// vkcomp animates the windows into their slots with per-surface
+// transforms. Since the windows are not really moved, pointer input is
+// hit-tested against the slots instead of the window positions.
+//
+// Austin Shafer - 2024
+
+use super::tiling::Tile;
+use super::*;
+use crate::category5::input::Input;
+use utils::log;
+
+/// Space between the windows in the grid, and around its edges
+static OVERVIEW_PADDING: f32 = 32.0;
+
+/// Where a window is shown in the overview
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverviewSlot {
+    /// The top left corner of the shrunk window geometry, relative to
+    /// the desktop
+    pub os_pos: (f32, f32),
+    /// The size of the shrunk window geometry
+    pub os_size: (f32, f32),
+    /// How much the window was shrunk by
+    pub os_scale: f32,
+}
+
+impl OverviewSlot {
+    fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.os_pos.0
+            && y >= self.os_pos.1
+            && x < self.os_pos.0 + self.os_size.0
+            && y < self.os_pos.1 + self.os_size.1
+    }
+}
+
+/// An open overview
+#[derive(Debug, Clone)]
+pub struct Overview {
+    /// The windows shown, in stacking order
+    pub ov_windows: Vec<SurfaceId>,
+    /// The slot of each window in `ov_windows`
+    pub ov_slots: Vec<OverviewSlot>,
+    /// The number of columns in the grid
+    pub ov_columns: usize,
+    /// The index of the selected window
+    pub ov_selected: usize,
+}
+
+/// Lay out windows of `sizes` in a grid filling `area`
+///
+/// The grid is as close to square as possible. Windows keep their aspect
+/// ratio and are centered in their cell, and are never scaled up. Returns
+/// the slots along with the number of columns.
+fn overview_grid(sizes: &[(f32, f32)], area: &Tile) -> (Vec<OverviewSlot>, usize) {
+    if sizes.is_empty() {
+        return (Vec::new(), 0);
+    }
+    let columns = (sizes.len() as f32).sqrt().ceil() as usize;
+    let rows = sizes.len().div_ceil(columns);
+    let cell = (
+        ((area.t_size.0 - OVERVIEW_PADDING * (columns + 1) as f32) / columns as f32).max(1.0),
+        ((area.t_size.1 - OVERVIEW_PADDING * (rows + 1) as f32) / rows as f32).max(1.0),
+    );
+
+    let slots = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| {
+            let (col, row) = ((i % columns) as f32, (i / columns) as f32);
+            let scale = (cell.0 / size.0.max(1.0))
+                .min(cell.1 / size.1.max(1.0))
+                .min(1.0);
+            let scaled = (size.0 * scale, size.1 * scale);
+
+            OverviewSlot {
+                os_pos: (
+                    area.t_pos.0
+                        + OVERVIEW_PADDING
+                        + col * (cell.0 + OVERVIEW_PADDING)
+                        + (cell.0 - scaled.0) / 2.0,
+                    area.t_pos.1
+                        + OVERVIEW_PADDING
+                        + row * (cell.1 + OVERVIEW_PADDING)
+                        + (cell.1 - scaled.1) / 2.0,
+                ),
+                os_size: scaled,
+                os_scale: scale,
+            }
+        })
+        .collect();
+
+    (slots, columns)
+}
+
+impl Atmosphere {
+    /// Recalculate the grid after the windows in the overview changed
+    fn overview_relayout(&mut self) {
+        let sizes: Vec<(f32, f32)> = match self.a_overview.as_ref() {
+            Some(overview) => overview
+                .ov_windows
+                .iter()
+                .map(|id| self.get_window_geometry(id).1)
+                .collect(),
+            None => return,
+        };
+
+        let (slots, columns) = overview_grid(&sizes, &self.get_tiling_area());
+        let overview = self.a_overview.as_mut().unwrap();
+        overview.ov_slots = slots;
+        overview.ov_columns = columns;
+    }
+
+    /// Open the overview, or close it without changing the focus
+    pub fn toggle_overview(&mut self) {
+        if self.is_overview_open() {
+            self.overview_cancel();
+            return;
+        }
+        if self.get_locked() {
+            return;
+        }
+
+        let windows: Vec<SurfaceId> = self.visible_windows().collect();
+        if windows.is_empty() {
+            return;
+        }
+        log::debug!("Opening the overview with {} windows", windows.len());
+
+        // The pointer is over the overview now, not a client
+        if let Some(id) = self.get_pointer_focus() {
+            Input::pointer_leave(self, &id);
+        }
+        self.set_pointer_focus(None);
+        self.set_grabbed(None);
+        self.set_resizing(None);
+        self.switcher_cancel();
+
+        // Start with the window in focus selected
+        let focus = self.get_keyboard_focus();
+        let selected = windows
+            .iter()
+            .position(|w| Some(w) == focus.as_ref())
+            .unwrap_or(0);
+        self.a_overview = Some(Overview {
+            ov_windows: windows,
+            ov_slots: Vec::new(),
+            ov_columns: 0,
+            ov_selected: selected,
+        });
+        self.overview_relayout();
+        self.set_cursor_shape(CursorShape::Default);
+        self.mark_changed();
+    }
+
+    /// Remove a window that is going away from the overview
+    pub(super) fn overview_remove_window(&mut self, id: &SurfaceId) {
+        let overview = match self.a_overview.as_mut() {
+            Some(overview) => overview,
+            None => return,
+        };
+        if let Some(index) = overview.ov_windows.iter().position(|w| w == id) {
+            overview.ov_windows.remove(index);
+            if index < overview.ov_selected || overview.ov_selected >= overview.ov_windows.len() {
+                overview.ov_selected = overview.ov_selected.saturating_sub(1);
+            }
+            match overview.ov_windows.is_empty() {
+                true => self.overview_cancel(),
+                false => self.overview_relayout(),
+            }
+            self.mark_changed();
+        }
+    }
+
+    /// Find the window shown in the overview at a point on the screen
+    ///
+    /// Returns its index in the overview's window list.
+    pub fn overview_window_at_point(&self, x: f32, y: f32) -> Option<usize> {
+        let overview = self.a_overview.as_ref()?;
+        let (x, y) = self.get_adjusted_desktop_coord(x, y);
+        overview.ov_slots.iter().position(|s| s.contains(x, y))
+    }
+
+    /// Select a window by its index in the overview's window list
+    pub fn overview_select(&mut self, index: usize) {
+        if let Some(overview) = self.a_overview.as_mut() {
+            if overview.ov_selected != index && index < overview.ov_windows.len() {
+                overview.ov_selected = index;
+                self.mark_changed();
+            }
+        }
+    }
+
+    /// Select the window under the cursor, if there is one
+    pub fn overview_select_at_cursor(&mut self) {
+        let (cx, cy) = self.get_cursor_pos();
+        if let Some(index) = self.overview_window_at_point(cx as f32, cy as f32) {
+            self.overview_select(index);
+        }
+    }
+
+    /// Move the selection through the grid
+    ///
+    /// `dx` moves between columns and `dy` between rows. The selection
+    /// stops at the edges of the grid.
+    pub fn overview_move_selection(&mut self, dx: i32, dy: i32) {
+        let overview = match self.a_overview.as_mut() {
+            Some(overview) => overview,
+            None => return,
+        };
+        let columns = overview.ov_columns.max(1) as i32;
+        let len = overview.ov_windows.len() as i32;
+        let rows = (len + columns - 1) / columns;
+        let col = (overview.ov_selected as i32 % columns + dx).clamp(0, columns - 1);
+        let row = (overview.ov_selected as i32 / columns + dy).clamp(0, rows - 1);
+
+        overview.ov_selected = (row * columns + col).min(len - 1) as usize;
+        self.mark_changed();
+    }
+
+    /// Close the overview and focus the selected window
+    pub fn overview_finish(&mut self) {
+        if let Some(overview) = self.a_overview.take() {
+            self.mark_changed();
+            let id = overview.ov_windows[overview.ov_selected].clone();
+            self.focus_on(Some(id));
+            self.recalculate_pointer_focus();
+        }
+    }
+
+    /// Close the overview without changing the focus
+    pub fn overview_cancel(&mut self) {
+        if self.a_overview.take().is_some() {
+            self.mark_changed();
+            self.recalculate_pointer_focus();
+        }
+    }
+
+    pub fn is_overview_open(&self) -> bool {
+        self.a_overview.is_some()
+    }
+
+    /// Get the open overview, for vkcomp to draw
+    pub fn get_overview(&self) -> Option<&Overview> {
+        self.a_overview.as_ref()
+    }
+}
@@ -0,0 +1,80 @@
+// GPU device loss recovery
+//
+// If the GPU is reset Dakota moves our scene to a new device, and every
+// image on the old one is gone. Client dmabufs can be imported again
+// since they live outside of the GPU context, but shm contents were
+// released after we copied them, so those clients need to draw again.
+//
+// Austin Shafer - 2024
+
+use super::*;
+use crate::category5::ways::role::Role;
+use utils::log;
+
+impl Atmosphere {
+    /// Import client dmabufs again after the GPU was lost
+    ///
+    /// The dmabuf last committed to each surface is imported on the new
+    /// device. Returns the number of surfaces restored.
+    pub fn reimport_dmabuf_resources(&mut self, scene: &mut dak::Scene) -> usize {
+        let resources: Vec<BufferId> = self
+            .a_surf_resource
+            .iter()
+            .flatten()
+            .map(|res| res.clone())
+            .collect();
+
+        let mut count = 0;
+        for res in resources.iter() {
+            let buffer = match self.a_dmabuf_buffer.get_clone(res) {
+                Some(buffer) => buffer,
+                None => continue,
+            };
+            // The client may have destroyed this buffer
+            let dmabuf = match buffer.data::<dak::Dmabuf>() {
+                Some(dmabuf) => dmabuf,
+                None => continue,
+            };
+
+            match self.create_dmabuf_resource(scene, res, buffer.clone(), dmabuf) {
+                Ok(()) => count += 1,
+                Err(e) => log::error!("Could not import dmabuf again: {:?}", e),
+            }
+        }
+
+        self.mark_changed();
+        count
+    }
+
+    /// Ask every window to draw itself again
+    ///
+    /// This sends a configure event to all xdg toplevels, which will make
+    /// clients commit new contents.
+    pub fn configure_all_windows(&mut self) {
+        let windows: Vec<SurfaceId> = (0..NUM_WORKSPACES)
+            .flat_map(|ws| self.windows_on_workspace(ws))
+            .collect();
+
+        for id in windows.iter() {
+            self.configure_window(id);
+        }
+    }
+
+    /// Send a configure event to an xdg toplevel
+    ///
+    /// Does nothing if `id` is not an xdg toplevel.
+    pub fn configure_window(&mut self, id: &SurfaceId) {
+        let surf_cell = match self.get_surface_from_id(id) {
+            Some(s) => s,
+            None => return,
+        };
+        let mut surf = surf_cell.lock().unwrap();
+        let (xdg_surf, ss) = match &surf.s_role {
+            Some(Role::xdg_shell_toplevel(xs, ss)) => (xs.clone(), ss.clone()),
+            _ => return,
+        };
+        ss.lock()
+            .unwrap()
+            .configure(self, xdg_surf, &mut surf, false);
+    }
+}
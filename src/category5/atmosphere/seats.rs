@@ -0,0 +1,173 @@
+// Multi-seat state
+//
+// Each seat is a group of input devices with its own cursor and focus,
+// so that several people can use the desktop at once. The state of the
+// seat whose input is being handled lives in the normal atmosphere
+// fields, which keeps the rest of the code from having to know about
+// seats. The other seats have their state stashed here, and are swapped
+// in with `make_seat_active` before their input is handled.
+//
+// Seat 0 is the default seat. It is the one active while input isn't
+// being handled, and the only one whose cursor clients may change.
+//
+// Austin Shafer - 2024
+
+use super::*;
+
+/// The state that is separate for each seat
+pub struct SeatState {
+    pub ss_cursor_pos: (f64, f64),
+    pub ss_grabbed: Option<SurfaceId>,
+    pub ss_resizing: Option<SurfaceId>,
+    pub ss_surf_focus: Option<SurfaceId>,
+    pub ss_pointer_focus: Option<SurfaceId>,
+    pub ss_grab_pos: Option<(SurfaceId, (f32, f32))>,
+    pub ss_grab_snapped: bool,
+}
+
+impl SeatState {
+    fn new(cursor_pos: (f64, f64)) -> Self {
+        Self {
+            ss_cursor_pos: cursor_pos,
+            ss_grabbed: None,
+            ss_resizing: None,
+            ss_surf_focus: None,
+            ss_pointer_focus: None,
+            ss_grab_pos: None,
+            ss_grab_snapped: false,
+        }
+    }
+
+    /// Drop any references to a surface that is going away
+    fn remove_surface(&mut self, id: &SurfaceId) {
+        for surf in [
+            &mut self.ss_grabbed,
+            &mut self.ss_resizing,
+            &mut self.ss_surf_focus,
+            &mut self.ss_pointer_focus,
+        ] {
+            if surf.as_ref() == Some(id) {
+                *surf = None;
+            }
+        }
+        if self.ss_grab_pos.as_ref().map(|(s, _)| s) == Some(id) {
+            self.ss_grab_pos = None;
+        }
+    }
+}
+
+/// A seat and the state it has while it isn't active
+pub struct SeatInfo {
+    /// The name of this seat, as given to libinput
+    pub si_name: String,
+    /// This is None while the seat is active, since its state is in
+    /// the atmosphere fields
+    si_state: Option<SeatState>,
+}
+
+impl SeatInfo {
+    /// The default seat, which starts out active
+    pub(super) fn default_seat() -> Self {
+        Self {
+            si_name: dak::DEFAULT_SEAT.to_string(),
+            si_state: None,
+        }
+    }
+}
+
+impl Atmosphere {
+    /// Swap the state of the active seat with `state`
+    fn swap_seat_state(&mut self, state: &mut SeatState) {
+        std::mem::swap(&mut self.a_cursor_pos, &mut state.ss_cursor_pos);
+        std::mem::swap(&mut self.a_grabbed, &mut state.ss_grabbed);
+        std::mem::swap(&mut self.a_resizing, &mut state.ss_resizing);
+        std::mem::swap(&mut self.a_surf_focus, &mut state.ss_surf_focus);
+        std::mem::swap(&mut self.a_pointer_focus, &mut state.ss_pointer_focus);
+        std::mem::swap(&mut self.a_grab_pos, &mut state.ss_grab_pos);
+        std::mem::swap(&mut self.a_grab_snapped, &mut state.ss_grab_snapped);
+    }
+
+    /// Get the names of all seats, indexed by seat number
+    pub fn get_seat_names(&self) -> Vec<String> {
+        self.a_seats.iter().map(|s| s.si_name.clone()).collect()
+    }
+
+    /// Get the number of a seat from its name
+    pub fn get_seat_index(&self, name: &str) -> Option<usize> {
+        self.a_seats.iter().position(|s| s.si_name == name)
+    }
+
+    /// Add a seat, returning its number
+    ///
+    /// If a seat with this name already exists its number is returned.
+    /// New seats start with their cursor in the middle of the screen.
+    pub fn add_seat_state(&mut self, name: &str) -> usize {
+        if let Some(index) = self.get_seat_index(name) {
+            return index;
+        }
+        log::debug!("Adding seat {}", name);
+
+        let res = self.get_resolution();
+        self.a_seats.push(SeatInfo {
+            si_name: name.to_string(),
+            si_state: Some(SeatState::new((res.0 as f64 / 2.0, res.1 as f64 / 2.0))),
+        });
+        self.mark_changed();
+        self.a_seats.len() - 1
+    }
+
+    /// Get the number of the seat whose state is in use
+    pub fn get_active_seat(&self) -> usize {
+        self.a_active_seat
+    }
+
+    /// Switch the focus and cursor state over to another seat
+    ///
+    /// Input handling does this before handling the events of a seat,
+    /// and switches back to the default seat afterwards.
+    pub fn make_seat_active(&mut self, seat: usize) {
+        if seat == self.a_active_seat || seat >= self.a_seats.len() {
+            return;
+        }
+
+        let mut old = self.a_seats[seat].si_state.take().unwrap();
+        self.swap_seat_state(&mut old);
+        self.a_seats[self.a_active_seat].si_state = Some(old);
+        self.a_active_seat = seat;
+        self.mark_changed();
+    }
+
+    /// Get the cursor positions of the seats that aren't active
+    ///
+    /// vkcomp uses these to draw a cursor for each extra seat.
+    pub fn get_inactive_seat_cursors(&self) -> Vec<(f64, f64)> {
+        self.a_seats
+            .iter()
+            .filter_map(|s| s.si_state.as_ref())
+            .map(|s| s.ss_cursor_pos)
+            .collect()
+    }
+
+    /// Remove a surface from the focus of the seats that aren't active
+    ///
+    /// The active seat is handled by the skiplist code.
+    pub(super) fn seats_remove_surface(&mut self, id: &SurfaceId) {
+        for seat in self.a_seats.iter_mut() {
+            if let Some(state) = seat.si_state.as_mut() {
+                state.remove_surface(id);
+            }
+        }
+    }
+
+    /// Get the root window that has the keyboard focus of the active seat
+    ///
+    /// This is different from `win_focus`, which is the top of the
+    /// window stack and is shared by all seats.
+    pub fn get_keyboard_focus(&self) -> Option<SurfaceId> {
+        let surf = self.get_surf_focus()?;
+        match self.a_root_window.get_clone(&surf) {
+            Some(root) => Some(root),
+            None => Some(surf),
+        }
+    }
+}
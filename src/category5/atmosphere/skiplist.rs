@@ -75,8 +75,8 @@ impl Atmosphere {
             if id == &focus {
                 let next_root = self.get_win_focus();
                 if self.a_root_window.get(id).is_some() {
-                    let next = match next_root {
-                        Some(nr) => self.a_top_child.get_clone(&nr),
+                    let next = match next_root.as_ref() {
+                        Some(nr) => self.a_top_child.get_clone(nr).or(next_root),
                         None => None,
                     };
                     self.set_surf_focus(next);
@@ -135,8 +135,18 @@ impl Atmosphere {
     /// This is better for subsystems like input which need to
     /// find the seat of the client currently in use.
     pub fn get_client_in_focus(&self) -> Option<ClientId> {
+        // Only the screen locker gets keys while locked
+        if self.get_locked() {
+            return self
+                .get_lock_focus()
+                .and_then(|id| self.a_owner.get_clone(&id));
+        }
         // get the surface in focus
-        if let Some(win) = self.get_win_focus() {
+        if let Some(win) = self.get_keyboard_focus() {
+            // Windows on other workspaces can't have keyboard focus
+            if !self.window_is_on_current_workspace(&win) {
+                return None;
+            }
             // now get the client for that surface
             return self.a_owner.get_clone(&win);
         }
@@ -148,12 +158,15 @@ impl Atmosphere {
     /// A root window is the base of a subsurface tree. i.e. the toplevel surf
     /// that all subsurfaces are attached to.
     pub fn get_root_win_in_focus(&self) -> Option<SurfaceId> {
-        if let Some(win) = self.get_win_focus() {
-            return match self.a_root_window.get_clone(&win) {
-                Some(root) => Some(root),
-                // If win doesn't have a root window, it is the root window
-                None => Some(win),
-            };
+        // Windows can't be acted on while locked
+        if self.get_locked() {
+            return None;
+        }
+        if let Some(win) = self.get_keyboard_focus() {
+            if !self.window_is_on_current_workspace(&win) {
+                return None;
+            }
+            return Some(win);
         }
         return None;
     }
@@ -161,34 +174,46 @@ impl Atmosphere {
     /// Set the window currently in focus
     pub fn focus_on(&mut self, win: Option<SurfaceId>) {
         log::debug!("focusing on window {:?}", win);
+        // The screen locker keeps the focus while locked
+        if self.get_locked() {
+            return;
+        }
 
         if let Some(id) = win.as_ref() {
             // check if a new app was selected
             let root = self.a_root_window.get_clone(id);
+            // Check if we need to change focus. We either compare with this
+            // window or the root app window, if we have one.
+            let cur = match root.as_ref() {
+                Some(r) => r,
+                None => id,
+            };
+            // Focusing a minimized window brings it back
+            self.unminimize_window(cur);
+
+            // Each seat has its own keyboard focus
+            if let Some(prev) = self.get_keyboard_focus() {
+                if cur == &prev {
+                    // This window is already in focus, so bail
+                    return;
+                }
+                // Send leave event(s) to the old focus
+                Input::keyboard_leave(self, &prev);
+            }
+
+            // The window stack is shared between seats, so the window may
+            // already be on top if another seat is using it
             let prev_win_focus = self.get_win_focus();
             if let Some(prev) = prev_win_focus.as_ref() {
-                // Check if we need to change focus. We either compare with this
-                // window or the root app window, if we have one.
-                let cur = match root.as_ref() {
-                    Some(r) => r,
-                    None => id,
-                };
-
                 if cur != prev {
                     // point the previous focus at the new focus
                     self.a_skiplist_prev.set(&prev, id.clone());
-
-                    // Send leave event(s) to the old focus
-                    Input::keyboard_leave(self, &prev);
-                } else {
-                    // Otherwise this window is already in focus, so bail
-                    return;
                 }
             }
 
             // If no root window is attached, then win is a root window and
             // we need to update the win focus
-            if root.is_none() {
+            if root.is_none() && prev_win_focus.as_ref() != Some(id) {
                 self.skiplist_remove_window(id);
                 self.a_skiplist_next.set_opt(id, prev_win_focus);
                 self.a_skiplist_prev.set_opt(id, None);
@@ -206,6 +231,7 @@ impl Atmosphere {
             // wl_subsurface changes the order
             // set win to the surf focus
             self.set_surf_focus(Some(id.clone()));
+            self.push_focus_history(cur);
             // Send enter event(s) to the new focus
             // spec says this MUST be done after the leave events are sent
             Input::keyboard_enter(self, id);
@@ -261,8 +287,27 @@ impl Atmosphere {
     /// In the case of delivering input enter/leave events, we don't just check
     /// which window contains the point, we need to check if windows with an
     /// input region contain the point.
+    ///
+    /// Points over server side decorations do not belong to the client, so
+    /// None is returned for them.
     pub fn find_window_with_input_at_point(&self, x: f32, y: f32) -> Option<SurfaceId> {
-        log::debug!("find_window_with_input_at_point {},{}", x, y);
+        match self.find_window_or_decoration_at_point(x, y) {
+            Some((id, false)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Find the top window whose input region or decorations contain (x, y)
+    ///
+    /// The returned boolean is true if the point is over the window's
+    /// server side decorations instead of its surface.
+    pub fn find_window_or_decoration_at_point(&self, x: f32, y: f32) -> Option<(SurfaceId, bool)> {
+        log::debug!("find_window_or_decoration_at_point {},{}", x, y);
+        // While locked all input goes to the screen locker, which covers
+        // the whole screen
+        if self.get_locked() {
+            return self.get_lock_focus().map(|id| (id, false));
+        }
         let mut ret = None;
         // Adjust for offsetting into the desktop
         let adjusted = self.get_adjusted_desktop_coord(x, y);
@@ -298,7 +343,7 @@ impl Atmosphere {
                 // by the position of the window, instead of scaling
                 // every Rect in the Region up by that amount
                 if input_region.lock().unwrap().intersects(x as i32, y as i32) {
-                    ret = Some(win);
+                    ret = Some((win, false));
                     return false;
                 }
             } else {
@@ -306,44 +351,37 @@ impl Atmosphere {
                 // then we need to check against the entire surface area.
                 let (ww, wh) = *self.a_surface_size.get(&win).unwrap();
                 if x > 0.0 && y > 0.0 && x < ww && y < wh {
-                    ret = Some(win);
+                    ret = Some((win, false));
                     return false;
                 }
             }
+
+            // Only toplevels have decorations, and they are checked after
+            // their subsurfaces
+            if self.point_is_on_decoration(&win, adjusted.0, adjusted.1) {
+                ret = Some((win, true));
+                return false;
+            }
             return true;
         });
 
-        log::debug!("Found window {:?}", ret.as_ref().map(|id| id.get_raw_id()));
+        log::debug!(
+            "Found window {:?}",
+            ret.as_ref().map(|(id, deco)| (id.get_raw_id(), deco))
+        );
         return ret;
     }
 
-    /// Is the current point over the titlebar of the window
-    ///
-    /// Id should have first been found with find_window_at_point
-    pub fn point_is_on_titlebar(&self, id: &SurfaceId, x: f32, y: f32) -> bool {
-        let barsize = self.get_barsize();
-        let (wx, wy) = *self.a_surface_pos.get(id).unwrap();
-        let (ww, _wh) = *self.a_surface_size.get(id).unwrap();
-
-        // If this window contains (x, y) then return it
-        if x > wx && y > (wy - barsize) && x < (wx + ww) && y < wy {
-            return true;
-        }
-        return false;
-    }
-
     /// calculates if a position is over the part of a window that
     /// procs a resize
     pub fn point_is_on_window_edge(&self, id: &SurfaceId, x: f32, y: f32) -> ResizeEdge {
-        let barsize = self.get_barsize();
-        // TODO: how should this be done with xdg-decoration?
         let (wx, wy) = *self.a_surface_pos.get(id).unwrap();
         let (ww, wh) = *self.a_surface_size.get(id).unwrap();
         let prox = 3.0; // TODO find a better val for this??
 
         // is (x,y) inside each dimension of the window
         let x_contained = x > wx && x < wx + ww;
-        let y_contained = y > wy && y < wy + barsize + wh;
+        let y_contained = y > wy && y < wy + wh;
 
         // closures for helping us with overlap calculations
         // v is val to check, a is axis location
@@ -480,11 +518,25 @@ impl Atmosphere {
 impl<'a> Atmosphere {
     /// return an iterator of valid ids.
     ///
-    /// This will be all ids that are have been `activate`d
+    /// This will be all ids that are have been `activate`d, except for
+    /// minimized windows
     pub fn visible_windows(&'a self) -> VisibleWindowIterator<'a> {
         self.into_iter()
     }
 
+    /// return an iterator over the windows assigned to a workspace
+    ///
+    /// This walks the same ordering as `visible_windows`, front to back,
+    /// but for any workspace. Minimized windows are included.
+    pub fn windows_on_workspace(&'a self, workspace: u32) -> VisibleWindowIterator<'a> {
+        VisibleWindowIterator {
+            vwi_atmos: &self,
+            vwi_cur: self.get_win_focus(),
+            vwi_workspace: Some(workspace),
+            vwi_skip_minimized: false,
+        }
+    }
+
     /// return an iterator over the subsurfaces of id
     ///
     /// This will be all ids that are have been `activate`d
@@ -492,6 +544,8 @@ impl<'a> Atmosphere {
         VisibleWindowIterator {
             vwi_atmos: &self,
             vwi_cur: self.a_top_child.get_clone(id),
+            vwi_workspace: None,
+            vwi_skip_minimized: false,
         }
     }
 }
@@ -501,6 +555,10 @@ pub struct VisibleWindowIterator<'a> {
     vwi_atmos: &'a Atmosphere,
     // the current window we are on
     vwi_cur: Option<SurfaceId>,
+    // only return windows on this workspace
+    vwi_workspace: Option<u32>,
+    // don't return minimized windows
+    vwi_skip_minimized: bool,
 }
 
 // Non-consuming iterator over an Atmosphere
@@ -515,6 +573,8 @@ impl<'a> IntoIterator for &'a Atmosphere {
         VisibleWindowIterator {
             vwi_atmos: &self,
             vwi_cur: self.get_win_focus(),
+            vwi_workspace: Some(self.get_current_workspace()),
+            vwi_skip_minimized: true,
         }
     }
 }
@@ -524,12 +584,25 @@ impl<'a> Iterator for VisibleWindowIterator<'a> {
     type Item = SurfaceId;
 
     fn next(&mut self) -> Option<SurfaceId> {
-        let ret = self.vwi_cur.take();
-        // TODO: actually skip
-        if let Some(id) = ret.as_ref() {
-            self.vwi_cur = self.vwi_atmos.a_skiplist_next.get_clone(id);
-        }
+        loop {
+            let ret = self.vwi_cur.take();
+            if let Some(id) = ret.as_ref() {
+                self.vwi_cur = self.vwi_atmos.a_skiplist_next.get_clone(id);
+
+                // Skip over windows that are on other workspaces
+                if let Some(workspace) = self.vwi_workspace {
+                    if let Some(ws) = self.vwi_atmos.a_workspace.get_clone(id) {
+                        if ws != workspace {
+                            continue;
+                        }
+                    }
+                }
+                if self.vwi_skip_minimized && self.vwi_atmos.window_is_minimized(id) {
+                    continue;
+                }
+            }
 
-        return ret;
+            return ret;
+        }
     }
 }
@@ -0,0 +1,133 @@
+// Edge snapping for interactive moves and resizes
+//
+// While the user drags a window we track where it would be if it
+// followed the pointer exactly. When one of its edges comes within
+// SNAP_DISTANCE of the screen or another window it sticks there, and
+// the pointer has to pull it past that distance to break free.
+//
+// Austin Shafer - 2024
+
+use super::*;
+
+/// Distance in pixels at which edges snap together
+static SNAP_DISTANCE: f32 = 16.0;
+
+/// The position and size of a grabbed window, and if it is snapped
+pub type GrabFeedback = ((f32, f32), (f32, f32), bool);
+
+/// Find the snap target closest to any of the given edges
+///
+/// `edges` are positions of the window's edges along one axis and
+/// `targets` are the positions they may snap to. Returns the offset to
+/// add to the window to line up with the target.
+fn snap_axis(edges: &[f32], targets: &[f32]) -> Option<f32> {
+    let mut best: Option<f32> = None;
+    for target in targets.iter() {
+        for edge in edges.iter() {
+            let diff = target - edge;
+            if diff.abs() <= SNAP_DISTANCE && best.map(|b| diff.abs() < b.abs()).unwrap_or(true) {
+                best = Some(diff);
+            }
+        }
+    }
+    best
+}
+
+impl Atmosphere {
+    /// Get the edges that a window may snap to
+    ///
+    /// This returns the x positions of vertical edges and the y positions
+    /// of horizontal edges, in desktop coordinates. These are the sides of
+    /// the desktop and of every other visible window.
+    fn get_snap_targets(&self, id: &SurfaceId) -> (Vec<f32>, Vec<f32>) {
+        let res = self.get_resolution();
+        let mut xs = vec![0.0, res.0 as f32];
+        let mut ys = vec![0.0, (res.1 as i32 - wm::DESKTOP_OFFSET) as f32];
+
+        for win in self.visible_windows() {
+            if &win == id || !self.a_toplevel.get_clone(&win).unwrap_or(false) {
+                continue;
+            }
+            let pos = match self.a_window_pos.get(&win) {
+                Some(pos) => *pos,
+                None => continue,
+            };
+            let size = match self.a_window_size.get(&win) {
+                Some(size) => *size,
+                None => continue,
+            };
+            xs.push(pos.0);
+            xs.push(pos.0 + size.0);
+            ys.push(pos.1);
+            ys.push(pos.1 + size.1);
+        }
+
+        (xs, ys)
+    }
+
+    /// Move the grabbed window along with the pointer
+    ///
+    /// The window is snapped to any nearby edges. The unsnapped position
+    /// is kept for the duration of the grab so that the window can be
+    /// pulled away again.
+    pub(super) fn move_grabbed_window(&mut self, grabbed: &SurfaceId, dx: f32, dy: f32) {
+        let wp = *self.a_window_pos.get(grabbed).unwrap();
+
+        // Start tracking from the current position if this is a new grab
+        let mut pos = match self.a_grab_pos.as_ref() {
+            Some((id, pos)) if id == grabbed => *pos,
+            _ => {
+                self.a_grab_snapped = false;
+                wp
+            }
+        };
+        pos.0 += dx;
+        pos.1 += dy;
+        self.a_grab_pos = Some((grabbed.clone(), pos));
+
+        let size = self
+            .a_window_size
+            .get(grabbed)
+            .map(|s| *s)
+            .unwrap_or((0.0, 0.0));
+        let (xs, ys) = self.get_snap_targets(grabbed);
+        let snap_x = snap_axis(&[pos.0, pos.0 + size.0], &xs);
+        let snap_y = snap_axis(&[pos.1, pos.1 + size.1], &ys);
+        self.a_grab_snapped = snap_x.is_some() || snap_y.is_some();
+
+        let new_pos = (pos.0 + snap_x.unwrap_or(0.0), pos.1 + snap_y.unwrap_or(0.0));
+        let delta = (new_pos.0 - wp.0, new_pos.1 - wp.1);
+
+        // Need to update both the surface and window positions
+        self.a_window_pos.set(grabbed, new_pos);
+        let mut sp = *self.a_surface_pos.get(grabbed).unwrap();
+        sp.0 += delta.0;
+        sp.1 += delta.1;
+        self.a_surface_pos.set(grabbed, sp);
+    }
+
+    /// Snap the pointer position used for an interactive resize
+    ///
+    /// The edge being dragged will stick to the desktop edges and the
+    /// edges of other windows.
+    pub fn snap_resize_point(&mut self, id: &SurfaceId, x: f32, y: f32) -> (f32, f32) {
+        let (xs, ys) = self.get_snap_targets(id);
+        let snap_x = snap_axis(&[x], &xs);
+        let snap_y = snap_axis(&[y], &ys);
+        self.a_grab_snapped = snap_x.is_some() || snap_y.is_some();
+
+        (x + snap_x.unwrap_or(0.0), y + snap_y.unwrap_or(0.0))
+    }
+
+    /// Get the outline to draw around a window being moved or resized
+    ///
+    /// Returns the window position and size, and if it is currently snapped
+    /// to an edge. This is None if there is no interactive grab.
+    pub fn get_grab_feedback(&self) -> Option<GrabFeedback> {
+        let id = self.get_grabbed().or(self.get_resizing())?;
+        let pos = *self.a_window_pos.get(&id)?;
+        let size = *self.a_window_size.get(&id)?;
+
+        Some((pos, size, self.a_grab_snapped))
+    }
+}
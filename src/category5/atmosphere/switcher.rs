@@ -0,0 +1,146 @@
+// Alt-tab window switcher
+//
+// While the switcher is open it holds a list of the windows on the
+// current workspace and which one is selected. Input steps through the
+// list while the keybinding's modifier is held, and focuses the
+// selected window when it is released. vkcomp draws the list with
+// thumbnails of each window.
+//
+// Windows are listed either by how recently they were focused, or in
+// their stacking order. The focus history is kept here, since windows
+// can be raised without being focused.
+//
+// Austin Shafer - 2024
+
+use super::*;
+
+/// The order windows are listed in the switcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitcherOrder {
+    /// Most recently focused first
+    mru,
+    /// Front to back
+    stacking,
+}
+
+impl SwitcherOrder {
+    /// Parse an order name, as used in the config file
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "mru" => Some(Self::mru),
+            "stacking" => Some(Self::stacking),
+            _ => None,
+        }
+    }
+}
+
+/// An open switcher
+#[derive(Debug, Clone)]
+pub struct WindowSwitcher {
+    /// The windows to choose from, in the order they are shown
+    pub sw_windows: Vec<SurfaceId>,
+    /// The index of the selected window
+    pub sw_selected: usize,
+}
+
+impl Atmosphere {
+    /// Record that a root window gained the keyboard focus
+    pub(super) fn push_focus_history(&mut self, id: &SurfaceId) {
+        self.a_focus_history.retain(|w| w != id);
+        self.a_focus_history.insert(0, id.clone());
+    }
+
+    /// Remove a window that is going away from the switcher
+    pub(super) fn switcher_remove_window(&mut self, id: &SurfaceId) {
+        self.a_focus_history.retain(|w| w != id);
+
+        let switcher = match self.a_switcher.as_mut() {
+            Some(switcher) => switcher,
+            None => return,
+        };
+        if let Some(index) = switcher.sw_windows.iter().position(|w| w == id) {
+            switcher.sw_windows.remove(index);
+            if index < switcher.sw_selected || switcher.sw_selected >= switcher.sw_windows.len() {
+                switcher.sw_selected = switcher.sw_selected.saturating_sub(1);
+            }
+            if switcher.sw_windows.is_empty() {
+                self.a_switcher = None;
+            }
+            self.mark_changed();
+        }
+    }
+
+    /// Get the windows on the current workspace in switcher order
+    ///
+    /// Minimized windows are included so that they can be brought back.
+    fn get_switcher_windows(&self, order: SwitcherOrder) -> Vec<SurfaceId> {
+        let mut windows: Vec<SurfaceId> = self
+            .windows_on_workspace(self.get_current_workspace())
+            .collect();
+        if order == SwitcherOrder::mru {
+            // Windows that were never focused go last, in stacking order
+            windows.sort_by_key(|id| {
+                self.a_focus_history
+                    .iter()
+                    .position(|w| w == id)
+                    .unwrap_or(usize::MAX)
+            });
+        }
+        windows
+    }
+
+    /// Open the switcher, or select the next window if it is open
+    ///
+    /// The window after the one in focus is selected first, so that a
+    /// single press and release switches between the last two windows.
+    /// If `backwards` is true the previous window is selected instead.
+    pub fn switcher_step(&mut self, order: SwitcherOrder, backwards: bool) {
+        if self.get_locked() || self.is_overview_open() {
+            return;
+        }
+
+        if self.a_switcher.is_none() {
+            let windows = self.get_switcher_windows(order);
+            if windows.is_empty() {
+                return;
+            }
+            self.a_switcher = Some(WindowSwitcher {
+                sw_windows: windows,
+                sw_selected: 0,
+            });
+        }
+
+        let switcher = self.a_switcher.as_mut().unwrap();
+        let len = switcher.sw_windows.len();
+        switcher.sw_selected = match backwards {
+            true => (switcher.sw_selected + len - 1) % len,
+            false => (switcher.sw_selected + 1) % len,
+        };
+        self.mark_changed();
+    }
+
+    /// Close the switcher and focus the selected window
+    pub fn switcher_finish(&mut self) {
+        if let Some(switcher) = self.a_switcher.take() {
+            self.mark_changed();
+            let id = switcher.sw_windows[switcher.sw_selected].clone();
+            self.focus_on(Some(id));
+        }
+    }
+
+    /// Close the switcher without changing the focus
+    pub fn switcher_cancel(&mut self) {
+        if self.a_switcher.take().is_some() {
+            self.mark_changed();
+        }
+    }
+
+    pub fn is_switcher_open(&self) -> bool {
+        self.a_switcher.is_some()
+    }
+
+    /// Get the open switcher, for vkcomp to draw
+    pub fn get_switcher(&self) -> Option<&WindowSwitcher> {
+        self.a_switcher.as_ref()
+    }
+}
@@ -0,0 +1,381 @@
+// Tiling window layouts
+//
+// Every workspace has a layout policy. Floating workspaces leave window
+// placement up to the user, while tiled workspaces arrange all of their
+// toplevels to fill the desktop. Tiles are recalculated lazily once per
+// event loop iteration, after ways and input have made their changes.
+//
+// Austin Shafer - 2024
+#![allow(non_camel_case_types)]
+
+use super::*;
+use crate::category5::ways::role::Role;
+
+/// Gap in pixels left around each tile
+static TILE_GAP: f32 = 4.0;
+/// The amount to change the master ratio by when growing or shrinking
+static RATIO_STEP: f32 = 0.05;
+static MIN_RATIO: f32 = 0.1;
+static MAX_RATIO: f32 = 0.9;
+
+/// How windows on a workspace are arranged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutMode {
+    /// Windows are placed and sized by the user
+    floating,
+    /// Each new tile splits the previous one in half, alternating
+    /// along the longer side
+    binary_split,
+    /// One large master tile with the rest stacked beside it
+    master_stack,
+}
+
+impl LayoutMode {
+    /// Parse a layout name, as used in the keybindings file
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "floating" => Some(Self::floating),
+            "binary_split" => Some(Self::binary_split),
+            "master_stack" => Some(Self::master_stack),
+            _ => None,
+        }
+    }
+
+    /// The layout that follows this one when cycling through modes
+    pub fn next(&self) -> Self {
+        match self {
+            Self::floating => Self::binary_split,
+            Self::binary_split => Self::master_stack,
+            Self::master_stack => Self::floating,
+        }
+    }
+}
+
+/// The layout policy of a single workspace
+#[derive(Debug, Clone)]
+pub struct WorkspaceLayout {
+    pub wl_mode: LayoutMode,
+    /// The fraction of the desktop given to the first tile
+    pub wl_ratio: f32,
+    /// Tiled windows in layout order. The first is the master tile.
+    wl_tiles: Vec<SurfaceId>,
+}
+
+impl WorkspaceLayout {
+    pub fn new() -> Self {
+        Self {
+            wl_mode: LayoutMode::floating,
+            wl_ratio: 0.5,
+            wl_tiles: Vec::new(),
+        }
+    }
+}
+
+/// A rectangle in desktop coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub t_pos: (f32, f32),
+    pub t_size: (f32, f32),
+}
+
+impl Tile {
+    /// Shrink this tile by the gap on every side
+    fn with_gap(&self) -> Self {
+        Self {
+            t_pos: (self.t_pos.0 + TILE_GAP, self.t_pos.1 + TILE_GAP),
+            t_size: (
+                (self.t_size.0 - TILE_GAP * 2.0).max(1.0),
+                (self.t_size.1 - TILE_GAP * 2.0).max(1.0),
+            ),
+        }
+    }
+
+    /// Split this tile in two along its longer side
+    ///
+    /// `ratio` is the fraction of the space given to the first half.
+    fn split(&self, ratio: f32) -> (Self, Self) {
+        if self.t_size.0 >= self.t_size.1 {
+            let w = (self.t_size.0 * ratio).floor();
+            (
+                Self {
+                    t_pos: self.t_pos,
+                    t_size: (w, self.t_size.1),
+                },
+                Self {
+                    t_pos: (self.t_pos.0 + w, self.t_pos.1),
+                    t_size: (self.t_size.0 - w, self.t_size.1),
+                },
+            )
+        } else {
+            let h = (self.t_size.1 * ratio).floor();
+            (
+                Self {
+                    t_pos: self.t_pos,
+                    t_size: (self.t_size.0, h),
+                },
+                Self {
+                    t_pos: (self.t_pos.0, self.t_pos.1 + h),
+                    t_size: (self.t_size.0, self.t_size.1 - h),
+                },
+            )
+        }
+    }
+}
+
+/// Calculate the tiles for a binary split layout
+///
+/// The first split uses `ratio`, after which every remaining area is
+/// halved for the next window.
+pub fn binary_split_tiles(count: usize, area: Tile, ratio: f32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    let mut remaining = area;
+    for i in 0..count {
+        if i == count - 1 {
+            tiles.push(remaining.with_gap());
+            break;
+        }
+
+        let (tile, rest) = remaining.split(if i == 0 { ratio } else { 0.5 });
+        tiles.push(tile.with_gap());
+        remaining = rest;
+    }
+    tiles
+}
+
+/// Calculate the tiles for a master/stack layout
+///
+/// The first window takes `ratio` of the desktop width, and all other
+/// windows are stacked vertically in the remaining column.
+pub fn master_stack_tiles(count: usize, area: Tile, ratio: f32) -> Vec<Tile> {
+    if count == 0 {
+        return Vec::new();
+    }
+    if count == 1 {
+        return vec![area.with_gap()];
+    }
+
+    let master_width = (area.t_size.0 * ratio).floor();
+    let mut tiles = vec![Tile {
+        t_pos: area.t_pos,
+        t_size: (master_width, area.t_size.1),
+    }
+    .with_gap()];
+
+    let stack_count = count - 1;
+    let stack_height = (area.t_size.1 / stack_count as f32).floor();
+    for i in 0..stack_count {
+        let y = area.t_pos.1 + stack_height * i as f32;
+        // The last tile takes up any space left over by rounding
+        let height = match i == stack_count - 1 {
+            true => area.t_pos.1 + area.t_size.1 - y,
+            false => stack_height,
+        };
+        tiles.push(
+            Tile {
+                t_pos: (area.t_pos.0 + master_width, y),
+                t_size: (area.t_size.0 - master_width, height),
+            }
+            .with_gap(),
+        );
+    }
+    tiles
+}
+
+impl Atmosphere {
+    /// Get the layout mode of a workspace
+    pub fn get_workspace_layout(&self, workspace: u32) -> LayoutMode {
+        self.a_workspace_layouts[workspace as usize].wl_mode
+    }
+
+    /// Change the layout mode of a workspace
+    pub fn set_workspace_layout(&mut self, workspace: u32, mode: LayoutMode) {
+        if workspace >= NUM_WORKSPACES {
+            return;
+        }
+        log::debug!("Setting layout of workspace {} to {:?}", workspace, mode);
+        self.a_workspace_layouts[workspace as usize].wl_mode = mode;
+        self.mark_tiling_dirty();
+    }
+
+    /// Switch the current workspace to the next layout mode
+    pub fn cycle_current_layout(&mut self) {
+        let workspace = self.get_current_workspace();
+        let mode = self.get_workspace_layout(workspace).next();
+        self.set_workspace_layout(workspace, mode);
+    }
+
+    /// Is this window being placed by a tiling layout?
+    ///
+    /// Tiled windows can't be moved or resized with the pointer.
+    pub fn window_is_tiled(&self, id: &SurfaceId) -> bool {
+        match self.get_window_workspace(id) {
+            Some(ws) => self.get_workspace_layout(ws) != LayoutMode::floating,
+            None => false,
+        }
+    }
+
+    /// Request that tiled workspaces be laid out again
+    ///
+    /// This should be called whenever windows are added to or removed
+    /// from a workspace.
+    pub fn mark_tiling_dirty(&mut self) {
+        self.a_tiling_dirty = true;
+    }
+
+    /// Grow or shrink the first tile of the current workspace
+    pub fn adjust_master_ratio(&mut self, grow: bool) {
+        let layout = &mut self.a_workspace_layouts[self.a_current_workspace as usize];
+        let step = if grow { RATIO_STEP } else { -RATIO_STEP };
+        layout.wl_ratio = (layout.wl_ratio + step).clamp(MIN_RATIO, MAX_RATIO);
+        self.mark_tiling_dirty();
+    }
+
+    /// Find the window next to `id` in the tiling order
+    ///
+    /// This wraps around at either end of the list.
+    fn neighboring_tile(&mut self, id: &SurfaceId, forward: bool) -> Option<(u32, usize, usize)> {
+        let workspace = self.get_window_workspace(id)?;
+        self.sync_tiles(workspace);
+        let tiles = &self.a_workspace_layouts[workspace as usize].wl_tiles;
+
+        let index = tiles.iter().position(|t| t == id)?;
+        let other = match forward {
+            true => (index + 1) % tiles.len(),
+            false => (index + tiles.len() - 1) % tiles.len(),
+        };
+        Some((workspace, index, other))
+    }
+
+    /// Move focus to the next or previous tile
+    pub fn focus_neighboring_tile(&mut self, id: &SurfaceId, forward: bool) {
+        if let Some((workspace, _, other)) = self.neighboring_tile(id, forward) {
+            let target = self.a_workspace_layouts[workspace as usize].wl_tiles[other].clone();
+            self.focus_on(Some(target));
+        }
+    }
+
+    /// Swap a window with the next or previous tile
+    pub fn swap_neighboring_tile(&mut self, id: &SurfaceId, forward: bool) {
+        if let Some((workspace, index, other)) = self.neighboring_tile(id, forward) {
+            self.a_workspace_layouts[workspace as usize]
+                .wl_tiles
+                .swap(index, other);
+            self.mark_tiling_dirty();
+        }
+    }
+
+    /// Update the tile order with the toplevels on this workspace
+    ///
+    /// Windows which have been closed or moved away are removed, and new
+    /// windows are added at the end of the list, oldest first. Minimized
+    /// and fullscreen windows are left out until they return.
+    fn sync_tiles(&mut self, workspace: u32) {
+        let windows: Vec<SurfaceId> = self
+            .windows_on_workspace(workspace)
+            .filter(|id| self.a_toplevel.get_clone(id).unwrap_or(false))
+            .filter(|id| !self.window_is_minimized(id) && !self.window_is_fullscreen(id))
+            .collect();
+
+        let tiles = &mut self.a_workspace_layouts[workspace as usize].wl_tiles;
+        tiles.retain(|t| windows.contains(t));
+        // windows is ordered front to back, so go in reverse to add
+        // the oldest windows first
+        for id in windows.iter().rev() {
+            if !tiles.contains(id) {
+                tiles.push(id.clone());
+            }
+        }
+    }
+
+    /// Get the area of the desktop that tiles are placed in
+    pub(super) fn get_tiling_area(&self) -> Tile {
+        let res = self.get_resolution();
+        Tile {
+            t_pos: (0.0, 0.0),
+            t_size: (
+                res.0 as f32,
+                (res.1 as i32 - wm::DESKTOP_OFFSET).max(0) as f32,
+            ),
+        }
+    }
+
+    /// Place a window in a tile
+    ///
+    /// The window geometry is moved to the tile position, and the client
+    /// is asked to resize itself to fill the tile. Passing None releases
+    /// the window back to floating.
+    fn place_in_tile(&mut self, id: &SurfaceId, tile: Option<Tile>) {
+        if let Some(tile) = tile.as_ref() {
+            // Keep the offset of the window geometry within the surface
+            let wp = *self.a_window_pos.get(id).unwrap();
+            let sp = *self.a_surface_pos.get(id).unwrap();
+            self.a_window_pos.set(id, tile.t_pos);
+            self.a_surface_pos.set(
+                id,
+                (tile.t_pos.0 - (wp.0 - sp.0), tile.t_pos.1 - (wp.1 - sp.1)),
+            );
+        }
+
+        let surf_cell = match self.get_surface_from_id(id) {
+            Some(s) => s,
+            None => return,
+        };
+        let mut surf = surf_cell.lock().unwrap();
+        let (xdg_surf, ss) = match &surf.s_role {
+            Some(Role::xdg_shell_toplevel(xs, ss)) => (xs.clone(), ss.clone()),
+            // wl_shell has no way to tell clients they are tiled
+            _ => return,
+        };
+        ss.lock().unwrap().configure_tile(
+            self,
+            xdg_surf,
+            &mut surf,
+            tile.map(|t| (t.t_size.0 as i32, t.t_size.1 as i32)),
+        );
+    }
+
+    /// Arrange the windows of one workspace according to its layout
+    fn retile_workspace(&mut self, workspace: u32) {
+        let layout = &self.a_workspace_layouts[workspace as usize];
+        let (mode, ratio) = (layout.wl_mode, layout.wl_ratio);
+
+        if mode == LayoutMode::floating {
+            // Let go of any windows we were tiling
+            let tiles = std::mem::take(&mut self.a_workspace_layouts[workspace as usize].wl_tiles);
+            for id in tiles.iter() {
+                self.place_in_tile(id, None);
+            }
+            return;
+        }
+
+        self.sync_tiles(workspace);
+        let ids = self.a_workspace_layouts[workspace as usize]
+            .wl_tiles
+            .clone();
+        let area = self.get_tiling_area();
+        let rects = match mode {
+            LayoutMode::binary_split => binary_split_tiles(ids.len(), area, ratio),
+            LayoutMode::master_stack => master_stack_tiles(ids.len(), area, ratio),
+            LayoutMode::floating => unreachable!(),
+        };
+
+        for (id, tile) in ids.iter().zip(rects) {
+            self.place_in_tile(id, Some(tile));
+        }
+    }
+
+    /// Lay out tiled workspaces if windows have changed
+    ///
+    /// This is called once per event loop iteration, when no surfaces
+    /// are locked.
+    pub fn retile_if_needed(&mut self) {
+        if !self.a_tiling_dirty {
+            return;
+        }
+        self.a_tiling_dirty = false;
+
+        for workspace in 0..NUM_WORKSPACES {
+            self.retile_workspace(workspace);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// Maximized, fullscreen and minimized windows
+//
+// Toplevels can ask to be maximized, made fullscreen or minimized. The
+// xdg_shell handlers keep track of the states each window is in and ask
+// the client to resize itself, while the windows are placed here:
+//
+// * Maximized windows fill the desktop below the menubar.
+// * Fullscreen windows cover the whole output, including the menubar.
+//   vkcomp draws a backdrop behind them so nothing else shows through,
+//   and windows that don't fill the output are centered in it.
+// * Minimized windows are hidden until they are focused again, for
+//   example from the window switcher or over IPC.
+//
+// A fullscreen window is only presented as covering the output once its
+// buffer matches the size it was configured to. Until then it is drawn
+// like any other window, with the backdrop as the border fill.
+//
+// Austin Shafer - 2024
+
+use super::*;
+use crate::category5::vkcomp::wm::task::Task;
+use crate::category5::ways::role::Role;
+use utils::log;
+
+impl Atmosphere {
+    /// Get the area covered by fullscreen windows, in desktop coordinates
+    ///
+    /// This is the whole output, so it starts above the desktop where
+    /// the menubar is.
+    pub fn get_fullscreen_area(&self) -> ((f32, f32), (f32, f32)) {
+        let res = self.get_resolution();
+        (
+            (0.0, -wm::DESKTOP_OFFSET as f32),
+            (res.0 as f32, res.1 as f32),
+        )
+    }
+
+    /// Get the area filled by maximized windows, in desktop coordinates
+    pub fn get_maximized_area(&self) -> ((f32, f32), (f32, f32)) {
+        let area = self.get_tiling_area();
+        (area.t_pos, area.t_size)
+    }
+
+    /// Move a window to a position on the desktop
+    ///
+    /// The offset of the window geometry within its surface is kept.
+    pub fn move_window_to(&mut self, id: &SurfaceId, pos: (f32, f32)) {
+        let wp = self.a_window_pos.get_clone(id).unwrap_or((0.0, 0.0));
+        if wp == pos {
+            return;
+        }
+        let sp = self.a_surface_pos.get_clone(id).unwrap_or(wp);
+        self.a_window_pos.set(id, pos);
+        self.a_surface_pos
+            .set(id, (sp.0 + pos.0 - wp.0, sp.1 + pos.1 - wp.1));
+        self.mark_changed();
+    }
+
+    /// Place a fullscreen window on the output
+    ///
+    /// Windows smaller than the output are centered in it, and larger
+    /// ones are placed in the top left corner.
+    pub fn place_fullscreen_window(&mut self, id: &SurfaceId) {
+        let ((x, y), (width, height)) = self.get_fullscreen_area();
+        let (_, (ww, wh)) = self.get_window_geometry(id);
+        let pos = (
+            x + ((width - ww) / 2.0).max(0.0).floor(),
+            y + ((height - wh) / 2.0).max(0.0).floor(),
+        );
+        self.move_window_to(id, pos);
+    }
+
+    /// Check if a window's buffer covers the output
+    ///
+    /// `configured` is the size the window was last configured to, or
+    /// None if it isn't fullscreen. The window covers the output if it
+    /// was configured to the output size, its buffer is exactly that size
+    /// without any scaling, and the window geometry is the whole buffer.
+    pub fn update_covers_output(&mut self, id: &SurfaceId, configured: Option<(i32, i32)>) {
+        let res = self.get_resolution();
+        let output = (res.0 as f32, res.1 as f32);
+
+        let covers = match configured {
+            Some(size) => {
+                size == (res.0 as i32, res.1 as i32)
+                    && self.a_buffer_size.get_clone(id) == Some(output)
+                    && self.a_surface_size.get_clone(id) == Some(output)
+                    && self.a_window_size.get_clone(id) == Some(output)
+                    && self.a_window_pos.get_clone(id) == self.a_surface_pos.get_clone(id)
+            }
+            None => false,
+        };
+
+        if covers != self.window_covers_output(id) {
+            log::debug!("Window {:?} covers the output: {}", id, covers);
+            self.a_covers_output.set(id, covers);
+        }
+    }
+
+    /// Is this a fullscreen window whose buffer covers the whole output?
+    pub fn window_covers_output(&self, id: &SurfaceId) -> bool {
+        self.a_covers_output.get_clone(id).unwrap_or(false)
+    }
+
+    /// Is the window this surface is part of maximized or fullscreen?
+    ///
+    /// Like tiled windows, these are placed for the user and can't be
+    /// moved or resized with the pointer.
+    pub fn window_is_maximized_or_fullscreen(&self, id: &SurfaceId) -> bool {
+        let root = self.a_root_window.get_clone(id).unwrap_or(id.clone());
+        match self.get_surface_from_id(&root) {
+            Some(surf) => surf
+                .lock()
+                .unwrap()
+                .s_state
+                .cs_xdg_state
+                .xs_tlstate
+                .as_ref()
+                .map(|tl| tl.tl_maximized || tl.tl_fullscreen)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    /// Ask maximized and fullscreen windows to fit the resolution again
+    ///
+    /// This should be called after the output is resized.
+    pub fn reconfigure_window_states(&mut self) {
+        let windows: Vec<SurfaceId> = (0..NUM_WORKSPACES)
+            .flat_map(|ws| self.windows_on_workspace(ws))
+            .collect();
+
+        for id in windows.iter() {
+            let surf_cell = match self.get_surface_from_id(id) {
+                Some(s) => s,
+                None => continue,
+            };
+            let mut surf = surf_cell.lock().unwrap();
+            let (xdg_surf, ss) = match &surf.s_role {
+                Some(Role::xdg_shell_toplevel(xs, ss)) => (xs.clone(), ss.clone()),
+                _ => continue,
+            };
+            ss.lock()
+                .unwrap()
+                .configure_state(self, xdg_surf, &mut surf);
+        }
+    }
+
+    /// Is this window minimized?
+    pub fn window_is_minimized(&self, id: &SurfaceId) -> bool {
+        self.a_minimized.get_clone(id).unwrap_or(false)
+    }
+
+    /// Hide a window until it is focused again
+    ///
+    /// If the window was in focus then the next window on the workspace
+    /// is focused instead.
+    pub fn minimize_window(&mut self, id: &SurfaceId) {
+        if self.window_is_minimized(id) {
+            return;
+        }
+        log::debug!("Minimizing window {:?}", id);
+
+        if self.get_grabbed().as_ref() == Some(id) {
+            self.set_grabbed(None);
+        }
+        if self.get_resizing().as_ref() == Some(id) {
+            self.set_resizing(None);
+        }
+        self.overview_remove_window(id);
+
+        self.a_minimized.set(id, true);
+        self.mark_tiling_dirty();
+        self.add_wm_task(Task::set_minimized {
+            id: id.clone(),
+            minimized: true,
+        });
+
+        if self.get_keyboard_focus().as_ref() == Some(id) {
+            self.refocus_current_workspace();
+        } else {
+            self.recalculate_pointer_focus();
+        }
+    }
+
+    /// Show a minimized window again
+    ///
+    /// This is done when the window is focused.
+    pub(super) fn unminimize_window(&mut self, id: &SurfaceId) {
+        if !self.window_is_minimized(id) {
+            return;
+        }
+        log::debug!("Restoring minimized window {:?}", id);
+
+        self.a_minimized.take(id);
+        self.mark_tiling_dirty();
+        self.add_wm_task(Task::set_minimized {
+            id: id.clone(),
+            minimized: false,
+        });
+    }
+}
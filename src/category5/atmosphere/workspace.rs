@@ -0,0 +1,155 @@
+// Workspace assignment for toplevel windows
+//
+// Each root window is assigned to one workspace. Only the windows on
+// the current workspace are visible and can receive input, the rest
+// stay in the skiplist but are skipped over when iterating.
+//
+// Workspaces are not per-output. Category5 only drives one output at a
+// time, so there is a single set of workspaces and one current
+// workspace for the whole desktop.
+//
+// Austin Shafer - 2024
+
+use super::*;
+use crate::category5::input::Input;
+use crate::category5::vkcomp::wm::task::Task;
+use utils::log;
+
+/// The number of workspaces available on the desktop
+pub static NUM_WORKSPACES: u32 = 4;
+
+impl Atmosphere {
+    /// Get the workspace this window is assigned to
+    ///
+    /// Subsurfaces are not assigned a workspace themselves, they
+    /// use the workspace of their root window.
+    pub fn get_window_workspace(&self, id: &SurfaceId) -> Option<u32> {
+        let root = match self.a_root_window.get_clone(id) {
+            Some(root) => root,
+            None => id.clone(),
+        };
+        self.a_workspace.get_clone(&root)
+    }
+
+    /// Is this window shown on the current workspace?
+    ///
+    /// Windows which have not been assigned a workspace are always
+    /// shown.
+    pub fn window_is_on_current_workspace(&self, id: &SurfaceId) -> bool {
+        match self.get_window_workspace(id) {
+            Some(ws) => ws == self.get_current_workspace(),
+            None => true,
+        }
+    }
+
+    /// Place a newly mapped toplevel on the current workspace
+    pub fn assign_to_current_workspace(&mut self, id: &SurfaceId) {
+        self.a_workspace.set(id, self.get_current_workspace());
+        self.mark_tiling_dirty();
+    }
+
+    /// Focus the top window of the current workspace
+    ///
+    /// If the workspace is empty then the keyboard focus is
+    /// cleared, leaving win_focus in place as the head of the skiplist.
+    pub(super) fn refocus_current_workspace(&mut self) {
+        let top = self.visible_windows().next();
+        match top {
+            Some(id) => self.focus_on(Some(id)),
+            None => {
+                if let Some(focus) = self.get_keyboard_focus() {
+                    Input::keyboard_leave(self, &focus);
+                }
+            }
+        }
+        self.recalculate_pointer_focus();
+    }
+
+    /// Change the workspace being displayed
+    ///
+    /// vkcomp is told to swap the window elements shown on the desktop,
+    /// and focus moves to the top window of the new workspace.
+    pub fn switch_to_workspace(&mut self, workspace: u32) {
+        let old = self.get_current_workspace();
+        if workspace == old || workspace >= NUM_WORKSPACES {
+            return;
+        }
+        log::debug!("Switching from workspace {} to {}", old, workspace);
+
+        // Drop any in-progress grabs, those windows are going away
+        self.set_grabbed(None);
+        self.set_resizing(None);
+        self.overview_cancel();
+
+        self.set_current_workspace(workspace);
+        self.add_wm_task(Task::switch_workspace {
+            old,
+            new: workspace,
+        });
+        self.refocus_current_workspace();
+    }
+
+    /// Free the shadow copies of windows on other workspaces
+    ///
+    /// This is used when the GPU is running low on memory. These windows
+    /// are shown without contents until their clients commit new buffers.
+    /// Returns the number of surfaces whose contents were freed.
+    pub fn evict_hidden_shadow_resources(&mut self) -> usize {
+        let current = self.get_current_workspace();
+        let mut surfaces: Vec<SurfaceId> = (0..NUM_WORKSPACES)
+            .filter(|ws| *ws != current)
+            .flat_map(|ws| self.windows_on_workspace(ws))
+            .collect();
+
+        // Subsurfaces are on the same workspace as their root window
+        let mut i = 0;
+        while i < surfaces.len() {
+            let children: Vec<SurfaceId> = self.visible_subsurfaces(&surfaces[i]).collect();
+            surfaces.extend(children);
+            i += 1;
+        }
+
+        let mut count = 0;
+        for id in surfaces.iter() {
+            if let Some(res) = self.a_surf_resource.get_clone(id) {
+                if self.a_shadow_buffer.get(&res).is_some() {
+                    self.a_surf_resource.take(id);
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Move a toplevel window to another workspace
+    ///
+    /// If the window leaves the current workspace then focus moves to the
+    /// next window on this workspace.
+    pub fn move_window_to_workspace(&mut self, id: &SurfaceId, workspace: u32) {
+        let old = match self.get_window_workspace(id) {
+            Some(old) => old,
+            None => return,
+        };
+        if workspace == old || workspace >= NUM_WORKSPACES {
+            return;
+        }
+        log::debug!(
+            "Moving window {:?} from workspace {} to {}",
+            id,
+            old,
+            workspace
+        );
+
+        self.a_workspace.set(id, workspace);
+        self.mark_tiling_dirty();
+        self.add_wm_task(Task::move_to_workspace {
+            id: id.clone(),
+            old,
+            new: workspace,
+        });
+        if old == self.get_current_workspace() {
+            self.refocus_current_workspace();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Programs we start for the user
+//
+// Keybindings and autostart entries are run with `sh -c`. We hold on to
+// the `Child` of each one so that it can be waited on once it exits,
+// otherwise it would be left behind as a zombie. Dakota delivers SIGCHLD
+// as the `ChildExited` event, at which point we check all of them since
+// several exits may be reported by a single signal.
+//
+// Austin Shafer - 2024
+extern crate nix;
+
+use nix::sys::signal::{SigHandler, SigSet, SigmaskHow, Signal};
+use utils::log;
+
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+/// The commands we launched which have not exited yet
+pub struct Children {
+    ch_children: Vec<Child>,
+}
+
+impl Children {
+    pub fn new() -> Self {
+        Self {
+            ch_children: Vec::new(),
+        }
+    }
+
+    /// Run `cmd` with the shell
+    ///
+    /// `env` holds extra variables to set for it.
+    pub fn spawn_shell(&mut self, cmd: &str, env: &[(&str, &str)]) -> std::io::Result<()> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(cmd).envs(env.iter().copied());
+        // Our event loop blocks or ignores the signals it watches. Both
+        // are inherited by the child, so put them back to the defaults.
+        // Only async signal safe calls are allowed here.
+        unsafe {
+            command.pre_exec(|| {
+                for sig in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGCHLD] {
+                    nix::sys::signal::signal(sig, SigHandler::SigDfl)?;
+                }
+                nix::sys::signal::sigprocmask(
+                    SigmaskHow::SIG_SETMASK,
+                    Some(&SigSet::empty()),
+                    None,
+                )?;
+                Ok(())
+            });
+        }
+
+        self.ch_children.push(command.spawn()?);
+        Ok(())
+    }
+
+    /// Wait on any of our children that have exited
+    pub fn reap(&mut self) {
+        self.ch_children.retain_mut(|child| match child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                log::debug!("Child {} exited with {}", child.id(), status);
+                false
+            }
+            // This happens if the child was already reaped for us
+            Err(e) => {
+                log::debug!("Could not wait on child {}: {:?}", child.id(), e);
+                false
+            }
+        });
+    }
+}
@@ -0,0 +1,422 @@
+// User configuration file
+//
+// Category5 is configured with one TOML file, which is
+// `$XDG_CONFIG_HOME/category5/config.toml` or the file named by
+// CATEGORY5_CONFIG. It has a section for each part of the desktop:
+//
+//   autostart = ["waybar", "mako"]
+//
+//   [keybindings]
+//   "Meta+Return" = "launch foot"
+//
+//   [keyboard]
+//   layout = "us,de"
+//   options = "compose:ralt"
+//
+//   [input]
+//   tap_to_click = true
+//   [input.devices.Touchpad]
+//   natural_scroll = true
+//
+//   [output]
+//   subpixel = "rgb"
+//   color_profile = "/home/me/display.icc"
+//   adaptive_sync = true
+//   present_mode = "fifo"
+//   zero_copy_shm = true
+//   msaa_samples = 4
+//
+//   [theme]
+//   palette = "light"
+//   border_width = 2
+//
+//   [switcher]
+//   order = "stacking"
+//
+//   [windows]
+//   dim_unfocused = 0.8
+//   [windows.opacity]
+//   foot = 0.9
+//
+// If the keybindings or input sections are left out then the older
+// keybindings and input files are used. The config directory is watched
+// so that changes are applied without restarting.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate nix;
+extern crate serde;
+extern crate toml;
+
+use crate::category5::atmosphere::SwitcherOrder;
+use crate::category5::children::Children;
+use crate::category5::input::device_config;
+use crate::category5::theme::Theme;
+use serde::Deserialize;
+use utils::platform::DirWatcher;
+use utils::{anyhow, log, Context, Result};
+
+use std::collections::BTreeMap;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+/// Get the directory holding the user's config files
+///
+/// This is `$XDG_CONFIG_HOME/category5`, or `~/.config/category5`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("category5"))
+}
+
+/// Get the path of the user's config file
+///
+/// This is `$CATEGORY5_CONFIG` if set, or else `config.toml` in
+/// `config_dir`.
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("CATEGORY5_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// Settings for the display
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Size of the window when running nested or headless
+    pub resolution: Option<(u32, u32)>,
+    /// One of none, rgb, bgr, vrgb or vbgr
+    pub subpixel: Option<String>,
+    /// ICC profile or 3D LUT to correct the screen with. This overrides
+    /// CATEGORY5_COLOR_PROFILE.
+    pub color_profile: Option<PathBuf>,
+    /// Use a variable refresh rate while a fullscreen window is focused,
+    /// if the monitor supports it
+    pub adaptive_sync: bool,
+    /// One of fifo, fifo_relaxed, mailbox or immediate
+    pub present_mode: Option<String>,
+    /// Have the GPU read shm buffers from client memory instead of copying
+    /// them, where the device allows it
+    pub zero_copy_shm: bool,
+    /// Samples per pixel to draw with, values above 1 enable MSAA. This
+    /// is only read at startup.
+    pub msaa_samples: Option<u32>,
+}
+
+impl OutputConfig {
+    /// Get the subpixel layout to use for text, if one was chosen
+    pub fn get_subpixel_layout(&self) -> Result<Option<dak::SubpixelLayout>> {
+        Ok(match self.subpixel.as_deref() {
+            None => None,
+            Some("none") => Some(dak::SubpixelLayout::None),
+            Some("rgb") => Some(dak::SubpixelLayout::Rgb),
+            Some("bgr") => Some(dak::SubpixelLayout::Bgr),
+            Some("vrgb") => Some(dak::SubpixelLayout::VerticalRgb),
+            Some("vbgr") => Some(dak::SubpixelLayout::VerticalBgr),
+            Some(other) => return Err(anyhow!("Unknown subpixel layout {}", other)),
+        })
+    }
+
+    /// Get the present mode to use, if one was chosen
+    pub fn get_present_mode(&self) -> Result<Option<dak::PresentMode>> {
+        Ok(match self.present_mode.as_deref() {
+            None => None,
+            Some("fifo") => Some(dak::PresentMode::Fifo),
+            Some("fifo_relaxed") => Some(dak::PresentMode::FifoRelaxed),
+            Some("mailbox") => Some(dak::PresentMode::Mailbox),
+            Some("immediate") => Some(dak::PresentMode::Immediate),
+            Some(other) => return Err(anyhow!("Unknown present mode {}", other)),
+        })
+    }
+}
+
+/// Settings for the alt-tab window switcher
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwitcherConfig {
+    /// One of mru or stacking
+    pub order: Option<String>,
+}
+
+impl SwitcherConfig {
+    /// Get the order windows are listed in
+    ///
+    /// By default the most recently focused windows come first.
+    pub fn get_order(&self) -> Result<SwitcherOrder> {
+        match self.order.as_deref() {
+            None => Ok(SwitcherOrder::mru),
+            Some(name) => {
+                SwitcherOrder::from_str(name).ok_or(anyhow!("Unknown switcher order {}", name))
+            }
+        }
+    }
+}
+
+/// Settings for how windows are drawn
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    /// The brightness of windows which aren't in focus, 1.0 turns off
+    /// dimming
+    pub dim_unfocused: f32,
+    /// The opacity of each app's windows, by app_id
+    pub opacity: BTreeMap<String, f32>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            dim_unfocused: 1.0,
+            opacity: BTreeMap::new(),
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Get the brightness of unfocused windows
+    pub fn get_dim_unfocused(&self) -> Result<f32> {
+        match self.dim_unfocused {
+            d if (0.0..=1.0).contains(&d) => Ok(d),
+            d => Err(anyhow!(
+                "dim_unfocused must be between 0.0 and 1.0, not {}",
+                d
+            )),
+        }
+    }
+}
+
+/// The keyboard layout
+///
+/// These are xkb names, and any that are left out use xkbcommon's
+/// defaults. Several layouts can be listed separated by commas, and the
+/// `next_keyboard_layout` keybinding cycles through them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    /// Such as `grp:alt_shift_toggle` or `compose:ralt`
+    pub options: Option<String>,
+    /// Milliseconds a key is held before it starts repeating
+    pub repeat_delay: u32,
+    /// Repeats per second, zero disables key repeat
+    pub repeat_rate: u32,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        let repeat = dak::KeyRepeatConfig::default();
+        Self {
+            rules: String::new(),
+            model: String::new(),
+            layout: String::new(),
+            variant: String::new(),
+            options: None,
+            repeat_delay: repeat.delay,
+            repeat_rate: repeat.rate,
+        }
+    }
+}
+
+impl KeyboardConfig {
+    /// Create the keymap settings handed to Dakota
+    pub fn get_keymap_config(&self) -> dak::KeymapConfig {
+        dak::KeymapConfig {
+            rules: self.rules.clone(),
+            model: self.model.clone(),
+            layout: self.layout.clone(),
+            variant: self.variant.clone(),
+            options: self.options.clone(),
+        }
+    }
+
+    /// Get the key repeat settings
+    pub fn get_key_repeat_config(&self) -> dak::KeyRepeatConfig {
+        dak::KeyRepeatConfig {
+            delay: self.repeat_delay,
+            rate: self.repeat_rate,
+        }
+    }
+}
+
+/// Settings for input devices
+///
+/// The values in this table apply to every device, and `devices` holds
+/// settings for devices whose name contains the table name. Settings use
+/// the same names as the input config file.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct InputSection {
+    #[serde(default)]
+    pub devices: BTreeMap<String, BTreeMap<String, toml::Value>>,
+    #[serde(flatten)]
+    pub default: BTreeMap<String, toml::Value>,
+}
+
+impl InputSection {
+    /// Add a table of settings to a device's settings
+    fn apply_settings(settings: &mut dak::DeviceSettings, table: &BTreeMap<String, toml::Value>) {
+        for (name, value) in table.iter() {
+            let arg = match value {
+                toml::Value::Boolean(true) => "on".to_string(),
+                toml::Value::Boolean(false) => "off".to_string(),
+                toml::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            if let Err(e) = device_config::parse_setting(settings, &format!("{} {}", name, arg)) {
+                log::error!("Invalid input setting {}: {:?}", name, e);
+            }
+        }
+    }
+
+    /// Create the config handed to Dakota
+    fn get_input_config(&self) -> dak::InputConfig {
+        let mut config = dak::InputConfig::default();
+        Self::apply_settings(&mut config.default, &self.default);
+
+        for (name, table) in self.devices.iter() {
+            let mut settings = dak::DeviceSettings::default();
+            Self::apply_settings(&mut settings, table);
+            config.devices.push((name.clone(), settings));
+        }
+        config
+    }
+}
+
+/// The user's configuration
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Commands run once when Category5 starts
+    pub autostart: Vec<String>,
+    /// Maps a key combination to an action. If this is not present the
+    /// keybindings file or default bindings are used.
+    pub keybindings: Option<BTreeMap<String, String>>,
+    pub keyboard: KeyboardConfig,
+    /// If this is not present the input config file is used
+    pub input: Option<InputSection>,
+    pub output: OutputConfig,
+    pub theme: Theme,
+    pub switcher: SwitcherConfig,
+    pub windows: WindowConfig,
+}
+
+impl Config {
+    /// Create a config from the contents of a config file
+    pub fn from_str(contents: &str) -> Result<Self> {
+        toml::from_str(contents).context("Could not parse config file")
+    }
+
+    /// Load the user's config file
+    ///
+    /// If there is no config file then the defaults are returned. An
+    /// error is only returned if the file is invalid.
+    pub fn load() -> Result<Self> {
+        if let Some(path) = config_path() {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    log::debug!("Loading config from {:?}", path);
+                    return Self::from_str(&contents);
+                }
+                Err(e) => log::debug!("Could not read config from {:?}: {:?}", path, e),
+            }
+        }
+
+        Ok(Self::default())
+    }
+
+    /// Get the settings for our input devices
+    pub fn get_input_config(&self) -> dak::InputConfig {
+        match self.input.as_ref() {
+            Some(input) => input.get_input_config(),
+            None => device_config::load_input_config(),
+        }
+    }
+
+    /// Get the names of the seats that input devices are assigned to
+    ///
+    /// This does not include the default seat unless a device was
+    /// explicitly assigned to it.
+    pub fn get_seat_names(&self) -> Vec<String> {
+        let config = self.get_input_config();
+        let mut names: Vec<String> = Vec::new();
+        let seats = std::iter::once(&config.default)
+            .chain(config.devices.iter().map(|(_, settings)| settings))
+            .filter_map(|settings| settings.seat.clone());
+
+        for seat in seats {
+            if !names.contains(&seat) {
+                names.push(seat);
+            }
+        }
+        names
+    }
+
+    /// Launch the autostart programs
+    ///
+    /// This should only be done once, after WAYLAND_DISPLAY is set.
+    pub fn run_autostart(&self, children: &mut Children) {
+        for cmd in self.autostart.iter() {
+            log::debug!("Autostarting {}", cmd);
+            if let Err(e) = children.spawn_shell(cmd, &[]) {
+                log::error!("Could not autostart {}: {:?}", cmd, e);
+            }
+        }
+    }
+}
+
+/// Watches the config directory for changes
+///
+/// Editors often save by writing a new file and renaming it over the old
+/// one, so we watch the directory the config files are in instead of the
+/// files themselves.
+pub struct ConfigWatcher {
+    cw_watcher: DirWatcher,
+}
+
+impl ConfigWatcher {
+    /// Start watching the config files
+    ///
+    /// Returns None if the config directory does not exist.
+    pub fn new() -> Option<Self> {
+        let path = config_path()?;
+        let dir = path.parent()?.to_path_buf();
+        let dir = match dir.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => dir,
+        };
+        let mut names = vec![path.file_name()?.to_os_string()];
+        if Some(&dir) == config_dir().as_ref() {
+            names.push("keybindings".into());
+            names.push("input".into());
+        }
+
+        match DirWatcher::new(dir.clone(), names) {
+            Ok(watcher) => Some(Self {
+                cw_watcher: watcher,
+            }),
+            Err(e) => {
+                log::debug!("Not watching config directory {:?}: {:?}", dir, e);
+                None
+            }
+        }
+    }
+
+    /// The fd to watch for changes
+    pub fn get_fd(&self) -> RawFd {
+        self.cw_watcher.get_fd()
+    }
+
+    /// Check if any of the config files were changed
+    ///
+    /// This clears the pending events, so it will return false until the
+    /// next change.
+    pub fn has_changed(&mut self) -> bool {
+        self.cw_watcher.has_changed()
+    }
+}
@@ -0,0 +1,137 @@
+// Input device configuration
+//
+// This loads the user's settings for their input devices, such as
+// tap-to-click and pointer acceleration. The settings are handed to
+// Dakota, which applies them to each device as it is plugged in.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use utils::{anyhow, log, Result};
+
+use std::path::PathBuf;
+
+/// Get the path of the user's input config file
+///
+/// This is `$CATEGORY5_INPUT_CONFIG` if set, or else
+/// `$XDG_CONFIG_HOME/category5/input`.
+fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("CATEGORY5_INPUT_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("category5").join("input"))
+}
+
+/// Parse an on/off value
+fn parse_bool(arg: Option<&str>) -> Result<bool> {
+    match arg {
+        Some("on") | Some("true") | Some("yes") => Ok(true),
+        Some("off") | Some("false") | Some("no") => Ok(false),
+        _ => Err(anyhow!("Expected on or off")),
+    }
+}
+
+/// Parse one line of an input config file into `settings`
+///
+/// The format is `setting value`, for example: `accel_speed 0.5`
+pub fn parse_setting(settings: &mut dak::DeviceSettings, line: &str) -> Result<()> {
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((n, a)) => (n, Some(a.trim())),
+        None => (line, None),
+    };
+
+    match name {
+        "tap_to_click" => settings.tap_to_click = Some(parse_bool(arg)?),
+        "natural_scroll" => settings.natural_scroll = Some(parse_bool(arg)?),
+        "disable_while_typing" => settings.disable_while_typing = Some(parse_bool(arg)?),
+        "accel_profile" => {
+            settings.accel_profile = Some(match arg {
+                Some("flat") => dak::AccelProfile::Flat,
+                Some("adaptive") => dak::AccelProfile::Adaptive,
+                _ => return Err(anyhow!("accel_profile must be flat or adaptive")),
+            })
+        }
+        "accel_speed" => {
+            let speed: f64 = arg
+                .ok_or(anyhow!("accel_speed requires a value"))?
+                .parse()?;
+            if !(-1.0..=1.0).contains(&speed) {
+                return Err(anyhow!("accel_speed must be between -1.0 and 1.0"));
+            }
+            settings.accel_speed = Some(speed);
+        }
+        "seat" => {
+            let seat = arg.filter(|a| !a.is_empty());
+            settings.seat = Some(seat.ok_or(anyhow!("seat requires a name"))?.to_string());
+        }
+        _ => return Err(anyhow!("Unknown input setting {}", name)),
+    }
+
+    Ok(())
+}
+
+/// Create an input config from the contents of a config file
+///
+/// Settings at the top of the file apply to every device. A line of
+/// `[name]` starts a section of settings for devices whose name contains
+/// `name`, which override the defaults:
+///
+/// ```text
+/// tap_to_click on
+///
+/// [Touchpad]
+/// natural_scroll on
+/// accel_profile flat
+/// ```
+///
+/// Blank lines and lines starting with `#` are ignored. Invalid lines
+/// are logged and skipped.
+pub fn parse_input_config(contents: &str) -> dak::InputConfig {
+    let mut config = dak::InputConfig::default();
+
+    for (num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            config
+                .devices
+                .push((name.trim().to_string(), dak::DeviceSettings::default()));
+            continue;
+        }
+
+        let settings = match config.devices.last_mut() {
+            Some((_, settings)) => settings,
+            None => &mut config.default,
+        };
+        if let Err(e) = parse_setting(settings, line) {
+            log::error!("Invalid input setting on line {}: {:?}", num + 1, e);
+        }
+    }
+
+    config
+}
+
+/// Load the user's input config
+///
+/// If no config file exists then every device keeps its defaults.
+pub fn load_input_config() -> dak::InputConfig {
+    if let Some(path) = config_path() {
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                log::debug!("Loading input config from {:?}", path);
+                return parse_input_config(&contents);
+            }
+            Err(e) => log::debug!("Could not read input config from {:?}: {:?}", path, e),
+        }
+    }
+
+    dak::InputConfig::default()
+}
@@ -0,0 +1,70 @@
+// Synthetic input injection
+//
+// This lets input come from something other than a physical device, such
+// as tests or a remote desktop server. Injected events are dispatched by
+// the same code as real ones, so they respect focus, grabs, keybindings
+// and the session lock.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use super::{event_time_to_millis, ButtonState, Input};
+use crate::category5::atmosphere::Atmosphere;
+use utils::timing::get_monotonic_micros;
+
+/// An input event to inject
+///
+/// Positions are in global coordinates and keys are Linux keycodes.
+#[derive(Debug, Copy, Clone)]
+pub enum InjectedEvent {
+    /// Move the pointer relative to its current position
+    PointerMotion { dx: i32, dy: i32 },
+    /// Press or release a pointer button
+    PointerButton {
+        button: dak::MouseButton,
+        pressed: bool,
+    },
+    /// Press or release a key
+    Key { key: u32, pressed: bool },
+    /// A finger touched the screen
+    TouchDown { id: i32, x: i32, y: i32 },
+    /// A finger on the screen moved
+    TouchMotion { id: i32, x: i32, y: i32 },
+    /// A finger was lifted from the screen
+    TouchUp { id: i32 },
+}
+
+fn get_button_state(pressed: bool) -> ButtonState {
+    match pressed {
+        true => ButtonState::Pressed,
+        false => ButtonState::Released,
+    }
+}
+
+impl Input {
+    /// Inject a synthetic input event
+    ///
+    /// The event is timestamped with the same clock as libinput uses, and
+    /// counts as user activity.
+    pub fn inject_event(&mut self, atmos: &mut Atmosphere, ev: InjectedEvent) {
+        let time = event_time_to_millis(get_monotonic_micros());
+        self.reset_idle_time();
+
+        match ev {
+            InjectedEvent::PointerMotion { dx, dy } => {
+                self.handle_pointer_move(atmos, dx, dy, time)
+            }
+            InjectedEvent::PointerButton { button, pressed } => {
+                self.handle_click_on_window(atmos, button, get_button_state(pressed), time)
+            }
+            InjectedEvent::Key { key, pressed } => {
+                self.handle_keyboard(atmos, key, get_button_state(pressed), time)
+            }
+            InjectedEvent::TouchDown { id, x, y } => self.handle_touch_down(atmos, id, x, y, time),
+            InjectedEvent::TouchMotion { id, x, y } => {
+                self.handle_touch_motion(atmos, id, x, y, time)
+            }
+            InjectedEvent::TouchUp { id } => self.handle_touch_up(atmos, id, time),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+// Compositor keybindings
+//
+// This maps modifier+key combinations to compositor actions. Bindings are
+// checked before a key event is forwarded to the client in focus, and if
+// one matches the event is consumed by Category5.
+//
+// Austin Shafer - 2024
+#![allow(non_camel_case_types)]
+
+extern crate xkbcommon;
+
+use crate::category5::atmosphere::{LayoutMode, NUM_WORKSPACES};
+use utils::{anyhow, log, Result};
+use xkbcommon::xkb;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The default set of bindings
+///
+/// These are used when the user has not provided a keybindings file.
+static DEFAULT_BINDINGS: &str = "
+# Category5 default keybindings
+Meta+Shift+q     close_window
+Meta+Tab         focus_next
+Meta+Return      launch weston-terminal
+Meta+Shift+r     toggle_renderdoc
+Meta+1           switch_workspace 1
+Meta+2           switch_workspace 2
+Meta+3           switch_workspace 3
+Meta+4           switch_workspace 4
+Meta+Shift+1     move_to_workspace 1
+Meta+Shift+2     move_to_workspace 2
+Meta+Shift+3     move_to_workspace 3
+Meta+Shift+4     move_to_workspace 4
+Meta+space       cycle_layout
+Meta+j           focus_next_tile
+Meta+k           focus_prev_tile
+Meta+Shift+j     swap_next_tile
+Meta+Shift+k     swap_prev_tile
+Meta+l           grow_master
+Meta+h           shrink_master
+Meta+Shift+a     toggle_animations
+Meta+Shift+p     toggle_hud
+Alt+Tab          switcher_next
+Alt+Shift+Tab    switcher_prev
+Meta+w           toggle_overview
+Meta+minus       adjust_opacity -0.1
+Meta+equal       adjust_opacity 0.1
+";
+
+/// An action to perform when a keybinding is triggered
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Ask the window in focus to close
+    close_window,
+    /// Cycle focus to the next window in the stack
+    focus_next,
+    /// Spawn a command with `sh -c`
+    launch(String),
+    /// Start or stop a renderdoc capture
+    toggle_renderdoc,
+    /// Display a workspace, numbered from zero
+    switch_workspace(u32),
+    /// Move the window in focus to a workspace, numbered from zero
+    move_to_workspace(u32),
+    /// Set the layout of the current workspace
+    set_layout(LayoutMode),
+    /// Switch the current workspace to the next layout
+    cycle_layout,
+    /// Focus the next window in the tiling order
+    focus_next_tile,
+    /// Focus the previous window in the tiling order
+    focus_prev_tile,
+    /// Swap the window in focus with the next tile
+    swap_next_tile,
+    /// Swap the window in focus with the previous tile
+    swap_prev_tile,
+    /// Give more space to the first tile
+    grow_master,
+    /// Give less space to the first tile
+    shrink_master,
+    /// Turn window animations on or off
+    toggle_animations,
+    /// Show or hide the performance overlay
+    toggle_hud,
+    /// Re-read the config files
+    reload_config,
+    /// Switch to the next of the keyboard layouts in the config
+    next_keyboard_layout,
+    /// Change which log messages are printed, in the CATEGORY5_LOG format
+    set_log_filter(String),
+    /// Open the window switcher, or select the next window in it
+    switcher_next,
+    /// Open the window switcher, or select the previous window in it
+    switcher_prev,
+    /// Show all windows of the current workspace in a grid, or leave it
+    toggle_overview,
+    /// Set the opacity of the window in focus and the rest of its app
+    set_opacity(f32),
+    /// Add to the opacity of the window in focus and the rest of its app
+    adjust_opacity(f32),
+}
+
+impl Action {
+    /// Parse a workspace argument
+    ///
+    /// Workspaces are numbered from one in the config file, but
+    /// from zero everywhere else.
+    fn parse_workspace(arg: Option<&str>) -> Result<u32> {
+        let num: u32 = arg
+            .ok_or(anyhow!("Workspace actions require a workspace number"))?
+            .parse()?;
+        if num == 0 || num > NUM_WORKSPACES {
+            return Err(anyhow!(
+                "Workspace must be between 1 and {}",
+                NUM_WORKSPACES
+            ));
+        }
+        Ok(num - 1)
+    }
+
+    /// Parse an opacity argument
+    fn parse_opacity(name: &str, arg: Option<&str>) -> Result<f32> {
+        let opacity: f32 = arg
+            .ok_or(anyhow!("{} requires an opacity", name))?
+            .parse()?;
+        if !opacity.is_finite() {
+            return Err(anyhow!("{} requires a number", name));
+        }
+        Ok(opacity)
+    }
+
+    /// Parse an action and its arguments, i.e. `switch_workspace 2`
+    ///
+    /// This is the part of a keybinding after the key combination, and
+    /// is also used for commands sent over IPC.
+    pub fn parse(command: &str) -> Result<Self> {
+        let command = command.trim();
+        match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => Self::from_str(name, Some(arg.trim())),
+            None => Self::from_str(command, None),
+        }
+    }
+
+    /// Parse an action from its name and optional argument
+    fn from_str(name: &str, arg: Option<&str>) -> Result<Self> {
+        Ok(match name {
+            "close_window" => Self::close_window,
+            "focus_next" => Self::focus_next,
+            "toggle_renderdoc" => Self::toggle_renderdoc,
+            "switch_workspace" => Self::switch_workspace(Self::parse_workspace(arg)?),
+            "move_to_workspace" => Self::move_to_workspace(Self::parse_workspace(arg)?),
+            "set_layout" => match arg.and_then(LayoutMode::from_str) {
+                Some(mode) => Self::set_layout(mode),
+                None => {
+                    return Err(anyhow!(
+                        "set_layout requires one of floating, binary_split or master_stack"
+                    ))
+                }
+            },
+            "cycle_layout" => Self::cycle_layout,
+            "focus_next_tile" => Self::focus_next_tile,
+            "focus_prev_tile" => Self::focus_prev_tile,
+            "swap_next_tile" => Self::swap_next_tile,
+            "swap_prev_tile" => Self::swap_prev_tile,
+            "grow_master" => Self::grow_master,
+            "shrink_master" => Self::shrink_master,
+            "toggle_animations" => Self::toggle_animations,
+            "toggle_hud" => Self::toggle_hud,
+            "reload_config" | "reload_input_config" => Self::reload_config,
+            "next_keyboard_layout" => Self::next_keyboard_layout,
+            "switcher_next" => Self::switcher_next,
+            "switcher_prev" => Self::switcher_prev,
+            "toggle_overview" => Self::toggle_overview,
+            "set_opacity" => match Self::parse_opacity(name, arg)? {
+                o if (0.0..=1.0).contains(&o) => Self::set_opacity(o),
+                _ => return Err(anyhow!("set_opacity must be between 0.0 and 1.0")),
+            },
+            "adjust_opacity" => Self::adjust_opacity(Self::parse_opacity(name, arg)?),
+            "set_log_filter" => match arg {
+                Some(spec) if !spec.is_empty() => Self::set_log_filter(spec.to_string()),
+                _ => return Err(anyhow!("set_log_filter requires a log filter")),
+            },
+            "launch" => match arg {
+                Some(cmd) if !cmd.is_empty() => Self::launch(cmd.to_string()),
+                _ => return Err(anyhow!("launch requires a command to run")),
+            },
+            _ => return Err(anyhow!("Unknown keybinding action {}", name)),
+        })
+    }
+}
+
+/// The set of modifiers that must be held for a binding
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BindingMods {
+    pub bm_ctrl: bool,
+    pub bm_alt: bool,
+    pub bm_shift: bool,
+    pub bm_meta: bool,
+}
+
+/// A single keybinding
+///
+/// The key is stored as an xkb keysym for the unmodified (level 0)
+/// symbol of the key, so `Meta+Shift+q` matches the `q` key and not `Q`.
+#[derive(Debug, Clone)]
+pub struct KeyBinding {
+    pub kb_mods: BindingMods,
+    pub kb_keysym: xkb::Keysym,
+    pub kb_action: Action,
+}
+
+impl KeyBinding {
+    /// Parse one line of a keybindings file
+    ///
+    /// The format is `Mod+Mod+key action [args]`, for example:
+    /// `Meta+Return launch weston-terminal`
+    fn parse(line: &str) -> Result<Self> {
+        let line = line.trim();
+        let (combo, rest) = match line.split_once(char::is_whitespace) {
+            Some((c, r)) => (c, r.trim()),
+            None => return Err(anyhow!("Keybinding is missing an action")),
+        };
+        let mut mods = BindingMods::default();
+        let mut keysym = None;
+        for part in combo.split('+') {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => mods.bm_ctrl = true,
+                "alt" => mods.bm_alt = true,
+                "shift" => mods.bm_shift = true,
+                "meta" | "super" | "logo" => mods.bm_meta = true,
+                _ => {
+                    if keysym.is_some() {
+                        return Err(anyhow!("Keybinding {} has more than one key", combo));
+                    }
+                    let sym = xkb::keysym_from_name(part, xkb::KEYSYM_CASE_INSENSITIVE);
+                    if sym == xkb::keysyms::KEY_NoSymbol {
+                        return Err(anyhow!("Unknown key name {}", part));
+                    }
+                    keysym = Some(sym);
+                }
+            }
+        }
+
+        Ok(Self {
+            kb_mods: mods,
+            kb_keysym: keysym.ok_or(anyhow!("Keybinding {} does not name a key", combo))?,
+            kb_action: Action::parse(rest)?,
+        })
+    }
+}
+
+/// The compositor's table of keybindings
+///
+/// Input consults this on every key press before forwarding the key
+/// to clients.
+pub struct KeyBindingManager {
+    kbm_bindings: Vec<KeyBinding>,
+    /// Raw keycodes whose press was consumed by a binding. We also
+    /// need to consume the matching release so that the client doesn't
+    /// see a release without a press.
+    kbm_consumed: Vec<u32>,
+}
+
+impl KeyBindingManager {
+    /// Get the path of the user's keybindings file
+    ///
+    /// This is `$CATEGORY5_KEYBINDINGS` if set, or else
+    /// `$XDG_CONFIG_HOME/category5/keybindings`.
+    fn config_path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var("CATEGORY5_KEYBINDINGS") {
+            return Some(PathBuf::from(path));
+        }
+
+        let base = match std::env::var("XDG_CONFIG_HOME") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+        };
+        Some(base.join("category5").join("keybindings"))
+    }
+
+    /// Create a new set of bindings from the contents of a keybindings file
+    ///
+    /// Blank lines and lines starting with `#` are ignored. Invalid lines
+    /// are logged and skipped.
+    pub fn from_str(contents: &str) -> Self {
+        let mut bindings = Vec::new();
+
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match KeyBinding::parse(line) {
+                Ok(binding) => bindings.push(binding),
+                Err(e) => log::error!("Invalid keybinding on line {}: {:?}", num + 1, e),
+            }
+        }
+
+        Self {
+            kbm_bindings: bindings,
+            kbm_consumed: Vec::new(),
+        }
+    }
+
+    /// Create a new set of bindings from the config file's keybindings table
+    ///
+    /// This maps each key combination to an action. Invalid bindings are
+    /// logged and skipped.
+    pub fn from_table(table: &BTreeMap<String, String>) -> Self {
+        let mut bindings = Vec::new();
+
+        for (combo, action) in table.iter() {
+            match KeyBinding::parse(&format!("{} {}", combo, action)) {
+                Ok(binding) => bindings.push(binding),
+                Err(e) => log::error!("Invalid keybinding {}: {:?}", combo, e),
+            }
+        }
+
+        Self {
+            kbm_bindings: bindings,
+            kbm_consumed: Vec::new(),
+        }
+    }
+
+    /// Load the user's keybindings
+    ///
+    /// If no keybindings file exists then the defaults are used.
+    pub fn new() -> Self {
+        if let Some(path) = Self::config_path() {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    log::debug!("Loading keybindings from {:?}", path);
+                    return Self::from_str(&contents);
+                }
+                Err(e) => log::debug!("Could not read keybindings from {:?}: {:?}", path, e),
+            }
+        }
+
+        Self::from_str(DEFAULT_BINDINGS)
+    }
+
+    /// Find the action bound to this key press
+    ///
+    /// `keysym` should be the level 0 symbol of the key. The modifiers must
+    /// match exactly, apart from caps and num lock which are ignored. If
+    /// this returns an action then the press is recorded so the release
+    /// can be consumed by `handle_release`.
+    pub fn handle_press(
+        &mut self,
+        mods: BindingMods,
+        keysym: xkb::Keysym,
+        raw_key: u32,
+    ) -> Option<Action> {
+        let binding = self
+            .kbm_bindings
+            .iter()
+            .find(|b| b.kb_mods == mods && b.kb_keysym == keysym)?;
+
+        let action = binding.kb_action.clone();
+        self.consume(raw_key);
+        Some(action)
+    }
+
+    /// Record a key press that was handled by the compositor
+    ///
+    /// The release of this key will be consumed by `handle_release`.
+    pub fn consume(&mut self, raw_key: u32) {
+        self.kbm_consumed.push(raw_key);
+    }
+
+    /// Check if the release of this key should be consumed
+    ///
+    /// Returns true if the press of this key triggered a binding.
+    pub fn handle_release(&mut self, raw_key: u32) -> bool {
+        match self.kbm_consumed.iter().position(|k| *k == raw_key) {
+            Some(index) => {
+                self.kbm_consumed.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -30,6 +30,10 @@
 // external input crate.
 #![allow(dead_code)]
 pub mod codes;
+pub mod device_config;
+pub mod inject;
+pub mod keybindings;
+use keybindings::{Action, BindingMods, KeyBindingManager};
 
 extern crate dakota as dak;
 extern crate nix;
@@ -40,16 +44,56 @@ extern crate xkbcommon;
 use wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge;
 use ws::protocol::wl_keyboard;
 use ws::protocol::wl_pointer;
+use ws::protocol::wl_touch;
 use ws::Resource;
 
-use crate::category5::atmosphere::{Atmosphere, SurfaceId};
-use crate::category5::vkcomp::wm;
+use wayland_protocols::wp::tablet::zv2::server::zwp_tablet_tool_v2 as tablet_tool;
+
+use crate::category5::atmosphere::{Atmosphere, SurfaceId, SwitcherOrder};
+use crate::category5::children::Children;
+use crate::category5::config::{Config, KeyboardConfig};
+use crate::category5::vkcomp::wm::cursor::CursorShape;
+use crate::category5::ways::activation::ActivationTokens;
 use crate::category5::ways::role::Role;
-use utils::{log, timing::*};
+use crate::category5::ways::seat::SeatInstance;
+use crate::category5::ways::tablet::TabletSeat;
+use utils::log;
+use utils::platform::create_anon_file;
 
+use nix::unistd::ftruncate;
 use xkbcommon::xkb;
 
 use core::convert::TryFrom;
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant};
+
+/// Swipes with this many fingers switch workspaces instead of being
+/// sent to clients
+const WORKSPACE_SWIPE_FINGERS: u32 = 3;
+/// How far the fingers need to move to switch workspaces
+const WORKSPACE_SWIPE_DISTANCE: f64 = 100.0;
+
+/// A touchpad gesture in progress
+enum Gesture {
+    /// A swipe being sent to this surface
+    Swipe(SurfaceId),
+    /// A pinch being sent to this surface
+    Pinch(SurfaceId),
+    /// A swipe which will switch workspaces, with the total distance
+    /// the fingers have moved
+    WorkspaceSwipe(f64, f64),
+}
+
+/// A tablet tool in proximity of a surface
+struct TabletFocus {
+    tf_tool: dak::TabletToolType,
+    tf_surface: SurfaceId,
+    /// Is the tip touching the tablet. The tool stays on this surface
+    /// until the tip is lifted.
+    tf_tip_down: bool,
+}
 
 /// This represents an input system
 ///
@@ -66,8 +110,22 @@ pub struct Input {
     /// this is referenced by Seat, which needs to map and
     /// share it with the clients
     pub i_xkb_keymap_name: String,
-    /// xkb state machine
+    /// xkb state machine of the active seat
     i_xkb_state: xkb::State,
+    /// The xkb state of the other seats, so that modifiers held on one
+    /// seat don't affect the others
+    i_seat_xkb_states: HashMap<usize, xkb::State>,
+    /// The seat whose xkb state is in `i_xkb_state`
+    i_active_seat: usize,
+    /// The seat the next input events come from
+    i_event_seat: usize,
+    /// The layout settings our keymap was made from
+    i_keyboard_config: KeyboardConfig,
+    /// True if the keymap changed and needs to be sent to clients
+    i_keymap_changed: bool,
+    /// True if the key repeat settings changed and need to be sent
+    /// to clients
+    i_key_repeat_changed: bool,
 
     /// Tracking info for the modifier keys
     /// These keys are sent separately in the modifiers event
@@ -77,6 +135,39 @@ pub struct Input {
     pub i_mod_caps: bool,
     pub i_mod_meta: bool,
     pub i_mod_num: bool,
+
+    /// Compositor keybindings, checked before keys are sent to clients
+    i_keybindings: KeyBindingManager,
+    /// The order the alt-tab switcher lists windows in
+    i_switcher_order: SwitcherOrder,
+
+    /// The surface each touch point went down on, indexed by touch id
+    ///
+    /// A touch point's events go to the surface it started on, even
+    /// if it is dragged off of it.
+    i_touch_focus: HashMap<i32, SurfaceId>,
+
+    /// The touchpad gesture in progress
+    i_gesture: Option<Gesture>,
+
+    /// The surface the tablet tool is over
+    i_tablet_focus: Option<TabletFocus>,
+
+    /// Settings for our input devices
+    i_input_config: dak::InputConfig,
+    /// True if the input config changed and needs to be applied
+    i_input_config_changed: bool,
+    /// True if the user asked for the config files to be read again
+    i_config_reload_requested: bool,
+
+    /// When the user last did anything, used to tell if they are idle
+    i_last_activity: Instant,
+
+    /// Tokens for xdg-activation, handed out to clients and to the
+    /// commands we launch
+    pub i_activation_tokens: ActivationTokens,
+    /// The commands we launched, which need to be waited on
+    pub i_children: Children,
 }
 
 #[derive(Copy, Eq, PartialEq, Clone)]
@@ -94,6 +185,13 @@ fn map_key_state(state: ButtonState) -> wl_keyboard::KeyState {
     }
 }
 
+// Convert a Dakota event timestamp to the milliseconds wayland uses
+//
+// Wayland timestamps are allowed to wrap, so truncating is fine.
+fn event_time_to_millis(time: u64) -> u32 {
+    (time / 1000) as u32
+}
+
 // NOTE:
 // The XKB entries above are not marked send/sync. Due to the way
 // cat5 is written they will never be used from multiple threads,
@@ -103,22 +201,22 @@ unsafe impl Send for Input {}
 impl Input {
     /// Create an input subsystem.
     ///
-    /// Setup the libinput library from a udev context
-    pub fn new() -> Input {
+    /// Setup the libinput library from a udev context. The keybindings
+    /// and device settings are taken from `config`.
+    pub fn new(config: &Config) -> Input {
         // Create all the components for xkb
         // A description of this can be found in the xkb
         // section of wayland-book.com
         let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
-        let keymap = xkb::Keymap::new_from_names(
-            &context,
-            &"",
-            &"",
-            &"",
-            &"", // These should be env vars
-            None,
-            xkb::KEYMAP_COMPILE_NO_FLAGS,
-        )
-        .expect("Could not initialize a xkb keymap");
+        let keymap = Self::create_keymap(&context, &config.keyboard)
+            .or_else(|| {
+                log::error!(
+                    "Invalid keyboard layout {:?}, using the default",
+                    config.keyboard
+                );
+                Self::create_keymap(&context, &KeyboardConfig::default())
+            })
+            .expect("Could not initialize a xkb keymap");
         let km_name = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
 
         let state = xkb::State::new(&keymap);
@@ -128,15 +226,286 @@ impl Input {
             i_xkb_keymap: keymap,
             i_xkb_keymap_name: km_name,
             i_xkb_state: state,
+            i_seat_xkb_states: HashMap::new(),
+            i_active_seat: 0,
+            i_event_seat: 0,
+            i_keyboard_config: config.keyboard.clone(),
+            // Hand our layout to Dakota the first time we are checked
+            i_keymap_changed: true,
+            i_key_repeat_changed: false,
             i_mod_ctrl: false,
             i_mod_alt: false,
             i_mod_shift: false,
             i_mod_caps: false,
             i_mod_meta: false,
             i_mod_num: false,
+            i_keybindings: Self::get_keybindings(config),
+            i_switcher_order: Self::get_switcher_order(config),
+            i_touch_focus: HashMap::new(),
+            i_gesture: None,
+            i_tablet_focus: None,
+            // Apply the user's settings the first time we are checked
+            i_input_config: config.get_input_config(),
+            i_input_config_changed: true,
+            i_config_reload_requested: false,
+            i_last_activity: Instant::now(),
+            i_activation_tokens: ActivationTokens::new(),
+            i_children: Children::new(),
+        }
+    }
+
+    /// Get how long it has been since the last input event
+    pub fn get_idle_time(&self) -> Duration {
+        self.i_last_activity.elapsed()
+    }
+
+    /// Treat the user as active right now
+    ///
+    /// This is used by idle inhibitors to keep the user from going idle.
+    pub fn reset_idle_time(&mut self) {
+        self.i_last_activity = Instant::now();
+    }
+
+    /// Get the current settings for our input devices
+    pub fn get_input_config(&self) -> &dak::InputConfig {
+        &self.i_input_config
+    }
+
+    /// Replace the settings for all input devices
+    pub fn set_input_config(&mut self, config: dak::InputConfig) {
+        self.i_input_config = config;
+        self.i_input_config_changed = true;
+    }
+
+    /// Change the settings of input devices
+    ///
+    /// If `device` is None this changes the settings used by all devices,
+    /// otherwise it changes the override for devices whose name contains
+    /// `device`.
+    pub fn set_device_settings(&mut self, device: Option<&str>, settings: dak::DeviceSettings) {
+        match device {
+            Some(name) => {
+                let config = &mut self.i_input_config;
+                match config.devices.iter_mut().find(|(n, _)| n.as_str() == name) {
+                    Some((_, old)) => *old = settings,
+                    None => config.devices.push((name.to_string(), settings)),
+                }
+            }
+            None => self.i_input_config.default = settings,
+        }
+        self.i_input_config_changed = true;
+    }
+
+    /// Get the input config if it has changed since it was last applied
+    ///
+    /// The caller is responsible for handing this to Dakota.
+    pub fn take_input_config_update(&mut self) -> Option<&dak::InputConfig> {
+        match std::mem::take(&mut self.i_input_config_changed) {
+            true => Some(&self.i_input_config),
+            false => None,
+        }
+    }
+
+    /// Create a keymap, returning None if the layout names are invalid
+    fn create_keymap(ctx: &xkb::Context, config: &KeyboardConfig) -> Option<xkb::Keymap> {
+        xkb::Keymap::new_from_names(
+            ctx,
+            &config.rules,
+            &config.model,
+            &config.layout,
+            &config.variant,
+            config.options.clone(),
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+    }
+
+    /// Switch to a new keyboard layout
+    ///
+    /// If the layout is invalid we keep using the current one. Clients
+    /// are sent the new keymap by `send_keymap_to_all`, and the new
+    /// repeat settings by `send_repeat_info_to_all`.
+    pub fn set_keyboard_config(&mut self, config: &KeyboardConfig) {
+        if config.get_key_repeat_config() != self.i_keyboard_config.get_key_repeat_config() {
+            self.i_keyboard_config.repeat_delay = config.repeat_delay;
+            self.i_keyboard_config.repeat_rate = config.repeat_rate;
+            self.i_key_repeat_changed = true;
+        }
+        if *config == self.i_keyboard_config {
+            return;
+        }
+
+        let keymap = match Self::create_keymap(&self.i_xkb_ctx, config) {
+            Some(keymap) => keymap,
+            None => {
+                log::error!("Invalid keyboard layout {:?}, keeping the old one", config);
+                return;
+            }
+        };
+        log::debug!("Switching to keyboard layout {:?}", config);
+
+        self.i_xkb_keymap_name = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
+        self.i_xkb_state = xkb::State::new(&keymap);
+        // The other seats get new states when they are next used
+        self.i_seat_xkb_states.clear();
+        self.i_xkb_keymap = keymap;
+        self.i_keyboard_config = config.clone();
+        self.update_mod_tracking();
+        self.i_keymap_changed = true;
+    }
+
+    /// Get the keyboard layout if it has changed since it was last sent
+    ///
+    /// The caller is responsible for calling `send_keymap_to_all` and
+    /// handing this to Dakota.
+    pub fn take_keymap_update(&mut self) -> Option<dak::KeymapConfig> {
+        match std::mem::take(&mut self.i_keymap_changed) {
+            true => Some(self.i_keyboard_config.get_keymap_config()),
+            false => None,
+        }
+    }
+
+    /// Check if the key repeat settings changed since they were last sent
+    ///
+    /// The caller is responsible for calling `send_repeat_info_to_all`.
+    pub fn take_key_repeat_update(&mut self) -> bool {
+        std::mem::take(&mut self.i_key_repeat_changed)
+    }
+
+    /// Tell a client's keyboard how to repeat keys
+    ///
+    /// Clients repeat held keys themselves, we only tell them the rate
+    /// and delay to use. This event was added in version 4.
+    pub fn send_repeat_info(&self, keyboard: &wl_keyboard::WlKeyboard) {
+        if keyboard.version() >= 4 {
+            let config = &self.i_keyboard_config;
+            keyboard.repeat_info(config.repeat_rate as i32, config.repeat_delay as i32);
         }
     }
 
+    /// Send the key repeat settings to every keyboard
+    pub fn send_repeat_info_to_all(&self, atmos: &Atmosphere) {
+        for cell in atmos.get_all_seats().iter() {
+            let seat = cell.lock().unwrap();
+            for si in seat.s_proxies.iter() {
+                for keyboard in si.si_keyboards.iter() {
+                    self.send_repeat_info(keyboard);
+                }
+            }
+        }
+    }
+
+    /// Share our keymap with a client's keyboard
+    pub fn send_keymap(&self, keyboard: &wl_keyboard::WlKeyboard) {
+        let keymap = self.i_xkb_keymap_name.as_bytes();
+
+        // Make a temp fd to share with the client
+        let mut file =
+            create_anon_file("cat5_keymap").expect("Could not create the temp xkb keymap file");
+        // according to the manpage: writes do not extend
+        // shm objects, so we need to call ftruncate first
+        ftruncate(&file, keymap.len() as i64).expect("Could not truncate the temp xkb keymap file");
+        // write the input systems keymap to our anon file
+        file.write_all(keymap)
+            .expect("Could not write to the temp xkb keymap file");
+        file.flush().unwrap();
+
+        keyboard.keymap(
+            wl_keyboard::KeymapFormat::XkbV1,
+            file.as_fd(),
+            keymap.len() as u32,
+        );
+    }
+
+    /// Send our keymap to every keyboard
+    ///
+    /// This is needed after the layout changes. Each client in focus
+    /// is also sent the new modifiers, since the keymap event resets them.
+    pub fn send_keymap_to_all(&self, atmos: &Atmosphere) {
+        let focus = atmos.get_client_in_focus();
+        let (depressed, latched, locked, layout) = self.serialize_mods();
+
+        for cell in atmos.get_all_seats().iter() {
+            let mut seat = cell.lock().unwrap();
+            let in_focus = focus.as_ref() == Some(&seat.s_id);
+
+            for si in seat.s_proxies.iter() {
+                for keyboard in si.si_keyboards.iter() {
+                    self.send_keymap(keyboard);
+                    if in_focus {
+                        keyboard.modifiers(seat.s_serial, depressed, latched, locked, layout);
+                    }
+                }
+            }
+            seat.s_serial += 1;
+        }
+    }
+
+    /// Serialize the xkb modifiers for the wl_keyboard.modifiers event
+    ///
+    /// Returns (depressed, latched, locked, layout).
+    fn serialize_mods(&self) -> (u32, u32, u32, u32) {
+        (
+            self.i_xkb_state.serialize_mods(xkb::STATE_MODS_DEPRESSED),
+            self.i_xkb_state.serialize_mods(xkb::STATE_MODS_LATCHED),
+            self.i_xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED),
+            self.i_xkb_state.serialize_layout(xkb::STATE_LAYOUT_LOCKED),
+        )
+    }
+
+    /// Update our tracking of which modifiers are held down
+    fn update_mod_tracking(&mut self) {
+        let state = &self.i_xkb_state;
+        self.i_mod_ctrl = state.mod_name_is_active(&xkb::MOD_NAME_CTRL, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_alt = state.mod_name_is_active(&xkb::MOD_NAME_ALT, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_shift =
+            state.mod_name_is_active(&xkb::MOD_NAME_SHIFT, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_caps = state.mod_name_is_active(&xkb::MOD_NAME_CAPS, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_meta = state.mod_name_is_active(&xkb::MOD_NAME_LOGO, xkb::STATE_MODS_EFFECTIVE);
+        self.i_mod_num = state.mod_name_is_active(&xkb::MOD_NAME_NUM, xkb::STATE_MODS_EFFECTIVE);
+    }
+
+    /// Send the current modifiers to the client in focus
+    fn send_modifiers(&self, atmos: &Atmosphere) {
+        let id = match atmos.get_client_in_focus() {
+            Some(id) => id,
+            None => return,
+        };
+        let (depressed, latched, locked, layout) = self.serialize_mods();
+
+        if let Some(cell) = atmos.get_seat_from_client_id(&id) {
+            let mut seat = cell.lock().unwrap();
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
+                for keyboard in si.si_keyboards.iter() {
+                    keyboard.modifiers(seat.s_serial, depressed, latched, locked, layout);
+                }
+            }
+            seat.s_serial += 1;
+        }
+    }
+
+    /// Switch to the next layout in our keymap
+    ///
+    /// This cycles through the comma separated layouts in the keyboard
+    /// config. The layout is part of the modifier state, so clients are
+    /// told about it with the modifiers event.
+    pub fn next_keyboard_layout(&mut self, atmos: &Atmosphere) {
+        let count = self.i_xkb_keymap.num_layouts();
+        if count <= 1 {
+            return;
+        }
+
+        let (depressed, latched, locked, layout) = self.serialize_mods();
+        let next = (layout + 1) % count;
+        self.i_xkb_state
+            .update_mask(depressed, latched, locked, 0, 0, next);
+        log::debug!(
+            "Switched to keyboard layout {}",
+            self.i_xkb_keymap.layout_get_name(next)
+        );
+
+        self.send_modifiers(atmos);
+    }
+
     fn send_pointer_frame(pointer: &wl_pointer::WlPointer) {
         if pointer.version() >= 5 {
             pointer.frame();
@@ -148,8 +517,8 @@ impl Input {
         axis_type: wl_pointer::Axis,
         val: f64,
         val_discrete: f64,
+        time: u32,
     ) {
-        let time = get_current_millis();
         // deliver the axis events, one for each direction
         if val != 0.0 {
             if val_discrete != 0.0 && pointer.version() >= 8 {
@@ -184,6 +553,7 @@ impl Input {
         yrel: Option<i32>,
         v120_val: (f64, f64),
         source: dak::AxisSource,
+        time: u32,
     ) {
         // Find the active window
         if let Some(id) = atmos.get_pointer_focus() {
@@ -191,7 +561,7 @@ impl Input {
             if let Some(cell) = atmos.get_seat_from_surface_id(&id) {
                 let seat = cell.lock().unwrap();
                 // Get the pointer
-                for si in seat.s_proxies.iter() {
+                for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                     for pointer in si.si_pointers.iter() {
                         // Send the source of this input event. This will for now be either
                         // finger scrolling on a touchpad or scroll wheel scrolling. Firefox
@@ -208,6 +578,7 @@ impl Input {
                                 wl_pointer::Axis::HorizontalScroll,
                                 hori_val as f64,
                                 v120_val.0,
+                                time,
                             );
                         }
                         if let Some(vert_val) = yrel {
@@ -217,6 +588,7 @@ impl Input {
                                 vert_val as f64,
                                 // convert our Option<tuple> to Option<f64>
                                 v120_val.1,
+                                time,
                             );
                         }
                         Self::send_pointer_frame(pointer);
@@ -242,7 +614,7 @@ impl Input {
             // TODO: verify
             // The client may have allocated multiple seats, and we should
             // deliver events to all of them
-            for si in seat.s_proxies.iter() {
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                 for keyboard in si.si_keyboards.iter() {
                     if let Some(surf) = atmos.get_wl_surface_from_id(id) {
                         keyboard.enter(
@@ -268,7 +640,7 @@ impl Input {
             // TODO: verify
             // The client may have allocated multiple seats, and we should
             // deliver events to all of them
-            for si in seat.s_proxies.iter() {
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                 for keyboard in si.si_keyboards.iter() {
                     if let Some(surf) = atmos.get_wl_surface_from_id(id) {
                         keyboard.leave(seat.s_serial, &surf);
@@ -294,7 +666,7 @@ impl Input {
                     // TODO: verify
                     // The client may have allocated multiple seats, and we should
                     // deliver events to all of them
-                    for si in seat.s_proxies.iter() {
+                    for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                         for pointer in si.si_pointers.iter() {
                             pointer.enter(seat.s_serial, &surf, sx, sy);
                             Self::send_pointer_frame(pointer);
@@ -314,14 +686,14 @@ impl Input {
         log::error!("Pointer left SurfaceId {:?}", id);
 
         // Clear the current cursor image
-        atmos.add_wm_task(wm::task::Task::reset_cursor);
+        atmos.reset_cursor();
 
         if let Some(cell) = atmos.get_seat_from_surface_id(id) {
             let seat = cell.lock().unwrap();
             // TODO: verify
             // The client may have allocated multiple seats, and we should
             // deliver events to all of them
-            for si in seat.s_proxies.iter() {
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                 for pointer in si.si_pointers.iter() {
                     if let Some(surf) = atmos.get_wl_surface_from_id(id) {
                         pointer.leave(seat.s_serial, &surf);
@@ -358,7 +730,7 @@ impl Input {
     ///
     /// Also generates wl_pointer.motion events to the surface
     /// in focus if the cursor is on that surface
-    fn handle_pointer_move(&mut self, atmos: &mut Atmosphere, dx: i32, dy: i32) {
+    fn handle_pointer_move(&mut self, atmos: &mut Atmosphere, dx: i32, dy: i32, time: u32) {
         // Update the atmosphere with the new cursor pos
         atmos.add_cursor_pos(dx as f64, dy as f64);
 
@@ -372,18 +744,24 @@ impl Input {
         let (cx, cy) = atmos.get_cursor_pos();
         atmos.recalculate_pointer_focus();
 
+        // Clients choose the cursor for their surfaces, everywhere
+        // else we show our own
+        if let Some(shape) = Self::get_cursor_shape_at(atmos, cx as f32, cy as f32) {
+            atmos.set_cursor_shape(shape);
+        }
+
         // deliver the motion event
         if let Some(id) = atmos.get_pointer_focus() {
             if let Some(cell) = atmos.get_seat_from_surface_id(&id) {
                 // get the seat for this client
                 let seat = cell.lock().unwrap();
                 // Get the pointer
-                for si in seat.s_proxies.iter() {
+                for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                     for pointer in si.si_pointers.iter() {
                         // If the pointer is over this surface
                         if let Some((sx, sy)) = atmos.global_coords_to_surf(&id, cx, cy) {
                             // deliver the motion event
-                            pointer.motion(get_current_millis(), sx, sy);
+                            pointer.motion(time, sx, sy);
                             Self::send_pointer_frame(pointer);
                         }
                     }
@@ -392,6 +770,42 @@ impl Input {
         }
     }
 
+    /// Get the cursor from our theme to show at this point
+    ///
+    /// Returns None if the point is over the contents of a window, in
+    /// which case the client picks the cursor.
+    fn get_cursor_shape_at(atmos: &Atmosphere, x: f32, y: f32) -> Option<CursorShape> {
+        // The screen locker picks its own cursor
+        if atmos.get_locked() {
+            return None;
+        }
+        if atmos.get_grabbed().is_some() {
+            return Some(CursorShape::Move);
+        }
+
+        match atmos.find_window_or_decoration_at_point(x, y) {
+            Some((id, on_decoration)) => {
+                let edge = match on_decoration {
+                    true => atmos.point_is_on_border(&id, x, y),
+                    false => atmos.point_is_on_window_edge(&id, x, y),
+                };
+                if edge != ResizeEdge::None
+                    && !atmos.window_is_tiled(&id)
+                    && !atmos.window_is_maximized_or_fullscreen(&id)
+                {
+                    return Some(CursorShape::from_resize_edge(edge));
+                }
+
+                match on_decoration {
+                    true => Some(CursorShape::Default),
+                    false => None,
+                }
+            }
+            // Over the desktop background
+            None => Some(CursorShape::Default),
+        }
+    }
+
     /// Delivers the wl_pointer.button event to any surface in focus.
     ///
     /// This is the big ugly state machine for processing an input
@@ -406,9 +820,18 @@ impl Input {
         atmos: &mut Atmosphere,
         button: dak::MouseButton,
         state: ButtonState,
+        time: u32,
     ) {
         let cursor = atmos.get_cursor_pos();
 
+        // While locked every click goes to the screen locker
+        if atmos.get_locked() {
+            if let Some(id) = atmos.get_lock_focus() {
+                Self::send_pointer_button(atmos, &id, button, state, time);
+            }
+            return;
+        }
+
         // first check if we are releasing a grab
         if let Some(_id) = atmos.get_grabbed() {
             match state {
@@ -451,8 +874,8 @@ impl Input {
                     }
                 }
             }
-        } else if let Some(id) =
-            atmos.find_window_with_input_at_point(cursor.0 as f32, cursor.1 as f32)
+        } else if let Some((id, on_decoration)) =
+            atmos.find_window_or_decoration_at_point(cursor.0 as f32, cursor.1 as f32)
         {
             // will our click bring a window into focus?
             let mut set_focus = false;
@@ -471,12 +894,20 @@ impl Input {
             // Tell atmos that this is the one in focus
             atmos.focus_on(Some(id.clone()));
 
-            // do this first here so we don't do it more than once
-            let edge = atmos.point_is_on_window_edge(&id, cursor.0 as f32, cursor.1 as f32);
+            // do this first here so we don't do it more than once. Windows
+            // with server side decorations are resized from their border.
+            let edge = match on_decoration {
+                true => atmos.point_is_on_border(&id, cursor.0 as f32, cursor.1 as f32),
+                false => atmos.point_is_on_window_edge(&id, cursor.0 as f32, cursor.1 as f32),
+            };
+            // Tiled windows are placed by the layout, and maximized or
+            // fullscreen ones fill their area, so they can't be grabbed or
+            // resized with the pointer
+            let tiled = atmos.window_is_tiled(&id) || atmos.window_is_maximized_or_fullscreen(&id);
 
             // First check if we are over an edge, or if we are resizing
             // and released the click
-            if edge != ResizeEdge::None {
+            if edge != ResizeEdge::None && !tiled {
                 // if on one of the edges start a resize
                 if let Some(surf_cell) = atmos.get_surface_from_id(&id) {
                     let mut surf = surf_cell.lock().unwrap();
@@ -484,6 +915,7 @@ impl Input {
                         if state == ButtonState::Pressed {
                             log::debug!("Resizing window {:?}", id);
                             atmos.set_resizing(Some(id));
+                            atmos.set_cursor_shape(CursorShape::from_resize_edge(edge));
                             surf.s_state
                                 .cs_xdg_state
                                 .xs_tlstate
@@ -493,75 +925,744 @@ impl Input {
                         }
                     }
                 }
-            } else if atmos.point_is_on_titlebar(&id, cursor.0 as f32, cursor.1 as f32) {
+            } else if !tiled && atmos.point_is_on_titlebar(&id, cursor.0 as f32, cursor.1 as f32) {
                 // now check if we are over the titlebar
                 // if so we will grab the bar
                 match state {
                     ButtonState::Pressed => {
                         log::debug!("Grabbing window {:?}", id);
                         atmos.set_grabbed(Some(id));
+                        atmos.set_cursor_shape(CursorShape::Move);
                     }
                     ButtonState::Released => {
                         log::debug!("Ungrabbing window {:?}", id);
                         atmos.set_grabbed(None);
                     }
                 }
-            } else if !set_focus {
+            } else if !set_focus && !on_decoration {
                 // else the click was over the meat of the window, so
                 // deliver the event to the wayland client
+                Self::send_pointer_button(atmos, &id, button, state, time);
+            }
+        }
+    }
 
-                // get the seat for this client
-                if let Some(cell) = atmos.get_seat_from_surface_id(&id) {
-                    let seat = cell.lock().unwrap();
-                    for si in seat.s_proxies.iter() {
-                        for pointer in si.si_pointers.iter() {
-                            // Trigger a button event
-                            pointer.button(
-                                seat.s_serial,
-                                get_current_millis(),
-                                button.to_linux_button_code(),
-                                match state {
-                                    ButtonState::Pressed => wl_pointer::ButtonState::Pressed,
-                                    ButtonState::Released => wl_pointer::ButtonState::Released,
-                                },
-                            );
-                            Self::send_pointer_frame(pointer);
-                        }
+    /// Send a wl_pointer.button event to the client owning `id`
+    fn send_pointer_button(
+        atmos: &Atmosphere,
+        id: &SurfaceId,
+        button: dak::MouseButton,
+        state: ButtonState,
+        time: u32,
+    ) {
+        // get the seat for this client
+        if let Some(cell) = atmos.get_seat_from_surface_id(id) {
+            let seat = cell.lock().unwrap();
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
+                for pointer in si.si_pointers.iter() {
+                    // Trigger a button event
+                    pointer.button(
+                        seat.s_serial,
+                        time,
+                        button.to_linux_button_code(),
+                        match state {
+                            ButtonState::Pressed => wl_pointer::ButtonState::Pressed,
+                            ButtonState::Released => wl_pointer::ButtonState::Released,
+                        },
+                    );
+                    Self::send_pointer_frame(pointer);
+                }
+            }
+        }
+    }
+
+    /// Send an event to every wl_touch of the client owning `id`
+    ///
+    /// `func` is passed the current seat serial, which is incremented
+    /// afterwards. A wl_touch.frame is sent after each event.
+    fn send_touch_event<F>(atmos: &Atmosphere, id: &SurfaceId, mut func: F)
+    where
+        F: FnMut(u32, &wl_touch::WlTouch),
+    {
+        if let Some(cell) = atmos.get_seat_from_surface_id(id) {
+            let mut seat = cell.lock().unwrap();
+            let serial = seat.s_serial;
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
+                for touch in si.si_touches.iter() {
+                    func(serial, touch);
+                    touch.frame();
+                }
+            }
+            seat.s_serial += 1;
+        }
+    }
+
+    /// A finger touched the touchscreen
+    ///
+    /// Touching a window focuses it, like clicking on it does. The
+    /// wl_touch.down event is delivered to the surface under the finger,
+    /// which will receive all events for this touch point until it is
+    /// lifted.
+    fn handle_touch_down(
+        &mut self,
+        atmos: &mut Atmosphere,
+        touch_id: i32,
+        x: i32,
+        y: i32,
+        time: u32,
+    ) {
+        let id = match atmos.find_window_with_input_at_point(x as f32, y as f32) {
+            Some(id) => id,
+            None => return,
+        };
+        atmos.focus_on(Some(id.clone()));
+
+        let surf = match atmos.get_wl_surface_from_id(&id) {
+            Some(surf) => surf,
+            None => return,
+        };
+        if let Some((sx, sy)) = atmos.global_coords_to_surf(&id, x as f64, y as f64) {
+            Self::send_touch_event(atmos, &id, |serial, touch| {
+                touch.down(serial, time, &surf, touch_id, sx, sy)
+            });
+            self.i_touch_focus.insert(touch_id, id);
+        }
+    }
+
+    /// A finger moved on the touchscreen
+    ///
+    /// Generates the wl_touch.motion event for the surface this touch
+    /// point went down on.
+    fn handle_touch_motion(
+        &mut self,
+        atmos: &mut Atmosphere,
+        touch_id: i32,
+        x: i32,
+        y: i32,
+        time: u32,
+    ) {
+        let id = match self.i_touch_focus.get(&touch_id) {
+            Some(id) => id.clone(),
+            None => return,
+        };
+        // The surface may have been destroyed since the finger went down
+        if atmos.get_wl_surface_from_id(&id).is_none() {
+            return;
+        }
+
+        if let Some((sx, sy)) = atmos.global_coords_to_surf(&id, x as f64, y as f64) {
+            Self::send_touch_event(atmos, &id, |_, touch| touch.motion(time, touch_id, sx, sy));
+        }
+    }
+
+    /// A finger was lifted from the touchscreen
+    fn handle_touch_up(&mut self, atmos: &mut Atmosphere, touch_id: i32, time: u32) {
+        let id = match self.i_touch_focus.remove(&touch_id) {
+            Some(id) => id,
+            None => return,
+        };
+        if atmos.get_wl_surface_from_id(&id).is_none() {
+            return;
+        }
+
+        Self::send_touch_event(atmos, &id, |serial, touch| touch.up(serial, time, touch_id));
+    }
+
+    /// All touch points were cancelled
+    ///
+    /// Every surface with a touch point on it gets wl_touch.cancel.
+    fn handle_touch_cancel(&mut self, atmos: &mut Atmosphere) {
+        let mut surfaces: Vec<SurfaceId> = Vec::new();
+        for (_, id) in self.i_touch_focus.drain() {
+            if !surfaces.contains(&id) {
+                surfaces.push(id);
+            }
+        }
+
+        for id in surfaces.iter() {
+            if atmos.get_wl_surface_from_id(id).is_some() {
+                Self::send_touch_event(atmos, id, |_, touch| touch.cancel());
+            }
+        }
+    }
+
+    /// Run `func` on every seat instance of the client owning `id`
+    ///
+    /// `func` is passed the current seat serial, which is incremented
+    /// afterwards.
+    fn for_each_seat_instance<F>(atmos: &Atmosphere, id: &SurfaceId, mut func: F)
+    where
+        F: FnMut(u32, &SeatInstance),
+    {
+        if let Some(cell) = atmos.get_seat_from_surface_id(id) {
+            let mut seat = cell.lock().unwrap();
+            let serial = seat.s_serial;
+            for si in seat.proxies_for_seat(atmos.get_active_seat()) {
+                func(serial, si);
+            }
+            seat.s_serial += 1;
+        }
+    }
+
+    /// Fingers started swiping on the touchpad
+    ///
+    /// Three finger swipes are used to switch workspaces. Other swipes
+    /// are sent to the surface with pointer focus.
+    fn handle_swipe_begin(&mut self, atmos: &mut Atmosphere, fingers: u32, time: u32) {
+        if fingers == WORKSPACE_SWIPE_FINGERS && !atmos.get_locked() {
+            self.i_gesture = Some(Gesture::WorkspaceSwipe(0.0, 0.0));
+            return;
+        }
+
+        self.i_gesture = None;
+        if let Some(id) = atmos.get_pointer_focus() {
+            if let Some(surf) = atmos.get_wl_surface_from_id(&id) {
+                Self::for_each_seat_instance(atmos, &id, |serial, si| {
+                    for gesture in si.si_swipe_gestures.iter() {
+                        gesture.begin(serial, time, &surf, fingers);
+                    }
+                });
+                self.i_gesture = Some(Gesture::Swipe(id));
+            }
+        }
+    }
+
+    fn handle_swipe_update(&mut self, atmos: &mut Atmosphere, dx: f64, dy: f64, time: u32) {
+        match self.i_gesture.as_mut() {
+            Some(Gesture::WorkspaceSwipe(x, y)) => {
+                *x += dx;
+                *y += dy;
+            }
+            Some(Gesture::Swipe(id)) => Self::for_each_seat_instance(atmos, id, |_, si| {
+                for gesture in si.si_swipe_gestures.iter() {
+                    gesture.update(time, dx, dy);
+                }
+            }),
+            _ => {}
+        }
+    }
+
+    /// The fingers of a swipe were lifted
+    ///
+    /// A workspace swipe moves to the neighboring workspace in the
+    /// direction the fingers moved, if they moved far enough sideways.
+    fn handle_swipe_end(&mut self, atmos: &mut Atmosphere, cancelled: bool, time: u32) {
+        match self.i_gesture.take() {
+            Some(Gesture::WorkspaceSwipe(x, y)) => {
+                if cancelled || x.abs() < WORKSPACE_SWIPE_DISTANCE || x.abs() < y.abs() {
+                    return;
+                }
+                // The desktop follows the fingers, so swiping left
+                // reveals the next workspace
+                let current = atmos.get_current_workspace();
+                let next = match x < 0.0 {
+                    true => current.checked_add(1),
+                    false => current.checked_sub(1),
+                };
+                if let Some(next) = next {
+                    atmos.switch_to_workspace(next);
+                }
+            }
+            Some(Gesture::Swipe(id)) => Self::for_each_seat_instance(atmos, &id, |serial, si| {
+                for gesture in si.si_swipe_gestures.iter() {
+                    gesture.end(serial, time, cancelled as i32);
+                }
+            }),
+            _ => {}
+        }
+    }
+
+    /// Fingers started pinching on the touchpad
+    ///
+    /// Pinches are sent to the surface with pointer focus.
+    fn handle_pinch_begin(&mut self, atmos: &mut Atmosphere, fingers: u32, time: u32) {
+        self.i_gesture = None;
+        if let Some(id) = atmos.get_pointer_focus() {
+            if let Some(surf) = atmos.get_wl_surface_from_id(&id) {
+                Self::for_each_seat_instance(atmos, &id, |serial, si| {
+                    for gesture in si.si_pinch_gestures.iter() {
+                        gesture.begin(serial, time, &surf, fingers);
                     }
+                });
+                self.i_gesture = Some(Gesture::Pinch(id));
+            }
+        }
+    }
+
+    fn handle_pinch_update(
+        &mut self,
+        atmos: &mut Atmosphere,
+        dx: f64,
+        dy: f64,
+        scale: f64,
+        rotation: f64,
+        time: u32,
+    ) {
+        if let Some(Gesture::Pinch(id)) = self.i_gesture.as_ref() {
+            Self::for_each_seat_instance(atmos, id, |_, si| {
+                for gesture in si.si_pinch_gestures.iter() {
+                    gesture.update(time, dx, dy, scale, rotation);
                 }
+            });
+        }
+    }
+
+    fn handle_pinch_end(&mut self, atmos: &mut Atmosphere, cancelled: bool, time: u32) {
+        if let Some(Gesture::Pinch(id)) = self.i_gesture.take() {
+            Self::for_each_seat_instance(atmos, &id, |serial, si| {
+                for gesture in si.si_pinch_gestures.iter() {
+                    gesture.end(serial, time, cancelled as i32);
+                }
+            });
+        }
+    }
+
+    /// Send an event to the tablet tools of this type on every tablet
+    /// seat of the client owning `id`
+    ///
+    /// `func` is passed the current seat serial. A frame is sent after
+    /// each event.
+    fn send_tablet_tool_event<F>(
+        atmos: &Atmosphere,
+        id: &SurfaceId,
+        tool_type: dak::TabletToolType,
+        time: u32,
+        mut func: F,
+    ) where
+        F: FnMut(u32, &TabletSeat, &tablet_tool::ZwpTabletToolV2),
+    {
+        Self::for_each_seat_instance(atmos, id, |serial, si| {
+            for ts in si.si_tablet_seats.iter() {
+                if let Some(tool) = ts.get_tool(tool_type) {
+                    func(serial, ts, tool);
+                    tool.frame(time);
+                }
+            }
+        });
+    }
+
+    /// Move the tablet tool onto the surface at this location
+    ///
+    /// Sends proximity_in and the position of the tool on the surface.
+    fn tablet_tool_enter(
+        &mut self,
+        atmos: &mut Atmosphere,
+        tool_type: dak::TabletToolType,
+        x: i32,
+        y: i32,
+        time: u32,
+    ) {
+        let id = match atmos.find_window_with_input_at_point(x as f32, y as f32) {
+            Some(id) => id,
+            None => return,
+        };
+        let surf = match atmos.get_wl_surface_from_id(&id) {
+            Some(surf) => surf,
+            None => return,
+        };
+        let (sx, sy) = match atmos.global_coords_to_surf(&id, x as f64, y as f64) {
+            Some(pos) => pos,
+            None => return,
+        };
+
+        Self::send_tablet_tool_event(atmos, &id, tool_type, time, |serial, ts, tool| {
+            tool.proximity_in(serial, &ts.ts_tablet, &surf);
+            tool.motion(sx, sy);
+        });
+        self.i_tablet_focus = Some(TabletFocus {
+            tf_tool: tool_type,
+            tf_surface: id,
+            tf_tip_down: false,
+        });
+    }
+
+    /// Move the tablet tool off of the surface it is over
+    fn tablet_tool_leave(&mut self, atmos: &mut Atmosphere, time: u32) {
+        if let Some(focus) = self.i_tablet_focus.take() {
+            if atmos.get_wl_surface_from_id(&focus.tf_surface).is_some() {
+                Self::send_tablet_tool_event(
+                    atmos,
+                    &focus.tf_surface,
+                    focus.tf_tool,
+                    time,
+                    |_, _, tool| tool.proximity_out(),
+                );
+            }
+        }
+    }
+
+    /// A tablet tool came into or left proximity of the tablet
+    fn handle_tablet_tool_proximity(
+        &mut self,
+        atmos: &mut Atmosphere,
+        tool_type: dak::TabletToolType,
+        in_proximity: bool,
+        x: i32,
+        y: i32,
+        time: u32,
+    ) {
+        self.tablet_tool_leave(atmos, time);
+        if in_proximity {
+            self.tablet_tool_enter(atmos, tool_type, x, y, time);
+        }
+    }
+
+    /// A tablet tool moved or its pressure or tilt changed
+    ///
+    /// If the tool moved onto a different surface it leaves the old one,
+    /// unless its tip is down.
+    #[allow(clippy::too_many_arguments)]
+    fn handle_tablet_tool_axis(
+        &mut self,
+        atmos: &mut Atmosphere,
+        tool_type: dak::TabletToolType,
+        x: i32,
+        y: i32,
+        pressure: f64,
+        tilt: (f64, f64),
+        time: u32,
+    ) {
+        let under = atmos.find_window_with_input_at_point(x as f32, y as f32);
+        let moved_surface = match self.i_tablet_focus.as_ref() {
+            Some(focus) => {
+                (!focus.tf_tip_down && Some(&focus.tf_surface) != under.as_ref())
+                    || focus.tf_tool != tool_type
             }
+            None => true,
+        };
+        if moved_surface {
+            self.tablet_tool_leave(atmos, time);
+            self.tablet_tool_enter(atmos, tool_type, x, y, time);
+        }
+
+        let id = match self.i_tablet_focus.as_ref() {
+            Some(focus) => focus.tf_surface.clone(),
+            None => return,
+        };
+        if let Some((sx, sy)) = atmos.global_coords_to_surf(&id, x as f64, y as f64) {
+            Self::send_tablet_tool_event(atmos, &id, tool_type, time, |_, _, tool| {
+                tool.motion(sx, sy);
+                // Pressure is scaled to 0-65535 by the protocol
+                tool.pressure((pressure.clamp(0.0, 1.0) * 65535.0) as u32);
+                tool.tilt(tilt.0, tilt.1);
+            });
         }
     }
 
+    /// The tip of a tablet tool touched or was lifted from the tablet
+    ///
+    /// Touching a window with the tip focuses it, like clicking on it.
+    fn handle_tablet_tool_tip(
+        &mut self,
+        atmos: &mut Atmosphere,
+        tool_type: dak::TabletToolType,
+        down: bool,
+        time: u32,
+    ) {
+        let id = match self.i_tablet_focus.as_mut() {
+            Some(focus) => {
+                focus.tf_tip_down = down;
+                focus.tf_surface.clone()
+            }
+            None => return,
+        };
+        if down {
+            atmos.focus_on(Some(id.clone()));
+        }
+
+        Self::send_tablet_tool_event(atmos, &id, tool_type, time, |serial, _, tool| match down {
+            true => tool.down(serial),
+            false => tool.up(),
+        });
+    }
+
+    /// A button on a tablet tool was pressed or released
+    fn handle_tablet_tool_button(
+        &mut self,
+        atmos: &mut Atmosphere,
+        tool_type: dak::TabletToolType,
+        button: u32,
+        pressed: bool,
+        time: u32,
+    ) {
+        let id = match self.i_tablet_focus.as_ref() {
+            Some(focus) => focus.tf_surface.clone(),
+            None => return,
+        };
+        let state = match pressed {
+            true => tablet_tool::ButtonState::Pressed,
+            false => tablet_tool::ButtonState::Released,
+        };
+
+        Self::send_tablet_tool_event(atmos, &id, tool_type, time, |serial, _, tool| {
+            tool.button(serial, button, state)
+        });
+    }
+
+    /// Ask a toplevel window to close
+    pub fn close_window(atmos: &Atmosphere, id: &SurfaceId) {
+        if let Some(surf) = atmos.get_surface_from_id(id) {
+            if let Some(Role::xdg_shell_toplevel(_, ss)) = &surf.lock().unwrap().s_role {
+                ss.lock().unwrap().send_close();
+            }
+        }
+    }
+
+    /// Get the keybindings from the config, or from the keybindings file
+    fn get_keybindings(config: &Config) -> KeyBindingManager {
+        match config.keybindings.as_ref() {
+            Some(table) => KeyBindingManager::from_table(table),
+            None => KeyBindingManager::new(),
+        }
+    }
+
+    /// Get the switcher order from the config
+    ///
+    /// Invalid orders are logged and the default is used.
+    fn get_switcher_order(config: &Config) -> SwitcherOrder {
+        match config.switcher.get_order() {
+            Ok(order) => order,
+            Err(e) => {
+                log::error!("Invalid switcher config: {:?}", e);
+                SwitcherOrder::mru
+            }
+        }
+    }
+
+    /// Use the keybindings and input device settings from a new config
+    pub fn apply_config(&mut self, config: &Config) {
+        self.i_keybindings = Self::get_keybindings(config);
+        self.i_switcher_order = Self::get_switcher_order(config);
+        self.set_input_config(config.get_input_config());
+        self.set_keyboard_config(&config.keyboard);
+    }
+
+    /// Ask for the config files to be read again
+    ///
+    /// The event loop does this, since the config also affects the
+    /// output and the window manager.
+    pub fn request_config_reload(&mut self) {
+        self.i_config_reload_requested = true;
+    }
+
+    /// Check if the config files should be read again
+    ///
+    /// This clears the request.
+    pub fn take_config_reload_request(&mut self) -> bool {
+        std::mem::take(&mut self.i_config_reload_requested)
+    }
+
+    /// Perform the action for a triggered keybinding
+    ///
+    /// This is also used to run commands sent over IPC.
+    pub fn run_keybinding_action(&mut self, atmos: &mut Atmosphere, action: Action) {
+        log::debug!("Running keybinding action {:?}", action);
+        match action {
+            Action::close_window => {
+                if let Some(id) = atmos.get_root_win_in_focus() {
+                    Self::close_window(atmos, &id);
+                }
+            }
+            Action::focus_next => {
+                // The bottom of the stack is the window that has been out
+                // of focus the longest. Raising it cycles through all windows.
+                let last = atmos.visible_windows().last();
+                if last.is_some() {
+                    atmos.focus_on(last);
+                }
+            }
+            Action::launch(cmd) => {
+                // The user asked for this, so let the new app take focus
+                let token = self.i_activation_tokens.issue(true);
+                let env = [
+                    ("XDG_ACTIVATION_TOKEN", token.as_str()),
+                    ("DESKTOP_STARTUP_ID", token.as_str()),
+                ];
+                if let Err(e) = self.i_children.spawn_shell(&cmd, &env) {
+                    log::error!("Could not launch {}: {:?}", cmd, e);
+                }
+            }
+            Action::toggle_renderdoc => {
+                let recording = atmos.get_renderdoc_recording();
+                atmos.set_renderdoc_recording(!recording);
+            }
+            Action::switch_workspace(workspace) => atmos.switch_to_workspace(workspace),
+            Action::move_to_workspace(workspace) => {
+                if let Some(id) = atmos.get_root_win_in_focus() {
+                    atmos.move_window_to_workspace(&id, workspace);
+                }
+            }
+            Action::set_layout(mode) => {
+                let workspace = atmos.get_current_workspace();
+                atmos.set_workspace_layout(workspace, mode);
+            }
+            Action::cycle_layout => atmos.cycle_current_layout(),
+            Action::focus_next_tile | Action::focus_prev_tile => {
+                if let Some(id) = atmos.get_root_win_in_focus() {
+                    atmos.focus_neighboring_tile(&id, action == Action::focus_next_tile);
+                }
+            }
+            Action::swap_next_tile | Action::swap_prev_tile => {
+                if let Some(id) = atmos.get_root_win_in_focus() {
+                    atmos.swap_neighboring_tile(&id, action == Action::swap_next_tile);
+                }
+            }
+            Action::grow_master => atmos.adjust_master_ratio(true),
+            Action::shrink_master => atmos.adjust_master_ratio(false),
+            Action::toggle_animations => {
+                let enabled = atmos.get_animations_enabled();
+                atmos.set_animations_enabled(!enabled);
+            }
+            Action::toggle_hud => {
+                let enabled = atmos.get_hud_enabled();
+                atmos.set_hud_enabled(!enabled);
+            }
+            Action::reload_config => self.request_config_reload(),
+            Action::next_keyboard_layout => self.next_keyboard_layout(atmos),
+            Action::set_log_filter(spec) => {
+                log::error!("Changing log filter to {}", spec);
+                log::set_log_filter(&spec);
+            }
+            Action::switcher_next | Action::switcher_prev => {
+                atmos.switcher_step(self.i_switcher_order, action == Action::switcher_prev)
+            }
+            Action::toggle_overview => atmos.toggle_overview(),
+            Action::set_opacity(opacity) => {
+                if let Some(id) = atmos.get_root_win_in_focus() {
+                    atmos.set_window_opacity(&id, opacity);
+                }
+            }
+            Action::adjust_opacity(delta) => {
+                if let Some(id) = atmos.get_root_win_in_focus() {
+                    atmos.adjust_window_opacity(&id, delta);
+                }
+            }
+        }
+    }
+
+    /// Check if a key event triggers a compositor keybinding
+    ///
+    /// Returns true if the event was consumed and should not be
+    /// forwarded to the client.
     // TODO: add gesture recognition
     fn handle_compositor_shortcut(
         &mut self,
         atmos: &mut Atmosphere,
-        key: dak::Keycode,
+        key: u32,
         state: ButtonState,
     ) -> bool {
-        // TODO: keysyms::KEY_Meta_L doesn't work? should be 125 for left meta
-        if key == dak::Keycode::LMETA && state == ButtonState::Pressed {
-            match atmos.get_renderdoc_recording() {
-                true => atmos.set_renderdoc_recording(false),
-                false => atmos.set_renderdoc_recording(true),
-            }
+        if state == ButtonState::Released {
+            return self.i_keybindings.handle_release(key);
+        }
+        // Keys can't be used to get around the screen locker
+        if atmos.get_locked() {
+            return false;
+        }
+
+        // Match against the unmodified symbol for this key, so that
+        // holding shift doesn't change which binding is triggered
+        let keycode = key + 8;
+        let layout = self.i_xkb_state.key_get_layout(keycode);
+        let keysym = match self
+            .i_xkb_keymap
+            .key_get_syms_by_level(keycode, layout, 0)
+            .first()
+        {
+            Some(sym) => *sym,
+            None => return false,
+        };
+
+        // Escape closes the switcher without switching windows
+        if atmos.is_switcher_open() && keysym == xkb::keysyms::KEY_Escape {
+            atmos.switcher_cancel();
+            self.i_keybindings.consume(key);
             return true;
         }
-        return false;
+        if atmos.is_overview_open() && Self::handle_overview_key(atmos, keysym) {
+            self.i_keybindings.consume(key);
+            return true;
+        }
+
+        let mods = BindingMods {
+            bm_ctrl: self.i_mod_ctrl,
+            bm_alt: self.i_mod_alt,
+            bm_shift: self.i_mod_shift,
+            bm_meta: self.i_mod_meta,
+        };
+
+        match self.i_keybindings.handle_press(mods, keysym, key) {
+            Some(action) => {
+                self.run_keybinding_action(atmos, action);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pick a window in the overview with the keyboard
+    ///
+    /// The arrow keys move through the grid, Return focuses the selected
+    /// window and Escape leaves the focus where it was. Returns true if
+    /// the key was one of these.
+    fn handle_overview_key(atmos: &mut Atmosphere, keysym: xkb::Keysym) -> bool {
+        match keysym {
+            xkb::keysyms::KEY_Left => atmos.overview_move_selection(-1, 0),
+            xkb::keysyms::KEY_Right => atmos.overview_move_selection(1, 0),
+            xkb::keysyms::KEY_Up => atmos.overview_move_selection(0, -1),
+            xkb::keysyms::KEY_Down => atmos.overview_move_selection(0, 1),
+            xkb::keysyms::KEY_Return | xkb::keysyms::KEY_KP_Enter => atmos.overview_finish(),
+            xkb::keysyms::KEY_Escape => atmos.overview_cancel(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handle pointer and touch input while the overview is open
+    ///
+    /// Clients don't see any of this input, instead it is hit-tested
+    /// against the windows in the overview's grid. Hovering over a window
+    /// selects it and clicking on one focuses it, while clicking on the
+    /// background closes the overview. Returns true if the event was
+    /// consumed.
+    fn handle_overview_event(atmos: &mut Atmosphere, ev: &dak::PlatformEvent) -> bool {
+        match ev {
+            dak::PlatformEvent::InputMouseMove { dx, dy, .. } => {
+                atmos.add_cursor_pos(*dx as f64, *dy as f64);
+                atmos.overview_select_at_cursor();
+            }
+            dak::PlatformEvent::InputMouseButtonDown { .. } => {
+                let (cx, cy) = atmos.get_cursor_pos();
+                match atmos.overview_window_at_point(cx as f32, cy as f32) {
+                    Some(index) => {
+                        atmos.overview_select(index);
+                        atmos.overview_finish();
+                    }
+                    None => atmos.overview_cancel(),
+                }
+            }
+            dak::PlatformEvent::InputTouchDown { x, y, .. } => {
+                match atmos.overview_window_at_point(*x as f32, *y as f32) {
+                    Some(index) => {
+                        atmos.overview_select(index);
+                        atmos.overview_finish();
+                    }
+                    None => atmos.overview_cancel(),
+                }
+            }
+            dak::PlatformEvent::InputMouseButtonUp { .. }
+            | dak::PlatformEvent::InputScroll { .. }
+            | dak::PlatformEvent::InputTouchMotion { .. }
+            | dak::PlatformEvent::InputTouchUp { .. } => {}
+            _ => return false,
+        }
+        true
     }
 
     /// Handle the user typing on the keyboard.
     ///
     /// Deliver the wl_keyboard.key and modifier events.
-    fn handle_keyboard(
-        &mut self,
-        atmos: &mut Atmosphere,
-        dakota_key: dak::Keycode,
-        key: u32,
-        state: ButtonState,
-    ) {
-        if self.handle_compositor_shortcut(atmos, dakota_key, state) {
+    fn handle_keyboard(&mut self, atmos: &mut Atmosphere, key: u32, state: ButtonState, time: u32) {
+        if self.handle_compositor_shortcut(atmos, key, state) {
             return;
         }
 
@@ -580,43 +1681,26 @@ impl Input {
         // if any modifiers were touched we should send their event
         let mods = if changed != 0 {
             // First we need to update our own tracking of what keys are held down
-            self.i_mod_ctrl = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_CTRL, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_alt = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_ALT, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_shift = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_SHIFT, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_caps = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_CAPS, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_meta = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_LOGO, xkb::STATE_MODS_EFFECTIVE);
-            self.i_mod_num = self
-                .i_xkb_state
-                .mod_name_is_active(&xkb::MOD_NAME_NUM, xkb::STATE_MODS_EFFECTIVE);
+            self.update_mod_tracking();
 
             // Now we can serialize the modifiers into a format suitable
             // for sending to the client
-            let depressed = self.i_xkb_state.serialize_mods(xkb::STATE_MODS_DEPRESSED);
-            let latched = self.i_xkb_state.serialize_mods(xkb::STATE_MODS_LATCHED);
-            let locked = self.i_xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED);
-            let layout = self.i_xkb_state.serialize_layout(xkb::STATE_LAYOUT_LOCKED);
-
-            Some((depressed, latched, locked, layout))
+            Some(self.serialize_mods())
         } else {
             None
         };
 
+        // Keys typed while the overview is open don't go to clients
+        if atmos.is_overview_open() {
+            return;
+        }
+
         // if there is a window in focus
         if let Some(id) = atmos.get_client_in_focus() {
             // get the seat for this client
             if let Some(cell) = atmos.get_seat_from_client_id(&id) {
                 let mut seat = cell.lock().unwrap();
-                for si in seat.s_proxies.iter() {
+                for si in seat.proxies_for_seat(atmos.get_active_seat()) {
                     for keyboard in si.si_keyboards.iter() {
                         if let Some((depressed, latched, locked, layout)) = mods {
                             // Finally fire the wayland event
@@ -625,7 +1709,6 @@ impl Input {
                         }
 
                         // give the keycode to the client
-                        let time = get_current_millis();
                         let state = map_key_state(state);
                         log::debug!("Sending key {} to window {:?}", key, id);
                         keyboard.key(seat.s_serial, time, key, state);
@@ -637,6 +1720,33 @@ impl Input {
         }
         // otherwise the click is over the background, so
         // ignore it
+
+        // The switcher is open while the modifiers of its keybinding are
+        // held, releasing them switches to the selected window. This is
+        // done after the release is sent so the old window sees it.
+        if atmos.is_switcher_open() && !(self.i_mod_alt || self.i_mod_ctrl || self.i_mod_meta) {
+            atmos.switcher_finish();
+        }
+    }
+
+    /// Switch to the keyboard and focus state of another seat
+    ///
+    /// Events are only delivered to the wl_seats of the active seat.
+    fn make_seat_active(&mut self, atmos: &mut Atmosphere, seat: usize) {
+        if seat == self.i_active_seat {
+            return;
+        }
+
+        let state = self
+            .i_seat_xkb_states
+            .remove(&seat)
+            .unwrap_or_else(|| xkb::State::new(&self.i_xkb_keymap));
+        let old = std::mem::replace(&mut self.i_xkb_state, state);
+        self.i_seat_xkb_states.insert(self.i_active_seat, old);
+        self.i_active_seat = seat;
+        self.update_mod_tracking();
+
+        atmos.make_seat_active(seat);
     }
 
     /// Dispatch an arbitrary input event
@@ -644,43 +1754,172 @@ impl Input {
     /// Input events are either handled by us or by the wayland client
     /// we need to figure out the appropriate destination and perform
     /// the right action.
+    ///
+    /// The state of the seat the event came from is used while handling
+    /// it, and afterwards the default seat is made active again.
     pub fn handle_input_event(&mut self, atmos: &mut Atmosphere, ev: &dak::PlatformEvent) {
+        if let dak::PlatformEvent::InputSeat { seat } = ev {
+            // Devices on seats that aren't in our config act like they
+            // are on the default seat
+            self.i_event_seat = atmos.get_seat_index(seat).unwrap_or_else(|| {
+                log::debug!("Input from unknown seat {}, using the default seat", seat);
+                0
+            });
+            return;
+        }
+
+        // Any input means the user is not idle
+        self.reset_idle_time();
+
+        self.make_seat_active(atmos, self.i_event_seat);
+        self.dispatch_input_event(atmos, ev);
+        self.make_seat_active(atmos, 0);
+    }
+
+    fn dispatch_input_event(&mut self, atmos: &mut Atmosphere, ev: &dak::PlatformEvent) {
+        if atmos.is_overview_open() && Self::handle_overview_event(atmos, ev) {
+            return;
+        }
+
         match ev {
-            dak::PlatformEvent::InputMouseMove { dx, dy } => {
-                self.handle_pointer_move(atmos, *dx, *dy)
+            dak::PlatformEvent::InputMouseMove { dx, dy, time } => {
+                self.handle_pointer_move(atmos, *dx, *dy, event_time_to_millis(*time))
             }
             dak::PlatformEvent::InputScroll {
                 xrel,
                 yrel,
                 v120_val,
                 source,
+                time,
                 ..
-            } => self.handle_pointer_axis(atmos, *xrel, *yrel, *v120_val, *source),
-            dak::PlatformEvent::InputMouseButtonUp { button, .. } => {
-                self.handle_click_on_window(atmos, *button, ButtonState::Released)
-            }
-            dak::PlatformEvent::InputMouseButtonDown { button, .. } => {
-                self.handle_click_on_window(atmos, *button, ButtonState::Pressed)
-            }
+            } => self.handle_pointer_axis(
+                atmos,
+                *xrel,
+                *yrel,
+                *v120_val,
+                *source,
+                event_time_to_millis(*time),
+            ),
+            dak::PlatformEvent::InputMouseButtonUp { button, time, .. } => self
+                .handle_click_on_window(
+                    atmos,
+                    *button,
+                    ButtonState::Released,
+                    event_time_to_millis(*time),
+                ),
+            dak::PlatformEvent::InputMouseButtonDown { button, time, .. } => self
+                .handle_click_on_window(
+                    atmos,
+                    *button,
+                    ButtonState::Pressed,
+                    event_time_to_millis(*time),
+                ),
             dak::PlatformEvent::InputKeyUp {
-                key, raw_keycode, ..
+                raw_keycode, time, ..
             } => self.handle_keyboard(
                 atmos,
-                *key,
                 match raw_keycode {
                     dak::RawKeycode::Linux(k) => *k,
                 },
                 ButtonState::Released,
+                event_time_to_millis(*time),
             ),
             dak::PlatformEvent::InputKeyDown {
-                key, raw_keycode, ..
+                raw_keycode, time, ..
             } => self.handle_keyboard(
                 atmos,
-                *key,
                 match raw_keycode {
                     dak::RawKeycode::Linux(k) => *k,
                 },
                 ButtonState::Pressed,
+                event_time_to_millis(*time),
+            ),
+            dak::PlatformEvent::InputTouchDown { id, x, y, time } => {
+                self.handle_touch_down(atmos, *id, *x, *y, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputTouchMotion { id, x, y, time } => {
+                self.handle_touch_motion(atmos, *id, *x, *y, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputTouchUp { id, time, .. } => {
+                self.handle_touch_up(atmos, *id, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputTouchCancel { .. } => self.handle_touch_cancel(atmos),
+            dak::PlatformEvent::InputSwipeBegin { fingers, time } => {
+                self.handle_swipe_begin(atmos, *fingers, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputSwipeUpdate { dx, dy, time } => {
+                self.handle_swipe_update(atmos, *dx, *dy, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputSwipeEnd { cancelled, time } => {
+                self.handle_swipe_end(atmos, *cancelled, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputPinchBegin { fingers, time } => {
+                self.handle_pinch_begin(atmos, *fingers, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputPinchUpdate {
+                dx,
+                dy,
+                scale,
+                rotation,
+                time,
+            } => self.handle_pinch_update(
+                atmos,
+                *dx,
+                *dy,
+                *scale,
+                *rotation,
+                event_time_to_millis(*time),
+            ),
+            dak::PlatformEvent::InputPinchEnd { cancelled, time } => {
+                self.handle_pinch_end(atmos, *cancelled, event_time_to_millis(*time))
+            }
+            dak::PlatformEvent::InputTabletToolProximity {
+                tool,
+                in_proximity,
+                x,
+                y,
+                time,
+                ..
+            } => self.handle_tablet_tool_proximity(
+                atmos,
+                *tool,
+                *in_proximity,
+                *x,
+                *y,
+                event_time_to_millis(*time),
+            ),
+            dak::PlatformEvent::InputTabletToolAxis {
+                tool,
+                x,
+                y,
+                pressure,
+                tilt,
+                time,
+                ..
+            } => self.handle_tablet_tool_axis(
+                atmos,
+                *tool,
+                *x,
+                *y,
+                *pressure,
+                *tilt,
+                event_time_to_millis(*time),
+            ),
+            dak::PlatformEvent::InputTabletToolTip {
+                tool, down, time, ..
+            } => self.handle_tablet_tool_tip(atmos, *tool, *down, event_time_to_millis(*time)),
+            dak::PlatformEvent::InputTabletToolButton {
+                tool,
+                button,
+                pressed,
+                time,
+                ..
+            } => self.handle_tablet_tool_button(
+                atmos,
+                *tool,
+                *button,
+                *pressed,
+                event_time_to_millis(*time),
             ),
             _ => (),
         };
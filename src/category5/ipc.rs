@@ -0,0 +1,449 @@
+// IPC control socket
+//
+// Scripts, bars, and the cat5ctl tool control the compositor through a
+// unix socket. Each connection sends one JSON request terminated by a
+// newline, and gets one JSON response back before the socket is closed.
+// Requests can query the windows, workspaces, outputs and the resources
+// each client has created, act on a window, reload the config files, or
+// run any keybinding action:
+//
+//   {"type": "get_windows"}
+//   {"type": "move_window", "id": 3, "workspace": 2}
+//   {"type": "set_window_opacity", "id": 3, "opacity": 0.9}
+//   {"type": "command", "command": "set_layout master_stack"}
+//
+// Responses always have a "success" field, and an "error" message if it
+// is false. Workspaces are numbered from one, as in the keybindings file.
+//
+// The socket path is exported to programs we launch in CATEGORY5_SOCK.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate serde;
+extern crate serde_json;
+
+use crate::category5::atmosphere::{Atmosphere, SurfaceId, NUM_WORKSPACES};
+use crate::category5::input::keybindings::Action;
+use crate::category5::input::Input;
+use crate::category5::Climate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utils::log;
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Connections are closed if they don't send a request within this time
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The largest request we will accept
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// A request sent by an IPC client
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum IpcRequest {
+    /// List all toplevel windows
+    get_windows,
+    /// List the workspaces and their layouts
+    get_workspaces,
+    /// Describe the display we are drawing on
+    get_outputs,
+    /// Count the surfaces, buffers and callbacks each client has alive
+    get_clients,
+    /// Focus a window, switching to its workspace
+    focus_window { id: usize },
+    /// Ask a window to close
+    close_window { id: usize },
+    /// Move a window to another workspace and/or desktop position
+    move_window {
+        id: usize,
+        workspace: Option<u32>,
+        position: Option<(f32, f32)>,
+    },
+    /// Set the opacity of a window and the other windows of its app
+    set_window_opacity { id: usize, opacity: f32 },
+    /// Re-read the config files
+    reload_config,
+    /// Run a keybinding action, i.e. `toggle_animations`
+    command { command: String },
+}
+
+#[derive(Serialize)]
+struct WindowInfo {
+    id: usize,
+    title: Option<String>,
+    app_id: Option<String>,
+    workspace: u32,
+    position: (f32, f32),
+    size: (f32, f32),
+    focused: bool,
+    tiled: bool,
+    maximized: bool,
+    fullscreen: bool,
+    minimized: bool,
+    opacity: f32,
+}
+
+#[derive(Serialize)]
+struct WorkspaceInfo {
+    num: u32,
+    active: bool,
+    layout: String,
+    window_count: usize,
+}
+
+#[derive(Serialize)]
+struct OutputInfo {
+    width: u32,
+    height: u32,
+    powered: bool,
+    frame_count: u64,
+}
+
+/// A client which has not sent its request yet
+struct IpcConnection {
+    ic_stream: UnixStream,
+    ic_buf: Vec<u8>,
+    ic_opened: Instant,
+}
+
+impl IpcConnection {
+    /// Read what the client has sent
+    ///
+    /// Returns the request once it is complete, or an error if the
+    /// connection should be closed.
+    fn read_request(&mut self) -> std::io::Result<Option<String>> {
+        let mut buf = [0u8; 4096];
+        let mut eof = false;
+        loop {
+            match self.ic_stream.read(&mut buf) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                }
+                Ok(len) => self.ic_buf.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if self.ic_buf.contains(&b'\n') || self.ic_buf.len() > MAX_REQUEST_SIZE {
+                break;
+            }
+        }
+
+        match self.ic_buf.iter().position(|b| *b == b'\n') {
+            Some(end) => Ok(Some(
+                String::from_utf8_lossy(&self.ic_buf[..end]).to_string(),
+            )),
+            // The client is done writing, use whatever it sent
+            None if eof && !self.ic_buf.is_empty() => {
+                Ok(Some(String::from_utf8_lossy(&self.ic_buf).to_string()))
+            }
+            None if eof => Err(ErrorKind::UnexpectedEof.into()),
+            None if self.ic_buf.len() > MAX_REQUEST_SIZE => Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "IPC request is too large",
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Send our response and close the connection
+    fn reply(mut self, response: &Value) {
+        // Large responses may not fit in the socket buffer, but don't let
+        // a client that stopped reading block us for long
+        let _ = self.ic_stream.set_nonblocking(false);
+        let _ = self
+            .ic_stream
+            .set_write_timeout(Some(Duration::from_millis(100)));
+        if let Err(e) = writeln!(self.ic_stream, "{}", response) {
+            log::debug!("Could not send IPC response: {:?}", e);
+        }
+    }
+}
+
+/// The listening IPC socket and its clients
+pub struct IpcServer {
+    is_listener: UnixListener,
+    is_path: PathBuf,
+    is_connections: Vec<IpcConnection>,
+}
+
+impl IpcServer {
+    /// Create the IPC socket
+    ///
+    /// The socket is placed next to the wayland socket in
+    /// XDG_RUNTIME_DIR. Returns None if it could not be created.
+    pub fn new(wayland_display: &str) -> Option<Self> {
+        let dir = match std::env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => {
+                log::error!("XDG_RUNTIME_DIR is not set, not creating IPC socket");
+                return None;
+            }
+        };
+        let path = dir.join(format!("category5-ipc.{}.sock", wayland_display));
+
+        // Clean up after a previous instance that did not exit cleanly
+        let _ = std::fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("Could not create IPC socket {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        listener
+            .set_nonblocking(true)
+            .expect("Could not make IPC socket nonblocking");
+
+        std::env::set_var("CATEGORY5_SOCK", &path);
+
+        Some(Self {
+            is_listener: listener,
+            is_path: path,
+            is_connections: Vec::new(),
+        })
+    }
+
+    /// The fd to watch for new connections
+    pub fn get_fd(&self) -> RawFd {
+        self.is_listener.as_raw_fd()
+    }
+
+    /// Get how long until the oldest client that hasn't sent its
+    /// request should be disconnected
+    pub fn get_next_timeout(&self) -> Option<Duration> {
+        self.is_connections
+            .iter()
+            .map(|conn| REQUEST_TIMEOUT.saturating_sub(conn.ic_opened.elapsed()))
+            .min()
+    }
+
+    /// Accept new clients and collect any complete requests
+    ///
+    /// Connections are watched by `dakota` until their request has been
+    /// read, so that the event loop wakes up when they send something.
+    fn take_requests(&mut self, dakota: &mut dak::Dakota) -> Vec<(IpcConnection, String)> {
+        loop {
+            match self.is_listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        log::error!("Could not make IPC connection nonblocking: {:?}", e);
+                        continue;
+                    }
+                    dakota.add_watch_fd(stream.as_raw_fd());
+                    self.is_connections.push(IpcConnection {
+                        ic_stream: stream,
+                        ic_buf: Vec::new(),
+                        ic_opened: Instant::now(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("Could not accept IPC connection: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut ret = Vec::new();
+        for mut conn in std::mem::take(&mut self.is_connections) {
+            let request = conn.read_request();
+            if let Ok(None) = request {
+                if conn.ic_opened.elapsed() < REQUEST_TIMEOUT {
+                    self.is_connections.push(conn);
+                    continue;
+                }
+            }
+
+            // Stop watching the connection before it is closed
+            dakota.remove_watch_fd(conn.ic_stream.as_raw_fd());
+            match request {
+                Ok(Some(request)) => ret.push((conn, request)),
+                Ok(None) => log::debug!("Closing IPC connection that sent no request"),
+                Err(e) => log::debug!("Closing IPC connection: {:?}", e),
+            }
+        }
+        ret
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.is_path);
+    }
+}
+
+/// Look up a toplevel window by the id we report to clients
+fn find_window(atmos: &Atmosphere, id: usize) -> Option<SurfaceId> {
+    (0..NUM_WORKSPACES)
+        .flat_map(|ws| atmos.windows_on_workspace(ws))
+        .find(|win| win.get_raw_id() == id)
+}
+
+/// Parse a workspace numbered from one
+fn workspace_from_user(workspace: u32) -> Result<u32, String> {
+    if (1..=NUM_WORKSPACES).contains(&workspace) {
+        Ok(workspace - 1)
+    } else {
+        Err(format!(
+            "Workspace must be between 1 and {}",
+            NUM_WORKSPACES
+        ))
+    }
+}
+
+impl Climate {
+    fn get_window_info(atmos: &Atmosphere, id: &SurfaceId, workspace: u32) -> WindowInfo {
+        let (title, app_id, maximized, fullscreen) = match atmos.get_surface_from_id(id) {
+            Some(surf) => {
+                let surf = surf.lock().unwrap();
+                match surf.s_state.cs_xdg_state.xs_tlstate.as_ref() {
+                    Some(tl) => (
+                        tl.tl_title.clone(),
+                        tl.tl_app_id.clone(),
+                        tl.tl_maximized,
+                        tl.tl_fullscreen,
+                    ),
+                    None => (None, None, false, false),
+                }
+            }
+            None => (None, None, false, false),
+        };
+
+        WindowInfo {
+            id: id.get_raw_id(),
+            title,
+            app_id,
+            workspace: workspace + 1,
+            position: atmos.a_window_pos.get_clone(id).unwrap_or((0.0, 0.0)),
+            size: atmos.a_window_size.get_clone(id).unwrap_or((0.0, 0.0)),
+            focused: atmos.get_keyboard_focus().as_ref() == Some(id),
+            tiled: atmos.window_is_tiled(id),
+            maximized,
+            fullscreen,
+            minimized: atmos.window_is_minimized(id),
+            opacity: atmos.get_window_opacity(id),
+        }
+    }
+
+    /// Perform an IPC request
+    ///
+    /// Returns the data to include in the response.
+    fn handle_ipc_request(&mut self, request: IpcRequest) -> Result<Value, String> {
+        log::debug!("Handling IPC request {:?}", request);
+        let mut atmos = self.c_atmos.lock().unwrap();
+
+        match request {
+            IpcRequest::get_windows => {
+                let mut windows = Vec::new();
+                for ws in 0..NUM_WORKSPACES {
+                    for id in atmos.windows_on_workspace(ws) {
+                        windows.push(Self::get_window_info(&atmos, &id, ws));
+                    }
+                }
+                Ok(json!({ "windows": windows }))
+            }
+            IpcRequest::get_workspaces => {
+                let current = atmos.get_current_workspace();
+                let workspaces: Vec<WorkspaceInfo> = (0..NUM_WORKSPACES)
+                    .map(|ws| WorkspaceInfo {
+                        num: ws + 1,
+                        active: ws == current,
+                        layout: format!("{:?}", atmos.get_workspace_layout(ws)),
+                        window_count: atmos.windows_on_workspace(ws).count(),
+                    })
+                    .collect();
+                Ok(json!({ "workspaces": workspaces }))
+            }
+            IpcRequest::get_outputs => {
+                let res = self.c_output.get_resolution();
+                let outputs = [OutputInfo {
+                    width: res.0,
+                    height: res.1,
+                    powered: self.c_output.is_powered(),
+                    frame_count: self.c_output.get_frame_count(),
+                }];
+                Ok(json!({ "outputs": outputs }))
+            }
+            IpcRequest::get_clients => Ok(json!({ "clients": self.c_resources.get_clients() })),
+            IpcRequest::focus_window { id } => {
+                let win = find_window(&atmos, id).ok_or("No window with that id")?;
+                if atmos.get_locked() {
+                    return Err("The session is locked".to_string());
+                }
+                if let Some(ws) = atmos.get_window_workspace(&win) {
+                    atmos.switch_to_workspace(ws);
+                }
+                atmos.focus_on(Some(win));
+                Ok(json!({}))
+            }
+            IpcRequest::close_window { id } => {
+                let win = find_window(&atmos, id).ok_or("No window with that id")?;
+                Input::close_window(&atmos, &win);
+                Ok(json!({}))
+            }
+            IpcRequest::move_window {
+                id,
+                workspace,
+                position,
+            } => {
+                let win = find_window(&atmos, id).ok_or("No window with that id")?;
+                if let Some(ws) = workspace {
+                    atmos.move_window_to_workspace(&win, workspace_from_user(ws)?);
+                }
+                if let Some(pos) = position {
+                    // Tiled windows are placed by the layout
+                    if atmos.window_is_tiled(&win) {
+                        return Err("Tiled windows can not be moved".to_string());
+                    }
+                    if atmos.window_is_maximized_or_fullscreen(&win) {
+                        return Err("Maximized and fullscreen windows can not be moved".to_string());
+                    }
+                    atmos.move_window_to(&win, pos);
+                }
+                Ok(json!({}))
+            }
+            IpcRequest::set_window_opacity { id, opacity } => {
+                let win = find_window(&atmos, id).ok_or("No window with that id")?;
+                if !(0.0..=1.0).contains(&opacity) {
+                    return Err("Opacity must be between 0.0 and 1.0".to_string());
+                }
+                atmos.set_window_opacity(&win, opacity);
+                Ok(json!({}))
+            }
+            IpcRequest::reload_config => {
+                self.c_input.request_config_reload();
+                Ok(json!({}))
+            }
+            IpcRequest::command { command } => {
+                let action = Action::parse(&command).map_err(|e| e.to_string())?;
+                self.c_input.run_keybinding_action(&mut atmos, action);
+                Ok(json!({}))
+            }
+        }
+    }
+
+    /// Answer any requests sent to the IPC socket
+    pub fn handle_ipc_requests(&mut self, server: &mut IpcServer) {
+        for (conn, request) in server.take_requests(&mut self.c_dakota) {
+            let result = serde_json::from_str::<IpcRequest>(&request)
+                .map_err(|e| format!("Invalid request: {}", e))
+                .and_then(|req| self.handle_ipc_request(req));
+
+            let response = match result {
+                Ok(mut data) => {
+                    data["success"] = json!(true);
+                    data
+                }
+                Err(e) => json!({ "success": false, "error": e }),
+            };
+            conn.reply(&response);
+        }
+    }
+}
@@ -7,26 +7,57 @@ extern crate wayland_protocols;
 extern crate wayland_server as ws;
 
 mod atmosphere;
+mod children;
+mod config;
 mod input;
+mod ipc;
+mod theme;
 mod vkcomp;
 mod ways;
 
 use crate::category5::input::Input;
 use atmosphere::{Atmosphere, ClientId};
-use cat5_utils::{log, Result};
+use cat5_utils::{log, trace, Result};
+use config::{Config, ConfigWatcher};
+use ipc::IpcServer;
 use vkcomp::wm::*;
 
+use wayland_protocols::ext::idle_notify::v1::server::ext_idle_notifier_v1;
+use wayland_protocols::ext::session_lock::v1::server::ext_session_lock_manager_v1;
+use wayland_protocols::wp::content_type::v1::server::wp_content_type_manager_v1;
+use wayland_protocols::wp::idle_inhibit::zv1::server::zwp_idle_inhibit_manager_v1 as ziimv1;
 use wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_v1 as zldv1;
+use wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gestures_v1 as zpgv1;
+use wayland_protocols::wp::presentation_time::server::wp_presentation;
+use wayland_protocols::wp::single_pixel_buffer::v1::server::wp_single_pixel_buffer_manager_v1;
+use wayland_protocols::wp::tablet::zv2::server::zwp_tablet_manager_v2 as ztmv2;
+use wayland_protocols::wp::tearing_control::v1::server::wp_tearing_control_manager_v1;
+use wayland_protocols::wp::viewporter::server::wp_viewporter;
+use wayland_protocols::xdg::activation::v1::server::xdg_activation_v1;
+use wayland_protocols::xdg::decoration::zv1::server::zxdg_decoration_manager_v1 as zxdmv1;
 use wayland_protocols::xdg::shell::server::*;
+use ways::accounting::ResourceAccounting;
+use ways::export_dmabuf::EXPORT_POLL_MS;
+use ways::ext_workspace::ExtWorkspaceState;
+use ways::idle::IdleState;
+use ways::presentation::PresentationState;
+use ways::protocol::export_dmabuf::{zwlr_export_dmabuf_frame_v1, zwlr_export_dmabuf_manager_v1};
+use ways::protocol::ext_workspace::ext_workspace_manager_v1;
+use ways::protocol::gamma_control::{zwlr_gamma_control_manager_v1, zwlr_gamma_control_v1};
+use ways::protocol::screencopy::zwlr_screencopy_manager_v1;
 use ways::protocol::wl_drm::wl_drm;
+use ways::screencopy::{PendingScreencopy, ScreencopyDamage};
+use ways::security::{Privilege, SecurityPolicy};
+use ways::session_lock::SessionLockState;
 use ws::protocol::{
     wl_compositor as wlci, wl_data_device_manager as wlddm, wl_output, wl_seat, wl_shell, wl_shm,
     wl_subcompositor,
 };
 
 use std::ops::DerefMut;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 // The category5 compositor
 #[allow(dead_code)]
@@ -82,11 +113,47 @@ pub struct Climate {
     c_outputs: Vec<wl_output::WlOutput>,
     /// The input subsystem
     c_input: Input,
+    /// Workspace managers bound by panels
+    c_ext_workspace: ExtWorkspaceState,
+    /// The gamma control currently adjusting our output, if any
+    c_gamma_control: Option<zwlr_gamma_control_v1::ZwlrGammaControlV1>,
+    /// Screen recorders waiting for the next frame to be drawn
+    c_export_frames: Vec<zwlr_export_dmabuf_frame_v1::ZwlrExportDmabufFrameV1>,
+    /// Screen recorders sent a frame the GPU is still copying, and the
+    /// timeline point to wait on before it is ready
+    c_export_pending: Vec<(zwlr_export_dmabuf_frame_v1::ZwlrExportDmabufFrameV1, u64)>,
+    /// Screencopy clients waiting for the next frame to be drawn
+    c_screencopy_frames: Vec<PendingScreencopy>,
+    /// What changed in recent frames, reported to screencopy clients
+    c_screencopy_damage: ScreencopyDamage,
+    /// Presentation feedback waiting for frames to be shown
+    c_presentation: PresentationState,
+    /// Idle notifications and inhibitors
+    c_idle: IdleState,
+    /// The screen locker, if the session is locked
+    c_session_lock: SessionLockState,
+    /// Which clients may use privileged protocols
+    c_security: SecurityPolicy,
+    /// How many resources each client has created
+    c_resources: ResourceAccounting,
+    /// The user's config file
+    c_config: Config,
 }
 
 impl Climate {
     fn new() -> Self {
+        let config = Config::load().unwrap_or_else(|e| {
+            log::error!("Invalid config file, using the defaults: {:?}", e);
+            Config::default()
+        });
+
         let mut dakota = dak::Dakota::new().expect("Could not create dakota instance");
+        // Clients repeat held keys themselves using the settings from
+        // wl_keyboard.repeat_info, so Dakota shouldn't repeat them too
+        dakota.set_key_repeat(&dak::KeyRepeatConfig { delay: 0, rate: 0 });
+        if let Some(samples) = config.output.msaa_samples {
+            dakota.set_sample_count(samples);
+        }
 
         let mut virtual_output = dakota
             .create_virtual_output()
@@ -109,7 +176,121 @@ impl Climate {
             c_output: output,
             c_scene: scene,
             c_outputs: Vec::new(),
-            c_input: Input::new(),
+            c_input: Input::new(&config),
+            c_ext_workspace: ExtWorkspaceState::new(),
+            c_gamma_control: None,
+            c_export_frames: Vec::new(),
+            c_export_pending: Vec::new(),
+            c_screencopy_frames: Vec::new(),
+            c_screencopy_damage: ScreencopyDamage::default(),
+            c_presentation: PresentationState::new(),
+            c_idle: IdleState::new(),
+            c_session_lock: SessionLockState::new(),
+            c_security: SecurityPolicy::load(),
+            c_resources: ResourceAccounting::new(),
+            c_config: config,
+        }
+    }
+
+    /// Advertise a wl_seat for each seat named in the config
+    ///
+    /// Seats are never removed, since clients may still be using them.
+    /// Devices assigned to a seat that is no longer in the config are
+    /// moved back to the default seat by Dakota.
+    fn create_seat_globals(&mut self, display: &ws::DisplayHandle) {
+        let mut atmos = self.c_atmos.lock().unwrap();
+        for name in self.c_config.get_seat_names() {
+            if atmos.get_seat_index(&name).is_none() {
+                let index = atmos.add_seat_state(&name);
+                display.create_global::<Climate, wl_seat::WlSeat, usize>(8, index);
+            }
+        }
+    }
+
+    /// Apply the output section of the config to our display
+    ///
+    /// The color profile may also come from CATEGORY5_COLOR_PROFILE.
+    fn apply_output_config(&mut self) {
+        let config = &self.c_config.output;
+
+        let profile = config
+            .color_profile
+            .clone()
+            .or(std::env::var_os("CATEGORY5_COLOR_PROFILE").map(std::path::PathBuf::from));
+        if let Err(e) = self.c_output.set_color_profile(profile.as_deref()) {
+            log::error!("Could not set color profile: {:?}", e);
+        }
+
+        match config.get_subpixel_layout() {
+            Ok(Some(layout)) => self.c_output.set_subpixel_layout(&mut self.c_scene, layout),
+            Ok(None) => {}
+            Err(e) => log::error!("Invalid output config: {:?}", e),
+        }
+
+        match config.get_present_mode() {
+            Ok(Some(mode)) => {
+                if let Err(e) = self.c_output.set_present_mode(mode) {
+                    log::error!(
+                        "Could not use present mode {:?}, supported modes are {:?}: {:?}",
+                        mode,
+                        self.c_output.get_supported_present_modes(),
+                        e
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Invalid output config: {:?}", e),
+        }
+
+        self.c_atmos.lock().unwrap().a_zero_copy_shm = config.zero_copy_shm;
+
+        if let Some((width, height)) = config.resolution {
+            if let Err(e) = self
+                .c_output
+                .set_resolution(&mut self.c_scene, width, height)
+            {
+                log::error!("Could not set resolution: {:?}", e);
+            }
+        }
+    }
+
+    /// Apply the windows section of the config
+    ///
+    /// This resets any window opacity changed since the config was loaded.
+    fn apply_window_config(&mut self) {
+        let config = &self.c_config.windows;
+        let mut atmos = self.c_atmos.lock().unwrap();
+
+        match config.get_dim_unfocused() {
+            Ok(dim) => atmos.set_dim_unfocused(dim),
+            Err(e) => log::error!("Invalid windows config: {:?}", e),
+        }
+        atmos.set_opacity_rules(config.opacity.clone());
+    }
+
+    /// Use adaptive sync while a fullscreen window is focused
+    ///
+    /// Games and videos often present at a rate that doesn't divide the
+    /// refresh rate. With a variable refresh rate their frames are shown
+    /// when they are ready instead of at the next fixed vblank. The
+    /// desktop itself keeps a fixed rate, since some monitors flicker
+    /// when the rate changes.
+    fn update_adaptive_sync(&mut self) {
+        let enabled = self.c_config.output.adaptive_sync
+            && self.c_output.supports_adaptive_sync()
+            && self
+                .c_atmos
+                .lock()
+                .unwrap()
+                .get_fullscreen_win_in_focus()
+                .is_some();
+
+        if enabled != self.c_output.is_adaptive_sync() {
+            log::debug!(
+                "Adaptive sync {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            self.c_output.set_adaptive_sync(enabled);
         }
     }
 }
@@ -120,6 +301,8 @@ impl Climate {
 /// to clean up after itself
 pub struct ClientInfo {
     ci_id: ClientId,
+    /// The privileged protocols this client may use
+    ci_privileges: Vec<Privilege>,
     _ci_atmos: Arc<Mutex<Atmosphere>>,
 }
 
@@ -133,6 +316,9 @@ impl ws::backend::ClientData for ClientInfo {
     }
 }
 
+/// How long to wait before trying to recover a lost GPU again
+static DEVICE_RETRY_MS: usize = 1000;
+
 /// The event manager
 ///
 /// This class the launching point of the wayland stack. It
@@ -148,6 +334,31 @@ pub struct EventManager {
     em_display: ws::Display<Climate>,
     /// The wayland unix socket
     em_socket: ws::ListeningSocket,
+    /// Our display was unplugged and we are waiting for another one
+    em_output_lost: bool,
+    /// We lost the GPU and have not been able to recover yet
+    em_device_lost: bool,
+    /// Wakes us up to try recovering from GPU device loss again
+    em_device_timer: Option<dak::TimerId>,
+    /// The control socket used by scripts and bars
+    em_ipc: Option<IpcServer>,
+    /// Notifies us when the config files change
+    em_config_watch: Option<ConfigWatcher>,
+    /// Wakes us up to draw the next frame of an animation
+    em_frame_timer: Option<dak::TimerId>,
+    /// Wakes us up to show the next image of an animated cursor
+    em_cursor_timer: Option<dak::TimerId>,
+    /// Wakes us up to check if exported frames are ready
+    em_export_timer: Option<dak::TimerId>,
+    /// The output fd we watch to learn when frames reached the screen
+    em_presentation_fd: Option<RawFd>,
+    /// Wakes us up to disconnect IPC clients that never sent a request
+    em_ipc_timer: Option<dak::TimerId>,
+    /// Wakes us up when the next idle timeout expires
+    em_idle_timer: Option<dak::TimerId>,
+    /// The DPI of the display we started on. Text is scaled relative to
+    /// this when our window moves to another display.
+    em_base_dpi: Option<(i32, i32)>,
 }
 
 impl EventManager {
@@ -173,22 +384,55 @@ impl EventManager {
             state.c_atmos.lock().unwrap().deref_mut(),
         );
 
-        let evman = EventManager {
+        let base_dpi = state.c_output.get_dpi().ok();
+
+        let mut evman = EventManager {
             em_wm: wm,
             em_climate: state,
             em_display: display,
             em_socket: ws::ListeningSocket::bind_auto("wayland", 0..9)
                 .expect("Could not create wayland socket"),
+            em_output_lost: false,
+            em_device_lost: false,
+            em_device_timer: None,
+            em_ipc: None,
+            em_config_watch: ConfigWatcher::new(),
+            em_frame_timer: None,
+            em_cursor_timer: None,
+            em_export_timer: None,
+            em_presentation_fd: None,
+            em_ipc_timer: None,
+            em_idle_timer: None,
+            em_base_dpi: base_dpi,
         };
 
+        // Apply the parts of the config that need the scene set up by
+        // the window manager
+        evman.em_climate.apply_output_config();
+        evman.em_climate.apply_window_config();
+        evman.apply_theme();
+
+        // Export our socket so that programs launched from keybindings
+        // connect to us
+        if let Some(name) = evman.em_socket.socket_name() {
+            std::env::set_var("WAYLAND_DISPLAY", name);
+            evman.em_ipc = IpcServer::new(&name.to_string_lossy());
+        }
+
         // Register our global interfaces that will be advertised to all clients
         // --------------------------
         // wl_compositor
         display_handle.create_global::<Climate, wlci::WlCompositor, ()>(5, ());
-        display_handle.create_global::<Climate, xdg_wm_base::XdgWmBase, ()>(1, ());
-        display_handle.create_global::<Climate, wl_seat::WlSeat, ()>(8, ());
+        display_handle.create_global::<Climate, xdg_wm_base::XdgWmBase, ()>(2, ());
+        display_handle.create_global::<Climate, zxdmv1::ZxdgDecorationManagerV1, ()>(1, ());
+        // The default seat, the others come from the config
+        display_handle.create_global::<Climate, wl_seat::WlSeat, usize>(8, 0);
+        evman.em_climate.create_seat_globals(&display_handle);
         display_handle.create_global::<Climate, wl_subcompositor::WlSubcompositor, ()>(1, ());
         display_handle.create_global::<Climate, wl_output::WlOutput, ()>(4, ());
+        // Version 2 adds release, hold gestures in version 3 are not supported
+        display_handle.create_global::<Climate, zpgv1::ZwpPointerGesturesV1, ()>(2, ());
+        display_handle.create_global::<Climate, ztmv2::ZwpTabletManagerV2, ()>(1, ());
         if evman.em_climate.c_atmos.lock().unwrap().get_drm_dev() != (0, 0) {
             log::debug!("No DRM device detected, not advertising DRM-based interfaces");
             display_handle.create_global::<Climate, zldv1::ZwpLinuxDmabufV1, ()>(3, ());
@@ -196,11 +440,94 @@ impl EventManager {
         }
         display_handle.create_global::<Climate, wl_shell::WlShell, ()>(1, ());
         display_handle.create_global::<Climate, wl_shm::WlShm, ()>(1, ());
+        display_handle.create_global::<Climate, wp_viewporter::WpViewporter, ()>(1, ());
+        display_handle.create_global::<
+            Climate,
+            wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1,
+            (),
+        >(1, ());
         display_handle.create_global::<Climate, wlddm::WlDataDeviceManager, ()>(3, ());
+        display_handle
+            .create_global::<Climate, ext_workspace_manager_v1::ExtWorkspaceManagerV1, ()>(1, ());
+        display_handle
+            .create_global::<Climate, zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1, ()>(
+                1,
+                (),
+            );
+        display_handle
+            .create_global::<Climate, zwlr_export_dmabuf_manager_v1::ZwlrExportDmabufManagerV1, ()>(
+                1,
+                (),
+            );
+        display_handle
+            .create_global::<Climate, zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, ()>(
+                3,
+                (),
+            );
+        display_handle.create_global::<Climate, xdg_activation_v1::XdgActivationV1, ()>(1, ());
+        display_handle.create_global::<Climate, wp_presentation::WpPresentation, ()>(1, ());
+        display_handle.create_global::<Climate, ext_idle_notifier_v1::ExtIdleNotifierV1, ()>(1, ());
+        display_handle.create_global::<Climate, ziimv1::ZwpIdleInhibitManagerV1, ()>(1, ());
+        display_handle
+            .create_global::<Climate, wp_content_type_manager_v1::WpContentTypeManagerV1, ()>(
+                1,
+                (),
+            );
+        display_handle
+            .create_global::<Climate, wp_tearing_control_manager_v1::WpTearingControlManagerV1, ()>(
+                1,
+                (),
+            );
+        display_handle
+            .create_global::<Climate, ext_session_lock_manager_v1::ExtSessionLockManagerV1, ()>(
+                1,
+                (),
+            );
+
+        // Start the user's programs now that they have a socket to connect to
+        let children = &mut evman.em_climate.c_input.i_children;
+        evman.em_climate.c_config.run_autostart(children);
 
         return evman;
     }
 
+    /// Read the config files again and apply any changes
+    ///
+    /// If the new config file is invalid we keep using the old one.
+    /// Autostart programs are not run again.
+    fn reload_config(&mut self) {
+        let config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Invalid config file, keeping the old config: {:?}", e);
+                return;
+            }
+        };
+        log::error!("Reloading config");
+
+        self.em_climate.c_input.apply_config(&config);
+        self.em_climate.c_config = config;
+        self.em_climate.apply_output_config();
+        self.em_climate.apply_window_config();
+        self.em_climate
+            .create_seat_globals(&self.em_display.handle());
+        self.apply_theme();
+    }
+
+    /// Show the theme from our config
+    ///
+    /// Decorations are repainted on the next frame without involving the
+    /// clients.
+    fn apply_theme(&mut self) {
+        let mut atmos = self.em_climate.c_atmos.lock().unwrap();
+        self.em_wm.set_theme(
+            &mut atmos,
+            &mut self.em_climate.c_scene,
+            &self.em_climate.c_config.theme,
+        );
+        atmos.mark_changed();
+    }
+
     /// Helper method for registering the property id of a client
     ///
     /// We need to make an id for the client for our entity component set in
@@ -215,11 +542,17 @@ impl EventManager {
         let mut atmos = self.em_climate.c_atmos.lock().unwrap();
         // make a new client id
         let id = atmos.mint_client_id();
+        // Check what this client is allowed to do before it can bind globals
+        let privileges = self
+            .em_climate
+            .c_security
+            .get_client_privileges(&client_stream);
         // add our ClientData
         self.em_display.handle().insert_client(
             client_stream,
             Arc::new(ClientInfo {
                 ci_id: id.clone(),
+                ci_privileges: privileges,
                 _ci_atmos: self.em_climate.c_atmos.clone(),
             }),
         )?;
@@ -232,22 +565,26 @@ impl EventManager {
     /// This is where we update the resolution and notify clients of the
     /// change
     fn handle_ood(&mut self) {
+        // First handle the resize on this output
+        self.em_climate
+            .c_output
+            .handle_resize()
+            .expect("Failed to resize output");
+
+        // This is the new size even if the swapchain hasn't been
+        // recreated yet
         let res = self.em_climate.c_output.get_resolution();
         {
             let mut atmos = self.em_climate.c_atmos.lock().unwrap();
             atmos.mark_changed();
             atmos.set_resolution(res);
+            // Maximized and fullscreen windows need to fit the new size
+            atmos.reconfigure_window_states();
         }
         self.em_climate.send_all_geometry();
 
-        // First handle the resize on this output
-        self.em_climate
-            .c_output
-            .handle_resize()
-            .expect("Failed to resize output");
         // Update our VirtualOutput with the newly resized dimensions
-        let resolution = self.em_climate.c_output.get_resolution();
-        self.em_climate.c_virtual_output.set_size(resolution);
+        self.em_climate.c_virtual_output.set_size(res);
 
         // Notify our WM that the resize has taken place
         self.em_wm.handle_ood(
@@ -256,10 +593,151 @@ impl EventManager {
         );
     }
 
+    /// Rasterize our text for the DPI of a new display
+    ///
+    /// Text is scaled relative to the display we started on, so that it
+    /// keeps its physical size as our window moves between displays.
+    fn handle_dpi_changed(&mut self, dpi: (i32, i32)) {
+        let base = match self.em_base_dpi {
+            Some(base) if base.1 > 0 && dpi.1 > 0 => base,
+            _ => return,
+        };
+
+        log::debug!("DPI changed from {:?} to {:?}", base, dpi);
+        self.em_climate
+            .c_scene
+            .set_text_scale(dpi.1 as f32 / base.1 as f32);
+        self.em_climate.c_atmos.lock().unwrap().mark_changed();
+    }
+
+    /// Free what we can when the GPU is running low on memory
+    fn handle_low_memory(&mut self) {
+        let usage = self.em_climate.c_output.get_memory_usage();
+        let count = self
+            .em_climate
+            .c_atmos
+            .lock()
+            .unwrap()
+            .evict_hidden_shadow_resources();
+        self.em_climate.c_scene.clear_asset_cache();
+        log::error!(
+            "Low on GPU memory, freed the contents of {} hidden surfaces: {:?}",
+            count,
+            usage
+        );
+    }
+
+    /// Handle our display being unplugged
+    ///
+    /// If another display is available we move to it, otherwise we stop
+    /// drawing until one is plugged in.
+    fn handle_output_destroyed(&mut self) {
+        log::error!("Our display was unplugged");
+        self.em_output_lost = true;
+
+        let info = self
+            .em_climate
+            .c_dakota
+            .get_output_infos()
+            .iter()
+            .find(|info| info.can_create_output())
+            .cloned();
+        if let Some(info) = info {
+            self.switch_output(&info);
+        }
+    }
+
+    /// Move to drawing on a different display
+    ///
+    /// The old Output is dropped, which destroys its swapchain. Everything
+    /// is then resized to fit the new display.
+    fn switch_output(&mut self, info: &dak::OutputInfo) {
+        // The fd belongs to the old Output
+        self.set_presentation_watch(None);
+        let output = match self
+            .em_climate
+            .c_dakota
+            .create_output_with_info(info, &self.em_climate.c_virtual_output)
+        {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!("Could not create Output for new display: {:?}", e);
+                return;
+            }
+        };
+
+        self.em_climate.c_output = output;
+        self.em_output_lost = false;
+        self.em_climate.apply_output_config();
+        // The gamma ramps were for the old display
+        self.em_climate.fail_gamma_control();
+
+        if let Some(drm_dev) = self.em_climate.c_output.get_drm_dev() {
+            self.em_climate.c_atmos.lock().unwrap().set_drm_dev(drm_dev);
+        }
+        self.handle_ood();
+    }
+
+    /// Recover from losing the GPU
+    ///
+    /// Dakota recreates our output on a new device, after which all of
+    /// our images need to be defined again. Client dmabufs are imported
+    /// again and every window is asked to draw a new frame.
+    ///
+    /// The GPU may not be usable again right away. If recovery fails we
+    /// stop drawing and try again every DEVICE_RETRY_MS.
+    fn handle_device_lost(&mut self) {
+        // Our output's fd goes away with the old device
+        self.set_presentation_watch(None);
+        self.em_device_lost = true;
+        if let Err(e) = self
+            .em_climate
+            .c_dakota
+            .recover_from_device_loss(&mut self.em_climate.c_output, &mut self.em_climate.c_scene)
+        {
+            log::error!(
+                "Could not recover from GPU device loss, trying again in {}ms: {:?}",
+                DEVICE_RETRY_MS,
+                e
+            );
+            return;
+        }
+        self.em_device_lost = false;
+
+        self.em_wm.handle_device_lost(&mut self.em_climate.c_scene);
+
+        let mut atmos = self.em_climate.c_atmos.lock().unwrap();
+        let count = atmos.reimport_dmabuf_resources(&mut self.em_climate.c_scene);
+        atmos.configure_all_windows();
+        log::error!(
+            "Recovered from GPU device loss, restored {} surfaces",
+            count
+        );
+    }
+
+    /// Apply images that finished loading in the background
+    ///
+    /// Returns true if we need to redraw to show them.
+    fn handle_asset_loaded(&mut self) -> bool {
+        match self.em_climate.c_scene.process_loaded_assets() {
+            Ok(updated) => updated,
+            Err(e) => {
+                log::error!("Could not load image: {:?}", e);
+                false
+            }
+        }
+    }
+
     /// Redraw the output
     ///
     /// This recompiles our scene and redraws our Dakota Output
     fn redraw(&mut self) {
+        // There is nowhere to draw until a display is plugged in or
+        // we have a working GPU again
+        if self.em_output_lost || self.em_device_lost {
+            return;
+        }
+        let _redraw = trace::span("category5", "redraw");
         let mut atmos = self.em_climate.c_atmos.lock().unwrap();
         log::debug!("trying to render frame");
         self.em_wm
@@ -272,6 +750,99 @@ impl EventManager {
             .expect("Failed to redraw output");
         log::debug!("rendering frame done");
         atmos.clear_changed();
+        drop(atmos);
+
+        // Hand the new frame to any screen recorders
+        self.em_climate.send_export_frames();
+        self.em_climate.send_screencopy_frames();
+        // The desktop is no longer on screen, tell the locker it is safe
+        self.em_climate.send_session_locked();
+    }
+
+    /// Keep a timer running every `interval` ms while `needed` is true
+    ///
+    /// Our main loop sleeps in Dakota until something happens. These
+    /// timers wake it up for work which has to be polled.
+    fn update_poll_timer(
+        dakota: &mut dak::Dakota,
+        timer: &mut Option<dak::TimerId>,
+        needed: bool,
+        interval: usize,
+    ) {
+        match (needed, timer.is_some()) {
+            (true, false) => {
+                *timer = Some(dakota.add_repeating_timer(Duration::from_millis(interval as u64)))
+            }
+            (false, true) => dakota.remove_timer(timer.take().unwrap()),
+            _ => {}
+        }
+    }
+
+    /// Wake up when an animated cursor should show its next image
+    fn update_cursor_timer(&mut self) {
+        if let Some(timer) = self.em_cursor_timer.take() {
+            self.em_climate.c_dakota.remove_timer(timer);
+        }
+        if let Some(delay) = self.em_wm.get_cursor_frame_delay() {
+            self.em_cursor_timer = Some(self.em_climate.c_dakota.add_timer(delay));
+        }
+    }
+
+    /// Wake up when a frame clients are waiting on reaches the screen
+    ///
+    /// Backends which learn this after presenting give us an fd which
+    /// becomes readable once they have.
+    fn update_presentation_watch(&mut self) {
+        let fd = match self.em_output_lost
+            || self.em_device_lost
+            || !self.em_climate.has_presentation_feedback_in_flight()
+        {
+            true => None,
+            false => self.em_climate.c_output.get_presentation_fd(),
+        };
+        self.set_presentation_watch(fd);
+    }
+
+    /// Replace the presentation fd we are watching
+    fn set_presentation_watch(&mut self, fd: Option<RawFd>) {
+        if fd == self.em_presentation_fd {
+            return;
+        }
+        if let Some(old) = self.em_presentation_fd.take() {
+            self.em_climate.c_dakota.remove_watch_fd(old);
+        }
+        if let Some(new) = fd {
+            self.em_climate.c_dakota.add_watch_fd(new);
+        }
+        self.em_presentation_fd = fd;
+    }
+
+    /// Wake up when the oldest IPC connection should be closed
+    fn update_ipc_timer(&mut self) {
+        if let Some(timer) = self.em_ipc_timer.take() {
+            self.em_climate.c_dakota.remove_timer(timer);
+        }
+        if let Some(d) = self.em_ipc.as_ref().and_then(|ipc| ipc.get_next_timeout()) {
+            let timer = self
+                .em_climate
+                .c_dakota
+                .add_timer(d + Duration::from_millis(1));
+            self.em_ipc_timer = Some(timer);
+        }
+    }
+
+    /// Wake up when the next idle timeout expires
+    fn update_idle_timer(&mut self, idle_timeout: Option<Duration>) {
+        if let Some(timer) = self.em_idle_timer.take() {
+            self.em_climate.c_dakota.remove_timer(timer);
+        }
+        if let Some(d) = idle_timeout {
+            let timer = self
+                .em_climate
+                .c_dakota
+                .add_timer(d + Duration::from_millis(1));
+            self.em_idle_timer = Some(timer);
+        }
     }
 
     /// Each subsystem has a function that implements its main
@@ -288,14 +859,55 @@ impl EventManager {
         self.em_climate
             .c_dakota
             .add_watch_fd(self.em_socket.as_raw_fd());
+        // Add the IPC socket
+        if let Some(ipc) = self.em_ipc.as_ref() {
+            self.em_climate.c_dakota.add_watch_fd(ipc.get_fd());
+        }
+        // Add the config directory watch
+        if let Some(watch) = self.em_config_watch.as_ref() {
+            self.em_climate.c_dakota.add_watch_fd(watch.get_fd());
+        }
 
         loop {
             log::debug!("starting loop");
 
+            // Wake up for the next frame if windows are being animated
+            Self::update_poll_timer(
+                &mut self.em_climate.c_dakota,
+                &mut self.em_frame_timer,
+                self.em_wm.is_animating(),
+                animation::ANIMATION_FRAME_MS,
+            );
+            // and for the next image of an animated cursor
+            self.update_cursor_timer();
+            // and for screen recorders waiting on the GPU
+            let exporting = self.em_climate.has_export_frames_pending();
+            Self::update_poll_timer(
+                &mut self.em_climate.c_dakota,
+                &mut self.em_export_timer,
+                exporting,
+                EXPORT_POLL_MS,
+            );
+            // and to try recovering the GPU again
+            Self::update_poll_timer(
+                &mut self.em_climate.c_dakota,
+                &mut self.em_device_timer,
+                self.em_device_lost,
+                DEVICE_RETRY_MS,
+            );
+            // and when the last frame has reached the screen
+            self.update_presentation_watch();
+            // and to drop IPC clients that are taking too long
+            self.update_ipc_timer();
+
+            // Sleep until one of our fds or timers is ready. Frame
+            // scheduling, input and clients all share this one wait.
+            let wait = trace::span("category5", "wait_for_events");
             self.em_climate
                 .c_dakota
                 .dispatch(None)
                 .expect("Dispatching Dakota platform handlers");
+            drop(wait);
             log::debug!("dispatch_platform done");
 
             log::debug!("begin event handling");
@@ -303,19 +915,43 @@ impl EventManager {
             // It has time sensitive operations which need to take
             // place as soon as the fd is readable
             // now go through each event
-            for event in self.em_climate.c_dakota.drain_events() {
-                match &event {
+            let events: Vec<dak::GlobalEvent> = self.em_climate.c_dakota.drain_events().collect();
+            for event in events {
+                match event {
                     // Don't print fd events since they happen constantly and
                     // flood the output
                     dak::GlobalEvent::UserFdReadable => {}
-                    // Exit gracefully if quit
-                    dak::GlobalEvent::Quit => return,
+                    // Timers just wake us up to do our polling
+                    dak::GlobalEvent::Timer(_) => {}
+                    // Exit gracefully if quit, including from SIGINT
+                    // and SIGTERM
+                    dak::GlobalEvent::ChildExited => self.em_climate.c_input.i_children.reap(),
+                    dak::GlobalEvent::Quit => {
+                        trace::flush();
+                        return;
+                    }
+                    // Start drawing on a new display if ours was unplugged
+                    dak::GlobalEvent::OutputAdded(info) => {
+                        log::debug!("Display plugged in: {:?}", info);
+                        if self.em_output_lost {
+                            self.switch_output(&info);
+                        }
+                    }
+                    // Our Output will get the Destroyed event if this was it
+                    dak::GlobalEvent::OutputRemoved(info) => {
+                        log::debug!("Display unplugged: {:?}", info)
+                    }
                 }
             }
             log::debug!("Global handling done");
 
+            let input = trace::span("category5", "input");
             while let Some(ev) = self.em_climate.c_virtual_output.pop_event() {
                 match &ev {
+                    dak::PlatformEvent::DpiChanged { dpi } => self.handle_dpi_changed(*dpi),
+                    dak::PlatformEvent::DisplayChanged { index, refresh } => {
+                        log::debug!("Moved to display {} ({}mHz)", index, refresh)
+                    }
                     e => {
                         log::debug!("Category5: got Dakota PlatformEvent: {:?}", e);
                         self.em_climate.c_input.handle_input_event(
@@ -325,8 +961,41 @@ impl EventManager {
                     }
                 }
             }
+            drop(input);
             log::debug!("Platform handling done");
 
+            // Answer scripts and bars using the IPC socket
+            if let Some(ipc) = self.em_ipc.as_mut() {
+                self.em_climate.handle_ipc_requests(ipc);
+            }
+
+            // Apply the config files if they were edited or the user asked
+            // for them to be reloaded
+            let config_changed = self
+                .em_config_watch
+                .as_mut()
+                .is_some_and(|watch| watch.has_changed());
+            if self.em_climate.c_input.take_config_reload_request() || config_changed {
+                self.reload_config();
+            }
+
+            // Configure input devices if our settings changed
+            if let Some(config) = self.em_climate.c_input.take_input_config_update() {
+                self.em_climate.c_dakota.set_input_config(config);
+            }
+            // Send clients the new keymap if the layout changed
+            if let Some(config) = self.em_climate.c_input.take_keymap_update() {
+                let atmos = self.em_climate.c_atmos.lock().unwrap();
+                self.em_climate.c_input.send_keymap_to_all(&atmos);
+                if let Err(e) = self.em_climate.c_dakota.set_keymap(&config) {
+                    log::error!("Dakota could not use the keyboard layout: {:?}", e);
+                }
+            }
+            if self.em_climate.c_input.take_key_repeat_update() {
+                let atmos = self.em_climate.c_atmos.lock().unwrap();
+                self.em_climate.c_input.send_repeat_info_to_all(&atmos);
+            }
+
             // Accept any new clients
             // Do this first to fill in their client data and initialize
             // atmos ids for each of them
@@ -343,13 +1012,40 @@ impl EventManager {
             // We should do this before rendering so that any updates are reflected
             // immediately.
             log::debug!("dispatching wayland");
+            let dispatch = trace::span("ways", "dispatch_clients");
             self.em_display
                 .dispatch_clients(&mut self.em_climate)
                 .unwrap();
+            drop(dispatch);
+            self.em_climate
+                .c_resources
+                .check_for_leaks(&self.em_display.handle());
+
+            // Check if the user became idle or came back. Do this after
+            // dispatching so new notifications and inhibitors are included.
+            let idle_timeout = self.em_climate.update_idle_state();
+            self.update_idle_timer(idle_timeout);
+            // Let fullscreen games tear if they asked to, and present
+            // fullscreen windows with a variable refresh rate
+            self.em_climate.update_tearing();
+            self.em_climate.update_adaptive_sync();
+
+            // Arrange any tiled workspaces that had windows come or go
+            self.em_climate.c_atmos.lock().unwrap().retile_if_needed();
+
+            // Let panels know if input or a client changed the workspace
+            self.em_climate.send_workspace_state();
 
             // If our state database was updated by input or wayland processing then
             // we need to rerender
-            let mut needs_render = self.em_climate.c_atmos.lock().unwrap().is_changed();
+            let mut needs_render = self.em_climate.c_atmos.lock().unwrap().is_changed()
+                || self.em_wm.is_animating()
+                || self.em_wm.get_cursor_frame_delay() == Some(Duration::ZERO);
+
+            // Our last attempt to recover the GPU failed, try again
+            if self.em_device_lost {
+                self.handle_device_lost();
+            }
 
             while let Some(ev) = self.em_climate.c_output.pop_event() {
                 match &ev {
@@ -360,7 +1056,12 @@ impl EventManager {
                     }
                     // Our output surface is out of date, reallocate it
                     dak::OutputEvent::Resized => self.handle_ood(),
-                    dak::OutputEvent::Destroyed => {}
+                    dak::OutputEvent::Destroyed => self.handle_output_destroyed(),
+                    dak::OutputEvent::LowMemory => self.handle_low_memory(),
+                    // The GPU was reset, move everything to a new device
+                    dak::OutputEvent::DeviceLost => self.handle_device_lost(),
+                    // Show the desktop images once they have been decoded
+                    dak::OutputEvent::AssetLoaded => needs_render |= self.handle_asset_loaded(),
                 }
             }
 
@@ -369,10 +1070,17 @@ impl EventManager {
             }
             log::debug!("Output handling done");
 
+            // Tell clients about any frames that reached the screen
+            if !self.em_output_lost && !self.em_device_lost {
+                self.em_climate.send_presentation_feedback();
+                self.em_climate.send_export_ready();
+            }
+
             // Flush any wayland events we sent here
             // The rendering code will send the wayland frame notifications, which
             // have been queued but not yet flushed to the wayland socket.
             log::debug!("flushing wayland");
+            let _flush = trace::span("ways", "flush_clients");
             self.em_display
                 .flush_clients()
                 .expect("Could not flush wayland display");
@@ -0,0 +1,384 @@
+// Desktop themes
+//
+// A theme holds the colors, fonts and sizes used for everything we draw
+// ourselves: the menubar, window decorations, and the lock screen. The
+// colors come from a named palette, either one of the built in `dark` and
+// `light` palettes or one defined in the config file:
+//
+//   [theme]
+//   palette = "solarized"
+//   border_width = 2
+//   corner_radius = 6
+//   titlebar_height = 28
+//   titlebar_font = "Inter"
+//
+//   [theme.palettes.solarized]
+//   inherits = "light"
+//   titlebar_focused = "#268bd2"
+//
+//   [theme.colors]
+//   menubar = "#002b36e6"
+//
+// Colors in `[theme.colors]` override the chosen palette. The window
+// manager publishes the theme as Dakota styles, see the STYLE_ names
+// below, so that anything in the scene can use it.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate serde;
+
+use dak::dom;
+use serde::Deserialize;
+use utils::{anyhow, Context, Error, Result};
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The menubar across the top of the screen
+pub const STYLE_MENUBAR: &str = "cat5-menubar";
+/// Titlebars of windows which are not in focus
+pub const STYLE_TITLEBAR: &str = "cat5-titlebar";
+/// The titlebar of the window in focus
+pub const STYLE_TITLEBAR_FOCUSED: &str = "cat5-titlebar-focused";
+/// Title text of windows which are not in focus
+pub const STYLE_TITLE: &str = "cat5-title";
+/// Title text of the window in focus
+pub const STYLE_TITLE_FOCUSED: &str = "cat5-title-focused";
+/// Borders of windows which are not in focus
+pub const STYLE_BORDER: &str = "cat5-border";
+/// Borders of the window in focus
+pub const STYLE_BORDER_FOCUSED: &str = "cat5-border-focused";
+/// The outline shown while moving or resizing a window
+pub const STYLE_GRAB_OUTLINE: &str = "cat5-grab-outline";
+/// The outline shown while a moved window is snapped to an edge
+pub const STYLE_SNAP_OUTLINE: &str = "cat5-snap-outline";
+/// The background of the lock screen
+pub const STYLE_LOCK_SCREEN: &str = "cat5-lock-screen";
+/// The panel of the window switcher
+pub const STYLE_SWITCHER: &str = "cat5-switcher";
+/// The highlight behind the selected window in the switcher
+pub const STYLE_SWITCHER_SELECTED: &str = "cat5-switcher-selected";
+/// The highlight over the selected window in the overview
+pub const STYLE_OVERVIEW_SELECTED: &str = "cat5-overview-selected";
+/// Drop shadows around floating windows
+pub const STYLE_SHADOW: &str = "cat5-shadow";
+/// The border fill behind fullscreen windows
+pub const STYLE_FULLSCREEN_BACKDROP: &str = "cat5-fullscreen-backdrop";
+
+/// Parse a `#rrggbb` or `#rrggbbaa` color
+pub fn parse_color(color: &str) -> Result<dom::Color> {
+    let hex = color
+        .strip_prefix('#')
+        .ok_or(anyhow!("Color {} must start with #", color))?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return Err(anyhow!("Color {} must be #rrggbb or #rrggbbaa", color));
+    }
+
+    let mut channels = [1.0; 4];
+    for (i, channel) in channels.iter_mut().enumerate().take(hex.len() / 2) {
+        let value = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .context(format!("Color {} is not valid hex", color))?;
+        *channel = value as f32 / 255.0;
+    }
+
+    Ok(dom::Color::new(
+        channels[0],
+        channels[1],
+        channels[2],
+        channels[3],
+    ))
+}
+
+/// Define the colors that make up a palette
+///
+/// This creates `Palette`, and the `PaletteConfig` read from the config
+/// file in which every color is optional.
+macro_rules! define_palette {
+    ($($(#[$doc:meta])* $name:ident),+ $(,)?) => {
+        /// The set of colors used by a theme
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct Palette {
+            $($(#[$doc])* pub $name: dom::Color,)+
+        }
+
+        /// A palette defined in the config file
+        ///
+        /// Colors that are not set are taken from the palette this
+        /// inherits from.
+        #[derive(Debug, Default, Clone, Deserialize)]
+        #[serde(default, deny_unknown_fields)]
+        struct PaletteConfig {
+            /// The palette to take unset colors from. By default this is
+            /// the built in palette with the same name, or `dark`.
+            inherits: Option<String>,
+            $($name: Option<String>,)+
+        }
+
+        impl PaletteConfig {
+            /// Replace the colors in `palette` with the ones set here
+            fn apply_to(&self, palette: &mut Palette) -> Result<()> {
+                $(if let Some(color) = self.$name.as_ref() {
+                    palette.$name = parse_color(color)
+                        .context(format!("Invalid {} color", stringify!($name)))?;
+                })+
+                Ok(())
+            }
+        }
+    };
+}
+
+define_palette!(
+    /// Background of the menubar
+    menubar,
+    /// Text in the menubar
+    text,
+    /// Titlebar of windows which are not in focus
+    titlebar,
+    /// Titlebar of the window in focus
+    titlebar_focused,
+    /// Title text of windows which are not in focus
+    title_text,
+    /// Title text of the window in focus
+    title_text_focused,
+    /// Border of windows which are not in focus
+    border,
+    /// Border of the window in focus
+    border_focused,
+    /// Outline shown while a window is moved or resized
+    grab_outline,
+    /// Outline shown while a moved window is snapped to an edge
+    snap_outline,
+    /// Background of the lock screen
+    lock_screen,
+    /// Panel of the window switcher
+    switcher,
+    /// Highlight of the selected window in the switcher
+    switcher_selected,
+    /// Highlight over the selected window in the overview
+    overview_selected,
+    /// Fill around and behind fullscreen windows
+    fullscreen_backdrop,
+);
+
+impl Palette {
+    /// Our default palette
+    pub fn dark() -> Self {
+        Self {
+            menubar: dom::Color::new(0.085, 0.09, 0.088, 0.9),
+            text: dom::Color::new(0.941, 0.921, 0.807, 1.0),
+            titlebar: dom::Color::new(0.16, 0.17, 0.165, 1.0),
+            titlebar_focused: dom::Color::new(0.22, 0.29, 0.4, 1.0),
+            title_text: dom::Color::new(0.6, 0.6, 0.56, 1.0),
+            title_text_focused: dom::Color::new(0.941, 0.921, 0.807, 1.0),
+            border: dom::Color::new(0.16, 0.17, 0.165, 1.0),
+            border_focused: dom::Color::new(0.22, 0.29, 0.4, 1.0),
+            grab_outline: dom::Color::new(0.4, 0.6, 0.9, 0.15),
+            snap_outline: dom::Color::new(0.4, 0.6, 0.9, 0.35),
+            lock_screen: dom::Color::new(0.0, 0.0, 0.0, 1.0),
+            switcher: dom::Color::new(0.085, 0.09, 0.088, 0.9),
+            switcher_selected: dom::Color::new(0.22, 0.29, 0.4, 1.0),
+            overview_selected: dom::Color::new(0.4, 0.6, 0.9, 0.25),
+            fullscreen_backdrop: dom::Color::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            menubar: dom::Color::new(0.93, 0.93, 0.91, 0.9),
+            text: dom::Color::new(0.15, 0.15, 0.15, 1.0),
+            titlebar: dom::Color::new(0.86, 0.86, 0.84, 1.0),
+            titlebar_focused: dom::Color::new(0.7, 0.8, 0.95, 1.0),
+            title_text: dom::Color::new(0.45, 0.45, 0.45, 1.0),
+            title_text_focused: dom::Color::new(0.1, 0.1, 0.1, 1.0),
+            border: dom::Color::new(0.86, 0.86, 0.84, 1.0),
+            border_focused: dom::Color::new(0.7, 0.8, 0.95, 1.0),
+            grab_outline: dom::Color::new(0.2, 0.4, 0.8, 0.15),
+            snap_outline: dom::Color::new(0.2, 0.4, 0.8, 0.35),
+            lock_screen: dom::Color::new(0.0, 0.0, 0.0, 1.0),
+            switcher: dom::Color::new(0.93, 0.93, 0.91, 0.9),
+            switcher_selected: dom::Color::new(0.7, 0.8, 0.95, 1.0),
+            overview_selected: dom::Color::new(0.2, 0.4, 0.8, 0.25),
+            fullscreen_backdrop: dom::Color::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Get one of the palettes that ship with Category5
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+
+    /// Get a palette by name
+    ///
+    /// Palettes in `palettes` take precedence over the built in ones.
+    fn resolve(palettes: &BTreeMap<String, PaletteConfig>, name: &str) -> Result<Self> {
+        let mut chain = Vec::new();
+        let mut next = name;
+
+        // Find the palettes we inherit from until we reach a built in one
+        let mut ret = loop {
+            if chain.iter().any(|(n, _)| *n == next) {
+                return Err(anyhow!("Palette {} inherits from itself", next));
+            }
+            match palettes.get(next) {
+                Some(palette) => {
+                    chain.push((next, palette));
+                    match palette.inherits.as_deref() {
+                        Some(parent) => next = parent,
+                        // This lets the built in palettes be changed
+                        None => break Self::builtin(next).unwrap_or_else(Self::dark),
+                    }
+                }
+                None => {
+                    break Self::builtin(next).ok_or(anyhow!("Palette {} is not defined", next))?
+                }
+            }
+        };
+
+        // Apply the base palettes first, so that their children override them
+        for (name, palette) in chain.iter().rev() {
+            palette
+                .apply_to(&mut ret)
+                .context(format!("In palette {}", name))?;
+        }
+
+        Ok(ret)
+    }
+}
+
+/// The `[theme]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeConfig {
+    palette: Option<String>,
+    palettes: BTreeMap<String, PaletteConfig>,
+    colors: PaletteConfig,
+    border_width: Option<u32>,
+    corner_radius: Option<u32>,
+    titlebar_height: Option<u32>,
+    font: Option<String>,
+    font_size: Option<u32>,
+    titlebar_font: Option<String>,
+    titlebar_font_size: Option<u32>,
+    shadow_radius: Option<u32>,
+    shadow_opacity: Option<f32>,
+}
+
+/// The look of the desktop
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "ThemeConfig")]
+pub struct Theme {
+    pub palette: Palette,
+    /// Width of the border drawn around decorated windows
+    pub border_width: u32,
+    /// Radius of the top corners of titlebars
+    pub corner_radius: u32,
+    pub titlebar_height: u32,
+    /// The font used in the menubar
+    pub font: String,
+    pub font_size: u32,
+    /// The font used for window titles
+    pub titlebar_font: String,
+    pub titlebar_font_size: u32,
+    /// How far drop shadows extend around floating windows, 0 disables them
+    pub shadow_radius: u32,
+    /// Opacity of drop shadows where they meet the window
+    pub shadow_opacity: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            palette: Palette::dark(),
+            border_width: 1,
+            corner_radius: 6,
+            titlebar_height: 28,
+            font: "JetBrainsMono".to_string(),
+            font_size: 16,
+            titlebar_font: "JetBrainsMono".to_string(),
+            titlebar_font_size: 14,
+            shadow_radius: 24,
+            shadow_opacity: 0.35,
+        }
+    }
+}
+
+impl TryFrom<ThemeConfig> for Theme {
+    type Error = Error;
+
+    fn try_from(config: ThemeConfig) -> Result<Self> {
+        let default = Self::default();
+
+        let mut palette = match config.palette.as_deref() {
+            Some(name) => Palette::resolve(&config.palettes, name)?,
+            None => default.palette,
+        };
+        config.colors.apply_to(&mut palette)?;
+
+        let titlebar_height = config.titlebar_height.unwrap_or(default.titlebar_height);
+        if titlebar_height == 0 {
+            return Err(anyhow!("titlebar_height must be at least 1"));
+        }
+        let font_size = config.font_size.unwrap_or(default.font_size);
+        let titlebar_font_size = config
+            .titlebar_font_size
+            .unwrap_or(default.titlebar_font_size);
+        if font_size == 0 || titlebar_font_size == 0 {
+            return Err(anyhow!("Font sizes must be at least 1"));
+        }
+        let shadow_opacity = config.shadow_opacity.unwrap_or(default.shadow_opacity);
+        if !(0.0..=1.0).contains(&shadow_opacity) {
+            return Err(anyhow!("shadow_opacity must be between 0.0 and 1.0"));
+        }
+
+        Ok(Self {
+            palette,
+            border_width: config.border_width.unwrap_or(default.border_width),
+            // The rounded corners have to fit in the titlebar
+            corner_radius: config
+                .corner_radius
+                .unwrap_or(default.corner_radius)
+                .min(titlebar_height),
+            titlebar_height,
+            font: config.font.unwrap_or(default.font),
+            font_size,
+            titlebar_font: config.titlebar_font.unwrap_or(default.titlebar_font),
+            titlebar_font_size,
+            shadow_radius: config.shadow_radius.unwrap_or(default.shadow_radius),
+            shadow_opacity,
+        })
+    }
+}
+
+impl Theme {
+    /// Get the font used in the menubar
+    pub fn get_font(&self) -> dom::Font {
+        dom::Font {
+            name: "Menubar".to_string(),
+            font_name: self.font.clone(),
+            style: None,
+            pixel_size: self.font_size,
+            color: Some(self.palette.text),
+        }
+    }
+
+    /// Get the font used for window titles
+    pub fn get_title_font(&self, focused: bool) -> dom::Font {
+        dom::Font {
+            name: match focused {
+                true => "TitleFocused".to_string(),
+                false => "Title".to_string(),
+            },
+            font_name: self.titlebar_font.clone(),
+            style: None,
+            pixel_size: self.titlebar_font_size,
+            color: Some(match focused {
+                true => self.palette.title_text_focused,
+                false => self.palette.title_text,
+            }),
+        }
+    }
+}
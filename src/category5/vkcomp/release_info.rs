@@ -1,9 +1,17 @@
 // Release info for dropping WlBuffers when the wm is done with them
 //
+// This is handed to Thundr along with a dmabuf, or a shm buffer it
+// samples in place. Thundr keeps it until
+// the image is destroyed and the GPU has finished every frame that read
+// it, so the client can't draw into the buffer while we are sampling it.
+//
 // Austin Shafer - 2020
 extern crate wayland_server as ws;
+use crate::category5::ways::shm::ShmMapping;
 use ws::protocol::wl_buffer;
 
+use std::sync::Arc;
+
 pub struct GenericReleaseInfo {
     // The wl_buffer that represents this  image
     pub wl_buffer: wl_buffer::WlBuffer,
@@ -20,3 +28,13 @@ impl Drop for GenericReleaseInfo {
         self.release();
     }
 }
+
+/// Release info for shm buffers that are sampled in place
+///
+/// Instead of being released right after we copy it, the wl_buffer is
+/// held until the GPU is done reading it. The pool's mapping is kept
+/// too, since the client may resize the pool in the meantime.
+pub struct ShmReleaseInfo {
+    pub _release: GenericReleaseInfo,
+    pub _mapping: Arc<ShmMapping>,
+}
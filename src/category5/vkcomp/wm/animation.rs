@@ -0,0 +1,365 @@
+// Window animations
+//
+// The animator interpolates a scale, offset and opacity for window
+// elements over a short period of time. These are applied as a transform
+// of the window element every frame until the animation finishes, so
+// subsurfaces and decorations move along with the window. The animated
+// opacity is multiplied with the opacity the user gave the window.
+//
+// Austin Shafer - 2024
+#![allow(non_camel_case_types)]
+
+extern crate dakota as dak;
+
+use crate::category5::atmosphere::*;
+
+use std::time::{Duration, Instant};
+
+/// How often to draw frames while animating, roughly 60 fps
+pub static ANIMATION_FRAME_MS: usize = 16;
+/// How long window map and unmap animations take
+static WINDOW_ANIMATION_MS: u64 = 150;
+/// How long the workspace slide takes
+static WORKSPACE_ANIMATION_MS: u64 = 250;
+/// How long moving windows in and out of the overview takes
+static OVERVIEW_ANIMATION_MS: u64 = 250;
+
+/// The transform applied to a window at one point in an animation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimState {
+    /// Scale around the center of the window
+    pub as_scale: f32,
+    /// Offset added to the window position
+    pub as_offset: (f32, f32),
+    pub as_opacity: f32,
+}
+
+impl AnimState {
+    /// The untransformed state
+    pub fn identity() -> Self {
+        Self {
+            as_scale: 1.0,
+            as_offset: (0.0, 0.0),
+            as_opacity: 1.0,
+        }
+    }
+
+    /// Get the state that moves a window into its slot in the overview
+    ///
+    /// The corner of the window geometry is placed at the corner of the
+    /// slot, and the window is shrunk to fit it.
+    pub fn for_overview_slot(atmos: &Atmosphere, id: &SurfaceId, slot: &OverviewSlot) -> Self {
+        let surface_pos = atmos.a_surface_pos.get_clone(id).unwrap_or((0.0, 0.0));
+        let surface_size = atmos.a_surface_size.get_clone(id).unwrap_or((0.0, 0.0));
+        let (window_pos, _) = atmos.get_window_geometry(id);
+        let scale = slot.os_scale;
+
+        // Undo the movement from scaling around the center of the
+        // surface, and the offset of the window geometry within it
+        let offset = |target: f32, surface: f32, window: f32, size: f32| {
+            target - surface - scale * (window - surface) - size / 2.0 * (1.0 - scale)
+        };
+        Self {
+            as_scale: scale,
+            as_offset: (
+                offset(slot.os_pos.0, surface_pos.0, window_pos.0, surface_size.0),
+                offset(slot.os_pos.1, surface_pos.1, window_pos.1, surface_size.1),
+            ),
+            as_opacity: 1.0,
+        }
+    }
+
+    /// Get the transform of an element of `size` in this state
+    fn get_transform(&self, size: (f32, f32)) -> dak::Transform {
+        dak::Transform::scale(self.as_scale, self.as_scale)
+            .around(size.0 / 2.0, size.1 / 2.0)
+            .then(&dak::Transform::translate(
+                self.as_offset.0,
+                self.as_offset.1,
+            ))
+    }
+
+    /// Interpolate between two states
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            as_scale: mix(self.as_scale, other.as_scale),
+            as_offset: (
+                mix(self.as_offset.0, other.as_offset.0),
+                mix(self.as_offset.1, other.as_offset.1),
+            ),
+            as_opacity: mix(self.as_opacity, other.as_opacity),
+        }
+    }
+}
+
+/// What to do with the window element once an animation completes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimationEnd {
+    /// Leave the element in place
+    keep,
+    /// Leave the element in its final state, until another animation
+    /// replaces this one
+    hold,
+    /// Remove the element from the desktop
+    remove,
+}
+
+/// A single running animation
+struct Animation {
+    an_id: SurfaceId,
+    /// When this animation started. This is None until the window has
+    /// a size, so that map animations don't run before the first commit.
+    an_start: Option<Instant>,
+    an_duration: Duration,
+    an_from: AnimState,
+    an_to: AnimState,
+    an_end: AnimationEnd,
+    /// Geometry to animate for windows that no longer exist in atmos
+    an_base: Option<((f32, f32), (f32, f32))>,
+}
+
+/// Tracks all running window animations
+pub struct Animator {
+    am_animations: Vec<Animation>,
+}
+
+/// Ease out so that animations slow down as they finish
+fn ease_out(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
+}
+
+impl Animator {
+    pub fn new() -> Self {
+        Self {
+            am_animations: Vec::new(),
+        }
+    }
+
+    /// Are any animations in progress?
+    ///
+    /// While this is true we need to keep drawing frames.
+    pub fn is_animating(&self) -> bool {
+        !self.am_animations.is_empty()
+    }
+
+    /// Start an animation for a window
+    ///
+    /// This replaces any existing animation for this window. If `base` is
+    /// provided it is used as the window geometry, otherwise the geometry
+    /// is read from atmos every frame.
+    fn start(
+        &mut self,
+        id: &SurfaceId,
+        duration_ms: u64,
+        from: AnimState,
+        to: AnimState,
+        end: AnimationEnd,
+        base: Option<((f32, f32), (f32, f32))>,
+    ) {
+        self.am_animations.retain(|a| &a.an_id != id);
+        self.am_animations.push(Animation {
+            an_id: id.clone(),
+            an_start: None,
+            an_duration: Duration::from_millis(duration_ms),
+            an_from: from,
+            an_to: to,
+            an_end: end,
+            an_base: base,
+        });
+    }
+
+    /// Scale up and fade in a newly mapped window
+    pub fn animate_map(&mut self, id: &SurfaceId) {
+        let from = AnimState {
+            as_scale: 0.85,
+            as_offset: (0.0, 0.0),
+            as_opacity: 0.0,
+        };
+        self.start(
+            id,
+            WINDOW_ANIMATION_MS,
+            from,
+            AnimState::identity(),
+            AnimationEnd::keep,
+            None,
+        );
+    }
+
+    /// Fade out a window that is being closed
+    ///
+    /// The window's surface has already been destroyed, so the last
+    /// geometry and opacity it was drawn with are passed in. The element
+    /// is removed from the desktop once the fade completes.
+    pub fn animate_unmap(&mut self, id: &SurfaceId, base: ((f32, f32), (f32, f32)), opacity: f32) {
+        let from = AnimState {
+            as_opacity: opacity,
+            ..AnimState::identity()
+        };
+        let to = AnimState {
+            as_scale: 0.9,
+            as_offset: (0.0, 0.0),
+            as_opacity: 0.0,
+        };
+        self.start(
+            id,
+            WINDOW_ANIMATION_MS,
+            from,
+            to,
+            AnimationEnd::remove,
+            Some(base),
+        );
+    }
+
+    /// Slide a window horizontally during a workspace switch
+    ///
+    /// Windows leaving the desktop slide from their position to `offset`
+    /// and are then removed. Windows entering slide from `offset` to their
+    /// position.
+    pub fn animate_workspace_slide(&mut self, id: &SurfaceId, offset: f32, leaving: bool) {
+        let moved = AnimState {
+            as_scale: 1.0,
+            as_offset: (offset, 0.0),
+            as_opacity: 1.0,
+        };
+        let (from, to, end) = match leaving {
+            true => (AnimState::identity(), moved, AnimationEnd::remove),
+            false => (moved, AnimState::identity(), AnimationEnd::keep),
+        };
+        self.start(id, WORKSPACE_ANIMATION_MS, from, to, end, None);
+    }
+
+    /// Move a window from `from` into its slot in the overview
+    ///
+    /// The window stays in the overview after the animation finishes,
+    /// until `animate_from_overview` moves it back.
+    pub fn animate_to_overview(&mut self, id: &SurfaceId, from: AnimState, to: AnimState) {
+        self.start(
+            id,
+            OVERVIEW_ANIMATION_MS,
+            from,
+            to,
+            AnimationEnd::hold,
+            None,
+        );
+    }
+
+    /// Move a window from the overview back to its place on the desktop
+    pub fn animate_from_overview(&mut self, id: &SurfaceId, from: AnimState) {
+        self.start(
+            id,
+            OVERVIEW_ANIMATION_MS,
+            from,
+            AnimState::identity(),
+            AnimationEnd::keep,
+            None,
+        );
+    }
+
+    /// Get the state a window is being drawn in, if it is animating
+    pub fn get_current_state(&self, id: &SurfaceId) -> Option<AnimState> {
+        let anim = self.am_animations.iter().find(|a| &a.an_id == id)?;
+        let start = anim.an_start?;
+        let t = (start.elapsed().as_secs_f32() / anim.an_duration.as_secs_f32()).min(1.0);
+        Some(anim.an_from.lerp(&anim.an_to, ease_out(t)))
+    }
+
+    /// Stop animating a window
+    pub fn cancel(&mut self, id: &SurfaceId) {
+        self.am_animations.retain(|a| &a.an_id != id);
+    }
+
+    /// Is this window being animated?
+    pub fn is_animating_window(&self, id: &SurfaceId) -> bool {
+        self.am_animations.iter().any(|a| &a.an_id == id)
+    }
+
+    /// Is this window fading out after being closed?
+    pub fn is_unmapping(&self, id: &SurfaceId) -> bool {
+        self.am_animations
+            .iter()
+            .any(|a| &a.an_id == id && a.an_base.is_some())
+    }
+
+    /// Apply the current state of all animations to the scene
+    ///
+    /// This should be called after the window geometry has been updated
+    /// for this frame. If `skip` is set then all animations are jumped to
+    /// their end state, which is used when animations have been disabled.
+    /// Returns the elements that finished animating and should be removed
+    /// from the desktop.
+    pub fn apply(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        skip: bool,
+    ) -> Vec<SurfaceId> {
+        let now = Instant::now();
+        let mut finished = Vec::new();
+
+        self.am_animations.retain_mut(|anim| {
+            // Closed windows carry their opacity in the animation
+            let opacity = match anim.an_base {
+                Some(_) => 1.0,
+                None => atmos.get_window_opacity(&anim.an_id),
+            };
+            let size = match anim.an_base {
+                Some((_, size)) => size,
+                None => match atmos.a_surface_size.get(&anim.an_id).map(|s| *s) {
+                    Some(size) => size,
+                    // This window is gone, nothing left to animate
+                    None => return false,
+                },
+            };
+
+            // Wait until the window has content before starting
+            let start = match anim.an_start {
+                Some(start) => start,
+                None if size.0 > 0.0 && size.1 > 0.0 => {
+                    anim.an_start = Some(now);
+                    now
+                }
+                None if !skip => {
+                    scene
+                        .opacity()
+                        .set(&anim.an_id, anim.an_from.as_opacity * opacity);
+                    return true;
+                }
+                None => now,
+            };
+
+            let elapsed = now.duration_since(start).as_secs_f32();
+            let t = match skip {
+                true => 1.0,
+                false => (elapsed / anim.an_duration.as_secs_f32()).min(1.0),
+            };
+            let state = anim.an_from.lerp(&anim.an_to, ease_out(t));
+
+            scene
+                .transform()
+                .set(&anim.an_id, state.get_transform(size));
+            scene.opacity().set(&anim.an_id, state.as_opacity * opacity);
+
+            if t < 1.0 {
+                return true;
+            }
+
+            match anim.an_end {
+                AnimationEnd::hold => return false,
+                AnimationEnd::remove => finished.push(anim.an_id.clone()),
+                AnimationEnd::keep => {}
+            }
+            // Leave the element untransformed once we are done
+            match opacity < 1.0 {
+                true => scene.opacity().set(&anim.an_id, opacity),
+                false => {
+                    scene.opacity().take(&anim.an_id);
+                }
+            }
+            scene.transform().take(&anim.an_id);
+            false
+        });
+
+        finished
+    }
+}
@@ -0,0 +1,216 @@
+// Cursor themes
+//
+// The cursors drawn by Category5 itself are loaded from the user's xcursor
+// theme, picked with the XCURSOR_THEME and XCURSOR_SIZE environment
+// variables. Cursors with more than one image are animated, with each
+// image shown for its own delay.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate xcursor;
+
+use dak::dom;
+use dak::DakotaId;
+use utils::{log, Result};
+use wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The theme to use if XCURSOR_THEME isn't set
+static DEFAULT_THEME: &str = "default";
+/// The cursor size to use if XCURSOR_SIZE isn't set
+const DEFAULT_SIZE: u32 = 24;
+
+/// The cursors Category5 shows when a client isn't choosing one
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CursorShape {
+    Default,
+    Move,
+    ResizeTop,
+    ResizeBottom,
+    ResizeLeft,
+    ResizeRight,
+    ResizeTopLeft,
+    ResizeTopRight,
+    ResizeBottomLeft,
+    ResizeBottomRight,
+}
+
+impl CursorShape {
+    /// Get the cursor for resizing a window from `edge`
+    pub fn from_resize_edge(edge: ResizeEdge) -> Self {
+        match edge {
+            ResizeEdge::Top => Self::ResizeTop,
+            ResizeEdge::Bottom => Self::ResizeBottom,
+            ResizeEdge::Left => Self::ResizeLeft,
+            ResizeEdge::Right => Self::ResizeRight,
+            ResizeEdge::TopLeft => Self::ResizeTopLeft,
+            ResizeEdge::TopRight => Self::ResizeTopRight,
+            ResizeEdge::BottomLeft => Self::ResizeBottomLeft,
+            ResizeEdge::BottomRight => Self::ResizeBottomRight,
+            _ => Self::Default,
+        }
+    }
+
+    /// The names this cursor may have in a theme, in order of preference
+    ///
+    /// Newer themes use the CSS cursor names, while older ones only have
+    /// the X11 cursor font names.
+    fn get_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::Default => &["default", "left_ptr"],
+            Self::Move => &["move", "fleur", "grabbing"],
+            Self::ResizeTop => &["n-resize", "top_side"],
+            Self::ResizeBottom => &["s-resize", "bottom_side"],
+            Self::ResizeLeft => &["w-resize", "left_side"],
+            Self::ResizeRight => &["e-resize", "right_side"],
+            Self::ResizeTopLeft => &["nw-resize", "top_left_corner"],
+            Self::ResizeTopRight => &["ne-resize", "top_right_corner"],
+            Self::ResizeBottomLeft => &["sw-resize", "bottom_left_corner"],
+            Self::ResizeBottomRight => &["se-resize", "bottom_right_corner"],
+        }
+    }
+}
+
+/// One image of a cursor
+pub struct CursorFrame {
+    pub cf_image: DakotaId,
+    pub cf_size: (i32, i32),
+    pub cf_hotspot: (i32, i32),
+    /// How long to show this image for in animated cursors
+    pub cf_delay: Duration,
+}
+
+/// A cursor loaded from the theme
+pub struct Cursor {
+    pub c_frames: Vec<CursorFrame>,
+}
+
+impl Cursor {
+    pub fn is_animated(&self) -> bool {
+        self.c_frames.len() > 1
+    }
+}
+
+/// The user's xcursor theme
+///
+/// Cursors are loaded the first time they are used.
+pub struct CursorTheme {
+    ct_theme: xcursor::CursorTheme,
+    /// The nominal size of the cursors we load
+    ct_size: u32,
+    /// Cursors we have loaded, or None if the theme doesn't have them
+    ct_cursors: HashMap<CursorShape, Option<Cursor>>,
+}
+
+impl CursorTheme {
+    pub fn new() -> Self {
+        let name = std::env::var("XCURSOR_THEME").unwrap_or(DEFAULT_THEME.to_string());
+        let size = std::env::var("XCURSOR_SIZE")
+            .ok()
+            .and_then(|s| s.parse::<u32>().ok())
+            .filter(|s| *s > 0)
+            .unwrap_or(DEFAULT_SIZE);
+        log::debug!("Using cursor theme {} with size {}", name, size);
+
+        Self {
+            ct_theme: xcursor::CursorTheme::load(&name),
+            ct_size: size,
+            ct_cursors: HashMap::new(),
+        }
+    }
+
+    /// Read the images of a cursor file
+    ///
+    /// Cursor files hold images for several sizes, so only the ones with
+    /// the size closest to ours are returned.
+    fn read_images(&self, path: &std::path::Path) -> Option<Vec<xcursor::parser::Image>> {
+        let data = std::fs::read(path).ok()?;
+        let images = xcursor::parser::parse_xcursor(&data)?;
+
+        let size = images
+            .iter()
+            .map(|i| i.size)
+            .min_by_key(|s| (*s as i64 - self.ct_size as i64).abs())?;
+        Some(images.into_iter().filter(|i| i.size == size).collect())
+    }
+
+    /// Turn a cursor image into a scene resource
+    fn create_frame(scene: &mut dak::Scene, image: &xcursor::parser::Image) -> Result<CursorFrame> {
+        // xcursor gives us RGBA, but our resources are BGRA
+        let mut data = image.pixels_rgba.clone();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+
+        let res = scene.create_resource()?;
+        scene.define_resource_from_bits(
+            &res,
+            &data,
+            image.width,
+            image.height,
+            0,
+            dom::Format::ARGB8888,
+        )?;
+
+        Ok(CursorFrame {
+            cf_image: res,
+            cf_size: (image.width as i32, image.height as i32),
+            cf_hotspot: (image.xhot as i32, image.yhot as i32),
+            cf_delay: Duration::from_millis(image.delay as u64),
+        })
+    }
+
+    fn load_cursor(&self, scene: &mut dak::Scene, shape: CursorShape) -> Option<Cursor> {
+        let images = shape
+            .get_names()
+            .iter()
+            .filter_map(|name| self.ct_theme.load_icon(name))
+            .find_map(|path| self.read_images(&path))?;
+
+        let mut frames = Vec::with_capacity(images.len());
+        for image in images.iter() {
+            match Self::create_frame(scene, image) {
+                Ok(frame) => frames.push(frame),
+                Err(e) => {
+                    log::error!("Could not create image for cursor {:?}: {:?}", shape, e);
+                    return None;
+                }
+            }
+        }
+
+        match frames.is_empty() {
+            true => None,
+            false => Some(Cursor { c_frames: frames }),
+        }
+    }
+
+    /// Get a cursor, loading it if this is the first time it is used
+    ///
+    /// Returns None if the theme does not have this cursor.
+    pub fn get_cursor(&mut self, scene: &mut dak::Scene, shape: CursorShape) -> Option<&Cursor> {
+        if !self.ct_cursors.contains_key(&shape) {
+            let cursor = self.load_cursor(scene, shape);
+            if cursor.is_none() {
+                log::error!("Cursor theme does not have a {:?} cursor", shape);
+            }
+            self.ct_cursors.insert(shape, cursor);
+        }
+
+        self.ct_cursors.get(&shape).unwrap().as_ref()
+    }
+
+    /// Get a cursor if it has already been loaded
+    pub fn get_loaded_cursor(&self, shape: CursorShape) -> Option<&Cursor> {
+        self.ct_cursors.get(&shape)?.as_ref()
+    }
+
+    /// Forget all of our cursor images
+    ///
+    /// This is used when the GPU was lost, since Dakota dropped all of
+    /// the images. They will be loaded again when next used.
+    pub fn clear(&mut self) {
+        self.ct_cursors.clear();
+    }
+}
@@ -0,0 +1,255 @@
+// Server side window decorations
+//
+// Windows that let us draw their decorations get a titlebar and a
+// border made of Dakota elements. These are children of the window's
+// element placed just outside of it, so they move with the window and
+// its animations. Their colors and fonts come from the theme's styles,
+// which means redefining the styles repaints every decoration.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use crate::category5::atmosphere::*;
+use crate::category5::theme;
+use dak::dom;
+use dak::DakotaId;
+use utils::Result;
+
+/// Create a titlebar image with rounded top corners
+///
+/// The image is a nine-patch whose corners are `radius` pixels wide, so
+/// that it can be stretched across windows of any width without
+/// distorting the corners.
+pub fn create_titlebar_image(
+    scene: &mut dak::Scene,
+    color: dom::Color,
+    radius: u32,
+    height: u32,
+) -> Result<DakotaId> {
+    let width = radius * 2 + 1;
+    let r = radius as f32;
+
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            // Distance from the center of the corner's circle, if this
+            // pixel is in one of the top corners
+            let py = y as f32 + 0.5;
+            let px = x as f32 + 0.5;
+            let dx = match x {
+                x if x < radius => r - px,
+                x if x >= width - radius => px - (width - radius) as f32,
+                _ => 0.0,
+            };
+            let dy = if y < radius { r - py } else { 0.0 };
+
+            let coverage = match dx > 0.0 && dy > 0.0 {
+                true => (r - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0),
+                false => 1.0,
+            };
+
+            let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            data.extend_from_slice(&[
+                channel(color.b),
+                channel(color.g),
+                channel(color.r),
+                channel(color.a * coverage),
+            ]);
+        }
+    }
+
+    let res = scene.create_resource()?;
+    scene.define_resource_from_bits(&res, &data, width, height, 0, dom::Format::ARGB8888)?;
+    scene.resource_nine_patch().set(
+        &res,
+        dom::NinePatch {
+            left: radius,
+            right: radius,
+            top: radius,
+            bottom: 0,
+        },
+    );
+
+    Ok(res)
+}
+
+/// The decorations drawn around one window
+pub struct Decoration {
+    /// The window these decorate
+    de_id: SurfaceId,
+    de_titlebar: DakotaId,
+    /// The window title, shown as the content of the titlebar
+    de_title: DakotaId,
+    /// The left, right and bottom borders
+    de_borders: [DakotaId; 3],
+    /// The title currently shown
+    de_title_text: Option<String>,
+    de_focused: bool,
+    /// Are our elements attached to the window?
+    de_visible: bool,
+}
+
+impl Decoration {
+    /// Create the elements for a window's decorations
+    ///
+    /// The decorations are hidden until `set_visible` is called.
+    pub fn new(scene: &mut dak::Scene, id: &SurfaceId) -> Result<Self> {
+        let titlebar = scene.create_element()?;
+        scene.add_class(&titlebar, theme::STYLE_TITLEBAR);
+
+        let title = scene.create_element()?;
+        scene.height().set(&title, dom::Value::Relative(1.0));
+        scene.add_class(&title, theme::STYLE_TITLE);
+        scene
+            .content()
+            .set(&titlebar, dom::Content { el: title.clone() });
+
+        let mut create_border = || -> Result<DakotaId> {
+            let border = scene.create_element()?;
+            scene.add_class(&border, theme::STYLE_BORDER);
+            Ok(border)
+        };
+        let borders = [create_border()?, create_border()?, create_border()?];
+
+        Ok(Self {
+            de_id: id.clone(),
+            de_titlebar: titlebar,
+            de_title: title,
+            de_borders: borders,
+            de_title_text: None,
+            de_focused: false,
+            de_visible: false,
+        })
+    }
+
+    /// The window these decorations belong to
+    pub fn get_id(&self) -> &SurfaceId {
+        &self.de_id
+    }
+
+    /// The elements we attach to the window
+    fn elements(&self) -> impl Iterator<Item = &DakotaId> {
+        std::iter::once(&self.de_titlebar).chain(self.de_borders.iter())
+    }
+
+    /// Attach or detach our elements from the window
+    ///
+    /// The decorations are placed below the window's subsurfaces, so that
+    /// popups are drawn over the titlebar.
+    pub fn set_visible(&mut self, scene: &mut dak::Scene, visible: bool) -> Result<()> {
+        if visible == self.de_visible {
+            return Ok(());
+        }
+        self.de_visible = visible;
+
+        let elements: Vec<DakotaId> = self.elements().cloned().collect();
+        if !visible {
+            for el in elements.iter() {
+                scene.remove_child_from_element(&self.de_id, el)?;
+            }
+            return Ok(());
+        }
+
+        let bottom = scene
+            .children()
+            .get(&self.de_id)
+            .and_then(|children| children.first().cloned());
+        for el in elements.into_iter() {
+            // Decorations are outside of the window, don't clip them to it
+            scene.unbounded_subsurface().set(&el, true);
+            scene.add_child_to_element(&self.de_id, el.clone());
+            if let Some(bottom) = bottom.as_ref() {
+                scene.reorder_children_element(
+                    &self.de_id,
+                    dak::SubsurfaceOrder::Below,
+                    &el,
+                    bottom,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch between the focused and unfocused styles
+    pub fn set_focused(&mut self, scene: &mut dak::Scene, focused: bool) {
+        if focused == self.de_focused {
+            return;
+        }
+        self.de_focused = focused;
+
+        let swap = |scene: &mut dak::Scene, el: &DakotaId, normal: &str, active: &str| {
+            let (old, new) = match focused {
+                true => (normal, active),
+                false => (active, normal),
+            };
+            scene.remove_class(el, old);
+            scene.add_class(el, new);
+        };
+
+        swap(
+            scene,
+            &self.de_titlebar,
+            theme::STYLE_TITLEBAR,
+            theme::STYLE_TITLEBAR_FOCUSED,
+        );
+        swap(
+            scene,
+            &self.de_title,
+            theme::STYLE_TITLE,
+            theme::STYLE_TITLE_FOCUSED,
+        );
+        for border in self.de_borders.iter() {
+            swap(
+                scene,
+                border,
+                theme::STYLE_BORDER,
+                theme::STYLE_BORDER_FOCUSED,
+            );
+        }
+    }
+
+    /// Update the title shown in the titlebar
+    pub fn set_title(&mut self, scene: &mut dak::Scene, title: Option<String>) {
+        if title == self.de_title_text {
+            return;
+        }
+        scene.set_text_regular(&self.de_title, title.as_deref().unwrap_or(""));
+        self.de_title_text = title;
+    }
+
+    /// Place the decorations around the window
+    ///
+    /// `pos` is the offset of the window geometry within the surface, and
+    /// `size` is the size of the window geometry. `decoration_size` is the
+    /// titlebar height and border width.
+    pub fn set_geometry(
+        &mut self,
+        scene: &mut dak::Scene,
+        pos: (f32, f32),
+        size: (f32, f32),
+        decoration_size: (u32, u32),
+    ) {
+        let (x, y) = (pos.0 as i32, pos.1 as i32);
+        let (w, h) = (size.0 as i32, size.1 as i32);
+        let (th, bw) = (decoration_size.0 as i32, decoration_size.1 as i32);
+
+        let place = |el: &DakotaId, x: i32, y: i32, w: i32, h: i32| {
+            scene.offset().set(
+                el,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant(x),
+                    y: dom::Value::Constant(y),
+                },
+            );
+            scene.width().set(el, dom::Value::Constant(w));
+            scene.height().set(el, dom::Value::Constant(h));
+        };
+
+        place(&self.de_titlebar, x - bw, y - th, w + bw * 2, th);
+        // left, right, and bottom
+        place(&self.de_borders[0], x - bw, y, bw, h);
+        place(&self.de_borders[1], x + w, y, bw, h);
+        place(&self.de_borders[2], x - bw, y + h, w + bw * 2, bw);
+    }
+}
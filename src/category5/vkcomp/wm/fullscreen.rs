@@ -0,0 +1,126 @@
+// Fullscreen backdrops
+//
+// Fullscreen windows have a backdrop drawn directly below them which
+// covers the whole output. It hides the menubar and every window behind
+// the fullscreen one, and is the border fill around windows which don't
+// fill the output. Since it is below the window and not one of its
+// children, it also shows through any translucent parts of the window
+// instead of the desktop.
+//
+// The backdrop is a sibling of the window on the desktop, so it has to
+// be kept directly below the window as the stacking order changes.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use super::DESKTOP_OFFSET;
+use crate::category5::atmosphere::*;
+use crate::category5::theme;
+use dak::dom;
+use dak::DakotaId;
+use utils::Result;
+
+/// The backdrops of all fullscreen windows on the current workspace
+pub struct Backdrops {
+    /// Each fullscreen window, and the element drawn below it
+    fb_windows: Vec<(SurfaceId, DakotaId)>,
+}
+
+impl Backdrops {
+    pub fn new() -> Self {
+        Self {
+            fb_windows: Vec::new(),
+        }
+    }
+
+    /// Place a backdrop directly below its window on the desktop
+    ///
+    /// Nothing is changed if it is already there. The backdrop is removed
+    /// if the window isn't on the desktop.
+    fn place_below(
+        scene: &mut dak::Scene,
+        desktop: &DakotaId,
+        id: &SurfaceId,
+        el: &DakotaId,
+    ) -> Result<()> {
+        let children = scene
+            .children()
+            .get(desktop)
+            .map(|c| c.to_vec())
+            .unwrap_or_default();
+        let window = match children.iter().position(|c| c == id) {
+            Some(window) => window,
+            None => return scene.remove_child_from_element(desktop, el),
+        };
+        if window > 0 && &children[window - 1] == el {
+            return Ok(());
+        }
+
+        scene.remove_child_from_element(desktop, el)?;
+        scene.add_child_to_element(desktop, el.clone());
+        scene.reorder_children_element(desktop, dak::SubsurfaceOrder::Below, el, id)
+    }
+
+    /// Update the backdrops of the root windows in `windows`
+    ///
+    /// `windows` should be the root windows shown on the desktop. Each
+    /// fullscreen window gets a backdrop covering the output, and all
+    /// others are removed. No backdrops are drawn while the overview is
+    /// open, so that the other windows can be seen.
+    pub fn update(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        desktop: &DakotaId,
+        windows: &[SurfaceId],
+    ) -> Result<()> {
+        let res = atmos.get_resolution();
+        let show = !atmos.is_overview_open();
+
+        for id in windows.iter() {
+            let index = self.fb_windows.iter().position(|(w, _)| w == id);
+            if !show || !atmos.window_is_fullscreen(id) {
+                if let Some(index) = index {
+                    let (_, el) = self.fb_windows.swap_remove(index);
+                    scene.remove_child_from_element(desktop, &el)?;
+                }
+                continue;
+            }
+
+            let el = match index {
+                Some(index) => self.fb_windows[index].1.clone(),
+                None => {
+                    let el = scene.create_element()?;
+                    scene.add_class(&el, theme::STYLE_FULLSCREEN_BACKDROP);
+                    self.fb_windows.push((id.clone(), el.clone()));
+                    el
+                }
+            };
+
+            // The desktop starts below the menubar, cover that too
+            scene.offset().set(
+                &el,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant(0),
+                    y: dom::Value::Constant(-DESKTOP_OFFSET),
+                },
+            );
+            scene.width().set(&el, dom::Value::Constant(res.0 as i32));
+            scene.height().set(&el, dom::Value::Constant(res.1 as i32));
+            Self::place_below(scene, desktop, id, &el)?;
+        }
+
+        // Windows that closed, left the workspace or were minimized
+        let mut i = 0;
+        while i < self.fb_windows.len() {
+            if windows.contains(&self.fb_windows[i].0) {
+                i += 1;
+                continue;
+            }
+            let (_, el) = self.fb_windows.swap_remove(i);
+            scene.remove_child_from_element(desktop, &el)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+// Performance overlay
+//
+// The HUD is a panel drawn over the desktop which shows how well we are
+// keeping up: the frame rate, a graph of recent frame times, the GPU time
+// spent on each part of the frame, how many surfaces were drawn, and a
+// small map of the screen showing which parts of it were redrawn.
+//
+// It is toggled with the toggle_hud keybinding, or shown at startup by
+// setting CATEGORY5_HUD. Thundr's GPU profiling is turned on while the
+// HUD is visible. The panel is updated every frame, so it always shows
+// up in the damage itself.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use super::{WindowManager, DESKTOP_OFFSET};
+use dak::dom;
+use dak::DakotaId;
+use utils::{log, Result};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+static PANEL_WIDTH: i32 = 400;
+static PADDING: i32 = 8;
+static LINE_HEIGHT: i32 = 20;
+static LINE_COUNT: usize = 4;
+/// The number of frames shown in the frame time graph
+static GRAPH_FRAMES: usize = 60;
+static GRAPH_BAR_WIDTH: i32 = 4;
+static GRAPH_HEIGHT: i32 = 48;
+/// The frame time at the top of the graph
+static GRAPH_MAX: Duration = Duration::from_millis(50);
+/// Frames which took longer than this are drawn in red
+static SLOW_FRAME: Duration = Duration::from_micros(16_667);
+/// The width of the damage map. Its height follows the screen's aspect ratio.
+static DAMAGE_MAP_WIDTH: i32 = 96;
+/// The most damage rectangles shown in the map
+static MAX_DAMAGE_RECTS: usize = 16;
+
+/// The performance overlay
+pub struct PerfHud {
+    /// The background of the overlay, all other elements are its children
+    ph_panel: DakotaId,
+    ph_font: DakotaId,
+    /// The text elements and the strings last assigned to them
+    ph_lines: Vec<(DakotaId, String)>,
+    /// The bars of the frame time graph, oldest first
+    ph_bars: Vec<DakotaId>,
+    ph_fast_color: DakotaId,
+    ph_slow_color: DakotaId,
+    /// A miniature of the screen which damage is drawn in
+    ph_damage_map: DakotaId,
+    ph_damage_color: DakotaId,
+    /// Elements for damage rectangles, only the first ph_shown_rects
+    /// are in the map
+    ph_damage_rects: Vec<DakotaId>,
+    ph_shown_rects: usize,
+    ph_visible: bool,
+    /// When the last frame was drawn
+    ph_last_draw: Option<Instant>,
+    /// When each frame in the last second was drawn
+    ph_draw_times: VecDeque<Instant>,
+    /// Time between recent frames, oldest first
+    ph_intervals: VecDeque<Duration>,
+    /// Time spent recording and submitting the last frame
+    ph_cpu_time: Duration,
+    /// The latest GPU timings we got from Thundr
+    ph_gpu: Option<dak::GpuFrameTimings>,
+    ph_stats: dak::FrameStats,
+    /// The frame count of the Output when we last drew. A new Output
+    /// starts counting from zero, which is how we notice it changed.
+    ph_last_frame: u64,
+    /// Has GPU profiling been turned on for the current Output
+    ph_profiling: bool,
+}
+
+impl PerfHud {
+    /// Create the overlay's elements
+    ///
+    /// The HUD is not shown until `set_visible` is called.
+    pub fn new(scene: &mut dak::Scene, font: DakotaId) -> Self {
+        let panel_color =
+            WindowManager::create_color(scene, dom::Color::new(0.05, 0.05, 0.05, 0.8));
+        let panel = scene.create_element().unwrap();
+        scene.resource().set(&panel, panel_color);
+        scene.width().set(&panel, dom::Value::Constant(PANEL_WIDTH));
+
+        let mut lines = Vec::new();
+        for i in 0..LINE_COUNT {
+            let line = scene.create_element().unwrap();
+            Self::place(scene, &line, PADDING, PADDING + i as i32 * LINE_HEIGHT);
+            scene
+                .width()
+                .set(&line, dom::Value::Constant(PANEL_WIDTH - 2 * PADDING));
+            scene.height().set(&line, dom::Value::Constant(LINE_HEIGHT));
+            scene.add_child_to_element(&panel, line.clone());
+            lines.push((line, String::new()));
+        }
+
+        let fast_color = WindowManager::create_color(scene, dom::Color::new(0.3, 0.8, 0.4, 1.0));
+        let slow_color = WindowManager::create_color(scene, dom::Color::new(0.9, 0.3, 0.3, 1.0));
+        let mut bars = Vec::new();
+        for _ in 0..GRAPH_FRAMES {
+            let bar = scene.create_element().unwrap();
+            scene
+                .width()
+                .set(&bar, dom::Value::Constant(GRAPH_BAR_WIDTH));
+            scene.resource().set(&bar, fast_color.clone());
+            scene.add_child_to_element(&panel, bar.clone());
+            bars.push(bar);
+        }
+
+        let map_color = WindowManager::create_color(scene, dom::Color::new(0.2, 0.2, 0.2, 1.0));
+        let damage_map = scene.create_element().unwrap();
+        scene.resource().set(&damage_map, map_color);
+        scene
+            .width()
+            .set(&damage_map, dom::Value::Constant(DAMAGE_MAP_WIDTH));
+        scene.add_child_to_element(&panel, damage_map.clone());
+
+        Self {
+            ph_panel: panel,
+            ph_font: font,
+            ph_lines: lines,
+            ph_bars: bars,
+            ph_fast_color: fast_color,
+            ph_slow_color: slow_color,
+            ph_damage_map: damage_map,
+            ph_damage_color: WindowManager::create_color(
+                scene,
+                dom::Color::new(0.9, 0.5, 0.2, 0.8),
+            ),
+            ph_damage_rects: Vec::new(),
+            ph_shown_rects: 0,
+            ph_visible: false,
+            ph_last_draw: None,
+            ph_draw_times: VecDeque::new(),
+            ph_intervals: VecDeque::new(),
+            ph_cpu_time: Duration::ZERO,
+            ph_gpu: None,
+            ph_stats: dak::FrameStats::default(),
+            ph_last_frame: 0,
+            ph_profiling: false,
+        }
+    }
+
+    /// Set the position of an element within its parent
+    fn place(scene: &mut dak::Scene, el: &DakotaId, x: i32, y: i32) {
+        scene.offset().set(
+            el,
+            dom::RelativeOffset {
+                x: dom::Value::Constant(x),
+                y: dom::Value::Constant(y),
+            },
+        );
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.ph_visible
+    }
+
+    /// Show or hide the overlay
+    ///
+    /// The panel is added to `root` as its last child, the caller should
+    /// raise the cursor above it afterwards.
+    pub fn set_visible(
+        &mut self,
+        scene: &mut dak::Scene,
+        root: &DakotaId,
+        output: &mut dak::Output,
+        visible: bool,
+    ) -> Result<()> {
+        self.ph_visible = visible;
+        if !visible {
+            scene.remove_child_from_element(root, &self.ph_panel)?;
+            // Leave profiling on if the user asked Thundr for it
+            if std::env::var("THUNDR_GPU_PROFILE").is_err() {
+                if let Err(e) = output.set_gpu_profiling(false) {
+                    log::error!("Could not turn off GPU profiling: {:?}", e);
+                }
+            }
+            self.ph_profiling = false;
+            return Ok(());
+        }
+
+        // Start over so that we don't show the time the HUD was hidden
+        self.ph_last_draw = None;
+        self.ph_draw_times.clear();
+        self.ph_intervals.clear();
+        self.ph_gpu = None;
+        scene.add_child_to_element(root, self.ph_panel.clone());
+        Ok(())
+    }
+
+    /// Record that the output finished drawing a frame
+    ///
+    /// `cpu_time` is how long we spent recording and submitting it.
+    pub fn frame_drawn(&mut self, output: &mut dak::Output, cpu_time: Duration) {
+        if !self.ph_visible {
+            return;
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.ph_last_draw {
+            self.ph_intervals.push_back(now - last);
+            if self.ph_intervals.len() > GRAPH_FRAMES {
+                self.ph_intervals.pop_front();
+            }
+        }
+        self.ph_last_draw = Some(now);
+        self.ph_draw_times.push_back(now);
+        while let Some(time) = self.ph_draw_times.front() {
+            if now - *time <= Duration::from_secs(1) {
+                break;
+            }
+            self.ph_draw_times.pop_front();
+        }
+        self.ph_cpu_time = cpu_time;
+        self.ph_stats = output.get_frame_stats().clone();
+
+        // Profiling has to be turned on again if the Output was replaced
+        let frame = output.get_frame_count();
+        if frame < self.ph_last_frame {
+            self.ph_profiling = false;
+        }
+        self.ph_last_frame = frame;
+        if !self.ph_profiling {
+            // Only try once, the HUD still works without GPU timings
+            self.ph_profiling = true;
+            if let Err(e) = output.set_gpu_profiling(true) {
+                log::error!("Could not turn on GPU profiling: {:?}", e);
+            }
+        }
+
+        // Timings arrive a frame or two late, show the newest
+        if let Some(timings) = output.take_gpu_timings().pop() {
+            self.ph_gpu = Some(timings);
+        }
+    }
+
+    /// Set the text of one line, if it changed
+    fn set_line(&mut self, scene: &mut dak::Scene, index: usize, text: String) {
+        let (el, old) = &mut self.ph_lines[index];
+        if *old == text {
+            return;
+        }
+        scene.set_text_regular(el, &text);
+        scene.text_font().set(el, self.ph_font.clone());
+        *old = text;
+    }
+
+    /// Update the overlay with the latest statistics
+    ///
+    /// This should be called before the scene is recompiled for the
+    /// next frame.
+    pub fn update(&mut self, scene: &mut dak::Scene, resolution: (u32, u32)) -> Result<()> {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        // ---------------- text ----------------
+        let last = self.ph_intervals.back().copied().unwrap_or(Duration::ZERO);
+        self.set_line(scene, 0, format!("{} fps", self.ph_draw_times.len()));
+        self.set_line(
+            scene,
+            1,
+            format!(
+                "frame {:.2} ms, cpu {:.2} ms",
+                ms(last),
+                ms(self.ph_cpu_time)
+            ),
+        );
+        let gpu = match self.ph_gpu.as_ref() {
+            Some(t) => format!(
+                "gpu {:.2} (up {:.2} draw {:.2} pp {:.2})",
+                ms(t.total()),
+                ms(t.gt_upload),
+                ms(t.gt_composite),
+                ms(t.gt_post_process)
+            ),
+            None => "gpu timings unavailable".to_string(),
+        };
+        self.set_line(scene, 2, gpu);
+        self.set_line(
+            scene,
+            3,
+            format!(
+                "surfaces {} (+{} -{} ~{}), drawn {}, damage {}",
+                self.ph_stats.fs_surface_count,
+                self.ph_stats.fs_surfaces_added,
+                self.ph_stats.fs_surfaces_removed,
+                self.ph_stats.fs_surfaces_changed,
+                self.ph_stats.fs_drawn_count,
+                self.ph_stats.fs_damage.len()
+            ),
+        );
+
+        // ---------------- frame time graph ----------------
+        // The newest frame is on the right
+        let graph_y = PADDING + LINE_COUNT as i32 * LINE_HEIGHT + PADDING;
+        let missing = GRAPH_FRAMES - self.ph_intervals.len();
+        for (i, bar) in self.ph_bars.iter().enumerate() {
+            let interval = match i.checked_sub(missing) {
+                Some(index) => self.ph_intervals[index],
+                None => Duration::ZERO,
+            };
+            let height = (interval.as_secs_f32() / GRAPH_MAX.as_secs_f32() * GRAPH_HEIGHT as f32)
+                .clamp(1.0, GRAPH_HEIGHT as f32) as i32;
+
+            Self::place(
+                scene,
+                bar,
+                PADDING + i as i32 * GRAPH_BAR_WIDTH,
+                graph_y + GRAPH_HEIGHT - height,
+            );
+            scene.height().set(bar, dom::Value::Constant(height));
+            let color = match interval > SLOW_FRAME {
+                true => self.ph_slow_color.clone(),
+                false => self.ph_fast_color.clone(),
+            };
+            scene.resource().set(bar, color);
+        }
+
+        // ---------------- damage map ----------------
+        let map_y = graph_y + GRAPH_HEIGHT + PADDING;
+        let scale = DAMAGE_MAP_WIDTH as f32 / resolution.0.max(1) as f32;
+        let map_height = (resolution.1 as f32 * scale) as i32;
+        Self::place(scene, &self.ph_damage_map, PADDING, map_y);
+        scene
+            .height()
+            .set(&self.ph_damage_map, dom::Value::Constant(map_height));
+
+        for rect in self.ph_damage_rects[..self.ph_shown_rects].iter() {
+            scene.remove_child_from_element(&self.ph_damage_map, rect)?;
+        }
+        let damage: Vec<_> = self
+            .ph_stats
+            .fs_damage
+            .iter()
+            .take(MAX_DAMAGE_RECTS)
+            .copied()
+            .collect();
+        while self.ph_damage_rects.len() < damage.len() {
+            let el = scene.create_element().unwrap();
+            scene.resource().set(&el, self.ph_damage_color.clone());
+            self.ph_damage_rects.push(el);
+        }
+        for (rect, el) in damage.iter().zip(self.ph_damage_rects.iter()) {
+            let scaled = |v: i32| (v as f32 * scale) as i32;
+            Self::place(scene, el, scaled(rect.r_pos.0), scaled(rect.r_pos.1));
+            // Keep tiny regions visible
+            scene
+                .width()
+                .set(el, dom::Value::Constant(scaled(rect.r_size.0).max(1)));
+            scene
+                .height()
+                .set(el, dom::Value::Constant(scaled(rect.r_size.1).max(1)));
+            scene.add_child_to_element(&self.ph_damage_map, el.clone());
+        }
+        self.ph_shown_rects = damage.len();
+
+        // ---------------- panel ----------------
+        // Top right corner, below the menubar
+        Self::place(
+            scene,
+            &self.ph_panel,
+            resolution.0 as i32 - PANEL_WIDTH - PADDING,
+            DESKTOP_OFFSET + PADDING,
+        );
+        scene.height().set(
+            &self.ph_panel,
+            dom::Value::Constant(map_y + map_height + PADDING),
+        );
+
+        Ok(())
+    }
+}
@@ -44,10 +44,31 @@ use dak::dom;
 use dak::DakotaId;
 
 use crate::category5::atmosphere::*;
-use utils::{log, Context, Result};
-
+use crate::category5::theme::{self, Theme};
+use utils::{log, trace, Context, Result};
+
+use std::time::{Duration, Instant};
+
+pub mod animation;
+pub mod cursor;
+pub mod decoration;
+pub mod fullscreen;
+pub mod hud;
+pub mod overview;
+pub mod shadow;
+pub mod switcher;
 pub mod task;
+pub mod thumbnail;
+use animation::Animator;
+use cursor::{CursorFrame, CursorShape, CursorTheme};
+use decoration::Decoration;
+use fullscreen::Backdrops;
+use hud::PerfHud;
+use overview::OverviewView;
+use shadow::Shadows;
+use switcher::SwitcherView;
 use task::*;
+use thumbnail::ThumbnailCache;
 
 #[cfg(feature = "renderdoc")]
 extern crate renderdoc;
@@ -57,6 +78,9 @@ use renderdoc::RenderDoc;
 // Menu bar is 16 pixels tall
 static MENUBAR_SIZE: i32 = 32;
 pub static DESKTOP_OFFSET: i32 = MENUBAR_SIZE;
+// Images loaded for the desktop
+static DESKTOP_BACKGROUND_PATH: &str = "images/cat5_desktop.png";
+static DEFAULT_CURSOR_PATH: &str = "images/cursor.png";
 
 /// Encapsulates vkcomp and provides a sensible windowing API
 ///
@@ -76,8 +100,23 @@ pub struct WindowManager {
     wm_scene_root: DakotaId,
     /// Font definition for UI widgets
     wm_menubar_font: DakotaId,
+    /// Fonts for the titles of unfocused and focused windows
+    wm_title_font: DakotaId,
+    wm_title_focused_font: DakotaId,
+    /// The current theme, published as styles in our scene
+    wm_theme: Theme,
+    /// Server side decorations for each window that has them
+    wm_decorations: Vec<Decoration>,
+    /// Drop shadows of floating windows
+    wm_shadows: Shadows,
+    /// The border fill behind fullscreen windows
+    wm_backdrops: Backdrops,
+    /// Scaled down copies of windows
+    wm_thumbnails: ThumbnailCache,
     /// The date time string UI element.
     wm_datetime: DakotaId,
+    /// The menu bar across the top of the screen
+    wm_menubar: DakotaId,
     /// The window area for this desktop
     ///
     /// This is a Dakota element that represents the region where all client windows
@@ -85,8 +124,32 @@ pub struct WindowManager {
     wm_desktop: DakotaId,
     /// Image representing the software cursor
     wm_cursor: Option<DakotaId>,
-    /// Category5's cursor, used when the client hasn't set one.
+    /// Category5's cursor, used when the cursor theme is missing
+    /// the cursor we want.
     wm_default_cursor: DakotaId,
+    /// The user's cursor theme
+    wm_cursor_theme: CursorTheme,
+    /// The element showing cursors from our theme
+    wm_theme_cursor: DakotaId,
+    /// The theme cursor being shown, if we are showing one
+    wm_cursor_shape: Option<CursorShape>,
+    /// The frame of an animated cursor we are on, and when it was shown
+    wm_cursor_frame: (usize, Instant),
+    /// The cursors of the seats other than the default one
+    wm_seat_cursors: Vec<DakotaId>,
+    /// Highlight drawn over a window while it is moved or resized
+    wm_grab_outline: DakotaId,
+    /// Running window animations
+    wm_animator: Animator,
+    /// Shown instead of the menubar and desktop while the session is
+    /// locked. The screen locker's surfaces are its children.
+    wm_lock_screen: DakotaId,
+    /// Performance overlay
+    wm_hud: PerfHud,
+    /// The alt-tab window switcher
+    wm_switcher: SwitcherView,
+    /// Moves windows in and out of the overview
+    wm_overview: OverviewView,
     #[cfg(feature = "renderdoc")]
     wm_renderdoc: RenderDoc<renderdoc::V141>,
 }
@@ -103,13 +166,70 @@ impl WindowManager {
         );
     }
 
+    /// Load our desktop images again after the GPU was lost
+    ///
+    /// Dakota drops every image when moving to a new GPU, so the
+    /// background and cursor need to be defined again.
+    pub fn handle_device_lost(&mut self, scene: &mut dak::Scene) {
+        let images = [
+            (&self.wm_desktop, DESKTOP_BACKGROUND_PATH),
+            (&self.wm_default_cursor, DEFAULT_CURSOR_PATH),
+        ];
+
+        for (el, path) in images.iter() {
+            if let Some(res) = scene.resource().get_clone(el) {
+                if let Err(e) = scene.define_resource_from_image(
+                    &res,
+                    std::path::Path::new(path),
+                    dom::Format::ARGB8888,
+                ) {
+                    log::error!("Could not reload {}: {:?}", path, e);
+                }
+            }
+        }
+
+        // Titlebar and shadow images were dropped too
+        self.define_styles(scene);
+
+        // As were the cursor images
+        self.wm_cursor_theme.clear();
+        if let Some(shape) = self.wm_cursor_shape {
+            if let Some(cursor) = self.wm_cursor_theme.get_cursor(scene, shape) {
+                let index = self.wm_cursor_frame.0.min(cursor.c_frames.len() - 1);
+                Self::apply_cursor_frame(scene, &self.wm_theme_cursor, &cursor.c_frames[index]);
+            }
+        }
+        for el in self.wm_seat_cursors.clone().iter() {
+            self.show_seat_cursor_image(scene, el);
+        }
+
+        // Thumbnails will be copied again when next used
+        self.wm_thumbnails.clear();
+    }
+
+    /// Get a scaled down copy of a window's contents
+    ///
+    /// The returned resource fits within `max_size` and keeps the window's
+    /// aspect ratio. It is cached until the window commits new contents.
+    /// Returns None if the window has no contents yet.
+    #[allow(dead_code)]
+    pub fn get_thumbnail(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        max_size: (u32, u32),
+    ) -> Option<DakotaId> {
+        self.wm_thumbnails.get_thumbnail(atmos, scene, id, max_size)
+    }
+
     /// Returns an ID for an element bound with a defaul texture resource
     fn get_default_cursor(scene: &mut dak::Scene) -> DakotaId {
         let image = scene.create_resource().unwrap();
         scene
             .define_resource_from_image(
                 &image,
-                std::path::Path::new("images/cursor.png"),
+                std::path::Path::new(DEFAULT_CURSOR_PATH),
                 dom::Format::ARGB8888,
             )
             .expect("Could not import background image into scene");
@@ -128,21 +248,23 @@ impl WindowManager {
         surf
     }
 
+    /// Create a solid color resource
+    fn create_color(scene: &mut dak::Scene, color: dom::Color) -> DakotaId {
+        let res = scene.create_resource().unwrap();
+        scene.resource_color().set(&res, color);
+        res
+    }
+
     /// Define all of the Dakota elements that make up the menu bar
     /// at the top of the screen
     fn create_menubar(scene: &mut dak::Scene, menubar_font: DakotaId) -> DakotaId {
-        let barcolor = scene.create_resource().unwrap();
-        scene
-            .resource_color()
-            .set(&barcolor, dak::dom::Color::new(0.085, 0.09, 0.088, 0.9));
-
         let menubar = scene.create_element().unwrap();
         // Make our bar 16 px tall but stretch across the screen
         scene.width().set(&menubar, dom::Value::Relative(1.0));
         scene
             .height()
             .set(&menubar, dom::Value::Constant(MENUBAR_SIZE));
-        scene.resource().set(&menubar, barcolor);
+        scene.add_class(&menubar, theme::STYLE_MENUBAR);
 
         let name = scene.create_element().unwrap();
         scene.set_text_regular(&name, "Category5");
@@ -152,6 +274,130 @@ impl WindowManager {
         return menubar;
     }
 
+    /// Create a style that fills elements with a solid color
+    fn color_style(scene: &mut dak::Scene, name: &str, color: dom::Color) -> dom::Style {
+        dom::Style {
+            resource: Some(Self::create_color(scene, color)),
+            ..dom::Style::new(name)
+        }
+    }
+
+    /// Create a titlebar style, with rounded corners if the theme has them
+    fn titlebar_style(
+        scene: &mut dak::Scene,
+        name: &str,
+        config: &Theme,
+        color: dom::Color,
+    ) -> dom::Style {
+        if config.corner_radius > 0 {
+            match decoration::create_titlebar_image(
+                scene,
+                color,
+                config.corner_radius,
+                config.titlebar_height,
+            ) {
+                Ok(image) => {
+                    return dom::Style {
+                        resource: Some(image),
+                        ..dom::Style::new(name)
+                    }
+                }
+                Err(e) => log::error!("Could not create titlebar image: {:?}", e),
+            }
+        }
+        Self::color_style(scene, name, color)
+    }
+
+    /// Create the style for drop shadows
+    ///
+    /// The style has no image if shadows are turned off.
+    fn shadow_style(scene: &mut dak::Scene, config: &Theme) -> dom::Style {
+        if config.shadow_radius > 0 && config.shadow_opacity > 0.0 {
+            match shadow::create_shadow_image(scene, config.shadow_radius, config.shadow_opacity) {
+                Ok(image) => {
+                    return dom::Style {
+                        resource: Some(image),
+                        ..dom::Style::new(theme::STYLE_SHADOW)
+                    }
+                }
+                Err(e) => log::error!("Could not create shadow image: {:?}", e),
+            }
+        }
+        dom::Style::new(theme::STYLE_SHADOW)
+    }
+
+    /// Publish the current theme as styles in our scene
+    ///
+    /// Resources can't be redefined, so new ones are created each time.
+    /// Elements using these styles are updated when the scene is next
+    /// recompiled.
+    fn define_styles(&mut self, scene: &mut dak::Scene) {
+        let config = &self.wm_theme;
+        let palette = &config.palette;
+
+        scene.define_font(&self.wm_menubar_font, config.get_font());
+        scene.define_font(&self.wm_title_font, config.get_title_font(false));
+        scene.define_font(&self.wm_title_focused_font, config.get_title_font(true));
+
+        let styles = [
+            dom::Style {
+                font: Some(self.wm_menubar_font.clone()),
+                ..Self::color_style(scene, theme::STYLE_MENUBAR, palette.menubar)
+            },
+            Self::titlebar_style(scene, theme::STYLE_TITLEBAR, config, palette.titlebar),
+            Self::titlebar_style(
+                scene,
+                theme::STYLE_TITLEBAR_FOCUSED,
+                config,
+                palette.titlebar_focused,
+            ),
+            dom::Style {
+                font: Some(self.wm_title_font.clone()),
+                ..dom::Style::new(theme::STYLE_TITLE)
+            },
+            dom::Style {
+                font: Some(self.wm_title_focused_font.clone()),
+                ..dom::Style::new(theme::STYLE_TITLE_FOCUSED)
+            },
+            Self::color_style(scene, theme::STYLE_BORDER, palette.border),
+            Self::color_style(scene, theme::STYLE_BORDER_FOCUSED, palette.border_focused),
+            Self::color_style(scene, theme::STYLE_GRAB_OUTLINE, palette.grab_outline),
+            Self::color_style(scene, theme::STYLE_SNAP_OUTLINE, palette.snap_outline),
+            Self::color_style(scene, theme::STYLE_LOCK_SCREEN, palette.lock_screen),
+            Self::color_style(scene, theme::STYLE_SWITCHER, palette.switcher),
+            Self::color_style(
+                scene,
+                theme::STYLE_SWITCHER_SELECTED,
+                palette.switcher_selected,
+            ),
+            Self::color_style(
+                scene,
+                theme::STYLE_OVERVIEW_SELECTED,
+                palette.overview_selected,
+            ),
+            Self::shadow_style(scene, config),
+            Self::color_style(
+                scene,
+                theme::STYLE_FULLSCREEN_BACKDROP,
+                palette.fullscreen_backdrop,
+            ),
+        ];
+        for style in styles {
+            scene.define_style(style);
+        }
+    }
+
+    /// Change the look of the desktop
+    ///
+    /// The menubar and window decorations are repainted on the next
+    /// redraw. Clients don't need to do anything, unless the size of the
+    /// decorations changed and their windows need to be moved.
+    pub fn set_theme(&mut self, atmos: &mut Atmosphere, scene: &mut dak::Scene, config: &Theme) {
+        self.wm_theme = config.clone();
+        self.define_styles(scene);
+        atmos.set_decoration_size((config.titlebar_height, config.border_width));
+    }
+
     /// Refresh the date and time string in the menubar
     ///
     /// This should be called every time change.
@@ -191,6 +437,9 @@ impl WindowManager {
         if let Some(drm_dev) = output.get_drm_dev() {
             atmos.set_drm_dev(drm_dev);
         }
+        // Only the windows that moved need to be updated each frame
+        atmos.subscribe(Property::SurfacePos);
+        atmos.subscribe(Property::SurfaceSize);
 
         // Create a DOM object that all others will hang off of
         // ------------------------------------------------------------------
@@ -214,26 +463,13 @@ impl WindowManager {
             root_element: root.clone(),
         });
 
-        // First create our menu bar across the top of the screen
+        // First create our menu bar across the top of the screen. The fonts
+        // are defined along with the rest of the theme below.
         // ------------------------------------------------------------------
         let menubar_font = scene.create_font().unwrap();
         let menubar = Self::create_menubar(scene, menubar_font.clone());
         scene.add_child_to_element(&root, menubar.clone());
 
-        scene.define_font(
-            &menubar_font,
-            dom::Font {
-                name: "Menubar".to_string(),
-                font_name: "JetBrainsMono".to_string(),
-                pixel_size: 16,
-                color: Some(dom::Color {
-                    r: 0.941,
-                    g: 0.921,
-                    b: 0.807,
-                    a: 1.0,
-                }),
-            },
-        );
         let datetime = scene.create_element().unwrap();
         scene.height().set(&datetime, dom::Value::Relative(1.0));
         scene.content().set(
@@ -253,7 +489,7 @@ impl WindowManager {
         scene
             .define_resource_from_image(
                 &image,
-                std::path::Path::new("images/cat5_desktop.png"),
+                std::path::Path::new(DESKTOP_BACKGROUND_PATH),
                 dom::Format::ARGB8888,
             )
             .expect("Could not import background image into scene");
@@ -263,18 +499,60 @@ impl WindowManager {
         // ------------------------------------------------------------------
         let cursor = WindowManager::get_default_cursor(scene);
         scene.add_child_to_element(&root, cursor.clone());
+        let theme_cursor = scene.create_element().unwrap();
+
+        // The outline shown during interactive moves and resizes. This
+        // is only added to the desktop while a grab is in progress.
+        // ------------------------------------------------------------------
+        let grab_outline = scene.create_element().unwrap();
+        scene.add_class(&grab_outline, theme::STYLE_GRAB_OUTLINE);
+
+        // The background of the lock screen. This is opaque so that nothing
+        // is visible if the screen locker crashes.
+        // ------------------------------------------------------------------
+        let lock_screen = scene.create_element().unwrap();
+        scene.width().set(&lock_screen, dom::Value::Relative(1.0));
+        scene.height().set(&lock_screen, dom::Value::Relative(1.0));
+        scene.add_class(&lock_screen, theme::STYLE_LOCK_SCREEN);
+
+        let hud = PerfHud::new(scene, menubar_font.clone());
+        let switcher = SwitcherView::new(scene).expect("Could not create the window switcher");
+        let overview = OverviewView::new(scene).expect("Could not create the overview");
 
         let mut ret = WindowManager {
             wm_cursor: Some(cursor.clone()),
             wm_default_cursor: cursor,
+            wm_cursor_theme: CursorTheme::new(),
+            wm_theme_cursor: theme_cursor,
+            wm_cursor_shape: None,
+            wm_cursor_frame: (0, Instant::now()),
+            wm_seat_cursors: Vec::new(),
+            wm_grab_outline: grab_outline,
+            wm_animator: Animator::new(),
+            wm_lock_screen: lock_screen,
+            wm_hud: hud,
+            wm_switcher: switcher,
+            wm_overview: overview,
             wm_scene_root: root,
             wm_menubar_font: menubar_font,
+            wm_title_font: scene.create_font().unwrap(),
+            wm_title_focused_font: scene.create_font().unwrap(),
+            wm_theme: Theme::default(),
+            wm_decorations: Vec::new(),
+            wm_shadows: Shadows::new(),
+            wm_backdrops: Backdrops::new(),
+            wm_thumbnails: ThumbnailCache::new(),
             wm_datetime: datetime,
+            wm_menubar: menubar,
             wm_desktop: desktop,
             wm_atmos_ids: Vec::new(),
             #[cfg(feature = "renderdoc")]
             wm_renderdoc: doc,
         };
+        ret.set_theme(atmos, scene, &Theme::default());
+        if let Err(e) = ret.set_cursor_shape(atmos, scene, CursorShape::Default) {
+            log::error!("Could not set the default cursor: {:?}", e);
+        }
         ret.refresh_datetime(scene);
         // This sets the desktop size
         ret.handle_ood(virtual_output, scene);
@@ -319,6 +597,20 @@ impl WindowManager {
         id: &SurfaceId,
     ) -> Result<()> {
         log::debug!("Closing window {:?}", id);
+        self.wm_thumbnails.remove(id);
+
+        // Toplevels on the desktop fade out before they are removed
+        if atmos.get_animations_enabled() && atmos.a_parent_window.get(id).is_none() {
+            if self.wm_animator.is_unmapping(id) {
+                return Ok(());
+            }
+            if let Some(base) = self.get_element_geometry(scene, id) {
+                let opacity = scene.opacity().get_clone(id).unwrap_or(1.0);
+                self.wm_animator.animate_unmap(id, base, opacity);
+                return Ok(());
+            }
+        }
+        self.wm_animator.cancel(id);
 
         // remove this surface in case it is a toplevel window
         scene.remove_child_from_element(&self.wm_desktop, id)?;
@@ -360,12 +652,137 @@ impl WindowManager {
     ///
     /// This maps a new toplevel surface and places it in the desktop. This
     /// is where the scene element is added to the desktop as a child.
-    fn new_toplevel(&mut self, scene: &mut dak::Scene, surf: &SurfaceId) -> Result<()> {
+    fn new_toplevel(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        surf: &SurfaceId,
+    ) -> Result<()> {
         // We might have not added this element to the desktop, moving to front
         // as part of focus is one of the first things that happens when a
         // new window is created
         scene.add_child_to_element(&self.wm_desktop, surf.clone());
 
+        if atmos.get_animations_enabled() {
+            self.wm_animator.animate_map(surf);
+        }
+
+        Ok(())
+    }
+
+    /// Swap the windows shown on the desktop
+    ///
+    /// The elements for windows on the old workspace are removed from the
+    /// desktop, and the windows on the new workspace are added back in
+    /// their stacking order.
+    fn switch_workspace(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        old: u32,
+        new: u32,
+    ) -> Result<()> {
+        let animate = atmos.get_animations_enabled();
+        // Slide the old workspace out the side opposite of the new one
+        let width = atmos.get_resolution().0 as f32;
+        let slide = if new > old { -width } else { width };
+
+        for id in atmos
+            .windows_on_workspace(old)
+            .filter(|id| !atmos.window_is_minimized(id))
+        {
+            // Leaving windows are removed once they finish sliding away
+            match animate {
+                true => self.wm_animator.animate_workspace_slide(&id, slide, true),
+                false => scene.remove_child_from_element(&self.wm_desktop, &id)?,
+            }
+        }
+
+        // Our window list is front to back, but the last child element
+        // is the one drawn on top. Add them in reverse.
+        let windows: Vec<SurfaceId> = atmos
+            .windows_on_workspace(new)
+            .filter(|id| !atmos.window_is_minimized(id))
+            .collect();
+        for id in windows.into_iter().rev() {
+            if animate {
+                self.wm_animator.animate_workspace_slide(&id, -slide, false);
+            }
+            scene.add_child_to_element(&self.wm_desktop, id);
+        }
+
+        Ok(())
+    }
+
+    /// Move a single window between workspaces
+    fn move_to_workspace(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        old: u32,
+        new: u32,
+    ) -> Result<()> {
+        let current = atmos.get_current_workspace();
+        if old == current {
+            scene.remove_child_from_element(&self.wm_desktop, id)?;
+        } else if new == current && !atmos.window_is_minimized(id) {
+            scene.add_child_to_element(&self.wm_desktop, id.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Hide or show a minimized window
+    ///
+    /// Windows are shown again on top of the others, since they are
+    /// restored by focusing them.
+    fn set_minimized(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        minimized: bool,
+    ) -> Result<()> {
+        if !atmos.window_is_on_current_workspace(id) {
+            return Ok(());
+        }
+
+        match minimized {
+            true => scene.remove_child_from_element(&self.wm_desktop, id)?,
+            false => scene.add_child_to_element(&self.wm_desktop, id.clone()),
+        }
+
+        Ok(())
+    }
+
+    /// Hide the desktop behind the lock screen
+    ///
+    /// The menubar and desktop are removed from the scene entirely, so
+    /// that none of the windows are drawn while the session is locked.
+    fn lock_session(&mut self, scene: &mut dak::Scene) -> Result<()> {
+        scene.remove_child_from_element(&self.wm_scene_root, &self.wm_menubar)?;
+        scene.remove_child_from_element(&self.wm_scene_root, &self.wm_desktop)?;
+        scene.add_child_to_element(&self.wm_scene_root, self.wm_lock_screen.clone());
+        self.raise_cursor(scene)
+    }
+
+    /// Show the desktop again
+    fn unlock_session(&mut self, scene: &mut dak::Scene) -> Result<()> {
+        scene.remove_child_from_element(&self.wm_scene_root, &self.wm_lock_screen)?;
+        scene.add_child_to_element(&self.wm_scene_root, self.wm_menubar.clone());
+        scene.add_child_to_element(&self.wm_scene_root, self.wm_desktop.clone());
+        self.raise_cursor(scene)
+    }
+
+    /// Keep the cursor above everything else in the scene
+    fn raise_cursor(&mut self, scene: &mut dak::Scene) -> Result<()> {
+        if let Some(cursor) = self.wm_cursor.as_ref() {
+            scene.move_child_to_front(&self.wm_scene_root, cursor)?;
+        }
+        for cursor in self.wm_seat_cursors.iter() {
+            scene.move_child_to_front(&self.wm_scene_root, cursor)?;
+        }
         Ok(())
     }
 
@@ -388,6 +805,7 @@ impl WindowManager {
         // Clear the cursor if the client unset it. Otherwise get the
         // new surface, add it as a child and set it.
         self.wm_cursor = surf;
+        self.wm_cursor_shape = None;
 
         if let Some(surf) = self.wm_cursor.as_ref() {
             scene.add_child_to_element(&self.wm_scene_root, surf.clone());
@@ -410,14 +828,139 @@ impl WindowManager {
     /// Used when we are no longer listening to the client's suggested
     /// cursor
     fn reset_cursor(&mut self, atmos: &mut Atmosphere, scene: &mut dak::Scene) -> Result<()> {
+        atmos.set_cursor_surface(None);
+        atmos.set_client_cursor(false);
+        let shape = atmos.get_cursor_shape();
+        self.set_cursor_shape(atmos, scene, shape)
+    }
+
+    /// Point a cursor element at one image of a theme cursor
+    fn apply_cursor_frame(scene: &mut dak::Scene, el: &DakotaId, frame: &CursorFrame) {
+        scene.resource().set(el, frame.cf_image.clone());
+        scene.width().set(el, dom::Value::Constant(frame.cf_size.0));
+        scene
+            .height()
+            .set(el, dom::Value::Constant(frame.cf_size.1));
+    }
+
+    /// Show one of the cursors from our theme
+    ///
+    /// If the theme doesn't have this cursor we fall back to our
+    /// built in one.
+    fn set_cursor_shape(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        shape: CursorShape,
+    ) -> Result<()> {
         if let Some(old) = self.wm_cursor.as_ref() {
             scene.remove_child_from_element(&self.wm_scene_root, old)?;
         }
 
-        scene.add_child_to_element(&self.wm_scene_root, self.wm_default_cursor.clone());
-        self.wm_cursor = Some(self.wm_default_cursor.clone());
-        atmos.set_cursor_hotspot((0, 0));
-        atmos.set_cursor_surface(None);
+        let (el, hotspot) = match self.wm_cursor_theme.get_cursor(scene, shape) {
+            Some(cursor) => {
+                let frame = &cursor.c_frames[0];
+                Self::apply_cursor_frame(scene, &self.wm_theme_cursor, frame);
+                self.wm_cursor_shape = Some(shape);
+                self.wm_cursor_frame = (0, Instant::now());
+                (self.wm_theme_cursor.clone(), frame.cf_hotspot)
+            }
+            None => {
+                self.wm_cursor_shape = None;
+                (self.wm_default_cursor.clone(), (0, 0))
+            }
+        };
+
+        scene.add_child_to_element(&self.wm_scene_root, el.clone());
+        self.wm_cursor = Some(el);
+        atmos.set_cursor_hotspot(hotspot);
+
+        Ok(())
+    }
+
+    /// How long until the next frame of an animated cursor
+    ///
+    /// Returns None if the cursor isn't animated.
+    pub fn get_cursor_frame_delay(&self) -> Option<Duration> {
+        let cursor = self
+            .wm_cursor_theme
+            .get_loaded_cursor(self.wm_cursor_shape?)?;
+        if !cursor.is_animated() {
+            return None;
+        }
+
+        let delay = cursor.c_frames[self.wm_cursor_frame.0].cf_delay;
+        Some(delay.saturating_sub(self.wm_cursor_frame.1.elapsed()))
+    }
+
+    /// Move an animated cursor to its next frame if it is time to
+    fn update_cursor_animation(&mut self, atmos: &mut Atmosphere, scene: &mut dak::Scene) {
+        if self.get_cursor_frame_delay() != Some(Duration::ZERO) {
+            return;
+        }
+        let cursor = match self
+            .wm_cursor_shape
+            .and_then(|shape| self.wm_cursor_theme.get_loaded_cursor(shape))
+        {
+            Some(cursor) => cursor,
+            None => return,
+        };
+
+        let index = (self.wm_cursor_frame.0 + 1) % cursor.c_frames.len();
+        let frame = &cursor.c_frames[index];
+        Self::apply_cursor_frame(scene, &self.wm_theme_cursor, frame);
+        atmos.set_cursor_hotspot(frame.cf_hotspot);
+        self.wm_cursor_frame = (index, Instant::now());
+    }
+
+    /// Show the default cursor in the element of another seat's cursor
+    ///
+    /// Only the first image of animated cursors is used.
+    fn show_seat_cursor_image(&mut self, scene: &mut dak::Scene, el: &DakotaId) {
+        match self.wm_cursor_theme.get_cursor(scene, CursorShape::Default) {
+            Some(cursor) => Self::apply_cursor_frame(scene, el, &cursor.c_frames[0]),
+            None => {
+                // Fall back to our built in cursor
+                let default = &self.wm_default_cursor;
+                scene
+                    .resource()
+                    .set_opt(el, scene.resource().get_clone(default));
+                scene.width().set_opt(el, scene.width().get_clone(default));
+                scene
+                    .height()
+                    .set_opt(el, scene.height().get_clone(default));
+            }
+        }
+    }
+
+    /// Draw a cursor for each seat other than the default one
+    ///
+    /// Clients can't change these, so they always show our default
+    /// cursor. Seats are never removed, so neither are their cursors.
+    fn update_seat_cursors(&mut self, atmos: &Atmosphere, scene: &mut dak::Scene) -> Result<()> {
+        let positions = atmos.get_inactive_seat_cursors();
+
+        while self.wm_seat_cursors.len() < positions.len() {
+            let el = scene.create_element()?;
+            self.show_seat_cursor_image(scene, &el);
+            scene.add_child_to_element(&self.wm_scene_root, el.clone());
+            self.wm_seat_cursors.push(el);
+        }
+
+        let hotspot = self
+            .wm_cursor_theme
+            .get_loaded_cursor(CursorShape::Default)
+            .map(|cursor| cursor.c_frames[0].cf_hotspot)
+            .unwrap_or((0, 0));
+        for (el, (x, y)) in self.wm_seat_cursors.iter().zip(positions.iter()) {
+            scene.offset().set(
+                el,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant((*x as i32).saturating_sub(hotspot.0)),
+                    y: dom::Value::Constant((*y as i32).saturating_sub(hotspot.1)),
+                },
+            );
+        }
 
         Ok(())
     }
@@ -485,6 +1028,169 @@ impl WindowManager {
         Ok(())
     }
 
+    /// Are there window animations in progress?
+    ///
+    /// Frames need to be drawn continuously until these finish.
+    pub fn is_animating(&self) -> bool {
+        self.wm_animator.is_animating()
+    }
+
+    /// Get the position and size last assigned to a desktop element
+    ///
+    /// Returns None if the element is not currently on the desktop.
+    fn get_element_geometry(
+        &self,
+        scene: &dak::Scene,
+        id: &SurfaceId,
+    ) -> Option<((f32, f32), (f32, f32))> {
+        let on_desktop = scene
+            .children()
+            .get(&self.wm_desktop)
+            .map(|c| c.iter().any(|c| c == id))
+            .unwrap_or(false);
+        if !on_desktop {
+            return None;
+        }
+
+        let constant = |val: Option<dom::Value>| match val {
+            Some(dom::Value::Constant(v)) => Some(v as f32),
+            _ => None,
+        };
+        let offset = scene.offset().get_clone(id)?;
+        Some((
+            (constant(Some(offset.x))?, constant(Some(offset.y))?),
+            (
+                constant(scene.width().get_clone(id))?,
+                constant(scene.height().get_clone(id))?,
+            ),
+        ))
+    }
+
+    /// Update the outline drawn over a window being moved or resized
+    ///
+    /// The outline is brighter while the window is snapped to an edge, so
+    /// the user can tell when letting go will line it up.
+    fn update_grab_outline(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+    ) -> Result<()> {
+        // Remove the outline first. If there is still a grab it will be
+        // added back as the last child so that it is on top.
+        scene.remove_child_from_element(&self.wm_desktop, &self.wm_grab_outline)?;
+
+        let (pos, size, snapped) = match atmos.get_grab_feedback() {
+            Some(feedback) => feedback,
+            None => return Ok(()),
+        };
+
+        let (old, new) = match snapped {
+            true => (theme::STYLE_GRAB_OUTLINE, theme::STYLE_SNAP_OUTLINE),
+            false => (theme::STYLE_SNAP_OUTLINE, theme::STYLE_GRAB_OUTLINE),
+        };
+        scene.remove_class(&self.wm_grab_outline, old);
+        scene.add_class(&self.wm_grab_outline, new);
+        scene.offset().set(
+            &self.wm_grab_outline,
+            dom::RelativeOffset {
+                x: dom::Value::Constant(pos.0 as i32),
+                y: dom::Value::Constant(pos.1 as i32),
+            },
+        );
+        scene
+            .width()
+            .set(&self.wm_grab_outline, dom::Value::Constant(size.0 as i32));
+        scene
+            .height()
+            .set(&self.wm_grab_outline, dom::Value::Constant(size.1 as i32));
+        scene.add_child_to_element(&self.wm_desktop, self.wm_grab_outline.clone());
+
+        Ok(())
+    }
+
+    /// Apply the opacity and dimming of every window
+    ///
+    /// The animator sets the opacity of windows that are animating. The
+    /// scene is only changed when a window's opacity or tint changes.
+    fn update_window_appearance(&self, atmos: &Atmosphere, scene: &mut dak::Scene) {
+        for id in self.wm_atmos_ids.iter() {
+            if atmos.a_parent_window.get(id).is_some() {
+                continue;
+            }
+
+            let opacity = Some(atmos.get_window_opacity(id)).filter(|o| *o < 1.0);
+            if !self.wm_animator.is_animating_window(id) && scene.opacity().get_clone(id) != opacity
+            {
+                match opacity {
+                    Some(opacity) => scene.opacity().set(id, opacity),
+                    None => {
+                        scene.opacity().take(id);
+                    }
+                }
+            }
+
+            let tint = atmos.get_window_dim(id).map(|d| (d, d, d));
+            if scene.tint().get_clone(id) != tint {
+                match tint {
+                    Some(tint) => scene.tint().set(id, tint),
+                    None => {
+                        scene.tint().take(id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Update the server side decorations of every window
+    ///
+    /// Decorations are created the first time a window needs them, and
+    /// hidden while it doesn't. They are dropped once the window is gone.
+    fn update_decorations(&mut self, atmos: &mut Atmosphere, scene: &mut dak::Scene) -> Result<()> {
+        let focus = atmos.get_keyboard_focus();
+        let decoration_size = atmos.get_decoration_size();
+        let windows: Vec<SurfaceId> = self
+            .wm_atmos_ids
+            .iter()
+            .filter(|id| atmos.a_parent_window.get(id).is_none())
+            .cloned()
+            .collect();
+
+        for id in windows.iter() {
+            let has_decorations = atmos.window_has_decorations(id);
+            let index = match self.wm_decorations.iter().position(|d| d.get_id() == id) {
+                Some(index) => index,
+                None if has_decorations => {
+                    self.wm_decorations.push(Decoration::new(scene, id)?);
+                    self.wm_decorations.len() - 1
+                }
+                None => continue,
+            };
+            let deco = &mut self.wm_decorations[index];
+
+            deco.set_visible(scene, has_decorations)?;
+            if !has_decorations {
+                continue;
+            }
+
+            deco.set_title(scene, atmos.get_window_title(id));
+            deco.set_focused(scene, focus.as_ref() == Some(id));
+
+            // The window geometry is relative to the desktop, but our
+            // elements are placed relative to the surface
+            let ((wx, wy), size) = atmos.get_window_geometry(id);
+            let (sx, sy) = atmos.a_surface_pos.get_clone(id).unwrap_or((wx, wy));
+            deco.set_geometry(scene, (wx - sx, wy - sy), size, decoration_size);
+        }
+
+        // Keep decorations of closing windows until they are done unmapping
+        let animator = &self.wm_animator;
+        self.wm_decorations.retain(|d| {
+            atmos.get_surface_from_id(d.get_id()).is_some() || animator.is_unmapping(d.get_id())
+        });
+
+        Ok(())
+    }
+
     /// Dispatch window management tasks
     ///
     /// This is where we handle things like surface/element creation, window creation and
@@ -507,13 +1213,36 @@ impl WindowManager {
             Task::close_window(id) => self
                 .close_window(atmos, scene, id)
                 .context("Task: close_window"),
-            Task::new_toplevel(id) => self.new_toplevel(scene, id).context("Task: new_toplevel"),
+            Task::new_toplevel(id) => self
+                .new_toplevel(atmos, scene, id)
+                .context("Task: new_toplevel"),
             Task::set_cursor { id } => self
                 .set_cursor(atmos, scene, id.clone())
                 .context("Task: set_cursor"),
             Task::reset_cursor => self
                 .reset_cursor(atmos, scene)
                 .context("Task: reset_cursor"),
+            Task::set_cursor_shape(shape) => self
+                .set_cursor_shape(atmos, scene, *shape)
+                .context("Task: set_cursor_shape"),
+            Task::switch_workspace { old, new } => self
+                .switch_workspace(atmos, scene, *old, *new)
+                .context("Task: switch_workspace"),
+            Task::move_to_workspace { id, old, new } => self
+                .move_to_workspace(atmos, scene, id, *old, *new)
+                .context("Task: move_to_workspace"),
+            Task::set_minimized { id, minimized } => self
+                .set_minimized(atmos, scene, id, *minimized)
+                .context("Task: set_minimized"),
+            Task::lock_session => self.lock_session(scene).context("Task: lock_session"),
+            Task::unlock_session => self.unlock_session(scene).context("Task: unlock_session"),
+            Task::new_lock_surface(id) => {
+                scene.add_child_to_element(&self.wm_lock_screen, id.clone());
+                Ok(())
+            }
+            Task::remove_lock_surface(id) => scene
+                .remove_child_from_element(&self.wm_lock_screen, id)
+                .context("Task: remove_lock_surface"),
         };
 
         match err {
@@ -550,6 +1279,9 @@ impl WindowManager {
                 },
             );
         }
+        if let Err(e) = self.update_seat_cursors(atmos, scene) {
+            log::error!("Could not draw the cursors of other seats: {:?}", e);
+        }
         // ----------------------------------------------------------------
 
         // Draw all of our windows on the desktop
@@ -569,11 +1301,22 @@ impl WindowManager {
         // do the draw call separately due to the borrow checker
         // throwing a fit if it is in the loop above.
         //
-        // This section really just updates the size and position of all the
-        // surfaces. They should already have images attached, and damage will
-        // be calculated from the result.
+        // This section really just updates the size and position of the
+        // surfaces that ways changed. They should already have images attached,
+        // and damage will be calculated from the result.
         // ----------------------------------------------------------------
+        let changes = atmos.take_changes();
         for id in self.wm_atmos_ids.iter() {
+            // Send any pending frame callbacks
+            atmos.send_frame_callbacks_for_surf(id);
+            atmos.mark_feedback_drawn(id);
+
+            if !changes.contains(Property::SurfacePos, id)
+                && !changes.contains(Property::SurfaceSize, id)
+            {
+                continue;
+            }
+
             // Now render the windows
             // get parameters
             // ----------------------------------------------------------------
@@ -600,9 +1343,73 @@ impl WindowManager {
                 .height()
                 .set(id, dom::Value::Constant(surface_size.1 as i32));
             // ----------------------------------------------------------------
+        }
 
-            // Send any pending frame callbacks
+        // Lock surfaces cover the whole screen
+        let lock_surfaces = atmos.get_lock_surfaces().to_vec();
+        for id in lock_surfaces.iter() {
+            let surface_size = *atmos.a_surface_size.get(id).unwrap();
+            scene.offset().set(
+                id,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant(0),
+                    y: dom::Value::Constant(0),
+                },
+            );
+            scene
+                .width()
+                .set(id, dom::Value::Constant(surface_size.0 as i32));
+            scene
+                .height()
+                .set(id, dom::Value::Constant(surface_size.1 as i32));
             atmos.send_frame_callbacks_for_surf(id);
+            atmos.mark_feedback_drawn(id);
+        }
+
+        // Animations are applied on top of the window geometry
+        self.wm_overview
+            .update_windows(atmos, scene, &mut self.wm_animator);
+        let skip = !atmos.get_animations_enabled();
+        for id in self.wm_animator.apply(atmos, scene, skip) {
+            if let Err(e) = scene.remove_child_from_element(&self.wm_desktop, &id) {
+                log::error!("Could not remove animated window {:?}: {:?}", id, e);
+            }
+        }
+
+        self.update_window_appearance(atmos, scene);
+        if let Err(e) = self.update_decorations(atmos, scene) {
+            log::error!("Could not update window decorations: {:?}", e);
+        }
+        let windows: Vec<SurfaceId> = self
+            .wm_atmos_ids
+            .iter()
+            .filter(|id| atmos.a_parent_window.get(id).is_none())
+            .cloned()
+            .collect();
+        if let Err(e) = self.wm_shadows.update(
+            atmos,
+            scene,
+            &windows,
+            self.wm_theme.shadow_radius,
+            &self.wm_animator,
+        ) {
+            log::error!("Could not update window shadows: {:?}", e);
+        }
+        if let Err(e) = self
+            .wm_backdrops
+            .update(atmos, scene, &self.wm_desktop, &windows)
+        {
+            log::error!("Could not update fullscreen backdrops: {:?}", e);
+        }
+        if let Err(e) = self.update_grab_outline(atmos, scene) {
+            log::error!("Could not update the grab outline: {:?}", e);
+        }
+        let animating = self.wm_animator.is_animating();
+        if let Err(e) = self
+            .wm_overview
+            .update_highlight(atmos, scene, &self.wm_desktop, animating)
+        {
+            log::error!("Could not update the overview highlight: {:?}", e);
         }
     }
 
@@ -623,9 +1430,13 @@ impl WindowManager {
         // iterate through all the tasks that ways left
         // us in this hemisphere
         //  (aka process the work queue)
+        let handoff = trace::span("vkcomp", "atmosphere_handoff");
         while let Some(task) = atmos.get_next_wm_task() {
             self.process_task(atmos, scene, &task);
         }
+        drop(handoff);
+
+        self.update_cursor_animation(atmos, scene);
 
         // If nothing has changed then we can exit
         //
@@ -636,9 +1447,41 @@ impl WindowManager {
 
         // start recording how much time we spent doing graphics
         log::debug!("_____________________________ FRAME BEGIN");
+        let start = std::time::Instant::now();
+        let _frame = trace::span("vkcomp", "render_frame");
+
+        // Show or hide the performance overlay
+        let show_hud = atmos.get_hud_enabled();
+        if show_hud != self.wm_hud.is_visible() {
+            self.wm_hud
+                .set_visible(scene, &self.wm_scene_root, output, show_hud)
+                .context("Toggling the HUD")?;
+            self.raise_cursor(scene)?;
+        }
+
+        // Show or hide the window switcher
+        let show_switcher = atmos.is_switcher_open() && !atmos.get_locked();
+        if show_switcher != self.wm_switcher.is_visible() {
+            self.wm_switcher
+                .set_visible(scene, &self.wm_scene_root, show_switcher)
+                .context("Toggling the window switcher")?;
+            self.raise_cursor(scene)?;
+        }
 
         // Update our dakota element positions
+        let record = trace::span("vkcomp", "record_draw");
         self.record_draw(atmos, scene);
+        if self.wm_hud.is_visible() {
+            self.wm_hud
+                .update(scene, atmos.get_resolution())
+                .context("Updating the HUD")?;
+        }
+        if self.wm_switcher.is_visible() {
+            self.wm_switcher
+                .update(atmos, scene, &mut self.wm_thumbnails)
+                .context("Updating the window switcher")?;
+        }
+        drop(record);
         scene
             .recompile(&virtual_output)
             .expect("Failed to recalculate layout");
@@ -646,6 +1489,7 @@ impl WindowManager {
         output
             .redraw(virtual_output, scene)
             .context("Redrawing WM Output")?;
+        self.wm_hud.frame_drawn(output, start.elapsed());
 
         atmos.clear_changed();
         log::debug!("_____________________________ FRAME END");
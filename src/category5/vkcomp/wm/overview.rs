@@ -0,0 +1,152 @@
+// Overview mode
+//
+// While the overview is open each window on the current workspace is
+// animated into its slot in the grid, and animated back once it closes.
+// The windows are moved with transforms, so their elements keep their
+// place in the desktop and the stacking order doesn't change. Once the
+// windows have settled a highlight is drawn over the selected one.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use super::animation::{AnimState, Animator};
+use crate::category5::atmosphere::*;
+use crate::category5::theme;
+use dak::dom;
+use dak::DakotaId;
+use utils::{log, Result};
+
+/// How far the highlight extends past the edges of the selected window
+static HIGHLIGHT_BORDER: f32 = 8.0;
+
+/// The windows moved into the overview
+pub struct OverviewView {
+    /// Drawn over the selected window
+    ovw_highlight: DakotaId,
+    /// Each window in the overview, and the state it was moved into
+    ovw_windows: Vec<(SurfaceId, AnimState)>,
+}
+
+impl OverviewView {
+    pub fn new(scene: &mut dak::Scene) -> Result<Self> {
+        let highlight = scene.create_element()?;
+        scene.add_class(&highlight, theme::STYLE_OVERVIEW_SELECTED);
+
+        Ok(Self {
+            ovw_highlight: highlight,
+            ovw_windows: Vec::new(),
+        })
+    }
+
+    /// Start moving windows in or out of the overview
+    ///
+    /// Windows that were added to the overview, or whose slot changed
+    /// because another window was closed, are moved into their slots.
+    /// Windows that left it are moved back to where they belong. This
+    /// should be called before animations are applied for the frame.
+    ///
+    /// Windows are drawn much smaller than their size in the overview,
+    /// so their images are sampled with mipmaps while they are in it.
+    pub fn update_windows(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        animator: &mut Animator,
+    ) {
+        let overview = atmos.get_overview();
+        let in_overview =
+            |id: &SurfaceId| overview.map(|o| o.ov_windows.contains(id)).unwrap_or(false);
+
+        for (id, state) in self.ovw_windows.iter() {
+            // Closed windows are already fading out
+            if !in_overview(id) && atmos.get_surface_from_id(id).is_some() {
+                let from = animator.get_current_state(id).unwrap_or(*state);
+                animator.animate_from_overview(id, from);
+                Self::set_sampler_mode(atmos, scene, id, dak::SamplerMode::Linear);
+            }
+        }
+        self.ovw_windows.retain(|(id, _)| in_overview(id));
+
+        let overview = match overview {
+            Some(overview) => overview,
+            None => return,
+        };
+        for (id, slot) in overview.ov_windows.iter().zip(overview.ov_slots.iter()) {
+            // Clients may attach a new buffer while in the overview
+            Self::set_sampler_mode(atmos, scene, id, dak::SamplerMode::Trilinear);
+
+            let state = AnimState::for_overview_slot(atmos, id, slot);
+            let old = match self.ovw_windows.iter().position(|(w, _)| w == id) {
+                Some(index) if self.ovw_windows[index].1 == state => continue,
+                Some(index) => Some(self.ovw_windows.remove(index).1),
+                None => None,
+            };
+
+            let from = animator
+                .get_current_state(id)
+                .or(old)
+                .unwrap_or(AnimState::identity());
+            animator.animate_to_overview(id, from, state);
+            self.ovw_windows.push((id.clone(), state));
+        }
+    }
+
+    /// Change how the current buffer of a window is sampled
+    fn set_sampler_mode(
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        mode: dak::SamplerMode,
+    ) {
+        let res = match atmos.a_surf_resource.get_clone(id) {
+            Some(res) => res,
+            None => return,
+        };
+        if let Err(e) = scene.set_resource_sampler_mode(&res, mode) {
+            log::error!("Could not set sampler mode of {:?}: {:?}", id, e);
+        }
+    }
+
+    /// Show the highlight over the selected window
+    ///
+    /// The highlight is hidden while windows are moving, since they
+    /// haven't reached their slots. It is added to `desktop` as its last
+    /// child, so this should be called after anything else is added.
+    pub fn update_highlight(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        desktop: &DakotaId,
+        animating: bool,
+    ) -> Result<()> {
+        scene.remove_child_from_element(desktop, &self.ovw_highlight)?;
+
+        let overview = match atmos.get_overview() {
+            Some(overview) if !animating => overview,
+            _ => return Ok(()),
+        };
+        let slot = match overview.ov_slots.get(overview.ov_selected) {
+            Some(slot) => slot,
+            None => return Ok(()),
+        };
+
+        scene.offset().set(
+            &self.ovw_highlight,
+            dom::RelativeOffset {
+                x: dom::Value::Constant((slot.os_pos.0 - HIGHLIGHT_BORDER) as i32),
+                y: dom::Value::Constant((slot.os_pos.1 - HIGHLIGHT_BORDER) as i32),
+            },
+        );
+        scene.width().set(
+            &self.ovw_highlight,
+            dom::Value::Constant((slot.os_size.0 + HIGHLIGHT_BORDER * 2.0) as i32),
+        );
+        scene.height().set(
+            &self.ovw_highlight,
+            dom::Value::Constant((slot.os_size.1 + HIGHLIGHT_BORDER * 2.0) as i32),
+        );
+        scene.add_child_to_element(desktop, self.ovw_highlight.clone());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+// Drop shadows
+//
+// Floating windows get a soft shadow around their frame. Every shadow
+// uses the same small nine-patch image from the theme's shadow style,
+// which Thundr stretches around each window. The middle of the image is
+// empty, so a shadow is only drawn outside of the frame and can be a
+// child of the window element without covering the window. This keeps
+// it moving with the window and its animations.
+//
+// Shadows are only drawn. They are not part of any input region, so
+// input over them goes to whatever is below.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use super::animation::Animator;
+use crate::category5::atmosphere::*;
+use crate::category5::theme;
+use dak::dom;
+use dak::DakotaId;
+use utils::Result;
+
+/// Create the shadow image
+///
+/// The image is a nine-patch with borders of `radius` pixels around an
+/// empty middle. The shadow fades out from `opacity` next to the middle
+/// to nothing at the edge of the image, with rounded corners.
+pub fn create_shadow_image(scene: &mut dak::Scene, radius: u32, opacity: f32) -> Result<DakotaId> {
+    let size = radius * 2 + 1;
+    let r = radius as f32;
+
+    // Distance from the middle pixel along one axis
+    let distance = |p: u32| match p {
+        p if p < radius => r - (p as f32 + 0.5),
+        p if p > radius => p as f32 + 0.5 - (r + 1.0),
+        _ => 0.0,
+    };
+
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = (distance(x), distance(y));
+            let alpha = match x == radius && y == radius {
+                // The window covers the middle
+                true => 0.0,
+                false => {
+                    let t = ((dx * dx + dy * dy).sqrt() / r).min(1.0);
+                    opacity * (1.0 - t) * (1.0 - t)
+                }
+            };
+            data.extend_from_slice(&[0, 0, 0, (alpha * 255.0).round() as u8]);
+        }
+    }
+
+    let res = scene.create_resource()?;
+    scene.define_resource_from_bits(&res, &data, size, size, 0, dom::Format::ARGB8888)?;
+    scene.resource_nine_patch().set(
+        &res,
+        dom::NinePatch {
+            left: radius,
+            right: radius,
+            top: radius,
+            bottom: radius,
+        },
+    );
+
+    Ok(res)
+}
+
+/// The shadows of all floating windows
+pub struct Shadows {
+    /// Each window with a shadow, and the element drawing it
+    sh_windows: Vec<(SurfaceId, DakotaId)>,
+}
+
+impl Shadows {
+    pub fn new() -> Self {
+        Self {
+            sh_windows: Vec::new(),
+        }
+    }
+
+    /// Create a shadow element and attach it below the window's children
+    fn attach(scene: &mut dak::Scene, id: &SurfaceId) -> Result<DakotaId> {
+        let el = scene.create_element()?;
+        scene.add_class(&el, theme::STYLE_SHADOW);
+        // Shadows are outside of the window, don't clip them to it
+        scene.unbounded_subsurface().set(&el, true);
+
+        let bottom = scene
+            .children()
+            .get(id)
+            .and_then(|children| children.first().cloned());
+        scene.add_child_to_element(id, el.clone());
+        if let Some(bottom) = bottom.as_ref() {
+            scene.reorder_children_element(id, dak::SubsurfaceOrder::Below, &el, bottom)?;
+        }
+
+        Ok(el)
+    }
+
+    /// Update the shadows of the root windows in `windows`
+    ///
+    /// Shadows are added to floating windows and placed around their
+    /// frame, and removed from windows that are no longer floating. A
+    /// `radius` of zero removes all shadows. Shadows of closed windows
+    /// are kept until they finish fading out.
+    pub fn update(
+        &mut self,
+        atmos: &Atmosphere,
+        scene: &mut dak::Scene,
+        windows: &[SurfaceId],
+        radius: u32,
+        animator: &Animator,
+    ) -> Result<()> {
+        let r = radius as f32;
+
+        for id in windows.iter() {
+            let index = self.sh_windows.iter().position(|(w, _)| w == id);
+            if radius == 0 || !atmos.window_has_shadow(id) {
+                if let Some(index) = index {
+                    let (_, el) = self.sh_windows.swap_remove(index);
+                    scene.remove_child_from_element(id, &el)?;
+                }
+                continue;
+            }
+
+            let el = match index {
+                Some(index) => self.sh_windows[index].1.clone(),
+                None => {
+                    let el = Self::attach(scene, id)?;
+                    self.sh_windows.push((id.clone(), el.clone()));
+                    el
+                }
+            };
+
+            // The frame is relative to the desktop, but our element is
+            // placed relative to the surface
+            let ((fx, fy), (fw, fh)) = atmos.get_window_frame(id);
+            let (sx, sy) = atmos.a_surface_pos.get_clone(id).unwrap_or((fx, fy));
+            scene.offset().set(
+                &el,
+                dom::RelativeOffset {
+                    x: dom::Value::Constant((fx - sx - r) as i32),
+                    y: dom::Value::Constant((fy - sy - r) as i32),
+                },
+            );
+            scene
+                .width()
+                .set(&el, dom::Value::Constant((fw + r * 2.0) as i32));
+            scene
+                .height()
+                .set(&el, dom::Value::Constant((fh + r * 2.0) as i32));
+        }
+
+        self.sh_windows
+            .retain(|(id, _)| atmos.get_surface_from_id(id).is_some() || animator.is_unmapping(id));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+// Window switcher overlay
+//
+// While the alt-tab switcher is open a panel is drawn in the middle of
+// the screen with a thumbnail and the title of each window in it, and a
+// highlight behind the selected one. The panel is a child of the scene
+// root added after the desktop, so it is drawn over every window
+// including fullscreen ones. Thumbnails are refreshed each frame while
+// the panel is shown, which only copies windows that have new contents.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use super::thumbnail::ThumbnailCache;
+use crate::category5::atmosphere::*;
+use crate::category5::theme;
+use dak::dom;
+use dak::DakotaId;
+use utils::Result;
+
+/// The largest size of a thumbnail
+static THUMBNAIL_SIZE: (i32, i32) = (240, 160);
+static PADDING: i32 = 12;
+static TITLE_HEIGHT: i32 = 24;
+
+/// The elements for one window in the switcher
+struct SwitcherEntry {
+    /// The highlight, which the other elements are children of
+    se_background: DakotaId,
+    se_thumbnail: DakotaId,
+    se_title: DakotaId,
+    se_title_text: Option<String>,
+}
+
+impl SwitcherEntry {
+    fn new(scene: &mut dak::Scene) -> Result<Self> {
+        let background = scene.create_element()?;
+
+        let thumbnail = scene.create_element()?;
+        scene.add_child_to_element(&background, thumbnail.clone());
+
+        let title = scene.create_element()?;
+        scene.add_class(&title, theme::STYLE_TITLE);
+        scene.add_child_to_element(&background, title.clone());
+
+        Ok(Self {
+            se_background: background,
+            se_thumbnail: thumbnail,
+            se_title: title,
+            se_title_text: None,
+        })
+    }
+}
+
+/// The switcher overlay
+pub struct SwitcherView {
+    /// The background of the overlay, all other elements are its children
+    sv_panel: DakotaId,
+    /// Elements for each window, only the first sv_shown are in the panel
+    sv_entries: Vec<SwitcherEntry>,
+    sv_shown: usize,
+    sv_visible: bool,
+}
+
+impl SwitcherView {
+    /// Create the overlay's panel
+    ///
+    /// The switcher is not shown until `set_visible` is called.
+    pub fn new(scene: &mut dak::Scene) -> Result<Self> {
+        let panel = scene.create_element()?;
+        scene.add_class(&panel, theme::STYLE_SWITCHER);
+
+        Ok(Self {
+            sv_panel: panel,
+            sv_entries: Vec::new(),
+            sv_shown: 0,
+            sv_visible: false,
+        })
+    }
+
+    /// Set the position and size of an element within its parent
+    fn place(scene: &mut dak::Scene, el: &DakotaId, pos: (i32, i32), size: (i32, i32)) {
+        scene.offset().set(
+            el,
+            dom::RelativeOffset {
+                x: dom::Value::Constant(pos.0),
+                y: dom::Value::Constant(pos.1),
+            },
+        );
+        scene.width().set(el, dom::Value::Constant(size.0));
+        scene.height().set(el, dom::Value::Constant(size.1));
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.sv_visible
+    }
+
+    /// Show or hide the overlay
+    ///
+    /// The panel is added to `root` as its last child, the caller should
+    /// raise the cursor above it afterwards.
+    pub fn set_visible(
+        &mut self,
+        scene: &mut dak::Scene,
+        root: &DakotaId,
+        visible: bool,
+    ) -> Result<()> {
+        self.sv_visible = visible;
+        match visible {
+            true => scene.add_child_to_element(root, self.sv_panel.clone()),
+            false => scene.remove_child_from_element(root, &self.sv_panel)?,
+        }
+        Ok(())
+    }
+
+    /// Update the overlay with the switcher's windows
+    ///
+    /// The thumbnails shrink if the windows don't fit across the screen.
+    /// This should be called before the scene is recompiled for the
+    /// next frame.
+    pub fn update(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        thumbnails: &mut ThumbnailCache,
+    ) -> Result<()> {
+        let switcher = match atmos.get_switcher() {
+            Some(switcher) => switcher.clone(),
+            None => return Ok(()),
+        };
+        let resolution = atmos.get_resolution();
+        let count = switcher.sw_windows.len() as i32;
+
+        // Fit the entries across the screen
+        let max_width = (resolution.0 as i32 - PADDING) / count.max(1) - PADDING * 3;
+        let thumb_width = THUMBNAIL_SIZE.0.min(max_width).max(1);
+        let thumb_height = THUMBNAIL_SIZE.1 * thumb_width / THUMBNAIL_SIZE.0;
+        let entry_size = (
+            thumb_width + PADDING * 2,
+            thumb_height + TITLE_HEIGHT + PADDING * 2,
+        );
+
+        for entry in self.sv_entries[..self.sv_shown].iter() {
+            scene.remove_child_from_element(&self.sv_panel, &entry.se_background)?;
+        }
+        while self.sv_entries.len() < switcher.sw_windows.len() {
+            self.sv_entries.push(SwitcherEntry::new(scene)?);
+        }
+
+        for (i, (id, entry)) in switcher
+            .sw_windows
+            .iter()
+            .zip(self.sv_entries.iter_mut())
+            .enumerate()
+        {
+            Self::place(
+                scene,
+                &entry.se_background,
+                (PADDING + i as i32 * (entry_size.0 + PADDING), PADDING),
+                entry_size,
+            );
+            match i == switcher.sw_selected {
+                true => scene.add_class(&entry.se_background, theme::STYLE_SWITCHER_SELECTED),
+                false => scene.remove_class(&entry.se_background, theme::STYLE_SWITCHER_SELECTED),
+            }
+
+            // Center the thumbnail in its space, keeping the window's shape
+            let max_size = (thumb_width as u32, thumb_height as u32);
+            match thumbnails.get_thumbnail(atmos, scene, id, max_size) {
+                Some(resource) => {
+                    let buffer_size = atmos.a_buffer_size.get_clone(id).unwrap_or((1.0, 1.0));
+                    let size = ThumbnailCache::get_scaled_size(
+                        (buffer_size.0 as u32, buffer_size.1 as u32),
+                        max_size,
+                    );
+                    let size = (size.0 as i32, size.1 as i32);
+                    Self::place(
+                        scene,
+                        &entry.se_thumbnail,
+                        (
+                            PADDING + (thumb_width - size.0) / 2,
+                            PADDING + (thumb_height - size.1) / 2,
+                        ),
+                        size,
+                    );
+                    scene.resource().set(&entry.se_thumbnail, resource);
+                }
+                None => scene.resource().set_opt(&entry.se_thumbnail, None),
+            }
+
+            let title = atmos.get_window_title(id);
+            if title != entry.se_title_text {
+                scene.set_text_regular(&entry.se_title, title.as_deref().unwrap_or(""));
+                entry.se_title_text = title;
+            }
+            Self::place(
+                scene,
+                &entry.se_title,
+                (PADDING, PADDING + thumb_height),
+                (thumb_width, TITLE_HEIGHT),
+            );
+
+            scene.add_child_to_element(&self.sv_panel, entry.se_background.clone());
+        }
+        self.sv_shown = switcher.sw_windows.len();
+
+        // Center the panel on the screen
+        let panel_size = (
+            PADDING + count * (entry_size.0 + PADDING),
+            entry_size.1 + PADDING * 2,
+        );
+        Self::place(
+            scene,
+            &self.sv_panel,
+            (
+                (resolution.0 as i32 - panel_size.0) / 2,
+                (resolution.1 as i32 - panel_size.1) / 2,
+            ),
+            panel_size,
+        );
+
+        Ok(())
+    }
+}
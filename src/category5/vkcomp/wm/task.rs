@@ -5,6 +5,7 @@
 // Austin Shafer - 2020
 #![allow(dead_code)]
 use crate::category5::atmosphere::SurfaceId;
+use crate::category5::vkcomp::wm::cursor::CursorShape;
 
 // Tell wm the desktop background
 //
@@ -32,4 +33,12 @@ pub enum Task {
     place_subsurface_below { id: SurfaceId, other: SurfaceId },
     set_cursor { id: Option<SurfaceId> },
     reset_cursor,
+    set_cursor_shape(CursorShape),
+    switch_workspace { old: u32, new: u32 },
+    move_to_workspace { id: SurfaceId, old: u32, new: u32 },
+    set_minimized { id: SurfaceId, minimized: bool },
+    lock_session,
+    unlock_session,
+    new_lock_surface(SurfaceId),
+    remove_lock_surface(SurfaceId),
 }
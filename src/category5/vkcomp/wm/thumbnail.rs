@@ -0,0 +1,125 @@
+// Window thumbnails
+//
+// Thumbnails are scaled down copies of a window's contents, used for
+// things like an overview of all windows or an alt-tab switcher. They
+// are made by blitting the window's image into a smaller one on the GPU,
+// and are only redrawn after the window commits a new buffer. Only the
+// window's own surface is copied, not its subsurfaces or decorations.
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+
+use crate::category5::atmosphere::*;
+use dak::DakotaId;
+use utils::log;
+
+/// A scaled down copy of one window
+struct Thumbnail {
+    t_id: SurfaceId,
+    t_resource: DakotaId,
+    /// The window resource this was last copied from
+    t_source: DakotaId,
+    t_size: (u32, u32),
+}
+
+/// The thumbnails of all windows that have been asked for
+pub struct ThumbnailCache {
+    tc_thumbnails: Vec<Thumbnail>,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> Self {
+        Self {
+            tc_thumbnails: Vec::new(),
+        }
+    }
+
+    /// Get the size of a thumbnail for a window of `size`
+    ///
+    /// The thumbnail keeps the window's aspect ratio and fits within
+    /// `max_size`. Windows that are already small enough are not scaled up.
+    pub fn get_scaled_size(size: (u32, u32), max_size: (u32, u32)) -> (u32, u32) {
+        let scale = (max_size.0 as f32 / size.0 as f32)
+            .min(max_size.1 as f32 / size.1 as f32)
+            .min(1.0);
+
+        (
+            ((size.0 as f32 * scale).round() as u32).max(1),
+            ((size.1 as f32 * scale).round() as u32).max(1),
+        )
+    }
+
+    /// Scale the window's current contents into its thumbnail
+    ///
+    /// Returns false if the thumbnail could not be updated, in which
+    /// case it should be made again.
+    fn update(scene: &mut dak::Scene, thumb: &mut Thumbnail, src: &DakotaId) -> bool {
+        if let Err(e) = scene.update_scaled_copy(&thumb.t_resource, src) {
+            log::debug!("Could not update thumbnail of {:?}: {:?}", thumb.t_id, e);
+            return false;
+        }
+        thumb.t_source = src.clone();
+        true
+    }
+
+    /// Get a thumbnail of a window that fits within `max_size`
+    ///
+    /// The thumbnail is cached, and is only redrawn when the window has
+    /// new contents. Windows whose contents can't be scaled on the GPU,
+    /// such as solid colors and shm buffers sampled in place, return their
+    /// own resource instead, which the renderer scales when drawing.
+    pub fn get_thumbnail(
+        &mut self,
+        atmos: &mut Atmosphere,
+        scene: &mut dak::Scene,
+        id: &SurfaceId,
+        max_size: (u32, u32),
+    ) -> Option<DakotaId> {
+        let src = atmos.a_surf_resource.get_clone(id)?;
+        let buffer_size = atmos.a_buffer_size.get_clone(id)?;
+        if buffer_size.0 < 1.0 || buffer_size.1 < 1.0 || max_size.0 == 0 || max_size.1 == 0 {
+            return None;
+        }
+        let size = Self::get_scaled_size((buffer_size.0 as u32, buffer_size.1 as u32), max_size);
+        let stale = atmos.a_thumbnail_stale.get_clone(id).unwrap_or(true);
+        atmos.a_thumbnail_stale.set(id, false);
+
+        if let Some(index) = self.tc_thumbnails.iter().position(|t| t.t_id == *id) {
+            let thumb = &mut self.tc_thumbnails[index];
+            if thumb.t_size == size
+                && ((!stale && thumb.t_source == src) || Self::update(scene, thumb, &src))
+            {
+                return Some(thumb.t_resource.clone());
+            }
+            // The size or format of the window changed
+            self.tc_thumbnails.remove(index);
+        }
+
+        let resource = scene.create_resource().ok()?;
+        if let Err(e) = scene.define_resource_as_scaled_copy(&resource, &src, size.0, size.1) {
+            log::debug!("Using window {:?} as its own thumbnail: {:?}", id, e);
+            return Some(src);
+        }
+
+        self.tc_thumbnails.push(Thumbnail {
+            t_id: id.clone(),
+            t_resource: resource.clone(),
+            t_source: src,
+            t_size: size,
+        });
+        Some(resource)
+    }
+
+    /// Drop the thumbnail of a window that is closing
+    pub fn remove(&mut self, id: &SurfaceId) {
+        self.tc_thumbnails.retain(|t| t.t_id != *id);
+    }
+
+    /// Forget all thumbnails
+    ///
+    /// This is used when the GPU was lost, since Dakota dropped all of
+    /// the images. They will be made again when next used.
+    pub fn clear(&mut self) {
+        self.tc_thumbnails.clear();
+    }
+}
@@ -0,0 +1,177 @@
+// Per-client resource accounting
+//
+// Every surface, buffer and frame callback a client creates costs us
+// memory, and buffers also hold on to fds and GPU imports. We count how
+// many of each every client has alive, and disconnect a client with
+// wl_display.no_memory once it goes over a limit, so that a misbehaving
+// client can't exhaust the compositor by leaking thousands of them.
+//
+// The counts are released in each resource's `destroyed` handler. All of
+// a client's resources are destroyed before it is disconnected, so a
+// client which is gone but still has counts left means one of our
+// handlers isn't releasing what it reserved.
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use crate::category5::ClientInfo;
+use utils::log;
+use ws::backend::protocol::ProtocolError;
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The kinds of resources we keep count of
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceKind {
+    Surface,
+    Buffer,
+    Callback,
+}
+
+impl ResourceKind {
+    /// The most of this resource a single client may have alive
+    fn limit(&self) -> usize {
+        match self {
+            ResourceKind::Surface => 1024,
+            ResourceKind::Buffer => 4096,
+            ResourceKind::Callback => 1024,
+        }
+    }
+}
+
+/// The resources one client has alive
+#[derive(Debug, Default, Serialize)]
+pub struct ClientResources {
+    /// The atmosphere id of this client, or None if it wasn't registered
+    pub id: Option<usize>,
+    pub surfaces: usize,
+    pub buffers: usize,
+    pub callbacks: usize,
+}
+
+impl ClientResources {
+    fn count(&mut self, kind: ResourceKind) -> &mut usize {
+        match kind {
+            ResourceKind::Surface => &mut self.surfaces,
+            ResourceKind::Buffer => &mut self.buffers,
+            ResourceKind::Callback => &mut self.callbacks,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.surfaces == 0 && self.buffers == 0 && self.callbacks == 0
+    }
+}
+
+/// Resource counts for all connected clients
+pub struct ResourceAccounting {
+    ra_clients: HashMap<ws::backend::ClientId, ClientResources>,
+}
+
+impl ResourceAccounting {
+    pub fn new() -> Self {
+        Self {
+            ra_clients: HashMap::new(),
+        }
+    }
+
+    /// Count a new resource for this client
+    ///
+    /// This should be called before the resource is initialized. If the
+    /// client is over its limit it is disconnected and false is returned,
+    /// in which case the resource should not be created.
+    pub fn reserve(
+        &mut self,
+        client: &ws::Client,
+        dhandle: &ws::DisplayHandle,
+        kind: ResourceKind,
+    ) -> bool {
+        let res = self
+            .ra_clients
+            .entry(client.id())
+            .or_insert_with(|| ClientResources {
+                id: client
+                    .get_data::<ClientInfo>()
+                    .map(|info| info.ci_id.get_raw_id()),
+                ..Default::default()
+            });
+
+        let count = res.count(kind);
+        if *count >= kind.limit() {
+            log::error!(
+                "Client {:?} created more than {} {:?} resources, disconnecting it",
+                res.id,
+                kind.limit(),
+                kind
+            );
+            client.kill(
+                dhandle,
+                ProtocolError {
+                    // wl_display.error.no_memory
+                    code: 2,
+                    object_id: 1,
+                    object_interface: "wl_display".to_string(),
+                    message: format!("Too many {:?} resources", kind),
+                },
+            );
+            return false;
+        }
+
+        *count += 1;
+        true
+    }
+
+    /// Release a resource counted by `reserve`
+    ///
+    /// This should be called from the resource's `destroyed` handler.
+    pub fn release(&mut self, client: ws::backend::ClientId, kind: ResourceKind) {
+        let res = match self.ra_clients.get_mut(&client) {
+            Some(res) => res,
+            None => {
+                log::error!("Releasing a {:?} that was never reserved", kind);
+                return;
+            }
+        };
+
+        let count = res.count(kind);
+        match count.checked_sub(1) {
+            Some(c) => *count = c,
+            None => log::error!("Releasing a {:?} that was never reserved", kind),
+        }
+
+        if res.is_empty() {
+            self.ra_clients.remove(&client);
+        }
+    }
+
+    /// Check for clients that disconnected without releasing everything
+    ///
+    /// Every remaining resource of a disconnected client was leaked by
+    /// our handlers. The counts are logged and dropped.
+    pub fn check_for_leaks(&mut self, dhandle: &ws::DisplayHandle) {
+        let handle = dhandle.backend_handle();
+        self.ra_clients.retain(|client, res| {
+            if handle.get_client_data(client.clone()).is_ok() {
+                return true;
+            }
+
+            // This only costs us memory, so don't take down the compositor
+            log::error!(
+                "Disconnected client {:?} leaked {} surfaces, {} buffers and {} callbacks",
+                res.id,
+                res.surfaces,
+                res.buffers,
+                res.callbacks
+            );
+            false
+        });
+    }
+
+    /// Get the resource counts of every client with resources alive
+    pub fn get_clients(&self) -> Vec<&ClientResources> {
+        let mut clients: Vec<_> = self.ra_clients.values().collect();
+        clients.sort_by_key(|res| res.id);
+        clients
+    }
+}
@@ -0,0 +1,210 @@
+// Implementation of xdg-activation-v1
+//
+// Activation lets a client ask for one of its windows to be focused,
+// such as when a link is opened in an already running browser. To
+// keep arbitrary clients from stealing focus, the request must carry a
+// token that we handed out. Tokens are only valid if they were created
+// by the client the user is interacting with, or by our own launcher
+// when running a command.
+//
+// https://wayland.app/protocols/xdg-activation-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::surface::Surface;
+use crate::category5::atmosphere::SurfaceId;
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use wayland_protocols::xdg::activation::v1::server::{
+    xdg_activation_token_v1 as token, xdg_activation_v1 as activation,
+};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a token may be used for after it is created
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A token we handed out
+struct ActivationToken {
+    at_created: Instant,
+    /// Can this token be used to change focus?
+    at_valid: bool,
+}
+
+/// The activation tokens which have not been used yet
+///
+/// These are owned by the input subsystem, since the launcher creates
+/// them when running commands.
+pub struct ActivationTokens {
+    at_tokens: HashMap<String, ActivationToken>,
+}
+
+/// Create a random token string
+fn generate_token_name() -> String {
+    let mut bytes = [0u8; 16];
+    unsafe {
+        libc::getrandom(bytes.as_mut_ptr() as *mut libc::c_void, bytes.len(), 0);
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl ActivationTokens {
+    pub fn new() -> Self {
+        Self {
+            at_tokens: HashMap::new(),
+        }
+    }
+
+    /// Create a new token
+    ///
+    /// Invalid tokens are still handed out so the client can't tell
+    /// the difference, they just won't do anything when used.
+    pub fn issue(&mut self, valid: bool) -> String {
+        self.at_tokens
+            .retain(|_, t| t.at_created.elapsed() < TOKEN_TIMEOUT);
+
+        let name = generate_token_name();
+        self.at_tokens.insert(
+            name.clone(),
+            ActivationToken {
+                at_created: Instant::now(),
+                at_valid: valid,
+            },
+        );
+        name
+    }
+
+    /// Use up a token
+    ///
+    /// Returns true if the token may be used to change focus.
+    fn consume(&mut self, name: &str) -> bool {
+        match self.at_tokens.remove(name) {
+            Some(t) => t.at_valid && t.at_created.elapsed() < TOKEN_TIMEOUT,
+            None => false,
+        }
+    }
+}
+
+/// The user data of a token being created by a client
+#[derive(Default)]
+pub struct TokenRequest {
+    /// The serial of the input event that caused this request
+    tr_serial: Option<u32>,
+    /// Has the token been handed out?
+    tr_committed: bool,
+}
+
+impl Climate {
+    /// Focus a surface's window if the token allows it
+    fn activate_surface(&mut self, name: &str, surf_id: SurfaceId) {
+        if !self.c_input.i_activation_tokens.consume(name) {
+            log::debug!("Ignoring activation request with invalid token");
+            return;
+        }
+
+        let mut atmos = self.c_atmos.lock().unwrap();
+        if atmos.get_locked() {
+            return;
+        }
+        let root = atmos.a_root_window.get_clone(&surf_id).unwrap_or(surf_id);
+
+        // Only mapped toplevels are assigned a workspace
+        let workspace = match atmos.get_window_workspace(&root) {
+            Some(workspace) => workspace,
+            None => {
+                log::debug!("Ignoring activation request for unmapped surface");
+                return;
+            }
+        };
+
+        log::debug!("Activating window {:?}", root);
+        atmos.switch_to_workspace(workspace);
+        atmos.focus_on(Some(root));
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<activation::XdgActivationV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<activation::XdgActivationV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<activation::XdgActivationV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &activation::XdgActivationV1,
+        request: activation::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            activation::Request::GetActivationToken { id } => {
+                data_init.init(id, Mutex::new(TokenRequest::default()));
+            }
+            activation::Request::Activate { token, surface } => {
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                let surf_id = surf.lock().unwrap().s_id.clone();
+                state.activate_surface(&token, surf_id);
+            }
+            activation::Request::Destroy => {}
+            _ => log::error!("Unimplemented xdg activation request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<token::XdgActivationTokenV1, Mutex<TokenRequest>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &token::XdgActivationTokenV1,
+        request: token::Request,
+        data: &Mutex<TokenRequest>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut req = data.lock().unwrap();
+        if req.tr_committed && !matches!(request, token::Request::Destroy) {
+            resource.post_error(token::Error::AlreadyUsed, "Token was already committed");
+            return;
+        }
+
+        match request {
+            token::Request::SetSerial { serial, seat } => req.tr_serial = Some(serial),
+            // We don't restrict tokens to a particular app or surface
+            token::Request::SetAppId { app_id } => {}
+            token::Request::SetSurface { surface } => {}
+            token::Request::Commit => {
+                req.tr_committed = true;
+
+                // Only the client the user is interacting with may hand
+                // out focus, and only in response to an input event
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let id = super::utils::get_id_from_client(&mut atmos, client.clone());
+                let valid = req.tr_serial.is_some() && atmos.get_client_in_focus() == Some(id);
+                drop(atmos);
+
+                let name = state.c_input.i_activation_tokens.issue(valid);
+                resource.done(name);
+            }
+            token::Request::Destroy => {}
+            _ => log::error!("Unimplemented xdg activation token request {:?}", request),
+        }
+    }
+}
@@ -7,6 +7,7 @@
 // Austin Shafer - 2019
 pub extern crate wayland_server as ws;
 
+use super::accounting::ResourceKind;
 use super::surface::Surface;
 use super::{utils, wl_region};
 use crate::category5::Climate;
@@ -46,7 +47,12 @@ impl ws::Dispatch<wlci::WlCompositor, ()> for Climate {
     ) {
         match request {
             ws::protocol::wl_compositor::Request::CreateSurface { id } => {
-                state.create_surface(client, id, data_init)
+                if state
+                    .c_resources
+                    .reserve(client, dhandle, ResourceKind::Surface)
+                {
+                    state.create_surface(client, id, data_init)
+                }
             }
             ws::protocol::wl_compositor::Request::CreateRegion { id } => {
                 wl_region::register_new(id, data_init)
@@ -0,0 +1,101 @@
+// Implementation of content-type-v1
+//
+// Clients use this to tell us what their surface shows, such as a game
+// or a video. We record it in the atmosphere so that the rest of the
+// compositor can adjust how these surfaces are presented.
+//
+// https://wayland.app/protocols/content-type-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::surface::Surface;
+use crate::category5::Climate;
+use utils::log;
+use ws::protocol::wl_surface as wlsi;
+use ws::Resource;
+
+use wayland_protocols::wp::content_type::v1::server::{
+    wp_content_type_manager_v1 as manager, wp_content_type_v1 as content_type,
+};
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::WpContentTypeManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::WpContentTypeManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::WpContentTypeManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::WpContentTypeManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::GetSurfaceContentType { id, surface } => {
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                let mut surf = surf.lock().unwrap();
+                if surf.s_state.cs_content_type.is_some() {
+                    resource.post_error(
+                        manager::Error::AlreadyConstructed as u32,
+                        "This surface already has a content type object".to_string(),
+                    );
+                    return;
+                }
+
+                surf.s_state.cs_content_type = Some(data_init.init(id, surface.clone()));
+            }
+            manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented content type request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<content_type::WpContentTypeV1, wlsi::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &content_type::WpContentTypeV1,
+        request: content_type::Request,
+        surface: &wlsi::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        // The content type no longer matters once the surface is gone
+        if !surface.is_alive() {
+            return;
+        }
+
+        let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+        let mut surf = surf.lock().unwrap();
+        let state = &mut surf.s_state;
+
+        match request {
+            content_type::Request::SetContentType { content_type: kind } => {
+                state.cs_content = kind.into_result().unwrap_or(content_type::Type::None);
+            }
+            // The surface goes back to no content type on the next commit
+            content_type::Request::Destroy => {
+                state.cs_content_type = None;
+                state.cs_content = content_type::Type::None;
+            }
+            _ => log::error!("Unimplemented content type request {:?}", request),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// Implementation of wlr-export-dmabuf-unstable-v1
+//
+// This lets screen recorders like the wlrobs OBS plugin capture our
+// output. Dakota copies each composited frame into a dmabuf, which is
+// handed to the client along with the frame's size and format. The copy
+// runs on the GPU after the frame, and the client is told the frame is
+// ready once the frame's timeline point has been reached.
+//
+// https://wayland.app/protocols/wlr-export-dmabuf-unstable-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::security::{self, Privilege};
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use super::protocol::export_dmabuf::{
+    zwlr_export_dmabuf_frame_v1 as frame, zwlr_export_dmabuf_manager_v1 as manager,
+};
+
+use std::os::unix::io::AsFd;
+
+/// DRM_FORMAT_ARGB8888 from drm_fourcc.h
+const DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+
+/// How often to check if the GPU has finished copying exported frames
+pub static EXPORT_POLL_MS: usize = 1;
+
+/// Get the current CLOCK_MONOTONIC time as (seconds, nanoseconds)
+fn get_monotonic_time() -> (u64, u32) {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    (ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+impl Climate {
+    /// Send the frame we just drew to any waiting screen recorders
+    ///
+    /// This should be called after the output has been redrawn. Each
+    /// capture request only receives one frame, clients ask for the next
+    /// one once they are done with it. The frame is described right away,
+    /// and `send_export_ready` sends the ready event once it is copied.
+    pub fn send_export_frames(&mut self) {
+        for frame in std::mem::take(&mut self.c_export_frames) {
+            if !frame.is_alive() {
+                continue;
+            }
+
+            let dmabuf = match self.c_output.export_frame() {
+                Ok(dmabuf) => dmabuf,
+                Err(e) => {
+                    log::error!("Could not export frame for recording: {:?}", e);
+                    frame.cancel(frame::CancelReason::Temporary);
+                    continue;
+                }
+            };
+
+            let modifier = dmabuf.db_planes.first().map(|p| p.db_mods).unwrap_or(0);
+            frame.frame(
+                dmabuf.db_width as u32,
+                dmabuf.db_height as u32,
+                0,
+                0,
+                0,
+                // The flags enum is not a bitfield so we can't send an
+                // empty set. Transient only asks the client to copy the
+                // frame before processing it, which is always safe.
+                frame::Flags::Transient,
+                DRM_FORMAT_ARGB8888,
+                (modifier >> 32) as u32,
+                (modifier & 0xffffffff) as u32,
+                dmabuf.db_planes.len() as u32,
+            );
+
+            for (i, plane) in dmabuf.db_planes.iter().enumerate() {
+                let size = plane.db_offset + plane.db_stride * dmabuf.db_height as u32;
+                frame.object(
+                    i as u32,
+                    plane.db_fd.as_fd(),
+                    size,
+                    plane.db_offset,
+                    plane.db_stride,
+                    plane.db_plane_idx,
+                );
+            }
+
+            let point = self.c_output.get_last_frame_point();
+            self.c_export_pending.push((frame, point));
+        }
+
+        self.send_export_ready();
+    }
+
+    /// Tell screen recorders which frames the GPU has finished copying
+    pub fn send_export_ready(&mut self) {
+        let output = &self.c_output;
+        self.c_export_pending.retain(|(frame, point)| {
+            if !output.is_frame_point_done(*point) {
+                return true;
+            }
+            if frame.is_alive() {
+                let (secs, nsecs) = get_monotonic_time();
+                frame.ready((secs >> 32) as u32, (secs & 0xffffffff) as u32, nsecs);
+            }
+            false
+        });
+    }
+
+    /// Are there exported frames the GPU is still copying
+    pub fn has_export_frames_pending(&self) -> bool {
+        !self.c_export_pending.is_empty()
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ZwlrExportDmabufManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ZwlrExportDmabufManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    // Only clients the user allowed may see this global
+    fn can_view(client: ws::Client, global_data: &()) -> bool {
+        security::client_has_privilege(&client, Privilege::ScreenCapture)
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ZwlrExportDmabufManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ZwlrExportDmabufManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            // We only have one output, and the cursor is always part of
+            // the composited frame
+            manager::Request::CaptureOutput {
+                frame,
+                overlay_cursor,
+                output,
+            } => {
+                let frame = data_init.init(frame, ());
+                state.c_export_frames.push(frame);
+                // Make sure a frame is drawn even if nothing changed
+                state.c_output.request_redraw();
+            }
+            manager::Request::Destroy => {}
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<frame::ZwlrExportDmabufFrameV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &frame::ZwlrExportDmabufFrameV1,
+        request: frame::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            frame::Request::Destroy => {}
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &frame::ZwlrExportDmabufFrameV1,
+        data: &(),
+    ) {
+        state.c_export_frames.retain(|f| f.id() != resource.id());
+        state
+            .c_export_pending
+            .retain(|(f, _)| f.id() != resource.id());
+    }
+}
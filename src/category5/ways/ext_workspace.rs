@@ -0,0 +1,297 @@
+// Implementation of ext-workspace-v1
+//
+// This lets panels and docks list our workspaces and switch
+// between them. Category5 has a single workspace group covering
+// its output, containing a fixed set of workspaces.
+//
+// https://wayland.app/protocols/ext-workspace-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use crate::category5::atmosphere::NUM_WORKSPACES;
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use super::protocol::ext_workspace::{
+    ext_workspace_group_handle_v1 as group_handle, ext_workspace_handle_v1 as ws_handle,
+    ext_workspace_manager_v1 as manager,
+};
+
+/// Private data for a workspace handle
+///
+/// This records which workspace the handle represents and which
+/// manager it was created for.
+pub struct WorkspaceHandleData {
+    whd_index: u32,
+    whd_manager: ws::backend::ObjectId,
+}
+
+/// One client's bound ext_workspace_manager_v1
+pub struct WorkspaceManagerInstance {
+    wmi_manager: manager::ExtWorkspaceManagerV1,
+    wmi_group: group_handle::ExtWorkspaceGroupHandleV1,
+    /// Handles for every workspace, indexed by workspace number
+    wmi_workspaces: Vec<ws_handle::ExtWorkspaceHandleV1>,
+    /// Activation requested by the client. This is applied
+    /// once the client sends commit.
+    wmi_pending_activate: Option<u32>,
+}
+
+/// Our ext-workspace state
+///
+/// This tracks every bound manager so that they can be notified
+/// when the current workspace changes.
+pub struct ExtWorkspaceState {
+    ews_instances: Vec<WorkspaceManagerInstance>,
+    /// The active workspace last sent to clients
+    ews_last_active: u32,
+}
+
+impl ExtWorkspaceState {
+    pub fn new() -> Self {
+        Self {
+            ews_instances: Vec::new(),
+            ews_last_active: 0,
+        }
+    }
+}
+
+/// Get the state bits to send for a workspace
+fn workspace_state(index: u32, active: u32) -> ws_handle::State {
+    match index == active {
+        true => ws_handle::State::Active,
+        false => ws_handle::State::empty(),
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ExtWorkspaceManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ExtWorkspaceManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mgr = data_init.init(resource, ());
+        let active = state.c_atmos.lock().unwrap().get_current_workspace();
+
+        // Create our one workspace group
+        let group = match client
+            .create_resource::<group_handle::ExtWorkspaceGroupHandleV1, (), Self>(
+                handle,
+                mgr.version(),
+                (),
+            ) {
+            Ok(group) => group,
+            Err(e) => {
+                log::error!("Could not create workspace group: {:?}", e);
+                return;
+            }
+        };
+        mgr.workspace_group(&group);
+        group.capabilities(group_handle::GroupCapabilities::empty());
+        // Our group spans all outputs this client knows about
+        for output in state
+            .c_outputs
+            .iter()
+            .filter(|o| o.id().same_client_as(&mgr.id()))
+        {
+            group.output_enter(output);
+        }
+
+        let mut workspaces = Vec::new();
+        for i in 0..NUM_WORKSPACES {
+            let data = WorkspaceHandleData {
+                whd_index: i,
+                whd_manager: mgr.id(),
+            };
+            let workspace = match client
+                .create_resource::<ws_handle::ExtWorkspaceHandleV1, _, Self>(
+                    handle,
+                    mgr.version(),
+                    data,
+                ) {
+                Ok(workspace) => workspace,
+                Err(e) => {
+                    log::error!("Could not create workspace handle: {:?}", e);
+                    return;
+                }
+            };
+
+            mgr.workspace(&workspace);
+            workspace.id(format!("category5-workspace-{}", i + 1));
+            workspace.name(format!("{}", i + 1));
+            workspace.coordinates(i.to_ne_bytes().to_vec());
+            workspace.capabilities(ws_handle::WorkspaceCapabilities::Activate);
+            workspace.state(workspace_state(i, active));
+            group.workspace_enter(&workspace);
+            workspaces.push(workspace);
+        }
+        mgr.done();
+
+        state
+            .c_ext_workspace
+            .ews_instances
+            .push(WorkspaceManagerInstance {
+                wmi_manager: mgr,
+                wmi_group: group,
+                wmi_workspaces: workspaces,
+                wmi_pending_activate: None,
+            });
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ExtWorkspaceManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ExtWorkspaceManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::Commit => {
+                let pending = state
+                    .c_ext_workspace
+                    .ews_instances
+                    .iter_mut()
+                    .find(|i| i.wmi_manager.id() == resource.id())
+                    .and_then(|i| i.wmi_pending_activate.take());
+
+                if let Some(workspace) = pending {
+                    state.c_atmos.lock().unwrap().switch_to_workspace(workspace);
+                }
+            }
+            manager::Request::Stop => {
+                resource.finished();
+                state
+                    .c_ext_workspace
+                    .ews_instances
+                    .retain(|i| i.wmi_manager.id() != resource.id());
+            }
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &manager::ExtWorkspaceManagerV1,
+        data: &(),
+    ) {
+        state
+            .c_ext_workspace
+            .ews_instances
+            .retain(|i| i.wmi_manager.id() != resource.id());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<group_handle::ExtWorkspaceGroupHandleV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &group_handle::ExtWorkspaceGroupHandleV1,
+        request: group_handle::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        // We don't advertise create_workspace, so there is nothing to do
+        log::debug!("Ignoring ext_workspace_group_handle request {:?}", request);
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &group_handle::ExtWorkspaceGroupHandleV1,
+        data: &(),
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<ws_handle::ExtWorkspaceHandleV1, WorkspaceHandleData> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &ws_handle::ExtWorkspaceHandleV1,
+        request: ws_handle::Request,
+        data: &WorkspaceHandleData,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            ws_handle::Request::Activate => {
+                if let Some(instance) = state
+                    .c_ext_workspace
+                    .ews_instances
+                    .iter_mut()
+                    .find(|i| i.wmi_manager.id() == data.whd_manager)
+                {
+                    instance.wmi_pending_activate = Some(data.whd_index);
+                }
+            }
+            ws_handle::Request::Destroy => {}
+            // Only activate is advertised in our capabilities
+            _ => log::debug!("Ignoring ext_workspace_handle request {:?}", request),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &ws_handle::ExtWorkspaceHandleV1,
+        data: &WorkspaceHandleData,
+    ) {
+        for instance in state.c_ext_workspace.ews_instances.iter_mut() {
+            instance
+                .wmi_workspaces
+                .retain(|w| Resource::id(w) != Resource::id(resource));
+        }
+    }
+}
+
+impl Climate {
+    /// Tell workspace groups about a newly bound output
+    ///
+    /// Our single group spans every output, so it enters any wl_output
+    /// that the same client binds.
+    pub fn workspace_output_enter(&mut self, output: &ws::protocol::wl_output::WlOutput) {
+        for instance in self.c_ext_workspace.ews_instances.iter() {
+            if instance.wmi_group.id().same_client_as(&output.id()) {
+                instance.wmi_group.output_enter(output);
+                instance.wmi_manager.done();
+            }
+        }
+    }
+
+    /// Notify workspace managers of a change in the active workspace
+    ///
+    /// This should be called after input and wayland events have been
+    /// handled. It does nothing if the active workspace hasn't changed.
+    pub fn send_workspace_state(&mut self) {
+        let active = self.c_atmos.lock().unwrap().get_current_workspace();
+        if active == self.c_ext_workspace.ews_last_active {
+            return;
+        }
+        self.c_ext_workspace.ews_last_active = active;
+
+        for instance in self.c_ext_workspace.ews_instances.iter() {
+            for workspace in instance.wmi_workspaces.iter() {
+                let index = workspace
+                    .data::<WorkspaceHandleData>()
+                    .map(|d| d.whd_index)
+                    .unwrap_or(0);
+                workspace.state(workspace_state(index, active));
+            }
+            instance.wmi_manager.done();
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Implementation of wlr-gamma-control-unstable-v1
+//
+// This lets night light tools like gammastep and redshift change the
+// gamma ramps of our output. The ramps are applied by Dakota as part
+// of the output's color correction pass, so this works on every
+// display backend.
+//
+// https://wayland.app/protocols/wlr-gamma-control-unstable-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::security::{self, Privilege};
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use super::protocol::gamma_control::{
+    zwlr_gamma_control_manager_v1 as manager, zwlr_gamma_control_v1 as control,
+};
+
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::OwnedFd;
+
+/// Read the red, green and blue ramps from a client's gamma table
+///
+/// Returns None if the table is not exactly three ramps of `size` entries.
+fn read_gamma_ramps(fd: OwnedFd, size: usize) -> Option<[Vec<u16>; 3]> {
+    let file = File::from(fd);
+    let len = size * 3 * std::mem::size_of::<u16>();
+    if file.metadata().ok()?.len() != len as u64 {
+        return None;
+    }
+
+    let mut bytes = vec![0; len];
+    file.read_exact_at(&mut bytes, 0).ok()?;
+    let table: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .collect();
+
+    Some([
+        table[..size].to_vec(),
+        table[size..size * 2].to_vec(),
+        table[size * 2..].to_vec(),
+    ])
+}
+
+impl Climate {
+    /// Is this the gamma control currently in charge of our output?
+    fn is_active_gamma_control(&self, resource: &control::ZwlrGammaControlV1) -> bool {
+        self.c_gamma_control
+            .as_ref()
+            .map(|c| c.id() == resource.id())
+            .unwrap_or(false)
+    }
+
+    /// Tell the client its gamma ramps can no longer be used
+    ///
+    /// This happens when we move to a new display.
+    pub fn fail_gamma_control(&mut self) {
+        if let Some(control) = self.c_gamma_control.take() {
+            control.failed();
+        }
+    }
+
+    /// Stop using the client's gamma ramps and restore the original gamma
+    fn reset_gamma(&mut self) {
+        self.c_gamma_control = None;
+        if let Err(e) = self.c_output.set_gamma_ramps(None) {
+            log::error!("Could not reset gamma: {:?}", e);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ZwlrGammaControlManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ZwlrGammaControlManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    // Only clients the user allowed may see this global
+    fn can_view(client: ws::Client, global_data: &()) -> bool {
+        security::client_has_privilege(&client, Privilege::GammaControl)
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ZwlrGammaControlManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ZwlrGammaControlManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::GetGammaControl { id, output } => {
+                let gamma_control = data_init.init(id, ());
+
+                // Only one gamma control may exist for an output
+                if state.c_gamma_control.is_some() {
+                    gamma_control.failed();
+                    return;
+                }
+
+                gamma_control.gamma_size(state.c_output.get_gamma_size());
+                state.c_gamma_control = Some(gamma_control);
+            }
+            manager::Request::Destroy => {}
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<control::ZwlrGammaControlV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &control::ZwlrGammaControlV1,
+        request: control::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            control::Request::SetGamma { fd } => {
+                // Controls that have failed are inert
+                if !state.is_active_gamma_control(resource) {
+                    return;
+                }
+
+                let size = state.c_output.get_gamma_size() as usize;
+                let ramps = match read_gamma_ramps(fd, size) {
+                    Some(ramps) => ramps,
+                    None => {
+                        resource.post_error(
+                            control::Error::InvalidGamma as u32,
+                            "Gamma table does not match the gamma size".to_string(),
+                        );
+                        state.reset_gamma();
+                        return;
+                    }
+                };
+
+                if let Err(e) = state.c_output.set_gamma_ramps(Some(ramps)) {
+                    log::error!("Could not set gamma: {:?}", e);
+                    resource.failed();
+                    state.reset_gamma();
+                }
+            }
+            control::Request::Destroy => {}
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &control::ZwlrGammaControlV1,
+        data: &(),
+    ) {
+        // Restore the original gamma once the client is done
+        if state.is_active_gamma_control(resource) {
+            state.reset_gamma();
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// Implementation of ext-idle-notify-v1 and idle-inhibit-unstable-v1
+//
+// Idle notifications tell clients like swayidle when the user has
+// stopped using the computer, so they can lock the screen. Idle
+// inhibitors let clients like video players keep the user from going
+// idle while their surface is shown. We also power off the display
+// ourselves once the user has been idle long enough.
+//
+// https://wayland.app/protocols/ext-idle-notify-v1
+// https://wayland.app/protocols/idle-inhibit-unstable-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::surface::Surface;
+use crate::category5::atmosphere::{Atmosphere, SurfaceId};
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use wayland_protocols::ext::idle_notify::v1::server::{
+    ext_idle_notification_v1 as notification, ext_idle_notifier_v1 as notifier,
+};
+use wayland_protocols::wp::idle_inhibit::zv1::server::{
+    zwp_idle_inhibit_manager_v1 as inhibit_manager, zwp_idle_inhibitor_v1 as inhibitor,
+};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many seconds the user must be idle before we power off the
+/// display, if `CATEGORY5_BLANK_TIMEOUT` is not set
+const DEFAULT_BLANK_TIMEOUT_SECS: u64 = 600;
+
+/// A client's request to be told when the user is idle
+struct IdleNotification {
+    in_resource: notification::ExtIdleNotificationV1,
+    in_timeout: Duration,
+    /// Have we told the client the user is idle?
+    in_idle: bool,
+}
+
+/// Our idle tracking state
+///
+/// The time since the last input event is tracked by the input
+/// subsystem. This holds everything that reacts to it.
+pub struct IdleState {
+    is_notifications: Vec<IdleNotification>,
+    /// Idle inhibitors and the surface each one belongs to
+    is_inhibitors: Vec<(inhibitor::ZwpIdleInhibitorV1, SurfaceId)>,
+    /// How long until we power off the display, None if we never do
+    is_blank_timeout: Option<Duration>,
+}
+
+impl IdleState {
+    /// Create our idle state
+    ///
+    /// The display blanking timeout is read from `CATEGORY5_BLANK_TIMEOUT`
+    /// in seconds. A timeout of zero disables blanking.
+    pub fn new() -> Self {
+        let secs = match std::env::var("CATEGORY5_BLANK_TIMEOUT") {
+            Ok(val) => val.parse().unwrap_or_else(|e| {
+                log::error!("Invalid CATEGORY5_BLANK_TIMEOUT {}: {:?}", val, e);
+                DEFAULT_BLANK_TIMEOUT_SECS
+            }),
+            Err(_) => DEFAULT_BLANK_TIMEOUT_SECS,
+        };
+
+        Self {
+            is_notifications: Vec::new(),
+            is_inhibitors: Vec::new(),
+            is_blank_timeout: match secs {
+                0 => None,
+                s => Some(Duration::from_secs(s)),
+            },
+        }
+    }
+
+    /// Is a surface with an idle inhibitor being shown?
+    fn is_inhibited(&self, atmos: &Atmosphere) -> bool {
+        self.is_inhibitors.iter().any(|(_, id)| {
+            atmos.get_surface_from_id(id).is_some() && atmos.window_is_on_current_workspace(id)
+        })
+    }
+}
+
+impl Climate {
+    /// Send idle notifications and power the display on or off
+    ///
+    /// This should be called after input events have been handled.
+    /// Returns how long until the next timeout expires, so that the
+    /// event loop can wake up for it.
+    pub fn update_idle_state(&mut self) -> Option<Duration> {
+        // Inhibitors keep the user active as long as they are shown
+        if self.c_idle.is_inhibited(&self.c_atmos.lock().unwrap()) {
+            self.c_input.reset_idle_time();
+        }
+        let idle_time = self.c_input.get_idle_time();
+
+        let mut next: Option<Duration> = None;
+        let mut check_timeout = |timeout: Duration| match timeout.checked_sub(idle_time) {
+            Some(remaining) if !remaining.is_zero() => {
+                next = Some(next.map_or(remaining, |n| n.min(remaining)));
+                false
+            }
+            _ => true,
+        };
+
+        for n in self.c_idle.is_notifications.iter_mut() {
+            let idle = check_timeout(n.in_timeout);
+            if idle != n.in_idle {
+                n.in_idle = idle;
+                match idle {
+                    true => n.in_resource.idled(),
+                    false => n.in_resource.resumed(),
+                }
+            }
+        }
+
+        if let Some(timeout) = self.c_idle.is_blank_timeout {
+            let idle = check_timeout(timeout);
+            if idle == self.c_output.is_powered() {
+                log::debug!("Powering display {}", if idle { "off" } else { "on" });
+                if let Err(e) = self.c_output.set_power(!idle) {
+                    log::error!("Could not set display power: {:?}", e);
+                }
+            }
+        }
+
+        next
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<notifier::ExtIdleNotifierV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<notifier::ExtIdleNotifierV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<notifier::ExtIdleNotifierV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &notifier::ExtIdleNotifierV1,
+        request: notifier::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            notifier::Request::GetIdleNotification { id, timeout, seat } => {
+                let notification = data_init.init(id, ());
+                // The next idle update will send idled if needed
+                state.c_idle.is_notifications.push(IdleNotification {
+                    in_resource: notification,
+                    in_timeout: Duration::from_millis(timeout as u64),
+                    in_idle: false,
+                });
+            }
+            notifier::Request::Destroy => {}
+            _ => log::error!("Unimplemented idle notifier request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<notification::ExtIdleNotificationV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &notification::ExtIdleNotificationV1,
+        request: notification::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &notification::ExtIdleNotificationV1,
+        data: &(),
+    ) {
+        state
+            .c_idle
+            .is_notifications
+            .retain(|n| n.in_resource.id() != resource.id());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<inhibit_manager::ZwpIdleInhibitManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<inhibit_manager::ZwpIdleInhibitManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<inhibit_manager::ZwpIdleInhibitManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &inhibit_manager::ZwpIdleInhibitManagerV1,
+        request: inhibit_manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            inhibit_manager::Request::CreateInhibitor { id, surface } => {
+                let inhibitor = data_init.init(id, ());
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                let surf_id = surf.lock().unwrap().s_id.clone();
+
+                state.c_idle.is_inhibitors.push((inhibitor, surf_id));
+            }
+            inhibit_manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented idle inhibit manager request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<inhibitor::ZwpIdleInhibitorV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &inhibitor::ZwpIdleInhibitorV1,
+        request: inhibitor::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &inhibitor::ZwpIdleInhibitorV1,
+        data: &(),
+    ) {
+        state
+            .c_idle
+            .is_inhibitors
+            .retain(|(i, _)| i.id() != resource.id());
+    }
+}
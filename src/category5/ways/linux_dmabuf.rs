@@ -6,6 +6,7 @@
 extern crate wayland_protocols;
 extern crate wayland_server as ws;
 
+use super::accounting::{ResourceAccounting, ResourceKind};
 use crate::category5::Atmosphere;
 use crate::category5::Climate;
 use utils::log;
@@ -17,9 +18,7 @@ use wayland_protocols::wp::linux_dmabuf::zv1::server::{
     zwp_linux_buffer_params_v1 as zlbpv1, zwp_linux_dmabuf_v1 as zldv1,
 };
 
-#[cfg(debug_assertions)]
-use std::os::unix::io::AsRawFd;
-use std::os::unix::io::OwnedFd;
+use std::os::unix::io::{AsRawFd, OwnedFd};
 use std::sync::{Arc, Mutex};
 
 // drm formats specified in mesa's private wl_drm
@@ -106,6 +105,9 @@ impl ws::Dispatch<zlbpv1::ZwpLinuxBufferParamsV1, Arc<Mutex<Params>>> for Climat
         data.lock().unwrap().handle_request(
             &mut state.c_scene,
             state.c_atmos.lock().as_mut().unwrap(),
+            &mut state.c_resources,
+            client,
+            dhandle,
             request,
             resource,
             data_init,
@@ -127,11 +129,14 @@ struct Params {
 }
 
 impl Params {
-    #[allow(unused_variables)]
+    #[allow(unused_variables, clippy::too_many_arguments)]
     fn handle_request(
         &mut self,
         scene: &mut dak::Scene,
         atmos: &mut Atmosphere,
+        resources: &mut ResourceAccounting,
+        client: &ws::Client,
+        dhandle: &ws::DisplayHandle,
         req: zlbpv1::Request,
         params: &zlbpv1::ZwpLinuxBufferParamsV1,
         data_init: &mut ws::DataInit<'_, Climate>,
@@ -165,6 +170,10 @@ impl Params {
                     }
                 };
 
+                if !resources.reserve(client, dhandle, ResourceKind::Buffer) {
+                    return;
+                }
+
                 let buffer = data_init.init(buffer_id, dmabuf);
 
                 params.created(&buffer);
@@ -231,10 +240,11 @@ impl ws::Dispatch<wl_buffer::WlBuffer, dak::Dmabuf> for Climate {
 
     fn destroyed(
         state: &mut Self,
-        _client: ws::backend::ClientId,
+        client: ws::backend::ClientId,
         _resource: &wl_buffer::WlBuffer,
         data: &dak::Dmabuf,
     ) {
+        state.c_resources.release(client, ResourceKind::Buffer);
         // Close our dmabuf fd since this object was deleted
         log::debug!(
             "Destroying wl_buffer: closing dmabuf with fd {}",
@@ -52,23 +52,41 @@
 // Austin Shafer - 2019
 
 // Supported protocols
+pub mod activation;
 pub mod compositor;
+mod content_type;
 mod data_devices;
+pub mod export_dmabuf;
+pub mod ext_workspace;
+mod gamma_control;
+pub mod idle;
 mod keyboard;
 pub mod linux_dmabuf;
 mod pointer;
+mod pointer_gestures;
+pub mod presentation;
 pub mod protocol;
+pub mod screencopy;
 pub mod seat;
+pub mod security;
+pub mod session_lock;
 pub mod shm;
+mod single_pixel_buffer;
 pub mod surface;
+pub mod tablet;
+pub mod tearing_control;
+mod touch;
+mod viewporter;
 mod wl_drm;
 mod wl_output;
 pub mod wl_region;
 mod wl_shell;
 mod wl_subcompositor;
+pub mod xdg_decoration;
 pub mod xdg_shell;
 
 // Utils
+pub mod accounting;
 pub mod role;
 pub mod task;
 pub mod utils;
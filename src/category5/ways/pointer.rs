@@ -13,13 +13,13 @@ use ws::{Resource, ResourceData};
 
 // Dispatch<Interface, Userdata>
 #[allow(unused_variables)]
-impl ws::Dispatch<wl_pointer::WlPointer, ()> for Climate {
+impl ws::Dispatch<wl_pointer::WlPointer, usize> for Climate {
     fn request(
         state: &mut Self,
         client: &ws::Client,
         resource: &wl_pointer::WlPointer,
         request: wl_pointer::Request,
-        data: &(),
+        data: &usize,
         dhandle: &ws::DisplayHandle,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
@@ -59,6 +59,11 @@ impl ws::Dispatch<wl_pointer::WlPointer, ()> for Climate {
 
                 let mut atmos = state.c_atmos.lock().unwrap();
 
+                // The other seats always use our default cursor
+                if *data != atmos.get_active_seat() {
+                    return;
+                }
+
                 atmos.set_cursor(id);
                 log::debug!("Setting cursor hotspot to {:?}", (hotspot_x, hotspot_y));
                 atmos.set_cursor_hotspot((hotspot_x, hotspot_y));
@@ -71,7 +76,7 @@ impl ws::Dispatch<wl_pointer::WlPointer, ()> for Climate {
         state: &mut Self,
         _client: ws::backend::ClientId,
         _resource: &wl_pointer::WlPointer,
-        data: &(),
+        data: &usize,
     ) {
     }
 }
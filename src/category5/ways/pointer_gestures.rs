@@ -0,0 +1,139 @@
+// Implementation of pointer-gestures-unstable-v1
+//
+// This delivers touchpad swipe and pinch gestures to clients, which
+// they use for things like pinch to zoom. Gesture objects are created
+// for a wl_pointer and stored in the seat instance that pointer belongs
+// to. The input subsystem sends the events to the client with pointer
+// focus.
+//
+// https://wayland.app/protocols/pointer-gestures-unstable-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::seat::SeatInstance;
+use crate::category5::Climate;
+use utils::log;
+use ws::protocol::wl_pointer;
+
+use wayland_protocols::wp::pointer_gestures::zv1::server::{
+    zwp_pointer_gesture_hold_v1 as hold, zwp_pointer_gesture_pinch_v1 as pinch,
+    zwp_pointer_gesture_swipe_v1 as swipe, zwp_pointer_gestures_v1 as gestures,
+};
+
+use std::ops::DerefMut;
+
+/// Find the seat instance that `pointer` was created from and run `func`
+/// on it
+fn with_seat_instance<F>(
+    state: &mut Climate,
+    client: &ws::Client,
+    pointer: &wl_pointer::WlPointer,
+    func: F,
+) where
+    F: FnOnce(&mut SeatInstance),
+{
+    let mut atmos = state.c_atmos.lock().unwrap();
+    let id = super::utils::get_id_from_client(atmos.deref_mut(), client.clone());
+
+    if let Some(cell) = atmos.get_seat_from_client_id(&id) {
+        let mut seat = cell.lock().unwrap();
+        if let Some(si) = seat
+            .s_proxies
+            .iter_mut()
+            .find(|si| si.si_pointers.contains(pointer))
+        {
+            func(si);
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<gestures::ZwpPointerGesturesV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<gestures::ZwpPointerGesturesV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<gestures::ZwpPointerGesturesV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &gestures::ZwpPointerGesturesV1,
+        request: gestures::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            gestures::Request::GetSwipeGesture { id, pointer } => {
+                let gesture = data_init.init(id, ());
+                with_seat_instance(state, client, &pointer, |si| {
+                    si.si_swipe_gestures.push(gesture)
+                });
+            }
+            gestures::Request::GetPinchGesture { id, pointer } => {
+                let gesture = data_init.init(id, ());
+                with_seat_instance(state, client, &pointer, |si| {
+                    si.si_pinch_gestures.push(gesture)
+                });
+            }
+            // Hold gestures are only in version 3, which we don't advertise
+            gestures::Request::GetHoldGesture { id, .. } => {
+                data_init.init(id, ());
+            }
+            gestures::Request::Release => {}
+            _ => log::error!("Unimplemented pointer gestures request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<swipe::ZwpPointerGestureSwipeV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &swipe::ZwpPointerGestureSwipeV1,
+        request: swipe::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<pinch::ZwpPointerGesturePinchV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &pinch::ZwpPointerGesturePinchV1,
+        request: pinch::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<hold::ZwpPointerGestureHoldV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &hold::ZwpPointerGestureHoldV1,
+        request: hold::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+}
@@ -0,0 +1,185 @@
+// Implementation of presentation-time
+//
+// Video players like mpv use this to find out exactly when their frames
+// were shown, so they can keep audio and video in sync. Surfaces ask for
+// feedback on a commit, and once the frame containing that commit reaches
+// the screen we tell them the time it was shown and how it got there.
+//
+// The times come from Thundr. With DRM they are the kernel's vblank
+// timestamps, other backends only know when the frame was queued.
+//
+// https://wayland.app/protocols/presentation-time
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate wayland_server as ws;
+
+use super::surface::Surface;
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use wayland_protocols::wp::presentation_time::server::{
+    wp_presentation as presentation, wp_presentation_feedback as feedback,
+};
+
+use std::sync::{Arc, Mutex};
+
+/// Presentation feedback waiting for frames to be shown
+pub struct PresentationState {
+    /// Feedback and the frame number it was drawn in
+    ps_in_flight: Vec<(u64, feedback::WpPresentationFeedback)>,
+    /// The last frame number we saw the output draw
+    ps_last_frame: u64,
+}
+
+impl PresentationState {
+    pub fn new() -> Self {
+        Self {
+            ps_in_flight: Vec::new(),
+            ps_last_frame: 0,
+        }
+    }
+}
+
+impl Climate {
+    /// Are any clients waiting to hear when a frame was shown?
+    pub fn has_presentation_feedback_in_flight(&self) -> bool {
+        !self.c_presentation.ps_in_flight.is_empty()
+    }
+
+    /// Tell a client when its contents were shown
+    fn send_presented(&self, fb: &feedback::WpPresentationFeedback, time: &dak::PresentationTime) {
+        // We only have one output, tell the client about it
+        for output in self.c_outputs.iter() {
+            if output.id().same_client_as(&fb.id()) {
+                fb.sync_output(output);
+            }
+        }
+
+        let mut kind = feedback::Kind::empty();
+        if time.pt_vsync {
+            kind |= feedback::Kind::Vsync;
+        }
+        if time.pt_hw_clock {
+            kind |= feedback::Kind::HwClock;
+        }
+        if time.pt_hw_completion {
+            kind |= feedback::Kind::HwCompletion;
+        }
+
+        let secs = time.pt_time.as_secs();
+        fb.presented(
+            (secs >> 32) as u32,
+            (secs & 0xffffffff) as u32,
+            time.pt_time.subsec_nanos(),
+            time.pt_refresh.as_nanos() as u32,
+            (time.pt_seq >> 32) as u32,
+            (time.pt_seq & 0xffffffff) as u32,
+            kind,
+        );
+    }
+
+    /// Send presentation feedback for frames which have been shown
+    ///
+    /// This should be called after redrawing, and whenever the output's
+    /// presentation fd is readable while `has_presentation_feedback_in_flight`
+    /// is true.
+    pub fn send_presentation_feedback(&mut self) {
+        // Feedback for the surfaces included in the frame just drawn
+        let drawn = std::mem::take(&mut self.c_atmos.lock().unwrap().a_drawn_feedback);
+        let frame = self.c_output.get_frame_count();
+
+        // A new output counts its frames from zero, and won't report
+        // anything for frames drawn by the old one
+        if frame < self.c_presentation.ps_last_frame {
+            for (_, fb) in std::mem::take(&mut self.c_presentation.ps_in_flight) {
+                fb.discarded();
+            }
+            self.c_presentation.ps_last_frame = 0;
+        }
+
+        if frame != self.c_presentation.ps_last_frame {
+            self.c_presentation.ps_last_frame = frame;
+            self.c_presentation
+                .ps_in_flight
+                .extend(drawn.into_iter().map(|fb| (frame, fb)));
+        } else {
+            // Nothing was actually drawn, i.e. the display is off
+            for fb in drawn.iter() {
+                fb.discarded();
+            }
+        }
+
+        for time in self.c_output.take_presentation_times() {
+            let (shown, waiting): (Vec<_>, Vec<_>) =
+                std::mem::take(&mut self.c_presentation.ps_in_flight)
+                    .into_iter()
+                    .partition(|(frame, _)| *frame <= time.pt_frame);
+            self.c_presentation.ps_in_flight = waiting;
+
+            for (_, fb) in shown.iter() {
+                if fb.is_alive() {
+                    self.send_presented(fb, &time);
+                }
+            }
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<presentation::WpPresentation, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<presentation::WpPresentation>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let presentation = data_init.init(resource, ());
+        presentation.clock_id(libc::CLOCK_MONOTONIC as u32);
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<presentation::WpPresentation, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &presentation::WpPresentation,
+        request: presentation::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            presentation::Request::Feedback { surface, callback } => {
+                let fb = data_init.init(callback, ());
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                surf.lock()
+                    .unwrap()
+                    .s_state
+                    .cs_presentation_feedback
+                    .push(fb);
+            }
+            presentation::Request::Destroy => {}
+            _ => log::error!("Unimplemented presentation request {:?}", request),
+        }
+    }
+}
+
+// Feedback objects have no requests
+#[allow(unused_variables)]
+impl ws::Dispatch<feedback::WpPresentationFeedback, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &feedback::WpPresentationFeedback,
+        request: feedback::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+}
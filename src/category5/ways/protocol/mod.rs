@@ -1 +1,5 @@
+pub mod export_dmabuf;
+pub mod ext_workspace;
+pub mod gamma_control;
+pub mod screencopy;
 pub mod wl_drm;
@@ -20,4 +20,6 @@ pub enum Role {
     xdg_shell_toplevel(xdg_surface::XdgSurface, Arc<Mutex<xdg_shell::ShellSurface>>),
     xdg_shell_popup(Arc<Mutex<xdg_shell::ShellSurface>>),
     cursor,
+    // This window is shown by the screen locker while the session is locked
+    session_lock,
 }
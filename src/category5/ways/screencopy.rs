@@ -0,0 +1,416 @@
+// Implementation of wlr-screencopy-unstable-v1
+//
+// This is how screen sharing works in browsers and OBS. They ask
+// xdg-desktop-portal for a screencast, and xdg-desktop-portal-wlr uses
+// this protocol to capture our output and feed it into a PipeWire stream.
+// Unlike export-dmabuf the client provides the buffer, either in shared
+// memory or as a dmabuf, and we copy each frame into it.
+//
+// Only whole outputs or regions of them can be captured. The cursor is
+// always part of the composited frame, so it is included no matter what
+// the client asks for.
+//
+// Clients using copy_with_damage are told which parts of their region
+// changed since their last copy. This comes from the damage Dakota finds
+// by comparing each frame's draw records against the last frame's.
+//
+// https://wayland.app/protocols/wlr-screencopy-unstable-v1
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate wayland_server as ws;
+
+use super::security::{self, Privilege};
+use super::shm::ShmBuffer;
+use crate::category5::Climate;
+use utils::timing::get_monotonic_micros;
+use utils::{anyhow, log};
+use ws::protocol::{wl_buffer, wl_shm};
+use ws::Resource;
+
+use super::protocol::screencopy::{
+    zwlr_screencopy_frame_v1 as frame, zwlr_screencopy_manager_v1 as manager,
+};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// DRM_FORMAT_ARGB8888 from drm_fourcc.h
+const DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+
+/// How many frames of damage we remember for screencopy clients
+const DAMAGE_HISTORY_LEN: usize = 16;
+
+/// The user data of a screencopy manager
+///
+/// Damage is reported relative to the last copy made through the same
+/// manager.
+#[derive(Default)]
+pub struct ScreencopyManagerInfo {
+    /// The number of the last frame copied for this manager, or zero
+    sm_last_frame: AtomicU64,
+}
+
+/// The damage of the last few frames drawn
+///
+/// Clients may have last copied a frame several redraws ago, so we keep
+/// enough history to tell them everything that changed since then.
+#[derive(Default)]
+pub struct ScreencopyDamage {
+    /// The number and damaged regions of each frame, oldest first
+    sd_frames: VecDeque<(u64, Vec<dak::Rect<i32>>)>,
+}
+
+impl ScreencopyDamage {
+    /// Remember what changed in a newly drawn frame
+    ///
+    /// If frames were drawn without being added here we can't know what
+    /// changed in them, so the older history is forgotten.
+    fn add_frame(&mut self, number: u64, damage: &[dak::Rect<i32>]) {
+        match self.sd_frames.back() {
+            Some((last, _)) if *last == number => return,
+            Some((last, _)) if *last + 1 != number => self.sd_frames.clear(),
+            _ => {}
+        }
+        if self.sd_frames.len() == DAMAGE_HISTORY_LEN {
+            self.sd_frames.pop_front();
+        }
+        self.sd_frames.push_back((number, damage.to_vec()));
+    }
+
+    /// Get the regions that changed after frame `since` was drawn
+    ///
+    /// Returns None if we no longer have that much history, in which case
+    /// everything should be treated as damaged.
+    fn get_damage_since(&self, since: u64) -> Option<Vec<dak::Rect<i32>>> {
+        let (first, _) = self.sd_frames.front()?;
+        if since == 0 || since + 1 < *first {
+            return None;
+        }
+
+        Some(
+            self.sd_frames
+                .iter()
+                .filter(|(number, _)| *number > since)
+                .flat_map(|(_, damage)| damage.iter().cloned())
+                .collect(),
+        )
+    }
+}
+
+/// The user data of a screencopy frame
+pub struct ScreencopyFrameInfo {
+    /// The manager this frame was created from
+    sf_manager: Arc<ScreencopyManagerInfo>,
+    /// The x, y, width and height of the area of the output to capture
+    sf_region: (u32, u32, u32, u32),
+    /// Has the client already asked for this frame to be copied
+    sf_used: AtomicBool,
+}
+
+/// A frame waiting for the output to be redrawn
+pub struct PendingScreencopy {
+    ps_frame: frame::ZwlrScreencopyFrameV1,
+    /// The client's buffer to copy into
+    ps_buffer: wl_buffer::WlBuffer,
+    /// Did the client ask for damage to be reported
+    ps_with_damage: bool,
+}
+
+impl Climate {
+    /// Create a screencopy frame and tell the client what buffers it accepts
+    ///
+    /// The region is clipped to the output. If nothing is left the
+    /// capture fails.
+    fn create_screencopy_frame(
+        &mut self,
+        frame: ws::New<frame::ZwlrScreencopyFrameV1>,
+        manager: &Arc<ScreencopyManagerInfo>,
+        region: (i32, i32, i32, i32),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let resolution = self.c_output.get_resolution();
+        let (x, y, width, height) = region;
+        let x0 = x.clamp(0, resolution.0 as i32) as u32;
+        let y0 = y.clamp(0, resolution.1 as i32) as u32;
+        let x1 = x.saturating_add(width).clamp(0, resolution.0 as i32) as u32;
+        let y1 = y.saturating_add(height).clamp(0, resolution.1 as i32) as u32;
+        let (width, height) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+
+        let frame = data_init.init(
+            frame,
+            ScreencopyFrameInfo {
+                sf_manager: manager.clone(),
+                sf_region: (x0, y0, width, height),
+                sf_used: AtomicBool::new(false),
+            },
+        );
+
+        if width == 0 || height == 0 {
+            frame.failed();
+            return;
+        }
+
+        frame.buffer(wl_shm::Format::Argb8888, width, height, width * 4);
+        if frame.version() >= 3 {
+            // Dmabufs are filled by copying the entire frame on the GPU,
+            // so they can't be used to capture part of the output
+            if (width, height) == resolution {
+                frame.linux_dmabuf(DRM_FORMAT_ARGB8888, width, height);
+            }
+            frame.buffer_done();
+        }
+    }
+
+    /// Does a client's buffer match what we told them to allocate?
+    fn screencopy_buffer_is_valid(
+        &self,
+        info: &ScreencopyFrameInfo,
+        buffer: &wl_buffer::WlBuffer,
+    ) -> bool {
+        let (_, _, width, height) = info.sf_region;
+
+        if let Some(shm) = buffer.data::<ShmBuffer>() {
+            return shm.sb_width as u32 == width
+                && shm.sb_height as u32 == height
+                && shm.sb_stride as u32 >= width * 4
+                && matches!(
+                    shm.sb_format,
+                    wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888
+                );
+        }
+
+        if let Some(dmabuf) = buffer.data::<dak::Dmabuf>() {
+            return (dmabuf.db_width as u32, dmabuf.db_height as u32) == (width, height)
+                && (width, height) == self.c_output.get_resolution();
+        }
+
+        false
+    }
+
+    /// Handle a copy request for a frame
+    ///
+    /// The copy takes place the next time the output is drawn.
+    fn queue_screencopy(
+        &mut self,
+        frame: &frame::ZwlrScreencopyFrameV1,
+        info: &ScreencopyFrameInfo,
+        buffer: wl_buffer::WlBuffer,
+        with_damage: bool,
+    ) {
+        if info.sf_used.swap(true, Ordering::SeqCst) {
+            frame.post_error(frame::Error::AlreadyUsed, "Frame was already copied");
+            return;
+        }
+        if !self.screencopy_buffer_is_valid(info, &buffer) {
+            frame.post_error(
+                frame::Error::InvalidBuffer,
+                "Buffer does not match the advertised parameters",
+            );
+            return;
+        }
+
+        self.c_screencopy_frames.push(PendingScreencopy {
+            ps_frame: frame.clone(),
+            ps_buffer: buffer,
+            ps_with_damage: with_damage,
+        });
+
+        // copy_with_damage waits until something in its region changes on
+        // screen, which will cause a redraw on its own
+        if !with_damage {
+            self.c_output.request_redraw();
+        }
+    }
+
+    /// Copy the frame we just drew into the buffers of waiting clients
+    ///
+    /// This should be called after the output has been redrawn. Each
+    /// frame object is only used once, clients create a new one to
+    /// capture the next frame. Frames waiting for damage are kept until
+    /// their region changes.
+    pub fn send_screencopy_frames(&mut self) {
+        let pending = std::mem::take(&mut self.c_screencopy_frames);
+        let resolution = self.c_output.get_resolution();
+        let stats = self.c_output.get_frame_stats();
+        let frame_number = stats.fs_frame_number;
+        self.c_screencopy_damage
+            .add_frame(frame_number, &stats.fs_damage);
+        // Reading back the frame is expensive, so only do it once for
+        // all shm clients
+        let mut pixels = None;
+
+        for copy in pending {
+            if !copy.ps_frame.is_alive() {
+                continue;
+            }
+            let info = copy.ps_frame.data::<ScreencopyFrameInfo>().unwrap();
+            let (x, y, width, height) = info.sf_region;
+
+            // Find what changed inside this frame's region since the
+            // manager's last copy, relative to the region
+            let region = dak::Rect::new(x as i32, y as i32, width as i32, height as i32);
+            let since = info.sf_manager.sm_last_frame.load(Ordering::SeqCst);
+            let damage: Vec<dak::Rect<i32>> = match self.c_screencopy_damage.get_damage_since(since)
+            {
+                Some(damage) => damage
+                    .iter()
+                    .filter_map(|r| r.intersection(&region))
+                    .map(|r| {
+                        dak::Rect::new(
+                            r.r_pos.0 - region.r_pos.0,
+                            r.r_pos.1 - region.r_pos.1,
+                            r.r_size.0,
+                            r.r_size.1,
+                        )
+                    })
+                    .collect(),
+                None => vec![dak::Rect::new(0, 0, width as i32, height as i32)],
+            };
+            if copy.ps_with_damage && damage.is_empty() {
+                self.c_screencopy_frames.push(copy);
+                continue;
+            }
+
+            let ret = if let Some(shm) = copy.ps_buffer.data::<ShmBuffer>() {
+                if x + width > resolution.0 || y + height > resolution.1 {
+                    Err(anyhow!("Region is no longer inside the output"))
+                } else {
+                    let frame = pixels.get_or_insert_with(|| self.c_output.read_frame());
+                    let stride = resolution.0 as usize * 4;
+                    let start = y as usize * stride + x as usize * 4;
+                    shm.write_pixels(&frame.mi_data[start..], stride)
+                }
+            } else if let Some(dmabuf) = copy.ps_buffer.data::<dak::Dmabuf>() {
+                self.c_output.copy_frame_to_dmabuf(dmabuf)
+            } else {
+                Err(anyhow!("Unsupported buffer type"))
+            };
+
+            if let Err(e) = ret {
+                log::error!("Could not copy frame for screencopy: {:?}", e);
+                copy.ps_frame.failed();
+                continue;
+            }
+
+            info.sf_manager
+                .sm_last_frame
+                .store(frame_number, Ordering::SeqCst);
+            copy.ps_frame.flags(0);
+            if copy.ps_with_damage {
+                for r in damage.iter() {
+                    copy.ps_frame.damage(
+                        r.r_pos.0 as u32,
+                        r.r_pos.1 as u32,
+                        r.r_size.0 as u32,
+                        r.r_size.1 as u32,
+                    );
+                }
+            }
+
+            let now = get_monotonic_micros();
+            let secs = now / 1_000_000;
+            let nsecs = (now % 1_000_000) * 1000;
+            copy.ps_frame.ready(
+                (secs >> 32) as u32,
+                (secs & 0xffffffff) as u32,
+                nsecs as u32,
+            );
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ZwlrScreencopyManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ZwlrScreencopyManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, Arc::new(ScreencopyManagerInfo::default()));
+    }
+
+    // Only clients the user allowed may see this global
+    fn can_view(client: ws::Client, global_data: &()) -> bool {
+        security::client_has_privilege(&client, Privilege::ScreenCapture)
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ZwlrScreencopyManagerV1, Arc<ScreencopyManagerInfo>> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ZwlrScreencopyManagerV1,
+        request: manager::Request,
+        data: &Arc<ScreencopyManagerInfo>,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            // We only have one output, and the cursor is always part of
+            // the composited frame
+            manager::Request::CaptureOutput {
+                frame,
+                overlay_cursor,
+                output,
+            } => {
+                let (width, height) = state.c_output.get_resolution();
+                state.create_screencopy_frame(
+                    frame,
+                    data,
+                    (0, 0, width as i32, height as i32),
+                    data_init,
+                );
+            }
+            manager::Request::CaptureOutputRegion {
+                frame,
+                overlay_cursor,
+                output,
+                x,
+                y,
+                width,
+                height,
+            } => state.create_screencopy_frame(frame, data, (x, y, width, height), data_init),
+            manager::Request::Destroy => {}
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<frame::ZwlrScreencopyFrameV1, ScreencopyFrameInfo> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &frame::ZwlrScreencopyFrameV1,
+        request: frame::Request,
+        data: &ScreencopyFrameInfo,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            frame::Request::Copy { buffer } => {
+                state.queue_screencopy(resource, data, buffer, false)
+            }
+            frame::Request::CopyWithDamage { buffer } => {
+                state.queue_screencopy(resource, data, buffer, true)
+            }
+            frame::Request::Destroy => {}
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &frame::ZwlrScreencopyFrameV1,
+        data: &ScreencopyFrameInfo,
+    ) {
+        state
+            .c_screencopy_frames
+            .retain(|f| f.ps_frame.id() != resource.id());
+    }
+}
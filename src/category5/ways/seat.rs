@@ -1,36 +1,34 @@
 // Implementation of the wl_seat interface
 //
 // This represents a group of input devices, it is in
-// charge of provisioning the keyboard and pointer.
+// charge of provisioning the keyboard, pointer, and touch.
 //
 // Austin Shafer - 2020
-extern crate libc;
-extern crate nix;
-use nix::unistd::ftruncate;
-
 extern crate wayland_server as ws;
 use ws::protocol::wl_seat::Capability;
-use ws::protocol::{wl_keyboard, wl_pointer, wl_seat};
+use ws::protocol::{wl_keyboard, wl_pointer, wl_seat, wl_touch};
 use ws::Resource;
 
+use wayland_protocols::wp::pointer_gestures::zv1::server::{
+    zwp_pointer_gesture_pinch_v1 as pinch, zwp_pointer_gesture_swipe_v1 as swipe,
+};
+
 use crate::category5::atmosphere::{Atmosphere, ClientId};
 use crate::category5::input::Input;
+use crate::category5::ways::tablet::TabletSeat;
 use crate::category5::Climate;
 
-use std::fs::File;
-use std::io::Write;
 use std::ops::DerefMut;
-use std::os::unix::io::FromRawFd;
 use std::sync::{Arc, Mutex};
 
 #[allow(unused_variables)]
-impl ws::GlobalDispatch<wl_seat::WlSeat, ()> for Climate {
+impl ws::GlobalDispatch<wl_seat::WlSeat, usize> for Climate {
     fn bind(
         state: &mut Self,
         handle: &ws::DisplayHandle,
         client: &ws::Client,
         resource: ws::New<wl_seat::WlSeat>,
-        global_data: &(),
+        global_data: &usize,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
         // get the id representing this client in the atmos
@@ -53,9 +51,18 @@ impl ws::GlobalDispatch<wl_seat::WlSeat, ()> for Climate {
         };
 
         let wl_seat = data_init.init(resource, seat.clone());
+        // Advertise the seat's name so clients can tell seats apart
+        let seat_index = *global_data;
+        if wl_seat.version() >= 2 {
+            if let Some(name) = atmos.get_seat_names().get(seat_index) {
+                wl_seat.name(name.clone());
+            }
+        }
         // make a new seat instance that adds this wl_seat to the Seat
         // see docs for this func for more
-        seat.lock().unwrap().add_seat_instance(wl_seat.clone());
+        seat.lock()
+            .unwrap()
+            .add_seat_instance(wl_seat.clone(), seat_index);
     }
 }
 
@@ -94,18 +101,32 @@ impl ws::Dispatch<wl_seat::WlSeat, Arc<Mutex<Seat>>> for Climate {
 pub struct SeatInstance {
     // the seat object itself
     pub si_seat: wl_seat::WlSeat,
+    // the number of the seat this wl_seat was created for
+    pub si_seat_index: usize,
     // wl_keyboard handle
     pub si_keyboards: Vec<wl_keyboard::WlKeyboard>,
     // wl_pointer handle
     pub si_pointers: Vec<wl_pointer::WlPointer>,
+    // wl_touch handle
+    pub si_touches: Vec<wl_touch::WlTouch>,
+    // touchpad gestures from pointer-gestures-unstable-v1
+    pub si_swipe_gestures: Vec<swipe::ZwpPointerGestureSwipeV1>,
+    pub si_pinch_gestures: Vec<pinch::ZwpPointerGesturePinchV1>,
+    // tablet seats from tablet-unstable-v2
+    pub si_tablet_seats: Vec<TabletSeat>,
 }
 
 impl SeatInstance {
-    pub fn new(seat: wl_seat::WlSeat) -> Self {
+    pub fn new(seat: wl_seat::WlSeat, seat_index: usize) -> Self {
         Self {
             si_seat: seat,
+            si_seat_index: seat_index,
             si_keyboards: Vec::new(),
             si_pointers: Vec::new(),
+            si_touches: Vec::new(),
+            si_swipe_gestures: Vec::new(),
+            si_pinch_gestures: Vec::new(),
+            si_tablet_seats: Vec::new(),
         }
     }
 
@@ -120,55 +141,28 @@ impl SeatInstance {
         parent_serial: u32,
         keyboard: wl_keyboard::WlKeyboard,
     ) {
-        // Make a temp fd to share with the client
-        #[cfg(target_os = "freebsd")]
-        let fd = unsafe {
-            libc::shm_open(
-                libc::SHM_ANON,
-                libc::O_CREAT | libc::O_RDWR | libc::O_EXCL | libc::O_CLOEXEC,
-                0o600,
-            )
-        };
-        #[cfg(target_os = "linux")]
-        let fd = unsafe {
-            let memfd_name = std::ffi::CString::new("cat5_keymap").unwrap();
-            libc::memfd_create(memfd_name.as_ptr() as *mut i8, libc::MFD_CLOEXEC)
-        };
-        assert!(fd > 0);
-        let mut file = unsafe { File::from_raw_fd(fd) };
-        // according to the manpage: writes do not extend
-        // shm objects, so we need to call ftruncate first
-        ftruncate(&file, input.i_xkb_keymap_name.as_bytes().len() as i64)
-            .expect("Could not truncate the temp xkb keymap file");
-        // write the input systems keymap to our anon file
-        file.write(input.i_xkb_keymap_name.as_bytes())
-            .expect("Could not write to the temp xkb keymap file");
-        file.flush().unwrap();
-        // Broadcast our keymap map
-        keyboard.keymap(
-            wl_keyboard::KeymapFormat::XkbV1,
-            unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) },
-            input.i_xkb_keymap_name.as_bytes().len() as u32,
-        );
-        // Advertise the server repeat capabilities. This is needed
+        // Broadcast our keymap
+        input.send_keymap(&keyboard);
+        // Advertise the repeat settings. This is needed
         // to make gtk apps not crash. They will check for this event
         // and if it is not found will resort to checking the peripherals
         // schema, which doesn't have a repeat key and causes an abort.
         // That gross behavior aside, the spec does require us to send this.
-        // Send 0 to show we don't repeat.
-        // as_ref turns the Main into a Resource
-        if keyboard.version() >= 4 {
-            keyboard.repeat_info(0, 0);
-        }
+        input.send_repeat_info(&keyboard);
 
         // add the keyboard to this seat
         self.si_keyboards.push(keyboard.clone());
 
+        // The focus in the atmosphere belongs to the active seat
+        if self.si_seat_index != atmos.get_active_seat() {
+            return;
+        }
+
         // If we are in focus, then we should go ahead and generate
         // the enter event
         if let Some(focus) = atmos.get_client_in_focus() {
             if parent_focus == focus {
-                if let Some(sid) = atmos.get_win_focus() {
+                if let Some(sid) = atmos.get_keyboard_focus() {
                     if let Some(surf) = atmos.get_wl_surface_from_id(&sid) {
                         // TODO: use Input::keyboard_enter and fix the refcell order
                         keyboard.enter(
@@ -191,9 +185,13 @@ impl SeatInstance {
     ) {
         self.si_pointers.push(pointer.clone());
 
+        if self.si_seat_index != atmos.get_active_seat() {
+            return;
+        }
+
         // If we are in focus, then we should go ahead and generate
         // the enter event
-        if let Some(sid) = atmos.get_win_focus() {
+        if let Some(sid) = atmos.get_keyboard_focus() {
             if let Some(pointer_focus) = atmos.get_pointer_focus() {
                 // check if the surface is the input sys's focus
                 if &sid == &pointer_focus {
@@ -244,12 +242,19 @@ impl Seat {
     /// instance needs to be added for every wl_seat global so that
     /// we can accurately track all wl_seats for a client that have
     /// been created.
-    pub fn add_seat_instance(&mut self, seat: wl_seat::WlSeat) {
+    pub fn add_seat_instance(&mut self, seat: wl_seat::WlSeat, seat_index: usize) {
         // broadcast the types of input we have available
-        // TODO: don't just default to keyboard + mouse
-        seat.capabilities(Capability::Keyboard | Capability::Pointer);
+        // TODO: only advertise the devices that are plugged in
+        seat.capabilities(Capability::Keyboard | Capability::Pointer | Capability::Touch);
 
-        self.s_proxies.push(SeatInstance::new(seat));
+        self.s_proxies.push(SeatInstance::new(seat, seat_index));
+    }
+
+    /// Get the wl_seats this client created for one of our seats
+    pub fn proxies_for_seat(&self, seat: usize) -> impl Iterator<Item = &SeatInstance> {
+        self.s_proxies
+            .iter()
+            .filter(move |si| si.si_seat_index == seat)
     }
 
     /// Handle client requests
@@ -277,9 +282,15 @@ impl Seat {
                 si.get_keyboard(atmos, input, self.s_id.clone(), self.s_serial, kb);
             }
             wl_seat::Request::GetPointer { id } => {
-                let ptr = data_init.init(id, ());
+                // Remember the seat so cursor changes can be checked
+                let ptr = data_init.init(id, si.si_seat_index);
                 si.get_pointer(atmos, input, ptr);
             }
+            wl_seat::Request::GetTouch { id } => {
+                // Touch points are only sent to the surface they went
+                // down on, so there is nothing to send until then
+                si.si_touches.push(data_init.init(id, ()));
+            }
             _ => unimplemented!("Did not recognize the request"),
         }
     }
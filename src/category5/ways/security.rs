@@ -0,0 +1,159 @@
+// Security policy for privileged protocols
+//
+// Some protocols give a client power over the entire desktop, such as
+// recording the screen or changing the output's colors. We don't want
+// any app to be able to do this without the user knowing, so these
+// globals are hidden from every client except those the user allowed.
+//
+// Clients are identified by their executable, which is looked up from
+// the pid of the process on the other end of the wayland socket.
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use crate::category5::ClientInfo;
+use utils::platform::get_peer_executable;
+use utils::{anyhow, log, Result};
+
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// The privileged actions a client may be allowed to take
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Privilege {
+    /// Record the screen through wlr-export-dmabuf
+    ScreenCapture,
+    /// Change the output gamma through wlr-gamma-control
+    GammaControl,
+}
+
+impl Privilege {
+    const ALL: [Privilege; 2] = [Privilege::ScreenCapture, Privilege::GammaControl];
+
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "screen_capture" => Ok(Privilege::ScreenCapture),
+            "gamma_control" => Ok(Privilege::GammaControl),
+            _ => Err(anyhow!("Unknown privilege {}", name)),
+        }
+    }
+}
+
+/// Which executables may use which privileged protocols
+pub struct SecurityPolicy {
+    /// Executable paths and what they are allowed to do. A path of
+    /// `*` matches every client.
+    sp_rules: Vec<(String, Vec<Privilege>)>,
+}
+
+/// Get the path of the user's security policy file
+///
+/// This is `$CATEGORY5_SECURITY_CONFIG` if set, or else
+/// `$XDG_CONFIG_HOME/category5/privileged`.
+fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("CATEGORY5_SECURITY_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("category5").join("privileged"))
+}
+
+impl SecurityPolicy {
+    /// Create a security policy from the contents of a config file
+    ///
+    /// Each line is the path of an executable followed by the privileges
+    /// it is allowed. If no privileges are listed it is allowed all of
+    /// them:
+    ///
+    /// ```text
+    /// /usr/bin/obs screen_capture
+    /// /usr/bin/gammastep gamma_control
+    /// ```
+    ///
+    /// Blank lines and lines starting with `#` are ignored. Invalid lines
+    /// are logged and skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut rules = Vec::new();
+
+        for (num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let path = words.next().unwrap().to_string();
+            let privileges: Result<Vec<Privilege>> = words.map(Privilege::from_name).collect();
+            match privileges {
+                Ok(p) if p.is_empty() => rules.push((path, Privilege::ALL.to_vec())),
+                Ok(p) => rules.push((path, p)),
+                Err(e) => log::error!("Invalid security rule on line {}: {:?}", num + 1, e),
+            }
+        }
+
+        Self { sp_rules: rules }
+    }
+
+    /// Load the user's security policy
+    ///
+    /// If no policy file exists then no client is privileged.
+    pub fn load() -> Self {
+        if let Some(path) = config_path() {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => {
+                    log::debug!("Loading security policy from {:?}", path);
+                    return Self::parse(&contents);
+                }
+                Err(e) => log::debug!("Could not read security policy from {:?}: {:?}", path, e),
+            }
+        }
+
+        Self {
+            sp_rules: Vec::new(),
+        }
+    }
+
+    /// Get the privileges allowed for an executable
+    fn get_privileges(&self, exe: Option<&Path>) -> Vec<Privilege> {
+        let mut ret = Vec::new();
+
+        for (path, privileges) in self.sp_rules.iter() {
+            let matches = path == "*" || exe.is_some_and(|e| e == Path::new(path));
+            if matches {
+                for p in privileges.iter() {
+                    if !ret.contains(p) {
+                        ret.push(*p);
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// Get the privileges allowed for a newly connected client
+    pub fn get_client_privileges(&self, stream: &UnixStream) -> Vec<Privilege> {
+        let exe = get_peer_executable(stream.as_raw_fd());
+        let privileges = self.get_privileges(exe.as_deref());
+        if !privileges.is_empty() {
+            log::debug!("Client {:?} is allowed {:?}", exe, privileges);
+        }
+        privileges
+    }
+}
+
+/// Is this client allowed to use a privileged protocol?
+///
+/// This is meant to be called from `GlobalDispatch::can_view`, so that
+/// privileged globals are never advertised to other clients.
+pub fn client_has_privilege(client: &ws::Client, privilege: Privilege) -> bool {
+    match client.get_data::<ClientInfo>() {
+        Some(info) => info.ci_privileges.contains(&privilege),
+        None => false,
+    }
+}
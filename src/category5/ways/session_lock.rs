@@ -0,0 +1,264 @@
+// Implementation of ext-session-lock-v1
+//
+// This lets screen lockers like swaylock lock the session. While it is
+// locked the desktop is hidden and only the locker's surfaces get input.
+// Only one locker may hold the lock at a time. If the locker crashes the
+// session stays locked, and a new locker may take over the lock.
+//
+// https://wayland.app/protocols/ext-session-lock-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::role::Role;
+use super::surface::Surface;
+use crate::category5::atmosphere::SurfaceId;
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use wayland_protocols::ext::session_lock::v1::server::{
+    ext_session_lock_manager_v1 as manager, ext_session_lock_surface_v1 as lock_surface,
+    ext_session_lock_v1 as lock,
+};
+
+use std::sync::{Arc, Mutex};
+
+/// Our session lock state
+///
+/// Whether the session is locked is tracked by the atmosphere. This
+/// holds the protocol objects of the current screen locker.
+pub struct SessionLockState {
+    /// The lock held by the current screen locker
+    sl_lock: Option<lock::ExtSessionLockV1>,
+    /// Have we told the locker that the session is locked?
+    sl_locked_sent: bool,
+    /// Was the session already locked when this lock was taken? This
+    /// happens when a new locker replaces one that crashed.
+    sl_relock: bool,
+    /// The locker's surfaces and the surface each one belongs to
+    sl_surfaces: Vec<(lock_surface::ExtSessionLockSurfaceV1, SurfaceId)>,
+    /// The serial of the last configure event
+    sl_serial: u32,
+}
+
+impl SessionLockState {
+    pub fn new() -> Self {
+        Self {
+            sl_lock: None,
+            sl_locked_sent: false,
+            sl_relock: false,
+            sl_surfaces: Vec::new(),
+            sl_serial: 0,
+        }
+    }
+
+    /// Is this the lock held by the current screen locker?
+    fn is_current_lock(&self, resource: &lock::ExtSessionLockV1) -> bool {
+        self.sl_lock
+            .as_ref()
+            .is_some_and(|l| l.id() == resource.id())
+    }
+}
+
+impl Climate {
+    /// Tell the screen locker that the session is locked
+    ///
+    /// This should be called after a frame has been drawn, so that the
+    /// locker is not told the session is locked while the desktop is
+    /// still on screen.
+    pub fn send_session_locked(&mut self) {
+        let state = &mut self.c_session_lock;
+        if state.sl_locked_sent {
+            return;
+        }
+
+        if let Some(lock) = state.sl_lock.as_ref() {
+            lock.locked();
+            state.sl_locked_sent = true;
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ExtSessionLockManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ExtSessionLockManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ExtSessionLockManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ExtSessionLockManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::Lock { id } => {
+                let new_lock = data_init.init(id, ());
+
+                // Another locker already holds the lock
+                if state.c_session_lock.sl_lock.is_some() {
+                    new_lock.finished();
+                    return;
+                }
+
+                let mut atmos = state.c_atmos.lock().unwrap();
+                state.c_session_lock.sl_relock = atmos.get_locked();
+                atmos.lock_session();
+
+                state.c_session_lock.sl_lock = Some(new_lock);
+                state.c_session_lock.sl_locked_sent = false;
+            }
+            manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented session lock manager request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<lock::ExtSessionLockV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &lock::ExtSessionLockV1,
+        request: lock::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            lock::Request::GetLockSurface {
+                id,
+                surface,
+                output,
+            } => {
+                let new_surface = data_init.init(id, ());
+                // Surfaces of a lock we rejected are never shown
+                if !state.c_session_lock.is_current_lock(resource) {
+                    return;
+                }
+
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                let mut surf = surf.lock().unwrap();
+                if surf.s_role.is_some() {
+                    resource.post_error(
+                        lock::Error::Role,
+                        "Surface is already assigned a role".to_string(),
+                    );
+                    return;
+                }
+                surf.s_role = Some(Role::session_lock);
+                let surf_id = surf.s_id.clone();
+                drop(surf);
+
+                let mut atmos = state.c_atmos.lock().unwrap();
+                atmos.add_lock_surface(&surf_id);
+                let res = atmos.get_resolution();
+                drop(atmos);
+
+                // Lock surfaces always cover the entire output
+                state.c_session_lock.sl_serial += 1;
+                new_surface.configure(state.c_session_lock.sl_serial, res.0, res.1);
+                state
+                    .c_session_lock
+                    .sl_surfaces
+                    .push((new_surface, surf_id));
+            }
+            lock::Request::UnlockAndDestroy => {
+                if !state.c_session_lock.is_current_lock(resource)
+                    || !state.c_session_lock.sl_locked_sent
+                {
+                    resource.post_error(
+                        lock::Error::InvalidUnlock,
+                        "The session was never locked by this lock".to_string(),
+                    );
+                    return;
+                }
+
+                state.c_atmos.lock().unwrap().unlock_session();
+                state.c_session_lock.sl_lock = None;
+            }
+            lock::Request::Destroy => {
+                if !state.c_session_lock.is_current_lock(resource) {
+                    return;
+                }
+                if state.c_session_lock.sl_locked_sent {
+                    resource.post_error(
+                        lock::Error::InvalidDestroy,
+                        "Destroying a lock requires unlocking the session".to_string(),
+                    );
+                    return;
+                }
+
+                // The locker gave up before the session was locked. Don't
+                // unlock a session that a crashed locker left locked.
+                if !state.c_session_lock.sl_relock {
+                    state.c_atmos.lock().unwrap().unlock_session();
+                }
+                state.c_session_lock.sl_lock = None;
+            }
+            _ => log::error!("Unimplemented session lock request {:?}", request),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &lock::ExtSessionLockV1,
+        data: &(),
+    ) {
+        // If the locker died while holding the lock the session stays
+        // locked, a new locker will have to unlock it
+        if state.c_session_lock.is_current_lock(resource) {
+            log::error!("Screen locker exited without unlocking the session");
+            state.c_session_lock.sl_lock = None;
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<lock_surface::ExtSessionLockSurfaceV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &lock_surface::ExtSessionLockSurfaceV1,
+        request: lock_surface::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            // Our lock surfaces are always the size of the output, so
+            // there is nothing to wait for
+            lock_surface::Request::AckConfigure { serial } => {}
+            lock_surface::Request::Destroy => {}
+            _ => log::error!("Unimplemented lock surface request {:?}", request),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &lock_surface::ExtSessionLockSurfaceV1,
+        data: &(),
+    ) {
+        let surfaces = &mut state.c_session_lock.sl_surfaces;
+        if let Some(index) = surfaces.iter().position(|(s, _)| s.id() == resource.id()) {
+            let (_, id) = surfaces.remove(index);
+            state.c_atmos.lock().unwrap().remove_lock_surface(&id);
+        }
+    }
+}
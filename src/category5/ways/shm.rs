@@ -10,14 +10,39 @@ use ws::protocol::wl_buffer;
 use ws::protocol::{wl_shm, wl_shm_pool};
 use ws::Resource;
 
+use super::accounting::ResourceKind;
 use crate::category5::Climate;
-use utils::{log, MemImage};
+use dakota::dom;
+use utils::{anyhow, log, MemImage, Result};
 
 use nix::sys::mman;
 use std::ffi::c_void;
 use std::os::unix::io::OwnedFd;
 use std::sync::{Arc, Mutex};
 
+/// The wl_shm formats we know about, and the Dakota formats they match
+///
+/// Only the ones the GPU can sample are advertised to clients.
+const SHM_FORMATS: [(wl_shm::Format, dom::Format); 9] = [
+    (wl_shm::Format::Argb8888, dom::Format::ARGB8888),
+    (wl_shm::Format::Xrgb8888, dom::Format::XRGB8888),
+    (wl_shm::Format::Abgr8888, dom::Format::ABGR8888),
+    (wl_shm::Format::Xbgr8888, dom::Format::XBGR8888),
+    (wl_shm::Format::Rgb565, dom::Format::RGB565),
+    (wl_shm::Format::Argb2101010, dom::Format::ARGB2101010),
+    (wl_shm::Format::Xrgb2101010, dom::Format::XRGB2101010),
+    (wl_shm::Format::Abgr2101010, dom::Format::ABGR2101010),
+    (wl_shm::Format::Xbgr2101010, dom::Format::XBGR2101010),
+];
+
+/// Get the Dakota format matching a wl_shm format
+fn get_dakota_format(format: wl_shm::Format) -> Option<dom::Format> {
+    SHM_FORMATS
+        .iter()
+        .find(|(shm, _)| *shm == format)
+        .map(|(_, dak)| *dak)
+}
+
 #[allow(unused_variables)]
 impl ws::GlobalDispatch<wl_shm::WlShm, ()> for Climate {
     fn bind(
@@ -28,7 +53,13 @@ impl ws::GlobalDispatch<wl_shm::WlShm, ()> for Climate {
         global_data: &(),
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
-        data_init.init(resource, ());
+        let shm = data_init.init(resource, ());
+        let supported = state.c_output.get_supported_shm_formats();
+        for (format, dak_format) in SHM_FORMATS.iter() {
+            if supported.contains(dak_format) {
+                shm.format(*format);
+            }
+        }
     }
 }
 
@@ -104,7 +135,11 @@ impl ws::Dispatch<wl_shm_pool::WlShmPool, Arc<Mutex<ShmRegion>>> for Climate {
                 let format = format_enum.into_result().expect("Not a valid format");
 
                 // Ensure that the requested format is supported
-                if format != wl_shm::Format::Xrgb8888 && format != wl_shm::Format::Argb8888 {
+                let supported = state.c_output.get_supported_shm_formats();
+                if !get_dakota_format(format)
+                    .map(|f| supported.contains(&f))
+                    .unwrap_or(false)
+                {
                     resource.post_error(
                         wl_shm::Error::InvalidFormat as u32,
                         format!("SHM format {:?} is not supported.", format),
@@ -112,6 +147,13 @@ impl ws::Dispatch<wl_shm_pool::WlShmPool, Arc<Mutex<ShmRegion>>> for Climate {
                     return;
                 }
 
+                if !state
+                    .c_resources
+                    .reserve(client, dhandle, ResourceKind::Buffer)
+                {
+                    return;
+                }
+
                 // Add our buffer priv data to the userdata
                 data_init.init(
                     id,
@@ -143,6 +185,52 @@ impl ws::Dispatch<wl_shm_pool::WlShmPool, Arc<Mutex<ShmRegion>>> for Climate {
     }
 }
 
+/// A read only mapping of a shm pool
+///
+/// Images that sample shm buffers in place hold a reference to the
+/// mapping they read from. Resizing the pool maps it again, and the old
+/// mapping stays alive until the GPU is done with those images.
+pub struct ShmMapping {
+    sm_ptr: std::ptr::NonNull<c_void>,
+    sm_size: usize,
+}
+
+// Have to do this manually because of the void *
+unsafe impl Send for ShmMapping {}
+unsafe impl Sync for ShmMapping {}
+
+impl ShmMapping {
+    // Map size bytes of fd
+    fn new(fd: &OwnedFd, size: usize) -> Option<ShmMapping> {
+        unsafe {
+            let ptr = mman::mmap(
+                None,
+                core::num::NonZeroUsize::new(size)?,
+                mman::ProtFlags::PROT_READ,
+                mman::MapFlags::MAP_SHARED,
+                fd.try_clone().ok()?,
+                0,
+            )
+            .ok()?;
+
+            Some(ShmMapping {
+                sm_ptr: ptr,
+                sm_size: size,
+            })
+        }
+    }
+}
+
+impl Drop for ShmMapping {
+    fn drop(&mut self) {
+        unsafe {
+            // We need to manually unmap this region whenever
+            // it goes out of scope. These prevent memory leaks
+            mman::munmap(self.sm_ptr, self.sm_size).unwrap();
+        }
+    }
+}
+
 // A ShmRegion is a mmapped anonymous region of
 // shared memory
 //
@@ -150,75 +238,38 @@ impl ws::Dispatch<wl_shm_pool::WlShmPool, Arc<Mutex<ShmRegion>>> for Climate {
 // by wl_shm
 //
 // It is the user_data for a shm pool
-#[allow(dead_code)]
 struct ShmRegion {
     sr_fd: OwnedFd,
-    sr_raw_ptr: std::ptr::NonNull<c_void>,
+    sr_map: Arc<ShmMapping>,
     sr_size: usize,
 }
 
-// Have to do this manually because of the void *
-unsafe impl Send for ShmRegion {}
-
 impl ShmRegion {
     // Create a new shared memory region from fd
     //
     // Maps size bytes of the fd as a shared memory region
     // in which the clients can reference data
     fn new(fd: OwnedFd, size: usize) -> Option<ShmRegion> {
-        unsafe {
-            // To create the region we need to map size
-            // bytes from fd
-            let ptr = match mman::mmap(
-                None,
-                core::num::NonZeroUsize::new(size).unwrap(),
-                mman::ProtFlags::PROT_READ,
-                mman::MapFlags::MAP_SHARED,
-                fd.try_clone().unwrap(),
-                0,
-            ) {
-                Ok(p) => p,
-                Err(_) => return None,
-            };
-
-            Some(ShmRegion {
-                sr_fd: fd,
-                sr_raw_ptr: ptr,
-                sr_size: size,
-            })
-        }
+        let map = ShmMapping::new(&fd, size)?;
+
+        Some(ShmRegion {
+            sr_fd: fd,
+            sr_map: Arc::new(map),
+            sr_size: size,
+        })
     }
 
     // Enlarge the shm pool
     // Shrinking a pool is not supported
     fn resize(&mut self, size: usize) {
         assert!(self.sr_size <= size);
-        self.sr_size = size;
 
-        unsafe {
-            mman::munmap(self.sr_raw_ptr, self.sr_size).unwrap();
-            self.sr_raw_ptr = match mman::mmap(
-                None,
-                core::num::NonZeroUsize::new(self.sr_size).unwrap(),
-                mman::ProtFlags::PROT_READ,
-                mman::MapFlags::MAP_SHARED,
-                self.sr_fd.try_clone().unwrap(),
-                0,
-            ) {
-                Ok(p) => p,
-                Err(e) => panic!("Could not resize the shm pool error: {:?}", e),
-            };
-        }
-    }
-}
-
-impl Drop for ShmRegion {
-    fn drop(&mut self) {
-        unsafe {
-            // We need to manually unmap this region whenever
-            // it goes out of scope. These prevent memory leaks
-            mman::munmap(self.sr_raw_ptr, self.sr_size).unwrap();
-        }
+        // The old mapping is unmapped once nothing is sampling it
+        self.sr_map = match ShmMapping::new(&self.sr_fd, size) {
+            Some(map) => Arc::new(map),
+            None => panic!("Could not resize the shm pool"),
+        };
+        self.sr_size = size;
     }
 }
 
@@ -239,6 +290,19 @@ pub struct ShmBuffer {
 }
 
 impl ShmBuffer {
+    /// Get the Dakota format of this buffer's pixels
+    pub fn get_format(&self) -> dom::Format {
+        // Buffers are only created with formats from SHM_FORMATS
+        get_dakota_format(self.sb_format).unwrap()
+    }
+
+    /// Get the mapping this buffer's pixels are read from
+    ///
+    /// This has to be kept alive while the GPU samples the pixels in place.
+    pub fn get_mapping(&self) -> Arc<ShmMapping> {
+        self.sb_reg.lock().unwrap().sr_map.clone()
+    }
+
     // Convert a ShmBuffer to a MemImage
     //
     // subsystems use MemImage to represent raw pointers
@@ -246,25 +310,89 @@ impl ShmBuffer {
     // the correct offset into the region and return
     // it as a MemImage
     pub fn get_mem_image(&self) -> MemImage {
+        let texel_size = self.get_format().get_size();
         let mut ret = MemImage::new(
             unsafe {
                 self.sb_reg
                     .lock()
                     .unwrap()
-                    .sr_raw_ptr
+                    .sr_map
+                    .sm_ptr
                     .offset(self.sb_offset as isize)
                     .as_ptr() as *const u8
             },
-            4, // 4 bytes per pixel hardcoded
+            texel_size,
             self.sb_width as usize,
             self.sb_height as usize,
         );
         // Need to convert from size in bytes to size
         // in texels as per Vulkan
-        ret.set_stride((self.sb_stride / 4) as u32);
+        ret.set_stride((self.sb_stride as usize / texel_size) as u32);
 
         return ret;
     }
+
+    /// Get the contents of this buffer
+    ///
+    /// Returns an error if the buffer does not fit in its pool, or its
+    /// stride is not a whole number of pixels.
+    pub fn get_pixels(&self) -> Result<MemImage> {
+        let texel_size = self.get_format().get_size();
+        let row_size = self.sb_width as usize * texel_size;
+        let stride = self.sb_stride as usize;
+        let height = self.sb_height as usize;
+        let pool_size = self.sb_reg.lock().unwrap().sr_size;
+
+        if stride < row_size || !stride.is_multiple_of(texel_size) {
+            return Err(anyhow!("Invalid shm buffer stride {}", stride));
+        }
+        if self.sb_offset as usize + stride * height.saturating_sub(1) + row_size > pool_size {
+            return Err(anyhow!("Shm buffer does not fit in its pool"));
+        }
+
+        Ok(self.get_mem_image())
+    }
+
+    /// Copy pixels into this buffer
+    ///
+    /// `data` holds rows `stride` bytes apart, each at least as wide as
+    /// this buffer. This is used for screen capture, where the client
+    /// gives us a buffer to fill.
+    pub fn write_pixels(&self, data: &[u8], stride: usize) -> Result<()> {
+        let reg = self.sb_reg.lock().unwrap();
+        let offset = self.sb_offset as usize;
+        let row_size = self.sb_width as usize * 4;
+        let height = self.sb_height as usize;
+
+        if offset + self.sb_stride as usize * height > reg.sr_size
+            || data.len() < stride * height.saturating_sub(1) + row_size
+        {
+            return Err(anyhow!("Pixel data does not fit in shm buffer"));
+        }
+
+        unsafe {
+            // Our mapping of the pool is read only, so map it again
+            // to write to it
+            let ptr = mman::mmap(
+                None,
+                core::num::NonZeroUsize::new(reg.sr_size).unwrap(),
+                mman::ProtFlags::PROT_READ | mman::ProtFlags::PROT_WRITE,
+                mman::MapFlags::MAP_SHARED,
+                reg.sr_fd.try_clone()?,
+                0,
+            )?;
+
+            for row in 0..height {
+                let src = &data[row * stride..row * stride + row_size];
+                let dst = (ptr.as_ptr() as *mut u8).add(offset + row * self.sb_stride as usize);
+                std::ptr::copy_nonoverlapping(src.as_ptr(), dst, row_size);
+            }
+
+            mman::munmap(ptr, reg.sr_size)?;
+        }
+
+        Ok(())
+    }
 }
 
 // Handle buffers with shm attached
@@ -283,10 +411,11 @@ impl ws::Dispatch<wl_buffer::WlBuffer, ShmBuffer> for Climate {
 
     fn destroyed(
         state: &mut Self,
-        _client: ws::backend::ClientId,
+        client: ws::backend::ClientId,
         _resource: &wl_buffer::WlBuffer,
         data: &ShmBuffer,
     ) {
+        state.c_resources.release(client, ResourceKind::Buffer);
         // don't close shm fd here since it is handled in Drop
     }
 }
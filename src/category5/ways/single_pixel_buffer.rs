@@ -0,0 +1,109 @@
+// Implementation of single-pixel-buffer-v1
+//
+// Clients like background setters and video players use these to fill
+// an area with one color, usually scaled up with wp_viewport. We don't
+// allocate an image for them. Their Dakota resource is a color, which
+// Thundr draws as a solid quad without sampling anything.
+//
+// https://wayland.app/protocols/single-pixel-buffer-v1
+//
+// Austin Shafer - 2024
+extern crate dakota as dak;
+extern crate wayland_server as ws;
+
+use super::accounting::ResourceKind;
+use crate::category5::Climate;
+use utils::log;
+use ws::protocol::wl_buffer;
+
+use wayland_protocols::wp::single_pixel_buffer::v1::server::wp_single_pixel_buffer_manager_v1 as manager;
+
+/// The color of a single pixel buffer
+///
+/// This is the userdata of the wl_buffer.
+pub struct SinglePixelBuffer {
+    pub spb_color: dak::dom::Color,
+}
+
+impl SinglePixelBuffer {
+    /// Create a buffer from the protocol's color values
+    ///
+    /// Each channel is a premultiplied value in the range [0, u32::MAX],
+    /// but Dakota colors are not premultiplied.
+    fn new(r: u32, g: u32, b: u32, a: u32) -> Self {
+        let channel = |c: u32| c as f32 / u32::MAX as f32;
+        let alpha = channel(a);
+        let unmultiply = |c: u32| match alpha {
+            a if a > 0.0 => (channel(c) / a).min(1.0),
+            _ => 0.0,
+        };
+
+        Self {
+            spb_color: dak::dom::Color::new(unmultiply(r), unmultiply(g), unmultiply(b), alpha),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::WpSinglePixelBufferManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::WpSinglePixelBufferManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::WpSinglePixelBufferManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::WpSinglePixelBufferManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::CreateU32RgbaBuffer { id, r, g, b, a } => {
+                if !state
+                    .c_resources
+                    .reserve(client, dhandle, ResourceKind::Buffer)
+                {
+                    return;
+                }
+                data_init.init(id, SinglePixelBuffer::new(r, g, b, a));
+            }
+            manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented single pixel buffer request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<wl_buffer::WlBuffer, SinglePixelBuffer> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wl_buffer::WlBuffer,
+        request: wl_buffer::Request,
+        data: &SinglePixelBuffer,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        client: ws::backend::ClientId,
+        _resource: &wl_buffer::WlBuffer,
+        data: &SinglePixelBuffer,
+    ) {
+        state.c_resources.release(client, ResourceKind::Buffer);
+    }
+}
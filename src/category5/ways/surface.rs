@@ -7,11 +7,17 @@
 // Austin Shafer - 2020
 extern crate dakota as dak;
 extern crate wayland_server as ws;
+use wayland_protocols::wp::content_type::v1::server::wp_content_type_v1;
+use wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
+use wayland_protocols::wp::tearing_control::v1::server::wp_tearing_control_v1;
+use wayland_protocols::wp::viewporter::server::wp_viewport;
 use ws::protocol::wl_surface::Request;
 use ws::protocol::{wl_buffer, wl_callback, wl_output, wl_region, wl_surface as wlsi};
 use ws::Resource;
 
+use super::accounting::ResourceKind;
 use super::role::Role;
+use super::single_pixel_buffer::SinglePixelBuffer;
 use super::wl_region::Region;
 use super::{shm::ShmBuffer, wl_subcompositor::SubSurfaceState, xdg_shell::XdgState};
 use crate::category5::atmosphere::{Atmosphere, SurfaceId};
@@ -34,6 +40,16 @@ impl ws::Dispatch<wlsi::WlSurface, Arc<Mutex<Surface>>> for Climate {
         dhandle: &ws::DisplayHandle,
         data_init: &mut ws::DataInit<'_, Self>,
     ) {
+        // Frame callbacks are counted against the client
+        if let Request::Frame { .. } = &request {
+            if !state
+                .c_resources
+                .reserve(client, dhandle, ResourceKind::Callback)
+            {
+                return;
+            }
+        }
+
         let surf = resource.data::<Arc<Mutex<Surface>>>().unwrap();
         surf.lock().unwrap().handle_request(
             &mut state.c_scene,
@@ -46,10 +62,11 @@ impl ws::Dispatch<wlsi::WlSurface, Arc<Mutex<Surface>>> for Climate {
 
     fn destroyed(
         state: &mut Self,
-        _client: ws::backend::ClientId,
+        client: ws::backend::ClientId,
         resource: &wlsi::WlSurface,
         surf: &Arc<Mutex<Surface>>,
     ) {
+        state.c_resources.release(client, ResourceKind::Surface);
         surf.lock()
             .unwrap()
             .destroy(state.c_atmos.lock().unwrap().deref_mut());
@@ -76,6 +93,8 @@ pub struct CommitState {
     /// This is a power saving feature, we will signal this when the
     /// client should redraw this surface
     pub cs_frame_callbacks: Vec<wl_callback::WlCallback>,
+    /// Presentation feedback requested for this commit
+    pub cs_presentation_feedback: Vec<wp_presentation_feedback::WpPresentationFeedback>,
     /// The opaque region.
     /// vkcomp can optimize displaying this region
     pub cs_opaque: Option<Arc<Mutex<Region>>>,
@@ -89,6 +108,20 @@ pub struct CommitState {
     pub cs_damage: dak::Damage,
     /// Surface position change from attach/offset
     cs_attached_xy: Option<(i32, i32)>,
+    /// The wp_viewport of this surface, if it has one
+    pub cs_viewport: Option<wp_viewport::WpViewport>,
+    /// The area of the buffer shown by the viewport: x, y, width, height
+    pub cs_viewport_src: Option<(f64, f64, f64, f64)>,
+    /// The size the viewport scales the surface to
+    pub cs_viewport_dst: Option<(i32, i32)>,
+    /// The wp_content_type_v1 of this surface, if it has one
+    pub cs_content_type: Option<wp_content_type_v1::WpContentTypeV1>,
+    /// What kind of content the client says this surface shows
+    pub cs_content: wp_content_type_v1::Type,
+    /// The wp_tearing_control_v1 of this surface, if it has one
+    pub cs_tearing_control: Option<wp_tearing_control_v1::WpTearingControlV1>,
+    /// Has the client asked for async presentation?
+    pub cs_tearing: bool,
 
     /// State programmed by wl_subcompositor
     pub cs_subsurf_state: SubSurfaceState,
@@ -110,11 +143,19 @@ impl CommitState {
             cs_id: id.clone(),
             cs_buffer: None,
             cs_frame_callbacks: Vec::with_capacity(1),
+            cs_presentation_feedback: Vec::with_capacity(0),
             cs_opaque: None,
             cs_input: None,
             cs_surf_damage: dak::Damage::empty(),
             cs_damage: dak::Damage::empty(),
             cs_attached_xy: None,
+            cs_viewport: None,
+            cs_viewport_src: None,
+            cs_viewport_dst: None,
+            cs_content_type: None,
+            cs_content: wp_content_type_v1::Type::None,
+            cs_tearing_control: None,
+            cs_tearing: false,
             cs_subsurf_state: SubSurfaceState::new(id),
             cs_children: Vec::with_capacity(0),
             cs_xdg_state: XdgState::empty(),
@@ -129,6 +170,12 @@ impl CommitState {
         let mut frame_callbacks = Vec::with_capacity(1);
         std::mem::swap(&mut frame_callbacks, &mut self.cs_frame_callbacks);
 
+        let mut presentation_feedback = Vec::with_capacity(0);
+        std::mem::swap(
+            &mut presentation_feedback,
+            &mut self.cs_presentation_feedback,
+        );
+
         let mut children = Vec::with_capacity(0);
         std::mem::swap(&mut children, &mut self.cs_children);
 
@@ -141,11 +188,19 @@ impl CommitState {
             cs_id: self.cs_id.clone(),
             cs_buffer: self.cs_buffer.clone(),
             cs_frame_callbacks: frame_callbacks,
+            cs_presentation_feedback: presentation_feedback,
             cs_opaque: self.cs_opaque.clone(),
             cs_input: self.cs_input.clone(),
             cs_surf_damage: surf_damage,
             cs_damage: damage,
             cs_attached_xy: self.cs_attached_xy.take(),
+            cs_viewport: self.cs_viewport.clone(),
+            cs_viewport_src: self.cs_viewport_src,
+            cs_viewport_dst: self.cs_viewport_dst,
+            cs_content_type: self.cs_content_type.clone(),
+            cs_content: self.cs_content,
+            cs_tearing_control: self.cs_tearing_control.clone(),
+            cs_tearing: self.cs_tearing,
             cs_subsurf_state: self.cs_subsurf_state.clone_refresh(),
             cs_xdg_state: self.cs_xdg_state.clone_refresh(),
             cs_children: children,
@@ -192,13 +247,66 @@ impl CommitState {
                 }
 
                 surf_size = (shm_buffer.sb_width as f32, shm_buffer.sb_height as f32)
+            } else if let Some(pixel) = buf.data::<SinglePixelBuffer>() {
+                // Single pixel buffers are drawn as a solid color, no image
+                // is needed for them
+                if let Err(e) = scene.define_resource_from_color(&buffer_id, pixel.spb_color) {
+                    log::error!("Error during commit: {:?}", e);
+                    return;
+                }
+                atmos.a_surf_resource.set(&self.cs_id, buffer_id.clone());
+                // We have what we need, the client can reuse this buffer
+                buf.release();
+
+                surf_size = (1.0, 1.0)
             } else {
-                panic!("Could not find dmabuf or shmbuf private data for wl_buffer");
+                panic!("Could not find dmabuf, shm or single pixel private data for wl_buffer");
+            }
+
+            atmos.a_buffer_size.set(&self.cs_id, surf_size);
+            atmos.a_thumbnail_stale.set(&self.cs_id, true);
+        }
+
+        // ----- Apply our viewport -----
+        // The viewport's destination size replaces the buffer size. Without
+        // one the size of the source rectangle is used.
+        let buffer_size = atmos.a_buffer_size.get_clone(&self.cs_id);
+        surf_size = match (self.cs_viewport_dst, self.cs_viewport_src) {
+            (Some((width, height)), _) => (width as f32, height as f32),
+            (None, Some((_, _, width, height))) => {
+                if width.fract() != 0.0 || height.fract() != 0.0 {
+                    if let Some(viewport) = self.cs_viewport.as_ref() {
+                        viewport.post_error(
+                            wp_viewport::Error::BadSize as u32,
+                            "Source size must be an integer without a destination size".to_string(),
+                        );
+                    }
+                    return;
+                }
+                (width as f32, height as f32)
+            }
+            (None, None) => buffer_size.unwrap_or(surf_size),
+        };
+        if let (Some((x, y, width, height)), Some(buffer_size)) =
+            (self.cs_viewport_src, buffer_size)
+        {
+            if x + width > buffer_size.0 as f64 || y + height > buffer_size.1 as f64 {
+                if let Some(viewport) = self.cs_viewport.as_ref() {
+                    viewport.post_error(
+                        wp_viewport::Error::OutOfBuffer as u32,
+                        "Source rectangle extends outside of the buffer".to_string(),
+                    );
+                }
+                return;
             }
         }
 
         atmos.a_surface_size.set(&self.cs_id, surf_size);
 
+        // ----- Commit our presentation hints -----
+        atmos.a_content_type.set(&self.cs_id, self.cs_content);
+        atmos.a_tearing.set(&self.cs_id, self.cs_tearing);
+
         // ----- Commit our frame callbacks -----
         if self.cs_frame_callbacks.len() > 0 {
             log::debug!(
@@ -219,6 +327,21 @@ impl CommitState {
             self.cs_frame_callbacks.clear();
         }
 
+        // ----- Commit our presentation feedback -----
+        if !self.cs_presentation_feedback.is_empty() {
+            // Contents that were never drawn have been replaced by
+            // this commit, so they will never be shown
+            if let Some(old) = atmos.a_presentation_feedback.take(&self.cs_id) {
+                for feedback in old.iter() {
+                    feedback.discarded();
+                }
+            }
+
+            let mut feedback = Vec::with_capacity(0);
+            std::mem::swap(&mut feedback, &mut self.cs_presentation_feedback);
+            atmos.a_presentation_feedback.set(&self.cs_id, feedback);
+        }
+
         // ------ Update damage regions -----
         if !self.cs_surf_damage.is_empty() {
             let mut nd = dak::Damage::empty();
@@ -509,9 +632,10 @@ impl ws::Dispatch<wl_callback::WlCallback, ()> for Climate {
 
     fn destroyed(
         state: &mut Self,
-        _client: ws::backend::ClientId,
+        client: ws::backend::ClientId,
         _resource: &wl_callback::WlCallback,
         data: &(),
     ) {
+        state.c_resources.release(client, ResourceKind::Callback);
     }
 }
@@ -0,0 +1,187 @@
+// Implementation of tablet-unstable-v2
+//
+// This delivers drawing tablet input to clients, including pressure
+// and tilt. Each tablet seat a client creates is given one tablet,
+// which all of our tablet devices are reported through, along with a
+// pen and an eraser tool. The input subsystem sends tool events to the
+// surface under the tool.
+//
+// https://wayland.app/protocols/tablet-unstable-v2
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use crate::category5::Climate;
+use dakota as dak;
+use utils::log;
+use ws::Resource;
+
+use wayland_protocols::wp::tablet::zv2::server::{
+    zwp_tablet_manager_v2 as manager, zwp_tablet_seat_v2 as tablet_seat,
+    zwp_tablet_tool_v2 as tool, zwp_tablet_v2 as tablet,
+};
+
+use std::ops::DerefMut;
+
+/// The tools advertised to each tablet seat
+///
+/// Tablet tools are reported as whichever of these is closest.
+const TABLET_TOOLS: [tool::Type; 2] = [tool::Type::Pen, tool::Type::Eraser];
+
+/// A zwp_tablet_seat_v2 and the objects we created for it
+pub struct TabletSeat {
+    pub ts_seat: tablet_seat::ZwpTabletSeatV2,
+    pub ts_tablet: tablet::ZwpTabletV2,
+    pub ts_tools: Vec<(tool::Type, tool::ZwpTabletToolV2)>,
+}
+
+impl TabletSeat {
+    /// Get the tool that Dakota's tool type is reported as
+    pub fn get_tool(&self, tool_type: dak::TabletToolType) -> Option<&tool::ZwpTabletToolV2> {
+        let wl_type = match tool_type {
+            dak::TabletToolType::Eraser => tool::Type::Eraser,
+            _ => tool::Type::Pen,
+        };
+
+        self.ts_tools
+            .iter()
+            .find(|(t, _)| *t == wl_type)
+            .map(|(_, tool)| tool)
+    }
+}
+
+/// Announce our tablet and tools on a new tablet seat
+fn create_tablet_seat(
+    client: &ws::Client,
+    dhandle: &ws::DisplayHandle,
+    seat: tablet_seat::ZwpTabletSeatV2,
+) -> Option<TabletSeat> {
+    let version = seat.version();
+
+    let tablet = client
+        .create_resource::<tablet::ZwpTabletV2, (), Climate>(dhandle, version, ())
+        .ok()?;
+    seat.tablet_added(&tablet);
+    tablet.name("Category5 Tablet".to_string());
+    tablet.done();
+
+    let mut tools = Vec::new();
+    for tool_type in TABLET_TOOLS.iter() {
+        let tool = client
+            .create_resource::<tool::ZwpTabletToolV2, (), Climate>(dhandle, version, ())
+            .ok()?;
+        seat.tool_added(&tool);
+        tool._type(*tool_type);
+        tool.capability(tool::Capability::Pressure);
+        tool.capability(tool::Capability::Tilt);
+        tool.done();
+        tools.push((*tool_type, tool));
+    }
+
+    Some(TabletSeat {
+        ts_seat: seat,
+        ts_tablet: tablet,
+        ts_tools: tools,
+    })
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ZwpTabletManagerV2, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ZwpTabletManagerV2>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ZwpTabletManagerV2, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ZwpTabletManagerV2,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::GetTabletSeat { tablet_seat, seat } => {
+                let tablet_seat = data_init.init(tablet_seat, ());
+                let tablet_seat = match create_tablet_seat(client, dhandle, tablet_seat) {
+                    Some(ts) => ts,
+                    None => {
+                        log::error!("Could not create tablet objects for client");
+                        return;
+                    }
+                };
+
+                // Add this to the seat instance for the wl_seat it was
+                // created from
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let id = super::utils::get_id_from_client(atmos.deref_mut(), client.clone());
+                if let Some(cell) = atmos.get_seat_from_client_id(&id) {
+                    let mut seat_data = cell.lock().unwrap();
+                    if let Some(si) = seat_data.s_proxies.iter_mut().find(|si| si.si_seat == seat) {
+                        si.si_tablet_seats.push(tablet_seat);
+                    }
+                }
+            }
+            manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented tablet manager request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<tablet_seat::ZwpTabletSeatV2, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &tablet_seat::ZwpTabletSeatV2,
+        request: tablet_seat::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<tablet::ZwpTabletV2, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &tablet::ZwpTabletV2,
+        request: tablet::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<tool::ZwpTabletToolV2, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &tool::ZwpTabletToolV2,
+        request: tool::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            // Tools use the default cursor for now
+            tool::Request::SetCursor { .. } => {}
+            tool::Request::Destroy => {}
+            _ => log::error!("Unimplemented tablet tool request {:?}", request),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Implementation of tearing-control-v1
+//
+// Games can ask for their frames to be shown as soon as they are ready,
+// even if that tears. We only allow this while the window asking for it
+// is fullscreen and focused, since tearing anything else would look
+// broken. The output then flips without waiting for vblank, using
+// IMMEDIATE presentation or async DRM page flips.
+//
+// https://wayland.app/protocols/tearing-control-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::surface::Surface;
+use crate::category5::Climate;
+use utils::log;
+use ws::protocol::wl_surface as wlsi;
+use ws::Resource;
+
+use wayland_protocols::wp::tearing_control::v1::server::{
+    wp_tearing_control_manager_v1 as manager, wp_tearing_control_v1 as tearing_control,
+};
+
+use std::sync::{Arc, Mutex};
+
+impl Climate {
+    /// Let the output tear if the focused window wants it to
+    ///
+    /// This should be called once per loop after dispatching clients, so
+    /// that tearing stops as soon as the game leaves fullscreen or loses
+    /// focus.
+    pub fn update_tearing(&mut self) {
+        let tearing = {
+            let atmos = self.c_atmos.lock().unwrap();
+            match atmos.get_fullscreen_win_in_focus() {
+                Some(win) => atmos.a_tearing.get_clone(&win).unwrap_or(false),
+                None => false,
+            }
+        };
+
+        if tearing != self.c_output.is_tearing() {
+            log::debug!("Tearing {}", if tearing { "enabled" } else { "disabled" });
+            if let Err(e) = self.c_output.set_tearing(tearing) {
+                log::error!("Could not change output tearing: {:?}", e);
+            }
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::WpTearingControlManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::WpTearingControlManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::WpTearingControlManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::WpTearingControlManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::GetTearingControl { id, surface } => {
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                let mut surf = surf.lock().unwrap();
+                if surf.s_state.cs_tearing_control.is_some() {
+                    resource.post_error(
+                        manager::Error::TearingControlExists as u32,
+                        "This surface already has a tearing control object".to_string(),
+                    );
+                    return;
+                }
+
+                surf.s_state.cs_tearing_control = Some(data_init.init(id, surface.clone()));
+            }
+            manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented tearing control request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<tearing_control::WpTearingControlV1, wlsi::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &tearing_control::WpTearingControlV1,
+        request: tearing_control::Request,
+        surface: &wlsi::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        if !surface.is_alive() {
+            return;
+        }
+
+        let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+        let mut surf = surf.lock().unwrap();
+        let state = &mut surf.s_state;
+
+        match request {
+            tearing_control::Request::SetPresentationHint { hint } => {
+                state.cs_tearing =
+                    hint.into_result().ok() == Some(tearing_control::PresentationHint::Async);
+            }
+            // The surface goes back to vsync on the next commit
+            tearing_control::Request::Destroy => {
+                state.cs_tearing_control = None;
+                state.cs_tearing = false;
+            }
+            _ => log::error!("Unimplemented tearing control request {:?}", request),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+// Implementation of the wl_touch interface
+//
+// Touch events are sent by the input subsystem, the only
+// request a client can make is to release the touch object.
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+use crate::category5::Climate;
+use ws::protocol::wl_touch;
+
+// Dispatch<Interface, Userdata>
+#[allow(unused_variables)]
+impl ws::Dispatch<wl_touch::WlTouch, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wl_touch::WlTouch,
+        request: wl_touch::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        _resource: &wl_touch::WlTouch,
+        data: &(),
+    ) {
+    }
+}
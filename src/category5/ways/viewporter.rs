@@ -0,0 +1,136 @@
+// Implementation of wp_viewporter
+//
+// Viewports let clients pick the size their surface is shown at,
+// independent of the size of their buffer. Combined with single pixel
+// buffers this lets a client fill any area with a color without
+// allocating anything.
+//
+// The source rectangle only changes the size of the surface. Dakota
+// always draws the entire buffer, so the buffer is not cropped to it.
+//
+// https://wayland.app/protocols/viewporter
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::surface::Surface;
+use crate::category5::Climate;
+use utils::log;
+use ws::protocol::wl_surface as wlsi;
+use ws::Resource;
+
+use wayland_protocols::wp::viewporter::server::{wp_viewport, wp_viewporter};
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<wp_viewporter::WpViewporter, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<wp_viewporter::WpViewporter>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<wp_viewporter::WpViewporter, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wp_viewporter::WpViewporter,
+        request: wp_viewporter::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_viewporter::Request::GetViewport { id, surface } => {
+                let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+                let mut surf = surf.lock().unwrap();
+                if surf.s_state.cs_viewport.is_some() {
+                    resource.post_error(
+                        wp_viewporter::Error::ViewportExists as u32,
+                        "This surface already has a viewport".to_string(),
+                    );
+                    return;
+                }
+
+                surf.s_state.cs_viewport = Some(data_init.init(id, surface.clone()));
+            }
+            wp_viewporter::Request::Destroy => {}
+            _ => log::error!("Unimplemented viewporter request {:?}", request),
+        }
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<wp_viewport::WpViewport, wlsi::WlSurface> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &wp_viewport::WpViewport,
+        request: wp_viewport::Request,
+        surface: &wlsi::WlSurface,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        if !surface.is_alive() {
+            if !matches!(request, wp_viewport::Request::Destroy) {
+                resource.post_error(
+                    wp_viewport::Error::NoSurface as u32,
+                    "The surface of this viewport was destroyed".to_string(),
+                );
+            }
+            return;
+        }
+
+        let surf = surface.data::<Arc<Mutex<Surface>>>().unwrap();
+        let mut surf = surf.lock().unwrap();
+        let state = &mut surf.s_state;
+
+        match request {
+            wp_viewport::Request::SetSource {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                // All -1 unsets the source rectangle
+                if x == -1.0 && y == -1.0 && width == -1.0 && height == -1.0 {
+                    state.cs_viewport_src = None;
+                } else if x < 0.0 || y < 0.0 || width <= 0.0 || height <= 0.0 {
+                    resource.post_error(
+                        wp_viewport::Error::BadValue as u32,
+                        format!("Invalid source rectangle {:?}", (x, y, width, height)),
+                    );
+                } else {
+                    state.cs_viewport_src = Some((x, y, width, height));
+                }
+            }
+            wp_viewport::Request::SetDestination { width, height } => {
+                if width == -1 && height == -1 {
+                    state.cs_viewport_dst = None;
+                } else if width <= 0 || height <= 0 {
+                    resource.post_error(
+                        wp_viewport::Error::BadValue as u32,
+                        format!("Invalid destination size {}x{}", width, height),
+                    );
+                } else {
+                    state.cs_viewport_dst = Some((width, height));
+                }
+            }
+            // The surface goes back to its buffer size on the next commit
+            wp_viewport::Request::Destroy => {
+                state.cs_viewport = None;
+                state.cs_viewport_src = None;
+                state.cs_viewport_dst = None;
+            }
+            _ => log::error!("Unimplemented viewport request {:?}", request),
+        }
+    }
+}
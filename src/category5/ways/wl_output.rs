@@ -23,6 +23,7 @@ impl ws::GlobalDispatch<wl_output::WlOutput, ()> for Climate {
     ) {
         let out = data_init.init(resource, ());
         state.send_geometry(out.clone());
+        state.workspace_output_enter(&out);
 
         // Add this new output object to our list to notify
         // when the output size changes
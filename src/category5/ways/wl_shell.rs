@@ -135,6 +135,7 @@ impl ShellSurface {
         println!("Setting surface {:?} to toplevel", surf.s_id.get_raw_id());
 
         atmos.a_toplevel.set(&surf.s_id, true);
+        atmos.assign_to_current_workspace(&surf.s_id);
         atmos.add_wm_task(wm::task::Task::new_toplevel(surf.s_id.clone()));
         // This places the surface at the front of the skiplist, aka
         // makes it in focus
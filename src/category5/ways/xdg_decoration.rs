@@ -0,0 +1,151 @@
+// Implementation of xdg-decoration-unstable-v1
+//
+// This lets toplevels choose between drawing their own titlebar and
+// having us draw it. We prefer drawing decorations ourselves so that
+// every window matches the theme, but clients may ask for client side
+// decorations and we will honor that.
+//
+// https://wayland.app/protocols/xdg-decoration-unstable-v1
+//
+// Austin Shafer - 2024
+extern crate wayland_server as ws;
+
+use super::role::Role;
+use super::xdg_shell::ShellSurface;
+use crate::category5::atmosphere::{Atmosphere, SurfaceId};
+use crate::category5::Climate;
+use utils::log;
+use ws::Resource;
+
+use wayland_protocols::xdg::decoration::zv1::server::{
+    zxdg_decoration_manager_v1 as manager, zxdg_toplevel_decoration_v1 as decoration,
+};
+
+use std::sync::{Arc, Mutex};
+
+/// The decoration mode used when the client has no preference
+const DEFAULT_MODE: decoration::Mode = decoration::Mode::ServerSide;
+
+/// Switch a window's decorations and tell the client
+///
+/// `initialized` is true if the toplevel has already been configured, in
+/// which case a new configure sequence is sent so the client can redraw
+/// without its decorations.
+fn set_mode(
+    atmos: &mut Atmosphere,
+    resource: &decoration::ZxdgToplevelDecorationV1,
+    id: &SurfaceId,
+    mode: decoration::Mode,
+    initialized: bool,
+) {
+    log::debug!("Setting decoration mode of {:?} to {:?}", id, mode);
+    atmos
+        .a_server_decorated
+        .set(id, mode == decoration::Mode::ServerSide);
+
+    resource.configure(mode);
+    if initialized {
+        atmos.configure_window(id);
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::GlobalDispatch<manager::ZxdgDecorationManagerV1, ()> for Climate {
+    fn bind(
+        state: &mut Self,
+        handle: &ws::DisplayHandle,
+        client: &ws::Client,
+        resource: ws::New<manager::ZxdgDecorationManagerV1>,
+        global_data: &(),
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<manager::ZxdgDecorationManagerV1, ()> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &manager::ZxdgDecorationManagerV1,
+        request: manager::Request,
+        data: &(),
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        match request {
+            manager::Request::GetToplevelDecoration { id, toplevel } => {
+                let ss = toplevel.data::<Arc<Mutex<ShellSurface>>>().unwrap();
+                let surf_id = ss.lock().unwrap().get_surface_id();
+
+                let deco = data_init.init(id, surf_id.clone());
+                let mut atmos = state.c_atmos.lock().unwrap();
+                let initialized = window_is_initialized(&atmos, &surf_id);
+                set_mode(&mut atmos, &deco, &surf_id, DEFAULT_MODE, initialized);
+            }
+            manager::Request::Destroy => {}
+            _ => log::error!("Unimplemented decoration manager request {:?}", request),
+        }
+    }
+}
+
+/// Has this window been configured already?
+fn window_is_initialized(atmos: &Atmosphere, id: &SurfaceId) -> bool {
+    match atmos.get_surface_from_id(id) {
+        Some(surf) => match &surf.lock().unwrap().s_role {
+            Some(Role::xdg_shell_toplevel(_, ss)) => ss.lock().unwrap().ss_serial > 0,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+#[allow(unused_variables)]
+impl ws::Dispatch<decoration::ZxdgToplevelDecorationV1, SurfaceId> for Climate {
+    fn request(
+        state: &mut Self,
+        client: &ws::Client,
+        resource: &decoration::ZxdgToplevelDecorationV1,
+        request: decoration::Request,
+        data: &SurfaceId,
+        dhandle: &ws::DisplayHandle,
+        data_init: &mut ws::DataInit<'_, Self>,
+    ) {
+        let mut atmos = state.c_atmos.lock().unwrap();
+        let initialized = window_is_initialized(&atmos, data);
+
+        match request {
+            decoration::Request::SetMode { mode } => {
+                let mode = match mode.into_result() {
+                    Ok(mode) => mode,
+                    Err(e) => {
+                        log::error!("Invalid decoration mode: {:?}", e);
+                        DEFAULT_MODE
+                    }
+                };
+                set_mode(&mut atmos, resource, data, mode, initialized);
+            }
+            decoration::Request::UnsetMode => {
+                set_mode(&mut atmos, resource, data, DEFAULT_MODE, initialized)
+            }
+            decoration::Request::Destroy => {}
+            _ => log::error!("Unimplemented toplevel decoration request {:?}", request),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ws::backend::ClientId,
+        resource: &decoration::ZxdgToplevelDecorationV1,
+        data: &SurfaceId,
+    ) {
+        // Without the decoration object the client draws its own
+        state
+            .c_atmos
+            .lock()
+            .unwrap()
+            .a_server_decorated
+            .set(data, false);
+    }
+}
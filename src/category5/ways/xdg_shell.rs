@@ -12,6 +12,7 @@ use ws::Resource;
 use super::role::Role;
 use super::surface::*;
 use crate::category5::vkcomp::wm;
+use crate::category5::vkcomp::wm::cursor::CursorShape;
 use crate::category5::Climate;
 
 extern crate utils as cat5_utils;
@@ -238,6 +239,11 @@ pub struct ShellSurface {
 }
 
 impl ShellSurface {
+    /// Get the id of the surface this role was given to
+    pub fn get_surface_id(&self) -> SurfaceId {
+        self.ss_surface.lock().unwrap().s_id.clone()
+    }
+
     /// Register a new popup surface.
     ///
     /// A popup surface is for dropdowns and alerts, and is the consumer
@@ -308,7 +314,10 @@ impl ShellSurface {
             let tlstate = state.xs_tlstate.as_mut().unwrap();
 
             // Get the current window size
-            let mut size = if let Some(cur_size) = state.xs_size {
+            let mut size = if let Some(state_size) = tlstate.tl_state_size.take() {
+                // Entering or leaving a state picks the size for the client
+                state_size
+            } else if let Some(cur_size) = state.xs_size {
                 // if the client manually requested a size, honor that
                 cur_size
             } else {
@@ -333,6 +342,8 @@ impl ShellSurface {
                 let (cx, cy) = atmos.get_cursor_pos();
                 // Adjust the cursor position to account for the menubar
                 let (cx, cy) = atmos.get_adjusted_desktop_coord(cx as f32, cy as f32);
+                // Let the edges being dragged stick to nearby windows
+                let (cx, cy) = atmos.snap_resize_point(&surf.s_id, cx, cy);
                 let (cx, cy) = (cx as i32, cy as i32);
                 log::debug!("cursor pos {:?}", (cx, cy));
                 let wp = atmos.a_window_pos.get(&surf.s_id).unwrap();
@@ -392,6 +403,9 @@ impl ShellSurface {
                 log::debug!("Resized to {:?}", size);
             }
 
+            // Never suggest a size outside of what the client asked for
+            size = tlstate.clamp_to_size_hints(size);
+
             // build an array of state flags to pass to toplevel.configure
             let mut states: Vec<u8> = Vec::new();
             if tlstate.tl_maximized {
@@ -403,6 +417,13 @@ impl ShellSurface {
             if tlstate.tl_fullscreen {
                 states.push(xdg_toplevel::State::Fullscreen as u8);
             }
+            // Tiled states were added in version 2
+            if tlstate.tl_tiled && toplevel.version() >= 2 {
+                states.push(xdg_toplevel::State::TiledLeft as u8);
+                states.push(xdg_toplevel::State::TiledRight as u8);
+                states.push(xdg_toplevel::State::TiledTop as u8);
+                states.push(xdg_toplevel::State::TiledBottom as u8);
+            }
             log::debug!("xdg_surface: sending states {:?}", states);
 
             tlstate.tl_cached_size = (size.0, size.1);
@@ -422,6 +443,77 @@ impl ShellSurface {
         self.ss_last_acked = serial;
     }
 
+    /// Configure this toplevel to fill a tile
+    ///
+    /// This is called by the tiling layout. `size` is the size of the tile,
+    /// or None if the window is no longer tiled. Nothing is sent if the
+    /// client has already been configured for this tile.
+    pub fn configure_tile(
+        &mut self,
+        atmos: &mut Atmosphere,
+        xdg_surf: xdg_surface::XdgSurface,
+        surf: &mut Surface,
+        size: Option<(i32, i32)>,
+    ) {
+        let state = &mut surf.s_state.cs_xdg_state;
+        let tlstate = match state.xs_tlstate.as_mut() {
+            Some(tl) => tl,
+            None => return,
+        };
+
+        match size {
+            Some(size) => {
+                if tlstate.tl_tiled && tlstate.tl_cached_size == size {
+                    return;
+                }
+                tlstate.tl_tiled = true;
+                tlstate.tl_cached_size = size;
+                // Our tile takes precedence over the client's geometry
+                state.xs_size = None;
+            }
+            None => {
+                if !tlstate.tl_tiled {
+                    return;
+                }
+                tlstate.tl_tiled = false;
+            }
+        }
+
+        self.configure(atmos, xdg_surf, surf, false);
+    }
+
+    /// Configure this toplevel for the size of its current state
+    ///
+    /// This is used when the output is resized. Windows which aren't
+    /// maximized or fullscreen are left alone.
+    pub fn configure_state(
+        &mut self,
+        atmos: &mut Atmosphere,
+        xdg_surf: xdg_surface::XdgSurface,
+        surf: &mut Surface,
+    ) {
+        let tlstate = match surf.s_state.cs_xdg_state.xs_tlstate.as_mut() {
+            Some(tl) => tl,
+            None => return,
+        };
+        if !tlstate.tl_maximized && !tlstate.tl_fullscreen {
+            return;
+        }
+
+        tlstate.update_state_size(atmos);
+        self.configure(atmos, xdg_surf, surf, false);
+    }
+
+    /// Ask the client to close this toplevel
+    ///
+    /// This only sends xdg_toplevel.close, the client is free to ignore
+    /// it or to show a confirmation dialog before destroying the window.
+    pub fn send_close(&self) {
+        if let Some(toplevel) = self.ss_xdg_toplevel.as_ref() {
+            toplevel.close();
+        }
+    }
+
     /// Set the window geometry for this surface
     ///
     /// ???: According to the spec:
@@ -474,6 +566,7 @@ impl ShellSurface {
         // Tell vkcomp to create a new window
         log::debug!("Setting surface {:?} to toplevel", surf.s_id.get_raw_id());
         atmos.a_toplevel.set(&surf.s_id, true);
+        atmos.assign_to_current_workspace(&surf.s_id);
         atmos.add_wm_task(wm::task::Task::new_toplevel(surf.s_id.clone()));
         // This places the surface at the front of the skiplist, aka
         // makes it in focus
@@ -511,18 +604,29 @@ pub struct ToplevelState {
     /// bounding dimensions. Zero means no bound in that dimension.
     pub tl_max_size: Option<(i32, i32)>,
     pub tl_min_size: Option<(i32, i32)>,
-    // self-explanitory I think
+    // self-explanitory I think. Minimized windows are tracked by atmos,
+    // since there is no way for the client to leave that state.
     pub tl_maximized: bool,
-    pub tl_minimized: bool,
     pub tl_fullscreen: bool,
     /// Is the window currently in focus?
     pub tl_activated: bool,
     pub tl_resizing: bool,
+    /// Is this window placed by a tiling layout?
+    pub tl_tiled: bool,
     /// The latest size used during configure. This doesn't
     /// actually control any commit behavior, but it is how we
     /// figure out the right size to recommend to our client
     /// every time we call configure.
     tl_cached_size: (i32, i32),
+    /// The window geometry from before it was maximized or made
+    /// fullscreen, which it returns to afterwards
+    tl_saved_geometry: Option<((i32, i32), (i32, i32))>,
+    /// The position to move the window back to on the next commit
+    tl_restore_pos: Option<(i32, i32)>,
+    /// The size picked for the current state. This takes precedence
+    /// over the client's geometry in the next configure, and zero lets
+    /// the client pick.
+    tl_state_size: Option<(i32, i32)>,
 }
 
 impl ToplevelState {
@@ -531,10 +635,13 @@ impl ToplevelState {
         ToplevelState {
             tl_maximized: false,
             tl_fullscreen: false,
-            tl_minimized: false,
             tl_activated: false,
             tl_resizing: false,
+            tl_tiled: false,
             tl_cached_size: (0, 0),
+            tl_saved_geometry: None,
+            tl_restore_pos: None,
+            tl_state_size: None,
             tl_title: None,
             tl_app_id: None,
             tl_resize_left: false,
@@ -546,6 +653,60 @@ impl ToplevelState {
         }
     }
 
+    /// Clamp a size to the min/max size hints set by the client
+    ///
+    /// A zero in the size means the client may pick, and a zero in a
+    /// hint means that dimension is unbounded. Both are left alone.
+    fn clamp_to_size_hints(&self, size: (i32, i32)) -> (i32, i32) {
+        let min = self.tl_min_size.unwrap_or((0, 0));
+        let max = self.tl_max_size.unwrap_or((0, 0));
+        let clamp = |val: i32, min: i32, max: i32| {
+            if val == 0 {
+                return 0;
+            }
+            let val = val.max(min);
+            match max {
+                0 => val,
+                max => val.min(max),
+            }
+        };
+
+        (clamp(size.0, min.0, max.0), clamp(size.1, min.1, max.1))
+    }
+
+    /// Remember the window geometry before leaving the normal state
+    ///
+    /// This should be called before setting the maximized or fullscreen
+    /// flags.
+    fn save_geometry(&mut self, surf_id: &SurfaceId, atmos: &Atmosphere) {
+        if self.tl_maximized || self.tl_fullscreen || self.tl_saved_geometry.is_some() {
+            return;
+        }
+        let ((x, y), (width, height)) = atmos.get_window_geometry(surf_id);
+        self.tl_saved_geometry = Some(((x as i32, y as i32), (width as i32, height as i32)));
+    }
+
+    /// Pick the size for the next configure after our state changed
+    ///
+    /// Fullscreen windows cover the output and maximized windows fill the
+    /// desktop. Once a window is in neither state it returns to the
+    /// geometry it had before, and is moved back on the next commit.
+    fn update_state_size(&mut self, atmos: &Atmosphere) {
+        let (_, size) = if self.tl_fullscreen {
+            atmos.get_fullscreen_area()
+        } else if self.tl_maximized {
+            atmos.get_maximized_area()
+        } else {
+            let saved = self.tl_saved_geometry.take();
+            self.tl_restore_pos = saved.map(|(pos, _)| pos);
+            self.tl_state_size = saved.map(|(_, size)| size);
+            return;
+        };
+
+        self.tl_restore_pos = None;
+        self.tl_state_size = Some((size.0 as i32, size.1 as i32));
+    }
+
     fn commit(&mut self, surf_id: &SurfaceId, atmos: &mut Atmosphere, size_diff: (f32, f32)) {
         // If we are resizing the left or top, then we need to offset
         // our window position by the change in size
@@ -569,7 +730,22 @@ impl ToplevelState {
             log::debug!("New surface pos is {:?}", *sp);
         }
 
-        // TODO: handle min/max/fullscreen/activated
+        // Place the window for its state now that the client has drawn
+        // itself in it. Tiled windows are placed by the layout.
+        if self.tl_fullscreen {
+            atmos.place_fullscreen_window(surf_id);
+        } else if self.tl_maximized && !atmos.window_is_tiled(surf_id) {
+            let (pos, _) = atmos.get_maximized_area();
+            atmos.move_window_to(surf_id, pos);
+        } else if let Some((x, y)) = self.tl_restore_pos.take() {
+            if !atmos.window_is_tiled(surf_id) {
+                atmos.move_window_to(surf_id, (x as f32, y as f32));
+            }
+        }
+
+        // Check that a fullscreen client drew the size we asked for before
+        // presenting it as covering the output
+        atmos.update_covers_output(surf_id, self.tl_fullscreen.then_some(self.tl_cached_size));
     }
 }
 
@@ -734,9 +910,13 @@ impl ShellSurface {
         _toplevel: &xdg_toplevel::XdgToplevel,
         req: xdg_toplevel::Request,
     ) {
-        let mut surf = self.ss_surface.lock().unwrap();
+        let surf_cell = self.ss_surface.clone();
+        let mut surf = surf_cell.lock().unwrap();
         let id = surf.s_id.clone();
         let tl = surf.s_state.cs_xdg_state.xs_tlstate.as_mut().unwrap();
+        // State changes are answered with a configure once handled
+        let mut reconfigure = false;
+        let mut minimize = false;
 
         #[allow(unused_variables)]
         match req {
@@ -745,10 +925,15 @@ impl ShellSurface {
             xdg_toplevel::Request::SetTitle { title } => tl.tl_title = Some(title),
             xdg_toplevel::Request::SetAppId { app_id } => tl.tl_app_id = Some(app_id),
             xdg_toplevel::Request::ShowWindowMenu { seat, serial, x, y } => (),
+            // Tiled windows are placed by the layout, not the user, and
+            // maximized and fullscreen windows stay where they are
+            xdg_toplevel::Request::Move { .. } | xdg_toplevel::Request::Resize { .. }
+                if atmos.window_is_tiled(&id) || tl.tl_maximized || tl.tl_fullscreen => {}
             xdg_toplevel::Request::Move { seat, serial } => {
                 // Moving is NOT double buffered so just grab it now
                 let id = surf.s_id.clone();
                 atmos.set_grabbed(Some(id));
+                atmos.set_cursor_shape(CursorShape::Move);
             }
             xdg_toplevel::Request::Resize {
                 seat,
@@ -756,13 +941,15 @@ impl ShellSurface {
                 edges,
             } => {
                 // Moving is NOT double buffered so just grab it now
-                atmos.set_resizing(Some(id));
+                atmos.set_resizing(Some(id.clone()));
+                let edge = edges.into_result().expect("Invalid resize edge flag");
+                atmos.set_cursor_shape(CursorShape::from_resize_edge(edge));
                 (
                     tl.tl_resize_right,
                     tl.tl_resize_left,
                     tl.tl_resize_top,
                     tl.tl_resize_bottom,
-                ) = match edges.into_result().expect("Invalid resize edge flag") {
+                ) = match edge {
                     xdg_toplevel::ResizeEdge::Right => (true, false, false, false),
                     xdg_toplevel::ResizeEdge::Left => (false, true, false, false),
                     xdg_toplevel::ResizeEdge::Top => (false, false, true, false),
@@ -781,13 +968,52 @@ impl ShellSurface {
             xdg_toplevel::Request::SetMinSize { width, height } => {
                 tl.tl_min_size = Some((width, height))
             }
-            xdg_toplevel::Request::SetMaximized => tl.tl_maximized = true,
-            xdg_toplevel::Request::UnsetMaximized => tl.tl_maximized = false,
-            xdg_toplevel::Request::SetFullscreen { output } => tl.tl_fullscreen = true,
-            xdg_toplevel::Request::UnsetFullscreen => tl.tl_fullscreen = false,
-            xdg_toplevel::Request::SetMinimized => tl.tl_minimized = true,
+            // While fullscreen these only change the state we return to
+            xdg_toplevel::Request::SetMaximized => {
+                // Tiles are already sized by the layout
+                if !atmos.window_is_tiled(&id) {
+                    tl.save_geometry(&id, atmos);
+                    tl.tl_maximized = true;
+                }
+                tl.update_state_size(atmos);
+                reconfigure = true;
+            }
+            xdg_toplevel::Request::UnsetMaximized => {
+                tl.tl_maximized = false;
+                tl.update_state_size(atmos);
+                reconfigure = true;
+            }
+            // There is only one output, so `output` is always the one used
+            xdg_toplevel::Request::SetFullscreen { output } => {
+                tl.save_geometry(&id, atmos);
+                tl.tl_fullscreen = true;
+                tl.update_state_size(atmos);
+                // Fullscreen windows leave their tile
+                atmos.mark_tiling_dirty();
+                reconfigure = true;
+            }
+            xdg_toplevel::Request::UnsetFullscreen => {
+                tl.tl_fullscreen = false;
+                tl.update_state_size(atmos);
+                atmos.mark_tiling_dirty();
+                reconfigure = true;
+            }
+            xdg_toplevel::Request::SetMinimized => minimize = true,
             _ => unimplemented!(),
         }
+
+        if minimize {
+            // Minimizing refocuses other windows, which needs our surface
+            // to be unlocked
+            drop(surf);
+            atmos.minimize_window(&id);
+        } else if reconfigure {
+            let xdg_surf = match &surf.s_role {
+                Some(Role::xdg_shell_toplevel(xs, _)) => xs.clone(),
+                _ => return,
+            };
+            self.configure(atmos, xdg_surf, &mut surf, false);
+        }
     }
 }
 
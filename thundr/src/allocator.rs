@@ -0,0 +1,352 @@
+// GPU memory suballocator
+//
+// Giving every image and buffer its own VkDeviceMemory quickly runs into
+// the driver's allocation count limit, and fragments memory when many
+// clients are connected. Instead we allocate large blocks of memory and
+// hand out regions of them.
+//
+// Each block keeps a sorted list of its free regions. Allocations take
+// the first region they fit in, and freed regions are merged back with
+// their neighbors. Linear resources (buffers and linear images) are kept
+// in separate blocks from optimally tiled images, so that we never need
+// to worry about bufferImageGranularity.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+/// The size of the memory blocks we suballocate from
+pub(crate) const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+/// Allocations at least this large get their own VkDeviceMemory
+pub(crate) const DEDICATED_THRESHOLD: u64 = BLOCK_SIZE / 4;
+
+/// Where the memory of an Allocation came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AllocationKind {
+    /// A region of the block with this id
+    Block(u64),
+    /// A VkDeviceMemory of its own, made by the Allocator
+    Dedicated,
+    /// Memory imported from or exported to somewhere else, which the
+    /// Allocator does not keep statistics for
+    External,
+}
+
+/// A region of device memory backing an image or buffer
+///
+/// This must be freed with `Device::free_allocation`.
+#[derive(Debug)]
+pub struct Allocation {
+    pub(crate) a_memory: vk::DeviceMemory,
+    /// Offset of this allocation into a_memory
+    pub(crate) a_offset: u64,
+    pub(crate) a_size: u64,
+    /// Host pointer to the start of this allocation. This is null if
+    /// the memory is not host visible.
+    pub(crate) a_mapped: *mut u8,
+    a_kind: AllocationKind,
+}
+
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+impl Allocation {
+    /// An empty allocation, which is safe to free
+    pub(crate) fn null() -> Self {
+        Self::external(vk::DeviceMemory::null(), 0)
+    }
+
+    /// Wrap memory which was not allocated by the Allocator
+    ///
+    /// This is used for dmabuf imports, which always need a dedicated
+    /// allocation. The memory is freed along with the Allocation.
+    pub(crate) fn external(memory: vk::DeviceMemory, size: u64) -> Self {
+        Self {
+            a_memory: memory,
+            a_offset: 0,
+            a_size: size,
+            a_mapped: std::ptr::null_mut(),
+            a_kind: AllocationKind::External,
+        }
+    }
+
+    /// Wrap a dedicated allocation made for a large resource
+    pub(crate) fn dedicated(memory: vk::DeviceMemory, size: u64, mapped: *mut u8) -> Self {
+        Self {
+            a_memory: memory,
+            a_offset: 0,
+            a_size: size,
+            a_mapped: mapped,
+            a_kind: AllocationKind::Dedicated,
+        }
+    }
+
+    /// Does this allocation have its own VkDeviceMemory
+    ///
+    /// If true the memory should be freed along with the allocation,
+    /// otherwise it needs to be returned to the Allocator.
+    pub(crate) fn is_dedicated(&self) -> bool {
+        !matches!(self.a_kind, AllocationKind::Block(_))
+    }
+}
+
+/// One VkDeviceMemory that allocations are made from
+struct Block {
+    b_id: u64,
+    b_memory_type: u32,
+    /// Does this block hold linear resources
+    b_linear: bool,
+    b_memory: vk::DeviceMemory,
+    b_size: u64,
+    /// Host pointer to the start of the block, or null
+    b_mapped: *mut u8,
+    /// Free regions as (offset, size), sorted by offset
+    b_free: Vec<(u64, u64)>,
+    b_allocation_count: usize,
+}
+
+impl Block {
+    /// Find room for `size` bytes aligned to `alignment`
+    ///
+    /// Returns the offset of the new allocation in this block.
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let alignment = alignment.max(1);
+
+        for i in 0..self.b_free.len() {
+            let (start, len) = self.b_free[i];
+            let offset = start.div_ceil(alignment) * alignment;
+            let end = offset + size;
+            if end > start + len {
+                continue;
+            }
+
+            // Split this region around the allocation, keeping any
+            // leftover space on either side
+            self.b_free.remove(i);
+            if end < start + len {
+                self.b_free.insert(i, (end, start + len - end));
+            }
+            if offset > start {
+                self.b_free.insert(i, (start, offset - start));
+            }
+
+            self.b_allocation_count += 1;
+            return Some(offset);
+        }
+
+        None
+    }
+
+    /// Return a region to this block, merging it with its neighbors
+    fn free(&mut self, offset: u64, size: u64) {
+        let i = self.b_free.partition_point(|(start, _)| *start < offset);
+        self.b_free.insert(i, (offset, size));
+
+        // Merge with the next region
+        if i + 1 < self.b_free.len() && offset + size == self.b_free[i + 1].0 {
+            self.b_free[i].1 += self.b_free[i + 1].1;
+            self.b_free.remove(i + 1);
+        }
+        // Merge with the previous region
+        if i > 0 && self.b_free[i - 1].0 + self.b_free[i - 1].1 == offset {
+            self.b_free[i - 1].1 += self.b_free[i].1;
+            self.b_free.remove(i);
+        }
+
+        self.b_allocation_count -= 1;
+    }
+
+    fn free_bytes(&self) -> u64 {
+        self.b_free.iter().map(|(_, size)| size).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.b_allocation_count == 0
+    }
+}
+
+/// Statistics about the memory managed by an Allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocatorStats {
+    /// Number of blocks we are suballocating from
+    pub as_block_count: usize,
+    /// Bytes of memory held in blocks
+    pub as_block_bytes: u64,
+    /// Number of allocations made from blocks
+    pub as_allocation_count: usize,
+    /// Bytes of blocks in use by allocations
+    pub as_used_bytes: u64,
+    /// Number of separate free regions across all blocks
+    pub as_free_region_count: usize,
+    /// The largest free region in any block
+    pub as_largest_free_region: u64,
+    /// Number of large allocations that were given their own memory
+    pub as_dedicated_count: usize,
+    pub as_dedicated_bytes: u64,
+}
+
+impl AllocatorStats {
+    /// How fragmented the free space in our blocks is
+    ///
+    /// This is the fraction of free memory which is not part of the
+    /// largest free region. 0 means all free memory is in one piece,
+    /// and values close to 1 mean it has been split into many small
+    /// regions.
+    pub fn fragmentation(&self) -> f32 {
+        let free = self.as_block_bytes - self.as_used_bytes;
+        if free == 0 {
+            return 0.0;
+        }
+        1.0 - self.as_largest_free_region as f32 / free as f32
+    }
+}
+
+/// Suballocates resources from large blocks of device memory
+///
+/// This only does the bookkeeping, the Device is in charge of creating
+/// and destroying the Vulkan memory objects.
+pub(crate) struct Allocator {
+    al_blocks: Vec<Block>,
+    al_next_block_id: u64,
+    al_dedicated_count: usize,
+    al_dedicated_bytes: u64,
+}
+
+unsafe impl Send for Allocator {}
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    pub fn new() -> Self {
+        Self {
+            al_blocks: Vec::new(),
+            al_next_block_id: 0,
+            al_dedicated_count: 0,
+            al_dedicated_bytes: 0,
+        }
+    }
+
+    /// Allocate from one of our existing blocks
+    ///
+    /// Returns None if no block of this memory type has room, in which
+    /// case a new block should be added with `add_block`.
+    pub fn allocate(
+        &mut self,
+        memory_type: u32,
+        linear: bool,
+        size: u64,
+        alignment: u64,
+    ) -> Option<Allocation> {
+        for block in self
+            .al_blocks
+            .iter_mut()
+            .filter(|b| b.b_memory_type == memory_type && b.b_linear == linear)
+        {
+            if let Some(offset) = block.allocate(size, alignment) {
+                return Some(Allocation {
+                    a_memory: block.b_memory,
+                    a_offset: offset,
+                    a_size: size,
+                    a_mapped: match block.b_mapped.is_null() {
+                        true => std::ptr::null_mut(),
+                        false => unsafe { block.b_mapped.add(offset as usize) },
+                    },
+                    a_kind: AllocationKind::Block(block.b_id),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Add a new block to allocate from
+    ///
+    /// `mapped` is a host pointer to the start of the block, or null if
+    /// the memory is not host visible.
+    pub fn add_block(
+        &mut self,
+        memory_type: u32,
+        linear: bool,
+        memory: vk::DeviceMemory,
+        size: u64,
+        mapped: *mut u8,
+    ) {
+        self.al_blocks.push(Block {
+            b_id: self.al_next_block_id,
+            b_memory_type: memory_type,
+            b_linear: linear,
+            b_memory: memory,
+            b_size: size,
+            b_mapped: mapped,
+            b_free: vec![(0, size)],
+            b_allocation_count: 0,
+        });
+        self.al_next_block_id += 1;
+    }
+
+    /// Record a dedicated allocation in our statistics
+    pub fn add_dedicated(&mut self, alloc: &Allocation) {
+        self.al_dedicated_count += 1;
+        self.al_dedicated_bytes += alloc.a_size;
+    }
+
+    /// Return an allocation
+    ///
+    /// If this leaves a block empty, and there is another block of the
+    /// same type to use, then the empty block is removed and its memory
+    /// is returned so that it can be freed. One block of each type is
+    /// always kept so that we don't keep reallocating blocks when an
+    /// image is repeatedly created and destroyed.
+    pub fn free(&mut self, alloc: &Allocation) -> Option<vk::DeviceMemory> {
+        let id = match alloc.a_kind {
+            AllocationKind::Block(id) => id,
+            AllocationKind::Dedicated => {
+                self.al_dedicated_count -= 1;
+                self.al_dedicated_bytes -= alloc.a_size;
+                return None;
+            }
+            AllocationKind::External => return None,
+        };
+
+        let index = self.al_blocks.iter().position(|b| b.b_id == id)?;
+        let block = &mut self.al_blocks[index];
+        block.free(alloc.a_offset, alloc.a_size);
+        if !block.is_empty() {
+            return None;
+        }
+
+        let (memory_type, linear) = (block.b_memory_type, block.b_linear);
+        let has_other = self
+            .al_blocks
+            .iter()
+            .any(|b| b.b_id != id && b.b_memory_type == memory_type && b.b_linear == linear);
+        match has_other {
+            true => Some(self.al_blocks.remove(index).b_memory),
+            false => None,
+        }
+    }
+
+    /// Remove all blocks, returning their memory to be freed
+    pub fn drain_blocks(&mut self) -> Vec<vk::DeviceMemory> {
+        self.al_blocks.drain(..).map(|b| b.b_memory).collect()
+    }
+
+    pub fn get_stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            as_block_count: self.al_blocks.len(),
+            as_dedicated_count: self.al_dedicated_count,
+            as_dedicated_bytes: self.al_dedicated_bytes,
+            ..Default::default()
+        };
+
+        for block in self.al_blocks.iter() {
+            stats.as_block_bytes += block.b_size;
+            stats.as_used_bytes += block.b_size - block.free_bytes();
+            stats.as_allocation_count += block.b_allocation_count;
+            stats.as_free_region_count += block.b_free.len();
+            for (_, size) in block.b_free.iter() {
+                stats.as_largest_free_region = stats.as_largest_free_region.max(*size);
+            }
+        }
+
+        stats
+    }
+}
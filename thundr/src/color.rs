@@ -0,0 +1,62 @@
+// Color spaces for outputs and images
+//
+// Images are tagged with the color space their contents are encoded in,
+// and each Display renders in the color space of its swapchain. The
+// fragment shader converts between the two when they differ.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+/// A color space and transfer function
+///
+/// The discriminants are passed to the shaders, and must match the
+/// constants defined there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// BT.709 primaries with the sRGB transfer function. This is what
+    /// normal SDR content uses.
+    #[default]
+    Srgb = 0,
+    /// BT.709 primaries with linear encoding, where 1.0 is SDR white.
+    /// Values above 1.0 are brighter than SDR white.
+    ScRgb = 1,
+    /// BT.2020 primaries with the SMPTE ST 2084 (PQ) transfer function
+    Hdr10 = 2,
+}
+
+impl ColorSpace {
+    /// Is this a high dynamic range color space
+    pub fn is_hdr(&self) -> bool {
+        *self != Self::Srgb
+    }
+
+    /// Get the color space for a Vulkan surface format
+    ///
+    /// Returns None if we do not support this format and color space
+    /// combination.
+    pub(crate) fn from_surface_format(format: &vk::SurfaceFormatKHR) -> Option<Self> {
+        match (format.format, format.color_space) {
+            (vk::Format::B8G8R8A8_UNORM, vk::ColorSpaceKHR::SRGB_NONLINEAR) => Some(Self::Srgb),
+            (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT) => {
+                Some(Self::ScRgb)
+            }
+            (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT)
+            | (vk::Format::A2R10G10B10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT) => {
+                Some(Self::Hdr10)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the surface formats used to render in this color space
+    pub(crate) fn get_surface_formats(&self) -> &'static [vk::Format] {
+        match self {
+            Self::Srgb => &[vk::Format::B8G8R8A8_UNORM],
+            Self::ScRgb => &[vk::Format::R16G16B16A16_SFLOAT],
+            Self::Hdr10 => &[
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::Format::A2R10G10B10_UNORM_PACK32,
+            ],
+        }
+    }
+}
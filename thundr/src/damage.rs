@@ -1,6 +1,7 @@
 // Damage tracking for a surface
 //
 // Austin Shafer - 2020
+use crate::Surface;
 use utils::region::Rect;
 
 /// Damage is always in surface coord space
@@ -45,4 +46,40 @@ impl Damage {
             self.d_damaged = true;
         }
     }
+
+    /// Get the smallest rect containing every damaged region
+    ///
+    /// Returns None if nothing is damaged.
+    pub fn bounding_box(&self) -> Option<Rect<i32>> {
+        let mut regions = self.d_regions.iter();
+        let mut bbox = *regions.next()?;
+        for region in regions {
+            bbox.union(region);
+        }
+        Some(bbox)
+    }
+
+    /// Convert surface damage into the area it covers on screen
+    ///
+    /// Each damaged region is transformed by the surface's transform and
+    /// replaced by its bounding box, offset by the surface position.
+    pub fn to_screen_space(&self, surf: &Surface) -> Self {
+        let pos = surf.get_pos();
+        Self {
+            d_damaged: self.d_damaged,
+            d_regions: self
+                .d_regions
+                .iter()
+                .map(|r| {
+                    let bbox = surf.s_transform.bounding_box(r);
+                    Rect::new(
+                        bbox.r_pos.0 + pos.0,
+                        bbox.r_pos.1 + pos.1,
+                        bbox.r_size.0,
+                        bbox.r_size.1,
+                    )
+                })
+                .collect(),
+        }
+    }
 }
@@ -35,15 +35,17 @@ impl DeletionQueue {
     /// Schedule the item to be dropped once the specified timeline
     /// point has passed.
     ///
-    /// This does not drop the item immediately, unless the timeline point
-    /// is already known to be signaled.
+    /// If the timeline point is already known to be signaled the item is
+    /// handed back, and the caller should drop it immediately. Items may
+    /// call back into the Device when dropped, so this lets the caller
+    /// release any locks first.
     pub fn schedule_drop_at_point(
         &mut self,
         item: Box<dyn Droppable + Send + Sync>,
         sync_point: u64,
-    ) {
+    ) -> Option<Box<dyn Droppable + Send + Sync>> {
         if sync_point <= self.dq_last_signaled {
-            return;
+            return Some(item);
         }
 
         // Add this item to an existing queue, if there is one for this sync point
@@ -53,7 +55,7 @@ impl DeletionQueue {
             .find(|pq| pq.pq_sync_point == sync_point)
         {
             point_queue.pq_items.push(item);
-            return;
+            return None;
         }
 
         // Otherwise create a new queue that contains this item
@@ -61,6 +63,27 @@ impl DeletionQueue {
             pq_sync_point: sync_point,
             pq_items: vec![item],
         });
+        None
+    }
+
+    /// Take all pending items for a timeline point
+    ///
+    /// This clears all deletion queues for this sync point, including
+    /// sync points preceeding this one. The items are returned so that
+    /// they can be dropped once any locks have been released.
+    pub fn take_all_at_point(&mut self, sync_point: u64) -> Vec<Box<dyn Droppable + Send + Sync>> {
+        self.dq_last_signaled = sync_point;
+
+        // Keep only the queues for points that have not been reached yet
+        let mut ret = Vec::new();
+        self.dq_point_queues.retain_mut(|pq| {
+            if pq.pq_sync_point > sync_point {
+                return true;
+            }
+            ret.append(&mut pq.pq_items);
+            false
+        });
+        ret
     }
 
     /// Release all pending items for a timeline point
@@ -68,9 +91,6 @@ impl DeletionQueue {
     /// This clears all deletion queues for this sync point, including
     /// sync points preceeding this one.
     pub fn drop_all_at_point(&mut self, sync_point: u64) {
-        self.dq_last_signaled = sync_point;
-
-        self.dq_point_queues
-            .retain(|pq| pq.pq_sync_point <= sync_point);
+        self.take_all_at_point(sync_point);
     }
 }
@@ -10,19 +10,31 @@ use ash::vk;
 use lluvia as ll;
 
 extern crate utils as cat5_utils;
+use crate::allocator::{Allocation, Allocator, AllocatorStats, BLOCK_SIZE, DEDICATED_THRESHOLD};
 use crate::descpool::{DescPool, Descriptor};
 #[cfg(feature = "drm")]
 extern crate drm;
 #[cfg(feature = "drm")]
 use crate::display::drm::drm_device::DrmDevice;
-use crate::image::ImageVk;
+use crate::image::{mip_region, ImageVk, ShmFormat};
 use crate::instance::Instance;
+use crate::memory::{HeapUsage, LowMemoryCallback, MemoryTracker, MemoryUsage};
+use crate::pipelines::cache::PipelineCache;
+use crate::pipelines::{lut::LutPass, GeomPipeline};
 use crate::platform::VKDeviceFeatures;
-use crate::{CreateInfo, Damage, DeletionQueue, Droppable, Result, ThundrError};
-use cat5_utils::log;
-
+use crate::profiler::CopyProfiler;
+use crate::staging::{StagingBuffer, StagingPool, StagingRelease};
+use crate::{ColorSpace, CreateInfo, Damage, DeletionQueue, Droppable, Result, ThundrError};
+use cat5_utils::region::Rect;
+use cat5_utils::{log, trace};
+
+use std::collections::HashMap;
+use std::os::unix::io::{FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 #[allow(unused_imports)]
 use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread::ThreadId;
+use std::time::Duration;
 
 /// Thundr Device
 ///
@@ -41,8 +53,23 @@ pub struct Device {
     pub(crate) mem_props: vk::PhysicalDeviceMemoryProperties,
     /// needed for VkGetMemoryFdPropertiesKHR
     pub(crate) external_mem_fd_loader: khr::ExternalMemoryFd,
+    /// needed for vkGetSemaphoreFdKHR
+    pub(crate) external_sema_fd_loader: khr::ExternalSemaphoreFd,
+    /// needed for vkGetMemoryHostPointerPropertiesEXT
+    pub(crate) external_mem_host_loader: vk::ExtExternalMemoryHostFn,
     /// Externally synchronized and mutable state
     pub(crate) d_internal: Arc<RwLock<DeviceInternal>>,
+    /// Accounting for all memory allocated on this device
+    pub(crate) d_memory: Mutex<MemoryTracker>,
+    /// Suballocator for images and buffers
+    pub(crate) d_allocator: Mutex<Allocator>,
+    /// Staging buffers for loading textures into images
+    pub(crate) d_staging: Arc<Mutex<StagingPool>>,
+    /// All pipelines are created through this, see `prewarm_pipelines`
+    pub(crate) d_pipeline_cache: PipelineCache,
+    /// Command pools for recording on other threads, keyed by the thread
+    /// and queue family. See `get_thread_command_pool`.
+    d_thread_cmd_pools: Mutex<HashMap<(ThreadId, u32), vk::CommandPool>>,
     /// This is a per-image backing resource that is resident on this Device
     pub d_image_vk: ll::Component<Arc<ImageVk>>,
     /// Drm Device corresponding to this VkDevice
@@ -51,6 +78,9 @@ pub struct Device {
     /// List of pending DRM Events
     #[cfg(feature = "drm")]
     pub d_drm_events: Arc<Mutex<Vec<drm::control::PageFlipEvent>>>,
+    /// Set when Vulkan reports VK_ERROR_DEVICE_LOST. Once lost this
+    /// device can no longer be used and must be recreated.
+    d_lost: AtomicBool,
 }
 
 /// This is the set of per-device data that needs to be "externally synchronized"
@@ -65,6 +95,7 @@ pub struct DeviceInternal {
     pub(crate) graphics_queue_families: Vec<u32>,
     /// queue for copy operations
     pub(crate) transfer_queue: vk::Queue,
+    pub(crate) transfer_queue_family: u32,
 
     pub(crate) copy_cmd_pool: vk::CommandPool,
     /// command buffer for copying shm images
@@ -78,6 +109,9 @@ pub struct DeviceInternal {
     /// The latest copy point we have already waited for. We can skip waiting on
     /// this again if needed.
     pub(crate) latest_acked_copy_timeline_point: u64,
+    /// Timestamps for uploads in copy_cbuf. This is only set while GPU
+    /// profiling is turned on.
+    pub(crate) copy_profiler: Option<CopyProfiler>,
 
     /// The latest timeline point. The last work submission is tracked by
     /// this value
@@ -88,18 +122,18 @@ pub struct DeviceInternal {
     /// the point on the timeline and bump the next value. This avoids
     /// oversynchronizing or having many semaphores.
     pub(crate) timeline_sema: vk::Semaphore,
+    /// Binary semaphores used to export sync files, along with the
+    /// timeline point after which each can be reused
+    pub(crate) sync_file_semas: Vec<(u64, vk::Semaphore)>,
 
     /// Deletion queue
     /// This holds all data that will be dropped after each frame is complete
     pub(crate) deletion_queue: DeletionQueue,
 
-    /// These are for loading textures into images
-    pub(crate) transfer_buf_len: usize,
-    pub(crate) transfer_buf: vk::Buffer,
-    pub(crate) transfer_mem: vk::DeviceMemory,
-
     /// One sampler for all swapchain images
     pub(crate) image_sampler: vk::Sampler,
+    /// Sampler for images with mipmaps. This blends between mip levels.
+    pub(crate) mip_sampler: vk::Sampler,
 
     /// Our image descriptor layout
     /// This controls allocation of image descriptors for all imagevks allocated
@@ -108,6 +142,47 @@ pub struct DeviceInternal {
 }
 
 impl Device {
+    /// Has this device been lost?
+    ///
+    /// A lost device is usually caused by a GPU reset. Every Display,
+    /// Image, and other resource created from it must be recreated on
+    /// a new Device.
+    pub fn is_lost(&self) -> bool {
+        self.d_lost.load(Ordering::SeqCst)
+    }
+
+    /// Act as if the GPU was reset
+    ///
+    /// This marks the device as lost in the same way a real DEVICE_LOST
+    /// would, so that applications can test their recovery path.
+    pub fn simulate_device_loss(&self) {
+        self.check_vk_result(Err(vk::Result::ERROR_DEVICE_LOST), "Simulating device loss");
+    }
+
+    /// Handle the result of a Vulkan call which we can't return errors from
+    ///
+    /// If the device was lost this is recorded so that the next frame can
+    /// return DEVICE_LOST. Other errors, such as running out of memory,
+    /// only fail this one call and are logged.
+    pub(crate) fn check_vk_result(&self, ret: std::result::Result<(), vk::Result>, msg: &str) {
+        match ret {
+            Ok(()) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                log::error!("{}: the Vulkan device was lost", msg);
+                self.d_lost.store(true, Ordering::SeqCst);
+            }
+            Err(e) => log::error!("{}: {:?}", msg, e),
+        }
+    }
+
+    /// Wait for all work on this device to complete
+    pub(crate) fn wait_idle(&self) {
+        self.check_vk_result(
+            unsafe { self.dev.device_wait_idle() },
+            "Could not wait for device to become idle",
+        );
+    }
+
     /// Create a vkDevice from a vkPhysicalDevice
     ///
     /// Create a logical device for interfacing with the physical device.
@@ -128,6 +203,7 @@ impl Device {
             .shader_clip_distance(true)
             .vertex_pipeline_stores_and_atomics(true)
             .fragment_stores_and_atomics(true)
+            .dual_src_blend(dev_features.vkc_supports_dual_src_blend)
             .build();
         let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::builder()
             .timeline_semaphore(true)
@@ -376,6 +452,14 @@ impl Device {
 
         let transfer_queue = unsafe { dev.get_device_queue(transfer_queue_family, 0) };
         let ext_mem_loader = khr::ExternalMemoryFd::new(&instance.inst, &dev);
+        let ext_sema_loader = khr::ExternalSemaphoreFd::new(&instance.inst, &dev);
+        let ext_mem_host_loader = vk::ExtExternalMemoryHostFn::load(|name| unsafe {
+            std::mem::transmute(
+                instance
+                    .inst
+                    .get_device_proc_addr(dev.handle(), name.as_ptr()),
+            )
+        });
 
         // make our timeline semaphore
         let mut timeline_info = vk::SemaphoreTypeCreateInfoKHR::builder()
@@ -394,6 +478,8 @@ impl Device {
                 .or(Err(ThundrError::INVALID))?
         };
         let descpool = DescPool::new(&dev);
+        let pdev_props = unsafe { instance.inst.get_physical_device_properties(pdev) };
+        let pipeline_cache = PipelineCache::new(&dev, &pdev_props);
 
         // If supported, get the DRM device fd for the master node
         // for this VkDevice
@@ -408,41 +494,52 @@ impl Device {
             pdev: pdev,
             mem_props: mem_props,
             external_mem_fd_loader: ext_mem_loader,
+            external_sema_fd_loader: ext_sema_loader,
+            external_mem_host_loader: ext_mem_host_loader,
             d_internal: Arc::new(RwLock::new(DeviceInternal {
                 d_self: Weak::new(),
                 graphics_queue_families: Vec::new(),
                 copy_cmd_pool: vk::CommandPool::null(),
                 copy_cbuf: vk::CommandBuffer::null(),
                 transfer_queue: transfer_queue,
-                transfer_buf: vk::Buffer::null(), // Initialize in its own method
-                transfer_mem: vk::DeviceMemory::null(),
-                transfer_buf_len: 0,
+                transfer_queue_family,
                 copy_timeline_point: 0,
                 latest_acked_copy_timeline_point: 0,
+                copy_profiler: None,
                 copy_timeline_sema: copy_timeline_sema,
                 timeline_point: 0,
                 timeline_sema: timeline_sema,
+                sync_file_semas: Vec::new(),
                 deletion_queue: DeletionQueue::new(),
                 descpool: descpool,
                 image_sampler: vk::Sampler::null(),
+                mip_sampler: vk::Sampler::null(),
             })),
+            d_memory: Mutex::new(MemoryTracker::new(&mem_props)),
+            d_allocator: Mutex::new(Allocator::new()),
+            d_staging: Arc::new(Mutex::new(StagingPool::new())),
+            d_pipeline_cache: pipeline_cache,
+            d_thread_cmd_pools: Mutex::new(HashMap::new()),
             d_image_vk: img_ecs.add_component(),
             #[cfg(feature = "drm")]
             d_drm_node: drm,
             #[cfg(feature = "drm")]
             d_drm_events: Arc::new(Mutex::new(Vec::new())),
+            d_lost: AtomicBool::new(false),
         });
 
         {
             let copy_cmd_pool = ret.create_command_pool(transfer_queue_family);
             let copy_cbuf = ret.create_command_buffers(copy_cmd_pool, 1)[0];
-            let sampler = ret.create_sampler();
+            let sampler = ret.create_sampler(false);
+            let mip_sampler = ret.create_sampler(true);
 
             let mut internal = ret.d_internal.write().unwrap();
             internal.d_self = Arc::downgrade(&ret);
             internal.copy_cmd_pool = copy_cmd_pool;
             internal.copy_cbuf = copy_cbuf;
             internal.image_sampler = sampler;
+            internal.mip_sampler = mip_sampler;
         }
 
         Ok(ret)
@@ -465,6 +562,59 @@ impl Device {
         }
     }
 
+    /// Get the command pool for recording on the calling thread
+    ///
+    /// Command pools must be externally synchronized, so each thread
+    /// recording in parallel needs its own. One is created the first time
+    /// a thread asks for a pool for `queue_family`, and lives until
+    /// `destroy_thread_command_pools` is called for that thread.
+    pub(crate) fn get_thread_command_pool(&self, queue_family: u32) -> vk::CommandPool {
+        let key = (std::thread::current().id(), queue_family);
+        let mut pools = self.d_thread_cmd_pools.lock().unwrap();
+        if let Some(pool) = pools.get(&key) {
+            return *pool;
+        }
+
+        let pool = self.create_command_pool(queue_family);
+        pools.insert(key, pool);
+        pool
+    }
+
+    /// Destroy the command pools of `thread`
+    ///
+    /// This frees all command buffers allocated from them, so none of
+    /// them may still be in use by the GPU.
+    pub(crate) fn destroy_thread_command_pools(&self, thread: ThreadId) {
+        let mut pools = self.d_thread_cmd_pools.lock().unwrap();
+        pools.retain(|(id, _), pool| {
+            if *id == thread {
+                unsafe { self.dev.destroy_command_pool(*pool, None) };
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Allocate a secondary command buffer
+    ///
+    /// Secondary cbufs are recorded separately and then executed as part
+    /// of a render pass in a primary cbuf.
+    pub(crate) fn create_secondary_command_buffer(
+        &self,
+        pool: vk::CommandPool,
+    ) -> vk::CommandBuffer {
+        let cbuf_allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::SECONDARY);
+
+        unsafe {
+            self.dev
+                .allocate_command_buffers(&cbuf_allocate_info)
+                .unwrap()[0]
+        }
+    }
+
     /// Allocate a vec of vkCommandBuffers
     ///
     /// Command buffers are constructed once, and can be executed
@@ -496,7 +646,7 @@ impl Device {
     /// Samplers are used to filter data from an image when
     /// it is referenced from a fragment shader. It allows
     /// for additional processing effects on the input.
-    pub(crate) fn create_sampler(&self) -> vk::Sampler {
+    pub(crate) fn create_sampler(&self, mipmapped: bool) -> vk::Sampler {
         let info = vk::SamplerCreateInfo::builder()
             // filter for magnified (oversampled) pixels
             .mag_filter(vk::Filter::LINEAR)
@@ -517,7 +667,12 @@ impl Device {
             .unnormalized_coordinates(false)
             .compare_enable(false)
             .compare_op(vk::CompareOp::ALWAYS)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR);
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            // Only look past the base level if there are mipmaps
+            .max_lod(match mipmapped {
+                true => vk::LOD_CLAMP_NONE,
+                false => 0.0,
+            });
 
         unsafe { self.dev.create_sampler(&info, None).unwrap() }
     }
@@ -543,11 +698,10 @@ impl Device {
             .build();
 
         // Immediately wait for our timeline point
-        unsafe {
-            self.dev
-                .wait_semaphores(&wait_info, u64::MAX)
-                .expect("Could not wait for timeline semaphore");
-        }
+        self.check_vk_result(
+            unsafe { self.dev.wait_semaphores(&wait_info, u64::MAX) },
+            "Could not wait for timeline semaphore",
+        );
 
         internal.latest_acked_copy_timeline_point = internal.copy_timeline_point;
     }
@@ -572,52 +726,340 @@ impl Device {
             .build();
 
         // Immediately wait for our timeline point
-        unsafe {
-            self.dev
-                .wait_semaphores(&wait_info, u64::MAX)
-                .expect("Could not wait for timeline semaphore");
-        }
+        self.check_vk_result(
+            unsafe { self.dev.wait_semaphores(&wait_info, u64::MAX) },
+            "Could not wait for timeline semaphore",
+        );
 
         internal.latest_acked_copy_timeline_point = internal.copy_timeline_point;
     }
 
-    /// Load a memory region into our staging area
-    fn upload_memimage_to_transfer(&self, data: &[u8]) {
-        unsafe {
-            // We might be in the middle of copying the transfer buf to an image
-            // wait for that if its the case
-            self.wait_for_copy();
-            let mut internal = self.d_internal.write().unwrap();
-            if data.len() > internal.transfer_buf_len {
-                let (buffer, buf_mem) = self.create_buffer(
-                    vk::BufferUsageFlags::TRANSFER_SRC,
-                    vk::SharingMode::EXCLUSIVE,
-                    vk::MemoryPropertyFlags::HOST_VISIBLE
-                        | vk::MemoryPropertyFlags::HOST_COHERENT
-                        | vk::MemoryPropertyFlags::HOST_CACHED,
-                    data,
-                );
+    /// Turn timing of image uploads on or off
+    ///
+    /// This is shared by every Display on this Device. Returns false if
+    /// the copy queue can't write timestamps.
+    pub(crate) fn set_upload_profiling(&self, enabled: bool) -> bool {
+        // The copy cbuf may still be writing our queries
+        self.wait_for_copy();
+        let mut internal = self.d_internal.write().unwrap();
+
+        if !enabled {
+            if let Some(profiler) = internal.copy_profiler.take() {
+                unsafe { profiler.destroy(&self.dev) };
+            }
+            return true;
+        }
+
+        if internal.copy_profiler.is_none() {
+            internal.copy_profiler = CopyProfiler::new(self, internal.transfer_queue_family);
+        }
+        internal.copy_profiler.is_some()
+    }
+
+    /// Get the GPU time spent uploading images since this was last called
+    ///
+    /// Uploads which are still in progress are counted next time.
+    pub(crate) fn take_upload_time(&self) -> Duration {
+        let mut internal = self.d_internal.write().unwrap();
+        match internal.copy_profiler.as_mut() {
+            Some(profiler) => profiler.take_elapsed(&self.dev),
+            None => Duration::ZERO,
+        }
+    }
 
-                self.dev.free_memory(internal.transfer_mem, None);
-                self.dev.destroy_buffer(internal.transfer_buf, None);
-                internal.transfer_buf = buffer;
-                internal.transfer_mem = buf_mem;
-                internal.transfer_buf_len = data.len();
-            } else {
-                // copy the data into the staging buffer
-                self.update_memory(internal.transfer_mem, 0, data);
+    /// Load a memory region into a staging buffer
+    ///
+    /// The buffer is taken from our staging pool, growing the pool if
+    /// none of its buffers are large enough. It must be handed to
+    /// `release_staging_buffer` once the copy from it has been submitted.
+    fn upload_to_staging(&self, data: &[u8]) -> StagingBuffer {
+        let size = data.len() as u64;
+        let reused = self.d_staging.lock().unwrap().take(size);
+
+        let buffer = match reused {
+            Some(buffer) => buffer,
+            None => {
+                // Replace one of the buffers that was too small, so that the
+                // pool does not fill up with buffers we will never use
+                let old = self.d_staging.lock().unwrap().take_smallest();
+                if let Some(old) = old {
+                    unsafe { self.destroy_staging_buffer(old) };
+                }
+                self.create_staging_buffer(StagingPool::buffer_size_for(size))
             }
+        };
+
+        // copy the data into the staging buffer
+        self.update_memory(&buffer.sb_memory, 0, data);
+        buffer
+    }
+
+    /// Create a new persistently mapped staging buffer
+    fn create_staging_buffer(&self, size: u64) -> StagingBuffer {
+        let (buffer, memory) = self.create_buffer_with_size(
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT
+                | vk::MemoryPropertyFlags::HOST_CACHED,
+            size,
+        );
+
+        StagingBuffer {
+            sb_buffer: buffer,
+            sb_memory: memory,
+            sb_size: size,
         }
     }
 
+    unsafe fn destroy_staging_buffer(&self, buffer: StagingBuffer) {
+        self.dev.destroy_buffer(buffer.sb_buffer, None);
+        self.free_allocation(&buffer.sb_memory);
+    }
+
+    /// Return a staging buffer to the pool once the GPU is done with it
+    ///
+    /// This must be called after the copy using this buffer has been
+    /// submitted. Graphics work waits for all submitted copies, so the
+    /// buffer is free to reuse once the next graphics submission completes.
+    fn release_staging_buffer(&self, buffer: StagingBuffer) {
+        let mut internal = self.d_internal.write().unwrap();
+        let sync_point = internal.timeline_point + 1;
+
+        internal.deletion_queue.schedule_drop_at_point(
+            Box::new(StagingRelease {
+                sr_pool: self.d_staging.clone(),
+                sr_buffer: Some(buffer),
+            }),
+            sync_point,
+        );
+    }
+
+    /// Wrapper for allocating device memory
+    ///
+    /// This records the allocation so that we can track our usage
+    /// against the memory budget.
+    pub(crate) unsafe fn allocate_memory(
+        &self,
+        info: &vk::MemoryAllocateInfo,
+    ) -> ash::prelude::VkResult<vk::DeviceMemory> {
+        let mem = self.dev.allocate_memory(info, None)?;
+        let heap = self.mem_props.memory_types[info.memory_type_index as usize].heap_index;
+        self.d_memory
+            .lock()
+            .unwrap()
+            .add_allocation(mem, heap as usize, info.allocation_size);
+
+        Ok(mem)
+    }
+
     /// Wrapper for freeing device memory
     ///
     /// Having this in one place lets us quickly handle any additional
     /// allocation tracking
     pub(crate) unsafe fn free_memory(&self, mem: vk::DeviceMemory) {
+        self.d_memory.lock().unwrap().remove_allocation(mem);
         self.dev.free_memory(mem, None);
     }
 
+    /// Allocate memory for an image or buffer
+    ///
+    /// Small resources are suballocated from large blocks of memory, and
+    /// large ones get a dedicated allocation. `linear` should be true for
+    /// buffers and linear images, which are kept separate from optimally
+    /// tiled images. Host visible memory is always mapped.
+    pub(crate) fn allocate(
+        &self,
+        reqs: &vk::MemoryRequirements,
+        flags: vk::MemoryPropertyFlags,
+        linear: bool,
+    ) -> Result<Allocation> {
+        let memory_type = Self::find_memory_type_index(&self.mem_props, reqs, flags)
+            .ok_or(ThundrError::OUT_OF_MEMORY)?;
+
+        if reqs.size >= DEDICATED_THRESHOLD {
+            let (memory, mapped) = self.allocate_mapped_memory(memory_type, reqs.size)?;
+            let ret = Allocation::dedicated(memory, reqs.size, mapped);
+            self.d_allocator.lock().unwrap().add_dedicated(&ret);
+            return Ok(ret);
+        }
+
+        let mut allocator = self.d_allocator.lock().unwrap();
+        if let Some(ret) = allocator.allocate(memory_type, linear, reqs.size, reqs.alignment) {
+            return Ok(ret);
+        }
+
+        // None of our blocks had room, make a new one
+        let (memory, mapped) = self.allocate_mapped_memory(memory_type, BLOCK_SIZE)?;
+        allocator.add_block(memory_type, linear, memory, BLOCK_SIZE, mapped);
+        Ok(allocator
+            .allocate(memory_type, linear, reqs.size, reqs.alignment)
+            .expect("New memory block could not fit allocation"))
+    }
+
+    /// Allocate `size` bytes of `memory_type`, mapping it if possible
+    fn allocate_mapped_memory(
+        &self,
+        memory_type: u32,
+        size: u64,
+    ) -> Result<(vk::DeviceMemory, *mut u8)> {
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+
+        unsafe {
+            let memory = self
+                .allocate_memory(&alloc_info)
+                .or(Err(ThundrError::OUT_OF_MEMORY))?;
+
+            let flags = self.mem_props.memory_types[memory_type as usize].property_flags;
+            if !flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+                return Ok((memory, std::ptr::null_mut()));
+            }
+
+            match self
+                .dev
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+            {
+                Ok(ptr) => Ok((memory, ptr as *mut u8)),
+                Err(_) => {
+                    self.free_memory(memory);
+                    Err(ThundrError::OUT_OF_MEMORY)
+                }
+            }
+        }
+    }
+
+    /// Free memory returned by `allocate`
+    ///
+    /// The resources using this memory must have already been destroyed.
+    pub(crate) unsafe fn free_allocation(&self, alloc: &Allocation) {
+        if alloc.is_dedicated() {
+            self.d_allocator.lock().unwrap().free(alloc);
+            self.free_memory(alloc.a_memory);
+            return;
+        }
+
+        let empty_block = self.d_allocator.lock().unwrap().free(alloc);
+        if let Some(memory) = empty_block {
+            self.free_memory(memory);
+        }
+    }
+
+    /// Get statistics about how our memory is suballocated
+    ///
+    /// This can be used to tell how fragmented our memory blocks are.
+    pub fn get_allocator_stats(&self) -> AllocatorStats {
+        self.d_allocator.lock().unwrap().get_stats()
+    }
+
+    /// Get the current memory usage of this Device
+    ///
+    /// If VK_EXT_memory_budget is supported this includes the driver's
+    /// view of our usage and budget for each heap. Otherwise only the
+    /// memory allocated by Thundr is known, and the budget is the size
+    /// of the heap.
+    pub fn get_memory_usage(&self) -> MemoryUsage {
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        if self.dev_features.vkc_supports_memory_budget {
+            let mut props = vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+            unsafe {
+                self.inst
+                    .inst
+                    .get_physical_device_memory_properties2(self.pdev, &mut props);
+            }
+        }
+
+        let tracker = self.d_memory.lock().unwrap();
+        let heap_count = self.mem_props.memory_heap_count as usize;
+        MemoryUsage {
+            mu_heaps: self.mem_props.memory_heaps[..heap_count]
+                .iter()
+                .enumerate()
+                .map(|(i, heap)| {
+                    let allocated = tracker.get_allocated(i);
+                    let (usage, budget) = match self.dev_features.vkc_supports_memory_budget {
+                        true => (budget.heap_usage[i], budget.heap_budget[i]),
+                        false => (allocated, heap.size),
+                    };
+
+                    HeapUsage {
+                        hu_device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                        hu_allocated: allocated,
+                        hu_usage: usage,
+                        hu_budget: budget,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Register a callback for when this Device is running low on memory
+    ///
+    /// The callback is called once each time our usage of a heap nears
+    /// its budget, giving the application a chance to free cached images.
+    pub fn add_low_memory_callback(&self, callback: LowMemoryCallback) {
+        self.d_memory.lock().unwrap().add_callback(callback);
+    }
+
+    /// Save the pipeline cache to disk
+    ///
+    /// This is done when the Device is destroyed, but apps which may not
+    /// exit cleanly can call this once their Displays are set up.
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        self.d_pipeline_cache.save(&self.dev)
+    }
+
+    /// Compile pipelines before they are needed
+    ///
+    /// This creates the pipelines a Display rendering in each of
+    /// `color_spaces` with `sample_count` MSAA samples would use, and
+    /// the color correction pipelines if `color_lut` is set. They are
+    /// thrown away, but their compiled code is kept in the pipeline
+    /// cache. Creating a Display afterwards, or the next time the app
+    /// is run, doesn't have to wait on the shader compiler.
+    pub fn prewarm_pipelines(
+        &self,
+        color_spaces: &[ColorSpace],
+        sample_count: u32,
+        color_lut: bool,
+    ) {
+        let samples = GeomPipeline::choose_sample_count(self, sample_count);
+
+        for space in color_spaces.iter() {
+            for format in space.get_surface_formats().iter() {
+                log::debug!("Prewarming pipelines for {:?}", format);
+                unsafe {
+                    GeomPipeline::prewarm(self, *format, samples);
+                    if color_lut {
+                        LutPass::prewarm(self, *format);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check our memory usage against the budget
+    ///
+    /// This calls the low memory callbacks if we have just run low. It
+    /// is called at the start of each frame, when no locks are held.
+    pub(crate) fn check_memory_budget(&self) {
+        let usage = self.get_memory_usage();
+        let callbacks = self
+            .d_memory
+            .lock()
+            .unwrap()
+            .update_near_budget(usage.is_near_budget());
+
+        if let Some(mut callbacks) = callbacks {
+            log::error!("Running low on GPU memory: {:?}", usage);
+            for callback in callbacks.iter_mut() {
+                callback(&usage);
+            }
+            self.d_memory.lock().unwrap().restore_callbacks(callbacks);
+        }
+    }
+
     /// Allocates a buffer/memory pair of size `size`.
     ///
     /// This is just a helper for `create_buffer`. It does not fill
@@ -628,7 +1070,7 @@ impl Device {
         mode: vk::SharingMode,
         flags: vk::MemoryPropertyFlags,
         size: u64,
-    ) -> (vk::Buffer, vk::DeviceMemory) {
+    ) -> (vk::Buffer, Allocation) {
         let create_info = vk::BufferCreateInfo::builder()
             .size(size)
             .usage(usage)
@@ -637,55 +1079,38 @@ impl Device {
 
         let buffer = unsafe { self.dev.create_buffer(&create_info, None).unwrap() };
         let req = unsafe { self.dev.get_buffer_memory_requirements(buffer) };
-        // find the memory type that best suits our requirements
-        let index = Self::find_memory_type_index(&self.mem_props, &req, flags).unwrap();
 
         // now we need to allocate memory to back the buffer
-        let alloc_info = vk::MemoryAllocateInfo {
-            allocation_size: req.size,
-            memory_type_index: index,
-            ..Default::default()
+        let memory = self.allocate(&req, flags, true).unwrap();
+        unsafe {
+            self.dev
+                .bind_buffer_memory(buffer, memory.a_memory, memory.a_offset)
+                .unwrap()
         };
 
-        let memory = unsafe { self.dev.allocate_memory(&alloc_info, None).unwrap() };
-
         return (buffer, memory);
     }
 
     /// Writes `data` to `memory`
     ///
-    /// This is a helper method for mapping and updating the value stored
-    /// in device memory Memory needs to be host visible and coherent.
-    /// This does not flush after writing.
-    pub(crate) fn update_memory<T: Copy>(
-        &self,
-        memory: vk::DeviceMemory,
-        offset: isize,
-        data: &[T],
-    ) {
+    /// This is a helper method for updating the value stored in device
+    /// memory. Memory needs to be host visible and coherent, in which case
+    /// the allocation is already mapped. This does not flush after writing.
+    pub(crate) fn update_memory<T: Copy>(&self, memory: &Allocation, offset: isize, data: &[T]) {
         if data.len() == 0 {
             return;
         }
+        assert!(!memory.a_mapped.is_null());
+        assert!(offset as u64 + std::mem::size_of_val(data) as u64 <= memory.a_size);
 
         // Now we copy our data into the buffer
-        let data_size = std::mem::size_of_val(data) as u64;
         unsafe {
-            let ptr = self
-                .dev
-                .map_memory(
-                    memory,
-                    offset as u64, // offset
-                    data_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .unwrap();
+            let ptr = memory.a_mapped.offset(offset);
 
             // rust doesn't have a raw memcpy, so we need to transform the void
             // ptr to a slice. This is unsafe as the length needs to be correct
             let dst = std::slice::from_raw_parts_mut(ptr as *mut T, data.len());
             dst.copy_from_slice(data);
-
-            self.dev.unmap_memory(memory);
         }
     }
 
@@ -702,14 +1127,11 @@ impl Device {
         mode: vk::SharingMode,
         flags: vk::MemoryPropertyFlags,
         data: &[T],
-    ) -> (vk::Buffer, vk::DeviceMemory) {
+    ) -> (vk::Buffer, Allocation) {
         let size = std::mem::size_of_val(data) as u64;
         let (buffer, memory) = self.create_buffer_with_size(usage, mode, flags, size);
 
-        self.update_memory(memory, 0, data);
-
-        // Until now the buffer has not had any memory assigned
-        unsafe { self.dev.bind_buffer_memory(buffer, memory, 0).unwrap() };
+        self.update_memory(&memory, 0, data);
 
         (buffer, memory)
     }
@@ -751,13 +1173,15 @@ impl Device {
     /// queue - a queue to use instead of the default
     /// wait_stages - a list of pipeline stages to wait on
     /// wait_semas - semaphores we consume
+    ///
+    /// Returns the timeline point signaled once the cbuf completes.
     pub(crate) fn cbuf_submit_async(
         &self,
         cbuf: vk::CommandBuffer,
         queue: vk::Queue,
         wait_semas: &[vk::Semaphore],
         signal_semas: &[vk::Semaphore],
-    ) {
+    ) -> u64 {
         let mut internal = self.d_internal.write().unwrap();
 
         // Get our wait values. We need to have an entry for each sema
@@ -786,6 +1210,85 @@ impl Device {
             all_signal_semas.as_slice(),
             signal_values.as_slice(),
         );
+
+        internal.timeline_point
+    }
+
+    /// Has the GPU reached this point on our timeline
+    ///
+    /// This can be used to check if a frame has finished without
+    /// blocking. A lost device will never signal anything, so this
+    /// returns true if the device was lost.
+    pub fn is_point_signaled(&self, point: u64) -> bool {
+        let sema = self.d_internal.read().unwrap().timeline_sema;
+        match unsafe { self.dev.get_semaphore_counter_value(sema) } {
+            Ok(value) => value >= point,
+            Err(_) => true,
+        }
+    }
+
+    /// Export a sync file which signals once the work submitted to
+    /// `queue` so far has completed
+    ///
+    /// Semaphore signal operations wait for everything submitted to the
+    /// queue before them, so an empty submission made after a frame
+    /// signals once the frame is done.
+    pub(crate) fn export_sync_file(&self, queue: vk::Queue) -> Result<OwnedFd> {
+        if !self.dev_features.vkc_supports_sync_fd {
+            return Err(ThundrError::SYNC_FILE_NOT_SUPPORTED);
+        }
+
+        let mut internal = self.d_internal.write().unwrap();
+
+        // Reuse a semaphore whose last signal has completed
+        let signaled =
+            unsafe { self.dev.get_semaphore_counter_value(internal.timeline_sema) }.unwrap_or(0);
+        let sema = match internal
+            .sync_file_semas
+            .iter()
+            .position(|(point, _)| *point <= signaled)
+        {
+            Some(i) => internal.sync_file_semas.swap_remove(i).1,
+            None => {
+                let mut export_info = vk::ExportSemaphoreCreateInfo::builder()
+                    .handle_types(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+                let info = vk::SemaphoreCreateInfo::builder().push_next(&mut export_info);
+                unsafe {
+                    self.dev
+                        .create_semaphore(&info, None)
+                        .or(Err(ThundrError::INVALID))?
+                }
+            }
+        };
+
+        // Also signal our timeline so we know when the semaphore is free
+        internal.timeline_point += 1;
+        let signal_semas = [sema, internal.timeline_sema];
+        let signal_values = [0, internal.timeline_point];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfoKHR::builder()
+            .signal_semaphore_values(&signal_values)
+            .build();
+        let submit_info = &[vk::SubmitInfo::builder()
+            .signal_semaphores(&signal_semas)
+            .push_next(&mut timeline_info)
+            .build()];
+        self.check_vk_result(
+            unsafe { self.dev.queue_submit(queue, submit_info, vk::Fence::null()) },
+            "Could not submit sync file signal",
+        );
+        let point = internal.timeline_point;
+        internal.sync_file_semas.push((point, sema));
+
+        let fd_info = vk::SemaphoreGetFdInfoKHR::builder()
+            .semaphore(sema)
+            .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+        unsafe {
+            let fd = self
+                .external_sema_fd_loader
+                .get_semaphore_fd(&fd_info)
+                .or(Err(ThundrError::INVALID_FD))?;
+            Ok(OwnedFd::from_raw_fd(fd))
+        }
     }
 
     /// Common submission code
@@ -822,11 +1325,10 @@ impl Device {
             .push_next(&mut timeline_info)
             .build()];
 
-        unsafe {
-            self.dev
-                .queue_submit(queue, submit_info, vk::Fence::null())
-                .expect("Could not submit buffer to queue");
-        }
+        self.check_vk_result(
+            unsafe { self.dev.queue_submit(queue, submit_info, vk::Fence::null()) },
+            "Could not submit buffer to queue",
+        );
     }
 
     /// Records but does not submit a command buffer.
@@ -860,6 +1362,37 @@ impl Device {
         }
     }
 
+    /// Start recording a secondary cbuf
+    ///
+    /// The cbuf will be executed inside subpass 0 of `pass` drawing to
+    /// `framebuffer`, and may only record commands valid in there.
+    pub(crate) fn cbuf_begin_secondary(
+        &self,
+        cbuf: vk::CommandBuffer,
+        pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+    ) {
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(pass)
+            .subpass(0)
+            .framebuffer(framebuffer);
+        let record_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                    | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+            )
+            .inheritance_info(&inheritance_info);
+
+        unsafe {
+            self.dev
+                .reset_command_buffer(cbuf, vk::CommandBufferResetFlags::RELEASE_RESOURCES)
+                .expect("Could not reset command buffer");
+            self.dev
+                .begin_command_buffer(cbuf, &record_info)
+                .expect("Could not start command buffer");
+        }
+    }
+
     /// Records but does not submit a command buffer.
     ///
     /// cbuf - the command buffer to use
@@ -946,6 +1479,7 @@ impl Device {
     /// and perform any needed layout conversions along the way.
     ///
     /// A stride of zero implies the data is tightly packed.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn update_image_from_data(
         &self,
         image: vk::Image,
@@ -953,8 +1487,12 @@ impl Device {
         width: u32,
         height: u32,
         stride: u32,
+        format: ShmFormat,
+        mip_levels: u32,
     ) -> Result<()> {
-        self.update_image_contents_from_damaged_data(image, data, width, height, stride, None)
+        self.update_image_contents_from_damaged_data(
+            image, data, width, height, stride, format, None, mip_levels,
+        )
     }
 
     /// Copies a list of regions from a buffer into an image.
@@ -962,6 +1500,10 @@ impl Device {
     /// Instead of copying the entire buffer, use a thundr::Damage to
     /// populate only certain parts of the image. `damage` takes place
     /// in the image's coordinate system.
+    ///
+    /// If the image has more than one mip level then the damaged parts
+    /// of every level are regenerated from the new contents.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn update_image_contents_from_damaged_data(
         &self,
         image: vk::Image,
@@ -969,8 +1511,11 @@ impl Device {
         width: u32,
         height: u32,
         stride: u32,
+        format: ShmFormat,
         damage: Option<Damage>,
+        mip_levels: u32,
     ) -> Result<()> {
+        let _upload = trace::span("thundr", "upload_image");
         log::debug!("Updating image with damage: {:?}", damage);
         log::debug!("Using {}x{} buffer with stride {}", width, height, stride);
 
@@ -981,11 +1526,26 @@ impl Device {
             s => s,
         };
 
-        // Verify our size does not overflow the data
-        if stride * height > data.len() as u32 {
+        // Verify our size does not overflow the data. The last row only
+        // needs to be as long as the image is wide.
+        let texel_size = format.get_size();
+        let texels = stride as usize * (height as usize).saturating_sub(1) + width as usize;
+        if texels * texel_size > data.len() {
             return Err(ThundrError::INVALID_STRIDE);
         }
 
+        // The areas of the mip chain that need to be regenerated
+        let mip_damage = match damage.as_ref() {
+            Some(damage) => damage.d_regions.clone(),
+            None => vec![Rect::new(0, 0, width as i32, height as i32)],
+        };
+        // Mipmapped images have optimal tiling and their existing contents
+        // must be kept, so transition them from the layout they are drawn with
+        let old_layout = match mip_levels > 1 && damage.is_some() {
+            true => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            false => vk::ImageLayout::UNDEFINED,
+        };
+
         // If we have damage to use, then generate our copy regions. If not,
         // then just create
         let mut regions = Vec::new();
@@ -993,7 +1553,9 @@ impl Device {
             for d in damage.d_regions.iter() {
                 regions.push(
                     vk::BufferImageCopy::builder()
-                        .buffer_offset((stride as i32 * d.r_pos.1 + d.r_pos.0) as u64 * 4)
+                        .buffer_offset(
+                            (stride as i32 * d.r_pos.1 + d.r_pos.0) as u64 * texel_size as u64,
+                        )
                         .buffer_row_length(stride)
                         // 0 specifies that the pixels are tightly packed
                         .buffer_image_height(0)
@@ -1045,25 +1607,29 @@ impl Device {
 
         // Now copy the bits into the image
         // TODO: only upload damaged regions
-        self.upload_memimage_to_transfer(data);
+        let staging = self.upload_to_staging(data);
         self.wait_for_copy();
 
         unsafe {
             let int_lock = self.d_internal.clone();
-            let internal = int_lock.write().unwrap();
+            let mut internal = int_lock.write().unwrap();
+            let copy_cbuf = internal.copy_cbuf;
 
             // transition us into the appropriate memory layout for shaders
             self.cbuf_begin_recording(
                 internal.copy_cbuf,
                 vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
             );
+            if let Some(profiler) = internal.copy_profiler.as_mut() {
+                profiler.begin(&self.dev, copy_cbuf);
+            }
 
             // First thing to do here is to copy the transfer memory into the image
             let layout_barrier = vk::ImageMemoryBarrier::builder()
                 .image(image)
                 .src_access_mask(vk::AccessFlags::default())
                 .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .old_layout(vk::ImageLayout::UNDEFINED)
+                .old_layout(old_layout)
                 .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
                 .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
                 .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
@@ -1071,7 +1637,7 @@ impl Device {
                     vk::ImageSubresourceRange::builder()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
                         .layer_count(1)
-                        .level_count(1)
+                        .level_count(mip_levels)
                         .build(),
                 )
                 .build();
@@ -1087,7 +1653,7 @@ impl Device {
 
             self.dev.cmd_copy_buffer_to_image(
                 internal.copy_cbuf,
-                internal.transfer_buf,
+                staging.sb_buffer,
                 image,
                 // this is the layout the image is currently using
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
@@ -1095,17 +1661,32 @@ impl Device {
                 regions.as_slice(),
             );
 
+            // This leaves every level in TRANSFER_SRC_OPTIMAL
+            if mip_levels > 1 {
+                self.record_mipmap_generation(
+                    internal.copy_cbuf,
+                    image,
+                    width,
+                    height,
+                    mip_levels,
+                    mip_damage.as_slice(),
+                );
+            }
+
             let layout_barrier = vk::ImageMemoryBarrier::builder()
                 .image(image)
                 .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
                 .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .old_layout(match mip_levels > 1 {
+                    true => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    false => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                })
                 .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
                 .subresource_range(
                     vk::ImageSubresourceRange::builder()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
                         .layer_count(1)
-                        .level_count(1)
+                        .level_count(mip_levels)
                         .build(),
                 )
                 .build();
@@ -1118,15 +1699,139 @@ impl Device {
                 &[],
                 &[layout_barrier],
             );
+            if let Some(profiler) = internal.copy_profiler.as_mut() {
+                profiler.end(&self.dev, copy_cbuf);
+            }
             self.cbuf_end_recording(internal.copy_cbuf);
         }
 
         // Do this without
         self.copy_cbuf_submit_async();
+        self.release_staging_buffer(staging);
 
         Ok(())
     }
 
+    /// Record the commands to regenerate the mip chain of an image
+    ///
+    /// Each level is downscaled from the level above it with a linear blit.
+    /// Only the parts of each level covered by `damage` are regenerated.
+    /// The image must have all levels in TRANSFER_DST_OPTIMAL, and they
+    /// are all left in TRANSFER_SRC_OPTIMAL.
+    pub(crate) unsafe fn record_mipmap_generation(
+        &self,
+        cbuf: vk::CommandBuffer,
+        image: vk::Image,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        damage: &[Rect<i32>],
+    ) {
+        // Barrier to move one level from being written to being read
+        let level_barrier = |level| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(level)
+                        .level_count(1)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()
+        };
+        let subresource = |level| {
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(level)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build()
+        };
+        let corners = |r: &Rect<i32>| {
+            [
+                vk::Offset3D {
+                    x: r.r_pos.0,
+                    y: r.r_pos.1,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: r.r_pos.0 + r.r_size.0,
+                    y: r.r_pos.1 + r.r_size.1,
+                    z: 1,
+                },
+            ]
+        };
+
+        for level in 1..mip_levels {
+            self.dev.cmd_pipeline_barrier(
+                cbuf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[level_barrier(level - 1)],
+            );
+
+            let blits: Vec<vk::ImageBlit> = damage
+                .iter()
+                .map(|d| {
+                    // Read the area of the level above that shrinks into dst,
+                    // which is larger than the damage there
+                    let dst = mip_region(d, level, width, height);
+                    let src_size = (
+                        std::cmp::max(width >> (level - 1), 1) as i32,
+                        std::cmp::max(height >> (level - 1), 1) as i32,
+                    );
+                    let src_pos = (dst.r_pos.0 * 2, dst.r_pos.1 * 2);
+                    let src = Rect::new(
+                        src_pos.0,
+                        src_pos.1,
+                        std::cmp::min(dst.r_size.0 * 2, src_size.0 - src_pos.0),
+                        std::cmp::min(dst.r_size.1 * 2, src_size.1 - src_pos.1),
+                    );
+                    vk::ImageBlit::builder()
+                        .src_subresource(subresource(level - 1))
+                        .src_offsets(corners(&src))
+                        .dst_subresource(subresource(level))
+                        .dst_offsets(corners(&dst))
+                        .build()
+                })
+                .filter(|b| b.dst_offsets[1].x > b.dst_offsets[0].x)
+                .filter(|b| b.dst_offsets[1].y > b.dst_offsets[0].y)
+                .collect();
+
+            self.dev.cmd_blit_image(
+                cbuf,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                blits.as_slice(),
+                vk::Filter::LINEAR,
+            );
+        }
+
+        // The last level was only written to
+        self.dev.cmd_pipeline_barrier(
+            cbuf,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[level_barrier(mip_levels - 1)],
+        );
+    }
+
     /// Returns an index into the array of memory types for the memory
     /// properties
     ///
@@ -1270,6 +1975,57 @@ impl Device {
         self.copy_cbuf_submit_async();
     }
 
+    /// Move an image backed by host memory into the layout it is sampled in
+    ///
+    /// The image must have been created in the PREINITIALIZED layout, so
+    /// that the contents already written by the host are kept.
+    pub(crate) fn acquire_host_memory_image(&self, image: vk::Image) {
+        self.wait_for_copy();
+
+        {
+            let int_lock = self.d_internal.clone();
+            let internal = int_lock.write().unwrap();
+
+            self.cbuf_begin_recording(
+                internal.copy_cbuf,
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            );
+
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .old_layout(vk::ImageLayout::PREINITIALIZED)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_access_mask(vk::AccessFlags::HOST_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .level_count(1)
+                        .build(),
+                )
+                .build();
+
+            unsafe {
+                self.dev.cmd_pipeline_barrier(
+                    internal.copy_cbuf,
+                    vk::PipelineStageFlags::HOST,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                );
+            }
+
+            self.cbuf_end_recording(internal.copy_cbuf);
+        }
+
+        self.copy_cbuf_submit_async();
+    }
+
     /// Create a vkImage and the resources needed to use it
     ///   (vkImageView and vkDeviceMemory)
     ///
@@ -1285,6 +2041,7 @@ impl Device {
     /// Resolution should probably be the same size as the swapchain's images
     /// usage defines the role the image will serve (transfer, depth data, etc)
     /// flags defines the memory type (probably DEVICE_LOCAL + others)
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn create_image(
         &self,
         resolution: &vk::Extent2D,
@@ -1293,7 +2050,39 @@ impl Device {
         aspect: vk::ImageAspectFlags,
         flags: vk::MemoryPropertyFlags,
         tiling: vk::ImageTiling,
-    ) -> (vk::Image, vk::ImageView, vk::DeviceMemory) {
+        samples: vk::SampleCountFlags,
+    ) -> (vk::Image, vk::ImageView, Allocation) {
+        self.create_mipmapped_image(
+            resolution,
+            format,
+            vk::ComponentMapping::default(),
+            usage,
+            aspect,
+            flags,
+            tiling,
+            samples,
+            1,
+        )
+    }
+
+    /// Create a vkImage with `mip_levels` levels
+    ///
+    /// This is the same as `create_image`, and the image view covers
+    /// every mip level. The view reads the image's channels through
+    /// `components`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create_mipmapped_image(
+        &self,
+        resolution: &vk::Extent2D,
+        format: vk::Format,
+        components: vk::ComponentMapping,
+        usage: vk::ImageUsageFlags,
+        aspect: vk::ImageAspectFlags,
+        flags: vk::MemoryPropertyFlags,
+        tiling: vk::ImageTiling,
+        samples: vk::SampleCountFlags,
+        mip_levels: u32,
+    ) -> (vk::Image, vk::ImageView, Allocation) {
         // we create the image now, but will have to bind
         // some memory to it later.
         let create_info = vk::ImageCreateInfo::builder()
@@ -1304,9 +2093,9 @@ impl Device {
                 height: resolution.height,
                 depth: 1,
             })
-            .mip_levels(1)
+            .mip_levels(mip_levels)
             .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .tiling(tiling)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
@@ -1315,17 +2104,12 @@ impl Device {
         // we need to find a memory type that matches the type our
         // new image needs
         let mem_reqs = unsafe { self.dev.get_image_memory_requirements(image) };
-        let memtype_index =
-            Self::find_memory_type_index(&self.mem_props, &mem_reqs, flags).unwrap();
-
-        let alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(mem_reqs.size)
-            .memory_type_index(memtype_index);
-
-        let image_memory = unsafe { self.dev.allocate_memory(&alloc_info, None).unwrap() };
+        let image_memory = self
+            .allocate(&mem_reqs, flags, tiling == vk::ImageTiling::LINEAR)
+            .unwrap();
         unsafe {
             self.dev
-                .bind_image_memory(image, image_memory, 0)
+                .bind_image_memory(image, image_memory.a_memory, image_memory.a_offset)
                 .expect("Unable to bind device memory to image")
         };
 
@@ -1333,12 +2117,13 @@ impl Device {
             .subresource_range(
                 vk::ImageSubresourceRange::builder()
                     .aspect_mask(aspect)
-                    .level_count(1)
+                    .level_count(mip_levels)
                     .layer_count(1)
                     .build(),
             )
             .image(image)
             .format(create_info.format)
+            .components(components)
             .view_type(vk::ImageViewType::TYPE_2D);
 
         let view = unsafe { self.dev.create_image_view(&view_info, None).unwrap() };
@@ -1346,21 +2131,272 @@ impl Device {
         return (image, view, image_memory);
     }
 
+    /// Create a 3D vkImage for sampling from shaders
+    ///
+    /// This is used for color lookup tables. The image has optimal tiling
+    /// and a single mip level, and its contents are filled in with
+    /// `update_3d_image_from_data`.
+    pub(crate) fn create_3d_image(
+        &self,
+        extent: &vk::Extent3D,
+        format: vk::Format,
+    ) -> (vk::Image, vk::ImageView, Allocation) {
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_3D)
+            .format(format)
+            .extent(*extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let image = unsafe { self.dev.create_image(&create_info, None).unwrap() };
+
+        let mem_reqs = unsafe { self.dev.get_image_memory_requirements(image) };
+        let image_memory = self
+            .allocate(&mem_reqs, vk::MemoryPropertyFlags::DEVICE_LOCAL, false)
+            .unwrap();
+        unsafe {
+            self.dev
+                .bind_image_memory(image, image_memory.a_memory, image_memory.a_offset)
+                .expect("Unable to bind device memory to image")
+        };
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image(image)
+            .format(format)
+            .view_type(vk::ImageViewType::TYPE_3D);
+
+        let view = unsafe { self.dev.create_image_view(&view_info, None).unwrap() };
+
+        return (image, view, image_memory);
+    }
+
+    /// Fill a 3D image created with `create_3d_image`
+    ///
+    /// `data` must be tightly packed and cover the entire image. The
+    /// image is left in SHADER_READ_ONLY_OPTIMAL.
+    pub(crate) fn update_3d_image_from_data(
+        &self,
+        image: vk::Image,
+        extent: &vk::Extent3D,
+        data: &[u8],
+    ) {
+        let staging = self.upload_to_staging(data);
+        self.wait_for_copy();
+
+        let range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(1)
+            .build();
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            // 0 means tightly packed
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(*extent)
+            .build();
+
+        unsafe {
+            let int_lock = self.d_internal.clone();
+            let internal = int_lock.write().unwrap();
+
+            self.cbuf_begin_recording(
+                internal.copy_cbuf,
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            );
+
+            let layout_barrier = vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::default())
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(range)
+                .build();
+            self.dev.cmd_pipeline_barrier(
+                internal.copy_cbuf,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[layout_barrier],
+            );
+
+            self.dev.cmd_copy_buffer_to_image(
+                internal.copy_cbuf,
+                staging.sb_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            let layout_barrier = vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(range)
+                .build();
+            self.dev.cmd_pipeline_barrier(
+                internal.copy_cbuf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[layout_barrier],
+            );
+            self.cbuf_end_recording(internal.copy_cbuf);
+        }
+
+        self.copy_cbuf_submit_async();
+        self.release_staging_buffer(staging);
+    }
+
+    /// Create a linear image whose memory can be exported as a dmabuf
+    ///
+    /// This is used to hand copies of our composited frames to other
+    /// processes. The image can only be used as a transfer destination,
+    /// and its memory can be exported with `export_memory_as_dmabuf`.
+    pub(crate) fn create_exportable_image(
+        &self,
+        resolution: &vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<(vk::Image, vk::DeviceMemory)> {
+        let mut ext_mem_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .build();
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: resolution.width,
+                height: resolution.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::LINEAR)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .push_next(&mut ext_mem_info);
+        let image = unsafe {
+            self.dev
+                .create_image(&create_info, None)
+                .or(Err(ThundrError::COULD_NOT_CREATE_IMAGE))?
+        };
+
+        let mem_reqs = unsafe { self.dev.get_image_memory_requirements(image) };
+        let memtype_index = match Self::find_memory_type_index(
+            &self.mem_props,
+            &mem_reqs,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ) {
+            Some(index) => index,
+            None => {
+                unsafe { self.dev.destroy_image(image, None) };
+                return Err(ThundrError::OUT_OF_MEMORY);
+            }
+        };
+
+        // Exported memory needs to be a dedicated allocation so that the
+        // importer knows the layout of the image
+        let mut export_info = vk::ExportMemoryAllocateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+            .build();
+        let mut dedicated_alloc_info = vk::MemoryDedicatedAllocateInfo::builder()
+            .image(image)
+            .build();
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(mem_reqs.size)
+            .memory_type_index(memtype_index)
+            .push_next(&mut export_info)
+            .push_next(&mut dedicated_alloc_info);
+
+        unsafe {
+            let image_memory = match self.allocate_memory(&alloc_info) {
+                Ok(mem) => mem,
+                Err(_) => {
+                    self.dev.destroy_image(image, None);
+                    return Err(ThundrError::OUT_OF_MEMORY);
+                }
+            };
+            self.dev
+                .bind_image_memory(image, image_memory, 0)
+                .expect("Unable to bind device memory to image");
+
+            Ok((image, image_memory))
+        }
+    }
+
+    /// Export memory allocated by `create_exportable_image` as a dmabuf
+    ///
+    /// The returned fd holds its own reference to the memory, so it stays
+    /// valid after the memory is freed.
+    pub(crate) fn export_memory_as_dmabuf(&self, mem: vk::DeviceMemory) -> Result<OwnedFd> {
+        let fd_info = vk::MemoryGetFdInfoKHR::builder()
+            .memory(mem)
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+
+        unsafe {
+            let fd = self
+                .external_mem_fd_loader
+                .get_memory_fd(&fd_info)
+                .or(Err(ThundrError::INVALID_FD))?;
+            Ok(OwnedFd::from_raw_fd(fd))
+        }
+    }
+
     /// Schedule the item to be dropped once the specified timeline
     /// point has passed.
     ///
     /// This does not drop the item immediately, unless the timeline point
     /// is already known to be signaled.
-    pub fn schedule_drop_at_point(
-        &mut self,
-        item: Box<dyn Droppable + Send + Sync>,
-        sync_point: u64,
-    ) {
-        self.d_internal
+    pub fn schedule_drop_at_point(&self, item: Box<dyn Droppable + Send + Sync>, sync_point: u64) {
+        let signaled = self
+            .d_internal
             .write()
             .unwrap()
             .deletion_queue
             .schedule_drop_at_point(item, sync_point);
+        // Drop this after unlocking, it may need the Device
+        drop(signaled);
+    }
+
+    /// Schedule the item to be dropped once the GPU is done with the
+    /// latest submitted frame
+    ///
+    /// This is used for resources which may have been read by frames that
+    /// are still in flight.
+    pub(crate) fn schedule_drop_after_latest_frame(&self, item: Box<dyn Droppable + Send + Sync>) {
+        let sync_point = self.d_internal.read().unwrap().timeline_point;
+        self.schedule_drop_at_point(item, sync_point);
     }
 
     /// Schedule the item to be dropped once the current timeline point
@@ -1383,27 +2419,47 @@ impl Device {
             .build();
 
         // Immediately wait for our timeline point
-        unsafe {
-            self.dev
-                .wait_semaphores(&wait_info, u64::MAX)
-                .expect("Could not wait for timeline semaphore");
-        }
+        self.check_vk_result(
+            unsafe { self.dev.wait_semaphores(&wait_info, u64::MAX) },
+            "Could not wait for timeline semaphore",
+        );
+
+        let items = internal.deletion_queue.take_all_at_point(timeline_point);
+        // Items may need the Device when dropped
+        drop(internal);
+        drop(items);
+    }
 
-        internal.deletion_queue.drop_all_at_point(timeline_point);
+    /// Drop everything in the deletion queue
+    ///
+    /// The device must be idle. Deferred images hold a reference to this
+    /// Device, so this must be called before tearing down.
+    pub(crate) fn drain_deletion_queue(&self) {
+        let items = self
+            .d_internal
+            .write()
+            .unwrap()
+            .deletion_queue
+            .take_all_at_point(u64::MAX);
+        drop(items);
     }
 
     /// Allocate an image descriptor
     ///
     /// This will use our DescPool to create a new vkDescriptor corresponding
     /// to the image passed in. The image is then written to the descriptor.
-    pub fn create_new_image_descriptor(&self, view: vk::ImageView) -> Descriptor {
+    pub fn create_new_image_descriptor(&self, view: vk::ImageView, mip_levels: u32) -> Descriptor {
         let mut internal = self.d_internal.write().unwrap();
 
         let ret = internal.descpool.alloc_descriptor(&self.dev);
+        let sampler = match mip_levels > 1 {
+            true => internal.mip_sampler,
+            false => internal.image_sampler,
+        };
 
         // Now write the new bindless descriptor
         let info = [vk::DescriptorImageInfo::builder()
-            .sampler(internal.image_sampler)
+            .sampler(sampler)
             .image_view(view)
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .build()];
@@ -1433,18 +2489,42 @@ impl Drop for Device {
 
         unsafe {
             // first wait for the device to finish working
-            self.dev.device_wait_idle().unwrap();
+            self.wait_idle();
 
             internal.descpool.destroy(&self.dev);
             self.dev.destroy_sampler(internal.image_sampler, None);
+            self.dev.destroy_sampler(internal.mip_sampler, None);
 
             self.dev
                 .destroy_semaphore(internal.copy_timeline_sema, None);
             self.dev.destroy_semaphore(internal.timeline_sema, None);
-            self.dev.destroy_buffer(internal.transfer_buf, None);
-            self.free_memory(internal.transfer_mem);
+            for (_, sema) in internal.sync_file_semas.drain(..) {
+                self.dev.destroy_semaphore(sema, None);
+            }
+            if let Some(profiler) = internal.copy_profiler.take() {
+                profiler.destroy(&self.dev);
+            }
+            // Return any staging buffers still waiting in the deletion
+            // queue to the pool, then destroy them all
+            internal.deletion_queue.drop_all_at_point(u64::MAX);
+            let staging = self.d_staging.lock().unwrap().drain();
+            for buffer in staging {
+                self.destroy_staging_buffer(buffer);
+            }
+            // Everything should have been freed by now, release our blocks
+            let blocks = self.d_allocator.lock().unwrap().drain_blocks();
+            for memory in blocks {
+                self.free_memory(memory);
+            }
+
+            // Save whatever pipelines were compiled during this run
+            let _ = self.d_pipeline_cache.save(&self.dev);
+            self.d_pipeline_cache.destroy(&self.dev);
 
             self.dev.destroy_command_pool(internal.copy_cmd_pool, None);
+            for (_, pool) in self.d_thread_cmd_pools.lock().unwrap().drain() {
+                self.dev.destroy_command_pool(pool, None);
+            }
             self.dev.destroy_device(None);
         }
     }
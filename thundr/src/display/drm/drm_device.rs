@@ -57,12 +57,11 @@ impl DrmDevice {
         let path = drm::node::dev_path(dev_t.into(), drm::node::NodeType::Primary)
             .context(format!("Could not get DRM path from dev_t {}", dev_t))?;
 
-        let mut options = std::fs::OpenOptions::new();
-        options.read(true);
-        options.write(true);
-        let file = options
-            .open(&path)
-            .context(format!("Could not open DRM Device path {}", path.display()))?;
+        // Open the node through our seat so we don't need to be root
+        let file: std::fs::File =
+            crate::session::open_device(&path, nix::fcntl::OFlag::O_CLOEXEC.bits())
+                .context(format!("Could not open DRM Device path {}", path.display()))?
+                .into();
 
         let gbm = gbm::Device::new(file.as_fd().try_clone_to_owned()?)
             .context("Could not create GBM Device")?;
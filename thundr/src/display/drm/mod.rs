@@ -12,13 +12,17 @@ use drm::control::{
 };
 use drm::{control, Device as DrmDeviceTrait};
 
-use super::{DisplayInfoPayload, DisplayState, Swapchain};
+use super::{DisplayInfoPayload, DisplayState, PresentMode, PresentationTime, Swapchain};
+use crate::allocator::Allocation;
 use crate::device::Device;
 use crate::image::{Dmabuf, DmabufPlane};
 use crate::{CreateInfo, Result, ThundrError};
 use utils::log;
 
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use std::os::fd::{AsFd, AsRawFd, RawFd};
 use std::sync::Arc;
+use std::time::Duration;
 
 // Constants to use to index for the property handles. We do this
 // instead of using a string search hashmap repeatedly.
@@ -56,6 +60,10 @@ pub(crate) struct DrmSwapchainPayload {
     ds_conn: connector::Info,
     /// The index of the current mode in ds_conn
     ds_current_mode: usize,
+    /// The VRR_ENABLED property of our CRTC, if the driver has one
+    ds_vrr_prop: Option<property::Handle>,
+    /// Does the monitor support adaptive sync?
+    ds_vrr_capable: bool,
 }
 
 impl DisplayInfoPayload for DrmSwapchainPayload {
@@ -64,6 +72,19 @@ impl DisplayInfoPayload for DrmSwapchainPayload {
         1
     }
 
+    /// Connectors are the same display if the same monitor is plugged
+    /// into them. If a different monitor is plugged in its modes will
+    /// have changed.
+    fn is_same_display(&self, other: &dyn DisplayInfoPayload) -> bool {
+        match other.as_any().downcast_ref::<Self>() {
+            Some(other) => {
+                self.ds_conn.handle() == other.ds_conn.handle()
+                    && self.ds_conn.modes() == other.ds_conn.modes()
+            }
+            None => false,
+        }
+    }
+
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
@@ -86,9 +107,29 @@ pub struct DrmSwapchain {
     ds_fbs: Vec<framebuffer::Handle>,
     /// Vulkan representation of the above bos and fbs
     ds_images: Vec<vk::Image>,
-    ds_image_mems: Vec<vk::DeviceMemory>,
+    ds_image_mems: Vec<Allocation>,
     /// Have we committed yet, i.e. should we wait for flip?
     ds_committed: bool,
+    /// The frame number of our last commit
+    ds_committed_frame: u64,
+    /// Frames shown since the last call to take_presentation_times
+    ds_presentation_times: Vec<PresentationTime>,
+    /// Is the display turned on? Frames are not presented while it is off.
+    ds_powered: bool,
+    /// Should frames be flipped right away instead of at vblank?
+    ds_tearing: bool,
+    /// The present mode chosen by the user. Immediate uses async flips
+    /// even when tearing isn't allowed.
+    ds_present_mode: PresentMode,
+    /// Cleared if the driver rejects an async flip
+    ds_async_supported: bool,
+    /// Has a full commit set our mode and plane? Async flips may only
+    /// change the framebuffer, so they need this to have happened.
+    ds_mode_set: bool,
+    /// Was our last commit an async flip?
+    ds_committed_async: bool,
+    /// Should the CRTC use a variable refresh rate?
+    ds_vrr: bool,
 }
 
 impl DrmSwapchain {
@@ -98,7 +139,7 @@ impl DrmSwapchain {
                 self.ds_dev.dev.destroy_image(image, None);
             }
             for mem in self.ds_image_mems.drain(..) {
-                self.ds_dev.dev.free_memory(mem, None);
+                self.ds_dev.free_allocation(&mem);
             }
         }
 
@@ -279,9 +320,10 @@ impl DrmSwapchain {
                 .or(Err(ThundrError::NO_DISPLAY))?
                 .as_hashmap(&*drm)
                 .or(Err(ThundrError::NO_DISPLAY))?;
-            let con_props = drm
+            let con_values = drm
                 .get_properties(con.handle())
-                .or(Err(ThundrError::NO_DISPLAY))?
+                .or(Err(ThundrError::NO_DISPLAY))?;
+            let con_props = con_values
                 .as_hashmap(&*drm)
                 .or(Err(ThundrError::NO_DISPLAY))?;
             let crtc_props = drm
@@ -304,6 +346,15 @@ impl DrmSwapchain {
             props.push(plane_props["CRTC_H"].handle());
             props.push(crtc_props["MODE_ID"].handle());
 
+            // Adaptive sync needs both the driver and the monitor to support it
+            let vrr_prop = crtc_props.get("VRR_ENABLED").map(|prop| prop.handle());
+            let vrr_capable = match con_props.get("vrr_capable") {
+                Some(prop) => con_values
+                    .iter()
+                    .any(|(&id, &val)| id == prop.handle() && val == 1),
+                None => false,
+            };
+
             // Filter a list of supported modifiers
             let render_mods = dev.get_supported_drm_render_modifiers();
             let mut mods = blob::get_argb8888_modifiers(&drm, plane)?;
@@ -330,6 +381,8 @@ impl DrmSwapchain {
                 // TODO: let user choose mode
                 ds_current_mode: 0,
                 ds_crtc: crtc.clone(),
+                ds_vrr_prop: vrr_prop,
+                ds_vrr_capable: vrr_capable,
             }));
         }
 
@@ -345,6 +398,99 @@ impl DrmSwapchain {
     ///
     /// Returns INVALID_FD if no DRM node is in use. Returns NO_DISPLAY if
     /// there are no available connectors.
+    /// Wait for the flip event of our last atomic commit
+    ///
+    /// There may be multiple DrmSwapchains using us to wait for flip events. If we
+    /// are processing a particular CRTC then we will cache flip events for other
+    /// CRTCs so they can find them.
+    ///
+    /// If `block` is false this returns right away if our flip has not
+    /// happened yet.
+    fn wait_for_flip(&mut self, block: bool) -> Result<()> {
+        let dev = self.ds_dev.clone();
+        let crtc = self
+            .ds_payload
+            .as_any()
+            .downcast_ref::<DrmSwapchainPayload>()
+            .unwrap()
+            .ds_crtc
+            .handle();
+
+        loop {
+            // First check the available event list. If there is an event for our CRTC
+            // then we remove it and are good to go.
+            let mut drm_events = dev.d_drm_events.lock().unwrap();
+            if let Some(index) = drm_events.iter().position(|flip| flip.crtc == crtc) {
+                let flip = drm_events.remove(index);
+                self.record_flip(&flip);
+                return Ok(());
+            }
+
+            // If there was no pending flip, then acquire the DrmDevice and wait for
+            // new events. If our CRTC was found we are good to go, record any others
+            // in the pending events list
+            let drm = dev.d_drm_node.as_ref().unwrap().lock().unwrap();
+
+            if !block {
+                let mut fds = [PollFd::new(drm.as_fd(), PollFlags::POLLIN)];
+                if !matches!(poll(&mut fds, PollTimeout::ZERO), Ok(n) if n > 0) {
+                    return Ok(());
+                }
+            }
+
+            let events = drm.receive_events().map_err(|e| {
+                log::debug!("Failed to get DRM events: {:?}", e);
+                ThundrError::COULD_NOT_ACQUIRE_NEXT_IMAGE
+            })?;
+
+            let mut our_flip = None;
+            for ev in events {
+                if let control::Event::PageFlip(flip) = ev {
+                    // Record all events except for our CRTC
+                    match flip.crtc == crtc {
+                        true => our_flip = Some(flip),
+                        false => drm_events.push(flip),
+                    }
+                }
+            }
+
+            // We found our flip event, now we can exit
+            if let Some(flip) = our_flip {
+                self.record_flip(&flip);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Record when our last commit was shown on screen
+    fn record_flip(&mut self, flip: &control::PageFlipEvent) {
+        self.ds_committed = false;
+
+        let payload = self
+            .ds_payload
+            .as_any()
+            .downcast_ref::<DrmSwapchainPayload>()
+            .unwrap();
+        let mode = payload.ds_conn.modes()[payload.ds_current_mode];
+        // With adaptive sync there is no fixed refresh interval
+        let refresh = match mode.vrefresh() {
+            _ if self.ds_vrr && payload.ds_vrr_capable => Duration::ZERO,
+            0 => Duration::ZERO,
+            hz => Duration::from_nanos(1_000_000_000 / hz as u64),
+        };
+
+        // The flip event's timestamp is taken by the kernel at vblank
+        self.ds_presentation_times.push(PresentationTime {
+            pt_frame: self.ds_committed_frame,
+            pt_time: flip.duration,
+            pt_refresh: refresh,
+            pt_seq: flip.frame as u64,
+            pt_vsync: !self.ds_committed_async,
+            pt_hw_clock: true,
+            pt_hw_completion: true,
+        });
+    }
+
     pub fn new<'a>(info: &CreateInfo<'a>, dev: Arc<Device>) -> Result<Self> {
         Ok(Self {
             ds_dev: dev,
@@ -354,6 +500,15 @@ impl DrmSwapchain {
             ds_images: Vec::new(),
             ds_image_mems: Vec::new(),
             ds_committed: false,
+            ds_committed_frame: 0,
+            ds_presentation_times: Vec::new(),
+            ds_powered: true,
+            ds_tearing: false,
+            ds_present_mode: PresentMode::Fifo,
+            ds_async_supported: true,
+            ds_mode_set: false,
+            ds_committed_async: false,
+            ds_vrr: false,
         })
     }
 }
@@ -425,9 +580,14 @@ impl Swapchain for DrmSwapchain {
     /// the window is being resized and we have to regenerate accordingly.
     /// Keep in mind the Pipeline in Thundr will also have to be recreated
     /// separately.
-    fn recreate_swapchain(&mut self, dstate: &mut DisplayState) -> Result<()> {
+    fn recreate_swapchain(
+        &mut self,
+        dstate: &mut DisplayState,
+        _size: Option<vk::Extent2D>,
+    ) -> Result<()> {
         self.destroy_swapchain();
         self.create_swapchain(dstate)?;
+        self.ds_mode_set = false;
         Ok(())
     }
 
@@ -461,58 +621,10 @@ impl Swapchain for DrmSwapchain {
     /// before updating our current image and continuing.
     fn get_next_swapchain_image(&mut self, dstate: &mut DisplayState) -> Result<()> {
         log::debug!("get_next_swapchain_image: enter");
-        let payload = self
-            .ds_payload
-            .as_any()
-            .downcast_ref::<DrmSwapchainPayload>()
-            .unwrap();
-
         if self.ds_committed {
             // Wait for an event saying the previous atomic commit has been
             // applied
-            //
-            // There may be multiple DrmSwapchains using us to wait for flip events. If we
-            // are processing a particular CRTC then we will cache flip events for other
-            // CRTCs so they can find them.
-            loop {
-                // First check the available event list. If there is an event for our CRTC
-                // then we remove it and are good to go.
-                let mut drm_events = self.ds_dev.d_drm_events.lock().unwrap();
-                if let Some(index) = drm_events
-                    .iter()
-                    .position(|flip| flip.crtc == payload.ds_crtc.handle())
-                {
-                    drm_events.remove(index);
-                    break;
-                }
-
-                // If there was no pending flip, then acquire the DrmDevice and wait for
-                // new events. If our CRTC was found we are good to go, record any others
-                // in the pending events list
-                let drm = self.ds_dev.d_drm_node.as_ref().unwrap().lock().unwrap();
-
-                let events = drm.receive_events().map_err(|e| {
-                    log::debug!("Failed to get DRM events: {:?}", e);
-                    ThundrError::COULD_NOT_ACQUIRE_NEXT_IMAGE
-                })?;
-
-                let mut flip_event_found = false;
-                for ev in events {
-                    if let control::Event::PageFlip(flip) = ev {
-                        // Record all events except for our CRTC
-                        match flip.crtc == payload.ds_crtc.handle() {
-                            true => flip_event_found = true,
-                            false => drm_events.push(flip),
-                        }
-                    }
-                }
-
-                // We found our flip event, now we can exit
-                if flip_event_found {
-                    self.ds_committed = false;
-                    break;
-                }
-            }
+            self.wait_for_flip(true)?;
         }
         log::debug!("get_next_swapchain_image: got image");
 
@@ -531,8 +643,20 @@ impl Swapchain for DrmSwapchain {
     /// this image.
     fn present(&mut self, dstate: &DisplayState) -> Result<()> {
         log::debug!("present: enter");
+        if !self.ds_powered {
+            log::debug!("present: display is powered off, skipping");
+            return Ok(());
+        }
+        // We are not DRM master while another session is active
+        if !crate::session::is_active() {
+            log::debug!("present: session is inactive, skipping");
+            return Ok(());
+        }
         // First wait for rendering to complete
         self.ds_dev.wait_for_latest_timeline();
+        if self.ds_dev.is_lost() {
+            return Err(ThundrError::DEVICE_LOST);
+        }
         log::debug!("present: waited for rendering");
         let payload = self
             .ds_payload
@@ -543,6 +667,34 @@ impl Swapchain for DrmSwapchain {
         // Now create an atomic commit with our latest frame
         let drm = self.ds_dev.d_drm_node.as_ref().unwrap().lock().unwrap();
         let mode = payload.ds_conn.modes()[payload.ds_current_mode];
+        let fb = property::Value::Framebuffer(Some(self.ds_fbs[dstate.d_current_image as usize]));
+
+        // While tearing, flip to the new framebuffer without waiting for
+        // vblank. Drivers which can't do this get vsync instead.
+        let immediate = self.ds_tearing || self.ds_present_mode == PresentMode::Immediate;
+        if immediate && self.ds_async_supported && self.ds_mode_set {
+            let mut atomic_req = atomic::AtomicModeReq::new();
+            atomic_req.add_property(payload.ds_plane, payload.ds_props[FB_ID], fb);
+
+            match drm.atomic_commit(
+                control::AtomicCommitFlags::PAGE_FLIP_ASYNC
+                    | control::AtomicCommitFlags::NONBLOCK
+                    | control::AtomicCommitFlags::PAGE_FLIP_EVENT,
+                atomic_req,
+            ) {
+                Ok(()) => {
+                    self.ds_committed = true;
+                    self.ds_committed_frame = dstate.d_frame;
+                    self.ds_committed_async = true;
+                    log::debug!("present: done with async flip");
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::error!("Async page flips are not supported, using vsync: {:?}", e);
+                    self.ds_async_supported = false;
+                }
+            }
+        }
 
         let mut atomic_req = atomic::AtomicModeReq::new();
         atomic_req.add_property(
@@ -559,11 +711,14 @@ impl Swapchain for DrmSwapchain {
             payload.ds_props[ACTIVE],
             property::Value::Boolean(true),
         );
-        atomic_req.add_property(
-            payload.ds_plane,
-            payload.ds_props[FB_ID],
-            property::Value::Framebuffer(Some(self.ds_fbs[dstate.d_current_image as usize])),
-        );
+        if let Some(vrr_prop) = payload.ds_vrr_prop {
+            atomic_req.add_property(
+                payload.ds_crtc.handle(),
+                vrr_prop,
+                property::Value::Boolean(self.ds_vrr && payload.ds_vrr_capable),
+            );
+        }
+        atomic_req.add_property(payload.ds_plane, payload.ds_props[FB_ID], fb);
         atomic_req.add_property(
             payload.ds_plane,
             payload.ds_props[CRTC_ID],
@@ -621,8 +776,113 @@ impl Swapchain for DrmSwapchain {
             )
             .or(Err(ThundrError::PRESENT_FAILED));
         self.ds_committed = true;
+        self.ds_committed_frame = dstate.d_frame;
+        self.ds_committed_async = false;
+        self.ds_mode_set = ret.is_ok();
         log::debug!("present: done with flip");
 
         ret
     }
+
+    fn take_presentation_times(&mut self) -> Vec<PresentationTime> {
+        // Pick up the flip for our last commit without waiting, so that
+        // frames are reported without having to draw the next one
+        if self.ds_committed {
+            if let Err(e) = self.wait_for_flip(false) {
+                log::error!("Could not check for flip events: {:?}", e);
+            }
+        }
+        std::mem::take(&mut self.ds_presentation_times)
+    }
+
+    fn get_presentation_fd(&self) -> Option<RawFd> {
+        // Page flip events are read from the DRM node
+        let drm = self.ds_dev.d_drm_node.as_ref()?.lock().unwrap();
+        Some(drm.as_fd().as_raw_fd())
+    }
+
+    /// Turn the display on or off
+    ///
+    /// This is DPMS for the atomic API: the CRTC is deactivated but keeps
+    /// its mode and planes. Every present activates the CRTC, so turning
+    /// the display back on takes effect with the next frame.
+    fn set_power(&mut self, on: bool) -> Result<()> {
+        if on == self.ds_powered {
+            return Ok(());
+        }
+        self.ds_powered = on;
+        if on {
+            return Ok(());
+        }
+        self.ds_mode_set = false;
+
+        let payload = self
+            .ds_payload
+            .as_any()
+            .downcast_ref::<DrmSwapchainPayload>()
+            .unwrap();
+        let drm = self.ds_dev.d_drm_node.as_ref().unwrap().lock().unwrap();
+
+        let mut atomic_req = atomic::AtomicModeReq::new();
+        atomic_req.add_property(
+            payload.ds_crtc.handle(),
+            payload.ds_props[ACTIVE],
+            property::Value::Boolean(false),
+        );
+
+        // This is a blocking commit, so that it waits for any flip
+        // still in flight
+        drm.atomic_commit(control::AtomicCommitFlags::ALLOW_MODESET, atomic_req)
+            .map_err(|e| {
+                log::error!("Could not power off display: {:?}", e);
+                ThundrError::PRESENT_FAILED
+            })
+    }
+
+    /// Use async page flips while tearing is allowed
+    ///
+    /// This takes effect with the next frame, no new framebuffers are
+    /// needed.
+    fn set_tearing(&mut self, allowed: bool) -> bool {
+        self.ds_tearing = allowed;
+        false
+    }
+
+    /// We only have vblank synced flips and async flips
+    fn get_supported_present_modes(&self) -> Vec<PresentMode> {
+        match self.ds_async_supported {
+            true => vec![PresentMode::Fifo, PresentMode::Immediate],
+            false => vec![PresentMode::Fifo],
+        }
+    }
+
+    fn get_present_mode(&self) -> PresentMode {
+        self.ds_present_mode
+    }
+
+    /// Like tearing, this takes effect with the next frame
+    fn set_present_mode(&mut self, mode: PresentMode) -> bool {
+        self.ds_present_mode = mode;
+        false
+    }
+
+    fn supports_adaptive_sync(&self) -> bool {
+        let payload = self
+            .ds_payload
+            .as_any()
+            .downcast_ref::<DrmSwapchainPayload>()
+            .unwrap();
+        payload.ds_vrr_prop.is_some() && payload.ds_vrr_capable
+    }
+
+    /// Set VRR_ENABLED on our CRTC
+    ///
+    /// This is applied by the next full commit. Async flips can't change
+    /// it, so the next frame is presented with one.
+    fn set_adaptive_sync(&mut self, enabled: bool) {
+        if enabled != self.ds_vrr {
+            self.ds_vrr = enabled;
+            self.ds_mode_set = false;
+        }
+    }
 }
@@ -7,17 +7,21 @@ use crate::display::{DisplayState, Swapchain};
 use crate::image::ImageVk;
 use crate::pipelines::*;
 use crate::*;
+use utils::trace;
 
-/// Shader push constants
+/// Per-surface shader data
 ///
 /// These will be updated when we record the per-viewport draw commands
 /// and will contain the scrolling model transformation of all content
 /// within a viewport.
 ///
-/// This is also where we pass in the Surface's data.
+/// This is also where we pass in the Surface's data. Surfaces are drawn
+/// instanced, and the shaders read this from a storage buffer indexed by
+/// the instance. This must match the std430 layout of `SurfaceData` in
+/// the shaders.
 #[repr(C)]
 #[derive(Clone, Copy)]
-pub(crate) struct PushConstants {
+pub(crate) struct SurfaceData {
     pub width: u32,
     pub height: u32,
     /// The id of the image. This is the offset into the unbounded sampler array.
@@ -29,6 +33,34 @@ pub(crate) struct PushConstants {
     pub color: (f32, f32, f32, f32),
     /// The complete dimensions of the window.
     pub dims: Rect<i32>,
+    /// Multiplied with the alpha of the surface contents
+    pub opacity: f32,
+    /// The region of the image to sample, normalized to the image size.
+    /// These are packed as unorm16x2, with x in the low 16 bits.
+    pub src_pos: u32,
+    pub src_size: u32,
+    /// Pad the transform to the 16 byte alignment of a vec4
+    pub _pad: f32,
+    /// The surface transform's 2x2 matrix, column major
+    pub transform: [f32; 4],
+    /// The surface transform's translation
+    pub translation: (f32, f32),
+    /// 0 for no gradient, 1 for linear, and 2 for radial. If a gradient
+    /// is in use then `color` is its starting color.
+    pub gradient_type: i32,
+    /// The ColorSpace the image contents or color are encoded in
+    pub image_color_space: i32,
+    /// Gradient start and end points in surface-local pixels
+    pub gradient_start: (f32, f32),
+    pub gradient_end: (f32, f32),
+    pub gradient_end_color: (f32, f32, f32, f32),
+    /// The region of the screen this surface may draw to. This is the
+    /// intersection of the viewports and clip rects it was drawn in.
+    pub clip: Rect<i32>,
+    /// Multiplied with the color of the surface contents
+    pub tint: (f32, f32, f32),
+    /// Pad the struct to the 16 byte alignment of its vec4 members
+    pub _tint_pad: f32,
 }
 
 /// Recording parameters
@@ -38,8 +70,8 @@ pub(crate) struct PushConstants {
 /// to begin/end recording operations
 /// This is that structure.
 pub(crate) struct RecordParams<'a> {
-    /// our cached pushbuffer constants
-    pub push: PushConstants,
+    /// The data of the surface being recorded
+    pub surf: SurfaceData,
     /// From our Display's Device
     pub image_vk: ll::Snapshot<'a, Arc<ImageVk>>,
 }
@@ -48,13 +80,27 @@ impl<'a> RecordParams<'a> {
     pub fn new(dev: &'a Device) -> Self {
         Self {
             image_vk: dev.d_image_vk.snapshot(),
-            push: PushConstants {
+            surf: SurfaceData {
                 width: 0,
                 height: 0,
                 image_id: -1,
                 use_color: -1,
                 color: (0.0, 0.0, 0.0, 0.0),
                 dims: Rect::new(0, 0, 0, 0),
+                opacity: 1.0,
+                src_pos: 0,
+                src_size: u32::MAX,
+                _pad: 0.0,
+                transform: [1.0, 0.0, 0.0, 1.0],
+                translation: (0.0, 0.0),
+                gradient_type: 0,
+                image_color_space: 0,
+                gradient_start: (0.0, 0.0),
+                gradient_end: (0.0, 0.0),
+                gradient_end_color: (0.0, 0.0, 0.0, 0.0),
+                clip: Rect::new(0, 0, 0, 0),
+                tint: (1.0, 1.0, 1.0),
+                _tint_pad: 0.0,
             },
         }
     }
@@ -76,14 +122,110 @@ pub struct FrameRenderer<'a> {
     pub(crate) fr_pipe: &'a mut GeomPipeline,
     /// The current draw calls parameters
     pub(crate) fr_params: RecordParams<'a>,
+    /// The region of the current viewport on screen
+    pub(crate) fr_viewport: Rect<i32>,
+    /// The viewports saved by `push_viewport`, to be restored by
+    /// `pop_viewport`
+    pub(crate) fr_viewports: Vec<Rect<i32>>,
+    /// The scissor currently in use. None if everything is clipped.
+    pub(crate) fr_scissor: Option<Rect<i32>>,
+    /// The region of the screen being redrawn this frame. The rest of
+    /// the image already holds up to date contents.
+    pub(crate) fr_redraw_area: Rect<i32>,
+    /// How many frames ago the swapchain image was last drawn to
+    pub(crate) fr_buffer_age: u32,
+    /// The areas of the screen covered by protected content this frame
+    pub(crate) fr_protected: &'a mut Vec<Rect<i32>>,
 }
 
 impl<'a> FrameRenderer<'a> {
+    /// Get the age of the swapchain image being drawn to
+    ///
+    /// This is the number of frames since the image was last presented,
+    /// so an age of 1 means it holds the previous frame. An age of 0
+    /// means its contents are undefined and everything is redrawn.
+    pub fn get_buffer_age(&self) -> u32 {
+        self.fr_buffer_age
+    }
+
+    /// Get the region of the screen being redrawn this frame
+    ///
+    /// Anything drawn outside of this region is clipped, so callers
+    /// may skip surfaces that do not intersect it.
+    pub fn get_redraw_area(&self) -> Rect<i32> {
+        self.fr_redraw_area
+    }
+
     /// Set the viewport
     ///
-    /// This restricts the draw operations to within the specified region
+    /// This restricts the draw operations to within the specified region.
+    /// Viewports are cheap to change, each surface is clipped to the
+    /// viewport it was drawn in without splitting up the draw calls, so
+    /// this may be called any number of times per frame.
     pub fn set_viewport(&mut self, viewport: &Viewport) -> Result<()> {
-        self.fr_pipe.set_viewport(&self.fr_dstate, viewport)
+        self.fr_viewport = Rect::new(
+            viewport.offset.0,
+            viewport.offset.1,
+            viewport.size.0,
+            viewport.size.1,
+        );
+
+        self.update_scissor();
+        Ok(())
+    }
+
+    /// Draw within a viewport nested in the current one
+    ///
+    /// The new viewport is clipped to the current one, which is useful
+    /// for scrolling regions inside of other scrolling regions. Every
+    /// call to this must be matched with a call to `pop_viewport`.
+    pub fn push_viewport(&mut self, viewport: &Viewport) {
+        let nested = Rect::new(
+            viewport.offset.0,
+            viewport.offset.1,
+            viewport.size.0,
+            viewport.size.1,
+        );
+        self.fr_viewports.push(self.fr_viewport);
+        self.fr_viewport = self
+            .fr_viewport
+            .intersection(&nested)
+            .unwrap_or(Rect::new(0, 0, 0, 0));
+
+        self.update_scissor();
+    }
+
+    /// Go back to the viewport in use before the last `push_viewport`
+    pub fn pop_viewport(&mut self) {
+        if let Some(viewport) = self.fr_viewports.pop() {
+            self.fr_viewport = viewport;
+        }
+
+        self.update_scissor();
+    }
+
+    /// Recalculate the scissor
+    ///
+    /// This is the intersection of the viewport and the area being
+    /// redrawn. Surfaces are clipped to it as they are drawn.
+    fn update_scissor(&mut self) {
+        self.fr_scissor = self.fr_viewport.intersection(&self.fr_redraw_area);
+
+        if let Some(scissor) = self.fr_scissor.as_ref() {
+            self.fr_params.surf.clip = *scissor;
+        }
+    }
+
+    /// Record that protected content covers `rect`
+    ///
+    /// This ignores the redraw area, since protected content drawn in an
+    /// earlier frame is still in the swapchain image. Callers must pass
+    /// protected surfaces to `draw_surface` even if they are outside of
+    /// the redraw area so that they are hidden from captures.
+    fn add_protected_region(&mut self, rect: &Rect<i32>) {
+        if let Some(region) = self.fr_viewport.intersection(rect) {
+            self.fr_protected.push(region);
+        }
     }
 
     /// Draw a set of surfaces within a viewport
@@ -91,12 +233,54 @@ impl<'a> FrameRenderer<'a> {
     /// This is the function for recording drawing of a set of surfaces. The surfaces
     /// in the list will be rendered withing the region specified by viewport.
     pub fn draw_surface(&mut self, surface: &Surface, image: Option<&Image>) -> Result<()> {
+        if surface.s_protected || image.map(|i| i.is_protected()).unwrap_or(false) {
+            self.add_protected_region(&surface.get_bounding_box());
+        }
+
+        // Skip surfaces that are entirely clipped
+        match self.fr_scissor.as_ref() {
+            Some(scissor) => {
+                if scissor.intersection(&surface.get_bounding_box()).is_none() {
+                    return Ok(());
+                }
+            }
+            None => return Ok(()),
+        }
+
         self.fr_pipe
             .draw(&mut self.fr_params, &self.fr_dstate, surface, image);
 
         Ok(())
     }
 
+    /// Draw a triangle mesh
+    ///
+    /// The mesh is drawn over everything drawn before it, in the current
+    /// viewport. If `image` is None the mesh must have a color, otherwise
+    /// nothing is drawn.
+    pub fn draw_mesh(&mut self, mesh: &Mesh, image: Option<&Image>) -> Result<()> {
+        if let (Some(image), Some(bbox)) = (image, mesh.get_bounding_box()) {
+            if image.is_protected() {
+                self.add_protected_region(&bbox);
+            }
+        }
+
+        // Skip meshes that are empty or entirely clipped
+        match (self.fr_scissor.as_ref(), mesh.get_bounding_box()) {
+            (Some(scissor), Some(bbox)) => {
+                if scissor.intersection(&bbox).is_none() {
+                    return Ok(());
+                }
+            }
+            _ => return Ok(()),
+        }
+
+        self.fr_pipe
+            .draw_mesh(&mut self.fr_params, self.fr_dstate, mesh, image);
+
+        Ok(())
+    }
+
     /// Present the current swapchain image to the screen.
     ///
     /// Finally we can actually flip the buffers and present
@@ -104,7 +288,11 @@ impl<'a> FrameRenderer<'a> {
     ///
     /// Once this has been called this object can no longer be used
     pub fn present(&mut self) -> Result<()> {
+        let record = trace::span("thundr", "end_record");
         self.fr_pipe.end_record(&self.fr_dstate);
+        drop(record);
+
+        let _present = trace::span("thundr", "present");
         self.fr_swapchain.present(&self.fr_dstate)
     }
 }
@@ -3,7 +3,8 @@
 /// Austin Shafer - 2024
 use ash::vk;
 
-use super::{DisplayInfoPayload, DisplayState, Swapchain};
+use super::{DisplayInfoPayload, DisplayState, PresentationTime, Swapchain};
+use crate::allocator::Allocation;
 use crate::device::Device;
 use crate::{Result, ThundrError};
 
@@ -20,6 +21,11 @@ impl DisplayInfoPayload for HeadlessOutputPayload {
         usize::MAX
     }
 
+    /// There is only ever one display of this type
+    fn is_same_display(&self, other: &dyn DisplayInfoPayload) -> bool {
+        other.as_any().is::<Self>()
+    }
+
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
@@ -34,7 +40,9 @@ pub struct HeadlessSwapchain {
     /// Copy of our images that we have allocated, so we
     /// can free them
     h_images: Vec<vk::Image>,
-    h_image_mems: Vec<vk::DeviceMemory>,
+    h_image_mems: Vec<Allocation>,
+    /// Frames "presented" since the last call to take_presentation_times
+    h_presentation_times: Vec<PresentationTime>,
 }
 
 impl HeadlessSwapchain {
@@ -49,7 +57,7 @@ impl HeadlessSwapchain {
                 self.h_dev.dev.destroy_image(image, None);
             }
             for mem in self.h_image_mems.drain(..) {
-                self.h_dev.dev.free_memory(mem, None);
+                self.h_dev.free_allocation(&mem);
             }
         }
     }
@@ -74,6 +82,7 @@ impl HeadlessSwapchain {
                     | vk::MemoryPropertyFlags::HOST_COHERENT
                     | vk::MemoryPropertyFlags::HOST_VISIBLE,
                 vk::ImageTiling::LINEAR,
+                vk::SampleCountFlags::TYPE_1,
             );
 
             dstate.d_images.push(image);
@@ -93,6 +102,7 @@ impl HeadlessSwapchain {
             h_dev: dev,
             h_images: Vec::new(),
             h_image_mems: Vec::new(),
+            h_presentation_times: Vec::new(),
         })
     }
 }
@@ -156,7 +166,11 @@ impl Swapchain for HeadlessSwapchain {
     /// the window is being resized and we have to regenerate accordingly.
     /// Keep in mind the Pipeline in Thundr will also have to be recreated
     /// separately.
-    fn recreate_swapchain(&mut self, dstate: &mut DisplayState) -> Result<()> {
+    fn recreate_swapchain(
+        &mut self,
+        dstate: &mut DisplayState,
+        _size: Option<vk::Extent2D>,
+    ) -> Result<()> {
         self.destroy_swapchain();
         self.create_swapchain(dstate);
         Ok(())
@@ -191,10 +205,16 @@ impl Swapchain for HeadlessSwapchain {
     ///
     /// Finally we can actually flip the buffers and present
     /// this image.
-    fn present(&mut self, _dstate: &DisplayState) -> Result<()> {
+    fn present(&mut self, dstate: &DisplayState) -> Result<()> {
         // no-op here, nothing to present
+        self.h_presentation_times
+            .push(PresentationTime::now(dstate.d_frame, false));
         Ok(())
     }
+
+    fn take_presentation_times(&mut self) -> Vec<PresentationTime> {
+        std::mem::take(&mut self.h_presentation_times)
+    }
 }
 
 impl Drop for HeadlessSwapchain {
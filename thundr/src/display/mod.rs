@@ -7,11 +7,16 @@ extern crate ash;
 use ash::extensions::khr;
 use ash::vk;
 
+use crate::allocator::Allocation;
 use crate::device::Device;
 use crate::pipelines::*;
 use crate::*;
+use utils::{log, trace};
 
+use std::collections::VecDeque;
+use std::os::unix::io::{OwnedFd, RawFd};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub mod vkswapchain;
 use vkswapchain::VkSwapchain;
@@ -31,11 +36,157 @@ pub trait DisplayInfoPayload {
     /// Returns the number of Displays we can create for this output.
     fn max_output_count(&self) -> usize;
 
+    /// Does this describe the same physical display as `other`?
+    ///
+    /// Displays are enumerated again when one is hotplugged, and this
+    /// is used to find which ones were added or removed.
+    fn is_same_display(&self, other: &dyn DisplayInfoPayload) -> bool;
+
     /// This method uses the Any trait to allow downcasing this payload
     /// to the underlying Display output info backend.
     fn as_any(&self) -> &dyn std::any::Any;
 }
 
+/// The arrangement of the color subpixels of a display
+///
+/// This is used for subpixel text rendering, which draws text using
+/// the color channels of a pixel separately to get more resolution.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubpixelLayout {
+    /// Subpixel rendering is not used
+    None,
+    /// Horizontal red, green, blue stripes
+    Rgb,
+    /// Horizontal blue, green, red stripes
+    Bgr,
+    /// Vertical red, green, blue stripes, from top to bottom
+    VerticalRgb,
+    /// Vertical blue, green, red stripes, from top to bottom
+    VerticalBgr,
+}
+
+/// How frames are queued for the display
+///
+/// These follow the Vulkan present modes of the same name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Frames are shown in order at vblank. This never tears.
+    Fifo,
+    /// Like Fifo, but a frame which missed its vblank is shown right
+    /// away, possibly tearing.
+    FifoRelaxed,
+    /// Frames are shown at vblank, but a newer frame replaces one that
+    /// is still waiting. This never tears.
+    Mailbox,
+    /// Frames are shown as soon as they are ready. This tears, and is
+    /// mostly useful for benchmarking.
+    Immediate,
+}
+
+impl PresentMode {
+    pub(crate) fn to_vk(self) -> vk::PresentModeKHR {
+        match self {
+            Self::Fifo => vk::PresentModeKHR::FIFO,
+            Self::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            Self::Mailbox => vk::PresentModeKHR::MAILBOX,
+            Self::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+
+    /// Returns None for modes we don't expose, such as shared ones
+    pub(crate) fn from_vk(mode: vk::PresentModeKHR) -> Option<Self> {
+        match mode {
+            vk::PresentModeKHR::FIFO => Some(Self::Fifo),
+            vk::PresentModeKHR::FIFO_RELAXED => Some(Self::FifoRelaxed),
+            vk::PresentModeKHR::MAILBOX => Some(Self::Mailbox),
+            vk::PresentModeKHR::IMMEDIATE => Some(Self::Immediate),
+            _ => None,
+        }
+    }
+}
+
+/// How frames are drawn while a resize is waiting to be applied
+///
+/// The caller lays out its frames for the new size right away, but the
+/// swapchain keeps its old size until the resize is applied. Frames are
+/// fit into the old images in the meantime.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Scale the frame evenly so that all of it fits, leaving the
+    /// right or bottom edge of the image clear
+    Letterbox,
+    /// Stretch the frame to fill the image
+    Scale,
+}
+
+/// The default time a resize must go unchanged before it is applied
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// A swapchain resize that has not been applied yet
+struct PendingResize {
+    /// The size the swapchain will be recreated with
+    pr_size: vk::Extent2D,
+    /// When this resize was first requested
+    pr_first: Instant,
+    /// When the size was last changed
+    pr_last: Instant,
+}
+
+/// A linear image the frames drawn into one swapchain image are exported in
+///
+/// Slots are reused every time their swapchain image is exported, so that
+/// recording doesn't allocate an image for every frame.
+struct ExportSlot {
+    es_image: vk::Image,
+    es_mem: vk::DeviceMemory,
+    es_layout: vk::SubresourceLayout,
+    /// Records the copy into `es_image`
+    es_cbuf: vk::CommandBuffer,
+    /// The timeline point signaled once the last copy is done
+    es_point: u64,
+    /// Zeros copied over protected content by the last copy
+    es_blank: Option<(vk::Buffer, Allocation)>,
+}
+
+/// When a frame was shown on the display
+///
+/// This is used to give clients presentation feedback.
+#[derive(Debug, Copy, Clone)]
+pub struct PresentationTime {
+    /// The frame this is for, see `Display::get_frame_count`
+    pub pt_frame: u64,
+    /// The CLOCK_MONOTONIC time the frame was shown
+    pub pt_time: Duration,
+    /// The display's refresh interval, zero if unknown or variable
+    pub pt_refresh: Duration,
+    /// The display's vblank counter, zero if unknown
+    pub pt_seq: u64,
+    /// Was the frame shown in sync with the display's refresh?
+    pub pt_vsync: bool,
+    /// Does `pt_time` come from the display hardware?
+    pub pt_hw_clock: bool,
+    /// Did the display hardware tell us the frame was shown?
+    pub pt_hw_completion: bool,
+}
+
+impl PresentationTime {
+    /// Create a presentation time stamped with the current time
+    ///
+    /// This is for backends that can't tell when a frame actually
+    /// reached the screen.
+    pub(crate) fn now(frame: u64, vsync: bool) -> Self {
+        Self {
+            pt_frame: frame,
+            pt_time: Duration::from_micros(utils::timing::get_monotonic_micros()),
+            pt_refresh: Duration::ZERO,
+            pt_seq: 0,
+            pt_vsync: vsync,
+            pt_hw_clock: false,
+            pt_hw_completion: false,
+        }
+    }
+}
+
 /// Shared state that subsystems consume. We need this
 /// since Display holds rendering objects, but also has
 /// to pass down swapchain/image info so those rendering
@@ -54,8 +205,13 @@ pub struct DisplayState {
     // Vulkan surface capabilities
     pub d_surface_caps: vk::SurfaceCapabilitiesKHR,
     pub d_surface_format: vk::SurfaceFormatKHR,
+    /// The color space we are rendering in, matching d_surface_format
+    pub d_color_space: ColorSpace,
     /// index into swapchain images that we are currently using
     pub(crate) d_current_image: u32,
+    /// The number of the frame being drawn, used to match up
+    /// presentation times
+    pub(crate) d_frame: u64,
     /// Headless backend does not need a present sema
     pub(crate) d_needs_present_sema: bool,
     /// These semaphores control access to d_images and signal
@@ -94,6 +250,30 @@ pub struct Display {
     /// Application specific stuff that will be set up after
     /// the original initialization
     pub(crate) d_pipe: GeomPipeline,
+    /// The number of frames we have started drawing
+    d_frame_count: u64,
+    /// The frame each swapchain image was last drawn in. None if the
+    /// image's contents are undefined.
+    d_image_last_frame: Vec<Option<u64>>,
+    /// The screen damage of recent frames, newest first. Only as many
+    /// frames as there are swapchain images are kept.
+    d_damage_history: VecDeque<Damage>,
+    /// The subpixel layout of this display
+    d_subpixel: SubpixelLayout,
+    /// The areas of the current frame covered by protected content,
+    /// which are blanked out when it is read back
+    d_protected_regions: Vec<Rect<i32>>,
+    /// A resize requested with `recreate_swapchain`, which is applied
+    /// once the size stops changing
+    d_pending_resize: Option<PendingResize>,
+    /// How long the size must stay the same before it is applied
+    d_resize_debounce: Duration,
+    /// How frames are fit into the swapchain while a resize is pending
+    d_resize_mode: ResizeMode,
+    /// Where frames are exported to, indexed by swapchain image
+    d_export_slots: Vec<Option<ExportSlot>>,
+    /// The export command buffers are allocated from this
+    d_export_pool: vk::CommandPool,
 }
 
 /// Our Swapchain Backend
@@ -123,7 +303,22 @@ pub(crate) trait Swapchain {
     /// the window is being resized and we have to regenerate accordingly.
     /// Keep in mind the Pipeline in Thundr will also have to be recreated
     /// separately.
-    fn recreate_swapchain(&mut self, dstate: &mut DisplayState) -> Result<()>;
+    ///
+    /// `size` is the resolution to use, or None for the current size of
+    /// the surface. Backends with a fixed size ignore it.
+    fn recreate_swapchain(
+        &mut self,
+        dstate: &mut DisplayState,
+        size: Option<vk::Extent2D>,
+    ) -> Result<()>;
+
+    /// Get the current size of the surface being presented to
+    ///
+    /// This is the size the swapchain will be recreated with. Backends
+    /// with a fixed size return the current resolution.
+    fn get_surface_size(&self, dstate: &DisplayState) -> vk::Extent2D {
+        dstate.d_resolution
+    }
 
     /// Get the Dots Per Inch for this display.
     ///
@@ -144,6 +339,71 @@ pub(crate) trait Swapchain {
     /// Finally we can actually flip the buffers and present
     /// this image.
     fn present(&mut self, dstate: &DisplayState) -> Result<()>;
+
+    /// Get when frames were shown since this was last called
+    ///
+    /// Times are in the order the frames were presented.
+    fn take_presentation_times(&mut self) -> Vec<PresentationTime>;
+
+    /// Get an fd which becomes readable when a presentation time is ready
+    ///
+    /// Backends which know when frames were shown as soon as they are
+    /// presented do not have one.
+    fn get_presentation_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Get the color spaces this display can be driven in
+    ///
+    /// Backends that do not support HDR only use sRGB.
+    fn get_supported_color_spaces(&self) -> Vec<ColorSpace> {
+        vec![ColorSpace::Srgb]
+    }
+
+    /// Turn the display on or off
+    ///
+    /// Backends which cannot control the power of their display
+    /// ignore this.
+    fn set_power(&mut self, _on: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Allow frames to be shown without waiting for vblank
+    ///
+    /// Returns true if the swapchain must be recreated for this to take
+    /// effect. Backends which cannot tear ignore this.
+    fn set_tearing(&mut self, _allowed: bool) -> bool {
+        false
+    }
+
+    /// Can the refresh rate follow the rate frames are presented at?
+    fn supports_adaptive_sync(&self) -> bool {
+        false
+    }
+
+    /// Turn variable refresh rate on or off
+    ///
+    /// Backends which don't support adaptive sync ignore this.
+    fn set_adaptive_sync(&mut self, _enabled: bool) {}
+
+    /// Get the present modes this backend can use
+    fn get_supported_present_modes(&self) -> Vec<PresentMode> {
+        vec![PresentMode::Fifo]
+    }
+
+    /// Get the present mode selected with `set_present_mode`
+    fn get_present_mode(&self) -> PresentMode {
+        PresentMode::Fifo
+    }
+
+    /// Select how frames are presented
+    ///
+    /// `mode` is one of `get_supported_present_modes`. Tearing overrides
+    /// this while it is allowed. Returns true if the swapchain must be
+    /// recreated for this to take effect.
+    fn set_present_mode(&mut self, _mode: PresentMode) -> bool {
+        false
+    }
 }
 
 impl Display {
@@ -153,6 +413,8 @@ impl Display {
         match &info.surface_type {
             #[cfg(feature = "sdl")]
             SurfaceType::SDL2 => Ok(Box::new(VkSwapchain::new(info, dev.clone())?)),
+            #[cfg(feature = "winit")]
+            SurfaceType::Winit(_) => Ok(Box::new(VkSwapchain::new(info, dev.clone())?)),
             SurfaceType::Display => Ok(Box::new(VkSwapchain::new(info, dev.clone())?)),
             SurfaceType::Headless => Ok(Box::new(HeadlessSwapchain::new(dev.clone())?)),
             #[cfg(feature = "drm")]
@@ -176,12 +438,15 @@ impl Display {
             let dstate = DisplayState {
                 d_surface_caps: surface_caps,
                 d_surface_format: surface_format,
+                d_color_space: ColorSpace::from_surface_format(&surface_format)
+                    .unwrap_or(ColorSpace::Srgb),
                 d_resolution: vk::Extent2D {
                     width: 0,
                     height: 0,
                 },
                 d_views: Vec::with_capacity(0),
                 d_current_image: 0,
+                d_frame: 0,
                 d_needs_present_sema: match info.surface_type {
                     SurfaceType::Headless => false,
                     #[cfg(feature = "drm")]
@@ -196,7 +461,9 @@ impl Display {
                 d_images: Vec::with_capacity(0),
             };
 
-            let pipe = GeomPipeline::new(dev.clone(), &dstate)?;
+            let pipe =
+                GeomPipeline::new(dev.clone(), &dstate, info.sample_count, info.record_threads)?;
+            let export_pool = dev.create_command_pool(queue_family);
 
             let mut ret = Self {
                 d_dev: dev,
@@ -204,7 +471,18 @@ impl Display {
                 d_swapchain: swapchain,
                 d_state: dstate,
                 d_pipe: pipe,
+                d_frame_count: 0,
+                d_image_last_frame: Vec::new(),
+                d_damage_history: VecDeque::new(),
+                d_subpixel: SubpixelLayout::None,
+                d_protected_regions: Vec::new(),
+                d_pending_resize: None,
+                d_resize_debounce: RESIZE_DEBOUNCE,
+                d_resize_mode: ResizeMode::Scale,
+                d_export_slots: Vec::new(),
+                d_export_pool: export_pool,
             };
+            ret.set_subpixel_layout(info.subpixel_layout);
 
             // Add a dummy image to the pipeline
             let pixels: Vec<u8> = std::iter::repeat(0).take(4 * 4 * 4).collect();
@@ -215,6 +493,7 @@ impl Display {
                     4, // width of texture
                     4, // height of texture
                     4, // stride
+                    ShmFormat::ARGB8888,
                     None,
                 )
                 .unwrap();
@@ -223,6 +502,12 @@ impl Display {
             // Trigger the creation of our swapchain images and pipeline framebuffers
             ret.handle_ood()?;
 
+            if std::env::var("THUNDR_GPU_PROFILE").is_ok() {
+                if let Err(e) = ret.set_gpu_profiling(true) {
+                    log::error!("Could not enable GPU profiling: {}", e);
+                }
+            }
+
             Ok(ret)
         }
     }
@@ -230,7 +515,7 @@ impl Display {
     /// Destroy the swapchain bits in dstate
     fn destroy_swapchain_resources(&mut self) {
         unsafe {
-            self.d_dev.dev.device_wait_idle().unwrap();
+            self.d_dev.wait_idle();
 
             // Don't destroy the images here, the destroy swapchain call
             // will take care of them
@@ -247,19 +532,42 @@ impl Display {
             for sema in self.d_state.d_available_present_semas.drain(..) {
                 self.d_dev.dev.destroy_semaphore(sema, None);
             }
+
+            // These are the size of the old swapchain
+            for slot in std::mem::take(&mut self.d_export_slots)
+                .into_iter()
+                .flatten()
+            {
+                self.destroy_export_slot(slot);
+            }
         }
     }
 
-    /// Recreate our swapchain.
+    /// Free an ExportSlot
     ///
-    /// This will be done on VK_ERROR_OUT_OF_DATE_KHR, signifying that
-    /// the window is being resized and we have to regenerate accordingly.
-    /// Keep in mind the Pipeline in Thundr will also have to be recreated
-    /// separately.
-    pub fn recreate_swapchain(&mut self) -> Result<()> {
+    /// The GPU must be done with it.
+    unsafe fn destroy_export_slot(&self, slot: ExportSlot) {
+        if let Some((buffer, memory)) = slot.es_blank {
+            self.d_dev.dev.destroy_buffer(buffer, None);
+            self.d_dev.free_allocation(&memory);
+        }
+        self.d_dev
+            .dev
+            .free_command_buffers(self.d_export_pool, &[slot.es_cbuf]);
+        self.d_dev.dev.destroy_image(slot.es_image, None);
+        self.d_dev.free_memory(slot.es_mem);
+    }
+
+    /// Recreate our swapchain with the resolution `size`
+    ///
+    /// None uses the current size of the surface. Keep in mind the
+    /// Pipeline in Thundr will also have to be recreated separately.
+    fn rebuild_swapchain(&mut self, size: Option<vk::Extent2D>) -> Result<()> {
         self.destroy_swapchain_resources();
+        self.d_pending_resize = None;
 
-        self.d_swapchain.recreate_swapchain(&mut self.d_state)?;
+        self.d_swapchain
+            .recreate_swapchain(&mut self.d_state, size)?;
 
         // Populate the present semas for these images
         let sema_create_info = vk::SemaphoreCreateInfo::default();
@@ -281,9 +589,38 @@ impl Display {
             self.d_state.d_present_semas.push(None);
         }
 
+        // None of the new images have been drawn to
+        self.invalidate_images();
+
         Ok(())
     }
 
+    /// Mark the contents of every swapchain image as undefined
+    ///
+    /// This forces each image to be completely redrawn the next time
+    /// it is used.
+    fn invalidate_images(&mut self) {
+        self.d_image_last_frame = self.d_state.d_images.iter().map(|_| None).collect();
+        self.d_damage_history.clear();
+    }
+
+    /// Get the damage of the last `age` frames
+    ///
+    /// This is the region that has changed on a swapchain image that
+    /// was last drawn `age` frames ago. Returns None if the image's
+    /// contents are unknown and it needs to be completely redrawn.
+    fn damage_since(&self, age: u32) -> Option<Damage> {
+        if age == 0 || age as usize > self.d_damage_history.len() {
+            return None;
+        }
+
+        let mut ret = Damage::empty();
+        for damage in self.d_damage_history.iter().take(age as usize) {
+            ret.union(damage);
+        }
+        Some(ret)
+    }
+
     /// This is a candidate for an out of date error. We should
     /// let the application know about this so it can recalculate anything
     /// that depends on the window size, so we exit returning OOD.
@@ -291,12 +628,256 @@ impl Display {
     /// We have to destroy and recreate our pipeline along the way since
     /// it depends on the swapchain.
     pub fn handle_ood(&mut self) -> Result<()> {
-        self.recreate_swapchain()?;
+        self.rebuild_swapchain(None)?;
         self.d_pipe.handle_ood(&mut self.d_state);
 
         Ok(())
     }
 
+    /// Resize the swapchain to `size`
+    ///
+    /// None resizes to the current size of the window. Windows being
+    /// dragged are resized many times a second, and recreating the
+    /// swapchain for each is slow, so the resize is only applied once the
+    /// size has stopped changing for the debounce interval. Until then
+    /// frames are still drawn into the old swapchain, fit to it as
+    /// described by `set_resize_mode`, and `get_resolution` returns the
+    /// new size so that they can be laid out for it.
+    ///
+    /// If the old swapchain can no longer be presented to the resize is
+    /// applied right away. Sizes with a zero width or height, such as a
+    /// minimized window, are ignored.
+    pub fn recreate_swapchain(&mut self, size: Option<(u32, u32)>) {
+        let size = match size {
+            Some((width, height)) => vk::Extent2D { width, height },
+            None => self.d_swapchain.get_surface_size(&self.d_state),
+        };
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        // Going back to the current size means the old swapchain
+        // is fine after all
+        if size == self.d_state.d_resolution {
+            self.d_pending_resize = None;
+            return;
+        }
+
+        let now = Instant::now();
+        match self.d_pending_resize.as_mut() {
+            Some(pending) => {
+                if pending.pr_size != size {
+                    pending.pr_size = size;
+                    pending.pr_last = now;
+                }
+            }
+            None => {
+                self.d_pending_resize = Some(PendingResize {
+                    pr_size: size,
+                    pr_first: now,
+                    pr_last: now,
+                })
+            }
+        }
+    }
+
+    /// Is there a resize waiting to be applied?
+    ///
+    /// Resizes are applied when a frame is started, so the caller should
+    /// keep drawing frames until this is false.
+    pub fn is_resize_pending(&self) -> bool {
+        self.d_pending_resize.is_some()
+    }
+
+    /// Set how long the size must go unchanged before a resize is applied
+    ///
+    /// A resize that keeps changing is still applied once it has been
+    /// pending for four times this long, so that the window doesn't stay
+    /// at the old size for an entire drag. The default is 50ms.
+    pub fn set_resize_debounce(&mut self, debounce: Duration) {
+        self.d_resize_debounce = debounce;
+    }
+
+    /// Set how frames are drawn while a resize is pending
+    ///
+    /// The default is `ResizeMode::Scale`.
+    pub fn set_resize_mode(&mut self, mode: ResizeMode) {
+        self.d_resize_mode = mode;
+    }
+
+    /// Apply the pending resize if it has waited long enough
+    ///
+    /// If `force` is set it is applied no matter how recent it is.
+    fn apply_pending_resize(&mut self, force: bool) -> Result<()> {
+        let size = match self.d_pending_resize.as_ref() {
+            Some(pending) => {
+                let ready = pending.pr_last.elapsed() >= self.d_resize_debounce
+                    || pending.pr_first.elapsed() >= self.d_resize_debounce * 4;
+                if !ready && !force {
+                    return Ok(());
+                }
+                pending.pr_size
+            }
+            None => return Ok(()),
+        };
+
+        self.rebuild_swapchain(Some(size))?;
+        self.d_pipe.handle_ood(&mut self.d_state);
+        Ok(())
+    }
+
+    /// Get the size frames are drawn at in the caller's coordinates
+    ///
+    /// This is the resolution of the swapchain, unless a resize is
+    /// pending. Then it is scaled so that the frame fits the old images.
+    fn get_draw_size(&self) -> (u32, u32) {
+        let image = self.d_state.d_resolution;
+        let size = match self.d_pending_resize.as_ref() {
+            Some(pending) => pending.pr_size,
+            None => return (image.width, image.height),
+        };
+
+        match self.d_resize_mode {
+            ResizeMode::Scale => (size.width, size.height),
+            ResizeMode::Letterbox => {
+                // Use the same scale on both axes
+                let scale = (image.width as f32 / size.width as f32)
+                    .min(image.height as f32 / size.height as f32);
+                (
+                    (image.width as f32 / scale).round() as u32,
+                    (image.height as f32 / scale).round() as u32,
+                )
+            }
+        }
+    }
+
+    /// Get the color space this Display renders in
+    ///
+    /// This is the color space requested in CreateInfo if the display
+    /// supports it, and sRGB otherwise.
+    pub fn get_color_space(&self) -> ColorSpace {
+        self.d_state.d_color_space
+    }
+
+    /// Get the color spaces supported by this Display
+    ///
+    /// This lists the color capabilities of the output, for use
+    /// in choosing a color space to request in CreateInfo.
+    pub fn get_supported_color_spaces(&self) -> Vec<ColorSpace> {
+        self.d_swapchain.get_supported_color_spaces()
+    }
+
+    /// Apply a color correction table to everything drawn on this Display
+    ///
+    /// The table is applied as the last step of drawing each frame, and
+    /// is used to correct the colors of calibrated monitors. Passing None
+    /// turns off color correction.
+    pub fn set_color_lut(&mut self, lut: Option<&ColorLut>) -> Result<()> {
+        self.d_pipe.set_color_lut(&self.d_state, lut)?;
+        // Everything on screen changes color
+        self.invalidate_images();
+        Ok(())
+    }
+
+    /// Get the number of samples per pixel used for drawing
+    ///
+    /// This may be lower than the count requested in CreateInfo if the
+    /// device does not support it.
+    pub fn get_sample_count(&self) -> u32 {
+        self.d_pipe.get_sample_count()
+    }
+
+    /// Set the subpixel layout of this display
+    ///
+    /// Surfaces marked with `Surface::set_subpixel` are drawn using this.
+    /// If the device does not support dual source blending then subpixel
+    /// rendering is not available, and the layout stays None.
+    pub fn set_subpixel_layout(&mut self, layout: SubpixelLayout) {
+        let layout = match self.d_pipe.supports_subpixel() {
+            true => layout,
+            false => SubpixelLayout::None,
+        };
+
+        if layout != self.d_subpixel {
+            self.d_subpixel = layout;
+            // Text everywhere will be drawn differently
+            self.invalidate_images();
+        }
+    }
+
+    /// Get the subpixel layout of this display
+    ///
+    /// Text should be rasterized for this layout. This is None if
+    /// subpixel rendering is not in use.
+    pub fn get_subpixel_layout(&self) -> SubpixelLayout {
+        self.d_subpixel
+    }
+
+    /// Turn the physical display on or off
+    ///
+    /// Frames are not shown while the display is off. This only has an
+    /// effect on the DRM backend.
+    pub fn set_power(&mut self, on: bool) -> Result<()> {
+        self.d_swapchain.set_power(on)
+    }
+
+    /// Allow this display to tear
+    ///
+    /// While enabled, frames are shown as soon as they are drawn instead
+    /// of waiting for vblank. This uses IMMEDIATE presentation or async
+    /// DRM page flips, and lowers latency for fullscreen games. Displays
+    /// which can't tear keep presenting with vsync.
+    pub fn set_tearing(&mut self, allowed: bool) -> Result<()> {
+        if self.d_swapchain.set_tearing(allowed) {
+            self.handle_ood()?;
+        }
+        Ok(())
+    }
+
+    /// Get the present modes this display supports
+    pub fn get_supported_present_modes(&self) -> Vec<PresentMode> {
+        self.d_swapchain.get_supported_present_modes()
+    }
+
+    /// Get the present mode in use when not tearing
+    pub fn get_present_mode(&self) -> PresentMode {
+        self.d_swapchain.get_present_mode()
+    }
+
+    /// Select how frames are presented
+    ///
+    /// By default displays use Mailbox if it is available and Fifo if
+    /// not. The swapchain is recreated if needed. Returns INVALID if
+    /// `mode` is not in `get_supported_present_modes`.
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> Result<()> {
+        if !self.get_supported_present_modes().contains(&mode) {
+            return Err(ThundrError::INVALID);
+        }
+
+        if self.d_swapchain.set_present_mode(mode) {
+            self.handle_ood()?;
+        }
+        Ok(())
+    }
+
+    /// Does this display support variable refresh rates?
+    ///
+    /// This is true for DRM connectors driving a monitor with
+    /// FreeSync or G-Sync compatible adaptive sync.
+    pub fn supports_adaptive_sync(&self) -> bool {
+        self.d_swapchain.supports_adaptive_sync()
+    }
+
+    /// Turn adaptive sync on or off
+    ///
+    /// While enabled the display refreshes when a frame is presented
+    /// instead of at a fixed interval, within the range the monitor
+    /// supports. This takes effect with the next frame. Displays which
+    /// don't support adaptive sync ignore this.
+    pub fn set_adaptive_sync(&mut self, enabled: bool) {
+        self.d_swapchain.set_adaptive_sync(enabled)
+    }
+
     /// Get the DRM device major/minor in use by this Display's Device
     pub fn get_drm_dev(&self) -> Option<(i64, i64)> {
         self.d_dev.get_drm_dev()
@@ -312,12 +893,17 @@ impl Display {
 
     /// Get the resolution of this display
     ///
-    /// This returns the extent as used by Vulkan
+    /// This returns the extent as used by Vulkan. While a resize is
+    /// pending this is the size it was resized to, which frames should
+    /// be laid out for.
     pub fn get_resolution(&self) -> (u32, u32) {
-        (
-            self.d_state.d_resolution.width,
-            self.d_state.d_resolution.height,
-        )
+        match self.d_pending_resize.as_ref() {
+            Some(pending) => (pending.pr_size.width, pending.pr_size.height),
+            None => (
+                self.d_state.d_resolution.width,
+                self.d_state.d_resolution.height,
+            ),
+        }
     }
 
     /// Get a list of any extension names needed by the Vulkan
@@ -339,6 +925,17 @@ impl Display {
                     khr::XcbSurface::name().as_ptr(),
                 ]
             }
+            // Let ash-window tell us which surface extensions this
+            // window system needs
+            #[cfg(feature = "winit")]
+            SurfaceType::Winit(display) => match ash_window::enumerate_required_extensions(display)
+            {
+                Ok(names) => names.to_vec(),
+                Err(e) => {
+                    log::error!("Unsupported window system for winit: {:?}", e);
+                    Vec::with_capacity(0)
+                }
+            },
         }
     }
 
@@ -352,21 +949,133 @@ impl Display {
         self.d_swapchain.get_next_swapchain_image(&mut self.d_state)
     }
 
+    /// Get the number of the last frame drawn
+    pub fn get_frame_count(&self) -> u64 {
+        self.d_frame_count
+    }
+
+    /// Get the point on our Device's timeline for the last frame drawn
+    ///
+    /// The frame has finished rendering once `Device::is_point_signaled`
+    /// returns true for this point. This lets the caller wait for frames
+    /// without blocking, such as to release client buffers.
+    pub fn get_last_frame_point(&self) -> u64 {
+        self.d_pipe.g_last_frame_point
+    }
+
+    /// Export a sync file for the last frame drawn
+    ///
+    /// The sync file signals once the frame has finished rendering. It can
+    /// be handed to clients using explicit synchronization, or polled for
+    /// readability. This should be called after the frame is presented.
+    pub fn export_frame_sync_file(&self) -> Result<OwnedFd> {
+        self.d_dev.export_sync_file(self.d_state.d_present_queue)
+    }
+
+    /// Get when recently presented frames were shown on the display
+    ///
+    /// Each frame is reported once. Depending on the backend this may
+    /// only be known some time after the frame was presented, so this
+    /// should be checked until all expected frames have been reported.
+    pub fn take_presentation_times(&mut self) -> Vec<PresentationTime> {
+        self.d_swapchain.take_presentation_times()
+    }
+
+    /// Get an fd to wait on for presentation times
+    ///
+    /// This becomes readable when `take_presentation_times` has new times
+    /// to report. It is None if times are always known once a frame has
+    /// been presented. The fd belongs to the Display and is only valid
+    /// while it exists.
+    pub fn get_presentation_fd(&self) -> Option<RawFd> {
+        self.d_swapchain.get_presentation_fd()
+    }
+
+    /// Turn GPU profiling on or off
+    ///
+    /// While profiling, timestamps are written around uploads, drawing,
+    /// and color correction so that the GPU time spent on each can be
+    /// read with `take_gpu_timings`. This can also be turned on by
+    /// setting THUNDR_GPU_PROFILE.
+    pub fn set_gpu_profiling(&mut self, enabled: bool) -> Result<()> {
+        if !self.d_pipe.set_gpu_profiling(&self.d_state, enabled) {
+            return Err(ThundrError::TIMESTAMPS_NOT_SUPPORTED);
+        }
+        // Frames can still be profiled if the copy queue lacks timestamps
+        if !self.d_dev.set_upload_profiling(enabled) {
+            log::error!("The copy queue does not support timestamps, uploads will not be timed");
+        }
+        Ok(())
+    }
+
+    /// Get the GPU timings of recently drawn frames
+    ///
+    /// Each frame is reported once, after the GPU has finished drawing
+    /// it. This is empty if GPU profiling is turned off.
+    pub fn take_gpu_timings(&mut self) -> Vec<GpuFrameTimings> {
+        self.d_pipe.take_gpu_timings()
+    }
+
     /// Begin recording a frame
     ///
     /// This is first called when trying to draw a frame. It will set
     /// up the command buffers and resources that Thundr will use while
-    /// recording draw commands.
+    /// recording draw commands. The entire frame will be redrawn.
     pub fn acquire_next_frame<'a>(&'a mut self) -> Result<FrameRenderer<'a>> {
+        self.begin_frame(None)
+    }
+
+    /// Begin recording a frame, only redrawing what has changed
+    ///
+    /// `damage` is the region of the screen in which the contents have
+    /// changed since the last frame. Swapchain images may be several
+    /// frames old, so all damage since the image was last drawn to is
+    /// redrawn. Drawing outside of that region is clipped.
+    pub fn acquire_next_frame_with_damage<'a>(
+        &'a mut self,
+        damage: &Damage,
+    ) -> Result<FrameRenderer<'a>> {
+        self.begin_frame(Some(damage))
+    }
+
+    /// Get the next image and start recording
+    ///
+    /// If `damage` is None the entire frame is redrawn.
+    fn begin_frame<'a>(&'a mut self, damage: Option<&Damage>) -> Result<FrameRenderer<'a>> {
+        // Nothing can be drawn with a lost device, the app needs to
+        // recreate its Thundr resources
+        if self.d_dev.is_lost() {
+            return Err(ThundrError::DEVICE_LOST);
+        }
         // Before waiting for the latest frame, free the previous
         // frame's release data
         self.d_dev.flush_deletion_queue();
+        // Let the application know if it needs to free some images
+        self.d_dev.check_memory_budget();
+
+        // Resize once the size has settled
+        self.apply_pending_resize(false)?;
 
         // Get our next swapchain image
+        let acquire = trace::span("thundr", "acquire_image");
         match self.get_next_swapchain_image() {
             Ok(()) => (),
+            // The old swapchain can't be used any more. If the caller
+            // already knows about the resize we can apply it now and
+            // carry on, otherwise they need to lay out for the new size
+            Err(ThundrError::OUT_OF_DATE) if self.d_pending_resize.is_some() => {
+                self.apply_pending_resize(true)?;
+                self.get_next_swapchain_image()?;
+            }
             Err(ThundrError::OUT_OF_DATE) => {
-                return Err(ThundrError::OUT_OF_DATE);
+                self.recreate_swapchain(None);
+                if self.d_pending_resize.is_some() {
+                    return Err(ThundrError::OUT_OF_DATE);
+                }
+                // The size didn't change, the swapchain just needs
+                // to be replaced
+                self.handle_ood()?;
+                self.get_next_swapchain_image()?;
             }
             Err(e) => return Err(e),
         };
@@ -378,50 +1087,123 @@ impl Display {
         // one frame at a time. With this we get one frame (16ms) latency.
         //
         // TODO: pace our frames better to reduce latency futher?
+        drop(acquire);
+        let wait = trace::span("thundr", "wait_for_previous_frame");
         self.d_dev.wait_for_latest_timeline();
+        drop(wait);
+        if self.d_dev.is_lost() {
+            return Err(ThundrError::DEVICE_LOST);
+        }
 
         // Now construct our FrameRenderer
         // This allows the caller to have
         let res = self.get_resolution();
+        let draw_size = self.get_draw_size();
         let mut params = RecordParams::new(&self.d_dev);
-        params.push.width = res.0;
-        params.push.height = res.1;
+        params.surf.width = draw_size.0;
+        params.surf.height = draw_size.1;
+
+        // Find how many frames old the contents of this image are, and
+        // record that it is now holding this frame
+        self.d_frame_count += 1;
+        self.d_state.d_frame = self.d_frame_count;
+        let current = self.d_state.d_current_image as usize;
+        let buffer_age = match self.d_image_last_frame[current] {
+            Some(last) => (self.d_frame_count - last) as u32,
+            None => 0,
+        };
+        self.d_image_last_frame[current] = Some(self.d_frame_count);
+
+        // Add this frame's damage to our history, and then take everything
+        // that has changed since this image was last drawn to
+        let full = Rect::new(0, 0, res.0 as i32, res.1 as i32);
+        self.d_damage_history.push_front(match damage {
+            Some(damage) => damage.clone(),
+            None => Damage::new(vec![full]),
+        });
+        self.d_damage_history.truncate(self.d_state.d_images.len());
+
+        // Vulkan needs a non-empty render area, so if nothing changed
+        // we redraw a single pixel
+        let redraw_area = match self.damage_since(buffer_age) {
+            Some(damage) => damage
+                .bounding_box()
+                .and_then(|bbox| bbox.intersection(&full))
+                .unwrap_or(Rect::new(0, 0, 1, 1)),
+            None => full,
+        };
+        // Damage doesn't line up with the images while they are scaled
+        let partial = redraw_area != full
+            && self.d_pending_resize.is_none()
+            && self.d_pipe.can_redraw_partially();
+        let redraw_area = match partial {
+            true => redraw_area,
+            false => full,
+        };
+
+        // Protected surfaces will add themselves as they are drawn
+        self.d_protected_regions.clear();
 
         // Kick off our new frame
-        self.d_pipe.begin_record(&self.d_state);
+        self.d_pipe.begin_record(
+            &self.d_state,
+            match partial {
+                true => Some(&redraw_area),
+                false => None,
+            },
+        );
 
+        params.surf.clip = redraw_area;
         let frame = FrameRenderer {
             fr_swapchain: &mut self.d_swapchain,
             fr_dstate: &self.d_state,
             fr_pipe: &mut self.d_pipe,
             fr_params: params,
+            fr_viewport: full,
+            fr_viewports: Vec::new(),
+            fr_scissor: Some(redraw_area),
+            fr_redraw_area: redraw_area,
+            fr_buffer_age: buffer_age,
+            fr_protected: &mut self.d_protected_regions,
         };
 
         Ok(frame)
     }
 
-    /// Get the content of the current swapchain image
+    /// Create a buffer of zeros large enough to cover any protected region
     ///
-    /// Keep in mind that this will be very expensive and synchronized. It
-    /// also should be done before the next image is acquired.
-    #[allow(dead_code)]
-    pub fn dump_framebuffer(&mut self, filename: &str) -> MappedImage {
-        // alloc a temp image
-        let (image, view, mem) = self.d_dev.create_image(
-            &self.d_state.d_resolution,
-            vk::Format::B8G8R8A8_UNORM,
-            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
-            vk::ImageAspectFlags::COLOR,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL
-                | vk::MemoryPropertyFlags::HOST_COHERENT
-                | vk::MemoryPropertyFlags::HOST_VISIBLE,
-            vk::ImageTiling::LINEAR,
+    /// Returns None if nothing protected was drawn in the current frame.
+    fn create_protected_blank(&self) -> Option<(vk::Buffer, Allocation)> {
+        let texel_size = match self.d_state.d_surface_format.format {
+            vk::Format::R16G16B16A16_SFLOAT => 8,
+            _ => 4,
+        };
+        let size = self
+            .d_protected_regions
+            .iter()
+            .map(|rect| rect.r_size.0 as u64 * rect.r_size.1 as u64 * texel_size)
+            .max()?;
+
+        let (buffer, memory) = self.d_dev.create_buffer_with_size(
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            size,
         );
+        self.d_dev
+            .update_memory(&memory, 0, vec![0u8; size as usize].as_slice());
 
-        let present_layout = match self.d_state.d_needs_present_sema {
-            true => vk::ImageLayout::PRESENT_SRC_KHR,
-            false => vk::ImageLayout::GENERAL,
-        };
+        Some((buffer, memory))
+    }
+
+    /// Copy the current swapchain image into `image`
+    ///
+    /// `image` must be the size of our swapchain images and support being
+    /// a transfer destination. It is left in `final_layout`. This waits
+    /// for the copy to complete. Any protected content in the frame is
+    /// replaced with black in the copy.
+    fn copy_current_image(&mut self, image: vk::Image, final_layout: vk::ImageLayout) {
+        let blank = self.create_protected_blank();
 
         // Wait for both the latest frame and for the copy cbuf
         self.d_dev.wait_for_latest_timeline();
@@ -435,110 +1217,208 @@ impl Display {
                 internal.copy_cbuf,
                 vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
             );
+            self.record_copy_current_image(internal.copy_cbuf, image, final_layout, &blank);
+            self.d_dev.cbuf_end_recording(internal.copy_cbuf);
+        }
 
-            let range = vk::ImageSubresourceRange::builder()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .layer_count(1)
-                .level_count(1)
-                .build();
+        self.d_dev.copy_cbuf_submit_async();
+        self.d_dev.wait_for_copy();
 
-            // transition our tmp image to TRANSFER_DST
-            let tmp_src = vk::ImageMemoryBarrier::builder()
-                .image(image)
-                .src_access_mask(vk::AccessFlags::default())
-                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
+        if let Some((buffer, memory)) = blank {
+            unsafe {
+                self.d_dev.dev.destroy_buffer(buffer, None);
+                self.d_dev.free_allocation(&memory);
+            }
+        }
+    }
 
-            // transition our swapchain image to TRANSFER_SRC
-            let swapchain_src = vk::ImageMemoryBarrier::builder()
-                .image(self.d_state.d_images[self.d_state.d_current_image as usize])
-                .src_access_mask(vk::AccessFlags::MEMORY_READ)
-                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-                .old_layout(present_layout)
-                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
-            self.d_dev.dev.cmd_pipeline_barrier(
-                internal.copy_cbuf,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[tmp_src, swapchain_src],
-            );
+    /// Record copying the current swapchain image into `image`
+    ///
+    /// See `copy_current_image`. `blank` is the buffer from
+    /// `create_protected_blank`, which must live until the copy is done.
+    unsafe fn record_copy_current_image(
+        &self,
+        cbuf: vk::CommandBuffer,
+        image: vk::Image,
+        final_layout: vk::ImageLayout,
+        blank: &Option<(vk::Buffer, Allocation)>,
+    ) {
+        let present_layout = match self.d_state.d_needs_present_sema {
+            true => vk::ImageLayout::PRESENT_SRC_KHR,
+            false => vk::ImageLayout::GENERAL,
+        };
 
-            // copy from the swapchain image
-            let image_copy = vk::ImageCopy::builder()
-                .src_subresource(
-                    vk::ImageSubresourceLayers::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .build(),
-                )
-                .dst_subresource(
-                    vk::ImageSubresourceLayers::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .build(),
-                )
-                .extent(self.d_state.d_resolution.into())
-                .build();
+        let range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(1)
+            .build();
+
+        // transition our tmp image to TRANSFER_DST
+        let tmp_src = vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::default())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(range)
+            .build();
+
+        // transition our swapchain image to TRANSFER_SRC
+        let swapchain_src = vk::ImageMemoryBarrier::builder()
+            .image(self.d_state.d_images[self.d_state.d_current_image as usize])
+            .src_access_mask(vk::AccessFlags::MEMORY_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(present_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(range)
+            .build();
+        self.d_dev.dev.cmd_pipeline_barrier(
+            cbuf,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[tmp_src, swapchain_src],
+        );
 
-            self.d_dev.dev.cmd_copy_image(
-                internal.copy_cbuf,
-                self.d_state.d_images[self.d_state.d_current_image as usize],
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[image_copy],
-            );
+        // copy from the swapchain image
+        let image_copy = vk::ImageCopy::builder()
+            .src_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .build(),
+            )
+            .dst_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .build(),
+            )
+            .extent(self.d_state.d_resolution.into())
+            .build();
+
+        self.d_dev.dev.cmd_copy_image(
+            cbuf,
+            self.d_state.d_images[self.d_state.d_current_image as usize],
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[image_copy],
+        );
 
-            // transition our tmp image to general
-            let tmp_dst = vk::ImageMemoryBarrier::builder()
-                .image(image)
+        // Copy zeros over protected content, which is black in
+        // every swapchain format
+        if let Some((buffer, _)) = blank.as_ref() {
+            let barrier = vk::MemoryBarrier::builder()
                 .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .new_layout(vk::ImageLayout::GENERAL)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
-                .build();
-
-            // transition the swapchain image back to optimal
-            let swapchain_dst = vk::ImageMemoryBarrier::builder()
-                .image(self.d_state.d_images[self.d_state.d_current_image as usize])
-                .src_access_mask(vk::AccessFlags::TRANSFER_READ)
-                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                .new_layout(present_layout)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(range)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
                 .build();
             self.d_dev.dev.cmd_pipeline_barrier(
-                internal.copy_cbuf,
+                cbuf,
                 vk::PipelineStageFlags::TRANSFER,
                 vk::PipelineStageFlags::TRANSFER,
                 vk::DependencyFlags::empty(),
+                &[barrier],
                 &[],
                 &[],
-                &[tmp_dst, swapchain_dst],
             );
 
-            self.d_dev.cbuf_end_recording(internal.copy_cbuf);
+            let regions: Vec<vk::BufferImageCopy> = self
+                .d_protected_regions
+                .iter()
+                .map(|rect| {
+                    vk::BufferImageCopy::builder()
+                        .image_subresource(
+                            vk::ImageSubresourceLayers::builder()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .layer_count(1)
+                                .build(),
+                        )
+                        .image_offset(vk::Offset3D {
+                            x: rect.r_pos.0,
+                            y: rect.r_pos.1,
+                            z: 0,
+                        })
+                        .image_extent(vk::Extent3D {
+                            width: rect.r_size.0 as u32,
+                            height: rect.r_size.1 as u32,
+                            depth: 1,
+                        })
+                        .build()
+                })
+                .collect();
+            self.d_dev.dev.cmd_copy_buffer_to_image(
+                cbuf,
+                *buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions.as_slice(),
+            );
         }
 
-        self.d_dev.copy_cbuf_submit_async();
-        self.d_dev.wait_for_copy();
+        // transition our tmp image to its final layout
+        let tmp_dst = vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(final_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(range)
+            .build();
+
+        // transition the swapchain image back to optimal
+        let swapchain_dst = vk::ImageMemoryBarrier::builder()
+            .image(self.d_state.d_images[self.d_state.d_current_image as usize])
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(present_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(range)
+            .build();
+        self.d_dev.dev.cmd_pipeline_barrier(
+            cbuf,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[tmp_dst, swapchain_dst],
+        );
+    }
+
+    /// Read back the content of the current swapchain image
+    ///
+    /// The returned pixels are tightly packed 8-bit BGRA, with rows
+    /// ordered from top to bottom. Keep in mind that this will be very
+    /// expensive and synchronized. It also should be done before the next
+    /// image is acquired. Like the other ways of capturing a frame, any
+    /// protected content is replaced with black.
+    pub fn read_frame(&mut self) -> MappedImage {
+        // alloc a temp image
+        let (image, view, mem) = self.d_dev.create_image(
+            &self.d_state.d_resolution,
+            vk::Format::B8G8R8A8_UNORM,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+                | vk::MemoryPropertyFlags::HOST_COHERENT
+                | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            vk::ImageTiling::LINEAR,
+            vk::SampleCountFlags::TYPE_1,
+        );
+
+        self.copy_current_image(image, vk::ImageLayout::GENERAL);
 
         unsafe {
             // get image layout
@@ -549,54 +1429,231 @@ impl Display {
                     .build(),
             );
 
-            // Map our tmp image's memory
-            let ptr = self
-                .d_dev
-                .dev
-                .map_memory(
-                    mem,
-                    sublayout.offset,
-                    sublayout.size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .unwrap();
-
-            // copy our image data from the tmp image to an array
-            let data =
-                std::slice::from_raw_parts_mut(ptr as *mut u8, sublayout.size as usize).to_vec();
-
-            self.d_dev.dev.unmap_memory(mem);
+            // Our tmp image's memory is already mapped
+            let ptr = mem.a_mapped.add(sublayout.offset as usize);
+            let mapped = std::slice::from_raw_parts(ptr, sublayout.size as usize);
+
+            // copy our image data from the tmp image to an array, skipping
+            // any padding the driver added to the end of each row
+            let row_size = self.d_state.d_resolution.width as usize * 4;
+            let mut data = Vec::with_capacity(row_size * self.d_state.d_resolution.height as usize);
+            for row in 0..self.d_state.d_resolution.height as usize {
+                let start = row * sublayout.row_pitch as usize;
+                data.extend_from_slice(&mapped[start..start + row_size]);
+            }
 
             // Clean up our tmp image
             self.d_dev.dev.destroy_image(image, None);
             self.d_dev.dev.destroy_image_view(view, None);
-            self.d_dev.free_memory(mem);
+            self.d_dev.free_allocation(&mem);
 
-            // dump our data to a ppm file
-            {
-                use std::io::Write;
-
-                let mut f = std::fs::File::create(filename).unwrap();
-                // write ppm header
-                f.write(
-                    format!(
-                        "P6\n{}\n{}\n255\n",
-                        self.d_state.d_resolution.width, self.d_state.d_resolution.height
-                    )
-                    .as_bytes(),
+            MappedImage { mi_data: data }
+        }
+    }
+
+    /// Get the content of the current swapchain image
+    ///
+    /// This is `read_frame`, but also dumps the frame to a PPM file at
+    /// `filename`.
+    #[allow(dead_code)]
+    pub fn dump_framebuffer(&mut self, filename: &str) -> MappedImage {
+        let image = self.read_frame();
+
+        // dump our data to a ppm file
+        {
+            use std::io::Write;
+
+            let mut f = std::fs::File::create(filename).unwrap();
+            // write ppm header
+            f.write(
+                format!(
+                    "P6\n{}\n{}\n255\n",
+                    self.d_state.d_resolution.width, self.d_state.d_resolution.height
                 )
-                .unwrap();
-                // write pixel data
-                for pixel in data.as_slice().chunks(4) {
-                    // swizzle to RGB format
-                    f.write(&[pixel[2]]).unwrap();
-                    f.write(&[pixel[1]]).unwrap();
-                    f.write(&[pixel[0]]).unwrap();
+                .as_bytes(),
+            )
+            .unwrap();
+            // write pixel data
+            for pixel in image.mi_data.as_slice().chunks(4) {
+                // swizzle to RGB format
+                f.write(&[pixel[2]]).unwrap();
+                f.write(&[pixel[1]]).unwrap();
+                f.write(&[pixel[0]]).unwrap();
+            }
+        }
+
+        image
+    }
+
+    /// Export the contents of the current swapchain image as a dmabuf
+    ///
+    /// This copies the last frame we drew into a linear image and exports
+    /// its memory, which is used for screen recording. The returned dmabuf
+    /// has a single plane in DRM_FORMAT_ARGB8888 with the linear modifier.
+    ///
+    /// The copy is submitted after the frame and does not wait for it.
+    /// It is done once `get_last_frame_point` is signaled, or the sync
+    /// file from `export_frame_sync_file` is readable. Each swapchain image
+    /// has its own export image which is reused the next time it is
+    /// exported, so the dmabuf should be released by then. This should be
+    /// done after presenting and before the next image is acquired.
+    pub fn export_frame(&mut self) -> Result<Dmabuf> {
+        // We only know how to describe 8-bit BGRA frames to importers
+        if self.d_state.d_surface_format.format != vk::Format::B8G8R8A8_UNORM {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+
+        let index = self.d_state.d_current_image as usize;
+        if self.d_export_slots.len() <= index {
+            self.d_export_slots.resize_with(index + 1, || None);
+        }
+        let mut slot = match self.d_export_slots[index].take() {
+            Some(slot) => slot,
+            None => {
+                let (image, mem) = self.d_dev.create_exportable_image(
+                    &self.d_state.d_resolution,
+                    vk::Format::B8G8R8A8_UNORM,
+                )?;
+                let layout = unsafe {
+                    self.d_dev.dev.get_image_subresource_layout(
+                        image,
+                        vk::ImageSubresource::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .build(),
+                    )
+                };
+                ExportSlot {
+                    es_image: image,
+                    es_mem: mem,
+                    es_layout: layout,
+                    es_cbuf: self.d_dev.create_command_buffers(self.d_export_pool, 1)[0],
+                    es_point: 0,
+                    es_blank: None,
                 }
             }
+        };
 
-            MappedImage { mi_data: data }
+        // The last copy into this slot is normally long done, since this
+        // swapchain image has been drawn to again since then
+        if !self.d_dev.is_point_signaled(slot.es_point) {
+            self.d_dev.wait_for_latest_timeline();
+        }
+        if let Some((buffer, memory)) = slot.es_blank.take() {
+            unsafe {
+                self.d_dev.dev.destroy_buffer(buffer, None);
+                self.d_dev.free_allocation(&memory);
+            }
         }
+
+        slot.es_blank = self.create_protected_blank();
+        unsafe {
+            self.d_dev
+                .cbuf_begin_recording(slot.es_cbuf, vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.record_copy_current_image(
+                slot.es_cbuf,
+                slot.es_image,
+                vk::ImageLayout::GENERAL,
+                &slot.es_blank,
+            );
+            self.d_dev.cbuf_end_recording(slot.es_cbuf);
+        }
+        // This is ordered after the frame on its queue, and becomes the
+        // last frame point so the frame's sync covers the export
+        slot.es_point =
+            self.d_dev
+                .cbuf_submit_async(slot.es_cbuf, self.d_state.d_present_queue, &[], &[]);
+        self.d_pipe.g_last_frame_point = slot.es_point;
+
+        let fd = self.d_dev.export_memory_as_dmabuf(slot.es_mem);
+        let layout = slot.es_layout;
+        self.d_export_slots[index] = Some(slot);
+
+        let mut dmabuf = Dmabuf::new(
+            self.d_state.d_resolution.width as i32,
+            self.d_state.d_resolution.height as i32,
+        );
+        dmabuf.db_planes.push(DmabufPlane::new(
+            fd?,
+            0,
+            layout.offset as u32,
+            layout.row_pitch as u32,
+            // DRM_FORMAT_MOD_LINEAR
+            0,
+        ));
+
+        Ok(dmabuf)
+    }
+
+    /// Check that we can copy our frames into an image of this size
+    ///
+    /// Only 8-bit BGRA frames are supported, and the target must be at
+    /// least as large as our resolution.
+    fn check_copy_target(&self, width: u32, height: u32) -> Result<()> {
+        if self.d_state.d_surface_format.format != vk::Format::B8G8R8A8_UNORM {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+        if width < self.d_state.d_resolution.width || height < self.d_state.d_resolution.height {
+            log::error!(
+                "Copy target of size {}x{} is smaller than our resolution {:?}",
+                width,
+                height,
+                self.d_state.d_resolution
+            );
+            return Err(ThundrError::INVALID);
+        }
+
+        Ok(())
+    }
+
+    /// Copy the contents of the current swapchain image into a dmabuf
+    ///
+    /// This renders our last frame into memory provided by someone else,
+    /// such as a client's buffer for screencopy or the buffer of a parent
+    /// compositor we are nested in. The dmabuf must be a single plane in
+    /// DRM_FORMAT_ARGB8888 and at least the size of this Display. Like
+    /// `export_frame` this is synchronized, and should be done after
+    /// presenting and before the next image is acquired.
+    pub fn copy_frame_to_dmabuf(&mut self, dmabuf: &Dmabuf) -> Result<()> {
+        self.check_copy_target(dmabuf.db_width as u32, dmabuf.db_height as u32)?;
+
+        let (image, view, mem) = Device::create_image_from_dmabuf_internal(
+            &self.d_dev,
+            dmabuf,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        )?;
+        self.copy_current_image(image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        // Give the memory back to its owner before we drop our handles
+        self.d_dev.release_dmabuf_image_from_external_queue(image);
+        self.d_dev.wait_for_copy();
+        unsafe {
+            self.d_dev.dev.destroy_image_view(view, None);
+            self.d_dev.dev.destroy_image(image, None);
+            self.d_dev.free_allocation(&mem);
+        }
+
+        Ok(())
+    }
+
+    /// Copy the contents of the current swapchain image into an Image
+    ///
+    /// This allows offscreen targets created by the user to hold our
+    /// frames, which may then be drawn as part of another scene. The image
+    /// must be at least the size of this Display. This has the same
+    /// synchronization requirements as `copy_frame_to_dmabuf`.
+    pub fn copy_frame_to_image(&mut self, image: &Image) -> Result<()> {
+        let (width, height) = image.get_size();
+        self.check_copy_target(width, height)?;
+
+        let vk_image = self
+            .d_dev
+            .d_image_vk
+            .get(&image.i_id)
+            .ok_or(ThundrError::INVALID)?
+            .iv_image;
+        self.copy_current_image(vk_image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        Ok(())
     }
 }
 
@@ -604,8 +1661,13 @@ impl Drop for Display {
     fn drop(&mut self) {
         println!("Destroying display");
         unsafe {
-            self.d_dev.dev.device_wait_idle().unwrap();
+            self.d_dev.wait_idle();
+            // Free any images waiting on frames from this display
+            self.d_dev.drain_deletion_queue();
             self.destroy_swapchain_resources();
+            self.d_dev
+                .dev
+                .destroy_command_pool(self.d_export_pool, None);
             self.d_dev
                 .dev
                 .destroy_semaphore(self.d_state.d_frame_sema, None);
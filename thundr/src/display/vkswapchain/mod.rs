@@ -7,14 +7,16 @@
 #[cfg(feature = "sdl")]
 mod sdl;
 mod vkd2d;
+#[cfg(feature = "winit")]
+mod winit;
 
 use ash::extensions::khr;
 use ash::vk;
 use ash::Entry;
 
-use super::{DisplayInfoPayload, DisplayState, Swapchain};
+use super::{DisplayInfoPayload, DisplayState, PresentMode, PresentationTime, Swapchain};
 use crate::device::Device;
-use crate::{CreateInfo, Result as ThundrResult, SurfaceType, ThundrError, WindowInfo};
+use crate::{ColorSpace, CreateInfo, Result as ThundrResult, SurfaceType, ThundrError, WindowInfo};
 use utils::log;
 
 use std::str::FromStr;
@@ -33,6 +35,11 @@ impl DisplayInfoPayload for VkSwapchainPayload {
         usize::MAX
     }
 
+    /// There is only ever one display of this type
+    fn is_same_display(&self, other: &dyn DisplayInfoPayload) -> bool {
+        other.as_any().is::<Self>()
+    }
+
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
@@ -52,11 +59,21 @@ pub(crate) struct VkSwapchain {
     d_back: Box<dyn VkSwapchainBackend>,
     /// Cache the present mode here so we don't re-request it
     pub d_present_mode: vk::PresentModeKHR,
+    /// The present modes supported by our surface
+    d_present_modes: Vec<vk::PresentModeKHR>,
+    /// The present mode chosen by the user, if any
+    d_selected_present_mode: Option<vk::PresentModeKHR>,
+    /// Is tearing currently allowed?
+    d_tearing: bool,
+    /// The color space requested by the user
+    d_requested_color_space: ColorSpace,
 
     /// loads swapchain extension
     pub(crate) d_swapchain_loader: khr::Swapchain,
     /// the actual swapchain
     pub(crate) d_swapchain: vk::SwapchainKHR,
+    /// Frames presented since the last call to take_presentation_times
+    d_presentation_times: Vec<PresentationTime>,
 }
 
 pub(crate) trait VkSwapchainBackend {
@@ -86,6 +103,45 @@ pub(crate) trait VkSwapchainBackend {
 }
 
 impl VkSwapchain {
+    /// Choose the present mode to use when not tearing
+    ///
+    /// The best mode for presentation is FIFO (with triple buffering)
+    /// as this is recommended by the samsung developer page, which
+    /// I am *assuming* is a good reference for low power apps
+    fn default_present_mode(modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        modes
+            .iter()
+            .cloned()
+            .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
+            // fallback to FIFO if the mailbox mode is not available
+            .unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+
+    /// Choose the present mode the swapchain should be created with
+    ///
+    /// Tearing wins over the user's selection, which wins over our
+    /// default.
+    fn choose_present_mode(&self) -> vk::PresentModeKHR {
+        if self.d_tearing
+            && self
+                .d_present_modes
+                .contains(&vk::PresentModeKHR::IMMEDIATE)
+        {
+            return vk::PresentModeKHR::IMMEDIATE;
+        }
+
+        self.d_selected_present_mode
+            .unwrap_or_else(|| Self::default_present_mode(&self.d_present_modes))
+    }
+
+    /// Update the present mode, returning true if it changed
+    fn update_present_mode(&mut self) -> bool {
+        let mode = self.choose_present_mode();
+        let changed = mode != self.d_present_mode;
+        self.d_present_mode = mode;
+        changed
+    }
+
     /// Check if a queue family is suited for our needs.
     /// Queue families need to support graphical presentation and
     /// presentation on the given surface.
@@ -106,23 +162,42 @@ impl VkSwapchain {
             }
     }
 
-    /// choose a vkSurfaceFormatKHR for the vkSurfaceKHR
-    ///
-    /// This selects the color space and layout for a surface. This should
-    /// be called by the Renderer after creating a Display.
-    fn select_surface_format(&self) -> ThundrResult<vk::SurfaceFormatKHR> {
+    /// Get the list of formats supported by our surface
+    fn get_surface_formats(&self) -> ThundrResult<Vec<vk::SurfaceFormatKHR>> {
         let payload = self
             .d_payload
             .as_any()
             .downcast_ref::<VkSwapchainPayload>()
             .unwrap();
 
-        let formats = unsafe {
+        unsafe {
             payload
                 .sp_surface_loader
                 .get_physical_device_surface_formats(self.d_dev.pdev, self.d_surface)
-                .or(Err(ThundrError::INVALID))?
-        };
+                .or(Err(ThundrError::INVALID))
+        }
+    }
+
+    /// choose a vkSurfaceFormatKHR for the vkSurfaceKHR
+    ///
+    /// This selects the color space and layout for a surface. This should
+    /// be called by the Renderer after creating a Display.
+    fn select_surface_format(&self) -> ThundrResult<vk::SurfaceFormatKHR> {
+        let formats = self.get_surface_formats()?;
+
+        // Use the requested color space if the surface supports it
+        if let Some(fmt) = formats
+            .iter()
+            .find(|fmt| ColorSpace::from_surface_format(fmt) == Some(self.d_requested_color_space))
+        {
+            return Ok(*fmt);
+        }
+        if self.d_requested_color_space != ColorSpace::Srgb {
+            log::error!(
+                "Color space {:?} is not supported by this display, falling back to sRGB",
+                self.d_requested_color_space
+            );
+        }
 
         // TODO: For now force the use of a B8G8R8A8_UNORM. Without doing this we end up
         // with mismatching colors because we assume UNORM everywhere
@@ -329,23 +404,23 @@ impl VkSwapchain {
                     &payload.sp_surface_loader,
                     &info.window_info,
                 ),
+                #[cfg(feature = "winit")]
+                SurfaceType::Winit(_) => winit::WinitDisplayBackend::new(
+                    entry,
+                    inst,
+                    dev.pdev,
+                    &payload.sp_surface_loader,
+                    &info.window_info,
+                ),
                 _ => panic!("Unsupported surface type"),
             }
             .unwrap();
 
-            // the best mode for presentation is FIFO (with triple buffering)
-            // as this is recommended by the samsung developer page, which
-            // I am *assuming* is a good reference for low power apps
             let present_modes = payload
                 .sp_surface_loader
                 .get_physical_device_surface_present_modes(dev.pdev, surf)
                 .unwrap();
-            let mode = present_modes
-                .iter()
-                .cloned()
-                .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-                // fallback to FIFO if the mailbox mode is not available
-                .unwrap_or(vk::PresentModeKHR::FIFO);
+            let mode = Self::default_present_mode(&present_modes);
 
             let swapchain_loader = khr::Swapchain::new(&dev.inst.inst, &dev.dev);
 
@@ -355,8 +430,13 @@ impl VkSwapchain {
                 d_back: back,
                 d_surface: surf,
                 d_present_mode: mode,
+                d_present_modes: present_modes,
+                d_selected_present_mode: None,
+                d_tearing: false,
+                d_requested_color_space: info.color_space,
                 d_swapchain_loader: swapchain_loader,
                 d_swapchain: vk::SwapchainKHR::null(),
+                d_presentation_times: Vec::new(),
             })
         }
     }
@@ -419,16 +499,29 @@ impl Swapchain for VkSwapchain {
     /// the window is being resized and we have to regenerate accordingly.
     /// Keep in mind the Pipeline in Thundr will also have to be recreated
     /// separately.
-    fn recreate_swapchain(&mut self, dstate: &mut DisplayState) -> ThundrResult<()> {
+    fn recreate_swapchain(
+        &mut self,
+        dstate: &mut DisplayState,
+        size: Option<vk::Extent2D>,
+    ) -> ThundrResult<()> {
         // first wait for the device to finish working
-        unsafe { self.d_dev.dev.device_wait_idle().unwrap() };
+        self.d_dev.wait_idle();
+
+        // The extents the surface allows change along with the window
+        let (surface_caps, _) = self.get_surface_info()?;
+        dstate.d_surface_caps = surface_caps;
 
         // We need to get the updated size of our swapchain. This
-        // will be the current size of the surface in use. We should
-        // also update Display.d_resolution while we are at it.
-        let new_res = self.get_vulkan_drawable_size();
-        // TODO: clamp resolution here
-        dstate.d_resolution = new_res;
+        // will be the current size of the surface in use unless the
+        // caller asked for a size. We should also update
+        // Display.d_resolution while we are at it.
+        let new_res = size.unwrap_or_else(|| self.get_vulkan_drawable_size());
+        let min = surface_caps.min_image_extent;
+        let max = surface_caps.max_image_extent;
+        dstate.d_resolution = vk::Extent2D {
+            width: new_res.width.clamp(min.width, max.width.max(min.width)),
+            height: new_res.height.clamp(min.height, max.height.max(min.height)),
+        };
 
         self.create_swapchain(dstate)?;
 
@@ -437,6 +530,10 @@ impl Swapchain for VkSwapchain {
         Ok(())
     }
 
+    fn get_surface_size(&self, _dstate: &DisplayState) -> vk::Extent2D {
+        self.get_vulkan_drawable_size()
+    }
+
     /// Get the Dots Per Inch for this display.
     ///
     /// For VK_KHR_display we will calculate it ourselves, and for
@@ -505,6 +602,7 @@ impl Swapchain for VkSwapchain {
                 }
                 Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Err(ThundrError::OUT_OF_DATE),
                 Err(vk::Result::SUBOPTIMAL_KHR) => Err(ThundrError::OUT_OF_DATE),
+                Err(vk::Result::ERROR_DEVICE_LOST) => Err(ThundrError::DEVICE_LOST),
                 // the call did not succeed
                 Err(_) => Err(ThundrError::COULD_NOT_ACQUIRE_NEXT_IMAGE),
             };
@@ -538,20 +636,76 @@ impl Swapchain for VkSwapchain {
                 .d_swapchain_loader
                 .queue_present(dstate.d_present_queue, &info)
             {
-                Ok(_) => Ok(()),
+                Ok(_) => {
+                    // Vulkan doesn't tell us when the image reaches the
+                    // screen, so use the time it was queued
+                    self.d_presentation_times.push(PresentationTime::now(
+                        dstate.d_frame,
+                        self.d_present_mode != vk::PresentModeKHR::IMMEDIATE,
+                    ));
+                    Ok(())
+                }
                 Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Err(ThundrError::OUT_OF_DATE),
                 Err(vk::Result::SUBOPTIMAL_KHR) => Err(ThundrError::OUT_OF_DATE),
+                Err(vk::Result::ERROR_DEVICE_LOST) => Err(ThundrError::DEVICE_LOST),
                 Err(_) => Err(ThundrError::PRESENT_FAILED),
             }
         }
     }
+    fn take_presentation_times(&mut self) -> Vec<PresentationTime> {
+        std::mem::take(&mut self.d_presentation_times)
+    }
+
+    /// Switch to IMMEDIATE presentation while tearing is allowed
+    ///
+    /// The swapchain has to be recreated to change its present mode.
+    fn set_tearing(&mut self, allowed: bool) -> bool {
+        self.d_tearing = allowed;
+        self.update_present_mode()
+    }
+
+    fn get_supported_present_modes(&self) -> Vec<PresentMode> {
+        self.d_present_modes
+            .iter()
+            .filter_map(|&mode| PresentMode::from_vk(mode))
+            .collect()
+    }
+
+    fn get_present_mode(&self) -> PresentMode {
+        let mode = self
+            .d_selected_present_mode
+            .unwrap_or_else(|| Self::default_present_mode(&self.d_present_modes));
+        PresentMode::from_vk(mode).unwrap_or(PresentMode::Fifo)
+    }
+
+    /// Select a present mode, recreating the swapchain if it changed
+    fn set_present_mode(&mut self, mode: PresentMode) -> bool {
+        self.d_selected_present_mode = Some(mode.to_vk());
+        self.update_present_mode()
+    }
+
+    /// Get the color spaces this display can be driven in
+    ///
+    /// This checks which of our supported surface formats the
+    /// surface advertises.
+    fn get_supported_color_spaces(&self) -> Vec<ColorSpace> {
+        let mut ret = Vec::new();
+        for fmt in self.get_surface_formats().unwrap_or_default().iter() {
+            if let Some(space) = ColorSpace::from_surface_format(fmt) {
+                if !ret.contains(&space) {
+                    ret.push(space);
+                }
+            }
+        }
+        ret
+    }
 }
 
 impl Drop for VkSwapchain {
     fn drop(&mut self) {
         println!("Destroying swapchain");
         unsafe {
-            self.d_dev.dev.device_wait_idle().unwrap();
+            self.d_dev.wait_idle();
             self.destroy_swapchain();
 
             let payload = self
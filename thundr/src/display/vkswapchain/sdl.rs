@@ -86,20 +86,31 @@ impl VkSwapchainBackend for SDL2DisplayBackend {
         }
     }
 
+    /// This is the DPI of the display the window is currently on, so
+    /// it changes as the window is moved between displays.
     fn get_dpi(&self) -> ThundrResult<(i32, i32)> {
+        let index = self
+            .sdl_window
+            .display_index()
+            .or(Err(crate::ThundrError::INVALID))?;
         let dpi = self
             .sdl_video
-            .display_dpi(self.sdl_window.display_index().unwrap())
+            .display_dpi(index)
             .or(Err(crate::ThundrError::INVALID))?;
 
-        // Scale the reported DPI by the scaling factor
+        // Scale the reported DPI by the scaling factor. Do this in
+        // floating point, as the drawable is larger than the window on
+        // high DPI displays.
         let win_size = self.sdl_window.size();
         let vk_size = self.sdl_window.vulkan_drawable_size();
+        if vk_size.0 == 0 || vk_size.1 == 0 {
+            return Ok((dpi.1 as i32, dpi.2 as i32));
+        }
 
         // return hdpi and vdpi
         let ret = Ok((
-            dpi.1 as i32 * (win_size.0 as i32 / vk_size.0 as i32),
-            dpi.2 as i32 * (win_size.1 as i32 / vk_size.1 as i32),
+            (dpi.1 * win_size.0 as f32 / vk_size.0 as f32) as i32,
+            (dpi.2 * win_size.1 as f32 / vk_size.1 as f32) as i32,
         ));
 
         log::info!("Final DPI: {:?}", ret);
@@ -0,0 +1,104 @@
+///! winit backend for generic window system controls
+///
+/// Austin Shafer - 2024
+use ash::extensions::khr;
+use ash::vk;
+use ash::Entry;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+
+use super::VkSwapchainBackend;
+use crate::{Result as ThundrResult, WindowInfo};
+use utils::log;
+
+use std::sync::Arc;
+
+/// The DPI of a display with a scale factor of 1.0
+///
+/// winit doesn't tell us the physical size of displays, only how much
+/// the window system wants us to scale our contents by.
+const BASE_DPI: f64 = 96.0;
+
+/// The winit backend is the pure rust alternative to SDL2. It
+/// creates its surfaces through ash-window, which supports the
+/// same window systems as winit does.
+pub struct WinitDisplayBackend {
+    wi_window: Arc<::winit::window::Window>,
+}
+
+impl WinitDisplayBackend {
+    /// Create an on-screen surface.
+    ///
+    /// This will grab the function pointer loaders for the
+    /// surface and display extensions and then create a
+    /// surface to be rendered to.
+    pub(crate) fn new(
+        entry: &Entry,
+        inst: &ash::Instance,
+        pdev: vk::PhysicalDevice,
+        surface_loader: &khr::Surface,
+        win_info: &WindowInfo,
+    ) -> Option<(Box<dyn VkSwapchainBackend>, vk::SurfaceKHR, vk::Extent2D)> {
+        match win_info {
+            WindowInfo::Winit(win) => {
+                let ret = Box::new(Self {
+                    wi_window: (*win).clone(),
+                });
+
+                let surface = ret
+                    .create_surface(entry, inst, pdev, surface_loader, win_info)
+                    .unwrap();
+                let size = ret.get_vulkan_drawable_size().unwrap();
+
+                Some((ret, surface, size))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl VkSwapchainBackend for WinitDisplayBackend {
+    /// Create a surface for whichever window system winit is using
+    fn create_surface(
+        &self,
+        entry: &Entry,
+        inst: &ash::Instance,
+        _pdev: vk::PhysicalDevice,
+        _surface_loader: &khr::Surface,
+        win_info: &WindowInfo,
+    ) -> Result<vk::SurfaceKHR, vk::Result> {
+        match win_info {
+            WindowInfo::Winit(win) => unsafe {
+                ash_window::create_surface(
+                    entry,
+                    inst,
+                    win.raw_display_handle(),
+                    win.raw_window_handle(),
+                    None,
+                )
+                .map_err(|e| {
+                    log::error!("ash-window create_surface failed: {:?}", e);
+                    e
+                })
+            },
+            _ => panic!("Trying to create winit backend on non-winit surface"),
+        }
+    }
+
+    /// This is derived from the scale factor of the display the window
+    /// is currently on, so it changes as the window is moved between
+    /// displays.
+    fn get_dpi(&self) -> ThundrResult<(i32, i32)> {
+        let dpi = (BASE_DPI * self.wi_window.scale_factor()) as i32;
+        Ok((dpi, dpi))
+    }
+
+    /// Wayland surfaces don't have an extent until we pick one, so
+    /// always use the size of the window.
+    fn get_vulkan_drawable_size(&self) -> Option<vk::Extent2D> {
+        let size = self.wi_window.inner_size();
+        Some(vk::Extent2D {
+            width: size.width,
+            height: size.height,
+        })
+    }
+}
@@ -7,8 +7,9 @@ extern crate lluvia as ll;
 extern crate nix;
 
 use super::device::Device;
+use crate::allocator::Allocation;
 use crate::descpool::Descriptor;
-use crate::{Damage, Droppable, Result, ThundrError};
+use crate::{ColorSpace, Damage, Droppable, Result, ThundrError};
 use utils::log;
 use utils::region::Rect;
 
@@ -20,11 +21,136 @@ use std::sync::{Arc, RwLock};
 
 use ash::vk;
 use nix::fcntl::{fcntl, FcntlArg};
+use nix::unistd::{sysconf, SysconfVar};
 
-// For now we only support one format.
+// For now we only support one format for dmabufs.
 // According to the mesa source, this supports all modifiers.
 const TARGET_FORMAT: vk::Format = vk::Format::B8G8R8A8_UNORM;
 
+/// The layout of the pixels passed to `create_image_from_bits`
+///
+/// These are the wl_shm formats of the same names. Like DRM formats the
+/// channels are listed from the most significant bit, and pixels are
+/// stored little endian. Formats starting with X have no alpha channel,
+/// and their padding bits are ignored.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShmFormat {
+    ARGB8888,
+    XRGB8888,
+    ABGR8888,
+    XBGR8888,
+    RGB565,
+    ARGB2101010,
+    XRGB2101010,
+    ABGR2101010,
+    XBGR2101010,
+}
+
+impl ShmFormat {
+    /// Every format, in order of preference
+    pub const ALL: [ShmFormat; 9] = [
+        ShmFormat::ARGB8888,
+        ShmFormat::XRGB8888,
+        ShmFormat::ABGR8888,
+        ShmFormat::XBGR8888,
+        ShmFormat::RGB565,
+        ShmFormat::ARGB2101010,
+        ShmFormat::XRGB2101010,
+        ShmFormat::ABGR2101010,
+        ShmFormat::XBGR2101010,
+    ];
+
+    /// Get the number of bytes in one pixel
+    pub fn get_size(&self) -> usize {
+        match self {
+            ShmFormat::RGB565 => 2,
+            _ => 4,
+        }
+    }
+
+    /// Is this format packed into a single 16 or 32 bit value?
+    ///
+    /// Vulkan reads packed formats in host byte order, while wl_shm
+    /// pixels are always little endian. They only have the same layout
+    /// on little endian hosts.
+    fn is_packed(&self) -> bool {
+        matches!(
+            self,
+            ShmFormat::RGB565
+                | ShmFormat::ARGB2101010
+                | ShmFormat::XRGB2101010
+                | ShmFormat::ABGR2101010
+                | ShmFormat::XBGR2101010
+        )
+    }
+
+    /// Get the Vulkan format with the same memory layout
+    ///
+    /// The 8 bit formats are read byte by byte, so they match on any host.
+    /// The packed formats only match on little endian hosts, see
+    /// `is_packed`.
+    fn get_vk_format(&self) -> vk::Format {
+        match self {
+            ShmFormat::ARGB8888 | ShmFormat::XRGB8888 => vk::Format::B8G8R8A8_UNORM,
+            ShmFormat::ABGR8888 | ShmFormat::XBGR8888 => vk::Format::R8G8B8A8_UNORM,
+            ShmFormat::RGB565 => vk::Format::R5G6B5_UNORM_PACK16,
+            ShmFormat::ARGB2101010 | ShmFormat::XRGB2101010 => vk::Format::A2R10G10B10_UNORM_PACK32,
+            ShmFormat::ABGR2101010 | ShmFormat::XBGR2101010 => vk::Format::A2B10G10R10_UNORM_PACK32,
+        }
+    }
+
+    /// Get the view swizzle for this format
+    ///
+    /// The padding of X formats is read as an opaque alpha.
+    fn get_components(&self) -> vk::ComponentMapping {
+        match self {
+            ShmFormat::XRGB8888
+            | ShmFormat::XBGR8888
+            | ShmFormat::XRGB2101010
+            | ShmFormat::XBGR2101010 => vk::ComponentMapping {
+                a: vk::ComponentSwizzle::ONE,
+                ..Default::default()
+            },
+            _ => vk::ComponentMapping::default(),
+        }
+    }
+}
+
+/// How an Image is sampled when it is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplerMode {
+    /// Blend between the nearest pixels of the full size image
+    #[default]
+    Linear,
+    /// Keep a chain of mipmaps and blend between the two closest levels.
+    /// This avoids shimmering when the image is drawn much smaller
+    /// than its real size, at the cost of extra memory and work on
+    /// every update.
+    Trilinear,
+}
+
+/// Get the number of mip levels for a full mip chain
+pub(crate) fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - std::cmp::max(width, height).max(1).leading_zeros()
+}
+
+/// Get the region of a mip level covered by a region of the base level
+///
+/// The result is grown to whole pixels of the mip level and clamped
+/// to its size.
+pub(crate) fn mip_region(rect: &Rect<i32>, level: u32, width: u32, height: u32) -> Rect<i32> {
+    let scale = 1 << level;
+    let level_w = std::cmp::max(width >> level, 1) as i32;
+    let level_h = std::cmp::max(height >> level, 1) as i32;
+
+    let x = (rect.r_pos.0 / scale).clamp(0, level_w);
+    let y = (rect.r_pos.1 / scale).clamp(0, level_h);
+    let end_x = ((rect.r_pos.0 + rect.r_size.0 + scale - 1) / scale).clamp(0, level_w);
+    let end_y = ((rect.r_pos.1 + rect.r_size.1 + scale - 1) / scale).clamp(0, level_h);
+
+    Rect::new(x, y, end_x - x, end_y - y)
+}
+
 /// dmabuf plane parameters from linux_dmabuf
 ///
 /// Represents one dma buffer the client has added.
@@ -97,8 +223,10 @@ pub struct ImageVk {
     /// image containing the contents of the window.
     pub iv_image: vk::Image,
     pub iv_image_view: vk::ImageView,
-    pub iv_image_mem: vk::DeviceMemory,
+    pub iv_image_mem: Allocation,
     pub iv_image_resolution: vk::Extent2D,
+    /// Number of mip levels in iv_image. This is 1 if there are no mipmaps.
+    pub iv_mip_levels: u32,
     /// Stuff to release when we are no longer using
     /// this gpu buffer (release the wl_buffer)
     iv_release_info: Option<Box<dyn Droppable + Send + Sync>>,
@@ -110,7 +238,13 @@ pub struct ImageVk {
 impl ImageVk {
     pub fn clear(&mut self) {
         self.iv_dev.wait_for_latest_timeline();
+        self.destroy();
+    }
 
+    /// Free our Vulkan resources and drop the release info
+    ///
+    /// The GPU must be done with this image.
+    fn destroy(&mut self) {
         if self.iv_is_dmabuf {
             // Now that we are done with this vulkan image, release ownership
             // of it.
@@ -124,18 +258,19 @@ impl ImageVk {
         unsafe {
             self.iv_dev.dev.destroy_image_view(self.iv_image_view, None);
             self.iv_dev.dev.destroy_image(self.iv_image, None);
-            self.iv_dev.free_memory(self.iv_image_mem);
+            self.iv_dev.free_allocation(&self.iv_image_mem);
         }
 
         self.iv_dev = self.iv_dev.clone();
         self.iv_is_dmabuf = false;
         self.iv_image = vk::Image::null();
         self.iv_image_view = vk::ImageView::null();
-        self.iv_image_mem = vk::DeviceMemory::null();
+        self.iv_image_mem = Allocation::null();
         self.iv_image_resolution = vk::Extent2D {
             width: 0,
             height: 0,
         };
+        self.iv_mip_levels = 1;
         self.iv_release_info = None;
     }
 }
@@ -151,10 +286,48 @@ impl Drop for ImageVk {
 
         log::debug!("Deleting image view {:?}", self.iv_image_view);
 
+        // Frames that are still in flight may be reading from a dmabuf or
+        // client memory, and the client can't have its buffer back until
+        // they are done. Instead of waiting for them here, hand our
+        // resources to the deletion queue which drops them once the GPU
+        // has finished.
+        if self.iv_is_dmabuf || self.iv_release_info.is_some() {
+            let deferred = DeferredImage(ImageVk {
+                iv_dev: self.iv_dev.clone(),
+                iv_is_dmabuf: self.iv_is_dmabuf,
+                iv_image: self.iv_image,
+                iv_image_view: self.iv_image_view,
+                iv_image_mem: std::mem::replace(&mut self.iv_image_mem, Allocation::null()),
+                iv_image_resolution: self.iv_image_resolution,
+                iv_mip_levels: self.iv_mip_levels,
+                iv_release_info: self.iv_release_info.take(),
+                iv_desc: self.iv_desc.clone(),
+            });
+            self.iv_image = vk::Image::null();
+            self.iv_image_view = vk::ImageView::null();
+            self.iv_desc.d_set = vk::DescriptorSet::null();
+
+            self.iv_dev
+                .schedule_drop_after_latest_frame(Box::new(deferred));
+            return;
+        }
+
         self.clear();
     }
 }
 
+/// An ImageVk whose frames have been submitted
+///
+/// This is scheduled in the Device's DeletionQueue, and frees the image
+/// without waiting once the frames reading it have completed.
+struct DeferredImage(ImageVk);
+
+impl Drop for DeferredImage {
+    fn drop(&mut self) {
+        self.0.destroy();
+    }
+}
+
 /// A image buffer containing contents to be composited.
 ///
 /// An Image will be created from a data source and attached to
@@ -168,6 +341,14 @@ pub(crate) struct ImageInternal {
     i_priv: ImagePrivate,
     pub i_opaque: Option<Rect<i32>>,
     i_resolution: vk::Extent2D,
+    i_sampler_mode: SamplerMode,
+    i_color_space: ColorSpace,
+    /// Should this image be hidden from captures of the screen
+    i_protected: bool,
+    /// The format of the pixels this image was last filled with
+    i_format: ShmFormat,
+    /// Can this image be scaled into others with `update_scaled_copy`
+    i_blit_src: bool,
 }
 
 impl Image {
@@ -181,6 +362,45 @@ impl Image {
     pub fn set_opaque(&mut self, opaque: Option<Rect<i32>>) {
         self.i_internal.write().unwrap().i_opaque = opaque;
     }
+
+    pub fn get_sampler_mode(&self) -> SamplerMode {
+        self.i_internal.read().unwrap().i_sampler_mode
+    }
+
+    /// Choose how this image is sampled
+    ///
+    /// Images drawn much smaller than their size should use
+    /// `SamplerMode::Trilinear`. The mip chain is created during the next
+    /// call to `update_image_from_bits`, after which only the damaged
+    /// parts of it are regenerated on updates. Dmabuf images are never
+    /// mipmapped since we do not own their contents.
+    pub fn set_sampler_mode(&mut self, mode: SamplerMode) {
+        self.i_internal.write().unwrap().i_sampler_mode = mode;
+    }
+
+    pub fn get_color_space(&self) -> ColorSpace {
+        self.i_internal.read().unwrap().i_color_space
+    }
+
+    /// Tag the color space the contents of this image are encoded in
+    ///
+    /// Images default to sRGB. The contents are converted to the color
+    /// space of the Display they are drawn on.
+    pub fn set_color_space(&mut self, space: ColorSpace) {
+        self.i_internal.write().unwrap().i_color_space = space;
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.i_internal.read().unwrap().i_protected
+    }
+
+    /// Mark the contents of this image as protected
+    ///
+    /// Surfaces drawn with a protected image are blanked out of frames
+    /// read back from the Display, such as screenshots and screencasts.
+    pub fn set_protected(&mut self, protected: bool) {
+        self.i_internal.write().unwrap().i_protected = protected;
+    }
 }
 
 #[derive(Clone)]
@@ -221,6 +441,8 @@ enum ImagePrivate {
     InvalidImage,
     Dmabuf,
     MemImage,
+    /// Sampled in place from host memory, see `create_image_from_host_memory`
+    HostMemory,
 }
 
 impl Default for ImagePrivate {
@@ -241,24 +463,119 @@ struct DmabufPrivate {
 }
 
 impl Device {
-    /// Helper that unifies the call for allocating a bgra image
-    fn alloc_bgra8_image(
+    /// Helper that unifies the call for allocating an image for shm contents
+    ///
+    /// Mipmapped images need optimal tiling, and blit between their own levels.
+    fn alloc_shm_image(
         &self,
         resolution: &vk::Extent2D,
-    ) -> (vk::Image, vk::ImageView, vk::DeviceMemory) {
-        self.create_image(
+        mip_levels: u32,
+        format: ShmFormat,
+    ) -> (vk::Image, vk::ImageView, Allocation) {
+        let (usage, flags, tiling) = match mip_levels > 1 {
+            true => (
+                vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vk::ImageTiling::OPTIMAL,
+            ),
+            false => (
+                match self.is_shm_blit_src_supported(format, 1) {
+                    true => {
+                        vk::ImageUsageFlags::SAMPLED
+                            | vk::ImageUsageFlags::TRANSFER_DST
+                            | vk::ImageUsageFlags::TRANSFER_SRC
+                    }
+                    false => vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+                },
+                vk::MemoryPropertyFlags::DEVICE_LOCAL
+                    | vk::MemoryPropertyFlags::HOST_COHERENT
+                    | vk::MemoryPropertyFlags::HOST_VISIBLE,
+                vk::ImageTiling::LINEAR,
+            ),
+        };
+
+        self.create_mipmapped_image(
             resolution,
-            TARGET_FORMAT,
-            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            format.get_vk_format(),
+            format.get_components(),
+            usage,
             vk::ImageAspectFlags::COLOR,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL
-                | vk::MemoryPropertyFlags::HOST_COHERENT
-                | vk::MemoryPropertyFlags::HOST_VISIBLE,
-            vk::ImageTiling::LINEAR,
+            flags,
+            tiling,
+            vk::SampleCountFlags::TYPE_1,
+            mip_levels,
         )
     }
 
+    /// Can images be created from pixels in `format`
+    ///
+    /// Images are sampled with linear tiling, or optimal tiling when they
+    /// have mipmaps, which are generated by blitting between levels.
+    /// Packed formats are only supported on little endian hosts.
+    fn is_shm_format_supported(&self, format: ShmFormat) -> bool {
+        // wl_shm requires these, and we have always used them
+        if format == ShmFormat::ARGB8888 || format == ShmFormat::XRGB8888 {
+            return true;
+        }
+        // Big endian hosts would read packed pixels with their bytes swapped
+        if format.is_packed() && cfg!(target_endian = "big") {
+            return false;
+        }
+
+        let props = unsafe {
+            self.inst
+                .inst
+                .get_physical_device_format_properties(self.pdev, format.get_vk_format())
+        };
+        let linear = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST;
+        let optimal = linear
+            | vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+
+        props.linear_tiling_features.contains(linear)
+            && props.optimal_tiling_features.contains(optimal)
+    }
+
+    /// Can shm images in `format` be scaled into other images
+    ///
+    /// Mipmapped images always can, since their levels are generated by
+    /// blitting. Blitting from linear images is optional in Vulkan.
+    fn is_shm_blit_src_supported(&self, format: ShmFormat, mip_levels: u32) -> bool {
+        if mip_levels > 1 {
+            return true;
+        }
+
+        let props = unsafe {
+            self.inst
+                .inst
+                .get_physical_device_format_properties(self.pdev, format.get_vk_format())
+        };
+        props.linear_tiling_features.contains(
+            vk::FormatFeatureFlags::TRANSFER_SRC
+                | vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    /// Get the formats `create_image_from_bits` accepts on this device
+    ///
+    /// ARGB8888 and XRGB8888 are always supported.
+    pub fn get_supported_shm_formats(&self) -> Vec<ShmFormat> {
+        ShmFormat::ALL
+            .iter()
+            .filter(|format| self.is_shm_format_supported(**format))
+            .cloned()
+            .collect()
+    }
+
     /// Update an existing image from a shm buffer
+    ///
+    /// Returns ThundrError::INVALID_FORMAT if `format` is not one of
+    /// `get_supported_shm_formats`.
+    #[allow(clippy::too_many_arguments)]
     pub fn update_image_from_bits(
         &self,
         image: &Image,
@@ -266,18 +583,35 @@ impl Device {
         width: u32,
         height: u32,
         stride: u32,
+        format: ShmFormat,
         damage: Option<Damage>,
         release: Option<Box<dyn Droppable + Send + Sync>>,
     ) -> Result<()> {
+        if !self.is_shm_format_supported(format) {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
         self.wait_for_latest_timeline();
 
         {
             let mut image_internal = image.i_internal.write().unwrap();
+            // Copying into these would write over the client's memory
+            if let ImagePrivate::HostMemory = image_internal.i_priv {
+                return Err(ThundrError::INVALID);
+            }
             let imgvk_id = &image.i_id;
             let resolution = image_internal.i_resolution;
+            let mip_levels = match image_internal.i_sampler_mode {
+                SamplerMode::Linear => 1,
+                SamplerMode::Trilinear => mip_level_count(width, height),
+            };
 
-            // If the sizes match then we can update according to the damage provided
-            if width == resolution.width && height == resolution.height {
+            // If the sizes and formats match then we can update according to
+            // the damage provided
+            if width == resolution.width
+                && height == resolution.height
+                && format == image_internal.i_format
+                && mip_levels == self.d_image_vk.get(imgvk_id).unwrap().iv_mip_levels
+            {
                 // Get our vk image here, we can copy it since we know we are holding
                 // the vk_image mutex mutably, so no other rendering is currently taking
                 // place. We then wait for the latest timeline point to ensure there is
@@ -291,18 +625,21 @@ impl Device {
                     width,
                     height,
                     stride,
+                    format,
                     damage,
+                    mip_levels,
                 );
             }
 
-            // If the new contents have a change in size, then we need to realloc our
+            // If the new contents have a change in size or format, or the mip
+            // chain needs to be added or removed, then we need to realloc our
             // internal image. In this case we can ignore damage
             let new_size = vk::Extent2D {
                 width: width,
                 height: height,
             };
 
-            let (image, view, img_mem) = self.alloc_bgra8_image(&new_size);
+            let (image, view, img_mem) = self.alloc_shm_image(&new_size, mip_levels, format);
             let _old_release = {
                 let old_image_vk = self.d_image_vk.take(&imgvk_id).unwrap();
 
@@ -316,17 +653,221 @@ impl Device {
                         iv_image_view: view,
                         iv_image_mem: img_mem,
                         iv_image_resolution: new_size,
+                        iv_mip_levels: mip_levels,
                         iv_release_info: release,
-                        iv_desc: self.create_new_image_descriptor(view),
+                        iv_desc: self.create_new_image_descriptor(view, mip_levels),
                     }),
                 );
                 image_internal.i_resolution = new_size;
+                image_internal.i_format = format;
+                image_internal.i_blit_src = self.is_shm_blit_src_supported(format, mip_levels);
 
                 old_image_vk
             };
 
-            self.update_image_from_data(image, data, width, height, stride)?;
+            self.update_image_from_data(image, data, width, height, stride, format, mip_levels)?;
+        }
+
+        Ok(())
+    }
+
+    /// Change how an existing image is sampled
+    ///
+    /// Unlike `Image::set_sampler_mode` this does not wait for the next
+    /// update of the contents. The mip chain is built from, or dropped
+    /// back to, the current contents of `image` on the copy queue. This
+    /// is for images that are only sometimes drawn much smaller than
+    /// their size. Images which can't be copied from, such as dmabufs,
+    /// only have the mode recorded.
+    pub fn set_image_sampler_mode(&self, image: &Image, mode: SamplerMode) -> Result<()> {
+        let mut image_internal = image.i_internal.write().unwrap();
+        image_internal.i_sampler_mode = mode;
+
+        if !matches!(image_internal.i_priv, ImagePrivate::MemImage) || !image_internal.i_blit_src {
+            return Ok(());
+        }
+
+        let imgvk_id = &image.i_id;
+        let res = image_internal.i_resolution;
+        let format = image_internal.i_format;
+        let mip_levels = match mode {
+            SamplerMode::Linear => 1,
+            SamplerMode::Trilinear => mip_level_count(res.width, res.height),
+        };
+        let (old_image, old_mip_levels) = {
+            let image_vk = self.d_image_vk.get(imgvk_id).ok_or(ThundrError::INVALID)?;
+            (image_vk.iv_image, image_vk.iv_mip_levels)
+        };
+        if mip_levels == old_mip_levels {
+            return Ok(());
+        }
+
+        let (new_image, view, img_mem) = self.alloc_shm_image(&res, mip_levels, format);
+
+        // Frames in flight may be sampling the old image, and we are about
+        // to change its layout
+        self.wait_for_latest_timeline();
+        self.wait_for_copy();
+
+        unsafe {
+            let int_lock = self.d_internal.clone();
+            let mut internal = int_lock.write().unwrap();
+            let copy_cbuf = internal.copy_cbuf;
+
+            self.cbuf_begin_recording(copy_cbuf, vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            if let Some(profiler) = internal.copy_profiler.as_mut() {
+                profiler.begin(&self.dev, copy_cbuf);
+            }
+
+            let barrier = |image: vk::Image,
+                           levels: u32,
+                           old_layout: vk::ImageLayout,
+                           new_layout: vk::ImageLayout,
+                           src_access: vk::AccessFlags,
+                           dst_access: vk::AccessFlags| {
+                vk::ImageMemoryBarrier::builder()
+                    .image(image)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1)
+                            .level_count(levels)
+                            .build(),
+                    )
+                    .build()
+            };
+
+            self.dev.cmd_pipeline_barrier(
+                copy_cbuf,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        old_image,
+                        old_mip_levels,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::SHADER_READ,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    barrier(
+                        new_image,
+                        mip_levels,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+
+            // Only the base level is kept, the rest of the chain is
+            // generated from it
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build();
+            let copy = vk::ImageCopy::builder()
+                .src_subresource(subresource)
+                .dst_subresource(subresource)
+                .extent(vk::Extent3D {
+                    width: res.width,
+                    height: res.height,
+                    depth: 1,
+                })
+                .build();
+            self.dev.cmd_copy_image(
+                copy_cbuf,
+                old_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[copy],
+            );
+
+            // This leaves every level in TRANSFER_SRC_OPTIMAL
+            let new_layout = match mip_levels > 1 {
+                true => {
+                    let full = Rect::new(0, 0, res.width as i32, res.height as i32);
+                    self.record_mipmap_generation(
+                        copy_cbuf,
+                        new_image,
+                        res.width,
+                        res.height,
+                        mip_levels,
+                        &[full],
+                    );
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+                }
+                false => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            };
+
+            self.dev.cmd_pipeline_barrier(
+                copy_cbuf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        old_image,
+                        old_mip_levels,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    barrier(
+                        new_image,
+                        mip_levels,
+                        new_layout,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                ],
+            );
+
+            if let Some(profiler) = internal.copy_profiler.as_mut() {
+                profiler.end(&self.dev, copy_cbuf);
+            }
+            self.cbuf_end_recording(copy_cbuf);
         }
+        self.copy_cbuf_submit_async();
+
+        let old_image_vk = self.d_image_vk.take(imgvk_id).unwrap();
+        self.d_image_vk.set(
+            imgvk_id,
+            Arc::new(ImageVk {
+                iv_dev: old_image_vk.iv_dev.clone(),
+                iv_image: new_image,
+                iv_is_dmabuf: false,
+                iv_image_view: view,
+                iv_image_mem: img_mem,
+                iv_image_resolution: res,
+                iv_mip_levels: mip_levels,
+                iv_release_info: None,
+                iv_desc: self.create_new_image_descriptor(view, mip_levels),
+            }),
+        );
+        image_internal.i_blit_src = self.is_shm_blit_src_supported(format, mip_levels);
+
+        // The old image is read by the copy, so it can't be freed until
+        // the copy is done
+        self.wait_for_copy();
+        drop(old_image_vk);
 
         Ok(())
     }
@@ -412,7 +953,7 @@ impl Device {
         dev: &Device,
         dmabuf: &Dmabuf,
         image_usage: vk::ImageUsageFlags,
-    ) -> Result<(vk::Image, vk::ImageView, vk::DeviceMemory)> {
+    ) -> Result<(vk::Image, vk::ImageView, Allocation)> {
         log::debug!("Updating new image with dmabuf {:?}", dmabuf);
         // A lot of this is duplicated from Renderer::create_image
         // Check validity of dmabuf format and print info
@@ -491,7 +1032,7 @@ impl Device {
         dmabuf: &Dmabuf,
         dmabuf_priv: &mut DmabufPrivate,
         image_usage: vk::ImageUsageFlags,
-    ) -> Result<(vk::Image, vk::ImageView, vk::DeviceMemory)> {
+    ) -> Result<(vk::Image, vk::ImageView, Allocation)> {
         // TODO: multiplanar support
         let plane = &dmabuf.db_planes[0];
 
@@ -602,10 +1143,11 @@ impl Device {
 
         // perform the import
         unsafe {
-            let image_memory = self.dev.allocate_memory(&alloc_info, None).unwrap();
+            let image_memory = self.allocate_memory(&alloc_info).unwrap();
             self.dev
                 .bind_image_memory(image, image_memory, 0)
                 .expect("Unable to bind device memory to image");
+            let image_memory = Allocation::external(image_memory, alloc_info.allocation_size);
 
             // finally make a view to wrap the image
             let view_info = vk::ImageViewCreateInfo::builder()
@@ -635,15 +1177,22 @@ impl Device {
 
     /// create_image_from_bits
     ///
-    /// A stride of zero implies tightly packed data
+    /// A stride of zero implies tightly packed data. Returns
+    /// ThundrError::INVALID_FORMAT if `format` is not one of
+    /// `get_supported_shm_formats`.
     pub fn create_image_from_bits(
         &self,
         data: &[u8],
         width: u32,
         height: u32,
         stride: u32,
+        format: ShmFormat,
         release_info: Option<Box<dyn Droppable + Send + Sync>>,
     ) -> Result<Image> {
+        if !self.is_shm_format_supported(format) {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+
         let tex_res = vk::Extent2D {
             width: width,
             height: height,
@@ -659,11 +1208,11 @@ impl Device {
         //);
 
         // This image will back the contents of the on-screen client window.
-        let (image, view, img_mem) = self.alloc_bgra8_image(&tex_res);
+        let (image, view, img_mem) = self.alloc_shm_image(&tex_res, 1, format);
 
-        self.update_image_from_data(image, data, width, height, stride)?;
+        self.update_image_from_data(image, data, width, height, stride, format, 1)?;
 
-        return self.create_image_common(
+        let ret = self.create_image_common(
             ImagePrivate::MemImage,
             &tex_res,
             image,
@@ -671,7 +1220,212 @@ impl Device {
             view,
             false,
             release_info,
+        )?;
+        {
+            let mut internal = ret.i_internal.write().unwrap();
+            internal.i_format = format;
+            internal.i_blit_src = self.is_shm_blit_src_supported(format, 1);
+        }
+
+        Ok(ret)
+    }
+
+    /// Create an image which samples a shm buffer in place
+    ///
+    /// Instead of copying `data` into a new image, the pages holding it
+    /// are imported with VK_EXT_external_memory_host and bound to a linear
+    /// image. This saves copying the whole buffer on every commit, which
+    /// adds up for large buffers that are updated at a high rate.
+    ///
+    /// The driver decides the row pitch of linear images, so this only
+    /// works if it matches `stride`. ThundrError::HOST_MEMORY_NOT_SUPPORTED
+    /// is returned if the memory can't be sampled in place, in which case
+    /// `create_image_from_bits` should be used instead.
+    ///
+    /// # Safety
+    ///
+    /// The pages holding `data` must stay mapped until `release_info` is
+    /// dropped. It is kept until the GPU has finished with this image.
+    pub unsafe fn create_image_from_host_memory(
+        &self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: ShmFormat,
+        release_info: Option<Box<dyn Droppable + Send + Sync>>,
+    ) -> Result<Image> {
+        if !self.is_shm_format_supported(format) {
+            return Err(ThundrError::INVALID_FORMAT);
+        }
+        if !self.dev_features.vkc_supports_host_mem {
+            return Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED);
+        }
+
+        let stride = match stride {
+            0 => width,
+            s => s,
+        };
+        let texel_size = format.get_size();
+        let texels = stride as usize * (height as usize).saturating_sub(1) + width as usize;
+        if texels * texel_size > data.len() {
+            return Err(ThundrError::INVALID_STRIDE);
+        }
+
+        // The imported range has to start and end on the driver's
+        // alignment. Only the pages holding `data` are guaranteed to be
+        // mapped, so we can't round past them if it is larger than a page.
+        let alignment = self.dev_features.vkc_host_mem_alignment.max(1);
+        let page_size = match sysconf(SysconfVar::PAGE_SIZE) {
+            Ok(Some(size)) => size as u64,
+            _ => 4096,
+        };
+        if alignment > page_size {
+            return Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED);
+        }
+        let start = data.as_ptr() as u64;
+        let import_start = start & !(alignment - 1);
+        let data_offset = start - import_start;
+        let import_size = (data_offset + data.len() as u64 + alignment - 1) & !(alignment - 1);
+
+        let mut ext_mem_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT)
+            .build();
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format.get_vk_format())
+            .extent(vk::Extent3D {
+                width: width,
+                height: height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::LINEAR)
+            .usage(vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            // The client has already written the contents
+            .initial_layout(vk::ImageLayout::PREINITIALIZED)
+            .push_next(&mut ext_mem_info)
+            .build();
+        let image = self
+            .dev
+            .create_image(&image_info, None)
+            .or(Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED))?;
+
+        // Check that the driver lays out the image the same way the
+        // client did, and find where to bind it so that the first texel
+        // lands on the start of `data`
+        let layout = self.dev.get_image_subresource_layout(
+            image,
+            vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                array_layer: 0,
+            },
+        );
+        let reqs = self.dev.get_image_memory_requirements(image);
+        let bind_offset = match data_offset.checked_sub(layout.offset) {
+            Some(offset)
+                if layout.row_pitch == stride as u64 * texel_size as u64
+                    && offset % reqs.alignment == 0
+                    && offset + reqs.size <= import_size =>
+            {
+                offset
+            }
+            _ => {
+                log::debug!(
+                    "Can't sample host memory with stride {} in place, the driver wants {:?}",
+                    stride,
+                    layout
+                );
+                self.dev.destroy_image(image, None);
+                return Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED);
+            }
+        };
+
+        // Find the memory types this pointer can be imported as
+        let mut host_props = vk::MemoryHostPointerPropertiesEXT::builder().build();
+        let res = (self
+            .external_mem_host_loader
+            .get_memory_host_pointer_properties_ext)(
+            self.dev.handle(),
+            vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT,
+            import_start as *const std::ffi::c_void,
+            &mut host_props,
         );
+        let memtype_index = match res {
+            vk::Result::SUCCESS => {
+                Self::find_memtype_for_dmabuf(host_props.memory_type_bits, &self.mem_props, &reqs)
+            }
+            _ => None,
+        };
+        let memtype_index = match memtype_index {
+            Some(index) => index,
+            None => {
+                log::debug!("No memory type can import host memory: {:?}", res);
+                self.dev.destroy_image(image, None);
+                return Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED);
+            }
+        };
+
+        let mut import_info = vk::ImportMemoryHostPointerInfoEXT::builder()
+            .handle_type(vk::ExternalMemoryHandleTypeFlags::HOST_ALLOCATION_EXT)
+            .host_pointer(import_start as *mut std::ffi::c_void)
+            .build();
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(import_size)
+            .memory_type_index(memtype_index)
+            .push_next(&mut import_info)
+            .build();
+        let image_memory = match self.allocate_memory(&alloc_info) {
+            Ok(mem) => mem,
+            Err(e) => {
+                log::debug!("Could not import host memory: {:?}", e);
+                self.dev.destroy_image(image, None);
+                return Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED);
+            }
+        };
+        if let Err(e) = self.dev.bind_image_memory(image, image_memory, bind_offset) {
+            log::debug!("Could not bind host memory to image: {:?}", e);
+            self.dev.destroy_image(image, None);
+            self.free_memory(image_memory);
+            return Err(ThundrError::HOST_MEMORY_NOT_SUPPORTED);
+        }
+        let image_memory = Allocation::external(image_memory, import_size);
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image(image)
+            .format(image_info.format)
+            .components(format.get_components())
+            .view_type(vk::ImageViewType::TYPE_2D);
+        let view = self.dev.create_image_view(&view_info, None).unwrap();
+
+        self.acquire_host_memory_image(image);
+
+        let ret = self.create_image_common(
+            ImagePrivate::HostMemory,
+            &vk::Extent2D {
+                width: width,
+                height: height,
+            },
+            image,
+            image_memory,
+            view,
+            false,
+            release_info,
+        )?;
+        ret.i_internal.write().unwrap().i_format = format;
+
+        Ok(ret)
     }
 
     /// create_image_from_dmabuf
@@ -684,10 +1438,29 @@ impl Device {
         dmabuf: &Dmabuf,
         release_info: Option<Box<dyn Droppable + Send + Sync>>,
     ) -> Result<Image> {
-        let (image, view, image_memory) =
-            Device::create_image_from_dmabuf_internal(&self, dmabuf, vk::ImageUsageFlags::SAMPLED)?;
+        // Ask for the image to be a blit source if the modifier allows it,
+        // so it can be scaled into thumbnails
+        let blit_src = self.get_supported_drm_modifiers().iter().any(|m| {
+            m.drm_format_modifier == dmabuf.db_planes[0].db_mods
+                && m.drm_format_modifier_tiling_features.contains(
+                    vk::FormatFeatureFlags::BLIT_SRC
+                        | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+                )
+        });
+        let (image, view, image_memory) = match blit_src {
+            true => Device::create_image_from_dmabuf_internal(
+                self,
+                dmabuf,
+                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC,
+            )?,
+            false => Device::create_image_from_dmabuf_internal(
+                self,
+                dmabuf,
+                vk::ImageUsageFlags::SAMPLED,
+            )?,
+        };
 
-        return self.create_image_common(
+        let ret = self.create_image_common(
             ImagePrivate::Dmabuf,
             &vk::Extent2D {
                 width: dmabuf.db_width as u32,
@@ -698,7 +1471,227 @@ impl Device {
             view,
             true,
             release_info,
+        )?;
+        ret.i_internal.write().unwrap().i_blit_src = blit_src;
+
+        Ok(ret)
+    }
+
+    /// Create a scaled down copy of an image
+    ///
+    /// The copy is `width` by `height`, and has the same format as `src`.
+    /// It is a separate image, so it is only updated when
+    /// `update_scaled_copy` is called. This is used for thumbnails of
+    /// windows. ThundrError::INVALID is returned if `src` can't be
+    /// scaled, which is the case for images sampled from host memory and
+    /// for some dmabuf modifiers.
+    pub fn create_scaled_copy(&self, src: &Image, width: u32, height: u32) -> Result<Image> {
+        let (format, blit_src) = {
+            let internal = src.i_internal.read().unwrap();
+            (internal.i_format, internal.i_blit_src)
+        };
+        if !blit_src || width == 0 || height == 0 {
+            return Err(ThundrError::INVALID);
+        }
+
+        let res = vk::Extent2D {
+            width: width,
+            height: height,
+        };
+        let (image, view, img_mem) = self.create_mipmapped_image(
+            &res,
+            format.get_vk_format(),
+            format.get_components(),
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageTiling::OPTIMAL,
+            vk::SampleCountFlags::TYPE_1,
+            1,
         );
+
+        let ret = self.create_image_common(
+            ImagePrivate::MemImage,
+            &res,
+            image,
+            img_mem,
+            view,
+            false,
+            None,
+        )?;
+        ret.i_internal.write().unwrap().i_format = format;
+
+        self.update_scaled_copy(src, &ret)?;
+        Ok(ret)
+    }
+
+    /// Scale the current contents of `src` into `dst`
+    ///
+    /// `dst` must have been created from `src` with `create_scaled_copy`,
+    /// or from another image of the same format. The whole image is
+    /// downscaled with a linear blit on the copy queue.
+    pub fn update_scaled_copy(&self, src: &Image, dst: &Image) -> Result<()> {
+        let (src_format, blit_src, src_res) = {
+            let internal = src.i_internal.read().unwrap();
+            (
+                internal.i_format,
+                internal.i_blit_src,
+                internal.i_resolution,
+            )
+        };
+        let (dst_format, dst_res) = {
+            let internal = dst.i_internal.read().unwrap();
+            (internal.i_format, internal.i_resolution)
+        };
+        if !blit_src || src_format != dst_format {
+            return Err(ThundrError::INVALID);
+        }
+
+        let (src_image, src_mip_levels) = {
+            let image_vk = self.d_image_vk.get(&src.i_id).ok_or(ThundrError::INVALID)?;
+            (image_vk.iv_image, image_vk.iv_mip_levels)
+        };
+        let dst_image = self
+            .d_image_vk
+            .get(&dst.i_id)
+            .ok_or(ThundrError::INVALID)?
+            .iv_image;
+
+        // Frames in flight may be sampling these, and we are about to
+        // change their layouts
+        self.wait_for_latest_timeline();
+        self.wait_for_copy();
+
+        unsafe {
+            let int_lock = self.d_internal.clone();
+            let mut internal = int_lock.write().unwrap();
+            let copy_cbuf = internal.copy_cbuf;
+
+            self.cbuf_begin_recording(copy_cbuf, vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            if let Some(profiler) = internal.copy_profiler.as_mut() {
+                profiler.begin(&self.dev, copy_cbuf);
+            }
+
+            let barrier = |image: vk::Image,
+                           levels: u32,
+                           old_layout: vk::ImageLayout,
+                           new_layout: vk::ImageLayout,
+                           src_access: vk::AccessFlags,
+                           dst_access: vk::AccessFlags| {
+                vk::ImageMemoryBarrier::builder()
+                    .image(image)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1)
+                            .level_count(levels)
+                            .build(),
+                    )
+                    .build()
+            };
+
+            // The old contents of dst are all replaced, so they can be
+            // discarded
+            self.dev.cmd_pipeline_barrier(
+                copy_cbuf,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        src_image,
+                        src_mip_levels,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::SHADER_READ,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    barrier(
+                        dst_image,
+                        1,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::SHADER_READ,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build();
+            let corners = |res: &vk::Extent2D| {
+                [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: res.width as i32,
+                        y: res.height as i32,
+                        z: 1,
+                    },
+                ]
+            };
+            let blit = vk::ImageBlit::builder()
+                .src_subresource(subresource)
+                .src_offsets(corners(&src_res))
+                .dst_subresource(subresource)
+                .dst_offsets(corners(&dst_res))
+                .build();
+            self.dev.cmd_blit_image(
+                copy_cbuf,
+                src_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+
+            self.dev.cmd_pipeline_barrier(
+                copy_cbuf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        src_image,
+                        src_mip_levels,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                    barrier(
+                        dst_image,
+                        1,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                ],
+            );
+
+            if let Some(profiler) = internal.copy_profiler.as_mut() {
+                profiler.end(&self.dev, copy_cbuf);
+            }
+            self.cbuf_end_recording(copy_cbuf);
+        }
+
+        self.copy_cbuf_submit_async();
+        Ok(())
     }
 
     /// Update the `VkDescriptorImageInfo` entry in the image ECS for the renderer
@@ -714,12 +1707,12 @@ impl Device {
         private: ImagePrivate,
         res: &vk::Extent2D,
         image: vk::Image,
-        image_mem: vk::DeviceMemory,
+        image_mem: Allocation,
         view: vk::ImageView,
         is_dmabuf: bool,
         release: Option<Box<dyn Droppable + Send + Sync>>,
     ) -> Result<Image> {
-        let descriptor = self.create_new_image_descriptor(view);
+        let descriptor = self.create_new_image_descriptor(view, 1);
 
         let image_vk = Arc::new(ImageVk {
             // use our device's weak pointer to get an Arc
@@ -729,6 +1722,7 @@ impl Device {
             iv_image_view: view,
             iv_image_mem: image_mem,
             iv_image_resolution: *res,
+            iv_mip_levels: 1,
             iv_release_info: release,
             iv_desc: descriptor,
         });
@@ -738,6 +1732,11 @@ impl Device {
             i_priv: private,
             i_opaque: None,
             i_resolution: *res,
+            i_sampler_mode: SamplerMode::Linear,
+            i_color_space: ColorSpace::Srgb,
+            i_protected: false,
+            i_format: ShmFormat::ARGB8888,
+            i_blit_src: false,
         };
 
         // Add our vulkan resources to the ECS
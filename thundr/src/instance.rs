@@ -7,6 +7,7 @@ use ash::{vk, Entry};
 
 extern crate utils as cat5_utils;
 use crate::display::Display;
+use crate::platform::contains_extensions;
 use crate::CreateInfo;
 use cat5_utils::log;
 
@@ -112,8 +113,23 @@ impl Instance {
             .collect();
 
         let mut extension_names_raw = Display::extension_names(info);
+        // Only the VkSurface based displays need any extensions
+        let uses_vk_surface = !extension_names_raw.is_empty();
         extension_names_raw.push(ext::DebugUtils::name().as_ptr());
 
+        // Allow selecting HDR color spaces for VkSurfaces if possible
+        let colorspace_ext = [vk::ExtSwapchainColorspaceFn::name().as_ptr()];
+        if uses_vk_surface
+            && contains_extensions(
+                &entry
+                    .enumerate_instance_extension_properties(None)
+                    .unwrap_or_default(),
+                &colorspace_ext,
+            )
+        {
+            extension_names_raw.push(colorspace_ext[0]);
+        }
+
         let appinfo = vk::ApplicationInfo::builder()
             .application_name(&app_name)
             .application_version(0)
@@ -47,6 +47,7 @@
 //!         64, // width of texture
 //!         64, // height of texture
 //!         64, // stride
+//!         th::ShmFormat::ARGB8888,
 //!         None,
 //!     )
 //!     .unwrap();
@@ -85,6 +86,8 @@ use lluvia as ll;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
+mod allocator;
+mod color;
 mod damage;
 mod deletion_queue;
 mod descpool;
@@ -92,27 +95,46 @@ mod device;
 mod display;
 mod image;
 mod instance;
+mod memory;
+mod mesh;
 mod pipelines;
 mod platform;
+mod profiler;
+#[cfg(feature = "drm")]
+pub mod session;
+mod staging;
 mod surface;
+mod transform;
 
 #[cfg(test)]
 mod tests;
 
 #[cfg(feature = "sdl")]
 extern crate sdl2;
+#[cfg(feature = "winit")]
+extern crate winit;
 
 pub use self::image::Image;
-pub use self::image::{Dmabuf, DmabufPlane};
+pub use self::image::{Dmabuf, DmabufPlane, SamplerMode, ShmFormat};
+pub use allocator::AllocatorStats;
+pub use color::ColorSpace;
 pub use damage::Damage;
 pub(crate) use deletion_queue::DeletionQueue;
 pub use device::Device;
 #[cfg(feature = "drm")]
 use display::drm::DrmSwapchain;
-pub use display::{frame::FrameRenderer, Display, DisplayInfoPayload};
+pub use display::{
+    frame::FrameRenderer, Display, DisplayInfoPayload, PresentMode, PresentationTime, ResizeMode,
+    SubpixelLayout,
+};
 use display::{headless::HeadlessSwapchain, vkswapchain::VkSwapchain};
 use instance::Instance;
-pub use surface::Surface;
+pub use memory::{HeapUsage, LowMemoryCallback, MemoryUsage};
+pub use mesh::{Mesh, MeshVertex};
+pub use pipelines::lut::ColorLut;
+pub use profiler::GpuFrameTimings;
+pub use surface::{Gradient, GradientKind, Surface};
+pub use transform::Transform;
 
 // Re-export some things from utils so clients
 // can use them
@@ -173,8 +195,18 @@ pub enum ThundrError {
     INVALID_DMABUF,
     #[error("Stride does not match dimensions and size of image data")]
     INVALID_STRIDE,
+    #[error("Could not parse color correction profile")]
+    INVALID_COLOR_PROFILE,
     #[error("Input error")]
     IOERROR,
+    #[error("The Vulkan device was lost and must be recreated")]
+    DEVICE_LOST,
+    #[error("The Vulkan device does not support timestamp queries")]
+    TIMESTAMPS_NOT_SUPPORTED,
+    #[error("The Vulkan device can not export sync files")]
+    SYNC_FILE_NOT_SUPPORTED,
+    #[error("This memory can not be imported into the Vulkan device")]
+    HOST_MEMORY_NOT_SUPPORTED,
 }
 
 impl From<std::io::Error> for ThundrError {
@@ -276,6 +308,9 @@ pub enum SurfaceType {
     Display,
     #[cfg(feature = "sdl")]
     SDL2,
+    /// A winit window on the window system with this display handle
+    #[cfg(feature = "winit")]
+    Winit(raw_window_handle::RawDisplayHandle),
 }
 
 pub enum WindowInfo<'a> {
@@ -292,6 +327,8 @@ pub enum WindowInfo<'a> {
     Display,
     #[cfg(feature = "sdl")]
     SDL2(&'a sdl2::VideoSubsystem, &'a sdl2::video::Window),
+    #[cfg(feature = "winit")]
+    Winit(&'a Arc<winit::window::Window>),
 }
 
 /// Parameters for Thundr creation.
@@ -312,6 +349,18 @@ pub struct CreateInfo<'a> {
     /// particular information about the target virtual/physical display
     /// region.
     pub payload: Option<Arc<dyn DisplayInfoPayload>>,
+    /// The number of samples per pixel to use when drawing. Values
+    /// above 1 enable MSAA. If the device does not support this many
+    /// samples the next lower supported count will be used.
+    pub sample_count: u32,
+    /// The color space to render in. If the display does not support
+    /// this color space then sRGB will be used.
+    pub color_space: ColorSpace,
+    /// The subpixel layout of the display, used for drawing text
+    pub subpixel_layout: SubpixelLayout,
+    /// The number of threads to record large frames on. Frames are
+    /// recorded on the calling thread if this is one or less.
+    pub record_threads: u32,
 }
 
 impl<'a> CreateInfo<'a> {
@@ -321,6 +370,10 @@ impl<'a> CreateInfo<'a> {
                 surface_type: SurfaceType::Headless,
                 window_info: WindowInfo::Invalid(PhantomData),
                 payload: None,
+                sample_count: 1,
+                color_space: ColorSpace::Srgb,
+                subpixel_layout: SubpixelLayout::None,
+                record_threads: 1,
             },
         }
     }
@@ -350,6 +403,30 @@ impl<'a> CreateInfoBuilder<'a> {
         self
     }
 
+    /// Use this many samples per pixel for anti-aliasing, i.e. 4 or 8
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.ci.sample_count = count;
+        self
+    }
+
+    /// Render in this color space if the display supports it
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.ci.color_space = color_space;
+        self
+    }
+
+    /// The arrangement of the display's subpixels, for subpixel text
+    pub fn subpixel_layout(mut self, layout: SubpixelLayout) -> Self {
+        self.ci.subpixel_layout = layout;
+        self
+    }
+
+    /// Record large frames in parallel on this many threads
+    pub fn record_threads(mut self, count: u32) -> Self {
+        self.ci.record_threads = count;
+        self
+    }
+
     pub fn build(self) -> CreateInfo<'a> {
         self.ci
     }
@@ -413,6 +490,8 @@ impl Thundr {
         match &info.surface_type {
             #[cfg(feature = "sdl")]
             SurfaceType::SDL2 => VkSwapchain::get_display_info_list(&self.th_primary_dev),
+            #[cfg(feature = "winit")]
+            SurfaceType::Winit(_) => VkSwapchain::get_display_info_list(&self.th_primary_dev),
             SurfaceType::Headless => HeadlessSwapchain::get_display_info_list(&self.th_primary_dev),
             _ => {
                 // In the case of DRM and VK_KHR_Display we want to create an
@@ -449,6 +528,7 @@ impl Thundr {
     }
 
     /// Update an existing image from a shm buffer
+    #[allow(clippy::too_many_arguments)]
     pub fn update_image_from_bits(
         &mut self,
         image: &Image,
@@ -456,10 +536,11 @@ impl Thundr {
         width: u32,
         height: u32,
         stride: u32,
+        format: ShmFormat,
         damage: Option<Damage>,
         release: Option<Box<dyn Droppable + Send + Sync>>,
     ) -> Result<()> {
         self.th_primary_dev
-            .update_image_from_bits(image, data, width, height, stride, damage, release)
+            .update_image_from_bits(image, data, width, height, stride, format, damage, release)
     }
 }
@@ -0,0 +1,135 @@
+// GPU memory accounting
+//
+// Device records every allocation it makes so that we know how much
+// of each memory heap Thundr is using. If VK_EXT_memory_budget is
+// available the driver also tells us how much we are allowed to use,
+// and the application is notified when we get close so that it can
+// free cached images before allocations start failing.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+use std::collections::HashMap;
+
+/// The fraction of a heap's budget at which we report low memory
+const LOW_MEMORY_THRESHOLD: f64 = 0.9;
+
+/// Called when a Device is running low on memory
+///
+/// This is called at the start of a frame, so it is safe for the callback
+/// to drop images and other Thundr resources.
+pub type LowMemoryCallback = Box<dyn FnMut(&MemoryUsage) + Send + Sync>;
+
+/// Memory usage of one Vulkan memory heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapUsage {
+    /// Is this heap VRAM
+    pub hu_device_local: bool,
+    /// Bytes allocated from this heap by Thundr
+    pub hu_allocated: u64,
+    /// Bytes of this heap in use by this process, as reported by the
+    /// driver. This is the same as `hu_allocated` if VK_EXT_memory_budget
+    /// is not supported.
+    pub hu_usage: u64,
+    /// Bytes of this heap this process may use before allocations may
+    /// fail. This is the size of the heap if VK_EXT_memory_budget is not
+    /// supported.
+    pub hu_budget: u64,
+}
+
+impl HeapUsage {
+    /// Is our usage of this heap close to its budget
+    pub fn is_near_budget(&self) -> bool {
+        self.hu_usage as f64 >= self.hu_budget as f64 * LOW_MEMORY_THRESHOLD
+    }
+}
+
+/// A snapshot of a Device's memory usage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Usage of each heap, indexed by the Vulkan heap index
+    pub mu_heaps: Vec<HeapUsage>,
+}
+
+impl MemoryUsage {
+    /// Is our usage of any heap close to its budget
+    pub fn is_near_budget(&self) -> bool {
+        self.mu_heaps.iter().any(|heap| heap.is_near_budget())
+    }
+
+    /// Total bytes allocated by Thundr across all heaps
+    pub fn total_allocated(&self) -> u64 {
+        self.mu_heaps.iter().map(|heap| heap.hu_allocated).sum()
+    }
+}
+
+/// Tracks the allocations made on a Device
+pub(crate) struct MemoryTracker {
+    /// The heap index and size of each live allocation
+    mt_allocations: HashMap<vk::DeviceMemory, (usize, u64)>,
+    /// Bytes allocated from each heap
+    mt_heap_allocated: Vec<u64>,
+    /// Have we already told the application we are low on memory. This
+    /// is cleared once usage drops back below the threshold, so that
+    /// callbacks are only called once each time we run low.
+    mt_near_budget: bool,
+    /// Callbacks to run when we are low on memory
+    mt_callbacks: Vec<LowMemoryCallback>,
+}
+
+impl MemoryTracker {
+    pub fn new(mem_props: &vk::PhysicalDeviceMemoryProperties) -> Self {
+        Self {
+            mt_allocations: HashMap::new(),
+            mt_heap_allocated: vec![0; mem_props.memory_heap_count as usize],
+            mt_near_budget: false,
+            mt_callbacks: Vec::new(),
+        }
+    }
+
+    /// Record a new allocation of `size` bytes from `heap`
+    pub fn add_allocation(&mut self, mem: vk::DeviceMemory, heap: usize, size: u64) {
+        self.mt_heap_allocated[heap] += size;
+        self.mt_allocations.insert(mem, (heap, size));
+    }
+
+    /// Record that an allocation has been freed
+    pub fn remove_allocation(&mut self, mem: vk::DeviceMemory) {
+        if let Some((heap, size)) = self.mt_allocations.remove(&mem) {
+            self.mt_heap_allocated[heap] -= size;
+        }
+    }
+
+    /// Bytes allocated from `heap`
+    pub fn get_allocated(&self, heap: usize) -> u64 {
+        self.mt_heap_allocated[heap]
+    }
+
+    pub fn add_callback(&mut self, callback: LowMemoryCallback) {
+        self.mt_callbacks.push(callback);
+    }
+
+    /// Update whether we are near our budget
+    ///
+    /// If we have just crossed the threshold then the callbacks are
+    /// removed from the tracker and returned so that they can be called
+    /// without holding any locks. They must be given back with
+    /// `restore_callbacks`.
+    pub fn update_near_budget(&mut self, near_budget: bool) -> Option<Vec<LowMemoryCallback>> {
+        let crossed = near_budget && !self.mt_near_budget;
+        self.mt_near_budget = near_budget;
+
+        match crossed {
+            true => Some(std::mem::take(&mut self.mt_callbacks)),
+            false => None,
+        }
+    }
+
+    /// Return callbacks taken by `update_near_budget`
+    ///
+    /// Any callbacks registered while they were being run are kept.
+    pub fn restore_callbacks(&mut self, mut callbacks: Vec<LowMemoryCallback>) {
+        callbacks.append(&mut self.mt_callbacks);
+        self.mt_callbacks = callbacks;
+    }
+}
@@ -0,0 +1,124 @@
+// A `Mesh` is a set of triangles drawn with an image or color
+//
+// Surfaces can only draw rectangles. Meshes are for everything else,
+// such as vector graphics or windows being warped by an effect. They
+// are drawn with the same shading as Surfaces: the image is sampled at
+// each vertex's texture coordinates, and the color replaces the image's
+// colors while keeping its alpha.
+//
+// Austin Shafer - 2024
+use crate::{Result, ThundrError};
+use utils::region::Rect;
+
+/// One corner of a triangle in a Mesh
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct MeshVertex {
+    /// The position in pixels, relative to the position of the Mesh
+    pub mv_pos: (f32, f32),
+    /// The point in the image to sample, normalized to the image size
+    pub mv_uv: (f32, f32),
+}
+
+impl MeshVertex {
+    pub fn new(pos: (f32, f32), uv: (f32, f32)) -> Self {
+        Self {
+            mv_pos: pos,
+            mv_uv: uv,
+        }
+    }
+}
+
+/// Arbitrary 2D geometry to draw
+#[derive(PartialEq, Debug, Clone)]
+pub struct Mesh {
+    /// The screen position the vertices are relative to
+    pub(crate) m_pos: (i32, i32),
+    pub(crate) m_vertices: Vec<MeshVertex>,
+    /// Each group of three indices into `m_vertices` is one triangle
+    pub(crate) m_indices: Vec<u32>,
+    pub(crate) m_color: Option<(f32, f32, f32, f32)>,
+    /// Multiplied with the alpha of the mesh contents
+    pub(crate) m_opacity: f32,
+}
+
+impl Mesh {
+    /// Create a mesh from a list of triangles
+    ///
+    /// Each group of three `indices` is a triangle made from those
+    /// `vertices`. Returns ThundrError::INVALID if there is a partial
+    /// triangle or an index is out of bounds.
+    pub fn new(
+        vertices: Vec<MeshVertex>,
+        indices: Vec<u32>,
+        color: Option<(f32, f32, f32, f32)>,
+    ) -> Result<Self> {
+        if !indices.len().is_multiple_of(3) || indices.iter().any(|i| *i as usize >= vertices.len())
+        {
+            return Err(ThundrError::INVALID);
+        }
+
+        Ok(Self {
+            m_pos: (0, 0),
+            m_vertices: vertices,
+            m_indices: indices,
+            m_color: color,
+            m_opacity: 1.0,
+        })
+    }
+
+    pub fn get_pos(&self) -> (i32, i32) {
+        self.m_pos
+    }
+
+    pub fn set_pos(&mut self, x: i32, y: i32) {
+        self.m_pos = (x, y);
+    }
+
+    pub fn get_vertices(&self) -> &[MeshVertex] {
+        &self.m_vertices
+    }
+
+    pub fn get_indices(&self) -> &[u32] {
+        &self.m_indices
+    }
+
+    pub fn get_color(&self) -> Option<(f32, f32, f32, f32)> {
+        self.m_color
+    }
+
+    pub fn set_color(&mut self, color: (f32, f32, f32, f32)) {
+        self.m_color = Some(color);
+    }
+
+    pub fn get_opacity(&self) -> f32 {
+        self.m_opacity
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.m_opacity = opacity;
+    }
+
+    /// Get the area of the screen covered by this mesh
+    ///
+    /// Returns None if the mesh has no triangles.
+    pub fn get_bounding_box(&self) -> Option<Rect<i32>> {
+        let mut verts = self
+            .m_indices
+            .iter()
+            .map(|i| self.m_vertices[*i as usize].mv_pos);
+        let first = verts.next()?;
+        let (x0, y0, x1, y1) = verts.fold((first.0, first.1, first.0, first.1), |b, p| {
+            (b.0.min(p.0), b.1.min(p.1), b.2.max(p.0), b.3.max(p.1))
+        });
+
+        let (x0, y0) = (x0.floor() as i32, y0.floor() as i32);
+        let (x1, y1) = (x1.ceil() as i32, y1.ceil() as i32);
+        Some(Rect::new(
+            self.m_pos.0 + x0,
+            self.m_pos.1 + y0,
+            x1 - x0,
+            y1 - y0,
+        ))
+    }
+}
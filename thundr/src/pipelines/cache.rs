@@ -0,0 +1,107 @@
+// Persistent pipeline cache
+//
+// Some drivers take a long time to compile our pipelines, which slows
+// down startup and the first frame of every new Display. Vulkan can
+// hand us the compiled pipelines in a VkPipelineCache, which we save
+// to `$XDG_CACHE_HOME/thundr` and load the next time the same GPU is
+// used.
+//
+// The driver checks that the saved data matches its version and the
+// GPU, and ignores it if not. If the cache can't be read or written
+// we just compile everything again.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+use crate::{Result, ThundrError};
+use utils::log;
+
+use std::path::PathBuf;
+
+/// A VkPipelineCache backed by a file
+pub(crate) struct PipelineCache {
+    pub pc_cache: vk::PipelineCache,
+    /// Where the cache is saved. None if there is no cache directory.
+    pc_path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    /// Get the directory to save pipeline caches in
+    ///
+    /// This is `$XDG_CACHE_HOME/thundr`, or `~/.cache/thundr`.
+    fn cache_dir() -> Option<PathBuf> {
+        let base = match std::env::var("XDG_CACHE_HOME") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".cache"),
+        };
+        Some(base.join("thundr"))
+    }
+
+    /// Create a pipeline cache, loading any saved data for this GPU
+    pub fn new(dev: &ash::Device, props: &vk::PhysicalDeviceProperties) -> Self {
+        // Name the file after the GPU so that switching between GPUs
+        // doesn't throw away the other's cache
+        let path = Self::cache_dir().map(|dir| {
+            dir.join(format!(
+                "pipelines-{:04x}-{:04x}.bin",
+                props.vendor_id, props.device_id
+            ))
+        });
+        let data = path
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .unwrap_or_default();
+
+        let info = vk::PipelineCacheCreateInfo::builder().initial_data(&data);
+        let cache = match unsafe { dev.create_pipeline_cache(&info, None) } {
+            Ok(cache) => cache,
+            Err(e) => {
+                // Drivers should ignore bad data, but some don't
+                log::error!("Could not load pipeline cache, starting over: {:?}", e);
+                let info = vk::PipelineCacheCreateInfo::builder();
+                unsafe { dev.create_pipeline_cache(&info, None).unwrap() }
+            }
+        };
+
+        Self {
+            pc_cache: cache,
+            pc_path: path,
+        }
+    }
+
+    /// Write the current contents of the cache to disk
+    ///
+    /// The file is replaced atomically, so that a crash while saving
+    /// can't leave a partial cache behind.
+    pub fn save(&self, dev: &ash::Device) -> Result<()> {
+        let path = match self.pc_path.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let data = unsafe {
+            dev.get_pipeline_cache_data(self.pc_cache)
+                .or(Err(ThundrError::INVALID))?
+        };
+
+        let tmp = path.with_extension("tmp");
+        let ret = path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| std::fs::write(&tmp, &data))
+            .and_then(|_| std::fs::rename(&tmp, path));
+
+        if let Err(e) = ret {
+            log::error!("Could not save pipeline cache to {:?}: {:?}", path, e);
+            return Err(ThundrError::IOERROR);
+        }
+        log::debug!("Saved {} bytes of pipeline cache to {:?}", data.len(), path);
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, dev: &ash::Device) {
+        unsafe { dev.destroy_pipeline_cache(self.pc_cache, None) };
+    }
+}
@@ -14,10 +14,14 @@ use std::sync::Arc;
 
 use ash::{util, vk};
 
+use super::lut::{ColorLut, LutPass};
+use super::record::{split_ops, DrawOp, RecordContext, RecordPool};
 use super::Pipeline;
-use crate::display::frame::{PushConstants, RecordParams};
+use crate::allocator::Allocation;
+use crate::display::frame::{RecordParams, SurfaceData};
 use crate::display::DisplayState;
-use crate::{Device, Image, Result, Surface, Viewport};
+use crate::profiler::{FrameProfiler, GpuFrameTimings};
+use crate::{ColorSpace, Device, GradientKind, Image, Mesh, Result, Surface};
 use utils::{log, region::Rect};
 
 // This is the reference data for a normal quad
@@ -43,6 +47,49 @@ static QUAD_DATA: [VertData; 4] = [
 
 static QUAD_INDICES: [Vector3<u32>; 2] = [Vector3::new(1, 2, 3), Vector3::new(1, 4, 2)];
 
+/// The number of surfaces the first storage buffer of a frame can hold
+const SURFACE_BUFFER_INITIAL_CAPACITY: usize = 1024;
+/// The most surface storage buffers that may exist at once
+const SURFACE_BUFFER_MAX_SETS: u32 = 64;
+/// The number of vertices the first mesh buffer of a frame can hold
+const MESH_BUFFER_INITIAL_VERTS: usize = 1024;
+/// The number of indices the first mesh buffer of a frame can hold
+const MESH_BUFFER_INITIAL_INDICES: usize = 3072;
+/// The fewest draw ops worth handing to a record thread
+const RECORD_CHUNK_MIN_OPS: usize = 256;
+
+/// A storage buffer holding the SurfaceData of part of a frame
+///
+/// Frames start with one of these. If it fills up while recording then
+/// another twice as large is added, and the next frame for this swapchain
+/// image will use one buffer big enough for all of them.
+struct SurfaceBuffer {
+    sb_buffer: vk::Buffer,
+    sb_mem: Allocation,
+    sb_desc: vk::DescriptorSet,
+    /// The number of surfaces this can hold
+    sb_capacity: usize,
+    /// The index of the first surface in this buffer
+    sb_first: usize,
+}
+
+/// Vertex and index buffers holding the meshes of part of a frame
+///
+/// These grow the same way as SurfaceBuffers. Frames which don't draw
+/// any meshes don't have one.
+struct MeshBuffer {
+    mb_verts: vk::Buffer,
+    mb_verts_mem: Allocation,
+    mb_indices: vk::Buffer,
+    mb_indices_mem: Allocation,
+    /// The number of vertices and indices this can hold
+    mb_vert_capacity: usize,
+    mb_index_capacity: usize,
+    /// The first vertex and index of this frame held in this buffer
+    mb_first_vert: usize,
+    mb_first_index: usize,
+}
+
 /// an application specific set of resources to draw.
 ///
 /// These are the "dynamic" parts of our application. The things
@@ -59,6 +106,9 @@ static QUAD_INDICES: [Vector3<u32>; 2] = [Vector3::new(1, 2, 3), Vector3::new(1,
 pub struct GeomPipeline {
     g_dev: Arc<Device>,
     pass: vk::RenderPass,
+    /// The same as `pass`, but keeps the existing contents of the image.
+    /// This is used to only redraw the damaged part of a frame.
+    g_load_pass: vk::RenderPass,
     pipeline: vk::Pipeline,
     pipeline_layout: vk::PipelineLayout,
     /// Pool for command buffers
@@ -77,17 +127,67 @@ pub struct GeomPipeline {
     framebuffers: Vec<vk::Framebuffer>,
     /// shader constants are shared by all swapchain images
     uniform_buffer: vk::Buffer,
-    uniform_buffers_memory: vk::DeviceMemory,
+    uniform_buffers_memory: Allocation,
     /// We will hold only one copy of the static QUAD_DATA
     /// which represents an onscreen window.
     vert_buffer: vk::Buffer,
-    vert_buffer_memory: vk::DeviceMemory,
+    vert_buffer_memory: Allocation,
     vert_count: u32,
     /// Resources for the index buffer
     index_buffer: vk::Buffer,
-    index_buffer_memory: vk::DeviceMemory,
+    index_buffer_memory: Allocation,
     /// Placeholder image for when the surface doesn't have one
     tmp_image: Option<Image>,
+    /// The image descriptor set currently bound in this frame's cbuf.
+    /// This is null if nothing has been bound yet.
+    g_bound_desc: vk::DescriptorSet,
+    /// Number of samples per pixel we render with
+    g_samples: vk::SampleCountFlags,
+    /// Multisampled color image that is resolved into the swapchain
+    /// image. This is only used if g_samples is more than one.
+    g_msaa_image: Option<(vk::Image, vk::ImageView, Allocation)>,
+    /// Color correction applied after drawing. If this is set we
+    /// draw into its frame instead of the swapchain image.
+    g_lut: Option<LutPass>,
+    /// Layout of the storage buffers holding SurfaceData (set 2)
+    g_surf_desc_layout: vk::DescriptorSetLayout,
+    g_surf_desc_pool: vk::DescriptorPool,
+    /// The SurfaceData storage buffers for each swapchain image
+    g_surf_bufs: Vec<Vec<SurfaceBuffer>>,
+    /// The surfaces recorded in this frame. These are copied into the
+    /// storage buffers when the frame is finished.
+    g_surfs: Vec<SurfaceData>,
+    /// The first surface which has not been drawn yet. Surfaces sharing
+    /// the same descriptors are drawn together with one instanced draw.
+    g_batch_start: usize,
+    /// The storage buffer descriptor set currently bound in this frame's
+    /// cbuf. This is null if nothing has been bound yet.
+    g_bound_surf_desc: vk::DescriptorSet,
+    /// Pipeline for subpixel text, which blends each color channel
+    /// separately. This is None if dual source blending isn't supported.
+    g_subpixel_pipeline: Option<vk::Pipeline>,
+    /// The pipeline currently bound in this frame's cbuf
+    g_bound_pipeline: vk::Pipeline,
+    /// The mesh vertex and index buffers for each swapchain image
+    g_mesh_bufs: Vec<Vec<MeshBuffer>>,
+    /// The mesh vertices and indices recorded in this frame. Like
+    /// `g_surfs` these are copied into the buffers at the end of the frame.
+    g_mesh_verts: Vec<VertData>,
+    g_mesh_indices: Vec<u32>,
+    /// Timestamps for each frame. This is only set while GPU profiling
+    /// is turned on.
+    g_profiler: Option<FrameProfiler>,
+    /// The Device timeline point signaled once the last frame finishes
+    pub(crate) g_last_frame_point: u64,
+    /// The commands recorded in this frame. These are recorded into the
+    /// cbuf once the frame is finished, see `record_ops`.
+    g_ops: Vec<DrawOp>,
+    /// The render pass and area drawn in this frame
+    g_render_pass: vk::RenderPass,
+    g_render_area: vk::Rect2D,
+    /// Threads for recording large frames in parallel. This is None if
+    /// frames are only recorded on this thread.
+    g_record_pool: Option<RecordPool>,
 }
 
 /// Contiains a vertex and all its related data
@@ -109,8 +209,11 @@ struct VertData {
 #[repr(C)]
 struct ShaderConstants {
     pub model: Matrix4<f32>,
-    pub width: u32,
-    pub height: u32,
+    /// The size of the images being drawn to
+    pub width: f32,
+    pub height: f32,
+    /// The ColorSpace of the output we are drawing to
+    pub color_space: i32,
 }
 
 impl Pipeline for GeomPipeline {
@@ -119,111 +222,67 @@ impl Pipeline for GeomPipeline {
     /// Each framebuffer has a set of resources, including command
     /// buffers. This records the cbufs for the framebuffer
     /// specified by `img`.
-    fn begin_record(&mut self, dstate: &DisplayState) {
-        // we need to clear any existing data when we start a pass
-        let clear_vals = [vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 0.0],
-            },
-        }];
-
-        // We want to start a render pass to hold all of
-        // our drawing. The actual pass is started in the cbuf
-        let pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.pass)
-            .framebuffer(self.framebuffers[dstate.d_current_image as usize])
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: dstate.d_resolution,
-            })
-            .clear_values(&clear_vals);
-
-        let cbuf = self.g_cbufs[dstate.d_current_image as usize];
-
-        unsafe {
-            // start the cbuf
-            self.g_dev
-                .cbuf_begin_recording(cbuf, vk::CommandBufferUsageFlags::SIMULTANEOUS_USE);
-
-            // -- Setup static drawing resources
-            // All of our drawing operations need
-            // to be recorded inside a render pass.
-            self.g_dev.dev.cmd_begin_render_pass(
-                cbuf,
-                &pass_begin_info,
-                vk::SubpassContents::INLINE,
-            );
-
-            self.g_dev
-                .dev
-                .cmd_bind_pipeline(cbuf, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
-
-            // bind the vertex and index buffers from
-            // the first image
-            self.g_dev.dev.cmd_bind_vertex_buffers(
-                cbuf,                // cbuf to draw in
-                0,                   // first vertex binding updated by the command
-                &[self.vert_buffer], // set of buffers to bind
-                &[0],                // offsets for the above buffers
-            );
-            self.g_dev.dev.cmd_bind_index_buffer(
-                cbuf,
-                self.index_buffer,
-                0, // offset
-                vk::IndexType::UINT32,
-            );
-        }
-    }
-
-    /// Set the viewport
-    ///
-    /// This restricts the draw operations to within the specified region
-    fn set_viewport(&mut self, dstate: &DisplayState, viewport: &Viewport) -> Result<()> {
-        let cbuf = self.g_cbufs[dstate.d_current_image as usize];
-
-        unsafe {
-            log::info!("Viewport is : {:?}", viewport);
-
-            // Reset our viewport, but always keep it consistent to the overall
-            // window size. Otherwise this will transform our viewport content
-            // which we do not want
-            self.g_dev.dev.cmd_set_viewport(
-                cbuf,
-                0,
-                &[vk::Viewport {
-                    x: 0.0,
-                    y: 0.0,
-                    width: dstate.d_resolution.width as f32,
-                    height: dstate.d_resolution.height as f32,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                }],
-            );
-            // Set the new scissor. This obeys our th::Viewport requested region
-            // and is what actually controls the content clipping
-            self.g_dev.dev.cmd_set_scissor(
-                cbuf,
-                0,
-                &[vk::Rect2D {
+    fn begin_record(&mut self, dstate: &DisplayState, damage: Option<&Rect<i32>>) {
+        // When only redrawing the damage we keep the old contents
+        // and limit the pass to the damaged region
+        let (pass, render_area) = match damage {
+            Some(damage) => (
+                self.g_load_pass,
+                vk::Rect2D {
                     offset: vk::Offset2D {
-                        x: viewport.offset.0 as i32,
-                        y: viewport.offset.1 as i32,
+                        x: damage.r_pos.0.max(0),
+                        y: damage.r_pos.1.max(0),
                     },
                     extent: vk::Extent2D {
-                        width: viewport.size.0 as u32,
-                        height: viewport.size.1 as u32,
+                        width: damage.r_size.0.max(0) as u32,
+                        height: damage.r_size.1.max(0) as u32,
                     },
-                }],
-            );
+                },
+            ),
+            None => (
+                self.pass,
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: dstate.d_resolution,
+                },
+            ),
+        };
+
+        // The render pass is started once the frame is finished,
+        // when we know how its commands will be recorded
+        self.g_render_pass = pass;
+        self.g_render_area = render_area;
+
+        let cbuf = self.g_cbufs[dstate.d_current_image as usize];
+        self.g_bound_desc = vk::DescriptorSet::null();
+        self.g_bound_pipeline = vk::Pipeline::null();
+        self.reset_surf_bufs(dstate);
+        self.reset_mesh_bufs(dstate);
+
+        // start the cbuf
+        self.g_dev
+            .cbuf_begin_recording(cbuf, vk::CommandBufferUsageFlags::SIMULTANEOUS_USE);
+        if let Some(profiler) = self.g_profiler.as_mut() {
+            profiler.begin_frame(cbuf, dstate.d_current_image as usize, dstate.d_frame);
         }
 
-        Ok(())
+        self.g_ops.clear();
+        // The load pass doesn't clear for us, so clear the damaged
+        // region before drawing over it
+        if damage.is_some() {
+            self.g_ops.push(DrawOp::Clear(render_area));
+        }
+        // Surfaces are all drawn with the quad geometry
+        self.g_ops
+            .push(DrawOp::BindGeometry(self.vert_buffer, self.index_buffer));
     }
 
     /// Our implementation of drawing one Surface
     ///
-    /// This binds any resources for the surface's image and loads its
-    /// data into the push constants. We can then draw the surface.
+    /// This binds any resources for the surface's image and adds its
+    /// data to the current batch. Surfaces are drawn when the batch is
+    /// flushed, which happens when the next surface needs different
+    /// descriptors or when the drawing state changes.
     fn draw(
         &mut self,
         params: &mut RecordParams,
@@ -231,86 +290,109 @@ impl Pipeline for GeomPipeline {
         surface: &Surface,
         image: Option<&Image>,
     ) -> bool {
-        let cbuf = self.g_cbufs[dstate.d_current_image as usize];
-
-        // update our cbuf constants. This is how we pass in
+        // update our surface data. This is how we pass in
         // the viewport information
-        self.update_surf_push_constants(surface, image, params);
+        self.update_surf_data(surface, image, params);
 
         // If this surface has no content then skip drawing it
-        let mut num_contents = (params.push.image_id >= 0) as i32;
-        num_contents += params.push.use_color;
+        let mut num_contents = (params.surf.image_id >= 0) as i32;
+        num_contents += params.surf.use_color;
         if num_contents == 0 {
             return true;
         }
 
-        // if we have an image bound to this surface grab its descriptor from the
-        // imagevk. If not, then use the default tmp image
-        let image_desc = {
-            let imagevk = params
-                .image_vk
-                .get(match image {
-                    Some(img) => &img.i_id,
-                    None => &self.tmp_image.as_ref().unwrap().i_id,
-                })
-                .expect("Image does not have ImageVK");
-
-            assert!(imagevk.iv_desc.d_set != vk::DescriptorSet::null());
-            imagevk.iv_desc.d_set
-        };
+        let image_desc = self.get_image_desc(params, image);
 
         // TODO: If this surface is not contained in the viewport then don't draw it
 
-        unsafe {
-            // Bind this surface's backing texture if it has one. Descriptor
-            // sets can be updated elsewhere, but they must be bound before drawing
-            //
-            // We need to bind both the uniform set, and the per-Image
-            // set for the image sampler
-            self.g_dev.dev.cmd_bind_descriptor_sets(
-                cbuf,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout,
-                0, // first set
-                &[self.g_desc, image_desc],
-                &[], // dynamic offsets
-            );
+        let surf_desc = self.get_surf_desc(dstate);
 
-            self.g_dev.dev.cmd_push_constants(
-                cbuf,
-                self.pipeline_layout,
-                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                0, // offset
-                // Get the raw bytes for our push constants without doing any
-                // expensinve serialization
-                std::slice::from_raw_parts(
-                    &params.push as *const _ as *const u8,
-                    std::mem::size_of::<PushConstants>(),
-                ),
-            );
+        // Subpixel text needs its own blending
+        let pipeline = match (surface.s_subpixel, self.g_subpixel_pipeline) {
+            (true, Some(subpixel_pipeline)) => subpixel_pipeline,
+            _ => self.pipeline,
+        };
+        self.bind_state(dstate, pipeline, image_desc, surf_desc);
 
-            // Draw this surface
-            self.g_dev.dev.cmd_draw_indexed(
-                cbuf,            // drawing command buffer
-                self.vert_count, // number of verts
-                1,               // number of instances
-                0,               // first vertex
-                0,               // vertex offset
-                0,               // first instance
-            );
-            log::info!("Drawing surface at {:?}", surface.s_rect);
-        }
+        self.g_surfs.push(params.surf);
+        log::info!("Drawing surface at {:?}", surface.s_rect);
 
         return true;
     }
 
+    /// Our implementation of drawing a Mesh
+    ///
+    /// Meshes are drawn with the same shaders as surfaces, as a one pixel
+    /// surface at the mesh position whose vertices are in pixels. The
+    /// vertices are added to this frame's mesh buffer, and the mesh is
+    /// drawn immediately instead of being batched.
+    fn draw_mesh(
+        &mut self,
+        params: &mut RecordParams,
+        dstate: &DisplayState,
+        mesh: &Mesh,
+        image: Option<&Image>,
+    ) {
+        self.update_mesh_data(mesh, image, params);
+        if params.surf.image_id < 0 && params.surf.use_color == 0 {
+            return;
+        }
+
+        let image_desc = self.get_image_desc(params, image);
+        let surf_desc = self.get_surf_desc(dstate);
+        self.bind_state(dstate, self.pipeline, image_desc, surf_desc);
+        // Surfaces recorded before this need to be drawn first
+        self.flush_batch(dstate);
+        self.reserve_mesh_buffer(dstate, mesh.m_vertices.len(), mesh.m_indices.len());
+
+        let index = dstate.d_current_image as usize;
+        let mesh_buf = self.g_mesh_bufs[index].last().unwrap();
+        let surf_buf = self.g_surf_bufs[index].last().unwrap();
+        self.g_ops
+            .push(DrawOp::BindGeometry(mesh_buf.mb_verts, mesh_buf.mb_indices));
+        self.g_ops.push(DrawOp::Draw {
+            index_count: mesh.m_indices.len() as u32,
+            instance_count: 1,
+            // The mesh's indices start at zero, so offset them to
+            // where its vertices are in the buffer
+            first_index: (self.g_mesh_indices.len() - mesh_buf.mb_first_index) as u32,
+            vertex_offset: (self.g_mesh_verts.len() - mesh_buf.mb_first_vert) as i32,
+            first_instance: (self.g_surfs.len() - surf_buf.sb_first) as u32,
+        });
+        // Go back to the quad used by surfaces
+        self.g_ops
+            .push(DrawOp::BindGeometry(self.vert_buffer, self.index_buffer));
+
+        self.g_mesh_verts
+            .extend(mesh.m_vertices.iter().map(|v| VertData {
+                vertex: Vector2::new(v.mv_pos.0, v.mv_pos.1),
+                tex: Vector2::new(v.mv_uv.0, v.mv_uv.1),
+            }));
+        self.g_mesh_indices.extend_from_slice(&mesh.m_indices);
+        self.g_surfs.push(params.surf);
+        // This has already been drawn, so don't include it in the next batch
+        self.g_batch_start = self.g_surfs.len();
+    }
+
     fn end_record(&mut self, dstate: &DisplayState) {
         let cbuf = self.g_cbufs[dstate.d_current_image as usize];
-        unsafe {
-            // make sure to end recording
-            self.g_dev.dev.cmd_end_render_pass(cbuf);
-            self.g_dev.cbuf_end_recording(cbuf);
+        self.flush_batch(dstate);
+        self.upload_surfaces(dstate);
+        self.upload_meshes(dstate);
+        self.record_ops(dstate, cbuf);
+
+        if let Some(profiler) = self.g_profiler.as_ref() {
+            profiler.end_composite(cbuf, dstate.d_current_image as usize);
+        }
+        // Color correction reads what we just drew and writes
+        // the result to the swapchain image
+        if let Some(lut) = self.g_lut.as_ref() {
+            lut.record(cbuf, dstate);
+        }
+        if let Some(profiler) = self.g_profiler.as_ref() {
+            profiler.end_frame(cbuf, dstate.d_current_image as usize);
         }
+        self.g_dev.cbuf_end_recording(cbuf);
         // now submit the cbuf
         self.submit_frame(dstate);
     }
@@ -324,9 +406,36 @@ impl Pipeline for GeomPipeline {
 
             let consts = GeomPipeline::get_shader_constants(dstate);
             self.g_dev
-                .update_memory(self.uniform_buffers_memory, 0, &[consts]);
+                .update_memory(&self.uniform_buffers_memory, 0, &[consts]);
+
+            // The multisampled image has to match the new resolution
+            self.destroy_msaa_image();
+            if self.g_samples != vk::SampleCountFlags::TYPE_1 {
+                self.g_msaa_image = Some(self.g_dev.create_image(
+                    &dstate.d_resolution,
+                    dstate.d_surface_format.format,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT
+                        | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                    vk::ImageAspectFlags::COLOR,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    vk::ImageTiling::OPTIMAL,
+                    self.g_samples,
+                ));
+            }
 
-            self.framebuffers = GeomPipeline::create_framebuffers(&self.g_dev, self.pass, dstate);
+            // With color correction we draw into its frame instead
+            let target_view = self.g_lut.as_mut().map(|lut| {
+                lut.handle_ood(dstate);
+                lut.get_frame_view()
+            });
+
+            self.framebuffers = GeomPipeline::create_framebuffers(
+                &self.g_dev,
+                self.pass,
+                dstate,
+                self.g_msaa_image.as_ref().map(|(_, view, _)| *view),
+                target_view,
+            );
             if self.g_cbufs.len() > 0 {
                 self.g_dev
                     .dev
@@ -338,16 +447,23 @@ impl Pipeline for GeomPipeline {
                 .g_dev
                 .create_command_buffers(self.g_pool, dstate.d_views.len() as u32);
         }
+
+        if let Some(profiler) = self.g_profiler.as_mut() {
+            profiler.handle_ood(dstate.d_views.len());
+        }
     }
 }
 
 impl Drop for GeomPipeline {
     fn drop(&mut self) {
+        // Stop our record threads before destroying what they reference
+        self.g_record_pool.take();
+
         unsafe {
-            self.g_dev.free_memory(self.vert_buffer_memory);
-            self.g_dev.free_memory(self.index_buffer_memory);
             self.g_dev.dev.destroy_buffer(self.vert_buffer, None);
             self.g_dev.dev.destroy_buffer(self.index_buffer, None);
+            self.g_dev.free_allocation(&self.vert_buffer_memory);
+            self.g_dev.free_allocation(&self.index_buffer_memory);
 
             self.g_dev
                 .dev
@@ -355,9 +471,10 @@ impl Drop for GeomPipeline {
             self.g_dev.dev.destroy_command_pool(self.g_pool, None);
 
             self.g_dev.dev.destroy_buffer(self.uniform_buffer, None);
-            self.g_dev.free_memory(self.uniform_buffers_memory);
+            self.g_dev.free_allocation(&self.uniform_buffers_memory);
 
             self.g_dev.dev.destroy_render_pass(self.pass, None);
+            self.g_dev.dev.destroy_render_pass(self.g_load_pass, None);
 
             self.g_dev
                 .dev
@@ -380,24 +497,47 @@ impl Drop for GeomPipeline {
             }
 
             self.g_dev.dev.destroy_pipeline(self.pipeline, None);
+            if let Some(pipeline) = self.g_subpixel_pipeline {
+                self.g_dev.dev.destroy_pipeline(pipeline, None);
+            }
+        }
+        self.destroy_msaa_image();
+
+        for bufs in std::mem::take(&mut self.g_surf_bufs).into_iter() {
+            for buf in bufs.into_iter() {
+                self.destroy_surf_buffer(buf);
+            }
+        }
+        for bufs in std::mem::take(&mut self.g_mesh_bufs).into_iter() {
+            for buf in bufs.into_iter() {
+                self.destroy_mesh_buffer(buf);
+            }
+        }
+        unsafe {
+            self.g_dev
+                .dev
+                .destroy_descriptor_set_layout(self.g_surf_desc_layout, None);
+            self.g_dev
+                .dev
+                .destroy_descriptor_pool(self.g_surf_desc_pool, None);
         }
     }
 }
 
 impl GeomPipeline {
-    /// Helper for getting the push constants
+    /// Helper for getting the surface data
     ///
     /// This will be where we calculate the viewport scroll amount
-    fn update_surf_push_constants(
+    fn update_surf_data(
         &mut self,
         surf: &Surface,
         image: Option<&Image>,
         params: &mut RecordParams,
     ) {
         // transform from blender's coordinate system to vulkan
-        params.push.image_id = image.map(|i| i.i_id.get_raw_id() as i32).unwrap_or(-1);
-        params.push.use_color = surf.s_color.is_some() as i32;
-        params.push.color = match surf.s_color {
+        params.surf.image_id = image.map(|i| i.i_id.get_raw_id() as i32).unwrap_or(-1);
+        params.surf.use_color = surf.s_color.is_some() as i32;
+        params.surf.color = match surf.s_color {
             Some((r, g, b, a)) => (r, g, b, a),
             // magic value so it's easy to debug
             // this is clear, since we don't have a color
@@ -405,12 +545,323 @@ impl GeomPipeline {
             // In that case, we want this surface to be clear.
             None => (0.0, 50.0, 100.0, 0.0),
         };
-        params.push.dims = Rect::new(
+        params.surf.dims = Rect::new(
             surf.s_rect.r_pos.0,
             surf.s_rect.r_pos.1,
             surf.s_rect.r_size.0,
             surf.s_rect.r_size.1,
         );
+        params.surf.opacity = surf.s_opacity;
+        params.surf.tint = surf.s_tint;
+        // Sample the whole image unless the surface only shows part of it
+        params.surf.src_pos = 0;
+        params.surf.src_size = u32::MAX;
+        if let (Some(src), Some(image)) = (surf.s_src_rect.as_ref(), image) {
+            let (width, height) = image.get_size();
+            let pack = |x: i32, y: i32| {
+                let x = (x as f32 / width as f32).clamp(0.0, 1.0);
+                let y = (y as f32 / height as f32).clamp(0.0, 1.0);
+                (x * u16::MAX as f32).round() as u32 | ((y * u16::MAX as f32).round() as u32) << 16
+            };
+            params.surf.src_pos = pack(src.r_pos.0, src.r_pos.1);
+            params.surf.src_size = pack(src.r_size.0, src.r_size.1);
+        }
+        params.surf.transform = surf.s_transform.t_matrix;
+        params.surf.translation = surf.s_transform.t_translation;
+        // Colors are always given in sRGB, only images may be tagged
+        // with another color space
+        params.surf.image_color_space = image
+            .filter(|_| surf.s_color.is_none() && surf.s_gradient.is_none())
+            .map(|i| i.get_color_space())
+            .unwrap_or(ColorSpace::Srgb) as i32;
+
+        // Gradients are drawn as a color that changes across the surface
+        params.surf.gradient_type = 0;
+        if let Some(gradient) = surf.s_gradient.as_ref() {
+            params.surf.gradient_type = match gradient.g_kind {
+                GradientKind::Linear => 1,
+                GradientKind::Radial => 2,
+            };
+            params.surf.use_color = 1;
+            params.surf.color = gradient.g_start_color;
+            params.surf.gradient_start = gradient.g_start;
+            params.surf.gradient_end = gradient.g_end;
+            params.surf.gradient_end_color = gradient.g_end_color;
+        }
+    }
+
+    /// Helper for getting the surface data of a mesh
+    ///
+    /// Mesh vertices are in pixels, so they are drawn as a 1x1 surface at
+    /// the mesh position.
+    fn update_mesh_data(&mut self, mesh: &Mesh, image: Option<&Image>, params: &mut RecordParams) {
+        params.surf.image_id = image.map(|i| i.i_id.get_raw_id() as i32).unwrap_or(-1);
+        params.surf.use_color = mesh.m_color.is_some() as i32;
+        params.surf.color = mesh.m_color.unwrap_or((0.0, 0.0, 0.0, 0.0));
+        params.surf.dims = Rect::new(mesh.m_pos.0, mesh.m_pos.1, 1, 1);
+        params.surf.opacity = mesh.m_opacity;
+        params.surf.tint = (1.0, 1.0, 1.0);
+        params.surf.src_pos = 0;
+        params.surf.src_size = u32::MAX;
+        params.surf.transform = [1.0, 0.0, 0.0, 1.0];
+        params.surf.translation = (0.0, 0.0);
+        params.surf.gradient_type = 0;
+        params.surf.image_color_space = image
+            .filter(|_| mesh.m_color.is_none())
+            .map(|i| i.get_color_space())
+            .unwrap_or(ColorSpace::Srgb) as i32;
+    }
+
+    /// Get the descriptor set for sampling `image`
+    ///
+    /// If there is no image then use the default tmp image. Color and
+    /// gradient surfaces don't sample anything, so they can use whatever
+    /// image is already bound.
+    fn get_image_desc(&self, params: &RecordParams, image: Option<&Image>) -> vk::DescriptorSet {
+        if image.is_none() && self.g_bound_desc != vk::DescriptorSet::null() {
+            return self.g_bound_desc;
+        }
+
+        let imagevk = params
+            .image_vk
+            .get(match image {
+                Some(img) => &img.i_id,
+                None => &self.tmp_image.as_ref().unwrap().i_id,
+            })
+            .expect("Image does not have ImageVK");
+
+        assert!(imagevk.iv_desc.d_set != vk::DescriptorSet::null());
+        imagevk.iv_desc.d_set
+    }
+
+    /// Get the storage buffer the next surface will be written to
+    ///
+    /// If the storage buffer is full then continue in a larger one
+    fn get_surf_desc(&mut self, dstate: &DisplayState) -> vk::DescriptorSet {
+        let index = dstate.d_current_image as usize;
+        let buf = self.g_surf_bufs[index].last().unwrap();
+        if self.g_surfs.len() - buf.sb_first >= buf.sb_capacity {
+            let capacity = buf.sb_capacity * 2;
+            self.flush_batch(dstate);
+            let new_buf = self.create_surf_buffer(capacity, self.g_surfs.len());
+            self.g_surf_bufs[index].push(new_buf);
+        }
+        self.g_surf_bufs[index].last().unwrap().sb_desc
+    }
+
+    /// Bind the pipeline and descriptors for the next draw
+    ///
+    /// Anything that is already bound is skipped, in which case the next
+    /// surface is drawn in the same batch as the last one.
+    fn bind_state(
+        &mut self,
+        dstate: &DisplayState,
+        pipeline: vk::Pipeline,
+        image_desc: vk::DescriptorSet,
+        surf_desc: vk::DescriptorSet,
+    ) {
+        if pipeline != self.g_bound_pipeline {
+            self.flush_batch(dstate);
+            self.g_ops.push(DrawOp::BindPipeline(pipeline));
+            self.g_bound_pipeline = pipeline;
+        }
+
+        // Bind this surface's backing texture if it has one. Descriptor
+        // sets can be updated elsewhere, but they must be bound before drawing
+        //
+        // We need to bind the uniform set, the per-Image set for the
+        // image sampler, and the storage buffer holding our surface.
+        if image_desc != self.g_bound_desc || surf_desc != self.g_bound_surf_desc {
+            self.flush_batch(dstate);
+            self.g_ops
+                .push(DrawOp::BindDescriptors(image_desc, surf_desc));
+            self.g_bound_desc = image_desc;
+            self.g_bound_surf_desc = surf_desc;
+        }
+    }
+
+    /// Record this frame's draw ops into `cbuf`
+    ///
+    /// Large frames are split between our record threads, which record
+    /// them into secondary cbufs that are executed here. Otherwise the
+    /// ops are recorded into `cbuf` directly.
+    fn record_ops(&self, dstate: &DisplayState, cbuf: vk::CommandBuffer) {
+        let index = dstate.d_current_image as usize;
+        let framebuffer = self.framebuffers[index];
+        let ctx = RecordContext {
+            rc_layout: self.pipeline_layout,
+            rc_ubo_desc: self.g_desc,
+            rc_resolution: dstate.d_resolution,
+            rc_render_area: self.g_render_area,
+        };
+        let chunks = match self.g_record_pool.as_ref() {
+            Some(pool) => (self.g_ops.len() / RECORD_CHUNK_MIN_OPS).min(pool.get_worker_count()),
+            None => 0,
+        };
+
+        // we need to clear any existing data when we start a pass
+        let clear_vals = [vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 0.0],
+            },
+        }];
+        let pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.g_render_pass)
+            .framebuffer(framebuffer)
+            .render_area(self.g_render_area)
+            .clear_values(&clear_vals);
+        // A pass recorded with secondary cbufs can't have any commands
+        // recorded inline
+        let contents = match chunks > 1 {
+            true => vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            false => vk::SubpassContents::INLINE,
+        };
+
+        unsafe {
+            // All of our drawing operations need
+            // to be recorded inside a render pass.
+            self.g_dev
+                .dev
+                .cmd_begin_render_pass(cbuf, &pass_begin_info, contents);
+
+            match self.g_record_pool.as_ref().filter(|_| chunks > 1) {
+                Some(pool) => {
+                    let secondaries = pool.record(
+                        index,
+                        self.g_render_pass,
+                        framebuffer,
+                        &ctx,
+                        split_ops(&self.g_ops, chunks),
+                    );
+                    self.g_dev.dev.cmd_execute_commands(cbuf, &secondaries);
+                }
+                None => ctx.record(&self.g_dev.dev, cbuf, &self.g_ops),
+            }
+
+            self.g_dev.dev.cmd_end_render_pass(cbuf);
+        }
+    }
+
+    /// Can we draw subpixel text
+    pub fn supports_subpixel(&self) -> bool {
+        self.g_subpixel_pipeline.is_some()
+    }
+
+    /// Get the number of samples per pixel we render with
+    pub fn get_sample_count(&self) -> u32 {
+        self.g_samples.as_raw()
+    }
+
+    /// Choose the sample count to render with
+    ///
+    /// This is the highest count supported by the device that is not
+    /// more than `requested`. If multisampling is not supported this
+    /// falls back to one sample.
+    pub(crate) fn choose_sample_count(dev: &Device, requested: u32) -> vk::SampleCountFlags {
+        let props = unsafe { dev.inst.inst.get_physical_device_properties(dev.pdev) };
+        let supported = props.limits.framebuffer_color_sample_counts;
+
+        let mut count = vk::SampleCountFlags::TYPE_64;
+        while count != vk::SampleCountFlags::TYPE_1 {
+            if count.as_raw() <= requested && supported.contains(count) {
+                break;
+            }
+            count = vk::SampleCountFlags::from_raw(count.as_raw() >> 1);
+        }
+
+        if count.as_raw() != requested {
+            log::error!(
+                "Requested {} samples per pixel, using {} instead",
+                requested,
+                count.as_raw()
+            );
+        }
+        count
+    }
+
+    /// Can we redraw only the damaged part of a frame?
+    ///
+    /// This requires that the image we draw into still holds the last
+    /// frame drawn to it. The multisampled image and the color correction
+    /// frame are shared by all swapchain images, so they always have to
+    /// be redrawn completely.
+    pub fn can_redraw_partially(&self) -> bool {
+        self.g_samples == vk::SampleCountFlags::TYPE_1 && self.g_lut.is_none()
+    }
+
+    /// Free our multisampled color image if we have one
+    fn destroy_msaa_image(&mut self) {
+        if let Some((image, view, mem)) = self.g_msaa_image.take() {
+            unsafe {
+                self.g_dev.dev.destroy_image_view(view, None);
+                self.g_dev.dev.destroy_image(image, None);
+                self.g_dev.free_allocation(&mem);
+            }
+        }
+    }
+
+    /// Set the color correction table to apply to each frame
+    ///
+    /// Passing None turns off color correction. This waits for the
+    /// device to be idle so that in flight frames are not affected.
+    pub fn set_color_lut(&mut self, dstate: &DisplayState, lut: Option<&ColorLut>) -> Result<()> {
+        self.g_dev.wait_idle();
+
+        self.g_lut = match lut {
+            Some(lut) => Some(LutPass::new(self.g_dev.clone(), dstate, lut)?),
+            None => None,
+        };
+
+        // Our output layout depends on if the frame is sampled afterwards.
+        // Layouts don't affect render pass compatibility, so the existing
+        // pipeline can still be used with the new pass.
+        unsafe {
+            self.g_dev.dev.destroy_render_pass(self.pass, None);
+            self.g_dev.dev.destroy_render_pass(self.g_load_pass, None);
+            self.pass = GeomPipeline::create_pass(
+                dstate.d_surface_format.format,
+                &self.g_dev,
+                self.g_samples,
+                self.g_lut.is_some(),
+                false,
+            );
+            self.g_load_pass = GeomPipeline::create_pass(
+                dstate.d_surface_format.format,
+                &self.g_dev,
+                self.g_samples,
+                self.g_lut.is_some(),
+                true,
+            );
+        }
+        self.handle_ood(dstate);
+
+        Ok(())
+    }
+
+    /// Turn GPU profiling of each frame on or off
+    ///
+    /// Returns false if the device can't write timestamps on our queue.
+    pub fn set_gpu_profiling(&mut self, dstate: &DisplayState, enabled: bool) -> bool {
+        // Our queries may still be in use by in flight frames
+        self.g_dev.wait_idle();
+
+        self.g_profiler = match enabled {
+            true => FrameProfiler::new(
+                self.g_dev.clone(),
+                dstate.d_graphics_queue_family,
+                dstate.d_views.len(),
+            ),
+            false => None,
+        };
+        self.g_profiler.is_some() || !enabled
+    }
+
+    /// Get the GPU timings of frames which have completed
+    pub fn take_gpu_timings(&mut self) -> Vec<GpuFrameTimings> {
+        match self.g_profiler.as_mut() {
+            Some(profiler) => profiler.take_timings(),
+            None => Vec::new(),
+        }
     }
 
     /// Set our temporary image
@@ -448,9 +899,31 @@ impl GeomPipeline {
     /// shaders, geometry, and the like.
     ///
     /// This fills in the GeomPipeline struct in the Renderer
-    pub fn new(dev: Arc<Device>, dstate: &DisplayState) -> Result<GeomPipeline> {
+    ///
+    /// If `record_threads` is more than one, large frames are recorded in
+    /// parallel on that many threads.
+    pub fn new(
+        dev: Arc<Device>,
+        dstate: &DisplayState,
+        sample_count: u32,
+        record_threads: u32,
+    ) -> Result<GeomPipeline> {
         unsafe {
-            let pass = GeomPipeline::create_pass(dstate.d_surface_format.format, &dev);
+            let samples = GeomPipeline::choose_sample_count(&dev, sample_count);
+            let pass = GeomPipeline::create_pass(
+                dstate.d_surface_format.format,
+                &dev,
+                samples,
+                false,
+                false,
+            );
+            let load_pass = GeomPipeline::create_pass(
+                dstate.d_surface_format.format,
+                &dev,
+                samples,
+                false,
+                true,
+            );
 
             // This is a really annoying issue with CString ptrs
             let program_entrypoint_name = CString::new("main").unwrap();
@@ -462,6 +935,7 @@ impl GeomPipeline {
             let shader_stages = Box::new(GeomPipeline::create_shader_stages(
                 &dev,
                 program_entrypoint_name.as_ptr(),
+                false,
             ));
 
             // prepare descriptors for all of the uniforms to pass to shaders
@@ -469,33 +943,59 @@ impl GeomPipeline {
             // NOTE: These need to be referenced in order by the `set` modifier
             // in the shaders
             let ubo_layout = GeomPipeline::create_ubo_layout(&dev);
+            let surf_layout = GeomPipeline::create_surf_layout(&dev);
             // These are the layout recognized by the pipeline
             let descriptor_layouts = &[
                 ubo_layout, // set 0
                 dev.d_internal.read().unwrap().descpool.ds_layout,
+                surf_layout, // set 2
             ];
 
-            // make a push constant entry for the z ordering of a window
-            let constants = &[vk::PushConstantRange::builder()
-                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
-                .offset(0)
-                // depth is measured as a normalized float
-                .size(std::mem::size_of::<PushConstants>() as u32)
-                .build()];
-
-            // even though we don't have anything special in our layout, we
-            // still need to have a created layout for the pipeline
+            // Surface data is read from a storage buffer instead of push
+            // constants, so that many surfaces can be drawn at once
             let layout_info = vk::PipelineLayoutCreateInfo::builder()
-                .push_constant_ranges(constants)
                 .set_layouts(descriptor_layouts)
                 .build();
             let layout = dev.dev.create_pipeline_layout(&layout_info, None).unwrap();
 
-            let pipeline =
-                GeomPipeline::create_pipeline(dstate, &dev, layout, pass, &*shader_stages);
+            let pipeline = GeomPipeline::create_pipeline(
+                dstate.d_resolution,
+                &dev,
+                layout,
+                pass,
+                &*shader_stages,
+                samples,
+                false,
+            );
+            let mut shader_modules: Vec<vk::ShaderModule> =
+                shader_stages.iter().map(|info| info.module).collect();
+
+            // Subpixel text is drawn with dual source blending, so only
+            // create its pipeline if that is supported
+            let subpixel_pipeline = match dev.dev_features.vkc_supports_dual_src_blend {
+                true => {
+                    let subpixel_stages = Box::new(GeomPipeline::create_shader_stages(
+                        &dev,
+                        program_entrypoint_name.as_ptr(),
+                        true,
+                    ));
+                    shader_modules.extend(subpixel_stages.iter().map(|info| info.module));
+                    Some(GeomPipeline::create_pipeline(
+                        dstate.d_resolution,
+                        &dev,
+                        layout,
+                        pass,
+                        &*subpixel_stages,
+                        samples,
+                        true,
+                    ))
+                }
+                false => None,
+            };
 
             // Allocate a pool only for the ubo descriptors
             let g_desc_pool = Self::create_descriptor_pool(&dev);
+            let surf_desc_pool = Self::create_surf_descriptor_pool(&dev);
             let layouts = [ubo_layout];
             let info = vk::DescriptorSetAllocateInfo::builder()
                 .descriptor_pool(g_desc_pool)
@@ -522,11 +1022,20 @@ impl GeomPipeline {
             dev.register_graphics_queue_family(graphics_queue_family);
 
             let pool = dev.create_command_pool(graphics_queue_family);
+            let record_pool = match record_threads {
+                0 | 1 => None,
+                count => Some(RecordPool::new(
+                    dev.clone(),
+                    graphics_queue_family,
+                    count as usize,
+                )),
+            };
 
             // The app context contains the scene specific data
             let mut ctx = GeomPipeline {
                 g_dev: dev,
                 pass: pass,
+                g_load_pass: load_pass,
                 pipeline: pipeline,
                 pipeline_layout: layout,
                 g_desc_layout: ubo_layout,
@@ -537,7 +1046,7 @@ impl GeomPipeline {
                 g_cbufs: Vec::with_capacity(0),
                 g_desc_pool: g_desc_pool,
                 g_desc: ubo,
-                shader_modules: shader_stages.iter().map(|info| info.module).collect(),
+                shader_modules,
                 vert_buffer: vbuf,
                 vert_buffer_memory: vmem,
                 // multiply the index len by the vector size
@@ -545,6 +1054,27 @@ impl GeomPipeline {
                 index_buffer: ibuf,
                 index_buffer_memory: imem,
                 tmp_image: None,
+                g_bound_desc: vk::DescriptorSet::null(),
+                g_samples: samples,
+                g_msaa_image: None,
+                g_lut: None,
+                g_surf_desc_layout: surf_layout,
+                g_surf_desc_pool: surf_desc_pool,
+                g_surf_bufs: Vec::new(),
+                g_surfs: Vec::new(),
+                g_batch_start: 0,
+                g_bound_surf_desc: vk::DescriptorSet::null(),
+                g_subpixel_pipeline: subpixel_pipeline,
+                g_bound_pipeline: vk::Pipeline::null(),
+                g_mesh_bufs: Vec::new(),
+                g_mesh_verts: Vec::new(),
+                g_mesh_indices: Vec::new(),
+                g_profiler: None,
+                g_last_frame_point: 0,
+                g_ops: Vec::new(),
+                g_render_pass: pass,
+                g_render_area: vk::Rect2D::default(),
+                g_record_pool: record_pool,
             };
 
             // now we need to update the descriptor set with the
@@ -572,7 +1102,7 @@ impl GeomPipeline {
         }
 
         // Submit the recorded cbuf to perform the draw calls
-        self.g_dev.cbuf_submit_async(
+        self.g_last_frame_point = self.g_dev.cbuf_submit_async(
             // submit the cbuf for the current image
             self.g_cbufs[dstate.d_current_image as usize],
             dstate.d_present_queue, // the graphics queue
@@ -585,37 +1115,86 @@ impl GeomPipeline {
     ///
     /// Render passses signify what attachments are used in which
     /// stages. They are composed of one or more subpasses.
-    unsafe fn create_pass(format: vk::Format, dev: &Device) -> vk::RenderPass {
+    ///
+    /// If `samples` is more than one then we draw into a multisampled
+    /// attachment which is resolved into the swapchain image.
+    ///
+    /// If `to_lut` is set then our output is sampled by the LutPass
+    /// instead of being presented.
+    ///
+    /// If `keep_contents` is set then the image is loaded instead of
+    /// cleared. This is only useful without multisampling, where we draw
+    /// directly into the swapchain image.
+    unsafe fn create_pass(
+        format: vk::Format,
+        dev: &Device,
+        samples: vk::SampleCountFlags,
+        to_lut: bool,
+        keep_contents: bool,
+    ) -> vk::RenderPass {
         // According to the spec we can only use PRESENT_SRC when vkSwapchain's
         // ext is enabled
-        let layout = match dev.dev_features.vkc_supports_swapchain {
-            true => vk::ImageLayout::PRESENT_SRC_KHR,
-            false => vk::ImageLayout::GENERAL,
+        let layout = match (to_lut, dev.dev_features.vkc_supports_swapchain) {
+            (true, _) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (false, true) => vk::ImageLayout::PRESENT_SRC_KHR,
+            (false, false) => vk::ImageLayout::GENERAL,
         };
 
-        let attachments = [
+        let msaa = samples != vk::SampleCountFlags::TYPE_1;
+
+        // The image is left in `layout` after each frame, so that is
+        // what we load it from
+        let (load_op, initial_layout) = match keep_contents {
+            true => (vk::AttachmentLoadOp::LOAD, layout),
+            false => (vk::AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED),
+        };
+
+        let mut attachments = vec![
             // the color dest. Its the surface we slected in Renderer::new.
             // see Renderer::create_swapchain for why we aren't using
             // the native surface formate
             vk::AttachmentDescription {
-                format: format,
+                format,
                 samples: vk::SampleCountFlags::TYPE_1,
-                load_op: vk::AttachmentLoadOp::CLEAR,
+                load_op,
                 store_op: vk::AttachmentStoreOp::STORE,
-                initial_layout: vk::ImageLayout::UNDEFINED,
+                initial_layout,
                 final_layout: layout,
                 ..Default::default()
             },
         ];
 
+        if msaa {
+            // With MSAA we draw into a multisampled image instead, and the
+            // swapchain image is only written to by the resolve. The
+            // multisampled contents are thrown away after the pass.
+            attachments[0].load_op = vk::AttachmentLoadOp::DONT_CARE;
+            attachments.insert(
+                0,
+                vk::AttachmentDescription {
+                    format,
+                    samples,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::DONT_CARE,
+                    initial_layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ..Default::default()
+                },
+            );
+        }
+
         // identify which of the above attachments
         let color_refs = [vk::AttachmentReference {
             attachment: 0, // index into the attachments variable
             layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
         }];
+        let resolve_refs = [vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
 
         // our subpass isn't dependent on anything, and it writes to color output
-        let dependencies = [vk::SubpassDependency {
+        let mut dependencies = vec![vk::SubpassDependency {
             src_subpass: vk::SUBPASS_EXTERNAL,
             src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
             dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
@@ -623,12 +1202,27 @@ impl GeomPipeline {
             dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
             ..Default::default()
         }];
+        // The LutPass samples our output once we are done
+        if to_lut {
+            dependencies.push(vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            });
+        }
 
         // our render pass only has one subpass, which only does graphical ops
-        let subpasses = [vk::SubpassDescription::builder()
+        let mut subpass = vk::SubpassDescription::builder()
             .color_attachments(&color_refs)
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .build()];
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        if msaa {
+            subpass = subpass.resolve_attachments(&resolve_refs);
+        }
+        let subpasses = [subpass.build()];
 
         let create_info = vk::RenderPassCreateInfo::builder()
             .attachments(&attachments)
@@ -667,18 +1261,23 @@ impl GeomPipeline {
     /// `entrypoint`: should be a CString.as_ptr(). The CString that it
     /// represents should live as long as the return type of this method.
     ///  see: https://doc.rust-lang.org/std/ffi/struct.CString.html#method.as_ptr
+    ///
+    /// If `subpixel` is set then the fragment shader outputs a second color
+    /// for dual source blending, see `create_pipeline`.
     unsafe fn create_shader_stages(
         dev: &Device,
         entrypoint: *const i8,
+        subpixel: bool,
     ) -> [vk::PipelineShaderStageCreateInfo; 2] {
         let vert_shader = GeomPipeline::create_shader_module(
             dev,
             &mut Cursor::new(&include_bytes!("./shaders/vert.spv")[..]),
         );
-        let frag_shader = GeomPipeline::create_shader_module(
-            dev,
-            &mut Cursor::new(&include_bytes!("./shaders/frag.spv")[..]),
-        );
+        let frag_spv: &[u8] = match subpixel {
+            true => &include_bytes!("./shaders/subpixel_frag.spv")[..],
+            false => &include_bytes!("./shaders/frag.spv")[..],
+        };
+        let frag_shader = GeomPipeline::create_shader_module(dev, &mut Cursor::new(frag_spv));
 
         // note that the return size is 2 elements to match the return type
         [
@@ -711,11 +1310,13 @@ impl GeomPipeline {
     /// This method roughly follows the "fixed function" part of the
     /// vulkan tutorial.
     unsafe fn create_pipeline(
-        dstate: &DisplayState,
+        extent: vk::Extent2D,
         dev: &Device,
         layout: vk::PipelineLayout,
         pass: vk::RenderPass,
         shader_stages: &[vk::PipelineShaderStageCreateInfo],
+        samples: vk::SampleCountFlags,
+        subpixel: bool,
     ) -> vk::Pipeline {
         // This binds our vertex input to location 0 to be passed to the shader
         // Think of it like specifying the data stream given to the shader
@@ -766,15 +1367,15 @@ impl GeomPipeline {
         let viewport = [vk::Viewport {
             x: 0.0,
             y: 0.0,
-            width: dstate.d_resolution.width as f32,
-            height: dstate.d_resolution.height as f32,
+            width: extent.width as f32,
+            height: extent.height as f32,
             min_depth: 0.0,
             max_depth: 1.0,
         }];
         // no scissor test
         let scissor = [vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
-            extent: dstate.d_resolution,
+            extent,
         }];
 
         let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
@@ -790,9 +1391,9 @@ impl GeomPipeline {
         };
 
         // combines all of the fragments found at a pixel for anti-aliasing
-        // just disable this
+        // this is only enabled if MSAA was requested
         let multisample_info = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            rasterization_samples: samples,
             ..Default::default()
         };
 
@@ -804,11 +1405,24 @@ impl GeomPipeline {
         };
 
         // just do basic alpha blending. This is straight from the tutorial
+        //
+        // Subpixel text uses the second fragment shader output as the alpha
+        // of each color channel, so that they are blended separately.
+        let (src_factor, dst_factor) = match subpixel {
+            true => (
+                vk::BlendFactor::SRC1_COLOR,
+                vk::BlendFactor::ONE_MINUS_SRC1_COLOR,
+            ),
+            false => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+        };
         let blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
             blend_enable: 1, // VK_TRUE
             // blend the new contents over the old
-            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            src_color_blend_factor: src_factor,
+            dst_color_blend_factor: dst_factor,
             color_blend_op: vk::BlendOp::ADD,
             src_alpha_blend_factor: vk::BlendFactor::ONE,
             dst_alpha_blend_factor: vk::BlendFactor::ZERO,
@@ -841,10 +1455,56 @@ impl GeomPipeline {
 
         // Allocate one pipeline and return it
         dev.dev
-            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .create_graphics_pipelines(dev.d_pipeline_cache.pc_cache, &[pipeline_info], None)
             .expect("Could not create graphics pipeline")[0]
     }
 
+    /// Create and destroy our pipelines to fill the pipeline cache
+    ///
+    /// This creates the same pipelines as `new` would for a Display
+    /// with `format`. The viewport is dynamic, so the size used here
+    /// doesn't matter.
+    pub(crate) unsafe fn prewarm(dev: &Device, format: vk::Format, samples: vk::SampleCountFlags) {
+        let pass = GeomPipeline::create_pass(format, dev, samples, false, false);
+        let ubo_layout = GeomPipeline::create_ubo_layout(dev);
+        let surf_layout = GeomPipeline::create_surf_layout(dev);
+        let descriptor_layouts = &[
+            ubo_layout,
+            dev.d_internal.read().unwrap().descpool.ds_layout,
+            surf_layout,
+        ];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_layouts)
+            .build();
+        let layout = dev.dev.create_pipeline_layout(&layout_info, None).unwrap();
+        let extent = vk::Extent2D {
+            width: 1,
+            height: 1,
+        };
+
+        let entrypoint = CString::new("main").unwrap();
+        let mut subpixel_modes = vec![false];
+        if dev.dev_features.vkc_supports_dual_src_blend {
+            subpixel_modes.push(true);
+        }
+        for subpixel in subpixel_modes {
+            let stages = GeomPipeline::create_shader_stages(dev, entrypoint.as_ptr(), subpixel);
+            let pipeline = GeomPipeline::create_pipeline(
+                extent, dev, layout, pass, &stages, samples, subpixel,
+            );
+
+            dev.dev.destroy_pipeline(pipeline, None);
+            for stage in stages.iter() {
+                dev.dev.destroy_shader_module(stage.module, None);
+            }
+        }
+
+        dev.dev.destroy_pipeline_layout(layout, None);
+        dev.dev.destroy_descriptor_set_layout(ubo_layout, None);
+        dev.dev.destroy_descriptor_set_layout(surf_layout, None);
+        dev.dev.destroy_render_pass(pass, None);
+    }
+
     /// Create framebuffers for each swapchain image
     ///
     /// Image views represent a portion of an allocated image, while
@@ -853,19 +1513,30 @@ impl GeomPipeline {
     ///
     /// In our example, we pair color and depth attachments in our
     /// framebuffers.
+    ///
+    /// If `target_view` is set it is drawn to instead of the swapchain
+    /// images, but there is still one framebuffer per swapchain image.
     unsafe fn create_framebuffers(
         dev: &Device,
         pass: vk::RenderPass,
         dstate: &DisplayState,
+        msaa_view: Option<vk::ImageView>,
+        target_view: Option<vk::ImageView>,
     ) -> Vec<vk::Framebuffer> {
         // A framebuffer should be created for each of the swapchain
         // images. Reuse the depth buffer for all images since it
-        // doesn't change.
+        // doesn't change. The same goes for the multisampled image,
+        // as we only ever render one frame at a time.
         dstate
             .d_views
             .iter()
             .map(|&view| {
-                let attachments = [view]; // color
+                let view = target_view.unwrap_or(view);
+                let attachments = match msaa_view {
+                    // multisampled color, resolve target
+                    Some(msaa_view) => vec![msaa_view, view],
+                    None => vec![view], // color
+                };
 
                 let info = vk::FramebufferCreateInfo::builder()
                     .render_pass(pass)
@@ -890,8 +1561,9 @@ impl GeomPipeline {
 
         ShaderConstants {
             model: model,
-            width: dstate.d_resolution.width,
-            height: dstate.d_resolution.height,
+            width: dstate.d_resolution.width as f32,
+            height: dstate.d_resolution.height as f32,
+            color_space: dstate.d_color_space as i32,
         }
     }
 
@@ -909,7 +1581,21 @@ impl GeomPipeline {
         let bindings = [vk::DescriptorSetLayoutBinding::builder()
             .binding(0)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .descriptor_count(1)
+            .build()];
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+        dev.dev.create_descriptor_set_layout(&info, None).unwrap()
+    }
+
+    /// Create the layout of the storage buffers holding SurfaceData
+    unsafe fn create_surf_layout(dev: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
             .descriptor_count(1)
             .build()];
 
@@ -918,6 +1604,271 @@ impl GeomPipeline {
         dev.dev.create_descriptor_set_layout(&info, None).unwrap()
     }
 
+    /// Create the pool for SurfaceBuffer descriptors
+    ///
+    /// Sets are freed individually as buffers are replaced.
+    unsafe fn create_surf_descriptor_pool(dev: &Device) -> vk::DescriptorPool {
+        let size = [vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(SURFACE_BUFFER_MAX_SETS)
+            .build()];
+
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .pool_sizes(&size)
+            .max_sets(SURFACE_BUFFER_MAX_SETS);
+
+        dev.dev.create_descriptor_pool(&info, None).unwrap()
+    }
+
+    /// Create a storage buffer for `capacity` surfaces, starting with
+    /// surface number `first` of this frame
+    fn create_surf_buffer(&self, capacity: usize, first: usize) -> SurfaceBuffer {
+        let (buffer, mem) = self.g_dev.create_buffer_with_size(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            (capacity * mem::size_of::<SurfaceData>()) as u64,
+        );
+
+        unsafe {
+            let layouts = [self.g_surf_desc_layout];
+            let info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.g_surf_desc_pool)
+                .set_layouts(&layouts)
+                .build();
+            let desc = self.g_dev.dev.allocate_descriptor_sets(&info).unwrap()[0];
+
+            let buf_info = &[vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()];
+            let write_info = &[vk::WriteDescriptorSet::builder()
+                .dst_set(desc)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(buf_info)
+                .build()];
+            self.g_dev.dev.update_descriptor_sets(write_info, &[]);
+
+            SurfaceBuffer {
+                sb_buffer: buffer,
+                sb_mem: mem,
+                sb_desc: desc,
+                sb_capacity: capacity,
+                sb_first: first,
+            }
+        }
+    }
+
+    fn destroy_surf_buffer(&self, buf: SurfaceBuffer) {
+        unsafe {
+            self.g_dev
+                .dev
+                .free_descriptor_sets(self.g_surf_desc_pool, &[buf.sb_desc])
+                .unwrap();
+            self.g_dev.dev.destroy_buffer(buf.sb_buffer, None);
+            self.g_dev.free_allocation(&buf.sb_mem);
+        }
+    }
+
+    /// Get the storage buffers ready to record a new frame
+    ///
+    /// If the last frame drawn to this swapchain image needed more than
+    /// one buffer, they are replaced by one which can hold all of them.
+    fn reset_surf_bufs(&mut self, dstate: &DisplayState) {
+        let index = dstate.d_current_image as usize;
+        if self.g_surf_bufs.len() <= index {
+            self.g_surf_bufs.resize_with(index + 1, Vec::new);
+        }
+
+        if self.g_surf_bufs[index].len() != 1 {
+            let old_bufs = std::mem::take(&mut self.g_surf_bufs[index]);
+            let capacity = old_bufs
+                .iter()
+                .map(|buf| buf.sb_capacity)
+                .sum::<usize>()
+                .max(SURFACE_BUFFER_INITIAL_CAPACITY);
+
+            // The old buffers may still be in use by the last frame
+            if !old_bufs.is_empty() {
+                self.g_dev.wait_for_latest_timeline();
+            }
+            for buf in old_bufs.into_iter() {
+                self.destroy_surf_buffer(buf);
+            }
+
+            let buf = self.create_surf_buffer(capacity, 0);
+            self.g_surf_bufs[index].push(buf);
+        }
+
+        self.g_surfs.clear();
+        self.g_batch_start = 0;
+        self.g_bound_surf_desc = vk::DescriptorSet::null();
+    }
+
+    /// Draw the surfaces recorded since the last flush
+    ///
+    /// These all use the same descriptors, so they are drawn with one
+    /// instanced draw call.
+    fn flush_batch(&mut self, dstate: &DisplayState) {
+        let count = self.g_surfs.len() - self.g_batch_start;
+        if count == 0 {
+            return;
+        }
+
+        // A batch never spans storage buffers, so it is in the latest one
+        let buf = self.g_surf_bufs[dstate.d_current_image as usize]
+            .last()
+            .unwrap();
+
+        self.g_ops.push(DrawOp::Draw {
+            index_count: self.vert_count,
+            instance_count: count as u32,
+            first_index: 0,
+            vertex_offset: 0,
+            // first instance, which is our index into the storage buffer
+            first_instance: (self.g_batch_start - buf.sb_first) as u32,
+        });
+        self.g_batch_start = self.g_surfs.len();
+    }
+
+    /// Copy the surfaces recorded in this frame into the storage buffers
+    fn upload_surfaces(&mut self, dstate: &DisplayState) {
+        for buf in self.g_surf_bufs[dstate.d_current_image as usize].iter() {
+            let end = (buf.sb_first + buf.sb_capacity).min(self.g_surfs.len());
+            if buf.sb_first < end {
+                self.g_dev
+                    .update_memory(&buf.sb_mem, 0, &self.g_surfs[buf.sb_first..end]);
+            }
+        }
+    }
+
+    /// Create mesh buffers holding `verts` vertices and `indices` indices,
+    /// starting with vertex `first_vert` and index `first_index` of this
+    /// frame
+    fn create_mesh_buffer(
+        &self,
+        verts: usize,
+        indices: usize,
+        first_vert: usize,
+        first_index: usize,
+    ) -> MeshBuffer {
+        let flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let (vbuf, vmem) = self.g_dev.create_buffer_with_size(
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            flags,
+            (verts * mem::size_of::<VertData>()) as u64,
+        );
+        let (ibuf, imem) = self.g_dev.create_buffer_with_size(
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            flags,
+            (indices * mem::size_of::<u32>()) as u64,
+        );
+
+        MeshBuffer {
+            mb_verts: vbuf,
+            mb_verts_mem: vmem,
+            mb_indices: ibuf,
+            mb_indices_mem: imem,
+            mb_vert_capacity: verts,
+            mb_index_capacity: indices,
+            mb_first_vert: first_vert,
+            mb_first_index: first_index,
+        }
+    }
+
+    fn destroy_mesh_buffer(&self, buf: MeshBuffer) {
+        unsafe {
+            self.g_dev.dev.destroy_buffer(buf.mb_verts, None);
+            self.g_dev.dev.destroy_buffer(buf.mb_indices, None);
+            self.g_dev.free_allocation(&buf.mb_verts_mem);
+            self.g_dev.free_allocation(&buf.mb_indices_mem);
+        }
+    }
+
+    /// Get the mesh buffers ready to record a new frame
+    ///
+    /// If the last frame drawn to this swapchain image needed more than
+    /// one buffer, they are replaced by one which can hold all of them.
+    fn reset_mesh_bufs(&mut self, dstate: &DisplayState) {
+        let index = dstate.d_current_image as usize;
+        if self.g_mesh_bufs.len() <= index {
+            self.g_mesh_bufs.resize_with(index + 1, Vec::new);
+        }
+
+        if self.g_mesh_bufs[index].len() > 1 {
+            let old_bufs = std::mem::take(&mut self.g_mesh_bufs[index]);
+            let verts = old_bufs.iter().map(|buf| buf.mb_vert_capacity).sum();
+            let indices = old_bufs.iter().map(|buf| buf.mb_index_capacity).sum();
+
+            // The old buffers may still be in use by the last frame
+            self.g_dev.wait_for_latest_timeline();
+            for buf in old_bufs.into_iter() {
+                self.destroy_mesh_buffer(buf);
+            }
+
+            let buf = self.create_mesh_buffer(verts, indices, 0, 0);
+            self.g_mesh_bufs[index].push(buf);
+        }
+
+        self.g_mesh_verts.clear();
+        self.g_mesh_indices.clear();
+    }
+
+    /// Make sure the mesh buffer has room for another mesh
+    ///
+    /// If it doesn't then continue in a new buffer large enough for it.
+    fn reserve_mesh_buffer(&mut self, dstate: &DisplayState, verts: usize, indices: usize) {
+        let index = dstate.d_current_image as usize;
+        let (vert_capacity, index_capacity) = match self.g_mesh_bufs[index].last() {
+            Some(buf) => {
+                if self.g_mesh_verts.len() + verts <= buf.mb_first_vert + buf.mb_vert_capacity
+                    && self.g_mesh_indices.len() + indices
+                        <= buf.mb_first_index + buf.mb_index_capacity
+                {
+                    return;
+                }
+                (buf.mb_vert_capacity * 2, buf.mb_index_capacity * 2)
+            }
+            None => (MESH_BUFFER_INITIAL_VERTS, MESH_BUFFER_INITIAL_INDICES),
+        };
+
+        let buf = self.create_mesh_buffer(
+            vert_capacity.max(verts),
+            index_capacity.max(indices),
+            self.g_mesh_verts.len(),
+            self.g_mesh_indices.len(),
+        );
+        self.g_mesh_bufs[index].push(buf);
+    }
+
+    /// Copy the meshes recorded in this frame into the mesh buffers
+    fn upload_meshes(&mut self, dstate: &DisplayState) {
+        for buf in self.g_mesh_bufs[dstate.d_current_image as usize].iter() {
+            let end = (buf.mb_first_vert + buf.mb_vert_capacity).min(self.g_mesh_verts.len());
+            if buf.mb_first_vert < end {
+                self.g_dev.update_memory(
+                    &buf.mb_verts_mem,
+                    0,
+                    &self.g_mesh_verts[buf.mb_first_vert..end],
+                );
+            }
+            let end = (buf.mb_first_index + buf.mb_index_capacity).min(self.g_mesh_indices.len());
+            if buf.mb_first_index < end {
+                self.g_dev.update_memory(
+                    &buf.mb_indices_mem,
+                    0,
+                    &self.g_mesh_indices[buf.mb_first_index..end],
+                );
+            }
+        }
+    }
+
     /// Create vertex/index buffers for the default quad
     ///
     /// All onscreen regions will be represented by a quad, and
@@ -925,7 +1876,7 @@ impl GeomPipeline {
     /// for it.
     unsafe fn create_default_geom_bufs(
         dev: &Device,
-    ) -> (vk::Buffer, vk::DeviceMemory, vk::Buffer, vk::DeviceMemory) {
+    ) -> (vk::Buffer, Allocation, vk::Buffer, Allocation) {
         let (vbuf, vmem) = dev.create_buffer(
             vk::BufferUsageFlags::VERTEX_BUFFER,
             vk::SharingMode::EXCLUSIVE,
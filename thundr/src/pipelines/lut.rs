@@ -0,0 +1,868 @@
+// Color correction with a 3D lookup table
+//
+// A ColorLut maps every output color to a corrected color. It can be
+// loaded from a .cube file or built from an ICC display profile. The
+// LutPass applies it as the last step of drawing a frame: the geometry
+// pipeline draws into an intermediate image, which is then run through
+// the table and written to the swapchain image.
+//
+// Austin Shafer - 2024
+use ash::{util, vk};
+
+use std::ffi::CString;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::allocator::Allocation;
+use crate::display::DisplayState;
+use crate::{Device, Result, ThundrError};
+use utils::log;
+
+/// The number of entries along each axis of tables built from ICC profiles
+static ICC_LUT_SIZE: u32 = 33;
+/// The largest table size allowed by the .cube format
+static MAX_LUT_SIZE: u32 = 256;
+
+/// A 3D color lookup table
+///
+/// The table is indexed by the red, green and blue values of the color
+/// being corrected, and stores the color to replace it with. This is
+/// applied to the encoded values of the output, after they have been
+/// converted to the Display's ColorSpace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorLut {
+    /// The number of entries along each axis
+    pub(crate) cl_size: u32,
+    /// The corrected colors, with red changing fastest and blue slowest
+    pub(crate) cl_data: Vec<[f32; 3]>,
+}
+
+impl ColorLut {
+    /// Create a table that leaves colors unchanged
+    pub fn identity(size: u32) -> Self {
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([r as f32 / max, g as f32 / max, b as f32 / max]);
+                }
+            }
+        }
+
+        Self {
+            cl_size: size,
+            cl_data: data,
+        }
+    }
+
+    /// Get the number of entries along each axis of this table
+    pub fn get_size(&self) -> u32 {
+        self.cl_size
+    }
+
+    /// Load a table from a file
+    ///
+    /// Files ending in `.cube` are parsed as a 3D LUT, and anything else
+    /// is treated as an ICC profile.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("cube") => {
+                let contents =
+                    std::str::from_utf8(&data).or(Err(ThundrError::INVALID_COLOR_PROFILE))?;
+                Self::from_cube(contents)
+            }
+            _ => Self::from_icc(&data),
+        }
+    }
+
+    /// Parse the contents of a .cube file
+    ///
+    /// Only 3D tables over the default domain of [0, 1] are supported.
+    pub fn from_cube(contents: &str) -> Result<Self> {
+        let mut size = None;
+        let mut data = Vec::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap();
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    size = Some(
+                        words
+                            .next()
+                            .and_then(|s| s.parse::<u32>().ok())
+                            .ok_or(ThundrError::INVALID_COLOR_PROFILE)?,
+                    );
+                }
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let expected = match keyword {
+                        "DOMAIN_MIN" => 0.0,
+                        _ => 1.0,
+                    };
+                    let vals: Vec<f32> = words.filter_map(|w| w.parse().ok()).collect();
+                    if vals.len() != 3 || vals.iter().any(|v| *v != expected) {
+                        log::error!("Only .cube files with a domain of [0, 1] are supported");
+                        return Err(ThundrError::INVALID_COLOR_PROFILE);
+                    }
+                }
+                "LUT_1D_SIZE" => {
+                    log::error!("1D .cube files are not supported");
+                    return Err(ThundrError::INVALID_COLOR_PROFILE);
+                }
+                _ => {
+                    let vals: Vec<f32> = line
+                        .split_whitespace()
+                        .map(|w| w.parse().or(Err(ThundrError::INVALID_COLOR_PROFILE)))
+                        .collect::<Result<_>>()?;
+                    if vals.len() != 3 {
+                        return Err(ThundrError::INVALID_COLOR_PROFILE);
+                    }
+                    data.push([vals[0], vals[1], vals[2]]);
+                }
+            }
+        }
+
+        let size = size.ok_or(ThundrError::INVALID_COLOR_PROFILE)?;
+        if size < 2 || size > MAX_LUT_SIZE || data.len() != (size * size * size) as usize {
+            log::error!(
+                "Expected {} entries in a .cube file of size {}, found {}",
+                size * size * size,
+                size,
+                data.len()
+            );
+            return Err(ThundrError::INVALID_COLOR_PROFILE);
+        }
+
+        Ok(Self {
+            cl_size: size,
+            cl_data: data,
+        })
+    }
+
+    /// Build a table from an ICC display profile
+    ///
+    /// This supports matrix/TRC profiles, which is what most monitor
+    /// calibration tools produce. The table converts sRGB content to the
+    /// display's colors as described by the profile.
+    pub fn from_icc(data: &[u8]) -> Result<Self> {
+        if data.get(36..40) != Some(&b"acsp"[..]) {
+            log::error!("File is not an ICC profile");
+            return Err(ThundrError::INVALID_COLOR_PROFILE);
+        }
+        if data.get(16..20) != Some(&b"RGB "[..]) {
+            log::error!("Only RGB ICC profiles are supported");
+            return Err(ThundrError::INVALID_COLOR_PROFILE);
+        }
+
+        // The columns of this matrix are the XYZ of each primary, which
+        // converts linear display RGB to the D50 profile connection space
+        let mut to_xyz = [[0.0; 3]; 3];
+        for (i, sig) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+            let tag = icc_find_tag(data, sig)?;
+            if tag.get(0..4) != Some(&b"XYZ "[..]) {
+                return Err(ThundrError::INVALID_COLOR_PROFILE);
+            }
+            for (row, xyz) in to_xyz.iter_mut().enumerate() {
+                xyz[i] = icc_read_s15f16(tag, 8 + row * 4)?;
+            }
+        }
+        let from_xyz = invert_matrix(&to_xyz).ok_or(ThundrError::INVALID_COLOR_PROFILE)?;
+
+        let curves = [
+            IccCurve::parse(icc_find_tag(data, b"rTRC")?)?,
+            IccCurve::parse(icc_find_tag(data, b"gTRC")?)?,
+            IccCurve::parse(icc_find_tag(data, b"bTRC")?)?,
+        ];
+
+        // sRGB primaries adapted to D50 with the Bradford transform
+        let srgb_to_xyz = [
+            [0.4360747, 0.3850649, 0.1430804],
+            [0.2225045, 0.7168786, 0.0606169],
+            [0.0139322, 0.0971045, 0.7141733],
+        ];
+        let srgb_to_display = multiply_matrix(&from_xyz, &srgb_to_xyz);
+
+        let mut ret = Self::identity(ICC_LUT_SIZE);
+        for entry in ret.cl_data.iter_mut() {
+            let linear = entry.map(srgb_to_linear);
+            for (i, curve) in curves.iter().enumerate() {
+                let val = srgb_to_display[i][0] * linear[0]
+                    + srgb_to_display[i][1] * linear[1]
+                    + srgb_to_display[i][2] * linear[2];
+                entry[i] = curve.invert(val.clamp(0.0, 1.0));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Pass the output of this table through per-channel gamma ramps
+    ///
+    /// Each ramp maps evenly spaced input values to an output value
+    /// scaled to the range of a u16. This is the format used by DRM
+    /// and gamma control tools, and is applied after any existing
+    /// correction in the table.
+    pub fn apply_gamma_ramps(&mut self, red: &[u16], green: &[u16], blue: &[u16]) -> Result<()> {
+        let ramps = [red, green, blue];
+        if ramps.iter().any(|r| r.len() < 2) {
+            return Err(ThundrError::INVALID);
+        }
+
+        for entry in self.cl_data.iter_mut() {
+            for (val, ramp) in entry.iter_mut().zip(ramps.iter()) {
+                let pos = val.clamp(0.0, 1.0) * (ramp.len() - 1) as f32;
+                let i = (pos as usize).min(ramp.len() - 2);
+                let t = pos - i as f32;
+                *val = (ramp[i] as f32 * (1.0 - t) + ramp[i + 1] as f32 * t) / 65535.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the table contents as RGBA half floats for uploading
+    fn as_rgba16f(&self) -> Vec<u16> {
+        self.cl_data
+            .iter()
+            .flat_map(|c| [f32_to_f16(c[0]), f32_to_f16(c[1]), f32_to_f16(c[2]), 0x3c00])
+            .collect()
+    }
+}
+
+/// The sRGB transfer function
+fn srgb_to_linear(c: f32) -> f32 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// Convert a float to IEEE half precision
+///
+/// Values too small for a normal half are flushed to zero, which
+/// is far below anything visible in a color table.
+fn f32_to_f16(val: f32) -> u16 {
+    let bits = val.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exp <= 0 {
+        return sign;
+    }
+    if exp >= 31 {
+        return sign | 0x7c00;
+    }
+
+    // Round to nearest, a carry into the exponent is still correct
+    let half = sign | ((exp as u16) << 10) | (mantissa >> 13) as u16;
+    half + ((mantissa >> 12) & 1) as u16
+}
+
+fn multiply_matrix(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut ret = [[0.0; 3]; 3];
+    for row in 0..3 {
+        for col in 0..3 {
+            ret[row][col] = (0..3).map(|i| a[row][i] * b[i][col]).sum();
+        }
+    }
+    ret
+}
+
+fn invert_matrix(m: &[[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+
+    Some([
+        [
+            cofactor(1, 2, 1, 2) / det,
+            -cofactor(0, 2, 1, 2) / det,
+            cofactor(0, 1, 1, 2) / det,
+        ],
+        [
+            -cofactor(1, 2, 0, 2) / det,
+            cofactor(0, 2, 0, 2) / det,
+            -cofactor(0, 1, 0, 2) / det,
+        ],
+        [
+            cofactor(1, 2, 0, 1) / det,
+            -cofactor(0, 2, 0, 1) / det,
+            cofactor(0, 1, 0, 1) / det,
+        ],
+    ])
+}
+
+fn icc_read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(ThundrError::INVALID_COLOR_PROFILE)
+}
+
+fn icc_read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(ThundrError::INVALID_COLOR_PROFILE)
+}
+
+/// Read a signed 15.16 fixed point number
+fn icc_read_s15f16(data: &[u8], offset: usize) -> Result<f32> {
+    Ok(icc_read_u32(data, offset)? as i32 as f32 / 65536.0)
+}
+
+/// Get the contents of a tag from an ICC profile's tag table
+fn icc_find_tag<'a>(data: &'a [u8], sig: &[u8; 4]) -> Result<&'a [u8]> {
+    let count = icc_read_u32(data, 128)? as usize;
+
+    for i in 0..count {
+        let entry = 132 + i * 12;
+        if data.get(entry..entry + 4) == Some(&sig[..]) {
+            let offset = icc_read_u32(data, entry + 4)? as usize;
+            let size = icc_read_u32(data, entry + 8)? as usize;
+            return data
+                .get(offset..offset + size)
+                .ok_or(ThundrError::INVALID_COLOR_PROFILE);
+        }
+    }
+
+    log::error!(
+        "ICC profile is missing the {} tag, only matrix/TRC profiles are supported",
+        String::from_utf8_lossy(sig)
+    );
+    Err(ThundrError::INVALID_COLOR_PROFILE)
+}
+
+/// A tone response curve from an ICC profile
+///
+/// This maps encoded values to linear light for one channel.
+enum IccCurve {
+    Gamma(f32),
+    /// Evenly spaced samples of the curve
+    Table(Vec<f32>),
+    /// One of the ICC parametric curves, with its parameters g, a, b,
+    /// c, d, e and f
+    Parametric(u16, [f32; 7]),
+}
+
+impl IccCurve {
+    /// Parse a `curv` or `para` tag
+    fn parse(tag: &[u8]) -> Result<Self> {
+        match tag.get(0..4) {
+            Some(b"curv") => {
+                let count = icc_read_u32(tag, 8)? as usize;
+                match count {
+                    0 => Ok(Self::Gamma(1.0)),
+                    1 => Ok(Self::Gamma(icc_read_u16(tag, 12)? as f32 / 256.0)),
+                    _ => Ok(Self::Table(
+                        (0..count)
+                            .map(|i| Ok(icc_read_u16(tag, 12 + i * 2)? as f32 / 65535.0))
+                            .collect::<Result<_>>()?,
+                    )),
+                }
+            }
+            Some(b"para") => {
+                let function = icc_read_u16(tag, 8)?;
+                let param_count = match function {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return Err(ThundrError::INVALID_COLOR_PROFILE),
+                };
+                let mut params = [0.0; 7];
+                for (i, param) in params.iter_mut().take(param_count).enumerate() {
+                    *param = icc_read_s15f16(tag, 12 + i * 4)?;
+                }
+                Ok(Self::Parametric(function, params))
+            }
+            _ => Err(ThundrError::INVALID_COLOR_PROFILE),
+        }
+    }
+
+    /// Convert an encoded value to linear light
+    fn eval(&self, x: f32) -> f32 {
+        match self {
+            Self::Gamma(g) => x.powf(*g),
+            Self::Table(table) => {
+                let pos = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+                let i = (pos as usize).min(table.len() - 2);
+                let t = pos - i as f32;
+                table[i] * (1.0 - t) + table[i + 1] * t
+            }
+            Self::Parametric(function, p) => {
+                let [g, a, b, c, d, e, f] = *p;
+                match function {
+                    0 => x.powf(g),
+                    1 if x >= -b / a => (a * x + b).powf(g),
+                    1 => 0.0,
+                    2 if x >= -b / a => (a * x + b).powf(g) + c,
+                    2 => c,
+                    3 if x >= d => (a * x + b).powf(g),
+                    3 => c * x,
+                    _ if x >= d => (a * x + b).powf(g) + e,
+                    _ => c * x + f,
+                }
+            }
+        }
+    }
+
+    /// Convert linear light to an encoded value
+    ///
+    /// Display curves are increasing, so this searches for the input
+    /// that produces `y`.
+    fn invert(&self, y: f32) -> f32 {
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..24 {
+            let mid = (low + high) / 2.0;
+            match self.eval(mid) < y {
+                true => low = mid,
+                false => high = mid,
+            }
+        }
+        (low + high) / 2.0
+    }
+}
+
+/// The final color correction pass
+///
+/// While this is in use the geometry pipeline draws into `lp_frame`
+/// instead of the swapchain image. This pass then draws a triangle
+/// covering the screen which samples the frame, looks up the corrected
+/// color in the table, and writes it to the swapchain image.
+pub(crate) struct LutPass {
+    lp_dev: Arc<Device>,
+    lp_pass: vk::RenderPass,
+    lp_pipeline: vk::Pipeline,
+    lp_pipeline_layout: vk::PipelineLayout,
+    lp_desc_pool: vk::DescriptorPool,
+    lp_desc_layout: vk::DescriptorSetLayout,
+    /// Holds the frame and table samplers
+    lp_desc: vk::DescriptorSet,
+    lp_shader_modules: Vec<vk::ShaderModule>,
+    lp_sampler: vk::Sampler,
+    /// The 3D image holding the ColorLut
+    lp_lut: (vk::Image, vk::ImageView, Allocation),
+    /// The geometry pipeline draws into this. It is recreated
+    /// whenever the resolution changes.
+    lp_frame: Option<(vk::Image, vk::ImageView, Allocation)>,
+    /// One framebuffer for each swapchain image
+    lp_framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl LutPass {
+    pub fn new(dev: Arc<Device>, dstate: &DisplayState, lut: &ColorLut) -> Result<Self> {
+        unsafe {
+            let extent = vk::Extent3D {
+                width: lut.cl_size,
+                height: lut.cl_size,
+                depth: lut.cl_size,
+            };
+            // Half floats are guaranteed to support linear filtering
+            let lut_image = dev.create_3d_image(&extent, vk::Format::R16G16B16A16_SFLOAT);
+            let data = lut.as_rgba16f();
+            dev.update_3d_image_from_data(
+                lut_image.0,
+                &extent,
+                std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * 2),
+            );
+
+            let pass = Self::create_pass(&dev, dstate.d_surface_format.format);
+            let desc_layout = Self::create_desc_layout(&dev);
+
+            let size = [vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(2)
+                .build()];
+            let info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&size)
+                .max_sets(1);
+            let desc_pool = dev.dev.create_descriptor_pool(&info, None).unwrap();
+
+            let layouts = [desc_layout];
+            let info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(desc_pool)
+                .set_layouts(&layouts)
+                .build();
+            let desc = dev.dev.allocate_descriptor_sets(&info).unwrap()[0];
+
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&layouts)
+                .build();
+            let pipeline_layout = dev.dev.create_pipeline_layout(&layout_info, None).unwrap();
+
+            let (vert_shader, frag_shader) = Self::create_shaders(&dev);
+            let pipeline =
+                Self::create_pipeline(&dev, pipeline_layout, pass, vert_shader, frag_shader);
+
+            let sampler = dev.create_sampler(false);
+
+            let mut ret = Self {
+                lp_dev: dev,
+                lp_pass: pass,
+                lp_pipeline: pipeline,
+                lp_pipeline_layout: pipeline_layout,
+                lp_desc_pool: desc_pool,
+                lp_desc_layout: desc_layout,
+                lp_desc: desc,
+                lp_shader_modules: vec![vert_shader, frag_shader],
+                lp_sampler: sampler,
+                lp_lut: lut_image,
+                lp_frame: None,
+                lp_framebuffers: Vec::new(),
+            };
+            ret.update_descriptor(1, ret.lp_lut.1);
+
+            Ok(ret)
+        }
+    }
+
+    /// Get the image view the geometry pipeline should draw into
+    ///
+    /// This is only valid after `handle_ood` has been called.
+    pub fn get_frame_view(&self) -> vk::ImageView {
+        self.lp_frame.as_ref().unwrap().1
+    }
+
+    /// Point one of our descriptor bindings at `view`
+    fn update_descriptor(&mut self, binding: u32, view: vk::ImageView) {
+        let info = [vk::DescriptorImageInfo::builder()
+            .sampler(self.lp_sampler)
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+        let write_info = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.lp_desc)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&info)
+            .build()];
+
+        unsafe {
+            self.lp_dev.dev.update_descriptor_sets(&write_info, &[]);
+        }
+    }
+
+    /// Free the resources that depend on the swapchain
+    fn destroy_frame(&mut self) {
+        unsafe {
+            for fb in self.lp_framebuffers.drain(..) {
+                self.lp_dev.dev.destroy_framebuffer(fb, None);
+            }
+            if let Some((image, view, mem)) = self.lp_frame.take() {
+                self.lp_dev.dev.destroy_image_view(view, None);
+                self.lp_dev.dev.destroy_image(image, None);
+                self.lp_dev.free_allocation(&mem);
+            }
+        }
+    }
+
+    /// Recreate the intermediate frame and our framebuffers
+    ///
+    /// This must be called when the swapchain is recreated.
+    pub fn handle_ood(&mut self, dstate: &DisplayState) {
+        self.destroy_frame();
+
+        let frame = self.lp_dev.create_image(
+            &dstate.d_resolution,
+            dstate.d_surface_format.format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageTiling::OPTIMAL,
+            vk::SampleCountFlags::TYPE_1,
+        );
+        self.update_descriptor(0, frame.1);
+        self.lp_frame = Some(frame);
+
+        self.lp_framebuffers = dstate
+            .d_views
+            .iter()
+            .map(|view| {
+                let attachments = [*view];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(self.lp_pass)
+                    .attachments(&attachments)
+                    .width(dstate.d_resolution.width)
+                    .height(dstate.d_resolution.height)
+                    .layers(1);
+
+                unsafe { self.lp_dev.dev.create_framebuffer(&info, None).unwrap() }
+            })
+            .collect();
+    }
+
+    /// Record the color correction into `cbuf`
+    ///
+    /// This must be called after the geometry pipeline's render pass
+    /// has ended.
+    pub fn record(&self, cbuf: vk::CommandBuffer, dstate: &DisplayState) {
+        let area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: dstate.d_resolution,
+        };
+        let pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.lp_pass)
+            .framebuffer(self.lp_framebuffers[dstate.d_current_image as usize])
+            .render_area(area);
+
+        unsafe {
+            let dev = &self.lp_dev.dev;
+            dev.cmd_begin_render_pass(cbuf, &pass_begin_info, vk::SubpassContents::INLINE);
+            dev.cmd_bind_pipeline(cbuf, vk::PipelineBindPoint::GRAPHICS, self.lp_pipeline);
+            dev.cmd_set_viewport(
+                cbuf,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: dstate.d_resolution.width as f32,
+                    height: dstate.d_resolution.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            dev.cmd_set_scissor(cbuf, 0, &[area]);
+            dev.cmd_bind_descriptor_sets(
+                cbuf,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lp_pipeline_layout,
+                0,
+                &[self.lp_desc],
+                &[],
+            );
+            // One triangle covering the screen
+            dev.cmd_draw(cbuf, 3, 1, 0, 0);
+            dev.cmd_end_render_pass(cbuf);
+        }
+    }
+
+    /// Create and destroy our pipeline to fill the pipeline cache
+    pub(crate) unsafe fn prewarm(dev: &Device, format: vk::Format) {
+        let pass = Self::create_pass(dev, format);
+        let desc_layout = Self::create_desc_layout(dev);
+        let layouts = [desc_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&layouts)
+            .build();
+        let pipeline_layout = dev.dev.create_pipeline_layout(&layout_info, None).unwrap();
+        let (vert_shader, frag_shader) = Self::create_shaders(dev);
+        let pipeline = Self::create_pipeline(dev, pipeline_layout, pass, vert_shader, frag_shader);
+
+        dev.dev.destroy_pipeline(pipeline, None);
+        dev.dev.destroy_shader_module(vert_shader, None);
+        dev.dev.destroy_shader_module(frag_shader, None);
+        dev.dev.destroy_pipeline_layout(pipeline_layout, None);
+        dev.dev.destroy_descriptor_set_layout(desc_layout, None);
+        dev.dev.destroy_render_pass(pass, None);
+    }
+
+    /// Create the layout for our frame and LUT samplers
+    unsafe fn create_desc_layout(dev: &Device) -> vk::DescriptorSetLayout {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .descriptor_count(1)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .descriptor_count(1)
+                .build(),
+        ];
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        dev.dev.create_descriptor_set_layout(&info, None).unwrap()
+    }
+
+    /// Create the vertex and fragment shader modules
+    unsafe fn create_shaders(dev: &Device) -> (vk::ShaderModule, vk::ShaderModule) {
+        let vert_shader = Self::create_shader_module(
+            dev,
+            &mut Cursor::new(&include_bytes!("./shaders/lut_vert.spv")[..]),
+        );
+        let frag_shader = Self::create_shader_module(
+            dev,
+            &mut Cursor::new(&include_bytes!("./shaders/lut_frag.spv")[..]),
+        );
+        (vert_shader, frag_shader)
+    }
+
+    /// Create a render pass that writes to the swapchain image
+    ///
+    /// Every pixel is overwritten so the old contents are not loaded.
+    unsafe fn create_pass(dev: &Device, format: vk::Format) -> vk::RenderPass {
+        // According to the spec we can only use PRESENT_SRC when vkSwapchain's
+        // ext is enabled
+        let layout = match dev.dev_features.vkc_supports_swapchain {
+            true => vk::ImageLayout::PRESENT_SRC_KHR,
+            false => vk::ImageLayout::GENERAL,
+        };
+
+        let attachments = [vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: layout,
+            ..Default::default()
+        }];
+        let color_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+
+        // Wait for the geometry pipeline to finish drawing the frame
+        // before we sample it
+        let dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+
+        let subpasses = [vk::SubpassDescription::builder()
+            .color_attachments(&color_refs)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .build()];
+
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+
+        dev.dev.create_render_pass(&create_info, None).unwrap()
+    }
+
+    unsafe fn create_shader_module(
+        dev: &Device,
+        cursor: &mut Cursor<&'static [u8]>,
+    ) -> vk::ShaderModule {
+        let code = util::read_spv(cursor).expect("Could not read spv file");
+        let info = vk::ShaderModuleCreateInfo::builder().code(&code);
+
+        dev.dev
+            .create_shader_module(&info, None)
+            .expect("Could not create new shader module")
+    }
+
+    /// Create the pipeline for drawing the screen covering triangle
+    ///
+    /// There is no vertex input or blending, the fragment shader
+    /// writes the final color of every pixel.
+    unsafe fn create_pipeline(
+        dev: &Device,
+        layout: vk::PipelineLayout,
+        pass: vk::RenderPass,
+        vert_shader: vk::ShaderModule,
+        frag_shader: vk::ShaderModule,
+    ) -> vk::Pipeline {
+        let entrypoint = CString::new("main").unwrap();
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .module(vert_shader)
+                .name(&entrypoint)
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .module(frag_shader)
+                .name(&entrypoint)
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let vertex_info = vk::PipelineVertexInputStateCreateInfo::builder();
+        let assembly = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+        // The viewport and scissor are set while recording
+        let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+        let raster_info = vk::PipelineRasterizationStateCreateInfo {
+            cull_mode: vk::CullModeFlags::NONE,
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            ..Default::default()
+        };
+        let multisample_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+        let depth_info = vk::PipelineDepthStencilStateCreateInfo::default();
+        let blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+            blend_enable: 0,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            ..Default::default()
+        }];
+        let blend_info =
+            vk::PipelineColorBlendStateCreateInfo::builder().attachments(&blend_attachment_states);
+        let dynamic_info = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+            .build();
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_info)
+            .input_assembly_state(&assembly)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&raster_info)
+            .multisample_state(&multisample_info)
+            .depth_stencil_state(&depth_info)
+            .color_blend_state(&blend_info)
+            .dynamic_state(&dynamic_info)
+            .layout(layout)
+            .render_pass(pass)
+            .build();
+
+        dev.dev
+            .create_graphics_pipelines(dev.d_pipeline_cache.pc_cache, &[pipeline_info], None)
+            .expect("Could not create color correction pipeline")[0]
+    }
+}
+
+impl Drop for LutPass {
+    fn drop(&mut self) {
+        self.destroy_frame();
+
+        unsafe {
+            let dev = &self.lp_dev.dev;
+            dev.destroy_pipeline(self.lp_pipeline, None);
+            dev.destroy_pipeline_layout(self.lp_pipeline_layout, None);
+            for module in self.lp_shader_modules.iter() {
+                dev.destroy_shader_module(*module, None);
+            }
+            dev.destroy_render_pass(self.lp_pass, None);
+            dev.destroy_descriptor_pool(self.lp_desc_pool, None);
+            dev.destroy_descriptor_set_layout(self.lp_desc_layout, None);
+            dev.destroy_sampler(self.lp_sampler, None);
+
+            let (image, view, mem) = &self.lp_lut;
+            dev.destroy_image_view(*view, None);
+            dev.destroy_image(*image, None);
+            self.lp_dev.free_allocation(mem);
+        }
+    }
+}
@@ -4,7 +4,18 @@
 //!performance characteristics.
 //!
 //!* `GeomPipeline` - renders surfaces using a traditional graphics
-//!  pipeline. Surfaces are drawn as textured quads.
+//!  pipeline. Surfaces are drawn as textured quads, and meshes as
+//!  triangles using the same shaders.
+//!
+//!Pipelines may apply a `ColorLut` to the finished frame with a `LutPass`.
+//!
+//!Draw commands are collected while a frame is drawn and recorded when it
+//!ends. Large frames are split into chunks which a `RecordPool` records
+//!into secondary command buffers on several threads.
+//!
+//!All pipelines are created through the Device's `PipelineCache`, which
+//!is saved to disk so that they don't have to be compiled again on the
+//!next run.
 //!
 //!The `Pipeline` trait outlines how the main Thundr instance interacts
 //!with the pipeline code. All pipeline resources must be isolated from
@@ -12,12 +23,16 @@
 //!
 
 // Austin Shafer - 2020
+pub mod cache;
 pub mod geometric;
+pub mod lut;
+pub mod record;
 
 pub use geometric::GeomPipeline;
 
 use crate::display::{frame::RecordParams, DisplayState};
-use crate::{Image, Result, Surface, Viewport};
+use crate::{Image, Mesh, Surface};
+use utils::region::Rect;
 
 // The pipeline trait is essentially a mini-backend for the
 // renderer. It determines what draw calls we generate for the
@@ -27,12 +42,16 @@ use crate::{Image, Result, Surface, Viewport};
 /// types. For now there is one: the traditional rendering pipeline
 /// (geometric).
 pub(crate) trait Pipeline {
-    fn begin_record(&mut self, dstate: &DisplayState);
-
-    /// Set the viewport
+    /// Start recording a frame
+    ///
+    /// If `damage` is None the entire image is cleared and redrawn.
+    /// Otherwise the existing contents are kept and drawing is limited
+    /// to the `damage` region, which is cleared first.
     ///
-    /// This restricts the draw operations to within the specified region
-    fn set_viewport(&mut self, dstate: &DisplayState, viewport: &Viewport) -> Result<()>;
+    /// Viewports and clip rects are not pipeline state. Each surface is
+    /// clipped to the `clip` in its `SurfaceData`, so that changing them
+    /// doesn't split up batches.
+    fn begin_record(&mut self, dstate: &DisplayState, damage: Option<&Rect<i32>>);
 
     /// Our function which records the cbufs used to draw
     /// a Surface.
@@ -44,6 +63,18 @@ pub(crate) trait Pipeline {
         image: Option<&Image>,
     ) -> bool;
 
+    /// Record drawing a triangle mesh
+    ///
+    /// Unlike surfaces, meshes are never batched together. The mesh is
+    /// drawn in order with the surfaces around it.
+    fn draw_mesh(
+        &mut self,
+        params: &mut RecordParams,
+        dstate: &DisplayState,
+        mesh: &Mesh,
+        image: Option<&Image>,
+    );
+
     fn end_record(&mut self, dstate: &DisplayState);
 
     /// Handle swapchain out of date
@@ -0,0 +1,313 @@
+// Multithreaded command recording
+//
+// Scenes with thousands of surfaces spend a lot of time recording draw
+// commands. Instead of recording straight into the frame's cbuf, the
+// GeomPipeline builds a list of DrawOps while surfaces are drawn. At the
+// end of the frame a large list is split into chunks, each chunk is
+// recorded into a secondary cbuf by one of our worker threads, and the
+// secondary cbufs are executed in order in the frame's render pass.
+//
+// Command pools must be externally synchronized, so each worker records
+// from its own pool managed by the Device. Each worker always records the
+// same chunk, and keeps one secondary cbuf per swapchain image. A cbuf is
+// only recorded again once the frame using it has finished, the same as
+// the primary cbufs.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+use crate::Device;
+use utils::trace;
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc};
+use std::thread::{JoinHandle, ThreadId};
+
+/// One command recorded into a frame
+///
+/// These only hold Vulkan handles, so they can be sent to the workers.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum DrawOp {
+    /// Clear this region of the image
+    Clear(vk::Rect2D),
+    BindPipeline(vk::Pipeline),
+    /// Bind the image set (1) and storage buffer set (2)
+    BindDescriptors(vk::DescriptorSet, vk::DescriptorSet),
+    /// Bind these vertex and index buffers
+    BindGeometry(vk::Buffer, vk::Buffer),
+    Draw {
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    },
+}
+
+/// The state shared by every chunk of a frame
+#[derive(Copy, Clone)]
+pub(crate) struct RecordContext {
+    pub rc_layout: vk::PipelineLayout,
+    /// The uniform buffer set, bound as set 0 alongside every draw
+    pub rc_ubo_desc: vk::DescriptorSet,
+    pub rc_resolution: vk::Extent2D,
+    /// The area of the image being redrawn
+    pub rc_render_area: vk::Rect2D,
+}
+
+impl RecordContext {
+    /// Record `ops` into `cbuf`
+    ///
+    /// Dynamic state isn't inherited by secondary cbufs, so the viewport
+    /// and scissor are set at the start of every cbuf.
+    pub unsafe fn record(&self, dev: &ash::Device, cbuf: vk::CommandBuffer, ops: &[DrawOp]) {
+        // Surfaces are positioned in screen coordinates and clipped
+        // by the shaders, so the viewport always covers the whole
+        // screen and the scissor only limits us to the redrawn area.
+        dev.cmd_set_viewport(
+            cbuf,
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: self.rc_resolution.width as f32,
+                height: self.rc_resolution.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        dev.cmd_set_scissor(cbuf, 0, &[self.rc_render_area]);
+
+        for op in ops.iter() {
+            match *op {
+                DrawOp::Clear(rect) => dev.cmd_clear_attachments(
+                    cbuf,
+                    &[vk::ClearAttachment {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        color_attachment: 0,
+                        clear_value: vk::ClearValue {
+                            color: vk::ClearColorValue {
+                                float32: [0.0, 0.0, 0.0, 0.0],
+                            },
+                        },
+                    }],
+                    &[vk::ClearRect {
+                        rect,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    }],
+                ),
+                DrawOp::BindPipeline(pipeline) => {
+                    dev.cmd_bind_pipeline(cbuf, vk::PipelineBindPoint::GRAPHICS, pipeline)
+                }
+                DrawOp::BindDescriptors(image_desc, surf_desc) => dev.cmd_bind_descriptor_sets(
+                    cbuf,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.rc_layout,
+                    0, // first set
+                    &[self.rc_ubo_desc, image_desc, surf_desc],
+                    &[], // dynamic offsets
+                ),
+                DrawOp::BindGeometry(verts, indices) => {
+                    dev.cmd_bind_vertex_buffers(cbuf, 0, &[verts], &[0]);
+                    dev.cmd_bind_index_buffer(cbuf, indices, 0, vk::IndexType::UINT32);
+                }
+                DrawOp::Draw {
+                    index_count,
+                    instance_count,
+                    first_index,
+                    vertex_offset,
+                    first_instance,
+                } => dev.cmd_draw_indexed(
+                    cbuf,
+                    index_count,
+                    instance_count,
+                    first_index,
+                    vertex_offset,
+                    first_instance,
+                ),
+            }
+        }
+    }
+}
+
+/// Split `ops` into `count` chunks which can be recorded separately
+///
+/// Each chunk starts by binding everything that was bound at that point
+/// in the frame, so that it draws the same thing it would have inline.
+pub(crate) fn split_ops(ops: &[DrawOp], count: usize) -> Vec<Vec<DrawOp>> {
+    let count = count.max(1);
+    let chunk_len = ops.len().div_ceil(count).max(1);
+    let mut chunks = Vec::with_capacity(count);
+    let mut pipeline = None;
+    let mut descs = None;
+    let mut geometry = None;
+
+    for chunk in ops.chunks(chunk_len) {
+        let mut chunk_ops: Vec<DrawOp> = [pipeline, descs, geometry]
+            .iter()
+            .filter_map(|op| *op)
+            .collect();
+        chunk_ops.extend_from_slice(chunk);
+        chunks.push(chunk_ops);
+
+        for op in chunk.iter() {
+            match op {
+                DrawOp::BindPipeline(_) => pipeline = Some(*op),
+                DrawOp::BindDescriptors(..) => descs = Some(*op),
+                DrawOp::BindGeometry(..) => geometry = Some(*op),
+                _ => {}
+            }
+        }
+    }
+
+    chunks
+}
+
+/// One chunk of a frame for a worker to record
+struct RecordJob {
+    /// The position of this chunk in the frame
+    rj_chunk: usize,
+    /// The swapchain image being drawn
+    rj_image: usize,
+    rj_pass: vk::RenderPass,
+    rj_framebuffer: vk::Framebuffer,
+    rj_ctx: RecordContext,
+    rj_ops: Vec<DrawOp>,
+}
+
+struct RecordWorker {
+    /// Chunks for this worker to record. This is None once we are
+    /// shutting down.
+    rw_jobs: Option<mpsc::Sender<RecordJob>>,
+    rw_thread: Option<JoinHandle<()>>,
+}
+
+/// A set of threads recording secondary cbufs
+pub(crate) struct RecordPool {
+    rp_dev: Arc<Device>,
+    rp_workers: Vec<RecordWorker>,
+    /// The finished secondary cbufs, along with their chunk index
+    rp_results: mpsc::Receiver<(usize, vk::CommandBuffer)>,
+}
+
+impl RecordPool {
+    /// Start `count` recording threads
+    ///
+    /// Cbufs are allocated for `queue_family`, which must be the family
+    /// of the queue the frames are submitted to.
+    pub fn new(dev: Arc<Device>, queue_family: u32, count: usize) -> Self {
+        let (result_tx, result_rx) = mpsc::channel();
+
+        let workers = (0..count)
+            .map(|i| {
+                let (job_tx, job_rx) = mpsc::channel::<RecordJob>();
+                let results = result_tx.clone();
+                let dev = dev.clone();
+
+                // Named so they can be told apart in traces
+                let thread = std::thread::Builder::new()
+                    .name(format!("thundr-record-{}", i))
+                    .spawn(move || {
+                        let pool = dev.get_thread_command_pool(queue_family);
+                        // Our secondary cbuf for each swapchain image
+                        let mut cbufs: HashMap<usize, vk::CommandBuffer> = HashMap::new();
+
+                        // The pool was dropped once the channel closes
+                        while let Ok(job) = job_rx.recv() {
+                            let _record = trace::span("thundr", "record_chunk");
+                            let cbuf = *cbufs
+                                .entry(job.rj_image)
+                                .or_insert_with(|| dev.create_secondary_command_buffer(pool));
+
+                            dev.cbuf_begin_secondary(cbuf, job.rj_pass, job.rj_framebuffer);
+                            unsafe { job.rj_ctx.record(&dev.dev, cbuf, &job.rj_ops) };
+                            dev.cbuf_end_recording(cbuf);
+
+                            if results.send((job.rj_chunk, cbuf)).is_err() {
+                                return;
+                            }
+                        }
+                    })
+                    .expect("Could not spawn record worker");
+
+                RecordWorker {
+                    rw_jobs: Some(job_tx),
+                    rw_thread: Some(thread),
+                }
+            })
+            .collect();
+
+        Self {
+            rp_dev: dev,
+            rp_workers: workers,
+            rp_results: result_rx,
+        }
+    }
+
+    /// The number of chunks we can record at once
+    pub fn get_worker_count(&self) -> usize {
+        self.rp_workers.len()
+    }
+
+    /// Record each of `chunks` into a secondary cbuf
+    ///
+    /// This blocks until all of them are recorded, and returns the cbufs
+    /// in the same order as `chunks`. There must not be more chunks than
+    /// workers.
+    pub fn record(
+        &self,
+        image: usize,
+        pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        ctx: &RecordContext,
+        chunks: Vec<Vec<DrawOp>>,
+    ) -> Vec<vk::CommandBuffer> {
+        assert!(chunks.len() <= self.rp_workers.len());
+        let count = chunks.len();
+
+        for (i, ops) in chunks.into_iter().enumerate() {
+            self.rp_workers[i]
+                .rw_jobs
+                .as_ref()
+                .unwrap()
+                .send(RecordJob {
+                    rj_chunk: i,
+                    rj_image: image,
+                    rj_pass: pass,
+                    rj_framebuffer: framebuffer,
+                    rj_ctx: *ctx,
+                    rj_ops: ops,
+                })
+                .expect("Record worker exited");
+        }
+
+        let mut cbufs = vec![vk::CommandBuffer::null(); count];
+        for _ in 0..count {
+            let (chunk, cbuf) = self.rp_results.recv().expect("Record worker exited");
+            cbufs[chunk] = cbuf;
+        }
+        cbufs
+    }
+}
+
+impl Drop for RecordPool {
+    fn drop(&mut self) {
+        // Our cbufs may still be in use by in flight frames
+        self.rp_dev.wait_idle();
+
+        let mut threads: Vec<ThreadId> = Vec::new();
+        for worker in self.rp_workers.iter_mut() {
+            // Closing the channel tells the worker to exit
+            worker.rw_jobs.take();
+            if let Some(thread) = worker.rw_thread.take() {
+                threads.push(thread.thread().id());
+                let _ = thread.join();
+            }
+        }
+
+        for thread in threads.into_iter() {
+            self.rp_dev.destroy_thread_command_pools(thread);
+        }
+    }
+}
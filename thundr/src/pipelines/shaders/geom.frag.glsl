@@ -3,9 +3,20 @@
 #extension GL_EXT_nonuniform_qualifier : enable
 
 layout(location = 0) in vec2 coord;
+layout(location = 1) flat in int instance;
+
+#ifdef SUBPIXEL
+/* Subpixel text uses dual source blending. The image holds the coverage
+ * of each color channel, which is used to blend `res` into the output. */
+layout(location = 0, index = 0) out vec4 res;
+layout(location = 0, index = 1) out vec4 coverage;
+#else
 layout(location = 0) out vec4 res;
+#endif
 
-layout(push_constant) uniform PushConstants {
+/* The per-surface data. Surfaces are drawn instanced, with each
+ * instance reading its surface from the buffer below. */
+struct SurfaceData {
  int width;
  int height;
  // The id of the image. This is the offset into the unbounded sampler array.
@@ -17,21 +28,191 @@ layout(push_constant) uniform PushConstants {
  // The complete dimensions of the window.
  ivec2 surface_pos;
  ivec2 surface_size;
-} push;
+ // Multiplied with the alpha of the surface contents
+ float opacity;
+ // The region of the image to sample, as normalized unorm16x2 values
+ uint src_pos;
+ uint src_size;
+ // The surface transform, applied in surface-local pixels.
+ // xy is the first column of the 2x2 matrix, zw the second.
+ vec4 transform;
+ vec2 translation;
+ // 0 for none, 1 for linear, 2 for radial. color is the start color.
+ int gradient_type;
+ // The color space of the image or color, see ColorSpace
+ int image_color_space;
+ // Gradient start and end points in surface-local pixels
+ vec2 gradient_start;
+ vec2 gradient_end;
+ vec4 gradient_end_color;
+ // The region of the screen this surface may draw to
+ ivec2 clip_pos;
+ ivec2 clip_size;
+ // Multiplied with the color of the surface contents
+ vec3 tint;
+};
+
+layout(std430, set = 2, binding = 0) readonly buffer Surfaces {
+ SurfaceData surfaces[];
+};
+
+/* The surface this invocation is drawing */
+SurfaceData surf;
+
+layout(set = 0, binding = 0) uniform ShaderConstants {
+ mat4 model;
+ float width;
+ float height;
+ // The color space of the output, see ColorSpace
+ int color_space;
+} ubo;
+
+/* Is this fragment outside of the surface's clip region
+ *
+ * Viewports and clip rects are applied here instead of with the scissor,
+ * so that surfaces in different viewports can be drawn together. */
+bool is_clipped() {
+ // Clip rects are in the same coordinates as the surfaces, which are
+ // scaled to fit the image while a resize is pending
+ vec2 pos = gl_FragCoord.xy * vec2(surf.width, surf.height)
+  / vec2(ubo.width, ubo.height);
+ return any(lessThan(pos, vec2(surf.clip_pos)))
+  || any(greaterThanEqual(pos, vec2(surf.clip_pos + surf.clip_size)));
+}
+
+/* These must match the ColorSpace enum */
+#define COLOR_SPACE_SRGB 0
+#define COLOR_SPACE_SCRGB 1
+#define COLOR_SPACE_HDR10 2
+
+/* SDR white in nits, used when placing SDR content in PQ */
+#define SDR_WHITE_NITS 203.0
 
 /* The array of textures that are the window contents */
 layout(set = 1, binding = 1) uniform sampler2D image;
 
+/* Get the gradient color for this fragment */
+vec4 gradient_color() {
+ vec2 pos = coord * vec2(surf.surface_size);
+ vec2 dir = surf.gradient_end - surf.gradient_start;
+ float t = 0.0;
+
+ if (surf.gradient_type == 1) {
+  // Project onto the line from start to end
+  t = dot(pos - surf.gradient_start, dir) / max(dot(dir, dir), 0.0001);
+ } else {
+  t = length(pos - surf.gradient_start) / max(length(dir), 0.0001);
+ }
+
+ return mix(surf.color, surf.gradient_end_color, clamp(t, 0.0, 1.0));
+}
+
+vec3 srgb_to_linear(vec3 c) {
+ return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+vec3 linear_to_srgb(vec3 c) {
+ c = clamp(c, 0.0, 1.0);
+ return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+/* SMPTE ST 2084 constants */
+#define PQ_M1 0.1593017578125
+#define PQ_M2 78.84375
+#define PQ_C1 0.8359375
+#define PQ_C2 18.8515625
+#define PQ_C3 18.6875
+
+/* PQ to linear, where 1.0 is SDR white */
+vec3 pq_to_linear(vec3 c) {
+ vec3 p = pow(max(c, 0.0), vec3(1.0 / PQ_M2));
+ vec3 l = pow(max(p - PQ_C1, 0.0) / (PQ_C2 - PQ_C3 * p), vec3(1.0 / PQ_M1));
+ return l * 10000.0 / SDR_WHITE_NITS;
+}
+
+/* Linear, where 1.0 is SDR white, to PQ */
+vec3 linear_to_pq(vec3 c) {
+ vec3 l = pow(clamp(c * SDR_WHITE_NITS / 10000.0, 0.0, 1.0), vec3(PQ_M1));
+ return pow((PQ_C1 + PQ_C2 * l) / (1.0 + PQ_C3 * l), vec3(PQ_M2));
+}
+
+/* Primaries conversions, column major */
+const mat3 BT709_TO_BT2020 = mat3(
+ 0.6274, 0.0691, 0.0164,
+ 0.3293, 0.9195, 0.0880,
+ 0.0433, 0.0114, 0.8956
+);
+const mat3 BT2020_TO_BT709 = mat3(
+ 1.6605, -0.1246, -0.0182,
+ -0.5876, 1.1329, -0.1006,
+ -0.0728, -0.0083, 1.1187
+);
+
+/* Convert a color in `space` to linear BT.709 */
+vec3 to_linear(vec3 c, int space) {
+ if (space == COLOR_SPACE_SCRGB) {
+  return c;
+ } else if (space == COLOR_SPACE_HDR10) {
+  return BT2020_TO_BT709 * pq_to_linear(c);
+ }
+ return srgb_to_linear(c);
+}
+
+/* Convert linear BT.709 to the output color space */
+vec3 from_linear(vec3 c, int space) {
+ if (space == COLOR_SPACE_SCRGB) {
+  return c;
+ } else if (space == COLOR_SPACE_HDR10) {
+  return linear_to_pq(BT709_TO_BT2020 * c);
+ }
+ return linear_to_srgb(c);
+}
+
+#ifdef SUBPIXEL
 void main() {
- if (push.image_id >= 0) {
-  res = texture(image, coord);
+ surf = surfaces[instance];
+ if (is_clipped()) {
+  discard;
  }
 
- if (push.use_color > 0) {
+ vec4 tex = texture(image, unpackUnorm2x16(surf.src_pos) + coord * unpackUnorm2x16(surf.src_size));
+ vec4 color = surf.use_color > 0 ? surf.color : vec4(1.0);
+ color.rgb *= surf.tint;
+
+ if (surf.image_color_space != ubo.color_space) {
+  color.rgb = from_linear(to_linear(color.rgb, surf.image_color_space), ubo.color_space);
+ }
+
+ res = vec4(color.rgb, tex.a * color.a * surf.opacity);
+ coverage = tex * color.a * surf.opacity;
+}
+#else
+void main() {
+ surf = surfaces[instance];
+ if (is_clipped()) {
+  discard;
+ }
+
+ if (surf.image_id >= 0) {
+  res = texture(image, unpackUnorm2x16(surf.src_pos) + coord * unpackUnorm2x16(surf.src_size));
+ }
+
+ if (surf.use_color > 0) {
+  vec4 color = surf.gradient_type > 0 ? gradient_color() : surf.color;
+
   // If we have a color but also have an image, then
   // we should only update the color but keep the alpha
   // set by the image. This lets us color text for example.
-  res = vec4(push.color.xyz,
-             push.image_id >= 0 ? res.a : push.color.a);
+  res = vec4(color.xyz,
+             surf.image_id >= 0 ? res.a : color.a);
  }
+
+ res.rgb *= surf.tint;
+
+ if (surf.image_color_space != ubo.color_space) {
+  res.rgb = from_linear(to_linear(res.rgb, surf.image_color_space), ubo.color_space);
+ }
+
+ res.a *= surf.opacity;
 }
+#endif
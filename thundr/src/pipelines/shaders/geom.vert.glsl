@@ -5,14 +5,19 @@ layout(location = 0) in vec2 loc;
 layout(location = 1) in vec2 coord;
 
 layout(location = 0) out vec2 fragcoord;
+layout(location = 1) flat out int instance;
 
 layout(binding = 0) uniform ShaderConstants {
  mat4 model;
  float width;
  float height;
+ // The color space of the output, see ColorSpace
+ int color_space;
 } ubo;
 
-layout(push_constant) uniform PushConstants {
+/* The per-surface data. Surfaces are drawn instanced, with each
+ * instance reading its surface from the buffer below. */
+struct SurfaceData {
  int width;
  int height;
  // The id of the image. This is the offset into the unbounded sampler array.
@@ -24,12 +29,43 @@ layout(push_constant) uniform PushConstants {
  // The complete dimensions of the window.
  ivec2 surface_pos;
  ivec2 surface_size;
-} push;
+ // Multiplied with the alpha of the surface contents
+ float opacity;
+ // The region of the image to sample, as normalized unorm16x2 values
+ uint src_pos;
+ uint src_size;
+ // The surface transform, applied in surface-local pixels.
+ // xy is the first column of the 2x2 matrix, zw the second.
+ vec4 transform;
+ vec2 translation;
+ // 0 for none, 1 for linear, 2 for radial. color is the start color.
+ int gradient_type;
+ // The color space of the image or color, see ColorSpace
+ int image_color_space;
+ // Gradient start and end points in surface-local pixels
+ vec2 gradient_start;
+ vec2 gradient_end;
+ vec4 gradient_end_color;
+ // The region of the screen this surface may draw to
+ ivec2 clip_pos;
+ ivec2 clip_size;
+ // Multiplied with the color of the surface contents
+ vec3 tint;
+};
+
+layout(std430, set = 2, binding = 0) readonly buffer Surfaces {
+ SurfaceData surfaces[];
+};
+
+/* The surface this invocation is drawing */
+SurfaceData surf;
 
 /* The array of textures that are the window contents */
 layout(set = 1, binding = 1) uniform sampler2D image;
 
 void main() {
+ surf = surfaces[gl_InstanceIndex];
+
  // 1. loc should ALWAYS be 0,1 for the default quad.
  // 2. multiply by two since the axis are over the range (-1,1).
  // 3. multiply by the percentage of the screen that the window
@@ -39,13 +75,18 @@ void main() {
  //
  // Use viewport size here instead of the total resolution size. We want
  // to scale around our display area, not the entire thing.
- vec2 adjusted = loc
-  * vec2(2, 2)
-  * (push.surface_size / vec2(push.width, push.height))
-  + (push.surface_pos / vec2(push.width, push.height))
+ //
+ // Before this the surface transform is applied to the corner in
+ // surface-local pixel coordinates.
+ vec2 local = loc * surf.surface_size;
+ local = mat2(surf.transform.xy, surf.transform.zw) * local + surf.translation;
+
+ vec2 adjusted = (local + surf.surface_pos)
+  / vec2(surf.width, surf.height)
   * vec2(2, 2);
 
  gl_Position = ubo.model * vec4(adjusted, 0.0, 1.0);
 
  fragcoord = coord;
+ instance = gl_InstanceIndex;
 }
@@ -0,0 +1,20 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) out vec4 res;
+
+/* The frame drawn by the geometry pipeline */
+layout(set = 0, binding = 0) uniform sampler2D frame;
+/* The color correction table, indexed by red, green and blue */
+layout(set = 0, binding = 1) uniform sampler3D lut;
+
+void main() {
+ vec4 color = texelFetch(frame, ivec2(gl_FragCoord.xy), 0);
+
+ // Sample between the centers of the first and last texels so
+ // that the edges of the table aren't blended with the border
+ float size = float(textureSize(lut, 0).x);
+ vec3 coord = clamp(color.rgb, 0.0, 1.0) * ((size - 1.0) / size) + 0.5 / size;
+
+ res = vec4(texture(lut, coord).rgb, color.a);
+}
@@ -0,0 +1,11 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+/*
+ * Draw one triangle that covers the entire screen. This doesn't
+ * need a vertex buffer, the corners are generated from the index.
+ */
+void main() {
+ vec2 pos = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+ gl_Position = vec4(pos * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -34,6 +34,16 @@ pub struct VKDeviceFeatures {
     pub vkc_supports_nvidia_aftermath: bool,
     /// Does this device support VkSwapchain
     pub vkc_supports_swapchain: bool,
+    /// Does this device report its memory budget
+    pub vkc_supports_memory_budget: bool,
+    /// Does this device support dual source blending, used for subpixel text
+    pub vkc_supports_dual_src_blend: bool,
+    /// Can semaphores be exported as sync files
+    pub vkc_supports_sync_fd: bool,
+    /// Can host allocations be imported as device memory
+    pub vkc_supports_host_mem: bool,
+    /// The alignment of host pointers and sizes that can be imported
+    pub vkc_host_mem_alignment: u64,
 
     // The following are the lists of extensions that map to the above features
     vkc_ext_mem_exts: [*const i8; 1],
@@ -46,12 +56,15 @@ pub struct VKDeviceFeatures {
     vkc_nv_aftermath_exts: [*const i8; 2],
     vkc_timeline_exts: [*const i8; 1],
     vkc_swapchain_exts: [*const i8; 1],
+    vkc_memory_budget_exts: [*const i8; 1],
+    vkc_sync_fd_exts: [*const i8; 1],
+    vkc_host_mem_exts: [*const i8; 1],
 }
 
 unsafe impl Send for VKDeviceFeatures {}
 unsafe impl Sync for VKDeviceFeatures {}
 
-fn contains_extensions(exts: &[vk::ExtensionProperties], req: &[*const i8]) -> bool {
+pub(crate) fn contains_extensions(exts: &[vk::ExtensionProperties], req: &[*const i8]) -> bool {
     let mut count = 0;
 
     for r in req.iter() {
@@ -91,6 +104,11 @@ impl VKDeviceFeatures {
             vkc_supports_phys_dev_drm: false,
             vkc_supports_nvidia_aftermath: false,
             vkc_supports_swapchain: false,
+            vkc_supports_memory_budget: false,
+            vkc_supports_dual_src_blend: false,
+            vkc_supports_sync_fd: false,
+            vkc_supports_host_mem: false,
+            vkc_host_mem_alignment: 0,
             vkc_ext_mem_exts: [khr::ExternalMemoryFd::name().as_ptr()],
             vkc_dmabuf_exts: [
                 vk::ExtExternalMemoryDmaBufFn::name().as_ptr(),
@@ -115,6 +133,9 @@ impl VKDeviceFeatures {
             ],
             vkc_timeline_exts: [vk::KhrTimelineSemaphoreFn::name().as_ptr()],
             vkc_swapchain_exts: [khr::Swapchain::name().as_ptr()],
+            vkc_memory_budget_exts: [vk::ExtMemoryBudgetFn::name().as_ptr()],
+            vkc_sync_fd_exts: [khr::ExternalSemaphoreFd::name().as_ptr()],
+            vkc_host_mem_exts: [vk::ExtExternalMemoryHostFn::name().as_ptr()],
         };
 
         let exts = unsafe { inst.enumerate_device_extension_properties(pdev).unwrap() };
@@ -205,6 +226,7 @@ impl VKDeviceFeatures {
             && index_features.descriptor_binding_storage_buffer_update_after_bind > 0
             && index_features.descriptor_binding_sampled_image_update_after_bind > 0;
         ret.vkc_supports_nvidia_aftermath = supports_aftermath;
+        ret.vkc_supports_dual_src_blend = features.features.dual_src_blend > 0;
         // Only enable VkSwapchain for a swapchain backend which uses it
         ret.vkc_supports_swapchain = supports_swapchain && uses_vk_surface;
         ret.vkc_supports_mut_swapchain = ret.vkc_supports_swapchain && supports_mut_swapchain;
@@ -213,6 +235,40 @@ impl VKDeviceFeatures {
             true => ret.vkc_supports_phys_dev_drm = true,
             false => log::error!("This vulkan device does not support VK_EXT_physical_device_drm"),
         }
+        match contains_extensions(exts.as_slice(), &ret.vkc_memory_budget_exts) {
+            true => ret.vkc_supports_memory_budget = true,
+            false => log::error!("This vulkan device does not support VK_EXT_memory_budget"),
+        }
+        if contains_extensions(exts.as_slice(), &ret.vkc_sync_fd_exts) {
+            // The extension only means some handle type can be exported,
+            // check that sync files are one of them
+            let info = vk::PhysicalDeviceExternalSemaphoreInfo::builder()
+                .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+            let mut props = vk::ExternalSemaphoreProperties::builder().build();
+            unsafe {
+                inst.get_physical_device_external_semaphore_properties(pdev, &info, &mut props)
+            };
+            ret.vkc_supports_sync_fd = props
+                .external_semaphore_features
+                .contains(vk::ExternalSemaphoreFeatureFlags::EXPORTABLE);
+        }
+        if !ret.vkc_supports_sync_fd {
+            log::error!("This vulkan device does not support exporting sync files");
+        }
+        match contains_extensions(exts.as_slice(), &ret.vkc_host_mem_exts) {
+            true => {
+                let mut host_props =
+                    vk::PhysicalDeviceExternalMemoryHostPropertiesEXT::builder().build();
+                let mut props = vk::PhysicalDeviceProperties2::builder()
+                    .push_next(&mut host_props)
+                    .build();
+                unsafe { inst.get_physical_device_properties2(pdev, &mut props) };
+
+                ret.vkc_supports_host_mem = true;
+                ret.vkc_host_mem_alignment = host_props.min_imported_host_pointer_alignment;
+            }
+            false => log::error!("This vulkan device does not support VK_EXT_external_memory_host"),
+        }
 
         return ret;
     }
@@ -261,6 +317,21 @@ impl VKDeviceFeatures {
                 ret.push(*e)
             }
         }
+        if self.vkc_supports_memory_budget {
+            for e in self.vkc_memory_budget_exts.iter() {
+                ret.push(*e)
+            }
+        }
+        if self.vkc_supports_sync_fd {
+            for e in self.vkc_sync_fd_exts.iter() {
+                ret.push(*e)
+            }
+        }
+        if self.vkc_supports_host_mem {
+            for e in self.vkc_host_mem_exts.iter() {
+                ret.push(*e)
+            }
+        }
 
         #[cfg(feature = "aftermath")]
         if self.vkc_supports_nvidia_aftermath {
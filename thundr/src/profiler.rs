@@ -0,0 +1,347 @@
+// GPU profiling with timestamp queries
+//
+// When profiling is turned on we write Vulkan timestamps around the major
+// parts of each frame: uploading client contents into images, drawing the
+// surfaces, and color correction. The GPU fills these in as it executes
+// the command buffers, so results are read back once the frame's cbuf has
+// completed, which is usually a frame or two after it was drawn.
+//
+// Uploads happen on the copy queue whenever the application updates an
+// image, not as part of a frame. Their time is added up and reported with
+// the next frame whose timings are read.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+use crate::device::Device;
+use utils::log;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The number of frame timings we keep if the app doesn't take them
+const MAX_SAVED_TIMINGS: usize = 64;
+
+/// Timestamps written in each frame
+const QUERY_FRAME_START: u32 = 0;
+const QUERY_COMPOSITE_END: u32 = 1;
+const QUERY_POST_PROCESS_END: u32 = 2;
+const QUERIES_PER_FRAME: u32 = 3;
+
+/// GPU time spent on one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GpuFrameTimings {
+    /// The frame these are for, as returned by `Display::get_frame_count`
+    pub gt_frame: u64,
+    /// Time spent copying image contents since the last frame was measured
+    pub gt_upload: Duration,
+    /// Time spent drawing surfaces
+    pub gt_composite: Duration,
+    /// Time spent on color correction. This is zero if no color lut is set.
+    pub gt_post_process: Duration,
+}
+
+impl GpuFrameTimings {
+    /// Total GPU time spent on this frame
+    pub fn total(&self) -> Duration {
+        self.gt_upload + self.gt_composite + self.gt_post_process
+    }
+}
+
+/// Converts timestamps from a queue into durations
+#[derive(Debug, Clone, Copy)]
+struct TimestampClock {
+    /// Nanoseconds per timestamp tick
+    tc_period: f64,
+    /// The bits of a timestamp which are valid
+    tc_mask: u64,
+}
+
+impl TimestampClock {
+    /// Returns None if timestamps can't be written on this queue family
+    fn new(dev: &Device, queue_family: u32) -> Option<Self> {
+        let (props, families) = unsafe {
+            (
+                dev.inst.inst.get_physical_device_properties(dev.pdev),
+                dev.inst
+                    .inst
+                    .get_physical_device_queue_family_properties(dev.pdev),
+            )
+        };
+        let bits = families.get(queue_family as usize)?.timestamp_valid_bits;
+        if bits == 0 || props.limits.timestamp_period == 0.0 {
+            return None;
+        }
+
+        Some(Self {
+            tc_period: props.limits.timestamp_period as f64,
+            tc_mask: match bits {
+                64 => u64::MAX,
+                bits => (1 << bits) - 1,
+            },
+        })
+    }
+
+    /// Get the time between two timestamps
+    fn elapsed(&self, start: u64, end: u64) -> Duration {
+        let ticks = end.wrapping_sub(start) & self.tc_mask;
+        Duration::from_nanos((ticks as f64 * self.tc_period) as u64)
+    }
+}
+
+unsafe fn create_query_pool(dev: &ash::Device, count: u32) -> vk::QueryPool {
+    let info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(count)
+        .build();
+    dev.create_query_pool(&info, None).unwrap()
+}
+
+/// Read back timestamps, returns None if they aren't available yet
+unsafe fn get_timestamps(
+    dev: &ash::Device,
+    pool: vk::QueryPool,
+    first: u32,
+    count: u32,
+) -> Option<Vec<u64>> {
+    let mut data = vec![0u64; count as usize];
+    match dev.get_query_pool_results(
+        pool,
+        first,
+        count,
+        data.as_mut_slice(),
+        vk::QueryResultFlags::TYPE_64,
+    ) {
+        Ok(()) => Some(data),
+        Err(vk::Result::NOT_READY) => None,
+        Err(e) => {
+            log::error!("Could not read GPU timestamps: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Times the uploads recorded in the Device's copy cbuf
+pub(crate) struct CopyProfiler {
+    cp_pool: vk::QueryPool,
+    cp_clock: TimestampClock,
+    /// Has the copy cbuf been submitted with timestamps we haven't read
+    cp_pending: bool,
+    /// Upload time measured since this was last taken
+    cp_elapsed: Duration,
+}
+
+impl CopyProfiler {
+    pub fn new(dev: &Device, queue_family: u32) -> Option<Self> {
+        let clock = TimestampClock::new(dev, queue_family)?;
+
+        Some(Self {
+            cp_pool: unsafe { create_query_pool(&dev.dev, 2) },
+            cp_clock: clock,
+            cp_pending: false,
+            cp_elapsed: Duration::ZERO,
+        })
+    }
+
+    /// Add the time of the last upload if it has completed
+    fn collect(&mut self, dev: &ash::Device) {
+        if !self.cp_pending {
+            return;
+        }
+
+        if let Some(ts) = unsafe { get_timestamps(dev, self.cp_pool, 0, 2) } {
+            self.cp_elapsed += self.cp_clock.elapsed(ts[0], ts[1]);
+            self.cp_pending = false;
+        }
+    }
+
+    /// Record the start of an upload
+    ///
+    /// The previous copy must have completed before the copy cbuf is
+    /// recorded again, so its timestamps are read here.
+    pub fn begin(&mut self, dev: &ash::Device, cbuf: vk::CommandBuffer) {
+        self.collect(dev);
+        unsafe {
+            dev.cmd_reset_query_pool(cbuf, self.cp_pool, 0, 2);
+            dev.cmd_write_timestamp(cbuf, vk::PipelineStageFlags::TOP_OF_PIPE, self.cp_pool, 0);
+        }
+    }
+
+    /// Record the end of an upload
+    pub fn end(&mut self, dev: &ash::Device, cbuf: vk::CommandBuffer) {
+        unsafe {
+            dev.cmd_write_timestamp(
+                cbuf,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.cp_pool,
+                1,
+            );
+        }
+        self.cp_pending = true;
+    }
+
+    /// Get the time spent uploading since this was last called
+    pub fn take_elapsed(&mut self, dev: &ash::Device) -> Duration {
+        self.collect(dev);
+        std::mem::replace(&mut self.cp_elapsed, Duration::ZERO)
+    }
+
+    pub unsafe fn destroy(&self, dev: &ash::Device) {
+        dev.destroy_query_pool(self.cp_pool, None);
+    }
+}
+
+/// Times the frames recorded by a pipeline
+///
+/// There is one set of queries for each swapchain image, since each
+/// image has its own cbuf.
+pub(crate) struct FrameProfiler {
+    fp_dev: Arc<Device>,
+    fp_pool: vk::QueryPool,
+    fp_clock: TimestampClock,
+    /// The frame whose timestamps are waiting to be read for each image
+    fp_frames: Vec<Option<u64>>,
+    /// Timings which have been read but not taken by the app
+    fp_timings: Vec<GpuFrameTimings>,
+}
+
+impl FrameProfiler {
+    /// Returns None if the device can't write timestamps on this queue family
+    pub fn new(dev: Arc<Device>, queue_family: u32, image_count: usize) -> Option<Self> {
+        let clock = TimestampClock::new(&dev, queue_family)?;
+        let pool = unsafe { create_query_pool(&dev.dev, QUERIES_PER_FRAME * image_count as u32) };
+
+        Some(Self {
+            fp_dev: dev,
+            fp_pool: pool,
+            fp_clock: clock,
+            fp_frames: vec![None; image_count],
+            fp_timings: Vec::new(),
+        })
+    }
+
+    fn query(image: usize, query: u32) -> u32 {
+        image as u32 * QUERIES_PER_FRAME + query
+    }
+
+    /// Read the timings of the last frame drawn to an image if it has completed
+    fn collect(&mut self, image: usize) {
+        let frame = match self.fp_frames[image] {
+            Some(frame) => frame,
+            None => return,
+        };
+        let ts = match unsafe {
+            get_timestamps(
+                &self.fp_dev.dev,
+                self.fp_pool,
+                Self::query(image, 0),
+                QUERIES_PER_FRAME,
+            )
+        } {
+            Some(ts) => ts,
+            None => return,
+        };
+        self.fp_frames[image] = None;
+
+        let timings = GpuFrameTimings {
+            gt_frame: frame,
+            gt_upload: self.fp_dev.take_upload_time(),
+            gt_composite: self.fp_clock.elapsed(
+                ts[QUERY_FRAME_START as usize],
+                ts[QUERY_COMPOSITE_END as usize],
+            ),
+            gt_post_process: self.fp_clock.elapsed(
+                ts[QUERY_COMPOSITE_END as usize],
+                ts[QUERY_POST_PROCESS_END as usize],
+            ),
+        };
+
+        if self.fp_timings.len() >= MAX_SAVED_TIMINGS {
+            self.fp_timings.remove(0);
+        }
+        self.fp_timings.push(timings);
+    }
+
+    /// Record the start of a frame
+    ///
+    /// This must be called before the render pass begins.
+    pub fn begin_frame(&mut self, cbuf: vk::CommandBuffer, image: usize, frame: u64) {
+        // This image's last frame has finished since we are recording
+        // its cbuf again
+        self.collect(image);
+        // If that frame never completed its timings are dropped here
+        self.fp_frames[image] = Some(frame);
+
+        unsafe {
+            self.fp_dev.dev.cmd_reset_query_pool(
+                cbuf,
+                self.fp_pool,
+                Self::query(image, 0),
+                QUERIES_PER_FRAME,
+            );
+            self.fp_dev.dev.cmd_write_timestamp(
+                cbuf,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.fp_pool,
+                Self::query(image, QUERY_FRAME_START),
+            );
+        }
+    }
+
+    /// Record the end of drawing surfaces
+    ///
+    /// This must be called after the render pass ends.
+    pub fn end_composite(&self, cbuf: vk::CommandBuffer, image: usize) {
+        unsafe {
+            self.fp_dev.dev.cmd_write_timestamp(
+                cbuf,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.fp_pool,
+                Self::query(image, QUERY_COMPOSITE_END),
+            );
+        }
+    }
+
+    /// Record the end of post processing
+    pub fn end_frame(&self, cbuf: vk::CommandBuffer, image: usize) {
+        unsafe {
+            self.fp_dev.dev.cmd_write_timestamp(
+                cbuf,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.fp_pool,
+                Self::query(image, QUERY_POST_PROCESS_END),
+            );
+        }
+    }
+
+    /// Get the timings of frames which have completed
+    ///
+    /// Timings are in the order the frames were drawn.
+    pub fn take_timings(&mut self) -> Vec<GpuFrameTimings> {
+        for image in 0..self.fp_frames.len() {
+            self.collect(image);
+        }
+        self.fp_timings.sort_by_key(|t| t.gt_frame);
+        std::mem::take(&mut self.fp_timings)
+    }
+
+    /// Resize our queries for a new swapchain
+    ///
+    /// Frames which were not read yet are lost.
+    pub fn handle_ood(&mut self, image_count: usize) {
+        unsafe {
+            self.fp_dev.dev.destroy_query_pool(self.fp_pool, None);
+            self.fp_pool =
+                create_query_pool(&self.fp_dev.dev, QUERIES_PER_FRAME * image_count as u32);
+        }
+        self.fp_frames = vec![None; image_count];
+    }
+}
+
+impl Drop for FrameProfiler {
+    fn drop(&mut self) {
+        unsafe {
+            self.fp_dev.dev.destroy_query_pool(self.fp_pool, None);
+        }
+    }
+}
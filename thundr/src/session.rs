@@ -0,0 +1,187 @@
+/// Seat management
+///
+/// Opening DRM and input device nodes normally requires root. Instead we
+/// ask libseat to open them for us, which talks to seatd or logind. The
+/// seat manager also tells us when our session is switched away from,
+/// i.e. when the user changes VTs. While we are inactive our devices are
+/// revoked, so we must not touch them until the session is enabled again.
+///
+/// If no seat manager is available we fall back to opening devices
+/// ourselves, which works when running as root.
+///
+/// Austin Shafer - 2024
+extern crate libseat;
+
+use utils::log;
+
+use std::fs::OpenOptions;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Is our session the active one? This is updated from the seat
+/// callback, so it can't live behind the session lock.
+static SESSION_ACTIVE: AtomicBool = AtomicBool::new(true);
+/// Has the session been enabled or disabled since we last checked?
+static SESSION_CHANGED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref SESSION: Mutex<Option<Session>> = Mutex::new(Session::open());
+}
+
+/// Our seat and the devices we opened with it
+struct Session {
+    s_seat: libseat::Seat,
+    /// The fd we handed out for each device, along with the device
+    s_devices: Vec<(RawFd, libseat::Device)>,
+}
+
+// NOTE:
+// libseat handles are not marked send/sync. The seat is only used
+// from the main thread, the mutex is here to make the global
+// accessible from both Thundr and Dakota.
+unsafe impl Send for Session {}
+
+impl Session {
+    fn open() -> Option<Self> {
+        let seat = libseat::Seat::open(|_seat, event| match event {
+            libseat::SeatEvent::Enable => {
+                log::debug!("Session enabled");
+                SESSION_ACTIVE.store(true, Ordering::SeqCst);
+                SESSION_CHANGED.store(true, Ordering::SeqCst);
+            }
+            libseat::SeatEvent::Disable => {
+                // We need to stop using our devices before acknowledging
+                // this, which is done in `acknowledge_disable`
+                log::debug!("Session disabled");
+                SESSION_ACTIVE.store(false, Ordering::SeqCst);
+                SESSION_CHANGED.store(true, Ordering::SeqCst);
+            }
+        });
+
+        match seat {
+            Ok(seat) => Some(Self {
+                s_seat: seat,
+                s_devices: Vec::new(),
+            }),
+            Err(e) => {
+                log::error!("Could not open a seat, opening devices directly: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Open a device node
+///
+/// This goes through the seat manager if there is one, otherwise the
+/// node is opened directly with `flags`.
+pub fn open_device(path: &Path, flags: i32) -> std::io::Result<OwnedFd> {
+    let mut session = SESSION.lock().unwrap();
+
+    if let Some(session) = session.as_mut() {
+        let device = session.s_seat.open_device(&path).map_err(|e| {
+            log::error!("Seat could not open {:?}: {:?}", path, e);
+            std::io::Error::from(std::io::ErrorKind::PermissionDenied)
+        })?;
+        let fd = device.as_fd().try_clone_to_owned()?;
+        session.s_devices.push((fd.as_raw_fd(), device));
+        return Ok(fd);
+    }
+
+    let file = OpenOptions::new()
+        // the unix extension's custom_flag field below
+        // masks out O_ACCMODE, i.e. read/write, so add
+        // them back in
+        .read(true)
+        .write(true)
+        .custom_flags(flags)
+        .open(path)?;
+    Ok(file.into())
+}
+
+/// Close a device opened with `open_device`
+pub fn close_device(fd: OwnedFd) {
+    let mut session = SESSION.lock().unwrap();
+
+    if let Some(session) = session.as_mut() {
+        let raw = fd.as_raw_fd();
+        if let Some(index) = session.s_devices.iter().position(|(f, _)| *f == raw) {
+            let (_, device) = session.s_devices.remove(index);
+            if let Err(e) = session.s_seat.close_device(device) {
+                log::error!("Seat could not close device: {:?}", e);
+            }
+        }
+    }
+    drop(fd);
+}
+
+/// Get the seat's fd
+///
+/// This becomes readable when the seat has events for us, at which point
+/// `dispatch` should be called. Returns None if there is no seat manager.
+pub fn get_fd() -> Option<RawFd> {
+    let mut session = SESSION.lock().unwrap();
+    let session = session.as_mut()?;
+
+    session.s_seat.get_fd().ok().map(|fd| fd.as_raw_fd())
+}
+
+/// Handle any events from the seat manager
+///
+/// Afterwards `take_state_change` reports if we were enabled or disabled.
+pub fn dispatch() {
+    let mut session = SESSION.lock().unwrap();
+
+    if let Some(session) = session.as_mut() {
+        if let Err(e) = session.s_seat.dispatch(0) {
+            log::error!("Could not dispatch seat events: {:?}", e);
+        }
+    }
+}
+
+/// Is our session active?
+///
+/// Devices must not be used while this is false.
+pub fn is_active() -> bool {
+    SESSION_ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Check if the session was enabled or disabled
+///
+/// Returns the new state if it changed since the last call. If the
+/// session was disabled, `acknowledge_disable` must be called once
+/// all devices have been released.
+pub fn take_state_change() -> Option<bool> {
+    match SESSION_CHANGED.swap(false, Ordering::SeqCst) {
+        true => Some(is_active()),
+        false => None,
+    }
+}
+
+/// Tell the seat manager we have stopped using our devices
+///
+/// This lets the session switch continue.
+pub fn acknowledge_disable() {
+    let mut session = SESSION.lock().unwrap();
+
+    if let Some(session) = session.as_mut() {
+        if let Err(e) = session.s_seat.disable() {
+            log::error!("Could not acknowledge session disable: {:?}", e);
+        }
+    }
+}
+
+/// Switch to another session, i.e. change VTs
+pub fn switch_session(vt: i32) {
+    let mut session = SESSION.lock().unwrap();
+
+    if let Some(session) = session.as_mut() {
+        log::debug!("Switching to session {}", vt);
+        if let Err(e) = session.s_seat.switch_session(vt) {
+            log::error!("Could not switch to session {}: {:?}", vt, e);
+        }
+    }
+}
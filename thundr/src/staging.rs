@@ -0,0 +1,112 @@
+// Staging buffer pool
+//
+// Uploading shm contents to an image first copies the data into a host
+// visible staging buffer, which the copy queue then transfers into the
+// image. Creating and freeing a staging buffer for every commit is slow,
+// so instead we keep a pool of persistently mapped buffers around.
+//
+// A buffer is taken out of the pool for each upload. Once the copy has
+// been submitted a StagingRelease is added to the Device's DeletionQueue,
+// which returns the buffer to the pool when the GPU is done with it.
+//
+// Austin Shafer - 2024
+use ash::vk;
+
+use crate::allocator::Allocation;
+
+use std::sync::{Arc, Mutex};
+
+/// The smallest staging buffer we will create
+pub(crate) const STAGING_MIN_SIZE: u64 = 1024 * 1024;
+
+/// A persistently mapped buffer to upload data from
+#[derive(Debug)]
+pub(crate) struct StagingBuffer {
+    pub sb_buffer: vk::Buffer,
+    /// Host visible memory backing sb_buffer. a_mapped is always valid.
+    pub sb_memory: Allocation,
+    pub sb_size: u64,
+}
+
+/// The set of staging buffers not currently in use by the GPU
+pub(crate) struct StagingPool {
+    sp_free: Vec<StagingBuffer>,
+}
+
+impl StagingPool {
+    pub fn new() -> Self {
+        Self {
+            sp_free: Vec::new(),
+        }
+    }
+
+    /// Get the size of buffer to create for an upload of `size` bytes
+    ///
+    /// Sizes are rounded up to a power of two, so that slowly growing
+    /// uploads don't each need a new buffer.
+    pub fn buffer_size_for(size: u64) -> u64 {
+        size.next_power_of_two().max(STAGING_MIN_SIZE)
+    }
+
+    /// Take the smallest free buffer that can hold `size` bytes
+    pub fn take(&mut self, size: u64) -> Option<StagingBuffer> {
+        let index = self
+            .sp_free
+            .iter()
+            .enumerate()
+            .filter(|(_, buf)| buf.sb_size >= size)
+            .min_by_key(|(_, buf)| buf.sb_size)
+            .map(|(i, _)| i)?;
+
+        Some(self.sp_free.swap_remove(index))
+    }
+
+    /// Take the smallest free buffer, regardless of size
+    ///
+    /// This is used to grow the pool. When no buffer is big enough for an
+    /// upload the Device destroys one of the ones that are too small, so
+    /// that the pool does not keep collecting buffers it won't use.
+    pub fn take_smallest(&mut self) -> Option<StagingBuffer> {
+        let index = self
+            .sp_free
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, buf)| buf.sb_size)
+            .map(|(i, _)| i)?;
+
+        Some(self.sp_free.swap_remove(index))
+    }
+
+    /// Return a buffer to the pool
+    pub fn put(&mut self, buffer: StagingBuffer) {
+        self.sp_free.push(buffer);
+    }
+
+    /// Remove all free buffers so they can be destroyed
+    pub fn drain(&mut self) -> Vec<StagingBuffer> {
+        self.sp_free.drain(..).collect()
+    }
+
+    /// Number of buffers waiting to be reused
+    #[cfg(test)]
+    pub fn free_count(&self) -> usize {
+        self.sp_free.len()
+    }
+}
+
+/// Returns a staging buffer to its pool when dropped
+///
+/// This is scheduled in the DeletionQueue once an upload has been
+/// submitted, so that the buffer is not reused while being copied from.
+pub(crate) struct StagingRelease {
+    pub sr_pool: Arc<Mutex<StagingPool>>,
+    pub sr_buffer: Option<StagingBuffer>,
+}
+
+impl Drop for StagingRelease {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.sr_buffer.take() {
+            self.sr_pool.lock().unwrap().put(buffer);
+        }
+    }
+}
@@ -7,17 +7,100 @@
 // Austin Shafer - 2020
 extern crate nix;
 
+use crate::Transform;
 use utils::region::Rect;
 
+/// The shape of a gradient
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GradientKind {
+    /// Colors change along the line from start to end
+    Linear,
+    /// Colors change with the distance from start. The radius
+    /// is the distance from start to end.
+    Radial,
+}
+
+/// A gradient fill for a surface
+///
+/// The start and end points are in surface-local pixels. The surface is
+/// filled with `g_start_color` before the start point and `g_end_color`
+/// past the end point.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Gradient {
+    pub g_kind: GradientKind,
+    pub g_start: (f32, f32),
+    pub g_end: (f32, f32),
+    pub g_start_color: (f32, f32, f32, f32),
+    pub g_end_color: (f32, f32, f32, f32),
+}
+
+impl Gradient {
+    pub fn linear(
+        start: (f32, f32),
+        end: (f32, f32),
+        start_color: (f32, f32, f32, f32),
+        end_color: (f32, f32, f32, f32),
+    ) -> Self {
+        Self {
+            g_kind: GradientKind::Linear,
+            g_start: start,
+            g_end: end,
+            g_start_color: start_color,
+            g_end_color: end_color,
+        }
+    }
+
+    pub fn radial(
+        center: (f32, f32),
+        radius: f32,
+        inner_color: (f32, f32, f32, f32),
+        outer_color: (f32, f32, f32, f32),
+    ) -> Self {
+        Self {
+            g_kind: GradientKind::Radial,
+            g_start: center,
+            g_end: (center.0 + radius, center.1),
+            g_start_color: inner_color,
+            g_end_color: outer_color,
+        }
+    }
+}
+
 /// A surface represents a geometric region that will be
 /// drawn. It needs to have an image attached. The same
 /// image can be bound to multiple surfaces.
-#[derive(PartialEq, Debug, Default)]
+#[derive(PartialEq, Debug)]
 pub struct Surface {
     /// The position and size of the surface.
     pub s_rect: Rect<i32>,
     /// For rendering a surface as a constant color
     pub s_color: Option<(f32, f32, f32, f32)>,
+    /// For rendering a surface as a gradient. This takes precedence
+    /// over `s_color`.
+    pub s_gradient: Option<Gradient>,
+    /// Multiplied with the alpha of the surface contents. 1.0 is opaque.
+    pub s_opacity: f32,
+    /// Multiplied with the color of the surface contents. White leaves
+    /// them unchanged.
+    pub s_tint: (f32, f32, f32),
+    /// Transform applied to the surface contents, relative to the top
+    /// left corner of the surface.
+    pub s_transform: Transform,
+    /// The region of the attached image to draw, in image pixels. The
+    /// whole image is drawn if this is None.
+    pub s_src_rect: Option<Rect<i32>>,
+    /// The attached image holds the coverage of each color channel,
+    /// for subpixel text. See `set_subpixel`.
+    pub s_subpixel: bool,
+    /// The contents of this surface must not be captured. See
+    /// `set_protected`.
+    pub s_protected: bool,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self::new(Rect::new(0, 0, 0, 0), None)
+    }
 }
 
 impl Surface {
@@ -26,6 +109,13 @@ impl Surface {
         Self {
             s_rect: geometry,
             s_color: color,
+            s_gradient: None,
+            s_opacity: 1.0,
+            s_tint: (1.0, 1.0, 1.0),
+            s_transform: Transform::identity(),
+            s_src_rect: None,
+            s_subpixel: false,
+            s_protected: false,
         }
     }
 
@@ -64,4 +154,104 @@ impl Surface {
     pub fn set_color(&mut self, color: (f32, f32, f32, f32)) {
         self.s_color = Some(color);
     }
+
+    #[inline]
+    pub fn get_gradient(&self) -> Option<Gradient> {
+        self.s_gradient
+    }
+
+    /// Fill this surface with a gradient
+    ///
+    /// Surfaces with a gradient do not need an image. If an image is
+    /// also used then the gradient colors it, keeping the image's alpha.
+    #[inline]
+    pub fn set_gradient(&mut self, gradient: Gradient) {
+        self.s_gradient = Some(gradient);
+    }
+
+    #[inline]
+    pub fn get_opacity(&self) -> f32 {
+        self.s_opacity
+    }
+
+    #[inline]
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.s_opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    #[inline]
+    pub fn get_tint(&self) -> (f32, f32, f32) {
+        self.s_tint
+    }
+
+    /// Multiply the color of the surface contents by `tint`
+    ///
+    /// This is mostly useful for darkening surfaces, values above 1.0
+    /// brighten them.
+    #[inline]
+    pub fn set_tint(&mut self, tint: (f32, f32, f32)) {
+        self.s_tint = (tint.0.max(0.0), tint.1.max(0.0), tint.2.max(0.0));
+    }
+
+    #[inline]
+    pub fn get_transform(&self) -> Transform {
+        self.s_transform
+    }
+
+    #[inline]
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.s_transform = transform;
+    }
+
+    #[inline]
+    pub fn get_src_rect(&self) -> Option<Rect<i32>> {
+        self.s_src_rect
+    }
+
+    /// Only draw part of the attached image
+    ///
+    /// `src` is in image pixels, and is stretched to fill the surface.
+    /// This lets one image be split across many surfaces, such as for
+    /// nine-patch borders.
+    #[inline]
+    pub fn set_src_rect(&mut self, src: Rect<i32>) {
+        self.s_src_rect = Some(src);
+    }
+
+    /// Draw this surface's image as subpixel coverage
+    ///
+    /// The red, green, and blue channels of the image are the coverage of
+    /// each subpixel, and the alpha channel is their average. The surface
+    /// color (or white) is blended into each channel separately. If the
+    /// Display does not support subpixel rendering then this is drawn
+    /// using the alpha channel.
+    #[inline]
+    pub fn set_subpixel(&mut self, subpixel: bool) {
+        self.s_subpixel = subpixel;
+    }
+
+    /// Keep this surface out of screenshots and screencasts
+    ///
+    /// The surface is drawn normally, but the area it covers is filled
+    /// with black in any frames read back from the Display. Surfaces
+    /// showing protected Images do this automatically.
+    #[inline]
+    pub fn set_protected(&mut self, protected: bool) {
+        self.s_protected = protected;
+    }
+
+    /// Get the area of the screen covered by this surface
+    ///
+    /// This is the bounding box of the surface after its transform
+    /// has been applied.
+    pub fn get_bounding_box(&self) -> Rect<i32> {
+        let local = Rect::new(0, 0, self.s_rect.r_size.0, self.s_rect.r_size.1);
+        let bbox = self.s_transform.bounding_box(&local);
+        Rect::new(
+            self.s_rect.r_pos.0 + bbox.r_pos.0,
+            self.s_rect.r_pos.1 + bbox.r_pos.1,
+            bbox.r_size.0,
+            bbox.r_size.1,
+        )
+    }
 }
@@ -60,6 +60,7 @@ fn basic_image() {
             size, // width of texture
             size, // height of texture
             size, // stride
+            th::ShmFormat::ARGB8888,
             None,
         )
         .unwrap();
@@ -102,6 +103,102 @@ fn basic_color() {
     check_pixels(&mut display, "basic_color.ppm");
 }
 
+#[test]
+fn gradients() {
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    // Red on the left fading to blue on the right
+    let mut linear = th::Surface::new(th::Rect::new(0, 0, 64, 64), None);
+    linear.set_gradient(th::Gradient::linear(
+        (0.0, 0.0),
+        (64.0, 0.0),
+        (1.0, 0.0, 0.0, 1.0),
+        (0.0, 0.0, 1.0, 1.0),
+    ));
+    // White in the center fading to black at the edges
+    let mut radial = th::Surface::new(th::Rect::new(64, 0, 64, 64), None);
+    radial.set_gradient(th::Gradient::radial(
+        (32.0, 32.0),
+        32.0,
+        (1.0, 1.0, 1.0, 1.0),
+        (0.0, 0.0, 0.0, 1.0),
+    ));
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.draw_surface(&linear, None).unwrap();
+        frame.draw_surface(&radial, None).unwrap();
+        frame.present().unwrap();
+    }
+
+    // Pixels are read back as BGRA
+    let pixels = display.read_frame().mi_data;
+    let pixel = |x: usize, y: usize| {
+        let i = (y * res.0 as usize + x) * 4;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    };
+
+    let left = pixel(1, 32);
+    assert!(left[2] > 240 && left[0] < 16);
+    let right = pixel(62, 32);
+    assert!(right[0] > 240 && right[2] < 16);
+    let middle = pixel(32, 32);
+    assert!(middle[0] > 16 && middle[0] < 240);
+    assert!(middle[2] > 16 && middle[2] < 240);
+    assert!([left, middle, right]
+        .iter()
+        .all(|p| p[1] == 0 && p[3] == 255));
+
+    let center = pixel(96, 32);
+    assert!(center[..3].iter().all(|c| *c > 240));
+    let corner = pixel(65, 1);
+    assert!(corner[..3].iter().all(|c| *c < 16));
+    let edge = pixel(80, 32);
+    assert!(edge[..3].iter().all(|c| *c > 16 && *c < 240));
+}
+
+#[test]
+fn msaa() {
+    let mut info = th::CreateInfo::builder()
+        .surface_type(th::SurfaceType::Headless)
+        .sample_count(4)
+        .build();
+    let mut thund = th::Thundr::new(&info).unwrap();
+    let display_infos = thund.get_display_info_list(&info).unwrap();
+    info.set_display_info(display_infos[0].clone());
+    let mut display = thund.get_display(&info).unwrap();
+    // Vulkan requires support for 4 samples
+    assert_eq!(display.get_sample_count(), 4);
+
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+    // A red diamond centered at (96, 96)
+    let mut surf = th::Surface::new(th::Rect::new(64, 64, 64, 64), Some((1.0, 0.0, 0.0, 1.0)));
+    surf.set_transform(th::Transform::rotate(std::f32::consts::FRAC_PI_4).around(32.0, 32.0));
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.draw_surface(&surf, None).unwrap();
+        frame.present().unwrap();
+    }
+
+    let pixels = display.read_frame().mi_data;
+    let pixel = |x: usize, y: usize| {
+        let i = (y * res.0 as usize + x) * 4;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    };
+    assert_eq!(pixel(96, 96), [0, 0, 255, 255]);
+    assert_eq!(pixel(66, 66), [0, 0, 0, 0]);
+    // The edge crosses the middle of this pixel, so only some of its
+    // samples are covered
+    let edge = pixel(120, 75);
+    assert!(edge[2] > 0 && edge[2] < 255);
+}
+
 #[test]
 fn many_colors() {
     let (_thundr, mut display) = init_thundr();
@@ -154,6 +251,7 @@ fn redraw() {
             size, // width of texture
             size, // height of texture
             size, // stride
+            th::ShmFormat::ARGB8888,
             None,
         )
         .unwrap();
@@ -179,3 +277,1084 @@ fn redraw() {
     // ------------ check output -------------
     check_pixels(&mut display, "redraw.ppm");
 }
+
+#[test]
+fn partial_redraw() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    let size = 64;
+    let u_size = size as usize;
+    let pixels: Vec<u8> = std::iter::repeat(128).take(4 * u_size * u_size).collect();
+    let image = display
+        .d_dev
+        .create_image_from_bits(
+            pixels.as_slice(),
+            size,
+            size,
+            size,
+            th::ShmFormat::ARGB8888,
+            None,
+        )
+        .unwrap();
+
+    // ------------ draw a full frame -------------
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), None);
+        frame.draw_surface(&surf, Some(&image)).unwrap();
+        frame.present().unwrap();
+    }
+
+    // ------------ move the surface, only damaging where it was and is -------------
+    let moved = th::Damage::new(vec![
+        th::Rect::new(0, 0, 16, 16),
+        th::Rect::new(32, 32, 16, 16),
+    ]);
+    {
+        let mut frame = display.acquire_next_frame_with_damage(&moved).unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        let surf = th::Surface::new(th::Rect::new(32, 32, 16, 16), None);
+        frame.draw_surface(&surf, Some(&image)).unwrap();
+        frame.present().unwrap();
+    }
+
+    // ------------ nothing changed, older images still get the move -------------
+    {
+        let mut frame = display
+            .acquire_next_frame_with_damage(&th::Damage::empty())
+            .unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        let surf = th::Surface::new(th::Rect::new(32, 32, 16, 16), None);
+        frame.draw_surface(&surf, Some(&image)).unwrap();
+        frame.present().unwrap();
+    }
+
+    // This should look the same as if everything was redrawn
+    check_pixels(&mut display, "partial_redraw.ppm");
+}
+
+#[test]
+fn instanced_surfaces() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    let size = 64;
+    let u_size = size as usize;
+    let pixels: Vec<u8> = std::iter::repeat(128).take(4 * u_size * u_size).collect();
+    let image = display
+        .d_dev
+        .create_image_from_bits(
+            pixels.as_slice(),
+            size,
+            size,
+            size,
+            th::ShmFormat::ARGB8888,
+            None,
+        )
+        .unwrap();
+
+    // Draw more small surfaces sharing one image than fit in the first
+    // storage buffer. Draw twice so that the next frame uses the
+    // combined buffer.
+    for _ in 0..2 {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        for i in 0..4096 {
+            let mut surf = th::Surface::new(th::Rect::new((i % 64) * 4, (i / 64) * 4, 4, 4), None);
+            surf.set_src_rect(th::Rect::new(0, 0, 4, 4));
+            frame.draw_surface(&surf, Some(&image)).unwrap();
+        }
+        frame.present().unwrap();
+    }
+}
+
+#[test]
+fn buffer_age() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let full = th::Rect::new(0, 0, res.0 as i32, res.1 as i32);
+    let damage = th::Damage::new(vec![th::Rect::new(8, 8, 4, 4)]);
+
+    // The headless backend cycles through two images. Both start out
+    // undefined and then hold the frame from two frames ago.
+    for (age, redraw_area) in [
+        (0, full),
+        (0, full),
+        (2, th::Rect::new(8, 8, 4, 4)),
+        (2, th::Rect::new(8, 8, 4, 4)),
+    ] {
+        let mut frame = display.acquire_next_frame_with_damage(&damage).unwrap();
+        assert_eq!(frame.get_buffer_age(), age);
+        assert_eq!(frame.get_redraw_area(), redraw_area);
+        frame.present().unwrap();
+    }
+}
+
+#[test]
+fn damage_bounding_box() {
+    assert_eq!(th::Damage::empty().bounding_box(), None);
+
+    let damage = th::Damage::new(vec![
+        th::Rect::new(10, 20, 5, 5),
+        th::Rect::new(0, 30, 4, 10),
+    ]);
+    assert_eq!(damage.bounding_box(), Some(th::Rect::new(0, 20, 15, 20)));
+}
+
+#[test]
+fn transform_bounding_box() {
+    let rect = th::Rect::new(0, 0, 20, 10);
+
+    // Identity leaves the rect alone
+    assert_eq!(th::Transform::identity().bounding_box(&rect), rect);
+
+    // Scaling about the center grows in both directions
+    let scale = th::Transform::scale(2.0, 2.0).around(10.0, 5.0);
+    assert_eq!(scale.bounding_box(&rect), th::Rect::new(-10, -5, 40, 20));
+
+    // A quarter turn around the center swaps the width and height
+    let rotate = th::Transform::rotate(std::f32::consts::FRAC_PI_2).around(10.0, 5.0);
+    assert_eq!(rotate.bounding_box(&rect), th::Rect::new(5, -5, 10, 20));
+}
+
+#[test]
+fn transformed_damage() {
+    let mut surf = th::Surface::new(th::Rect::new(100, 50, 20, 10), None);
+    surf.set_transform(th::Transform::translate(5.0, 0.0).then(&th::Transform::scale(2.0, 1.0)));
+
+    let damage = th::Damage::new(vec![th::Rect::new(0, 0, 4, 4)]);
+    let screen = damage.to_screen_space(&surf);
+    assert_eq!(
+        screen.regions().collect::<Vec<_>>(),
+        vec![&th::Rect::new(110, 50, 8, 4)]
+    );
+    assert_eq!(surf.get_bounding_box(), th::Rect::new(110, 50, 40, 10));
+}
+
+#[test]
+fn trilinear_sampling() {
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    // Opaque vertical stripes, two pixels each of white and black
+    let pixels: Vec<u8> = (0..64 * 64)
+        .flat_map(|i| match (i % 64 / 2) % 2 {
+            0 => [255, 255, 255, 255],
+            _ => [0, 0, 0, 255],
+        })
+        .collect();
+    let image = display
+        .d_dev
+        .create_image_from_bits(pixels.as_slice(), 64, 64, 0, th::ShmFormat::ARGB8888, None)
+        .unwrap();
+    // Shrunk far enough that a linear sample only blends pixels
+    // from the same stripe
+    let surf = th::Surface::new(th::Rect::new(0, 0, 5, 5), None);
+
+    let draw = |display: &mut th::Display| {
+        {
+            let mut frame = display.acquire_next_frame().unwrap();
+            frame.set_viewport(&viewport).unwrap();
+            frame.draw_surface(&surf, Some(&image)).unwrap();
+            frame.present().unwrap();
+        }
+
+        // Pixels are read back as BGRA
+        let pixels = display.read_frame().mi_data;
+        let i = (res.0 as usize + 1) * 4;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    };
+
+    let linear = draw(&mut display);
+    assert!(linear[..3].iter().all(|c| *c < 16 || *c > 240));
+
+    // The mip chain is made from the current contents, and the
+    // smaller levels average the stripes to gray
+    display
+        .d_dev
+        .set_image_sampler_mode(&image, th::SamplerMode::Trilinear)
+        .unwrap();
+    assert_eq!(image.get_sampler_mode(), th::SamplerMode::Trilinear);
+    let trilinear = draw(&mut display);
+    assert!(trilinear[..3].iter().all(|c| *c > 96 && *c < 160));
+    assert_eq!(trilinear[3], 255);
+
+    // Going back drops the mip chain
+    display
+        .d_dev
+        .set_image_sampler_mode(&image, th::SamplerMode::Linear)
+        .unwrap();
+    assert_eq!(draw(&mut display), linear);
+}
+
+#[test]
+fn color_lut_from_cube() {
+    let cube = "# A small table that swaps red and blue
+TITLE \"swap\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0.0 0.0 0.0
+DOMAIN_MAX 1.0 1.0 1.0
+0.0 0.0 0.0
+0.0 0.0 1.0
+0.0 1.0 0.0
+0.0 1.0 1.0
+1.0 0.0 0.0
+1.0 0.0 1.0
+1.0 1.0 0.0
+1.0 1.0 1.0
+";
+    let lut = th::ColorLut::from_cube(cube).unwrap();
+    assert_eq!(lut.get_size(), 2);
+    // Red is the fastest changing index
+    assert_eq!(lut.cl_data[1], [0.0, 0.0, 1.0]);
+    assert_eq!(lut.cl_data[4], [1.0, 0.0, 0.0]);
+
+    // Missing entries and unsupported tables are rejected
+    assert!(th::ColorLut::from_cube("LUT_3D_SIZE 2\n0.0 0.0 0.0\n").is_err());
+    assert!(th::ColorLut::from_cube("LUT_1D_SIZE 2\n0.0 0.0 0.0\n1.0 1.0 1.0\n").is_err());
+    assert!(th::ColorLut::from_cube(&cube.replace("DOMAIN_MAX 1.0", "DOMAIN_MAX 2.0")).is_err());
+}
+
+#[test]
+fn color_lut_from_icc() {
+    // Build a matrix/TRC profile with the sRGB primaries and curve
+    let s15f16 = |v: f32| ((v * 65536.0).round() as i32).to_be_bytes();
+    let primaries = [
+        (b"rXYZ", [0.4360747, 0.2225045, 0.0139322]),
+        (b"gXYZ", [0.3850649, 0.7168786, 0.0971045]),
+        (b"bXYZ", [0.1430804, 0.0606169, 0.7141733]),
+    ];
+    let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+    for (sig, xyz) in primaries.iter() {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for v in xyz.iter() {
+            tag.extend_from_slice(&s15f16(*v));
+        }
+        tags.push((*sig, tag));
+    }
+    let mut curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+    for v in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045].iter() {
+        curve.extend_from_slice(&s15f16(*v));
+    }
+    for sig in [b"rTRC", b"gTRC", b"bTRC"].iter() {
+        tags.push((*sig, curve.clone()));
+    }
+
+    let mut icc = vec![0; 128];
+    icc[16..20].copy_from_slice(b"RGB ");
+    icc[36..40].copy_from_slice(b"acsp");
+    icc.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    let mut offset = 132 + tags.len() * 12;
+    for (sig, tag) in tags.iter() {
+        icc.extend_from_slice(&sig[..]);
+        icc.extend_from_slice(&(offset as u32).to_be_bytes());
+        icc.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        offset += tag.len();
+    }
+    for (_, tag) in tags.iter() {
+        icc.extend_from_slice(tag);
+    }
+
+    // An sRGB display needs no correction
+    let lut = th::ColorLut::from_icc(&icc).unwrap();
+    let identity = th::ColorLut::identity(lut.get_size());
+    for (a, b) in lut.cl_data.iter().zip(identity.cl_data.iter()) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 0.01, "{:?} != {:?}", a, b);
+        }
+    }
+
+    assert!(th::ColorLut::from_icc(&icc[..100]).is_err());
+}
+
+#[test]
+fn color_lut_gamma_ramps() {
+    let mut lut = th::ColorLut::identity(3);
+    // Halve red, invert green, and leave blue alone
+    let red = [0, 32767];
+    let green = [65535, 0];
+    let blue = [0, 32767, 65535];
+    lut.apply_gamma_ramps(&red, &green, &blue).unwrap();
+
+    assert_eq!(lut.cl_data[0], [0.0, 1.0, 0.0]);
+    let last = lut.cl_data[lut.cl_data.len() - 1];
+    assert!((last[0] - 0.5).abs() < 0.001);
+    assert_eq!(last[1], 0.0);
+    assert_eq!(last[2], 1.0);
+
+    assert!(lut.apply_gamma_ramps(&[0], &green, &blue).is_err());
+}
+
+#[test]
+fn memory_usage() {
+    let (mut _thund, display) = init_thundr();
+    let before = display.d_dev.get_memory_usage().total_allocated();
+
+    let pixels: Vec<u8> = std::iter::repeat_n(128, 4 * 64 * 64).collect();
+    let _image = display
+        .d_dev
+        .create_image_from_bits(pixels.as_slice(), 64, 64, 64, th::ShmFormat::ARGB8888, None)
+        .unwrap();
+
+    let usage = display.d_dev.get_memory_usage();
+    assert!(usage.total_allocated() >= before + 4 * 64 * 64);
+    for heap in usage.mu_heaps.iter() {
+        assert!(heap.hu_allocated <= heap.hu_budget);
+    }
+}
+
+#[test]
+fn low_memory_callbacks() {
+    use ash::vk::Handle;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let props = ash::vk::PhysicalDeviceMemoryProperties {
+        memory_heap_count: 1,
+        ..Default::default()
+    };
+    let mut tracker = crate::memory::MemoryTracker::new(&props);
+
+    let mem = ash::vk::DeviceMemory::from_raw(1);
+    tracker.add_allocation(mem, 0, 4096);
+    assert_eq!(tracker.get_allocated(0), 4096);
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let cb_count = count.clone();
+    tracker.add_callback(Box::new(move |_| {
+        cb_count.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    // Callbacks are only handed out when we first cross the threshold
+    let usage = th::MemoryUsage {
+        mu_heaps: Vec::new(),
+    };
+    assert!(tracker.update_near_budget(false).is_none());
+    let mut callbacks = tracker.update_near_budget(true).unwrap();
+    for callback in callbacks.iter_mut() {
+        callback(&usage);
+    }
+    tracker.restore_callbacks(callbacks);
+    assert!(tracker.update_near_budget(true).is_none());
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // Dropping below the threshold lets them be called again
+    assert!(tracker.update_near_budget(false).is_none());
+    assert_eq!(tracker.update_near_budget(true).unwrap().len(), 1);
+
+    tracker.remove_allocation(mem);
+    assert_eq!(tracker.get_allocated(0), 0);
+}
+
+#[test]
+fn suballocator() {
+    use crate::allocator::{Allocator, BLOCK_SIZE};
+    use ash::vk::Handle;
+
+    let mut allocator = Allocator::new();
+    let first = ash::vk::DeviceMemory::from_raw(1);
+    let second = ash::vk::DeviceMemory::from_raw(2);
+
+    // Nothing can be allocated until we have a block of that type
+    assert!(allocator.allocate(0, false, 4096, 256).is_none());
+    allocator.add_block(0, false, first, BLOCK_SIZE, std::ptr::null_mut());
+    assert!(allocator.allocate(0, true, 4096, 256).is_none());
+
+    let a = allocator.allocate(0, false, 100, 256).unwrap();
+    let b = allocator.allocate(0, false, 4096, 256).unwrap();
+    let c = allocator.allocate(0, false, 4096, 256).unwrap();
+    assert_eq!(a.a_memory, first);
+    assert_eq!(a.a_offset, 0);
+    assert_eq!(b.a_offset, 256);
+    assert_eq!(c.a_offset, 256 + 4096);
+
+    let stats = allocator.get_stats();
+    assert_eq!(stats.as_block_count, 1);
+    assert_eq!(stats.as_allocation_count, 3);
+    // The padding after `a` is a free region of its own
+    assert_eq!(stats.as_free_region_count, 2);
+
+    // Freeing the middle allocation fragments the free space, and
+    // freeing the rest merges it back into a single region
+    assert!(allocator.free(&b).is_none());
+    assert!(allocator.get_stats().fragmentation() > 0.0);
+    assert!(allocator.free(&a).is_none());
+    assert!(allocator.free(&c).is_none());
+    let stats = allocator.get_stats();
+    assert_eq!(stats.as_allocation_count, 0);
+    assert_eq!(stats.as_used_bytes, 0);
+    assert_eq!(stats.as_free_region_count, 1);
+    assert_eq!(stats.as_largest_free_region, BLOCK_SIZE);
+    assert_eq!(stats.fragmentation(), 0.0);
+
+    // An empty block is only released if another block can take its place
+    allocator.add_block(0, false, second, BLOCK_SIZE, std::ptr::null_mut());
+    let big = allocator.allocate(0, false, BLOCK_SIZE, 256).unwrap();
+    assert_eq!(big.a_memory, first);
+    let d = allocator.allocate(0, false, 4096, 256).unwrap();
+    assert_eq!(d.a_memory, second);
+    assert_eq!(allocator.free(&big), Some(first));
+    assert!(allocator.free(&d).is_none());
+    assert_eq!(allocator.drain_blocks(), vec![second]);
+}
+
+#[test]
+fn allocator_stats() {
+    let (mut _thund, display) = init_thundr();
+    let before = display.d_dev.get_allocator_stats();
+
+    let pixels: Vec<u8> = std::iter::repeat_n(128, 4 * 64 * 64).collect();
+    let _image = display
+        .d_dev
+        .create_image_from_bits(pixels.as_slice(), 64, 64, 64, th::ShmFormat::ARGB8888, None)
+        .unwrap();
+
+    // Small images are suballocated instead of getting their own memory
+    let stats = display.d_dev.get_allocator_stats();
+    assert!(stats.as_allocation_count > before.as_allocation_count);
+    assert_eq!(stats.as_dedicated_count, before.as_dedicated_count);
+}
+
+#[test]
+fn staging_pool() {
+    use crate::allocator::Allocation;
+    use crate::staging::{StagingBuffer, StagingPool, StagingRelease, STAGING_MIN_SIZE};
+    use ash::vk::Handle;
+    use std::sync::{Arc, Mutex};
+
+    let buffer = |handle: u64, size: u64| StagingBuffer {
+        sb_buffer: ash::vk::Buffer::from_raw(handle),
+        sb_memory: Allocation::null(),
+        sb_size: size,
+    };
+
+    assert_eq!(StagingPool::buffer_size_for(16), STAGING_MIN_SIZE);
+    assert_eq!(
+        StagingPool::buffer_size_for(STAGING_MIN_SIZE + 1),
+        STAGING_MIN_SIZE * 2
+    );
+
+    // The smallest buffer that fits is reused
+    let pool = Arc::new(Mutex::new(StagingPool::new()));
+    pool.lock().unwrap().put(buffer(1, STAGING_MIN_SIZE * 4));
+    pool.lock().unwrap().put(buffer(2, STAGING_MIN_SIZE));
+    pool.lock().unwrap().put(buffer(3, STAGING_MIN_SIZE * 2));
+    let taken = pool.lock().unwrap().take(STAGING_MIN_SIZE + 1).unwrap();
+    assert_eq!(taken.sb_buffer.as_raw(), 3);
+    assert!(pool.lock().unwrap().take(STAGING_MIN_SIZE * 8).is_none());
+
+    // Buffers are returned to the pool once their timeline point is reached
+    let mut queue = crate::DeletionQueue::new();
+    queue.schedule_drop_at_point(
+        Box::new(StagingRelease {
+            sr_pool: pool.clone(),
+            sr_buffer: Some(taken),
+        }),
+        2,
+    );
+    queue.drop_all_at_point(1);
+    assert_eq!(pool.lock().unwrap().free_count(), 2);
+    queue.drop_all_at_point(2);
+    assert_eq!(pool.lock().unwrap().free_count(), 3);
+
+    let smallest = pool.lock().unwrap().take_smallest().unwrap();
+    assert_eq!(smallest.sb_buffer.as_raw(), 2);
+    assert_eq!(pool.lock().unwrap().drain().len(), 2);
+}
+
+#[test]
+fn deletion_queue() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Counted(Arc<AtomicUsize>);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let mut queue = crate::DeletionQueue::new();
+    assert!(queue
+        .schedule_drop_at_point(Box::new(Counted(dropped.clone())), 1)
+        .is_none());
+    assert!(queue
+        .schedule_drop_at_point(Box::new(Counted(dropped.clone())), 3)
+        .is_none());
+
+    // Items are handed back to be dropped once their point is reached
+    let items = queue.take_all_at_point(2);
+    assert_eq!(items.len(), 1);
+    assert_eq!(dropped.load(Ordering::SeqCst), 0);
+    drop(items);
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+
+    // Points that have already been reached don't queue anything
+    let item = queue.schedule_drop_at_point(Box::new(Counted(dropped.clone())), 2);
+    assert!(item.is_some());
+    drop(item);
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+
+    queue.drop_all_at_point(u64::MAX);
+    assert_eq!(dropped.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn gpu_timings() {
+    let (mut _thund, mut display) = init_thundr();
+    match display.set_gpu_profiling(true) {
+        Ok(()) => {}
+        // Nothing to test on this device
+        Err(th::ThundrError::TIMESTAMPS_NOT_SUPPORTED) => return,
+        Err(e) => panic!("Could not enable GPU profiling: {:?}", e),
+    }
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    let pixels: Vec<u8> = std::iter::repeat_n(128, 4 * 64 * 64).collect();
+    let image = display
+        .d_dev
+        .create_image_from_bits(pixels.as_slice(), 64, 64, 64, th::ShmFormat::ARGB8888, None)
+        .unwrap();
+    let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), None);
+
+    for _ in 0..3 {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.draw_surface(&surf, Some(&image)).unwrap();
+        frame.present().unwrap();
+    }
+    display.d_dev.wait_idle();
+
+    // Every frame is reported once the GPU is done with it
+    let timings = display.take_gpu_timings();
+    let frames: Vec<u64> = timings.iter().map(|t| t.gt_frame).collect();
+    assert_eq!(frames, vec![1, 2, 3]);
+    assert!(display.take_gpu_timings().is_empty());
+
+    // Nothing is recorded once profiling is turned off
+    display.set_gpu_profiling(false).unwrap();
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.present().unwrap();
+    }
+    assert!(display.take_gpu_timings().is_empty());
+}
+
+#[test]
+fn frame_sync() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+    let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), Some((1.0, 0.0, 0.0, 1.0)));
+
+    let mut points = Vec::new();
+    for _ in 0..2 {
+        {
+            let mut frame = display.acquire_next_frame().unwrap();
+            frame.set_viewport(&viewport).unwrap();
+            frame.draw_surface(&surf, None).unwrap();
+            frame.present().unwrap();
+        }
+        points.push(display.get_last_frame_point());
+    }
+    // Each frame signals a later point than the one before it
+    assert!(points[1] > points[0]);
+
+    let sync_file = match display.export_frame_sync_file() {
+        Ok(fd) => Some(fd),
+        // The timeline point is still usable without sync files
+        Err(th::ThundrError::SYNC_FILE_NOT_SUPPORTED) => None,
+        Err(e) => panic!("Could not export sync file: {:?}", e),
+    };
+
+    display.d_dev.wait_idle();
+    assert!(display.d_dev.is_point_signaled(points[1]));
+
+    // A signaled sync file is readable
+    if let Some(fd) = sync_file {
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+        use std::os::unix::io::AsFd;
+        let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLIN)];
+        assert_eq!(poll(&mut fds, PollTimeout::ZERO).unwrap(), 1);
+    }
+}
+
+#[test]
+fn export_frames() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+    let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), Some((1.0, 0.0, 0.0, 1.0)));
+
+    for _ in 0..4 {
+        {
+            let mut frame = display.acquire_next_frame().unwrap();
+            frame.set_viewport(&viewport).unwrap();
+            frame.draw_surface(&surf, None).unwrap();
+            frame.present().unwrap();
+        }
+        let frame_point = display.get_last_frame_point();
+
+        let dmabuf = match display.export_frame() {
+            Ok(dmabuf) => dmabuf,
+            // Not every swapchain format can be exported
+            Err(th::ThundrError::INVALID_FORMAT) => return,
+            Err(e) => panic!("Could not export frame: {:?}", e),
+        };
+        assert_eq!(dmabuf.db_width, res.0 as i32);
+        assert_eq!(dmabuf.db_height, res.1 as i32);
+        assert_eq!(dmabuf.db_planes.len(), 1);
+        assert!(dmabuf.db_planes[0].db_stride >= res.0 * 4);
+
+        // The copy is not waited on, it is signaled after the frame
+        let export_point = display.get_last_frame_point();
+        assert!(export_point > frame_point);
+        display.d_dev.wait_idle();
+        assert!(display.d_dev.is_point_signaled(export_point));
+    }
+}
+
+#[test]
+fn nested_viewports() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+    let red = th::Surface::new(th::Rect::new(0, 0, 64, 64), Some((1.0, 0.0, 0.0, 1.0)));
+    let blue = th::Surface::new(th::Rect::new(0, 0, 64, 64), Some((0.0, 0.0, 1.0, 1.0)));
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        // A scrolling region inside another one is clipped to both
+        frame.push_viewport(&th::Viewport::new(8, 8, 16, 16));
+        frame.push_viewport(&th::Viewport::new(16, 16, 32, 32));
+        frame.draw_surface(&red, None).unwrap();
+        frame.pop_viewport();
+        frame.pop_viewport();
+        // The outer viewport is back in use
+        frame.push_viewport(&th::Viewport::new(40, 40, 8, 8));
+        frame.draw_surface(&blue, None).unwrap();
+        frame.pop_viewport();
+        frame.present().unwrap();
+    }
+
+    let pixels = display.read_frame().mi_data;
+    let pixel = |x: usize, y: usize| {
+        let i = (y * res.0 as usize + x) * 4;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    };
+    assert_eq!(pixel(4, 4), [0, 0, 0, 0]);
+    assert_eq!(pixel(12, 12), [0, 0, 0, 0]);
+    assert_eq!(pixel(20, 20), [0, 0, 255, 255]);
+    assert_eq!(pixel(30, 30), [0, 0, 0, 0]);
+    assert_eq!(pixel(44, 44), [255, 0, 0, 255]);
+    assert_eq!(pixel(50, 50), [0, 0, 0, 0]);
+}
+
+#[test]
+fn mesh_bounding_box() {
+    // Partial triangles and out of bounds indices are rejected
+    let verts = vec![
+        th::MeshVertex::new((0.0, 0.0), (0.0, 0.0)),
+        th::MeshVertex::new((10.5, 0.0), (1.0, 0.0)),
+        th::MeshVertex::new((0.0, 4.0), (0.0, 1.0)),
+    ];
+    assert!(th::Mesh::new(verts.clone(), vec![0, 1], None).is_err());
+    assert!(th::Mesh::new(verts.clone(), vec![0, 1, 3], None).is_err());
+
+    let mut mesh = th::Mesh::new(verts, vec![0, 1, 2], Some((1.0, 0.0, 0.0, 1.0))).unwrap();
+    mesh.set_pos(100, 50);
+    assert_eq!(mesh.get_bounding_box(), Some(th::Rect::new(100, 50, 11, 4)));
+
+    let empty = th::Mesh::new(Vec::new(), Vec::new(), None).unwrap();
+    assert_eq!(empty.get_bounding_box(), None);
+}
+
+#[test]
+fn meshes() {
+    let (mut _thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    // A fan of triangles approximating a circle
+    let mut verts = vec![th::MeshVertex::new((0.0, 0.0), (0.5, 0.5))];
+    let mut indices = Vec::new();
+    for i in 0..64 {
+        let angle = i as f32 / 64.0 * std::f32::consts::TAU;
+        verts.push(th::MeshVertex::new(
+            (angle.cos() * 16.0, angle.sin() * 16.0),
+            (0.5 + angle.cos() / 2.0, 0.5 + angle.sin() / 2.0),
+        ));
+        indices.extend_from_slice(&[0, i + 1, (i + 1) % 64 + 1]);
+    }
+    let mut mesh = th::Mesh::new(verts, indices, Some((0.0, 0.0, 1.0, 1.0))).unwrap();
+
+    // Draw more meshes than fit in the first mesh buffer, between
+    // surfaces. Draw twice so that the next frame uses the combined buffer.
+    for _ in 0..2 {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        for i in 0..64 {
+            let surf = th::Surface::new(
+                th::Rect::new((i % 8) * 32, (i / 8) * 32, 8, 8),
+                Some((1.0, 0.0, 0.0, 1.0)),
+            );
+            frame.draw_surface(&surf, None).unwrap();
+            mesh.set_pos((i % 8) * 32 + 16, (i / 8) * 32 + 16);
+            frame.draw_mesh(&mesh, None).unwrap();
+        }
+        frame.present().unwrap();
+    }
+}
+
+#[test]
+fn parallel_recording() {
+    let mut info = th::CreateInfo::builder()
+        .surface_type(th::SurfaceType::Headless)
+        .record_threads(4)
+        .build();
+    let mut _thund = th::Thundr::new(&info).unwrap();
+    let display_infos = _thund.get_display_info_list(&info).unwrap();
+    info.set_display_info(display_infos[0].clone());
+    let mut display = _thund.get_display(&info).unwrap();
+
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+    let square = |color| {
+        let verts = vec![
+            th::MeshVertex::new((0.0, 0.0), (0.0, 0.0)),
+            th::MeshVertex::new((4.0, 0.0), (1.0, 0.0)),
+            th::MeshVertex::new((0.0, 4.0), (0.0, 1.0)),
+            th::MeshVertex::new((4.0, 4.0), (1.0, 1.0)),
+        ];
+        th::Mesh::new(verts, vec![0, 1, 2, 1, 3, 2], Some(color)).unwrap()
+    };
+    let mut red = square((1.0, 0.0, 0.0, 1.0));
+    let mut blue = square((0.0, 0.0, 1.0, 1.0));
+
+    // Every mesh is its own draw, so this is split between the threads.
+    // Each chunk has to pick up the geometry and descriptors bound by
+    // the chunk before it.
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        for i in 0..512 {
+            let mesh = match i % 2 {
+                0 => &mut red,
+                _ => &mut blue,
+            };
+            mesh.set_pos((i % 32) * 8, (i / 32) * 8);
+            frame.draw_mesh(mesh, None).unwrap();
+        }
+        frame.present().unwrap();
+    }
+
+    let pixels = display.read_frame().mi_data;
+    for i in 0..512 {
+        let (x, y) = ((i % 32) * 8 + 2, (i / 32) * 8 + 2);
+        let p = (y * res.0 as usize + x) * 4;
+        let expected = match i % 2 {
+            0 => [0, 0, 255, 255],
+            _ => [255, 0, 0, 255],
+        };
+        assert_eq!(pixels[p..p + 4], expected);
+        // The gaps between them stay clear
+        assert_eq!(pixels[p + 16..p + 20], [0, 0, 0, 0]);
+    }
+}
+
+#[test]
+fn protected_surfaces() {
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    let mut protected = th::Surface::new(th::Rect::new(0, 0, 16, 16), Some((1.0, 1.0, 1.0, 1.0)));
+    protected.set_protected(true);
+    let visible = th::Surface::new(th::Rect::new(32, 0, 16, 16), Some((1.0, 1.0, 1.0, 1.0)));
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.draw_surface(&protected, None).unwrap();
+        frame.draw_surface(&visible, None).unwrap();
+        frame.present().unwrap();
+    }
+
+    // Protected content is still hidden when it isn't redrawn
+    {
+        let mut frame = display
+            .acquire_next_frame_with_damage(&th::Damage::empty())
+            .unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.draw_surface(&protected, None).unwrap();
+        frame.draw_surface(&visible, None).unwrap();
+        frame.present().unwrap();
+    }
+
+    let stride = res.0 as usize * 4;
+    let pixels = display.read_frame().mi_data;
+    let pixel = |x: usize, y: usize| &pixels[y * stride + x * 4..y * stride + x * 4 + 4];
+    assert_eq!(pixel(8, 8), &[0, 0, 0, 0]);
+    assert_eq!(pixel(40, 8), &[255, 255, 255, 255]);
+}
+
+#[test]
+fn shm_formats() {
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    let formats = display.d_dev.get_supported_shm_formats();
+    assert!(formats.contains(&th::ShmFormat::ARGB8888));
+    assert!(formats.contains(&th::ShmFormat::XRGB8888));
+
+    // Opaque red with a zeroed padding byte, which must not be read as alpha
+    let red: Vec<u8> = [255, 0, 0, 0].repeat(4 * 4);
+    let image = display.d_dev.create_image_from_bits(
+        red.as_slice(),
+        4,
+        4,
+        0,
+        th::ShmFormat::XBGR8888,
+        None,
+    );
+    let image = match formats.contains(&th::ShmFormat::XBGR8888) {
+        true => image.unwrap(),
+        false => {
+            assert_eq!(image.err(), Some(th::ThundrError::INVALID_FORMAT));
+            return;
+        }
+    };
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), None);
+        frame.draw_surface(&surf, Some(&image)).unwrap();
+        frame.present().unwrap();
+    }
+
+    let pixels = display.read_frame().mi_data;
+    assert_eq!(&pixels[0..4], &[0, 0, 255, 255]);
+}
+
+#[test]
+fn host_memory_images() {
+    use std::alloc::{alloc_zeroed, dealloc, Layout};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct Released(Arc<AtomicBool>);
+    impl Drop for Released {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    // Opaque red, in page aligned memory like a shm pool
+    let layout = Layout::from_size_align(64 * 64 * 4, 4096).unwrap();
+    let data = unsafe {
+        let ptr = alloc_zeroed(layout);
+        std::slice::from_raw_parts_mut(ptr, layout.size())
+    };
+    for pixel in data.chunks_mut(4) {
+        pixel.copy_from_slice(&[0, 0, 255, 255]);
+    }
+
+    let released = Arc::new(AtomicBool::new(false));
+    let image = unsafe {
+        display.d_dev.create_image_from_host_memory(
+            data,
+            64,
+            64,
+            0,
+            th::ShmFormat::ARGB8888,
+            Some(Box::new(Released(released.clone()))),
+        )
+    };
+    let image = match image {
+        Ok(image) => image,
+        // Callers copy the contents instead
+        Err(th::ThundrError::HOST_MEMORY_NOT_SUPPORTED) => {
+            unsafe { dealloc(data.as_mut_ptr(), layout) };
+            return;
+        }
+        Err(e) => panic!("Could not create image from host memory: {:?}", e),
+    };
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), None);
+        frame.draw_surface(&surf, Some(&image)).unwrap();
+        frame.present().unwrap();
+    }
+
+    let pixels = display.read_frame().mi_data;
+    assert_eq!(&pixels[0..4], &[0, 0, 255, 255]);
+
+    // The memory can't be released until the GPU is done with it
+    drop(image);
+    drop(display);
+    assert!(released.load(Ordering::SeqCst));
+    unsafe { dealloc(data.as_mut_ptr(), layout) };
+}
+
+#[test]
+fn scaled_copies() {
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    // Opaque red
+    let pixels: Vec<u8> = std::iter::repeat_n([0, 0, 255, 255], 64 * 64)
+        .flatten()
+        .collect();
+    let image = display
+        .d_dev
+        .create_image_from_bits(pixels.as_slice(), 64, 64, 0, th::ShmFormat::ARGB8888, None)
+        .unwrap();
+
+    let copy = match display.d_dev.create_scaled_copy(&image, 16, 16) {
+        Ok(copy) => copy,
+        // Some drivers can't blit from linear images
+        Err(th::ThundrError::INVALID) => return,
+        Err(e) => panic!("Could not create scaled copy: {:?}", e),
+    };
+    assert_eq!(copy.get_size(), (16, 16));
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), None);
+        frame.draw_surface(&surf, Some(&copy)).unwrap();
+        frame.present().unwrap();
+    }
+
+    let pixels = display.read_frame().mi_data;
+    assert_eq!(&pixels[0..4], &[0, 0, 255, 255]);
+}
+
+#[test]
+fn present_modes() {
+    let (_thund, mut display) = init_thundr();
+
+    let modes = display.get_supported_present_modes();
+    assert!(modes.contains(&th::PresentMode::Fifo));
+    for mode in modes.iter() {
+        display.set_present_mode(*mode).unwrap();
+        assert_eq!(display.get_present_mode(), *mode);
+    }
+
+    // Modes the display doesn't list must be rejected
+    for mode in [th::PresentMode::Mailbox, th::PresentMode::Immediate] {
+        if !modes.contains(&mode) {
+            assert_eq!(
+                display.set_present_mode(mode).err(),
+                Some(th::ThundrError::INVALID)
+            );
+        }
+    }
+}
+
+#[test]
+fn resize_scaling() {
+    let (_thund, mut display) = init_thundr();
+    let (width, height) = display.get_resolution();
+
+    // Keep the resize pending so that the old images are drawn to
+    display.set_resize_debounce(std::time::Duration::from_secs(60));
+    display.set_resize_mode(th::ResizeMode::Scale);
+    display.recreate_swapchain(Some((width / 2, height / 2)));
+    assert!(display.is_resize_pending());
+    assert_eq!(display.get_resolution(), (width / 2, height / 2));
+
+    {
+        let mut frame = display.acquire_next_frame().unwrap();
+        let viewport = th::Viewport::new(0, 0, width as i32 / 2, height as i32 / 2);
+        frame.set_viewport(&viewport).unwrap();
+        // The bottom right quarter of the new size
+        let surf = th::Surface::new(
+            th::Rect::new(
+                width as i32 / 4,
+                height as i32 / 4,
+                width as i32 / 4,
+                height as i32 / 4,
+            ),
+            Some((1.0, 0.0, 0.0, 1.0)),
+        );
+        frame.draw_surface(&surf, None).unwrap();
+        frame.present().unwrap();
+    }
+
+    // It is stretched over the bottom right quarter of the old images
+    let pixels = display.read_frame().mi_data;
+    let last = pixels.len() - 4;
+    assert_eq!(&pixels[last..], &[0, 0, 255, 255]);
+    assert_eq!(&pixels[0..4], &[0, 0, 0, 0]);
+
+    // The headless swapchain keeps its size once the resize is applied
+    display.handle_ood().unwrap();
+    assert!(!display.is_resize_pending());
+    assert_eq!(display.get_resolution(), (width, height));
+}
+
+#[test]
+fn pipeline_cache() {
+    let (_thund, mut display) = init_thundr();
+    let res = display.get_resolution();
+    let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+
+    let spaces = display.get_supported_color_spaces();
+    display
+        .d_dev
+        .prewarm_pipelines(&spaces, display.get_sample_count(), true);
+    display.d_dev.save_pipeline_cache().unwrap();
+
+    // The display's own pipelines must still work afterwards
+    let mut frame = display.acquire_next_frame().unwrap();
+    frame.set_viewport(&viewport).unwrap();
+    let surf = th::Surface::new(th::Rect::new(0, 0, 16, 16), None);
+    frame.draw_surface(&surf, None).unwrap();
+    frame.present().unwrap();
+}
+
+#[test]
+fn device_loss() {
+    let (_thund, mut display) = init_thundr();
+
+    // Other errors are only logged and leave the device usable
+    display.d_dev.check_vk_result(
+        Err(ash::vk::Result::ERROR_OUT_OF_DEVICE_MEMORY),
+        "Testing error handling",
+    );
+    assert!(!display.d_dev.is_lost());
+    {
+        let res = display.get_resolution();
+        let viewport = th::Viewport::new(0, 0, res.0 as i32, res.1 as i32);
+        let mut frame = display.acquire_next_frame().unwrap();
+        frame.set_viewport(&viewport).unwrap();
+        frame.present().unwrap();
+    }
+
+    // Nothing can be drawn once the device is lost
+    display.d_dev.simulate_device_loss();
+    assert!(display.d_dev.is_lost());
+    assert_eq!(
+        display.acquire_next_frame().err(),
+        Some(th::ThundrError::DEVICE_LOST)
+    );
+}
@@ -0,0 +1,139 @@
+// 2D affine transforms for surfaces
+//
+// A transform is applied to a surface's contents in surface-local pixel
+// coordinates, with (0, 0) at the surface's top left corner. The result
+// is then placed at the surface's position.
+//
+// Austin Shafer - 2024
+use utils::region::Rect;
+
+/// A 2x3 affine transform
+///
+/// A point (x, y) is mapped to:
+///   x' = a * x + c * y + tx
+///   y' = b * x + d * y + ty
+///
+/// The matrix is stored column major as [a, b, c, d], which matches the
+/// layout of a GLSL mat2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub t_matrix: [f32; 4],
+    pub t_translation: (f32, f32),
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            t_matrix: [1.0, 0.0, 0.0, 1.0],
+            t_translation: (0.0, 0.0),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::identity()
+    }
+
+    pub fn translate(x: f32, y: f32) -> Self {
+        Self {
+            t_matrix: [1.0, 0.0, 0.0, 1.0],
+            t_translation: (x, y),
+        }
+    }
+
+    pub fn scale(x: f32, y: f32) -> Self {
+        Self {
+            t_matrix: [x, 0.0, 0.0, y],
+            t_translation: (0.0, 0.0),
+        }
+    }
+
+    /// Rotate clockwise on screen by `radians`
+    pub fn rotate(radians: f32) -> Self {
+        let (sin, cos) = radians.sin_cos();
+        Self {
+            t_matrix: [cos, sin, -sin, cos],
+            t_translation: (0.0, 0.0),
+        }
+    }
+
+    /// Skew along the x and y axis by the given angles
+    pub fn skew(x_radians: f32, y_radians: f32) -> Self {
+        Self {
+            t_matrix: [1.0, y_radians.tan(), x_radians.tan(), 1.0],
+            t_translation: (0.0, 0.0),
+        }
+    }
+
+    /// Apply `other` after this transform
+    pub fn then(&self, other: &Self) -> Self {
+        let [a1, b1, c1, d1] = self.t_matrix;
+        let [a2, b2, c2, d2] = other.t_matrix;
+        let (tx, ty) = other.apply(self.t_translation);
+
+        Self {
+            t_matrix: [
+                a2 * a1 + c2 * b1,
+                b2 * a1 + d2 * b1,
+                a2 * c1 + c2 * d1,
+                b2 * c1 + d2 * d1,
+            ],
+            t_translation: (tx, ty),
+        }
+    }
+
+    /// Apply this transform around a point instead of the origin
+    ///
+    /// This is useful for rotating or scaling around the center of a
+    /// surface.
+    pub fn around(&self, x: f32, y: f32) -> Self {
+        Self::translate(-x, -y)
+            .then(self)
+            .then(&Self::translate(x, y))
+    }
+
+    /// Transform a point
+    pub fn apply(&self, point: (f32, f32)) -> (f32, f32) {
+        let [a, b, c, d] = self.t_matrix;
+        (
+            a * point.0 + c * point.1 + self.t_translation.0,
+            b * point.0 + d * point.1 + self.t_translation.1,
+        )
+    }
+
+    /// Get the axis aligned bounding box of a transformed rectangle
+    ///
+    /// The result is grown to whole pixels so that it always covers
+    /// the transformed area.
+    pub fn bounding_box(&self, rect: &Rect<i32>) -> Rect<i32> {
+        if self.is_identity() {
+            return *rect;
+        }
+
+        let (x, y) = (rect.r_pos.0 as f32, rect.r_pos.1 as f32);
+        let (w, h) = (rect.r_size.0 as f32, rect.r_size.1 as f32);
+        let corners = [
+            self.apply((x, y)),
+            self.apply((x + w, y)),
+            self.apply((x, y + h)),
+            self.apply((x + w, y + h)),
+        ];
+
+        let min_x = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min).floor();
+        let min_y = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min).floor();
+        let max_x = corners.iter().map(|c| c.0).fold(f32::MIN, f32::max).ceil();
+        let max_y = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max).ceil();
+
+        Rect::new(
+            min_x as i32,
+            min_y as i32,
+            (max_x - min_x) as i32,
+            (max_y - min_y) as i32,
+        )
+    }
+}
@@ -0,0 +1,251 @@
+// A small event loop
+//
+// This is the one place our main loops sleep. It wakes up when a watched
+// fd is readable, when a timer expires, or when one of the signals we
+// were asked to watch is delivered. Idle callbacks are run right before
+// the loop goes to sleep.
+//
+// Timers are kept in a list sorted by deadline, and the kernel timer
+// (a timerfd on Linux, EVFILT_TIMER on FreeBSD) is only ever armed for
+// the earliest one. Signals are turned into events instead of running a
+// handler, so they can be handled from the main loop like anything else.
+// The kqueue and epoll backends live in `platform`.
+//
+// Austin Shafer - 2020
+extern crate nix;
+
+use crate::platform::Poller;
+use crate::{log, Result};
+pub use nix::sys::signal::Signal;
+
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+/// Identifies a timer added to an `EventLoop`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+/// Something the event loop woke up for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoopEvent {
+    /// This fd is readable
+    Readable(RawFd),
+    /// This timer expired
+    Timer(TimerId),
+    /// This signal was delivered to us
+    Signal(Signal),
+}
+
+struct Timer {
+    t_id: TimerId,
+    t_deadline: Instant,
+    /// Repeating timers are added again with this interval once
+    /// they expire
+    t_interval: Option<Duration>,
+}
+
+/// An event loop for fds, timers and signals
+pub struct EventLoop {
+    el_poller: Poller,
+    el_fds: Vec<RawFd>,
+    /// Our timers, sorted by deadline
+    el_timers: Vec<Timer>,
+    el_next_timer_id: u64,
+    /// The deadline the kernel timer is currently armed for
+    el_armed: Option<Instant>,
+    el_idle: Vec<Box<dyn FnOnce()>>,
+    /// The events from the last `wait`
+    el_events: Vec<LoopEvent>,
+}
+
+impl Default for EventLoop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLoop {
+    pub fn new() -> EventLoop {
+        EventLoop {
+            el_poller: Poller::new().expect("Could not create event loop"),
+            el_fds: Vec::new(),
+            el_timers: Vec::new(),
+            el_next_timer_id: 0,
+            el_armed: None,
+            el_idle: Vec::new(),
+            el_events: Vec::new(),
+        }
+    }
+
+    /// Wake up when `fd` is readable
+    ///
+    /// The fd is not owned by the loop, and must be removed before
+    /// it is closed.
+    pub fn add_fd(&mut self, fd: RawFd) {
+        if let Err(e) = self.el_poller.add_fd(fd) {
+            log::error!("Could not watch fd {}: {:?}", fd, e);
+            return;
+        }
+        self.el_fds.push(fd);
+    }
+
+    pub fn remove_fd(&mut self, fd: RawFd) {
+        let index = self
+            .el_fds
+            .iter()
+            .position(|f| *f == fd)
+            .expect("EventLoop: Could not find requested fd");
+        self.el_fds.remove(index);
+        self.el_poller.remove_fd(fd);
+    }
+
+    fn insert_timer(&mut self, timer: Timer) {
+        let index = self
+            .el_timers
+            .partition_point(|t| t.t_deadline <= timer.t_deadline);
+        self.el_timers.insert(index, timer);
+    }
+
+    fn add_timer_internal(&mut self, after: Duration, interval: Option<Duration>) -> TimerId {
+        let id = TimerId(self.el_next_timer_id);
+        self.el_next_timer_id += 1;
+
+        self.insert_timer(Timer {
+            t_id: id,
+            t_deadline: Instant::now() + after,
+            t_interval: interval,
+        });
+        self.update_kernel_timer();
+        id
+    }
+
+    /// Wake up once after `after` has passed
+    pub fn add_timer(&mut self, after: Duration) -> TimerId {
+        self.add_timer_internal(after, None)
+    }
+
+    /// Wake up every `interval` until the timer is removed
+    pub fn add_repeating_timer(&mut self, interval: Duration) -> TimerId {
+        self.add_timer_internal(interval, Some(interval))
+    }
+
+    /// Stop a timer
+    ///
+    /// This does nothing if a one shot timer already expired.
+    pub fn remove_timer(&mut self, id: TimerId) {
+        self.el_timers.retain(|t| t.t_id != id);
+        self.update_kernel_timer();
+    }
+
+    /// Arm the kernel timer for our earliest deadline
+    fn update_kernel_timer(&mut self) {
+        let deadline = self.el_timers.first().map(|t| t.t_deadline);
+        if deadline == self.el_armed {
+            return;
+        }
+
+        let after = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        if let Err(e) = self.el_poller.arm_timer(after) {
+            log::error!("Could not arm event loop timer: {:?}", e);
+            return;
+        }
+        self.el_armed = deadline;
+    }
+
+    /// Queue events for any timers which have expired
+    fn check_timers(&mut self) {
+        let now = Instant::now();
+        let expired = self.el_timers.partition_point(|t| t.t_deadline <= now);
+
+        for timer in self.el_timers.drain(..expired).collect::<Vec<_>>() {
+            self.el_events.push(LoopEvent::Timer(timer.t_id));
+
+            if let Some(interval) = timer.t_interval {
+                // If we fell far behind, skip the missed expirations
+                // instead of firing for all of them at once
+                let mut deadline = timer.t_deadline + interval;
+                if deadline <= now {
+                    deadline = now + interval;
+                }
+                self.insert_timer(Timer {
+                    t_deadline: deadline,
+                    ..timer
+                });
+            }
+        }
+
+        // The kernel timer fired, so it needs to be armed again
+        self.el_armed = None;
+        self.update_kernel_timer();
+    }
+
+    /// Deliver `signals` as events instead of running their handlers
+    ///
+    /// This blocks the signals in the calling thread. Threads inherit
+    /// this, so it should be called before any other threads are started
+    /// or they may still be killed by the signal.
+    pub fn watch_signals(&mut self, signals: &[Signal]) -> Result<()> {
+        self.el_poller.watch_signals(signals)
+    }
+
+    /// Run `f` the next time the loop is about to sleep
+    ///
+    /// This is used to defer work until everything that is ready
+    /// has been handled.
+    pub fn add_idle<F: FnOnce() + 'static>(&mut self, f: F) {
+        self.el_idle.push(Box::new(f));
+    }
+
+    /// Run idle callbacks and sleep until something happens
+    ///
+    /// The timeout is in milliseconds, and None waits until the next
+    /// event. Returns true if we woke up for an event, which can then be
+    /// read with `drain_events`.
+    pub fn wait_for_events(&mut self, timeout: Option<usize>) -> bool {
+        self.el_events.clear();
+
+        // Idle callbacks may add more of themselves, which will run
+        // the next time we sleep
+        for idle in std::mem::take(&mut self.el_idle).into_iter() {
+            idle();
+        }
+
+        if let Err(e) = self.el_poller.wait(timeout, &mut self.el_events) {
+            // Being interrupted by a signal just means we woke up early
+            if e != nix::errno::Errno::EINTR {
+                log::error!("Error while waiting for events: {:?}", e);
+            }
+        }
+        self.check_timers();
+
+        !self.el_events.is_empty()
+    }
+
+    /// Is `fd` readable as of the last wait
+    pub fn is_readable(&self, fd: RawFd) -> bool {
+        self.el_events.contains(&LoopEvent::Readable(fd))
+    }
+
+    /// Did `id` expire during the last wait
+    ///
+    /// This removes the event so that whoever owns the timer can handle
+    /// it without it also showing up in `drain_events`.
+    pub fn take_timer(&mut self, id: TimerId) -> bool {
+        match self
+            .el_events
+            .iter()
+            .position(|e| *e == LoopEvent::Timer(id))
+        {
+            Some(index) => {
+                self.el_events.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drain the events from the last wait
+    pub fn drain_events<'a>(&'a mut self) -> std::vec::Drain<'a, LoopEvent> {
+        self.el_events.drain(..)
+    }
+}
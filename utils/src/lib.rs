@@ -4,9 +4,13 @@
 pub mod timing;
 #[macro_use]
 pub mod logging;
-pub mod fdwatch;
+pub mod event_loop;
 pub mod log;
+pub mod platform;
 pub mod region;
+#[cfg(test)]
+mod tests;
+pub mod trace;
 
 use std::ops::Deref;
 use std::slice;
@@ -35,14 +39,22 @@ pub struct MemImage {
 
 #[allow(dead_code)]
 impl MemImage {
+    /// Get the pixels of this image
+    ///
+    /// Rows are `stride` pixels apart, and the last row ends after
+    /// `width` pixels.
     pub fn as_slice(&self) -> &[u8] {
         if !self.ptr.is_null() {
-            unsafe {
-                return slice::from_raw_parts(
-                    self.ptr,
-                    self.width * self.height * self.element_size,
-                );
-            }
+            let stride = match self.stride {
+                0 => self.width,
+                s => s as usize,
+            };
+            let len = match self.height {
+                0 => 0,
+                h => stride * (h - 1) + self.width,
+            };
+
+            unsafe { slice::from_raw_parts(self.ptr, len * self.element_size) }
         } else {
             panic!("Trying to dereference null pointer");
         }
@@ -6,6 +6,7 @@ pub use crate::debug;
 pub use crate::error;
 pub use crate::info;
 pub use crate::log_internal;
+pub use crate::logging::{is_level_enabled, set_log_filter, LogLevel};
 pub use crate::profiling;
+pub use crate::timing::get_current_millis;
 pub use crate::verbose;
-pub use crate::{logging::LogLevel, timing::get_current_millis};
@@ -1,9 +1,20 @@
 // Category-based logging infrastructure
 //
-// This will be used from multiple threads, so it needs
-// to be stateless
+// This will be used from multiple threads. The only state is the log
+// filter, which is behind a lock and may be changed at runtime.
+//
+// The filter is read from CATEGORY5_LOG, which is a comma separated list
+// of levels. A bare level applies to everything, and `name=level` applies
+// to modules with `name` in their path. For example
+// `CATEGORY5_LOG=error,thundr=debug,ways=verbose` logs debug messages
+// from Thundr and verbose messages from the wayland protocol handling,
+// while the rest of the compositor only logs errors. Usual names are the
+// crates (thundr, dakota) and the compositor subsystems (ways, vkcomp,
+// atmosphere, input).
 //
 // Austin Shafer - 2020
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::RwLock;
 
 #[allow(dead_code, non_camel_case_types)]
 pub enum LogLevel {
@@ -38,12 +49,117 @@ impl LogLevel {
             LogLevel::profiling => 5,
         }
     }
+
+    /// Get the level with this name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "critical" => Some(LogLevel::critical),
+            "error" => Some(LogLevel::error),
+            "debug" => Some(LogLevel::debug),
+            "verbose" => Some(LogLevel::verbose),
+            "info" => Some(LogLevel::info),
+            "profiling" => Some(LogLevel::profiling),
+            _ => None,
+        }
+    }
+}
+
+/// Which messages are logged
+struct LogFilter {
+    /// The level for modules which don't match any of lf_modules
+    lf_default: u32,
+    /// Module names and their levels. Later entries take priority.
+    lf_modules: Vec<(String, u32)>,
+}
+
+impl LogFilter {
+    /// Parse a filter in the CATEGORY5_LOG format
+    ///
+    /// Invalid entries are reported and skipped.
+    fn parse(spec: &str) -> Self {
+        let mut ret = Self {
+            lf_default: LogLevel::error.get_level(),
+            lf_modules: Vec::new(),
+        };
+
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (module, name) = match entry.split_once('=') {
+                Some((module, name)) => (Some(module.trim()), name.trim()),
+                None => (None, entry),
+            };
+            let level = match LogLevel::from_name(name) {
+                Some(mut level) => level.get_level(),
+                None => {
+                    eprintln!("Ignoring unknown log level \"{}\"", entry);
+                    continue;
+                }
+            };
+
+            match module {
+                Some(module) => ret.lf_modules.push((module.to_string(), level)),
+                None => ret.lf_default = level,
+            }
+        }
+
+        ret
+    }
+
+    /// The most verbose level any module will log
+    fn max_level(&self) -> u32 {
+        self.lf_modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.lf_default, u32::max)
+    }
+
+    /// Get the level for a module path, i.e. `thundr::display::frame`
+    fn get_level(&self, module_path: &str) -> u32 {
+        self.lf_modules
+            .iter()
+            .rev()
+            .find(|(module, _)| module_path.split("::").any(|m| m == module))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.lf_default)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LOG_FILTER: RwLock<LogFilter> = {
+        let filter = LogFilter::parse(&std::env::var("CATEGORY5_LOG").unwrap_or_default());
+        MAX_LEVEL.store(filter.max_level(), Ordering::Relaxed);
+        RwLock::new(filter)
+    };
+}
+
+/// Cached max_level of LOG_FILTER, so most messages can be skipped
+/// without taking the lock
+static MAX_LEVEL: AtomicU32 = AtomicU32::new(0);
+
+/// Change which messages are logged
+///
+/// `spec` is in the same format as CATEGORY5_LOG, and replaces the
+/// current filter. This allows turning on debug logging for one part of
+/// the compositor while it is running.
+pub fn set_log_filter(spec: &str) {
+    let filter = LogFilter::parse(spec);
+    let mut current = LOG_FILTER.write().unwrap();
+    MAX_LEVEL.store(filter.max_level(), Ordering::Relaxed);
+    *current = filter;
+}
+
+/// Should a message at `level` from `module_path` be logged?
+pub fn is_level_enabled(level: u32, module_path: &str) -> bool {
+    lazy_static::initialize(&LOG_FILTER);
+    if level > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    level <= LOG_FILTER.read().unwrap().get_level(module_path)
 }
 
 #[macro_export]
 macro_rules! debug {
     ($($format_args:tt)+) => {{
-        #[cfg(debug_assertions)]
         log::log_internal!(log::LogLevel::debug, $($format_args)+)
     }};
 }
@@ -51,7 +167,6 @@ macro_rules! debug {
 #[macro_export]
 macro_rules! verbose {
     ($($format_args:tt)+) => {{
-        #[cfg(debug_assertions)]
         log::log_internal!(log::LogLevel::verbose, $($format_args)+)
     }};
 }
@@ -59,7 +174,6 @@ macro_rules! verbose {
 #[macro_export]
 macro_rules! profiling {
     ($($format_args:tt)+) => {{
-        #[cfg(debug_assertions)]
         log::log_internal!(log::LogLevel::profiling, $($format_args)+)
     }};
 }
@@ -67,7 +181,6 @@ macro_rules! profiling {
 #[macro_export]
 macro_rules! info {
     ($($format_args:tt)+) => {{
-        #[cfg(debug_assertions)]
         log::log_internal!(log::LogLevel::info, $($format_args)+)
     }};
 }
@@ -85,29 +198,16 @@ macro_rules! log_internal{
     ($loglevel:expr, $($format_args:tt)+) => ({
 
         lazy_static::lazy_static! {
-            static ref DEFAULT_LEVEL: u32 = crate::utils::logging::LogLevel::error.get_level();
-
-            static ref LOG_LEVEL_RAW: u32 = match std::env::var("CATEGORY5_LOG") {
-                Ok(val) => match val.as_str() {
-                    "debug" => crate::utils::logging::LogLevel::debug.get_level(),
-                    "verbose" => crate::utils::logging::LogLevel::verbose.get_level(),
-                    "info" => crate::utils::logging::LogLevel::info.get_level(),
-                    _ => *DEFAULT_LEVEL,
-                },
-                Err(_) => *DEFAULT_LEVEL,
-            };
-
             static ref LOG_MATCH_STRING: Option<String> = match std::env::var("CATEGORY5_LOG_MATCH") {
                 Ok(val) => Some(val),
                 Err(_) => None,
             };
         }
 
-        // !! NOTE: current log level set here !!
-        //
-        // Currently set to the debug level (2)
-        let is_err = $loglevel.get_level() <= *DEFAULT_LEVEL;
-        let mut should_log = $loglevel.get_level() <= *LOG_LEVEL_RAW;
+        // Errors are always logged, everything else depends on the
+        // level set for this module in CATEGORY5_LOG
+        let is_err = $loglevel.get_level() <= log::LogLevel::error.get_level();
+        let mut should_log = is_err || log::is_level_enabled($loglevel.get_level(), module_path!());
 
         // Restrict the following more expensive operations to the case where we
         // are logging this message.
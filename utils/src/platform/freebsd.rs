@@ -0,0 +1,408 @@
+// FreeBSD support
+//
+// Events are waited on with kqueue, which handles timers and signals
+// itself. Devices are monitored by listening to devd, which forwards the
+// kernel's devctl notifications to anyone connected to its socket.
+//
+// Austin Shafer - 2024
+use super::{DeviceAction, DeviceEvent};
+use crate::event_loop::{LoopEvent, Signal};
+use crate::{log, Result};
+
+use nix::sys::event::*;
+use nix::sys::socket::{
+    connect, recv, socket, AddressFamily, MsgFlags, SockFlag, SockType, UnixAddr,
+};
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// =============================================
+// kqueue
+// =============================================
+
+/// The ident of our EVFILT_TIMER event
+const KQ_TIMER_IDENT: usize = 0;
+
+/// Waits for events for `EventLoop`
+pub(crate) struct Poller {
+    po_kq: Kqueue,
+}
+
+impl Poller {
+    pub fn new() -> nix::Result<Self> {
+        Ok(Self {
+            po_kq: Kqueue::new()?,
+        })
+    }
+
+    fn change(
+        &self,
+        ident: usize,
+        filter: EventFilter,
+        flags: EventFlag,
+        data: isize,
+    ) -> nix::Result<()> {
+        let kev = KEvent::new(ident, filter, flags, FilterFlag::empty(), data, 0);
+        self.po_kq.kevent(&[kev], &mut [], None).map(drop)
+    }
+
+    pub fn add_fd(&mut self, fd: RawFd) -> nix::Result<()> {
+        self.change(fd as usize, EventFilter::EVFILT_READ, EventFlag::EV_ADD, 0)
+    }
+
+    pub fn remove_fd(&mut self, fd: RawFd) {
+        // Closed fds are removed from the kqueue automatically
+        let _ = self.change(
+            fd as usize,
+            EventFilter::EVFILT_READ,
+            EventFlag::EV_DELETE,
+            0,
+        );
+    }
+
+    pub fn arm_timer(&mut self, after: Option<Duration>) -> nix::Result<()> {
+        match after {
+            // Adding the timer again replaces the old deadline. The
+            // period is in milliseconds, rounded up so we don't wake
+            // up right before the deadline.
+            Some(after) => self.change(
+                KQ_TIMER_IDENT,
+                EventFilter::EVFILT_TIMER,
+                EventFlag::EV_ADD | EventFlag::EV_ONESHOT,
+                after.as_micros().div_ceil(1000).max(1) as isize,
+            ),
+            None => {
+                // The timer is already gone if it fired
+                let _ = self.change(
+                    KQ_TIMER_IDENT,
+                    EventFilter::EVFILT_TIMER,
+                    EventFlag::EV_DELETE,
+                    0,
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub fn watch_signals(&mut self, signals: &[Signal]) -> Result<()> {
+        for sig in signals.iter() {
+            // kqueue records signals even when they are ignored, which
+            // keeps the default handler from killing us
+            unsafe { nix::sys::signal::signal(*sig, nix::sys::signal::SigHandler::SigIgn)? };
+            self.change(
+                *sig as usize,
+                EventFilter::EVFILT_SIGNAL,
+                EventFlag::EV_ADD,
+                0,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn wait(&mut self, timeout: Option<usize>, events: &mut Vec<LoopEvent>) -> nix::Result<()> {
+        let mut kevs = [KEvent::new(
+            0,
+            EventFilter::EVFILT_READ,
+            EventFlag::empty(),
+            FilterFlag::empty(),
+            0,
+            0,
+        ); 16];
+        let timeout = timeout.map(|t| {
+            *nix::sys::time::TimeSpec::from_duration(Duration::from_millis(t as u64)).as_ref()
+        });
+
+        let count = self.po_kq.kevent(&[], &mut kevs, timeout)?;
+        for kev in kevs[..count].iter() {
+            match kev.filter()? {
+                EventFilter::EVFILT_READ => events.push(LoopEvent::Readable(kev.ident() as RawFd)),
+                EventFilter::EVFILT_SIGNAL => {
+                    if let Ok(sig) = Signal::try_from(kev.ident() as i32) {
+                        events.push(LoopEvent::Signal(sig));
+                    }
+                }
+                // Expired timers are found by EventLoop::check_timers
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// =============================================
+// Device monitoring
+// =============================================
+
+/// The socket devd sends notifications on
+const DEVD_SOCKET: &str = "/var/run/devd.seqpacket.pipe";
+
+/// Listens for devd notifications
+pub(crate) struct DeviceMonitor {
+    dm_sock: OwnedFd,
+}
+
+impl DeviceMonitor {
+    pub fn new() -> Result<Self> {
+        let sock = socket(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        connect(sock.as_raw_fd(), &UnixAddr::new(DEVD_SOCKET)?)?;
+
+        Ok(Self { dm_sock: sock })
+    }
+
+    pub fn get_fd(&self) -> RawFd {
+        self.dm_sock.as_raw_fd()
+    }
+
+    /// Parse one devd notification
+    ///
+    /// We only care about notifications of the form
+    /// `!system=DEVFS subsystem=CDEV type=CREATE cdev=dri/card0`, which
+    /// are sent for device nodes and by drm-kmod for hotplug. Device
+    /// attach and detach messages don't have a device node.
+    fn parse_event(msg: &str) -> Option<DeviceEvent> {
+        let msg = msg.trim_end().strip_prefix('!')?;
+
+        let mut system = None;
+        let mut kind = None;
+        let mut cdev = None;
+        for part in msg.split_whitespace() {
+            match part.split_once('=') {
+                Some(("system", val)) => system = Some(val),
+                Some(("type", val)) => kind = Some(val),
+                Some(("cdev", val)) => cdev = Some(val),
+                _ => {}
+            }
+        }
+        let cdev = cdev?;
+
+        // Name the subsystem after the device node directory, which
+        // matches Linux for the devices we care about
+        let subsystem = match cdev.split('/').next()? {
+            "dri" | "drm" => "drm",
+            "input" => "input",
+            other => other,
+        };
+        let (action, hotplug) = match (system?, kind?) {
+            ("DEVFS", "CREATE") => (DeviceAction::Add, false),
+            ("DEVFS", "DESTROY") => (DeviceAction::Remove, false),
+            ("DRM", "HOTPLUG") => (DeviceAction::Change, true),
+            _ => return None,
+        };
+
+        Some(DeviceEvent {
+            de_action: action,
+            de_subsystem: subsystem.to_string(),
+            de_devnode: Some(PathBuf::from("/dev").join(cdev)),
+            de_hotplug: hotplug,
+        })
+    }
+
+    pub fn read_events(&mut self, events: &mut Vec<DeviceEvent>) {
+        let mut buf = [0; 8192];
+        // This returns EAGAIN once there are no more events
+        while let Ok(len) = recv(self.dm_sock.as_raw_fd(), &mut buf, MsgFlags::empty()) {
+            // devd closed the socket
+            if len == 0 {
+                log::error!("devd went away, no longer monitoring devices");
+                return;
+            }
+            if let Some(ev) = Self::parse_event(&String::from_utf8_lossy(&buf[..len])) {
+                events.push(ev);
+            }
+        }
+    }
+}
+
+// =============================================
+// Directory watching
+// =============================================
+
+pub(crate) struct DirWatcher {
+    dw_kqueue: Kqueue,
+    /// The directory and files. kqueue watches open files, so these
+    /// are opened again each time the files change.
+    dw_files: Vec<File>,
+    dw_dir: PathBuf,
+    /// The files in the directory that we care about
+    dw_names: Vec<OsString>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: PathBuf, names: Vec<OsString>) -> Result<Self> {
+        let mut ret = Self {
+            dw_kqueue: Kqueue::new()?,
+            dw_files: Vec::new(),
+            dw_dir: dir,
+            dw_names: names,
+        };
+        ret.register_files()?;
+        Ok(ret)
+    }
+
+    /// Open the directory and files and add them to our kqueue
+    ///
+    /// Closing the old files removes their events from the kqueue.
+    fn register_files(&mut self) -> Result<()> {
+        self.dw_files.clear();
+        self.dw_files.push(File::open(&self.dw_dir)?);
+        for name in self.dw_names.iter() {
+            if let Ok(file) = File::open(self.dw_dir.join(name)) {
+                self.dw_files.push(file);
+            }
+        }
+
+        let events: Vec<KEvent> = self
+            .dw_files
+            .iter()
+            .map(|file| {
+                KEvent::new(
+                    file.as_raw_fd() as usize,
+                    EventFilter::EVFILT_VNODE,
+                    EventFlag::EV_ADD | EventFlag::EV_CLEAR,
+                    FilterFlag::NOTE_WRITE
+                        | FilterFlag::NOTE_EXTEND
+                        | FilterFlag::NOTE_DELETE
+                        | FilterFlag::NOTE_RENAME,
+                    0,
+                    0,
+                )
+            })
+            .collect();
+        self.dw_kqueue.kevent(&events, &mut [], None)?;
+        Ok(())
+    }
+
+    pub fn get_fd(&self) -> RawFd {
+        self.dw_kqueue.as_fd().as_raw_fd()
+    }
+
+    /// Directory events don't say which file changed, so any change to
+    /// the directory counts.
+    pub fn has_changed(&mut self) -> bool {
+        let mut events = [KEvent::new(
+            0,
+            EventFilter::EVFILT_VNODE,
+            EventFlag::empty(),
+            FilterFlag::empty(),
+            0,
+            0,
+        ); 8];
+        let timeout = nix::sys::time::TimeSpec::from_duration(Duration::ZERO);
+
+        let mut changed = false;
+        while let Ok(count) =
+            self.dw_kqueue
+                .kevent(&[], &mut events, Some(timeout.as_ref().clone()))
+        {
+            if count == 0 {
+                break;
+            }
+            changed = true;
+        }
+
+        // The files may have been replaced, watch the new ones
+        if changed {
+            if let Err(e) = self.register_files() {
+                log::error!("Could not watch directory {:?}: {:?}", self.dw_dir, e);
+            }
+        }
+        changed
+    }
+}
+
+// =============================================
+// Anonymous files
+// =============================================
+
+pub(crate) fn create_anon_file(_name: &str) -> Result<File> {
+    let fd = unsafe {
+        nix::libc::shm_open(
+            nix::libc::SHM_ANON,
+            nix::libc::O_CREAT | nix::libc::O_RDWR | nix::libc::O_EXCL | nix::libc::O_CLOEXEC,
+            0o600,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// =============================================
+// Peer credentials
+// =============================================
+
+/// struct xucred from sys/ucred.h
+///
+/// The libc crate hides the pid, which shares a union with an unused
+/// pointer.
+#[repr(C)]
+struct XUCred {
+    cr_version: nix::libc::c_uint,
+    cr_uid: nix::libc::uid_t,
+    cr_ngroups: nix::libc::c_short,
+    cr_groups: [nix::libc::gid_t; 16],
+    cr_pid: CrPid,
+}
+
+#[repr(C)]
+union CrPid {
+    _cr_unused1: *mut nix::libc::c_void,
+    cr_pid: nix::libc::pid_t,
+}
+
+pub(crate) fn get_peer_executable(fd: RawFd) -> Option<PathBuf> {
+    let mut cred: XUCred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<XUCred>() as nix::libc::socklen_t;
+    let ret = unsafe {
+        nix::libc::getsockopt(
+            fd,
+            0, // SOL_LOCAL
+            nix::libc::LOCAL_PEERCRED,
+            &mut cred as *mut XUCred as *mut nix::libc::c_void,
+            &mut len,
+        )
+    };
+    let pid = unsafe { cred.cr_pid.cr_pid };
+    if ret != 0 || cred.cr_version != nix::libc::XUCRED_VERSION || pid <= 0 {
+        return None;
+    }
+
+    // There is no procfs by default, ask the kernel for the path
+    let mib = [
+        nix::libc::CTL_KERN,
+        nix::libc::KERN_PROC,
+        nix::libc::KERN_PROC_PATHNAME,
+        pid,
+    ];
+    let mut buf = vec![0u8; nix::libc::PATH_MAX as usize];
+    let mut len = buf.len();
+    let ret = unsafe {
+        nix::libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as nix::libc::c_uint,
+            buf.as_mut_ptr() as *mut nix::libc::c_void,
+            &mut len,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if ret != 0 || len == 0 {
+        return None;
+    }
+
+    // The length includes the NUL terminator
+    buf.truncate(len - 1);
+    Some(PathBuf::from(OsString::from_vec(buf)))
+}
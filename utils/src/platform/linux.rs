@@ -0,0 +1,310 @@
+// Linux support
+//
+// Events are waited on with epoll, and timers and signals are delivered
+// through a timerfd and signalfd added to it. Devices are monitored with
+// the kernel's uevent netlink socket, the same one udevd reads from.
+//
+// Austin Shafer - 2024
+use super::{DeviceAction, DeviceEvent};
+use crate::event_loop::{LoopEvent, Signal};
+use crate::{log, Result};
+
+use nix::sys::epoll::*;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::signal::SigSet;
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::sys::socket::{
+    bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use std::convert::TryFrom;
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// =============================================
+// epoll
+// =============================================
+
+/// Waits for events for `EventLoop`
+pub(crate) struct Poller {
+    po_epoll: Epoll,
+    po_timer: TimerFd,
+    /// Created the first time signals are watched
+    po_signals: Option<SignalFd>,
+    po_sigset: SigSet,
+}
+
+impl Poller {
+    pub fn new() -> nix::Result<Self> {
+        let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let timer = TimerFd::new(
+            ClockId::CLOCK_MONOTONIC,
+            TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC,
+        )?;
+        epoll.add(
+            timer.as_fd(),
+            EpollEvent::new(EpollFlags::EPOLLIN, timer.as_fd().as_raw_fd() as u64),
+        )?;
+
+        Ok(Self {
+            po_epoll: epoll,
+            po_timer: timer,
+            po_signals: None,
+            po_sigset: SigSet::empty(),
+        })
+    }
+
+    pub fn add_fd(&mut self, fd: RawFd) -> nix::Result<()> {
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        self.po_epoll
+            .add(borrowed, EpollEvent::new(EpollFlags::EPOLLIN, fd as u64))
+    }
+
+    pub fn remove_fd(&mut self, fd: RawFd) {
+        // Closed fds are removed from the epoll set automatically
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        let _ = self.po_epoll.delete(borrowed);
+    }
+
+    pub fn arm_timer(&mut self, after: Option<Duration>) -> nix::Result<()> {
+        match after {
+            // A zero timeout disarms the timerfd, so wake up as soon
+            // as possible instead
+            Some(after) => self.po_timer.set(
+                Expiration::OneShot(nix::sys::time::TimeSpec::from_duration(
+                    after.max(Duration::from_nanos(1)),
+                )),
+                TimerSetTimeFlags::empty(),
+            ),
+            None => self.po_timer.unset(),
+        }
+    }
+
+    pub fn watch_signals(&mut self, signals: &[Signal]) -> Result<()> {
+        for sig in signals.iter() {
+            self.po_sigset.add(*sig);
+        }
+        // signalfd only receives signals which are blocked
+        self.po_sigset.thread_block()?;
+
+        match self.po_signals.as_ref() {
+            Some(sfd) => sfd.set_mask(&self.po_sigset)?,
+            None => {
+                let sfd = SignalFd::with_flags(
+                    &self.po_sigset,
+                    SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC,
+                )?;
+                self.po_epoll.add(
+                    sfd.as_fd(),
+                    EpollEvent::new(EpollFlags::EPOLLIN, sfd.as_fd().as_raw_fd() as u64),
+                )?;
+                self.po_signals = Some(sfd);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn wait(&mut self, timeout: Option<usize>, events: &mut Vec<LoopEvent>) -> nix::Result<()> {
+        let mut epevs = [EpollEvent::empty(); 16];
+        let timeout = match timeout {
+            Some(ms) => EpollTimeout::try_from(ms as u64).unwrap_or(EpollTimeout::MAX),
+            None => EpollTimeout::NONE,
+        };
+
+        let count = self.po_epoll.wait(&mut epevs, timeout)?;
+        let timer_fd = self.po_timer.as_fd().as_raw_fd();
+        let signal_fd = self.po_signals.as_ref().map(|s| s.as_fd().as_raw_fd());
+
+        for ev in epevs[..count].iter() {
+            let fd = ev.data() as RawFd;
+
+            if fd == timer_fd {
+                // Expired timers are found by EventLoop::check_timers,
+                // the timerfd just needs to stop being readable
+                let _ = nix::unistd::read(timer_fd, &mut [0; 8]);
+            } else if Some(fd) == signal_fd {
+                let sfd = self.po_signals.as_mut().unwrap();
+                while let Ok(Some(info)) = sfd.read_signal() {
+                    if let Ok(sig) = Signal::try_from(info.ssi_signo as i32) {
+                        events.push(LoopEvent::Signal(sig));
+                    }
+                }
+            } else {
+                events.push(LoopEvent::Readable(fd));
+            }
+        }
+        Ok(())
+    }
+}
+
+// =============================================
+// Device monitoring
+// =============================================
+
+/// The netlink multicast group the kernel sends uevents to
+const UEVENT_KERNEL_GROUP: u32 = 1;
+
+/// Listens for kernel uevents
+///
+/// These are the raw events that udevd receives, so they may arrive
+/// before udev has finished setting up the device node.
+pub(crate) struct DeviceMonitor {
+    dm_sock: OwnedFd,
+}
+
+impl DeviceMonitor {
+    pub fn new() -> Result<Self> {
+        let sock = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkKObjectUEvent,
+        )?;
+        bind(sock.as_raw_fd(), &NetlinkAddr::new(0, UEVENT_KERNEL_GROUP))?;
+
+        Ok(Self { dm_sock: sock })
+    }
+
+    pub fn get_fd(&self) -> RawFd {
+        self.dm_sock.as_raw_fd()
+    }
+
+    /// Parse one uevent
+    ///
+    /// These start with a `action@devpath` header, followed by
+    /// `KEY=value` properties. All of them are NUL terminated.
+    fn parse_event(msg: &[u8]) -> Option<DeviceEvent> {
+        let mut parts = msg.split(|b| *b == 0).map(String::from_utf8_lossy);
+        if !parts.next()?.contains('@') {
+            return None;
+        }
+
+        let mut action = None;
+        let mut subsystem = None;
+        let mut devnode = None;
+        let mut hotplug = false;
+        for part in parts {
+            match part.split_once('=') {
+                Some(("ACTION", val)) => {
+                    action = match val {
+                        "add" => Some(DeviceAction::Add),
+                        "remove" => Some(DeviceAction::Remove),
+                        "change" => Some(DeviceAction::Change),
+                        _ => None,
+                    }
+                }
+                Some(("SUBSYSTEM", val)) => subsystem = Some(val.to_string()),
+                Some(("DEVNAME", val)) => devnode = Some(PathBuf::from("/dev").join(val)),
+                Some(("HOTPLUG", val)) => hotplug = val == "1",
+                _ => {}
+            }
+        }
+
+        Some(DeviceEvent {
+            de_action: action?,
+            de_subsystem: subsystem?,
+            de_devnode: devnode,
+            de_hotplug: hotplug,
+        })
+    }
+
+    pub fn read_events(&mut self, events: &mut Vec<DeviceEvent>) {
+        let mut buf = [0; 8192];
+        // This returns EAGAIN once there are no more events
+        while let Ok(len) = recv(self.dm_sock.as_raw_fd(), &mut buf, MsgFlags::empty()) {
+            match Self::parse_event(&buf[..len]) {
+                Some(ev) => events.push(ev),
+                None => log::debug!("Ignoring unknown uevent"),
+            }
+        }
+    }
+}
+
+// =============================================
+// Directory watching
+// =============================================
+
+pub(crate) struct DirWatcher {
+    dw_inotify: Inotify,
+    /// The files in the directory that we care about
+    dw_names: Vec<OsString>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: PathBuf, names: Vec<OsString>) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(
+            &dir,
+            AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE,
+        )?;
+
+        Ok(Self {
+            dw_inotify: inotify,
+            dw_names: names,
+        })
+    }
+
+    pub fn get_fd(&self) -> RawFd {
+        self.dw_inotify.as_fd().as_raw_fd()
+    }
+
+    pub fn has_changed(&mut self) -> bool {
+        let mut changed = false;
+        // This returns EAGAIN once there are no more events
+        while let Ok(events) = self.dw_inotify.read_events() {
+            changed |= events.iter().any(|ev| match ev.name.as_ref() {
+                Some(name) => self.dw_names.contains(name),
+                None => false,
+            });
+        }
+        changed
+    }
+}
+
+// =============================================
+// Anonymous files
+// =============================================
+
+pub(crate) fn create_anon_file(name: &str) -> Result<File> {
+    let name = CString::new(name)?;
+    let fd = unsafe { nix::libc::memfd_create(name.as_ptr(), nix::libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+// =============================================
+// Peer credentials
+// =============================================
+
+pub(crate) fn get_peer_executable(fd: RawFd) -> Option<PathBuf> {
+    let mut cred = nix::libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<nix::libc::ucred>() as nix::libc::socklen_t;
+    let ret = unsafe {
+        nix::libc::getsockopt(
+            fd,
+            nix::libc::SOL_SOCKET,
+            nix::libc::SO_PEERCRED,
+            &mut cred as *mut nix::libc::ucred as *mut nix::libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 || cred.pid <= 0 {
+        return None;
+    }
+
+    std::fs::read_link(format!("/proc/{}/exe", cred.pid)).ok()
+}
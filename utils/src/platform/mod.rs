@@ -0,0 +1,131 @@
+// OS-compatibility layer
+//
+// Category5 is developed on FreeBSD but also runs on Linux, and the two
+// have different ways of doing the same things. FreeBSD uses kqueue for
+// waiting on events and devd for device notifications, while Linux uses
+// epoll and the uevent netlink socket that udev is built on.
+//
+// Everything in here has the same interface on both, with the OS specific
+// code living in `freebsd.rs` and `linux.rs`. Callers should use these
+// instead of sprinkling #[cfg(target_os)] around.
+//
+// Austin Shafer - 2024
+use crate::Result;
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "freebsd")]
+use freebsd as os;
+
+#[cfg(not(target_os = "freebsd"))]
+mod linux;
+#[cfg(not(target_os = "freebsd"))]
+use linux as os;
+
+/// The kqueue or epoll backend of `EventLoop`
+pub(crate) use os::Poller;
+
+/// What happened to a device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceAction {
+    Add,
+    Remove,
+    Change,
+}
+
+/// A notification about a device from the kernel
+#[derive(Debug, Clone)]
+pub struct DeviceEvent {
+    pub de_action: DeviceAction,
+    /// The subsystem of the device, such as "drm" or "input"
+    pub de_subsystem: String,
+    /// The device node under /dev, if it has one
+    pub de_devnode: Option<PathBuf>,
+    /// A display was plugged in or unplugged from this GPU
+    pub de_hotplug: bool,
+}
+
+/// Listens for devices being added, removed or changed
+///
+/// The fd from `get_fd` becomes readable when there are events, which
+/// can then be read with `read_events`.
+pub struct DeviceMonitor {
+    dm_backend: os::DeviceMonitor,
+    /// The subsystems we report events for
+    dm_subsystems: Vec<String>,
+}
+
+impl DeviceMonitor {
+    /// Listen for events from devices in `subsystems`
+    pub fn new(subsystems: &[&str]) -> Result<Self> {
+        Ok(Self {
+            dm_backend: os::DeviceMonitor::new()?,
+            dm_subsystems: subsystems.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    /// The fd to watch for events
+    pub fn get_fd(&self) -> RawFd {
+        self.dm_backend.get_fd()
+    }
+
+    /// Read all of the pending events
+    pub fn read_events(&mut self) -> Vec<DeviceEvent> {
+        let mut events = Vec::new();
+        self.dm_backend.read_events(&mut events);
+        events.retain(|ev| self.dm_subsystems.contains(&ev.de_subsystem));
+        events
+    }
+}
+
+/// Watches a directory for files being changed
+///
+/// Editors often save by writing a new file and renaming it over the old
+/// one, so we watch the directory the files are in instead of the files
+/// themselves.
+pub struct DirWatcher {
+    dw_backend: os::DirWatcher,
+}
+
+impl DirWatcher {
+    /// Watch the files named `names` in `dir`
+    pub fn new(dir: PathBuf, names: Vec<OsString>) -> Result<Self> {
+        Ok(Self {
+            dw_backend: os::DirWatcher::new(dir, names)?,
+        })
+    }
+
+    /// The fd to watch for changes
+    pub fn get_fd(&self) -> RawFd {
+        self.dw_backend.get_fd()
+    }
+
+    /// Check if any of the files were changed
+    ///
+    /// This clears the pending events, so it will return false until the
+    /// next change. On some OSes any change to the directory counts.
+    pub fn has_changed(&mut self) -> bool {
+        self.dw_backend.has_changed()
+    }
+}
+
+/// Create an anonymous file in memory
+///
+/// This is used for sharing data such as keymaps with clients. The file
+/// is not visible in the filesystem and is freed once all of its fds are
+/// closed.
+pub fn create_anon_file(name: &str) -> Result<File> {
+    os::create_anon_file(name)
+}
+
+/// Get the executable of the process on the other end of a unix socket
+///
+/// Returns None if the process could not be found.
+pub fn get_peer_executable(fd: RawFd) -> Option<PathBuf> {
+    os::get_peer_executable(fd)
+}
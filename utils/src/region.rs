@@ -48,12 +48,41 @@ impl<T: Ord + PartialOrd + Copy + Add + Add<Output = T> + Sub + Sub<Output = T>>
         )
     }
 
+    /// Get the overlapping area of this Rect and `other`
+    ///
+    /// Returns None if the two do not overlap.
+    pub fn intersection(&self, other: &Rect<T>) -> Option<Rect<T>> {
+        let start = (
+            std::cmp::max(self.r_pos.0, other.r_pos.0),
+            std::cmp::max(self.r_pos.1, other.r_pos.1),
+        );
+        let end = (
+            std::cmp::min(self.r_pos.0 + self.r_size.0, other.r_pos.0 + other.r_size.0),
+            std::cmp::min(self.r_pos.1 + self.r_size.1, other.r_pos.1 + other.r_size.1),
+        );
+
+        if end.0 <= start.0 || end.1 <= start.1 {
+            return None;
+        }
+
+        Some(Rect::new(
+            start.0,
+            start.1,
+            end.0 - start.0,
+            end.1 - start.1,
+        ))
+    }
+
     /// Enlarge this rect enough to contain `other`
     pub fn union(&mut self, other: &Self) {
+        let end = (
+            std::cmp::max(self.r_pos.0 + self.r_size.0, other.r_pos.0 + other.r_size.0),
+            std::cmp::max(self.r_pos.1 + self.r_size.1, other.r_pos.1 + other.r_size.1),
+        );
         self.r_pos.0 = std::cmp::min(self.r_pos.0, other.r_pos.0);
-        self.r_pos.1 = std::cmp::max(self.r_pos.1, other.r_pos.1);
-        self.r_size.0 = std::cmp::min(self.r_size.0, other.r_size.0);
-        self.r_size.1 = std::cmp::max(self.r_size.1, other.r_size.1);
+        self.r_pos.1 = std::cmp::min(self.r_pos.1, other.r_pos.1);
+        self.r_size.0 = end.0 - self.r_pos.0;
+        self.r_size.1 = end.1 - self.r_pos.1;
     }
 }
 
@@ -0,0 +1,44 @@
+/// Utils tests
+///
+/// Austin Shafer - 2024
+use crate::event_loop::{EventLoop, LoopEvent};
+
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+#[test]
+fn event_loop_timers_and_fds() {
+    let mut evloop = EventLoop::new();
+    let (mut tx, rx) = std::os::unix::net::UnixStream::pair().unwrap();
+    evloop.add_fd(rx.as_raw_fd());
+
+    let idle_ran = std::rc::Rc::new(std::cell::Cell::new(false));
+    let idle = idle_ran.clone();
+    evloop.add_idle(move || idle.set(true));
+
+    let repeating = evloop.add_repeating_timer(Duration::from_millis(5));
+    let once = evloop.add_timer(Duration::from_millis(20));
+    let mut repeats = 0;
+    let mut fired = false;
+    while !fired {
+        evloop.wait_for_events(None);
+        for ev in evloop.drain_events() {
+            match ev {
+                LoopEvent::Timer(id) if id == repeating => repeats += 1,
+                LoopEvent::Timer(id) if id == once => fired = true,
+                LoopEvent::Readable(_) => panic!("Nothing was written to the fd"),
+                _ => {}
+            }
+        }
+    }
+    assert!(repeats >= 2);
+    assert!(idle_ran.get());
+
+    // Our fd wakes up the same loop
+    evloop.remove_timer(repeating);
+    tx.write_all(b"x").unwrap();
+    assert!(evloop.wait_for_events(None));
+    assert!(evloop.is_readable(rx.as_raw_fd()));
+    evloop.remove_fd(rx.as_raw_fd());
+}
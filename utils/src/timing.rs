@@ -1,7 +1,8 @@
 // Helpers to handle budgeting subsystems based on time
 //
 // Austin Shafer - 2020
-use std::time::{Duration,SystemTime,UNIX_EPOCH};
+use nix::time::{clock_gettime, ClockId};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn get_current_time() -> Duration {
     SystemTime::now()
@@ -12,8 +13,16 @@ pub fn get_current_time() -> Duration {
 // Helper to get the current time in milliseconds
 #[allow(dead_code)]
 pub fn get_current_millis() -> u32 {
-    get_current_time()
-        .as_millis() as u32
+    get_current_time().as_millis() as u32
+}
+
+// Helper to get the CLOCK_MONOTONIC time in microseconds
+//
+// This is the clock libinput stamps its events with, so input created
+// by us can be ordered against input from real devices.
+pub fn get_monotonic_micros() -> u64 {
+    let time = clock_gettime(ClockId::CLOCK_MONOTONIC).expect("Error getting monotonic time");
+    time.tv_sec() as u64 * 1_000_000 + time.tv_nsec() as u64 / 1000
 }
 
 // Manages subsystem timings
@@ -68,9 +77,9 @@ impl TimingManager {
     // should reset it.
     pub fn time_remaining(&mut self) -> usize {
         let time_elapsed = get_current_time() - self.tm_start;
-	if self.is_overdue() {
-		return 0;
-	}
+        if self.is_overdue() {
+            return 0;
+        }
         return (self.tm_period - time_elapsed).as_millis() as usize;
     }
 }
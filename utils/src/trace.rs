@@ -0,0 +1,217 @@
+// Timeline tracing
+//
+// Spans record how long a piece of work took and which thread it ran on.
+// When CATEGORY5_TRACE is set to a file path, the spans from every crate
+// are written to it in the Chrome trace event format. The file can be
+// opened in chrome://tracing or https://ui.perfetto.dev to see one
+// timeline of the compositor, the toolkit, and the renderer.
+//
+// Events are buffered and written out in batches. The JSON array in the
+// file is never closed, which trace viewers allow so that a trace can be
+// read even if we crash.
+//
+// Austin Shafer - 2024
+use crate::timing::get_monotonic_micros;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Write buffered events once this many have been recorded
+const FLUSH_THRESHOLD: usize = 4096;
+
+/// A completed span
+struct TraceEvent {
+    te_category: &'static str,
+    te_name: &'static str,
+    te_tid: u64,
+    /// Start time in CLOCK_MONOTONIC microseconds
+    te_start: u64,
+    te_duration: u64,
+}
+
+struct Tracer {
+    t_file: BufWriter<File>,
+    t_pid: u32,
+    /// Have we written any events yet? Every event after the first is
+    /// preceded by a comma.
+    t_started: bool,
+    t_events: Vec<TraceEvent>,
+    /// Threads we have not written a name for yet
+    t_new_threads: Vec<(u64, String)>,
+}
+
+lazy_static::lazy_static! {
+    static ref TRACER: Option<Mutex<Tracer>> = Tracer::from_env();
+}
+
+/// Trace ids handed out to threads
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: u64 = register_thread();
+}
+
+/// Give this thread an id and remember its name for the trace
+fn register_thread() -> u64 {
+    let tid = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+    let name = match std::thread::current().name() {
+        Some(name) => name.to_string(),
+        None => format!("thread-{}", tid),
+    };
+
+    if let Some(tracer) = TRACER.as_ref() {
+        tracer.lock().unwrap().t_new_threads.push((tid, name));
+    }
+    tid
+}
+
+/// Escape a string for use in JSON
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+impl Tracer {
+    /// Open the trace file named by CATEGORY5_TRACE
+    fn from_env() -> Option<Mutex<Self>> {
+        let path = std::env::var("CATEGORY5_TRACE").ok()?;
+        let mut file = match File::create(&path) {
+            Ok(file) => BufWriter::new(file),
+            Err(e) => {
+                eprintln!("Could not create trace file {}: {}", path, e);
+                return None;
+            }
+        };
+        if let Err(e) = writeln!(file, "[") {
+            eprintln!("Could not write to trace file {}: {}", path, e);
+            return None;
+        }
+
+        Some(Mutex::new(Self {
+            t_file: file,
+            t_pid: std::process::id(),
+            t_started: false,
+            t_events: Vec::new(),
+            t_new_threads: Vec::new(),
+        }))
+    }
+
+    /// Write one JSON object to the trace
+    fn write_entry(&mut self, entry: &str) -> std::io::Result<()> {
+        if self.t_started {
+            writeln!(self.t_file, ",")?;
+        }
+        self.t_started = true;
+        write!(self.t_file, "{}", entry)
+    }
+
+    /// Write all buffered events to the file
+    fn flush(&mut self) -> std::io::Result<()> {
+        for (tid, name) in std::mem::take(&mut self.t_new_threads) {
+            let entry = format!(
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                self.t_pid,
+                tid,
+                escape(&name)
+            );
+            self.write_entry(&entry)?;
+        }
+
+        for ev in std::mem::take(&mut self.t_events) {
+            let entry = format!(
+                "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":{},\"tid\":{}}}",
+                escape(ev.te_name),
+                escape(ev.te_category),
+                ev.te_start,
+                ev.te_duration,
+                self.t_pid,
+                ev.te_tid
+            );
+            self.write_entry(&entry)?;
+        }
+
+        self.t_file.flush()
+    }
+}
+
+/// Is tracing turned on?
+pub fn is_enabled() -> bool {
+    TRACER.is_some()
+}
+
+/// Write any buffered events to the trace file
+///
+/// This should be called before exiting. Events are also written out
+/// whenever enough of them have been recorded.
+pub fn flush() {
+    if let Some(tracer) = TRACER.as_ref() {
+        if let Err(e) = tracer.lock().unwrap().flush() {
+            eprintln!("Could not write trace events: {}", e);
+        }
+    }
+}
+
+/// A timed region of work
+///
+/// The span ends when this is dropped.
+#[must_use = "The span ends as soon as it is dropped"]
+pub struct Span {
+    s_category: &'static str,
+    s_name: &'static str,
+    /// Start time, None if tracing is off
+    s_start: Option<u64>,
+}
+
+/// Start a span
+///
+/// `category` is the subsystem doing the work, such as "ways" or
+/// "thundr", and can be used to filter the trace. This does nothing
+/// unless tracing is turned on.
+pub fn span(category: &'static str, name: &'static str) -> Span {
+    Span {
+        s_category: category,
+        s_name: name,
+        s_start: match is_enabled() {
+            true => Some(get_monotonic_micros()),
+            false => None,
+        },
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let start = match self.s_start {
+            Some(start) => start,
+            None => return,
+        };
+        let end = get_monotonic_micros();
+        let tid = TID.with(|tid| *tid);
+
+        if let Some(tracer) = TRACER.as_ref() {
+            let mut tracer = tracer.lock().unwrap();
+            tracer.t_events.push(TraceEvent {
+                te_category: self.s_category,
+                te_name: self.s_name,
+                te_tid: tid,
+                te_start: start,
+                te_duration: end.saturating_sub(start),
+            });
+
+            if tracer.t_events.len() >= FLUSH_THRESHOLD {
+                if let Err(e) = tracer.flush() {
+                    eprintln!("Could not write trace events: {}", e);
+                }
+            }
+        }
+    }
+}